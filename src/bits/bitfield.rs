@@ -41,12 +41,12 @@ pub struct BitfieldIter {
 
 impl Bitfield {
     /// True if this Bitfield is valid. The only way to construct an invalid bitfield is Bitfield::default().
-    pub(crate) fn valid(&self) -> bool {
+    pub fn valid(&self) -> bool {
         self.start != !0b0
     }
 
     /// Construct a new Bitfield with the given value.
-    pub(crate) fn new(i: usize) -> Self {
+    pub fn new(i: usize) -> Self {
         Bitfield {
             start: i / BITS,
             bits: 0b1 << (i % BITS),
@@ -54,7 +54,7 @@ impl Bitfield {
     }
 
     /// Construct a new Bitfield with no bits set. The Bitfield's range will include the specified bit index.
-    pub(crate) fn new_empty(i: usize) -> Self {
+    pub fn new_empty(i: usize) -> Self {
         Bitfield {
             start: i / BITS,
             bits: 0,
@@ -62,7 +62,7 @@ impl Bitfield {
     }
 
     /// Intersection of two bitfields. If they do not have overlapping ranges, then the result will always be the empty set.
-    pub(crate) fn intersect(&self, other: &Bitfield) -> Bitfield {
+    pub fn intersect(&self, other: &Bitfield) -> Bitfield {
         assert!(self.valid());
         assert!(other.valid());
         Bitfield {
@@ -77,6 +77,33 @@ impl Bitfield {
         }
     }
 
+    /// Union of two bitfields. Both bitfields must cover the same range.
+    pub fn union(&self, other: &Bitfield) -> Bitfield {
+        assert!(self.valid());
+        assert!(other.valid());
+        assert_eq!(self.start, other.start);
+        Bitfield {
+            start: self.start,
+            bits: self.bits | other.bits,
+        }
+    }
+
+    /// The bits of this bitfield that are not set in the other. If they do not have overlapping ranges, then the result is this bitfield.
+    pub fn difference(&self, other: &Bitfield) -> Bitfield {
+        assert!(self.valid());
+        assert!(other.valid());
+        Bitfield {
+            start: self.start,
+            bits: {
+                if self.start == other.start {
+                    self.bits & !other.bits
+                } else {
+                    self.bits
+                }
+            },
+        }
+    }
+
     /// Construct a Bitfield from the given Range of indices. This consumes the given indices from the range and adds them to returned Bitfield.
     /// The Bitfield can consume at most `size_of<usize>()` bits, so some portion of the Range is likely to remain afterwards.
     pub(crate) fn from_range(i: &mut Range<usize>) -> Option<Self> {
@@ -110,28 +137,31 @@ impl Bitfield {
     /// Clip this Bitfield to the given Range of indices.
     pub(crate) fn clip(mut self, range: &Range<usize>) -> Self {
         assert!(self.valid());
+        let (low, high) = (self.start(), self.start() + BITS);
 
-        if range.end < self.start || range.start >= self.start + BITS {
+        if range.end <= low || range.start >= high {
             self.bits = 0b0;
             return self;
         }
 
-        for i in self.start..range.start {
+        for i in low..range.start {
             self.unset(i);
         }
 
-        for i in range.end..self.start + BITS {
+        for i in range.end..high {
             self.unset(i);
         }
 
         self
     }
 
-    pub(crate) fn ones(&self) -> usize {
+    /// The number of bits set.
+    pub fn ones(&self) -> usize {
         self.bits.count_ones() as usize
     }
 
-    pub(crate) fn start(&self) -> usize {
+    /// The lowest index within this Bitfield's range.
+    pub fn start(&self) -> usize {
         assert!(self.valid());
         self.start * BITS
     }
@@ -158,7 +188,8 @@ impl Bitfield {
         self.bits &= !(0b1 << (i % BITS));
     }
 
-    pub(crate) fn get(&self, i: usize) -> bool {
+    /// True if the given index is set. The index must be within this Bitfield's range.
+    pub fn get(&self, i: usize) -> bool {
         assert!(self.valid());
         assert_eq!(i / BITS, self.start);
 
@@ -414,4 +445,22 @@ mod test {
             assert_eq!(b.get(x), h.contains(&x));
         }
     }
+
+    #[test]
+    fn test_clip() {
+        for _ in 0..1000 {
+            let range =
+                rand::thread_rng().gen_range(0..1000)..rand::thread_rng().gen_range(0..1000);
+            let block = rand::thread_rng().gen_range(0..1000);
+            let full = Bitfield {
+                start: block / BITS,
+                bits: !0b0,
+            };
+
+            let clipped: Vec<usize> = full.clip(&range).into_iter().collect();
+            let expected: Vec<usize> = full.into_iter().filter(|i| range.contains(i)).collect();
+
+            assert_eq!(expected, clipped, "block: {}, range: {:?}", block, &range);
+        }
+    }
 }
@@ -65,7 +65,11 @@ impl Bitset {
             .bits
             .binary_search_by_key(&start_of(i), Bitfield::sort_order)
         {
-            Arc::make_mut(&mut self.bits)[bidx].unset(i);
+            let bits = Arc::make_mut(&mut self.bits);
+            bits[bidx].unset(i);
+            if bits[bidx].ones() == 0 {
+                bits.remove(bidx);
+            }
         }
     }
 
@@ -126,6 +130,68 @@ impl Bitset {
         }
     }
 
+    /// Merge this Bitset with another, producing their union one Bitfield at a time.
+    /// Unlike `IdxSet::union`, the result is itself a Bitset.
+    pub fn merge(&self, other: &Bitset) -> Bitset {
+        if other.is_empty() {
+            return self.clone();
+        } else if self.is_empty() {
+            return other.clone();
+        }
+
+        let (ours, theirs) = (&self.bits, &other.bits);
+        let mut result = Vec::with_capacity(ours.len().max(theirs.len()));
+        let (mut i, mut j) = (0, 0);
+
+        while i < ours.len() && j < theirs.len() {
+            if ours[i].start == theirs[j].start {
+                result.push(Bitfield::union(&ours[i], &theirs[j]));
+                i += 1;
+                j += 1;
+            } else if ours[i].start < theirs[j].start {
+                result.push(ours[i]);
+                i += 1;
+            } else {
+                result.push(theirs[j]);
+                j += 1;
+            }
+        }
+
+        result.extend_from_slice(&ours[i..]);
+        result.extend_from_slice(&theirs[j..]);
+
+        Bitset {
+            bits: Arc::new(result),
+        }
+    }
+
+    /// Intersect this Bitset with another, producing the bits they share one Bitfield at a time.
+    /// Unlike `IdxSet::intersection`, the result is itself a Bitset.
+    pub fn intersect_bitset(&self, other: &Bitset) -> Bitset {
+        let (ours, theirs) = (&self.bits, &other.bits);
+        let mut result = Vec::with_capacity(ours.len().min(theirs.len()));
+        let (mut i, mut j) = (0, 0);
+
+        while i < ours.len() && j < theirs.len() {
+            if ours[i].start == theirs[j].start {
+                let bits = Bitfield::intersect(&ours[i], &theirs[j]);
+                if bits.ones() > 0 {
+                    result.push(bits);
+                }
+                i += 1;
+                j += 1;
+            } else if ours[i].start < theirs[j].start {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+
+        Bitset {
+            bits: Arc::new(result),
+        }
+    }
+
     /// Iterate over all Bitfields in this Bitset.
     ///
     /// You might don't want the Bitfield items themselves. To get at the actual bit indices
@@ -366,6 +432,7 @@ mod test {
         b.unset(27);
 
         assert_eq!(0, b.iter().flatten().count());
+        assert!(b.is_empty());
     }
 
     #[test]
@@ -449,4 +516,58 @@ mod test {
             assert_eq!(b.get(x), h.contains(&x));
         }
     }
+
+    #[test]
+    fn test_merge_random() {
+        let mut a = Bitset::default();
+        let mut b = Bitset::default();
+        let mut h = BTreeSet::new();
+
+        for _ in 0..500 {
+            let x = rand::thread_rng().gen_range(0..10_000);
+            a.set(x);
+            h.insert(x);
+
+            let y = rand::thread_rng().gen_range(5_000..15_000);
+            b.set(y);
+            h.insert(y);
+        }
+
+        let u: Vec<usize> = a.merge(&b).iter().flatten().collect();
+        let h: Vec<usize> = h.into_iter().collect();
+        assert_eq!(h, u);
+
+        assert_eq!(a.len(), a.merge(&Bitset::default()).len());
+        assert_eq!(b.len(), Bitset::default().merge(&b).len());
+    }
+
+    #[test]
+    fn test_intersect_random() {
+        let mut a = Bitset::default();
+        let mut b = Bitset::default();
+        let mut ha = BTreeSet::new();
+        let mut hb = BTreeSet::new();
+
+        for _ in 0..2_000 {
+            let x = rand::thread_rng().gen_range(0..10_000);
+            a.set(x);
+            ha.insert(x);
+
+            let y = rand::thread_rng().gen_range(5_000..15_000);
+            b.set(y);
+            hb.insert(y);
+        }
+
+        let i: Vec<usize> = a.intersect_bitset(&b).iter().flatten().collect();
+        let h: Vec<usize> = ha.intersection(&hb).cloned().collect();
+        assert_eq!(h, i);
+
+        assert!(a.intersect_bitset(&Bitset::default()).is_empty());
+        assert!(Bitset::default().intersect_bitset(&b).is_empty());
+
+        for x in 0..10_000 {
+            let i: Vec<usize> = a.intersect(&Bitfield::from(x)).into_iter().collect();
+            assert_eq!(ha.contains(&x), i == vec![x]);
+        }
+    }
 }
@@ -0,0 +1,97 @@
+use crate::bits::bitfield::Bitfield;
+use crate::traits::idxset::IdxSet;
+
+/// The indices of one `IdxSet` that are not in another.
+#[derive(Clone)]
+pub struct Difference<A, B> {
+    a: A,
+    b: B,
+}
+
+/// An iterator over a `Difference`.
+pub struct DifferenceIter<A: IdxSet, B: IdxSet> {
+    a: A::IdxIter,
+    b: B,
+}
+
+impl<A, B> Difference<A, B>
+where
+    A: IdxSet,
+    B: IdxSet,
+{
+    /// Construct the indices of `a` that are not in `b`.
+    pub fn new(a: A, b: B) -> Self {
+        Difference { a, b }
+    }
+}
+
+impl<A, B> IdxSet for Difference<A, B>
+where
+    A: IdxSet,
+    B: IdxSet,
+{
+    type IdxIter = DifferenceIter<A, B>;
+
+    fn into_idx_iter(self) -> Self::IdxIter {
+        DifferenceIter {
+            a: self.a.into_idx_iter(),
+            b: self.b,
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.a.size()
+    }
+
+    fn intersect(&self, other: &Bitfield) -> Bitfield {
+        Bitfield::difference(&self.a.intersect(other), &self.b.intersect(other))
+    }
+}
+
+impl<A, B> Iterator for DifferenceIter<A, B>
+where
+    A: IdxSet,
+    B: IdxSet,
+{
+    type Item = Bitfield;
+
+    #[inline(always)]
+    fn next(&mut self) -> Option<Bitfield> {
+        let a = self.a.next()?;
+        Some(Bitfield::difference(&a, &self.b.intersect(&a)))
+    }
+}
+
+impl<A, B> DoubleEndedIterator for DifferenceIter<A, B>
+where
+    A: IdxSet,
+    B: IdxSet,
+{
+    #[inline(always)]
+    fn next_back(&mut self) -> Option<Bitfield> {
+        let a = self.a.next_back()?;
+        Some(Bitfield::difference(&a, &self.b.intersect(&a)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bits::Bitset;
+    use crate::idxsets::idxrange::IdxRange;
+
+    #[test]
+    fn test_difference_of_overlapping_idxsets() {
+        let a = IdxRange(60..140);
+        let b: Bitset = (0..62).chain(70..130).chain(200..300).collect();
+
+        let difference: Vec<usize> = Difference::new(a.clone(), b.clone())
+            .into_idx_iter()
+            .flatten()
+            .collect();
+        assert_eq!((62..70).chain(130..140).collect::<Vec<usize>>(), difference);
+
+        let difference: Vec<usize> = Difference::new(b, a).into_idx_iter().flatten().collect();
+        assert_eq!((0..60).chain(200..300).collect::<Vec<usize>>(), difference);
+    }
+}
@@ -1,6 +1,10 @@
+/// Module for an `IdxSet` containing the indices of one IdxSet that are not in another.
+pub mod difference;
 /// Module for an `IdxSet` containing a all indices within a range.
 pub mod idxrange;
 /// Module for an `IdxSet` representing the intersection of two IdxSets.
 pub mod intersection;
 /// Module for an `IdxSet` containing nothing.
 pub mod noidx;
+/// Module for an `IdxSet` representing the union of two IdxSets.
+pub mod union;
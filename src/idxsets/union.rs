@@ -0,0 +1,80 @@
+use crate::bits::bitfield::Bitfield;
+use crate::traits::idxset::IdxSet;
+
+/// The union of two `IdxSets`.
+#[derive(Clone)]
+pub struct Union<A, B> {
+    a: A,
+    b: B,
+}
+
+impl<A, B> Union<A, B>
+where
+    A: IdxSet,
+    B: IdxSet,
+{
+    /// Construct the union of two `IdxSets`.
+    pub fn new(a: A, b: B) -> Self {
+        Union { a, b }
+    }
+}
+
+impl<A, B> IdxSet for Union<A, B>
+where
+    A: IdxSet,
+    B: IdxSet,
+{
+    type IdxIter = std::vec::IntoIter<Bitfield>;
+
+    fn into_idx_iter(self) -> Self::IdxIter {
+        let mut a = self.a.into_idx_iter().filter(Bitfield::valid).peekable();
+        let mut b = self.b.into_idx_iter().filter(Bitfield::valid).peekable();
+        let mut result = Vec::with_capacity(a.size_hint().0.max(b.size_hint().0));
+
+        // Both sides are sorted, so merge them like the merge step of a merge sort.
+        loop {
+            let next = match (a.peek(), b.peek()) {
+                (Some(x), Some(y)) if x.start() == y.start() => {
+                    let union = Bitfield::union(x, y);
+                    a.next();
+                    b.next();
+                    union
+                }
+                (Some(x), Some(y)) if x.start() < y.start() => a.next().unwrap(),
+                (Some(_), Some(_)) => b.next().unwrap(),
+                (Some(_), None) => a.next().unwrap(),
+                (None, Some(_)) => b.next().unwrap(),
+                (None, None) => break,
+            };
+
+            result.push(next);
+        }
+
+        result.into_iter()
+    }
+
+    fn size(&self) -> usize {
+        self.a.size() + self.b.size()
+    }
+
+    fn intersect(&self, other: &Bitfield) -> Bitfield {
+        Bitfield::union(&self.a.intersect(other), &self.b.intersect(other))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bits::Bitset;
+    use crate::idxsets::idxrange::IdxRange;
+
+    #[test]
+    fn test_union_of_overlapping_idxsets() {
+        let a: Bitset = vec![1, 3, 200].into_iter().collect();
+        let b = IdxRange(2..5);
+
+        let union: Vec<usize> = Union::new(a, b).into_idx_iter().flatten().collect();
+
+        assert_eq!(vec![1, 2, 3, 4, 200], union);
+    }
+}
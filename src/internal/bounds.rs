@@ -0,0 +1,30 @@
+use std::borrow::Borrow;
+use std::ops::Bound;
+
+/// Borrow the key of a `Bound`.
+pub(crate) fn borrow_bound<Q, K>(bound: Bound<&Q>) -> Bound<&K>
+where
+    Q: Borrow<K> + ?Sized,
+    K: ?Sized,
+{
+    match bound {
+        Bound::Included(key) => Bound::Included(key.borrow()),
+        Bound::Excluded(key) => Bound::Excluded(key.borrow()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+/// False if the `BTreeSet::range` of these bounds would panic, because the range is inverted.
+pub(crate) fn is_valid_range<K>(start: Bound<&K>, end: Bound<&K>) -> bool
+where
+    K: Ord + ?Sized,
+{
+    match (start, end) {
+        (Bound::Excluded(start), Bound::Excluded(end)) => start < end,
+        (
+            Bound::Included(start) | Bound::Excluded(start),
+            Bound::Included(end) | Bound::Excluded(end),
+        ) => start <= end,
+        _ => true,
+    }
+}
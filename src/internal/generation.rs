@@ -0,0 +1,14 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// shared by every Storage, so that a generation from one Storage is comparable with its clones
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// A new generation, later than every generation handed out before it.
+pub(crate) fn next_generation() -> u64 {
+    GENERATION.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+/// The latest generation handed out so far.
+pub(crate) fn current_generation() -> u64 {
+    GENERATION.load(Ordering::Relaxed)
+}
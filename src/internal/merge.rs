@@ -0,0 +1,91 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// Lazily merges runs that are already sorted by some key into a single sorted sequence.
+/// Only the key of the head of each run is held at any one time. Ties are broken in favor of
+/// the earlier run, so the merge is stable.
+pub(crate) struct KWayMerge<T, K, F> {
+    runs: Vec<std::vec::IntoIter<T>>,
+    heads: BinaryHeap<Reverse<(K, usize)>>,
+    pending: Vec<Option<T>>,
+    key: F,
+}
+
+impl<T, K, F> KWayMerge<T, K, F>
+where
+    K: Ord,
+    F: Fn(&T) -> K,
+{
+    pub(crate) fn new(runs: Vec<Vec<T>>, key: F) -> Self {
+        let mut result = KWayMerge {
+            heads: BinaryHeap::with_capacity(runs.len()),
+            pending: Vec::with_capacity(runs.len()),
+            runs: runs.into_iter().map(Vec::into_iter).collect(),
+            key,
+        };
+
+        for run in 0..result.runs.len() {
+            result.pending.push(None);
+            result.advance(run);
+        }
+
+        result
+    }
+
+    fn advance(&mut self, run: usize) {
+        if let Some(t) = self.runs[run].next() {
+            self.heads.push(Reverse(((self.key)(&t), run)));
+            self.pending[run] = Some(t);
+        }
+    }
+}
+
+impl<T, K, F> Iterator for KWayMerge<T, K, F>
+where
+    K: Ord,
+    F: Fn(&T) -> K,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        let Reverse((_, run)) = self.heads.pop()?;
+        let result = self.pending[run].take();
+        self.advance(run);
+        result
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.heads.len() + self.runs.iter().map(|r| r.len()).sum::<usize>();
+        (remaining, Some(remaining))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::KWayMerge;
+
+    #[test]
+    fn test_merge_is_sorted_and_stable() {
+        let runs = vec![
+            vec![(1, 'a'), (3, 'a'), (5, 'a')],
+            vec![],
+            vec![(1, 'c'), (2, 'c'), (6, 'c')],
+            vec![(3, 'd')],
+        ];
+
+        let merged: Vec<(u32, char)> = KWayMerge::new(runs, |x: &(u32, char)| x.0).collect();
+
+        assert_eq!(
+            vec![
+                (1, 'a'),
+                (1, 'c'),
+                (2, 'c'),
+                (3, 'a'),
+                (3, 'd'),
+                (5, 'a'),
+                (6, 'c')
+            ],
+            merged
+        );
+    }
+}
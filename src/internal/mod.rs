@@ -1,4 +1,18 @@
+/// Helpers for working with range bounds
+pub(crate) mod bounds;
+/// Generations marking when element positions last moved
+pub(crate) mod generation;
 /// The hasher configuration
 pub(crate) mod hasher;
+/// Lazy merging of sorted runs
+pub(crate) mod merge;
 /// Functions and data structures related to map reductions
 pub(crate) mod mr;
+/// Tracking of outstanding pinned chunks
+#[cfg(feature = "debug_borrows")]
+pub(crate) mod pins;
+/// Uniform random sampling
+#[cfg(feature = "rand")]
+pub(crate) mod sample;
+/// Readable names of types
+pub(crate) mod type_name;
@@ -1,13 +1,17 @@
 use crate::internal::mr::rvec::RVec;
 use crate::traits::memory_usage::MemoryUsage;
 use crate::traits::memory_usage::MemoryUser;
+use std::ops::Range;
 use std::sync::Arc;
 
 pub(crate) struct ReduceRules<Element, Summary> {
     map: Arc<dyn Fn(&Element, &Summary, usize) -> Option<Summary> + Send + Sync + 'static>,
     reduce: Arc<dyn Fn(&[Summary], &Summary) -> Option<Summary> + Send + Sync + 'static>,
+    inverse: Option<InverseFn<Summary>>,
 }
 
+type InverseFn<Summary> = Arc<dyn Fn(&Summary, &Summary) -> Summary + Send + Sync + 'static>;
+
 pub(crate) struct Reduce<Element, Summary> {
     rules: ReduceRules<Element, Summary>,
     // This is a reduction stack. It looks like:
@@ -16,6 +20,12 @@ pub(crate) struct Reduce<Element, Summary> {
     // Used for exponential collapse the reduction vector into a single element.
     reductions: Vec<RVec<Summary>>,
     group_size: usize,
+    // Running total, maintained one element at a time when the rules have an inverse.
+    // In that case, only the bottom layer of the reduction stack is used.
+    total: Summary,
+    // The number of times the rules have reported a new result, or the result has appeared or
+    // disappeared.
+    result_changes: u64,
 }
 
 impl<Element, Summary> ReduceRules<Element, Summary> {
@@ -27,13 +37,49 @@ impl<Element, Summary> ReduceRules<Element, Summary> {
         ReduceRules {
             map: Arc::new(map),
             reduce: Arc::new(reduce),
+            inverse: None,
         }
     }
+
+    pub(crate) fn with_inverse<Inverse>(mut self, inverse: Inverse) -> Self
+    where
+        Inverse: Fn(&Summary, &Summary) -> Summary + Send + Sync + 'static,
+    {
+        self.inverse = Some(Arc::new(inverse));
+        self
+    }
+
+    /// These same rules, without any inverse.
+    pub(crate) fn without_inverse(&self) -> Self {
+        ReduceRules {
+            map: Arc::clone(&self.map),
+            reduce: Arc::clone(&self.reduce),
+            inverse: None,
+        }
+    }
+
+    /// The `Summary` of a single element, from scratch.
+    pub(crate) fn map_one(&self, element: &Element, idx: usize) -> Summary
+    where
+        Summary: Default,
+    {
+        let was = Summary::default();
+        (self.map)(element, &was, idx).unwrap_or(was)
+    }
+
+    /// The fold of some `Summaries`, from scratch.
+    pub(crate) fn fold(&self, summaries: &[Summary]) -> Summary
+    where
+        Summary: Default,
+    {
+        let was = Summary::default();
+        (self.reduce)(summaries, &was).unwrap_or(was)
+    }
 }
 
 impl<Element, Summary> Reduce<Element, Summary>
 where
-    Summary: Default,
+    Summary: Default + Clone,
 {
     pub(crate) fn new(
         _parent: &RVec<Element>,
@@ -46,10 +92,17 @@ where
             rules,
             reductions: vec![RVec::default()],
             group_size,
+            total: Summary::default(),
+            result_changes: 0,
         }
     }
 
     pub(crate) fn update(&mut self, parent: &RVec<Element>) -> Option<&Summary> {
+        if self.rules.inverse.is_some() {
+            return self.update_invertible(parent);
+        }
+
+        let mut before = self.before_update(parent.len());
         let mut layer = 0;
         let map = &self.rules.map;
         let reduce = &self.rules.reduce;
@@ -68,7 +121,7 @@ where
 
         while self.reductions[layer].len() > 1 {
             if self.reductions.len() == layer + 1 {
-                self.reductions.push(RVec::default());
+                self.reductions.push(before.new_layer(layer + 1));
             }
 
             let (left, right) = self.reductions.split_at_mut(layer + 1);
@@ -84,11 +137,212 @@ where
         }
 
         self.reductions.truncate(layer + 1);
+        self.after_update(before);
+
+        self.peek()
+    }
+
+    /// As `Reduce::update`, except that the groups of each layer that need to be recalculated
+    /// are recalculated in parallel. A running total is always updated sequentially.
+    #[cfg(feature = "rayon")]
+    pub(crate) fn par_update(&mut self, parent: &RVec<Element>) -> Option<&Summary>
+    where
+        Element: Sync,
+        Summary: Send + Sync,
+    {
+        if self.rules.inverse.is_some() {
+            return self.update_invertible(parent);
+        }
+
+        let mut before = self.before_update(parent.len());
+        let mut layer = 0;
+        let map = &self.rules.map;
+        let reduce = &self.rules.reduce;
+
+        if parent.is_empty() {
+            self.reductions[layer] = RVec::default();
+        } else {
+            self.reductions[layer].par_reduce(parent, 1, |xs, y, i| {
+                if xs.is_empty() {
+                    None
+                } else {
+                    (map)(&xs[0], y, i)
+                }
+            });
+        }
+
+        while self.reductions[layer].len() > 1 {
+            if self.reductions.len() == layer + 1 {
+                self.reductions.push(before.new_layer(layer + 1));
+            }
+
+            let (left, right) = self.reductions.split_at_mut(layer + 1);
+            right[0].par_reduce(&left[layer], self.group_size, |xs, y, _| {
+                if xs.is_empty() {
+                    None
+                } else {
+                    (reduce)(xs, y)
+                }
+            });
+
+            layer += 1;
+        }
+
+        self.reductions.truncate(layer + 1);
+        self.after_update(before);
+
+        self.peek()
+    }
+
+    /// Update the running total by removing the old contribution of each changed element and
+    /// adding its new contribution, rather than recomputing whole groups.
+    fn update_invertible(&mut self, parent: &RVec<Element>) -> Option<&Summary> {
+        let map = &self.rules.map;
+        let reduce = &self.rules.reduce;
+        let inverse = self
+            .rules
+            .inverse
+            .as_ref()
+            .expect("retriever bug: update_invertible requires an inverse");
+        let total = &mut self.total;
+        let had_result = !self.reductions[0].is_empty();
+        let version = self.reductions[0].version();
+
+        if parent.is_empty() || !self.reductions[0].is_reduced_from(parent) {
+            self.reductions[0] = RVec::default();
+            *total = Summary::default();
+        }
+
+        self.reductions[0].reduce(parent, 1, |xs, y, i| {
+            if xs.is_empty() {
+                *total = (inverse)(total, y);
+                return None;
+            }
+
+            let summary = (map)(&xs[0], y, i)?;
+            let without = (inverse)(total, y);
+            *total = (reduce)(&[without, summary.clone()], &Summary::default()).unwrap_or_default();
+            Some(summary)
+        });
+
+        let has_result = !self.reductions[0].is_empty();
+        if had_result != has_result || version != self.reductions[0].version() {
+            self.result_changes += 1;
+        }
 
         self.peek()
     }
 
+    /// Note what's needed to tell whether the result changes while updating from a parent of
+    /// the given length.
+    fn before_update(&self, len: usize) -> BeforeUpdate<Summary> {
+        let mut height = 1;
+        let mut top_len = len;
+        while top_len > 1 {
+            top_len = top_len.div_ceil(self.group_size);
+            height += 1;
+        }
+
+        let top = &self.reductions[self.reductions.len() - 1];
+
+        BeforeUpdate {
+            had_result: self.peek().is_some(),
+            height: self.reductions.len(),
+            version: top.version(),
+            new_height: height,
+            // If the stack grows, its new top layer starts out holding the old result, so that
+            // the rules only report a new result if it's actually different.
+            seed: if height > self.reductions.len() {
+                self.peek().cloned()
+            } else {
+                None
+            },
+            seed_version: None,
+        }
+    }
+
+    /// Count a change of result since `Reduce::before_update`.
+    fn after_update(&mut self, before: BeforeUpdate<Summary>) {
+        let top = &self.reductions[self.reductions.len() - 1];
+        let has_result = self.peek().is_some();
+
+        let changed = if has_result != before.had_result {
+            true
+        } else if !has_result {
+            false
+        } else if self.reductions.len() == before.height {
+            top.version() != before.version
+        } else {
+            Some(top.version()) != before.seed_version
+        };
+
+        if changed {
+            self.result_changes += 1;
+        }
+    }
+
+    /// The fold of a contiguous range of the bottom layer of the reduction stack, using whole
+    /// groups from the layers above wherever the range covers them, so that only
+    /// O(group_size * log(n)) summaries are folded. Call `Reduce::update` first.
+    pub(crate) fn fold_range(&self, range: Range<usize>) -> Option<Summary> {
+        assert!(
+            self.rules.inverse.is_none(),
+            "retriever bug: fold_range requires the whole reduction stack"
+        );
+
+        let group_size = self.group_size;
+        let (mut lo, mut hi) = (range.start, range.end.min(self.reductions[0].len()));
+
+        if lo >= hi {
+            return None;
+        }
+
+        // The summaries to the left and right of the part of the range that is covered by the
+        // next layer up, so that they can be folded in order.
+        let mut left: Vec<&Summary> = Vec::new();
+        let mut right: Vec<&[Summary]> = Vec::new();
+
+        for (layer, reductions) in self.reductions.iter().enumerate() {
+            let reductions: &[Summary] = reductions;
+            let lo_up = lo.div_ceil(group_size);
+            let hi_up = hi / group_size;
+
+            if layer + 1 == self.reductions.len() || lo_up >= hi_up {
+                left.extend(reductions[lo..hi].iter());
+                break;
+            }
+
+            left.extend(reductions[lo..lo_up * group_size].iter());
+            right.push(&reductions[hi_up * group_size..hi]);
+            lo = lo_up;
+            hi = hi_up;
+        }
+
+        let summaries: Vec<Summary> = left
+            .into_iter()
+            .chain(right.into_iter().rev().flatten())
+            .cloned()
+            .collect();
+
+        Some(self.rules.fold(&summaries))
+    }
+
+    /// The number of times the result has changed, or None if there is no result. This changes
+    /// whenever the rules report that the result has changed.
+    pub(crate) fn result_version(&self) -> Option<u64> {
+        self.peek()?;
+        Some(self.result_changes)
+    }
+
     pub(crate) fn peek(&self) -> Option<&Summary> {
+        if self.rules.inverse.is_some() {
+            return if self.reductions[0].is_empty() {
+                None
+            } else {
+                Some(&self.total)
+            };
+        }
+
         let result_slice = &self.reductions[self.reductions.len() - 1];
 
         if result_slice.len() == 0 {
@@ -101,11 +355,43 @@ where
     }
 }
 
+// What `Reduce::after_update` needs to know about the reduction stack as it was before an update.
+struct BeforeUpdate<Summary> {
+    had_result: bool,
+    height: usize,
+    version: (u64, u128),
+    new_height: usize,
+    seed: Option<Summary>,
+    seed_version: Option<(u64, u128)>,
+}
+
+impl<Summary> BeforeUpdate<Summary>
+where
+    Summary: Default,
+{
+    /// A new layer for the given position of the reduction stack.
+    fn new_layer(&mut self, layer: usize) -> RVec<Summary> {
+        if layer + 1 != self.new_height {
+            return RVec::default();
+        }
+
+        match self.seed.take() {
+            Some(seed) => {
+                let result = RVec::from(vec![seed]);
+                self.seed_version = Some(result.version());
+                result
+            }
+            None => RVec::default(),
+        }
+    }
+}
+
 impl<Element, Summary> Clone for ReduceRules<Element, Summary> {
     fn clone(&self) -> Self {
         ReduceRules {
             map: Arc::clone(&self.map),
             reduce: Arc::clone(&self.reduce),
+            inverse: self.inverse.clone(),
         }
     }
 }
@@ -126,9 +412,14 @@ mod test {
                 },
             ),
             reduce: Arc::new(|ns: &[i64], _old_n: &i64| Some(ns.iter().cloned().sum::<i64>())),
+            inverse: None,
         }
     }
 
+    fn invertible_summation_rules() -> ReduceRules<i64, i64> {
+        summation_rules().with_inverse(|total: &i64, n: &i64| total - n)
+    }
+
     #[test]
     fn test_sum() {
         use super::*;
@@ -150,6 +441,34 @@ mod test {
         assert_eq!(sum.peek(), Some(&28));
     }
 
+    #[test]
+    fn test_sum_after_parent_shrinks() {
+        use super::*;
+
+        let copy = |xs: &[i64], old_x: &i64, _: usize| xs.first().cloned().filter(|x| x != old_x);
+        let mut numbers = RVec::default();
+        let mut copies = RVec::default();
+
+        for i in 0..16 {
+            numbers.push(i);
+        }
+
+        copies.reduce(&numbers, 1, copy);
+        let mut sum = Reduce::new(&copies, 2, summation_rules());
+        assert_eq!(sum.update(&copies), Some(&120));
+
+        numbers[1] *= 3;
+        numbers[6] *= 3;
+        copies.reduce(&numbers, 1, copy);
+        assert_eq!(sum.update(&copies), Some(&134));
+
+        // The last group of every layer of the sum loses an element, but none of the elements
+        // that remain in that group change.
+        numbers.swap_remove(3);
+        copies.reduce(&numbers, 1, copy);
+        assert_eq!(sum.update(&copies), Some(&131));
+    }
+
     #[test]
     fn test_sum_with_update() {
         use super::*;
@@ -224,6 +543,40 @@ mod test {
         sum.update(&numbers);
         assert_eq!(sum.peek(), Some(&36));
     }
+
+    #[test]
+    fn test_invertible_sum_with_changes() {
+        let mut numbers = RVec::default();
+
+        for n in 1..=7 {
+            numbers.push(n);
+        }
+
+        let mut sum = Reduce::new(&numbers, 2, invertible_summation_rules());
+
+        sum.update(&numbers);
+        assert_eq!(sum.peek(), Some(&28));
+
+        numbers[3] += 10;
+        sum.update(&numbers);
+        assert_eq!(sum.peek(), Some(&38));
+
+        numbers.swap_remove(1);
+        sum.update(&numbers);
+        assert_eq!(sum.peek(), Some(&36));
+
+        numbers.push(8);
+        sum.update(&numbers);
+        assert_eq!(sum.peek(), Some(&44));
+
+        let mut others = RVec::default();
+        others.push(100);
+        sum.update(&others);
+        assert_eq!(sum.peek(), Some(&100));
+
+        sum.update(&RVec::default());
+        assert_eq!(sum.peek(), None);
+    }
 }
 
 impl<Element, Summary> MemoryUser for Reduce<Element, Summary> {
@@ -17,17 +17,76 @@ const STRIDE: [usize; 5] = [
     SCALE * SCALE * SCALE * SCALE * SCALE,
 ];
 
-struct ChangedVec {
+// The most elements of a block that can be remembered as touched, before forgetting
+// the older touches of that block.
+const TOUCHED_LIMIT: u32 = 4;
+
+#[derive(Clone)]
+pub(crate) struct ChangedVec {
     count: u128,
     counts: [Vec<u128>; 5],
+    // for each block of SCALE elements, every element touched after this count is in `touched`
+    since: Vec<u128>,
+    // for each block of SCALE elements, a bitmask of the elements touched after `since`
+    touched: Vec<u16>,
+}
+
+impl ChangedVec {
+    /// Touch an element of the RVec this ChangedVec belongs to, by index.
+    pub(crate) fn touch(&mut self, i: usize) {
+        if i / STRIDE[0] + 1 > self.counts[0].len() {
+            self.resize_to_fit(i + 1);
+        }
+
+        let block = i / STRIDE[0];
+        let bit = 1 << (i % STRIDE[0]);
+        if self.touched[block] & bit == 0 {
+            if self.touched[block].count_ones() < TOUCHED_LIMIT {
+                self.touched[block] |= bit;
+            } else {
+                // Anyone who caught up with the last touch of this block only needs to see this one.
+                self.since[block] = self.counts[0][block];
+                self.touched[block] = bit;
+            }
+        }
+
+        self.count += 1;
+        self.counts[0][i / STRIDE[0]] = self.count;
+        self.counts[1][i / STRIDE[1]] = self.count;
+        self.counts[2][i / STRIDE[2]] = self.count;
+        self.counts[3][i / STRIDE[3]] = self.count;
+        self.counts[4][i / STRIDE[4]] = self.count;
+    }
+
+    /// Resize the hierarchical change count vectors to fit the size of the data.
+    fn resize_to_fit(&mut self, len: usize) {
+        for (j, stride) in STRIDE.iter().enumerate() {
+            self.counts[j].resize(self.counts[j].len().max(len / stride + 1), 0);
+        }
+
+        self.since.resize(self.counts[0].len(), 0);
+        self.touched.resize(self.counts[0].len(), 0);
+    }
+
+    /// True IFF the element at the given index, within a block that has changed since the given
+    /// count, might have changed since then too.
+    fn changed_since(&self, i: usize, expected_count: u128) -> bool {
+        let block = i / STRIDE[0];
+        self.since[block] > expected_count || self.touched[block] & (1 << (i % STRIDE[0])) != 0
+    }
 }
 
 pub(crate) struct RVec<T> {
     id: u64,
     parent_id: Option<u64>,
     parent_count: u128,
+    // the length of the parent (source) as of the last reduction
+    parent_len: usize,
     data: Vec<T>,
     changed_vec: ChangedVec,
+    // the id and change count of the RVec this one was cloned from, if any, so that
+    // reductions of the original can carry on from the clone
+    forked_from: Option<(u64, u128)>,
 }
 
 impl<T> RVec<T> {
@@ -42,30 +101,29 @@ impl<T> RVec<T> {
         self.changed_vec.count
     }
 
+    /// An identifier of this RVec and every change made to it. This is different after
+    /// any change, and different from that of any other RVec.
+    pub(crate) fn version(&self) -> (u64, u128) {
+        (self.id, self.changed_vec.count)
+    }
+
     /// Touch an element of this RVec, but index.
     pub(crate) fn touch(&mut self, i: usize) -> &mut Self {
-        if i / STRIDE[0] + 1 > self.changed_vec.counts[0].len() {
-            for (j, stride) in STRIDE.iter().enumerate() {
-                self.changed_vec.counts[j]
-                    .resize(self.changed_vec.counts[j].len().max(i / stride + 1), 0);
-            }
-        }
-
-        self.changed_vec.count += 1;
-        self.changed_vec.counts[0][i / STRIDE[0]] = self.changed_vec.count;
-        self.changed_vec.counts[1][i / STRIDE[1]] = self.changed_vec.count;
-        self.changed_vec.counts[2][i / STRIDE[2]] = self.changed_vec.count;
-        self.changed_vec.counts[3][i / STRIDE[3]] = self.changed_vec.count;
-        self.changed_vec.counts[4][i / STRIDE[4]] = self.changed_vec.count;
-
+        self.changed_vec.touch(i);
         self
     }
 
+    /// Mutable access to every element of this RVec, without touching any of them. The caller
+    /// must touch each element it changes using the returned ChangedVec.
+    pub(crate) fn split_mut(&mut self) -> (&mut [T], &mut ChangedVec) {
+        (&mut self.data, &mut self.changed_vec)
+    }
+
     fn resize_touch(&mut self, new_size: usize) -> &mut Self
     where
         T: Default,
     {
-        resize_to_fit(&mut self.changed_vec.counts, new_size);
+        self.changed_vec.resize_to_fit(new_size);
 
         self.data.resize_with(new_size, Default::default);
         for i in self.data.len()..new_size {
@@ -99,10 +157,10 @@ impl<T> RVec<T> {
     }
 
     fn validate_parent_id<S>(&mut self, source: &RVec<S>) {
-        if let Some(parent_id) = self.parent_id {
-            if parent_id != source.id {
-                self.reset();
-            }
+        if self.is_reduced_from(source) {
+            self.parent_id = Some(source.id);
+        } else if self.parent_id.is_some() {
+            self.reset();
         }
 
         if self.parent_id.is_none() {
@@ -112,7 +170,101 @@ impl<T> RVec<T> {
         assert_eq!(self.parent_id, Some(source.id));
     }
 
+    /// True IFF reducing the given source would start this RVec over from scratch, because it
+    /// was last reduced from some unrelated source.
+    pub(crate) fn would_reset<S>(&self, source: &RVec<S>) -> bool {
+        self.parent_id.is_some() && !self.is_reduced_from(source)
+    }
+
+    /// True IFF this RVec was last reduced from the given source, or from the RVec that the
+    /// source was cloned from, before the two diverged.
+    pub(crate) fn is_reduced_from<S>(&self, source: &RVec<S>) -> bool {
+        match (self.parent_id, source.forked_from) {
+            (Some(parent_id), _) if parent_id == source.id => true,
+            (Some(parent_id), Some((forked_id, forked_count))) => {
+                parent_id == forked_id && self.parent_count <= forked_count
+            }
+            _ => false,
+        }
+    }
+
+    /// An RVec holding the given data, which is already the reduction of the given source.
+    #[cfg(feature = "serde")]
+    pub(crate) fn reduced_from<S>(source: &RVec<S>, data: Vec<T>) -> Self {
+        let mut result = RVec::from(data);
+        result.parent_id = Some(source.id);
+        result.parent_count = source.changed_vec.count;
+        result.parent_len = source.data.len();
+        result
+    }
+
     pub(crate) fn reduce<S, Op>(&mut self, source: &RVec<S>, group_size: usize, mut op: Op)
+    where
+        Op: FnMut(&[S], &T, usize) -> Option<T>,
+        T: Default,
+    {
+        let needs_recalc = self.needs_recalc(source, group_size, &mut op);
+
+        // Perform the updates
+        let source_length = source.data.len();
+        for i in needs_recalc {
+            if let Some(replacement) = op(
+                &source.data[i * group_size..((i + 1) * group_size).min(source_length)],
+                &self.data[i],
+                i,
+            ) {
+                self[i] = replacement;
+            }
+        }
+
+        self.parent_count = source.changed_vec.count;
+        self.parent_len = source_length;
+    }
+
+    /// As `RVec::reduce`, except that the groups that need to be recalculated are recalculated
+    /// in parallel.
+    #[cfg(feature = "rayon")]
+    pub(crate) fn par_reduce<S, Op>(&mut self, source: &RVec<S>, group_size: usize, op: Op)
+    where
+        S: Sync,
+        Op: Fn(&[S], &T, usize) -> Option<T> + Sync,
+        T: Default + Send + Sync,
+    {
+        use rayon::prelude::*;
+
+        let needs_recalc = self.needs_recalc(source, group_size, &mut |xs, t, i| op(xs, t, i));
+
+        // Perform the updates
+        let source_length = source.data.len();
+        let data = &self.data;
+        let replacements: Vec<(usize, T)> = needs_recalc
+            .into_par_iter()
+            .filter_map(|i| {
+                op(
+                    &source.data[i * group_size..((i + 1) * group_size).min(source_length)],
+                    &data[i],
+                    i,
+                )
+                .map(|replacement| (i, replacement))
+            })
+            .collect();
+
+        for (i, replacement) in replacements {
+            self[i] = replacement;
+        }
+
+        self.parent_count = source.changed_vec.count;
+        self.parent_len = source_length;
+    }
+
+    // Resize this RVec to match the parent (source), and list the elements of this RVec that
+    // need to be recalculated, in order.
+    fn needs_recalc<S, Op>(
+        &mut self,
+        source: &RVec<S>,
+        group_size: usize,
+        op: &mut Op,
+    ) -> Vec<usize>
     where
         Op: FnMut(&[S], &T, usize) -> Option<T>,
         T: Default,
@@ -152,10 +304,15 @@ impl<T> RVec<T> {
             } else if source.changed_vec.counts[1][i / STRIDE[1]] <= expected_count {
                 i = ((i / STRIDE[1]) + 1) * STRIDE[1];
             } else if source.changed_vec.counts[0][i / STRIDE[0]] > expected_count {
+                // Only recalculate the groups of the elements that have actually changed.
                 let stop = ((i / STRIDE[0]) + 1) * STRIDE[0];
                 while i < stop {
-                    needs_recalc.push(i / group_size);
-                    i = ((i / group_size) + 1) * group_size;
+                    if source.changed_vec.changed_since(i, expected_count)
+                        && needs_recalc.last() != Some(&(i / group_size))
+                    {
+                        needs_recalc.push(i / group_size);
+                    }
+                    i += 1;
                 }
             } else {
                 let stop = ((i / STRIDE[0]) + 1) * STRIDE[0];
@@ -165,20 +322,18 @@ impl<T> RVec<T> {
             }
         }
 
-        // Perform the updates
-        let source_length = source.data.len();
-        let dest_length = self.data.len();
-        for i in needs_recalc.into_iter().filter(|i| *i < dest_length) {
-            if let Some(replacement) = op(
-                &source.data[i * group_size..((i + 1) * group_size).min(source_length)],
-                &self.data[i],
-                i,
-            ) {
-                self[i] = replacement;
+        // If the parent (source) is shorter than last time, then its last group lost elements
+        // that are no longer there to be noticed as changed.
+        if source.data.len() < self.parent_len && !self.data.is_empty() {
+            let last = self.data.len() - 1;
+            if needs_recalc.last() != Some(&last) {
+                needs_recalc.push(last);
             }
         }
 
-        self.parent_count = source.changed_vec.count;
+        let dest_length = self.data.len();
+        needs_recalc.retain(|i| *i < dest_length);
+        needs_recalc
     }
 }
 
@@ -205,29 +360,31 @@ impl<T> IndexMut<usize> for RVec<T> {
 
 impl<T> From<Vec<T>> for RVec<T> {
     fn from(data: Vec<T>) -> Self {
-        let mut counts: [Vec<u128>; 5] =
-            [Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new()];
-        resize_to_fit(&mut counts, data.len());
+        let mut changed_vec = ChangedVec {
+            count: 0,
+            counts: [Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new()],
+            since: Vec::new(),
+            touched: Vec::new(),
+        };
+        changed_vec.resize_to_fit(data.len());
+
+        // Every element is new to anything that reduces from this RVec.
+        for i in 0..data.len() {
+            changed_vec.touch(i);
+        }
 
         RVec {
             id: ID_COUNTER.fetch_add(1, Ordering::Relaxed),
             data,
             parent_count: 0,
+            parent_len: 0,
             parent_id: None,
-            changed_vec: ChangedVec { count: 0, counts },
+            changed_vec,
+            forked_from: None,
         }
     }
 }
 
-/// Resize the hierarchical change count vectors to fit the size of the data.
-fn resize_to_fit(counts: &mut [Vec<u128>; 5], len: usize) {
-    counts[0].resize(len / STRIDE[0] + 1, 0);
-    counts[1].resize(len / STRIDE[1] + 1, 0);
-    counts[2].resize(len / STRIDE[2] + 1, 0);
-    counts[3].resize(len / STRIDE[3] + 1, 0);
-    counts[4].resize(len / STRIDE[4] + 1, 0);
-}
-
 impl<T> Into<Vec<T>> for RVec<T> {
     fn into(self) -> Vec<T> {
         self.data
@@ -247,29 +404,39 @@ where
     T: Clone,
 {
     fn clone(&self) -> Self {
-        let mut result = RVec::default();
-
-        for e in self.data.iter() {
-            result.push(e.clone());
+        // The clone keeps the change history of the original, so that anything that has reduced
+        // the original only needs to look at what changes in the clone after this point.
+        RVec {
+            id: ID_COUNTER.fetch_add(1, Ordering::Relaxed),
+            parent_id: None,
+            parent_count: 0,
+            parent_len: 0,
+            data: self.data.clone(),
+            changed_vec: self.changed_vec.clone(),
+            forked_from: Some((self.id, self.changed_vec.count)),
         }
-
-        result
     }
 }
 
 impl MemoryUser for ChangedVec {
     fn memory_usage(&self) -> MemoryUsage {
-        MemoryUsage {
+        let counts = MemoryUsage {
             size_of: Some(std::mem::size_of::<u128>()),
-            len: self.counts.iter().map(|v| v.len()).sum::<usize>(),
-            capacity: self.counts.iter().map(|v| v.capacity()).sum::<usize>(),
-        }
+            len: self.counts.iter().map(|v| v.len()).sum::<usize>() + self.since.len(),
+            capacity: self.counts.iter().map(|v| v.capacity()).sum::<usize>()
+                + self.since.capacity(),
+        };
+
+        MemoryUsage::merge(counts, self.touched.memory_usage())
     }
 
     fn shrink_with<F: Fn(&MemoryUsage) -> Option<usize>>(&mut self, f: F) {
         for count in self.counts.iter_mut() {
             count.shrink_with(&f);
         }
+
+        self.since.shrink_with(&f);
+        self.touched.shrink_with(&f);
     }
 }
 
@@ -447,6 +614,76 @@ mod test {
         assert_eq!(layer_1.len(), 4);
     }
 
+    #[test]
+    fn test_map_reduce_only_revisits_changed_elements() {
+        use super::*;
+
+        let mut v = RVec::default();
+        for i in 0..0x100 {
+            v.push(i);
+        }
+
+        let mut result = RVec::default();
+        let mut visits = 0;
+        result.reduce(&v, 1, |xs, _, _| {
+            visits += 1;
+            xs.first().map(|x| x * 2)
+        });
+        assert_eq!(0x100, visits);
+
+        v[0x11] = 0x1000;
+        v[0x13] = 0x1001;
+
+        let mut visits = 0;
+        result.reduce(&v, 1, |xs, _, _| {
+            visits += 1;
+            xs.first().map(|x| x * 2)
+        });
+        assert_eq!(2, visits);
+        assert_eq!(0x2000, result[0x11]);
+        assert_eq!(0x2002, result[0x13]);
+        assert_eq!(0x24, result[0x12]);
+    }
+
+    #[test]
+    fn test_map_reduce_with_many_changes_in_one_block() {
+        use super::*;
+
+        fn sums(result: &mut RVec<i32>, v: &RVec<i32>) {
+            result.reduce(v, 3, |xs, _, _| {
+                if xs.is_empty() {
+                    None
+                } else {
+                    Some(xs.iter().sum::<i32>())
+                }
+            });
+        }
+
+        let mut v = RVec::default();
+        for i in 0..0x40 {
+            v.push(i);
+        }
+
+        let mut eager = RVec::default();
+        let mut lagging = RVec::default();
+        sums(&mut eager, &v);
+        sums(&mut lagging, &v);
+
+        for round in 0..4 {
+            for i in 0..10 {
+                v[0x10 + (i * 7 + round) % 0x10] += 1;
+                sums(&mut eager, &v);
+            }
+            v.swap_remove(round * 5);
+        }
+        sums(&mut eager, &v);
+        sums(&mut lagging, &v);
+
+        let expected: Vec<i32> = v.chunks(3).map(|xs| xs.iter().sum()).collect();
+        assert_eq!(&expected[..], &*eager);
+        assert_eq!(&expected[..], &*lagging);
+    }
+
     #[test]
     fn test_map_reduce_with_changing_source_should_no_longer_panic() {
         use super::*;
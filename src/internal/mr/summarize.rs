@@ -34,6 +34,31 @@ where
         }
     }
 
+    /// Restore a summary of the given source from tokens that were previously mapped from
+    /// each of its elements, without mapping any element again.
+    #[cfg(feature = "serde")]
+    pub(crate) fn restore(
+        source: &RVec<Element>,
+        rules: Arc<SummaryRules<Element, Token, Summary>>,
+        tokens: Vec<Token>,
+    ) -> Self
+    where
+        Summary: Default,
+    {
+        let mut summary = Summary::default();
+        for (i, token) in tokens.iter().enumerate() {
+            if token != &Token::default() {
+                (rules.contribute)(token, i, &mut summary);
+            }
+        }
+
+        Summarize {
+            rules,
+            tokens: RVec::reduced_from(source, tokens),
+            summary,
+        }
+    }
+
     pub(crate) fn update(&mut self, parent: &RVec<Element>) {
         let tokens = &mut self.tokens;
         let map = &self.rules.map;
@@ -41,6 +66,15 @@ where
         let uncontribute = &self.rules.uncontribute;
         let summary = &mut self.summary;
 
+        // The tokens are about to start over, so take back everything they contributed.
+        if tokens.would_reset(parent) {
+            for (i, old_token) in tokens.iter().enumerate() {
+                if old_token != &Token::default() {
+                    (uncontribute)(old_token, i, summary);
+                }
+            }
+        }
+
         tokens.reduce(parent, 1, move |elements, old_token, i| {
             if elements.is_empty() {
                 if old_token != &Token::default() {
@@ -69,6 +103,15 @@ where
     pub(crate) fn peek(&self) -> &Summary {
         &self.summary
     }
+
+    pub(crate) fn peek_mut(&mut self) -> &mut Summary {
+        &mut self.summary
+    }
+
+    /// The token most recently mapped from each element of the source.
+    pub(crate) fn tokens(&self) -> &[Token] {
+        &self.tokens
+    }
 }
 
 impl<Element, Token, Summary> MemoryUser for Summarize<Element, Token, Summary>
@@ -0,0 +1,57 @@
+use std::sync::{Arc, Mutex, Weak};
+
+/// Held by every `PinnedChunk` while it is alive, so that its `Storage` can tell which of its
+/// chunks are pinned.
+#[derive(Debug)]
+pub(crate) struct PinToken {
+    // the address of the pinned ChunkStorage, which can't be reused while the pin is alive
+    chunk: usize,
+    generation: u64,
+}
+
+/// Tracks the outstanding `PinnedChunks` of a single `Storage`.
+#[derive(Debug, Default)]
+pub(crate) struct PinRegistry {
+    // incremented every time a chunk is modified
+    generation: u64,
+    pins: Mutex<Vec<Weak<PinToken>>>,
+}
+
+impl PinRegistry {
+    /// Register a new pin of the chunk at the given address.
+    pub(crate) fn pin<T>(&self, chunk: *const T) -> Arc<PinToken> {
+        let token = Arc::new(PinToken {
+            chunk: chunk as usize,
+            generation: self.generation,
+        });
+
+        let mut pins = self.pins.lock().unwrap_or_else(|e| e.into_inner());
+        pins.retain(|pin| pin.strong_count() > 0);
+        pins.push(Arc::downgrade(&token));
+
+        token
+    }
+
+    /// Note that the chunk at the given address is about to be modified, panicking if it is
+    /// still pinned.
+    pub(crate) fn modify<T>(&mut self, chunk: *const T) {
+        let pins = self.pins.get_mut().unwrap_or_else(|e| e.into_inner());
+        pins.retain(|pin| pin.strong_count() > 0);
+
+        if let Some(token) = pins
+            .iter()
+            .filter_map(Weak::upgrade)
+            .find(|token| token.chunk == chunk as usize)
+        {
+            panic!(
+                "retriever: attempted to modify a chunk at generation {} while it is still pinned \
+                 by a PinnedChunk taken at generation {}. The modification would silently copy \
+                 the whole chunk. Drop the PinnedChunk before modifying the Storage, or clone \
+                 the Storage if you meant to take a snapshot.",
+                self.generation, token.generation
+            );
+        }
+
+        self.generation += 1;
+    }
+}
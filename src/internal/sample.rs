@@ -0,0 +1,76 @@
+use rand::Rng;
+
+/// A uniform random sample of at most `n` values, drawn from a stream of values one at a time.
+pub(crate) struct Reservoir<T> {
+    n: usize,
+    seen: usize,
+    sample: Vec<T>,
+}
+
+impl<T> Reservoir<T> {
+    pub(crate) fn new(n: usize) -> Self {
+        Reservoir {
+            n,
+            seen: 0,
+            sample: Vec::new(),
+        }
+    }
+
+    /// The number of values offered to this reservoir so far.
+    pub(crate) fn seen(&self) -> usize {
+        self.seen
+    }
+
+    pub(crate) fn offer<R>(&mut self, t: T, rng: &mut R)
+    where
+        R: Rng + ?Sized,
+    {
+        if self.sample.len() < self.n {
+            self.sample.push(t);
+        } else {
+            let j = rng.gen_range(0..=self.seen);
+            if j < self.n {
+                self.sample[j] = t;
+            }
+        }
+
+        self.seen += 1;
+    }
+
+    /// Combine two reservoirs drawn from disjoint streams into a uniform sample of both
+    /// streams. Each value is drawn from one side or the other in proportion to the number of
+    /// values that side has seen but not yet contributed.
+    pub(crate) fn merge<R>(mut self, mut other: Self, rng: &mut R) -> Self
+    where
+        R: Rng + ?Sized,
+    {
+        let n = self.n.min(other.n);
+        let total = self.seen + other.seen;
+        let mut sample = Vec::with_capacity(n.min(total));
+        let mut remaining_self = self.seen;
+        let mut remaining_other = other.seen;
+
+        while sample.len() < n && remaining_self + remaining_other > 0 {
+            let side = if rng.gen_range(0..remaining_self + remaining_other) < remaining_self {
+                remaining_self -= 1;
+                &mut self.sample
+            } else {
+                remaining_other -= 1;
+                &mut other.sample
+            };
+
+            let i = rng.gen_range(0..side.len());
+            sample.push(side.swap_remove(i));
+        }
+
+        Reservoir {
+            n,
+            seen: total,
+            sample,
+        }
+    }
+
+    pub(crate) fn into_sample(self) -> Vec<T> {
+        self.sample
+    }
+}
@@ -0,0 +1,36 @@
+/// The name of a type, like `std::any::type_name`, but without any module paths.
+pub(crate) fn short_type_name<T: ?Sized>() -> String {
+    let name = std::any::type_name::<T>();
+    let mut result = String::with_capacity(name.len());
+    let mut segment_start = 0;
+    let mut chars = name.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c == ':' && chars.peek() == Some(&':') {
+            chars.next();
+            result.truncate(segment_start);
+        } else {
+            result.push(c);
+
+            if !(c.is_alphanumeric() || c == '_') {
+                segment_start = result.len();
+            }
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::short_type_name;
+
+    #[test]
+    fn test_short_type_name() {
+        assert_eq!("u64", short_type_name::<u64>());
+        assert_eq!(
+            "HashMap<String, Vec<(u8, &str)>>",
+            short_type_name::<std::collections::HashMap<String, Vec<(u8, &str)>>>()
+        );
+    }
+}
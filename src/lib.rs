@@ -26,13 +26,13 @@
 //! * Choice of borrowed or computed (dynamic) keys (using [Cow](https://doc.rust-lang.org/std/borrow/enum.Cow.html)).
 //! * Map-reduce-style summaries, if you want them.
 //! * Chunking: (optional) all records belonging to the same chunk are stored together in the same Vec.
+//! * Parallelism across chunks, behind the `rayon` feature flag.
 //! * 100% safe Rust with no default dependencies.
 //! * Over 60 tests, doc-tests and benchmarks (need more)
 //! * Lots of full-featured examples to get started!
 //!
 //! ## Retriever does not have:
 //!
-//! * Parallelism. This is a "to-do".
 //! * Persistence. You can access the raw data for any chunk
 //!   and pass it to serde for serialization. See `Storage::raw()` for an example.
 //! * Networking. Retriever is embedded in your application like any other crate. It doesn't
@@ -306,12 +306,12 @@
 //! // Set up an index of puppies by their parent.
 //! // In SecondaryIndexes, we always return a collection of secondary keys.
 //! // (In this case, a HashSet containing the Ids of the parents.)
-//! let mut by_parents = SecondaryIndex::new(&storage,
+//! let by_parents = SecondaryIndex::new(&storage,
 //!   |puppy: &Puppy| Cow::Borrowed(&puppy.parents));
 //!
 //! // Use an index to search for all children of Yeller:
 //! let yeller_id = ID.chunk(2010).item(String::from("Yeller"));
-//! let q = Everything.matching(&mut by_parents, Cow::Borrowed(&yeller_id));
+//! let q = Everything.matching(&by_parents, Cow::Borrowed(&yeller_id));
 //! let mut children_of_yeller : Vec<_> = storage.query(&q)
 //!   .map(|puppy: &Puppy| &puppy.name).collect();
 //! children_of_yeller.sort();
@@ -434,11 +434,6 @@
 //! I'm also interested in any suggestions that would help further simplify the code base.
 //!
 //! ## To Do: (I want these features, but they aren't yet implemented)
-//! * Parallelism (will probably be implemented behind a rayon feature flag)
-//! * Sorted indexes / range queries
-//! * Boolean queries (union, intersection, difference, etc -- note: you can perform intersection
-//!   queries now just by chaining query operators)
-//! * External mutable iterators (currently only internal iteration is supported for modify)
 //! * More small vector optimization in some places where I expect it to matter
 //! * Need rigorous testing for space usage (currently no effort is made to shrink storage
 //!   or index vectors, this is probably priority #1 right now)
@@ -477,14 +472,25 @@ pub mod types;
 #[cfg(test)]
 mod test {
     use crate::prelude::*;
+    use crate::queries::boolean::{Not, Or};
+    use crate::queries::unique_index::UniqueIndex;
+    use crate::types::budget::Budget;
+    use crate::types::cursor::Cursor;
+    use crate::types::dedup_window::{DedupStats, DedupWindow};
+    use crate::types::prepared_query::PreparedQuery;
     use crate::types::reduction::Reduction;
+    use crate::types::storage_builder::{StorageBuilder, Strictness};
     use std::borrow::Cow;
+    use std::collections::{BTreeSet, HashMap};
+    use std::time::Duration;
 
     static_assertions::assert_impl_all!(Storage<u64,u64,(u64,u64,u64)>: Send, Sync);
     static_assertions::assert_impl_all!(Reduction<u64, (u64,u64,u64), u64>: Send, Sync);
     static_assertions::assert_impl_all!(SecondaryIndex<u64, (u64,u64,u64), std::collections::HashSet<u64>, u64>: Send, Sync);
+    static_assertions::assert_impl_all!(crate::types::published_storage::SnapshotReader<u64,u64,(u64,u64,u64)>: Send, Sync);
+    static_assertions::assert_impl_all!(crate::types::concurrent_storage::ConcurrentStorage<u64,u64,(u64,u64,u64)>: Send, Sync);
 
-    #[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+    #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
     struct X(u64, u64);
 
     impl Record<u64, u64> for X {
@@ -834,18 +840,3511 @@ mod test {
             .or_insert_with(|| X(1, 0));
     }
 
+    #[test]
+    #[cfg(feature = "debug_borrows")]
+    #[should_panic(expected = "still pinned")]
+    fn test_modify_pinned_chunk() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+
+        storage.add(X(0, 1));
+        storage.add(X(1, 1));
+
+        let _pinned = storage.pin_chunk(&0).unwrap();
+        storage.entry(&ID.chunk(0).item(0)).get_mut().unwrap().1 = 2;
+    }
+
+    #[test]
+    fn test_modify_after_unpinning_chunk() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+
+        storage.add(X(0, 1));
+        storage.add(X(1, 1));
+
+        let pinned = storage.pin_chunk(&0).unwrap();
+        storage.add(X(0x10, 1));
+        assert_eq!(&[X(0, 1), X(1, 1)][..], pinned.raw());
+        drop(pinned);
+
+        let snapshot = storage.clone();
+        storage.entry(&ID.chunk(0).item(0)).get_mut().unwrap().1 = 2;
+
+        assert_eq!(Some(&X(0, 2)), storage.get(&ID.chunk(0).item(0)));
+        assert_eq!(Some(&X(0, 1)), snapshot.get(&ID.chunk(0).item(0)));
+    }
+
     #[test]
     fn test_duplicate_clean() {
         let mut storage: Storage<u64, u64, X> = Storage::new();
 
-        storage.add(X(0, 0));
+        storage.add(X(0, 0));
+
+        storage.entry(&ID.chunk(0).item(0));
+        storage.remove(&ID.chunk(0).item(0), std::mem::drop);
+
+        storage.validate();
+    }
+
+    #[test]
+    fn test_try_add_chunks_leaves_storage_unchanged() {
+        use crate::types::error::{AddChunkError, ChunkMismatchError};
+
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        storage.add(X(0x001, 0x001));
+
+        let error = storage
+            .try_add_chunks(vec![
+                vec![X(0x002, 0x002), X(0x003, 0x003)],
+                vec![X(0x011, 0x011), X(0x021, 0x021)],
+            ])
+            .unwrap_err();
+
+        assert_eq!(
+            AddChunkError::ChunkMismatch(ChunkMismatchError {
+                element: X(0x021, 0x021),
+                group: 1,
+                position: 1,
+            }),
+            error
+        );
+        assert_eq!(1, storage.iter().count());
+
+        // Duplicates of a present element, within a group, and across groups are all refused.
+        for groups in [
+            vec![
+                vec![X(0x011, 0x011)],
+                vec![X(0x002, 0x002), X(0x001, 0x005)],
+            ],
+            vec![
+                vec![X(0x021, 0x021)],
+                vec![X(0x011, 0x011), X(0x011, 0x012)],
+            ],
+            vec![
+                vec![X(0x012, 0x012)],
+                vec![X(0x011, 0x011), X(0x012, 0x013)],
+            ],
+        ] {
+            let duplicate = groups[1][1];
+            assert_eq!(
+                Err(AddChunkError::DuplicateItem {
+                    element: duplicate,
+                    group: 1,
+                    position: 1,
+                }),
+                storage.try_add_chunks(groups)
+            );
+            assert_eq!(1, storage.iter().count());
+        }
+
+        storage
+            .try_add_chunks(vec![
+                vec![X(0x002, 0x002), X(0x003, 0x003)],
+                vec![X(0x011, 0x011), X(0x012, 0x012)],
+            ])
+            .unwrap();
+
+        assert_eq!(5, storage.iter().count());
+        storage.validate();
+    }
+
+    #[test]
+    fn test_try_validate_reports_duplicate_item_key() {
+        use crate::types::error::ValidationError;
+
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        storage.add(X(0x001, 0x001));
+        storage.add(X(0x002, 0x002));
+        assert_eq!(Ok(()), storage.try_validate());
+
+        // Changing an element's item key behind retriever's back corrupts the storage.
+        storage.entry(&ID.chunk(0).item(0x002)).get_mut().unwrap().0 = 0x001;
+
+        assert_eq!(
+            Err(ValidationError::DuplicateItemKey {
+                chunk_key: 0,
+                item_key: 0x001,
+                indexed_idx: 0,
+                duplicate_idx: 1,
+            }),
+            storage.try_validate()
+        );
+    }
+
+    #[test]
+    fn test_clone_is_copy_on_write() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let index: SecondaryIndex<u64, X, Option<u64>, u64> =
+            SecondaryIndex::new(&storage, |x: &X| Cow::Owned(Some(x.1)));
+
+        storage.add(X(0x001, 0x001));
+        storage.add(X(0x011, 0x001));
+        storage.add(X(0x021, 0x002));
+
+        let snapshot = storage.clone();
+
+        storage.modify(&ID.chunk(0).item(0x001), |mut editor| {
+            editor.get_mut().1 = 0x002;
+        });
+        storage.remove(&ID.chunk(1).item(0x011), std::mem::drop);
+        storage.add(X(0x031, 0x002));
+
+        assert_eq!(
+            Some(&X(0x001, 0x002)),
+            storage.get(&ID.chunk(0).item(0x001))
+        );
+        assert_eq!(
+            Some(&X(0x001, 0x001)),
+            snapshot.get(&ID.chunk(0).item(0x001))
+        );
+        assert_eq!(None, storage.get(&ID.chunk(1).item(0x011)));
+        assert_eq!(
+            Some(&X(0x011, 0x001)),
+            snapshot.get(&ID.chunk(1).item(0x011))
+        );
+        assert_eq!(None, snapshot.get(&ID.chunk(3).item(0x031)));
+
+        assert_eq!(
+            3,
+            storage
+                .query(&Everything.matching(&index, Cow::Owned(0x002)))
+                .count()
+        );
+
+        storage.validate();
+        index.validate(&storage);
+        snapshot.clone().validate();
+        assert_eq!(3, snapshot.dissolve().into_iter().flatten().count());
+    }
+
+    #[test]
+    fn test_repair_rebinds_reduction_to_new_storage() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let mut reduction: Reduction<u64, X, u64> = Reduction::new(
+            &storage,
+            2,
+            |x: &X, _| Some(x.1),
+            |xs: &[u64], _| Some(xs.iter().sum()),
+        );
+
+        storage.add(X(0x001, 1)).add(X(0x011, 2));
+        assert_eq!(Some(&3), reduction.reduce(&storage));
+
+        let mut other: Storage<u64, u64, X> =
+            StorageBuilder::new().strictness(Strictness::Repair).build();
+        other.add(X(0x001, 10)).add(X(0x001, 20)).add(X(0x021, 30));
+
+        assert_eq!(Some(&50), reduction.reduce(&other));
+        other.validate();
+    }
+
+    #[test]
+    fn test_transfer_chunk_keeps_indices_consistent() {
+        let mut hot: Storage<u64, u64, X> = Storage::new();
+        let mut cold: Storage<u64, u64, X> = Storage::new();
+        let index: SecondaryIndex<u64, X, Option<u64>, u64> =
+            SecondaryIndex::new(&cold, |x: &X| Cow::Owned(Some(x.1)));
+
+        hot.add(X(0x001, 0x001));
+        hot.add(X(0x011, 0x001));
+        hot.add(X(0x021, 0x002));
+        cold.add(X(0x022, 0x002));
+
+        assert_eq!(
+            1,
+            cold.query(&Everything.matching(&index, Cow::Owned(0x002)))
+                .count()
+        );
+
+        assert!(hot.transfer_chunk(&0, &mut cold));
+        assert!(hot.transfer_chunk(&2, &mut cold));
+        assert_eq!(Some(&X(0x011, 0x001)), hot.get(&ID.chunk(1).item(0x011)));
+
+        assert_eq!(
+            2,
+            cold.query(&Everything.matching(&index, Cow::Owned(0x002)))
+                .count()
+        );
+        assert_eq!(
+            1,
+            cold.query(&Everything.matching(&index, Cow::Owned(0x001)))
+                .count()
+        );
+
+        hot.validate();
+        cold.validate();
+        index.validate(&cold);
+    }
+
+    #[test]
+    fn test_append_merges_colliding_chunks() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let mut other: Storage<u64, u64, X> = Storage::new();
+        let index: SecondaryIndex<u64, X, Option<u64>, u64> =
+            SecondaryIndex::new(&storage, |x: &X| Cow::Owned(Some(x.1)));
+
+        storage.add(X(0x001, 0x001));
+        storage.add(X(0x011, 0x002));
+        other.add(X(0x002, 0x002));
+        other.add(X(0x021, 0x002));
+
+        assert_eq!(
+            Err(crate::types::error::ChunkCollisionError { chunk_key: 0 }),
+            storage.try_append(&mut other)
+        );
+        assert_eq!(2, other.iter().count());
+
+        storage.append(&mut other);
+
+        assert_eq!(4, storage.iter().count());
+        assert_eq!(0, other.iter().count());
+        assert_eq!(
+            Some(&X(0x002, 0x002)),
+            storage.get(&ID.chunk(0).item(0x002))
+        );
+        assert_eq!(
+            3,
+            storage
+                .query(&Everything.matching(&index, Cow::Owned(0x002)))
+                .count()
+        );
+
+        storage.validate();
+        other.validate();
+        index.validate(&storage);
+    }
+
+    #[test]
+    fn test_reduction_sees_modified_and_emptied_chunks() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let mut reduction: Reduction<u64, X, u64> = Reduction::new(
+            &storage,
+            2,
+            |x: &X, _| Some(x.1),
+            |xs: &[u64], _| Some(xs.iter().sum()),
+        );
+
+        storage.add(X(0x001, 1)).add(X(0x002, 2)).add(X(0x011, 4));
+        assert_eq!(Some(&7), reduction.reduce(&storage));
+
+        storage.modify(&ID.chunk(0).item(0x002), |mut editor| {
+            editor.get_mut().1 = 8;
+        });
+        assert_eq!(Some(&13), reduction.reduce(&storage));
+
+        storage.remove(&ID.chunk(1).item(0x011), std::mem::drop);
+        assert_eq!(Some(&9), reduction.reduce(&storage));
+    }
+
+    #[test]
+    // ChunkRange(6..2) is deliberately empty, to check that reversed bounds match nothing.
+    #[allow(clippy::reversed_empty_ranges)]
+    fn test_chunk_range_with_and_without_ordered_chunk_keys() {
+        use crate::queries::chunk_range::ChunkRange;
+
+        let mut ordered: Storage<u64, u64, X> =
+            StorageBuilder::new().ordered_chunk_keys(true).build();
+        let mut unordered: Storage<u64, u64, X> = Storage::new();
+
+        for storage in [&mut ordered, &mut unordered] {
+            for i in 0..8 {
+                storage.add(X(i << 4, i));
+            }
+            storage.remove(&ID.chunk(3).item(0x030), std::mem::drop);
+            storage.remove_chunk(&5);
+
+            let mut ids: Vec<u64> = storage.query(ChunkRange(2..=6)).map(|x| x.1).collect();
+            ids.sort();
+            assert_eq!(vec![2, 4, 6], ids);
+            assert_eq!(0, storage.query(ChunkRange(6..2)).count());
+            storage.validate();
+        }
+
+        assert!(ordered.has_ordered_chunk_keys());
+        assert!(!unordered.has_ordered_chunk_keys());
+    }
+
+    #[test]
+    fn test_item_queries_with_and_without_ordered_item_keys() {
+        let mut ordered: Storage<u64, u64, X> =
+            StorageBuilder::new().ordered_item_keys(true).build();
+        let mut unordered: Storage<u64, u64, X> = Storage::new();
+
+        for storage in [&mut ordered, &mut unordered] {
+            for i in [0x37, 0x31, 0x3A, 0x30, 0x35, 0x3F, 0x52, 0x33] {
+                storage.add(X(i, i));
+            }
+            storage.remove(ID.chunk(3).item(0x30), std::mem::drop);
+
+            let mut ids: Vec<u64> = storage
+                .query(ID.chunk(3).items(0x32..=0x3A))
+                .map(|x| x.1)
+                .collect();
+            ids.sort();
+            assert_eq!(vec![0x33, 0x35, 0x37, 0x3A], ids);
+            let (start, end) = (0x3A, 0x32);
+            assert_eq!(0, storage.query(ID.chunk(3).items(start..end)).count());
+
+            let mut ids: Vec<u64> = storage
+                .query(Query::<u64, u64, X>::after(
+                    Everything,
+                    ID.chunk(3).item(0x35),
+                ))
+                .map(|x| x.1)
+                .collect();
+            ids.sort();
+            assert_eq!(vec![0x37, 0x3A, 0x3F, 0x52], ids);
+
+            assert_eq!(Some(&0x31), storage.min_item_key(&3));
+            assert_eq!(Some(&0x3F), storage.max_item_key(&3));
+
+            let mut iter = storage.iter_resumable();
+            let mut ids = Vec::new();
+            while let Some(x) = iter.next_element(storage) {
+                ids.push(x.1);
+            }
+            assert_eq!(vec![0x31, 0x33, 0x35, 0x37, 0x3A, 0x3F, 0x52], ids);
+
+            storage.validate();
+        }
+
+        assert!(ordered.has_ordered_item_keys());
+        assert!(!unordered.has_ordered_item_keys());
+    }
+
+    #[test]
+    fn test_limit_spans_chunks_and_resets() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        for i in 0..0x40 {
+            storage.add(X(i, i));
+        }
+
+        let query = Everything.filter(|x: &X| x.1 % 3 == 0).limit(10);
+        assert_eq!(10, storage.query(&query).count());
+        assert_eq!(10, storage.query(&query).count());
+        assert_eq!(4, storage.query(query.clone().skip(6)).count());
+
+        storage.modify(&query, |mut editor| editor.get_mut().1 = 1);
+        assert_eq!(
+            12,
+            storage
+                .query(Everything.filter(|x: &X| x.1 % 3 == 0))
+                .count()
+        );
+    }
+
+    #[test]
+    fn test_limit_chooses_the_same_elements_however_run() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        for i in 0..0x40 {
+            storage.add(X(i, i));
+        }
+
+        let everything = Everything.filter(|_: &X| true);
+        let limit = everything.limit(3);
+        let skip = everything.skip(0x3E);
+
+        let first: Vec<X> = storage.query(&limit).cloned().collect();
+        assert_eq!(vec![X(0, 0), X(1, 1), X(2, 2)], first);
+
+        assert_eq!(3, storage.query(&limit).zip(storage.query(&limit)).count());
+        assert_eq!(2, storage.query(&skip).zip(storage.query(&skip)).count());
+        assert_eq!(0, storage.query(limit.clone().and(Chunks([1]))).count());
+        assert_eq!(0, storage.query(skip.clone().and(Chunks([0]))).count());
+        assert_eq!(3, storage.count(&limit));
+
+        #[cfg(feature = "rayon")]
+        {
+            use rayon::prelude::*;
+
+            let mut actual: Vec<X> = storage.par_query(&limit).cloned().collect();
+            actual.sort();
+            assert_eq!(first, actual);
+        }
+    }
+
+    #[test]
+    fn test_compare_and_swap_repairs_changed_id() {
+        let mut storage: Storage<u64, u64, X> =
+            StorageBuilder::new().strictness(Strictness::Repair).build();
+        storage.add(X(0x001, 1)).add(X(0x011, 2));
+
+        let result = storage.compare_and_swap(&ID.chunk(0).item(0x001), |_| Some(X(0x012, 3)));
+        assert_eq!(Some(X(0x001, 1)), result.into_previous());
+
+        assert_eq!(None, storage.get(&ID.chunk(0).item(0x001)));
+        assert_eq!(Some(&X(0x012, 3)), storage.get(&ID.chunk(1).item(0x012)));
+        storage.validate();
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_compare_and_swap_rejects_changed_id() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        storage.add(X(0x001, 1));
+
+        storage.compare_and_swap(&ID.chunk(0).item(0x001), |_| Some(X(0x002, 1)));
+    }
+
+    #[test]
+    fn test_iter_resumable_with_additions() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        storage.add(X(0x001, 1)).add(X(0x002, 2)).add(X(0x011, 3));
+
+        let mut plain = storage.iter_resumable();
+        let mut additions = storage.iter_resumable().include_additions(true);
+        assert_eq!(Some(&X(0x001, 1)), plain.next_element(&storage));
+        assert_eq!(Some(&X(0x001, 1)), additions.next_element(&storage));
+        assert_eq!(Some(&X(0x002, 2)), plain.next_element(&storage));
+        assert_eq!(Some(&X(0x002, 2)), additions.next_element(&storage));
+
+        // behind the cursor in the current chunk, and ahead of the cursor
+        storage.add(X(0x000, 4)).add(X(0x003, 5)).add(X(0x012, 6));
+
+        let rest: Vec<u64> = plain.resume(&storage).map(|x| x.0).collect();
+        assert_eq!(vec![0x003, 0x011, 0x012], rest);
+
+        let rest: Vec<u64> = additions.resume(&storage).take(2).map(|x| x.0).collect();
+        assert_eq!(vec![0x003, 0x011], rest);
+
+        // in a chunk that has already been visited
+        storage.add(X(0x004, 7));
+
+        let rest: Vec<u64> = additions.resume(&storage).map(|x| x.0).collect();
+        assert_eq!(vec![0x012, 0x004], rest);
+
+        assert_eq!(None, plain.next_element(&storage));
+        assert_eq!(None, additions.next_element(&storage));
+    }
+
+    #[test]
+    fn test_boolean_queries_with_secondary_index() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let index: SecondaryIndex<u64, X, Option<u64>, u64> =
+            SecondaryIndex::new(&storage, |x: &X| Cow::Owned(Some(x.1 & 0x1)));
+
+        storage
+            .add(X(0x001, 1))
+            .add(X(0x002, 2))
+            .add(X(0x011, 3))
+            .add(X(0x012, 4))
+            .add(X(0x021, 5));
+
+        fn ids<Q: Query<u64, u64, X> + Clone>(
+            storage: &Storage<u64, u64, X>,
+            query: Q,
+        ) -> Vec<u64> {
+            let mut result: Vec<u64> = storage.query(query).map(|x| x.0).collect();
+            result.sort();
+            result
+        }
+
+        let odd = Everything.matching(&index, Cow::Owned(1));
+
+        assert_eq!(vec![0x011], ids(&storage, odd.clone().and(Chunks([1]))));
+        assert_eq!(
+            vec![0x001, 0x002, 0x011, 0x021],
+            ids(&storage, odd.clone().or(ID.chunk(0).item(0x002)))
+        );
+        assert_eq!(vec![0x002, 0x012], ids(&storage, odd.clone().not()));
+        assert_eq!(
+            vec![0x001, 0x021],
+            ids(&storage, Not::new(Chunks([1])).and(odd))
+        );
+    }
+
+    #[test]
+    fn test_matching_any_agrees_with_or() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let index: SecondaryIndex<u64, X, Option<u64>, u64> =
+            SecondaryIndex::new(&storage, |x: &X| Cow::Owned(Some(x.1 % 4)));
+
+        for i in 0..300 {
+            storage.add(X(i, i * 7));
+        }
+
+        fn ids<Q: Query<u64, u64, X> + Clone>(
+            storage: &Storage<u64, u64, X>,
+            query: Q,
+        ) -> Vec<u64> {
+            let mut result: Vec<u64> = storage.query(query).map(|x| x.0).collect();
+            result.sort();
+            result
+        }
+
+        let any =
+            Everything.matching_any(&index, vec![Cow::Owned(0), Cow::Owned(2), Cow::Owned(9)]);
+        let or = Everything
+            .matching(&index, Cow::Owned(0))
+            .or(Everything.matching(&index, Cow::Owned(2)));
+
+        assert_eq!(150, ids(&storage, any.clone()).len());
+        assert_eq!(ids(&storage, or.clone()), ids(&storage, any.clone()));
+
+        storage.modify(ID.chunk(0).item(1), |mut x| x.get_mut().1 = 2);
+        storage.remove(ID.chunk(0).item(2), std::mem::drop);
+
+        assert_eq!(150, ids(&storage, any.clone()).len());
+        assert_eq!(ids(&storage, or), ids(&storage, any.clone()));
+        assert!(ids(
+            &storage,
+            Everything.matching_any(&index, Vec::<Cow<u64>>::new())
+        )
+        .is_empty());
+
+        index.validate(&storage);
+    }
+
+    #[test]
+    fn test_text_index_agrees_with_filter() {
+        use crate::queries::text_index::TextPattern;
+
+        let mut storage: Storage<str, str, S> = Storage::new();
+        let index: TextIndex<str, S> = TextIndex::words(&storage, |s: &S| s.2.as_str());
+        let messages = [
+            "disk full",
+            "timeout: retrying",
+            "retry ok",
+            "full timeout",
+            "",
+        ];
+
+        for i in 0..100 {
+            storage.add(S(
+                format!("chunk{}", i % 3),
+                format!("item{}", i),
+                String::from(messages[i % messages.len()]),
+            ));
+        }
+
+        let count = |storage: &Storage<str, str, S>, pattern: TextPattern| {
+            storage
+                .query(Everything.matching_text(&index, pattern))
+                .count()
+        };
+
+        assert_eq!(40, count(&storage, TextPattern::word("timeout")));
+        assert_eq!(0, count(&storage, TextPattern::word("time")));
+        assert_eq!(20, count(&storage, TextPattern::word("disk")));
+        assert_eq!(40, count(&storage, TextPattern::prefix("retr")));
+        assert_eq!(
+            storage
+                .query(Everything.filter(|s: &S| s.2.contains("ful")))
+                .count(),
+            count(&storage, TextPattern::matching(|w| w.contains("ful")))
+        );
+        assert_eq!(
+            20,
+            count(
+                &storage,
+                TextPattern::all(vec![
+                    TextPattern::word("full"),
+                    TextPattern::word("timeout")
+                ])
+            )
+        );
+        assert_eq!(
+            60,
+            count(
+                &storage,
+                TextPattern::any(vec![TextPattern::word("disk"), TextPattern::prefix("retr")])
+            )
+        );
+        assert_eq!(
+            40,
+            count(
+                &storage,
+                TextPattern::all(vec![
+                    TextPattern::any(vec![TextPattern::word("full"), TextPattern::word("ok")]),
+                    TextPattern::any(vec![TextPattern::word("disk"), TextPattern::word("retry")]),
+                ])
+            )
+        );
+        assert_eq!(100, count(&storage, TextPattern::all(vec![])));
+        assert_eq!(0, count(&storage, TextPattern::any(vec![])));
+
+        storage.modify(ID.chunk("chunk0").item("item0"), |mut s| {
+            s.get_mut().2 = String::from("timeout")
+        });
+        storage.remove(ID.chunk("chunk1").item("item1"), std::mem::drop);
+
+        assert_eq!(40, count(&storage, TextPattern::word("timeout")));
+        assert_eq!(19, count(&storage, TextPattern::word("disk")));
+
+        storage.validate();
+        index.validate(&storage);
+    }
+
+    #[test]
+    fn test_composite_index_agrees_with_filter() {
+        use crate::queries::composite_index::{CompositeIndex, CompositePattern};
+
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let index: CompositeIndex<u64, X, u64, u64> =
+            CompositeIndex::new(&storage, |x: &X| (x.1 % 3, x.1 % 5));
+
+        for i in 0..200 {
+            storage.add(X(i, i * 7));
+        }
+
+        let count = |storage: &Storage<u64, u64, X>, pattern: CompositePattern<u64, u64>| {
+            storage
+                .query(Everything.matching_composite(&index, pattern))
+                .count()
+        };
+        let check = |storage: &Storage<u64, u64, X>| {
+            for a in 0..4 {
+                assert_eq!(
+                    storage
+                        .query(Everything.filter(move |x: &X| x.1 % 3 == a))
+                        .count(),
+                    count(storage, CompositePattern::Prefix(a))
+                );
+
+                for b in 0..6 {
+                    assert_eq!(
+                        storage
+                            .query(Everything.filter(move |x: &X| x.1 % 3 == a && x.1 % 5 == b))
+                            .count(),
+                        count(storage, CompositePattern::Exact(a, b))
+                    );
+                }
+            }
+        };
+
+        check(&storage);
+
+        storage.modify(Everything.filter(|x: &X| x.0 % 4 == 1), |mut x| {
+            x.get_mut().1 += 1;
+        });
+        storage.remove(Everything.filter(|x: &X| x.0 % 9 == 2), std::mem::drop);
+
+        check(&storage);
+
+        storage.validate();
+        index.validate(&storage);
+    }
+
+    #[test]
+    fn test_chunk_tag_index_follows_added_and_removed_chunks() {
+        use crate::queries::chunk_tag_index::{ChunkTagIndex, ChunksTagged};
+
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let index: ChunkTagIndex<u64, u64> = ChunkTagIndex::new(&storage, |chunk_key: &u64| {
+            vec![chunk_key % 3, 10 + chunk_key % 2]
+        });
+
+        let check = |storage: &Storage<u64, u64, X>| {
+            for tag in &[0, 1, 2, 10, 11, 12] {
+                let tag = *tag;
+                let expected = storage
+                    .query(Everything.filter(move |x: &X| {
+                        let chunk_key = (x.0 & 0xF0) >> 4;
+                        chunk_key % 3 == tag || 10 + chunk_key % 2 == tag
+                    }))
+                    .count();
+                assert_eq!(
+                    expected,
+                    storage.query(ChunksTagged::new(&index, tag)).count()
+                );
+            }
+            index.validate(storage);
+        };
+
+        for i in 0..0x80 {
+            storage.add(X(i, i));
+        }
+        check(&storage);
+
+        storage.remove_chunk(&1);
+        storage.remove_chunk(&4);
+        check(&storage);
+
+        for i in 0x100..0x180 {
+            storage.add(X(i, i));
+        }
+        storage.remove(
+            Everything.filter(|x: &X| (x.0 & 0xF0) >> 4 == 2),
+            std::mem::drop,
+        );
+        check(&storage);
+
+        let mut chunk_keys = index.chunk_keys(&storage, &11);
+        chunk_keys.sort();
+        assert_eq!(vec![1, 3, 5, 7], chunk_keys);
+
+        storage.validate();
+    }
+
+    #[test]
+    fn test_counting_index_agrees_with_group_by() {
+        use crate::queries::counting_index::CountingIndex;
+
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let index: CountingIndex<u64, X, BTreeSet<u64>, u64> =
+            CountingIndex::new(&storage, |x: &X| {
+                Cow::Owned([x.1 % 3, 10 + x.1 % 2].iter().cloned().collect())
+            });
+
+        let check = |storage: &Storage<u64, u64, X>| {
+            let mut expected: HashMap<u64, usize> = HashMap::new();
+            for (k, group) in storage.group_by(Everything, |x: &X| x.1 % 3) {
+                expected.insert(k, group.len());
+            }
+            for (k, group) in storage.group_by(Everything, |x: &X| 10 + x.1 % 2) {
+                expected.insert(k, group.len());
+            }
+            assert_eq!(expected, storage.counts_by(&index));
+            index.validate(storage);
+        };
+
+        for i in 0..0x80 {
+            storage.add(X(i, i));
+        }
+        check(&storage);
+
+        storage.modify(Everything.filter(|x: &X| x.0 % 5 == 0), |mut editor| {
+            editor.get_mut().1 += 1;
+        });
+        check(&storage);
+
+        storage.remove_chunk(&3);
+        storage.remove(Everything.filter(|x: &X| x.0 % 7 == 0), std::mem::drop);
+        check(&storage);
+
+        for i in 0x30..0x40 {
+            storage.add(X(i, 0));
+        }
+        check(&storage);
+
+        storage.validate();
+    }
+
+    #[test]
+    fn test_spatial_index_agrees_with_filter() {
+        use crate::queries::spatial_index::{SpatialIndex, SpatialPattern};
+
+        fn position(x: &X) -> (f64, f64) {
+            ((x.1 % 50) as f64 - 20.0, (x.1 / 50 % 50) as f64 * 0.5)
+        }
+
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let index: SpatialIndex<u64, X> = SpatialIndex::new(&storage, 4.0, position);
+
+        let patterns = [
+            SpatialPattern::Rectangle {
+                min: (-7.5, 0.0),
+                max: (3.0, 6.5),
+            },
+            SpatialPattern::Rectangle {
+                min: (10.0, 10.0),
+                max: (-10.0, 20.0),
+            },
+            SpatialPattern::Radius {
+                center: (0.0, 5.0),
+                radius: 6.0,
+            },
+            SpatialPattern::Radius {
+                center: (-19.0, 0.0),
+                radius: 0.0,
+            },
+        ];
+
+        let check = |storage: &Storage<u64, u64, X>| {
+            for pattern in patterns.iter().cloned() {
+                let expected: BTreeSet<X> = storage
+                    .query(Everything.filter(move |x: &X| {
+                        let p = position(x);
+                        match pattern {
+                            SpatialPattern::Rectangle { min, max } => {
+                                min.0 <= p.0 && p.0 <= max.0 && min.1 <= p.1 && p.1 <= max.1
+                            }
+                            SpatialPattern::Radius { center, radius } => {
+                                (p.0 - center.0).powi(2) + (p.1 - center.1).powi(2)
+                                    <= radius * radius
+                            }
+                        }
+                    }))
+                    .cloned()
+                    .collect();
+                let actual: BTreeSet<X> = storage
+                    .query(Everything.matching_spatial(&index, pattern))
+                    .cloned()
+                    .collect();
+                assert_eq!(expected, actual);
+            }
+            index.validate(storage);
+        };
+
+        for i in 0..0x400 {
+            storage.add(X(i, i * 7));
+        }
+        check(&storage);
+
+        storage.modify(Everything.filter(|x: &X| x.0 % 3 == 0), |mut editor| {
+            editor.get_mut().1 += 13;
+        });
+        check(&storage);
+
+        storage.remove_chunk(&5);
+        storage.remove(Everything.filter(|x: &X| x.0 % 5 == 0), std::mem::drop);
+        check(&storage);
+
+        storage.validate();
+    }
+
+    #[test]
+    fn test_prefix_index_agrees_with_filter() {
+        use crate::queries::prefix_index::PrefixIndex;
+
+        fn path(x: &X) -> Vec<u64> {
+            (0..x.1 % 3 + 1).map(|d| (x.1 >> (2 * d)) & 0x3).collect()
+        }
+
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let index: PrefixIndex<u64, X, u64> = PrefixIndex::new(&storage, path);
+
+        let prefixes: Vec<Vec<u64>> =
+            vec![vec![], vec![1], vec![1, 2], vec![3, 0, 1], vec![2, 2, 2, 2]];
+
+        let check = |storage: &Storage<u64, u64, X>| {
+            for prefix in prefixes.iter() {
+                let filter_prefix = prefix.clone();
+                let expected: BTreeSet<X> = storage
+                    .query(Everything.filter(move |x: &X| path(x).starts_with(&filter_prefix)))
+                    .cloned()
+                    .collect();
+                let actual: BTreeSet<X> = storage
+                    .query(Everything.matching_prefix(&index, prefix.clone()))
+                    .cloned()
+                    .collect();
+                assert_eq!(expected, actual);
+                assert_eq!(expected.len(), index.count(storage, prefix.clone()));
+
+                let children = index.children(storage, prefix.clone());
+                for (segment, count) in children.iter() {
+                    let mut child = prefix.clone();
+                    child.push(*segment);
+                    assert_eq!(*count, index.count(storage, child));
+                }
+            }
+            index.validate(storage);
+        };
+
+        for i in 0..0x400 {
+            storage.add(X(i, i * 7));
+        }
+        check(&storage);
+
+        storage.modify(Everything.filter(|x: &X| x.0 % 3 == 0), |mut editor| {
+            editor.get_mut().1 += 13;
+        });
+        check(&storage);
+
+        storage.remove_chunk(&5);
+        storage.remove(Everything.filter(|x: &X| x.0 % 5 == 0), std::mem::drop);
+        check(&storage);
+
+        storage.validate();
+    }
+
+    #[test]
+    fn test_shared_index_labels_each_storage() {
+        use crate::queries::shared_index::SharedIndex;
+
+        let mut a: Storage<u64, u64, X> = Storage::new();
+        let mut b: Storage<u64, u64, X> = Storage::new();
+        let c: Storage<u64, u64, X> = Storage::new();
+        let index: SharedIndex<char, u64, X, Option<u64>, u64> =
+            SharedIndex::new(|x: &X| Cow::Owned(Some(x.1 % 4)));
+        index.register(&a, 'a');
+        index.register(&b, 'b');
+
+        for i in 0..0x100 {
+            a.add(X(i, i));
+            b.add(X(i, i + 1));
+        }
+
+        let count = |storages: Vec<&Storage<u64, u64, X>>, label: char| {
+            index
+                .matching(storages, &0)
+                .into_iter()
+                .filter(|(l, x)| *l == label && x.1 % 4 == 0)
+                .count()
+        };
+        assert_eq!(0x40, count(vec![&a, &b], 'a'));
+        assert_eq!(0x40, count(vec![&a, &b], 'b'));
+        assert_eq!(0x80, index.matching(vec![&a, &b], &0).len());
+        assert_eq!(0x40, index.matching(vec![&b], &0).len());
+
+        b.modify(Everything.filter(|x: &X| x.0 < 0x10), |mut editor| {
+            editor.get_mut().1 = 0
+        });
+        assert_eq!(0x4C, count(vec![&a, &b], 'b'));
+
+        index.register(&b, 'c');
+        assert_eq!(Some('c'), index.label_of(&b));
+        assert_eq!(0x4C, count(vec![&b], 'c'));
+
+        assert_eq!(None, index.label_of(&c));
+        assert_eq!(Some('a'), index.unregister(&a));
+        assert_eq!(None, index.label_of(&a));
+
+        index.validate(vec![&b]);
+    }
+
+    #[test]
+    fn test_cached_index_only_extracts_changed_elements() {
+        use crate::queries::cached_index::CachedIndex;
+        use crate::traits::memory_usage::MemoryUser;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let extractions = Arc::new(AtomicUsize::new(0));
+        let extractions_in_rule = Arc::clone(&extractions);
+
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let index: CachedIndex<u64, u64, X, u64, Option<u64>, u64> = CachedIndex::new(
+            &storage,
+            move |x: &X| {
+                extractions_in_rule.fetch_add(1, Ordering::SeqCst);
+                x.1 % 4
+            },
+            |value: &u64| Cow::Owned(Some(*value)),
+        );
+
+        for i in 0..0x100 {
+            storage.add(X(i, i));
+        }
+
+        let matching = |storage: &Storage<u64, u64, X>, value: u64| {
+            storage
+                .query(Everything.matching(index.as_secondary_index(), Cow::Owned(value)))
+                .cloned()
+                .collect::<BTreeSet<X>>()
+        };
+        let filtered = |storage: &Storage<u64, u64, X>, value: u64| {
+            storage
+                .query(Everything.filter(move |x: &X| x.1 % 4 == value))
+                .cloned()
+                .collect::<BTreeSet<X>>()
+        };
+
+        assert_eq!(filtered(&storage, 1), matching(&storage, 1));
+        assert_eq!(0x100, extractions.load(Ordering::SeqCst));
+
+        storage.modify(ID.chunk(2).item(0x23), |mut editor| editor.get_mut().1 = 4);
+        storage.remove(ID.chunk(3).item(0x30), std::mem::drop);
+        assert_eq!(filtered(&storage, 0), matching(&storage, 0));
+        assert_eq!(0x101, extractions.load(Ordering::SeqCst));
+
+        assert_eq!(Some(0), index.cached_value_of(&storage, &X(0x23, 4)));
+        assert_eq!(Some(3), index.cached_value_of(&storage, &X(0x3F, 0x3F)));
+        assert_eq!(None, index.cached_value_of(&storage, &X(0x30, 0x30)));
+
+        let before = index.memory_usage();
+        index.evict_removed(&storage);
+        assert_eq!(before.len - 1, index.memory_usage().len);
+        assert_eq!(0x101, extractions.load(Ordering::SeqCst));
+
+        index.validate(&storage);
+    }
+
+    #[test]
+    fn test_normalized_index_agrees_with_filter() {
+        use crate::queries::normalized_index::{NormalizedIndex, Normalizer};
+
+        let mut storage: Storage<u64, u64, (u64, u64, String)> = Storage::new();
+        let normalizer = Normalizer::default().trim().case_fold();
+        let index: NormalizedIndex<u64, (u64, u64, String)> =
+            NormalizedIndex::new(&storage, normalizer.clone(), |x: &(u64, u64, String)| {
+                x.2.as_str()
+            });
+
+        let spellings = ["Alpha", "ALPHA ", " alpha", "Beta", "bEtA\t", "gamma"];
+        for i in 0..0x40 {
+            storage.add((
+                i % 4,
+                i,
+                String::from(spellings[i as usize % spellings.len()]),
+            ));
+        }
+
+        let check = |storage: &Storage<u64, u64, (u64, u64, String)>, key: &str| {
+            let normalized = key.trim().to_lowercase();
+            let expected: BTreeSet<u64> =
+                storage
+                    .query(Everything.filter(move |x: &(u64, u64, String)| {
+                        x.2.trim().to_lowercase() == normalized
+                    }))
+                    .map(|x| x.1)
+                    .collect();
+            let actual: BTreeSet<u64> = storage
+                .query(Everything.matching_normalized(&index, key))
+                .map(|x| x.1)
+                .collect();
+            assert_eq!(expected, actual);
+            actual.len()
+        };
+
+        assert_eq!(0x21, check(&storage, " aLPHA"));
+        assert_eq!(0x15, check(&storage, "BETA"));
+        assert_eq!(0, check(&storage, "delta"));
+
+        storage.modify(
+            Everything.filter(|x: &(u64, u64, String)| x.1 < 0x10),
+            |mut editor| editor.get_mut().2 = String::from("  Delta  "),
+        );
+        assert_eq!(0x10, check(&storage, "delta"));
+        assert_eq!(0x18, check(&storage, "Alpha"));
+
+        index.validate(&storage);
+    }
+
+    #[cfg(feature = "unicode-normalization")]
+    #[test]
+    fn test_normalized_index_nfc() {
+        use crate::queries::normalized_index::{NormalizedIndex, Normalizer};
+
+        let mut storage: Storage<u64, u64, (u64, u64, String)> = Storage::new();
+        let index: NormalizedIndex<u64, (u64, u64, String)> = NormalizedIndex::new(
+            &storage,
+            Normalizer::default().nfc().case_fold(),
+            |x: &(u64, u64, String)| x.2.as_str(),
+        );
+
+        storage.add((0, 0, String::from("Caf\u{e9}")));
+        storage.add((0, 1, String::from("CAFE\u{301}")));
+        storage.add((0, 2, String::from("Cafe")));
+
+        assert_eq!(
+            2,
+            storage
+                .query(Everything.matching_normalized(&index, "cafe\u{301}"))
+                .count()
+        );
+
+        index.validate(&storage);
+    }
+
+    #[test]
+    fn test_histogram_index_agrees_with_filter() {
+        use crate::queries::histogram_index::HistogramIndex;
+
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let index: HistogramIndex<u64, X> = HistogramIndex::new(&storage, 16.0, |x: &X| x.1 as f64);
+
+        for i in 0..0x100 {
+            storage.add(X(i, i));
+        }
+
+        let check = |storage: &Storage<u64, u64, X>, start: u64, end: u64| {
+            let expected: BTreeSet<X> = storage
+                .query(Everything.filter(move |x: &X| start <= x.1 && x.1 < end))
+                .cloned()
+                .collect();
+            let query = Everything.matching_histogram(&index, start as f64..end as f64);
+            let actual: BTreeSet<X> = storage.query(&query).cloned().collect();
+            assert_eq!(expected, actual);
+            query.explain(storage).chunks.len()
+        };
+
+        assert_eq!(2, check(&storage, 0x20, 0x40));
+        assert_eq!(3, check(&storage, 0x28, 0x48));
+        assert_eq!(0, check(&storage, 0x200, 0x300));
+
+        storage.modify(ID.chunk(1).item(0x11), |mut editor| {
+            editor.get_mut().1 = 0x250
+        });
+        storage.remove(Everything.filter(|x: &X| x.0 >= 0xF0), std::mem::drop);
+        assert_eq!(1, check(&storage, 0x200, 0x300));
+        assert_eq!(0, check(&storage, 0xF0, 0x100));
+
+        let histogram = index.histogram(&storage);
+        assert_eq!(0xF0, histogram.len());
+        assert_eq!(31.0, histogram.estimate(0.0..32.0));
+        assert_eq!(7.5, histogram.estimate(16.0..24.0));
+        assert!(histogram.may_contain(0x250 as f64..=0x250 as f64));
+        assert_eq!(
+            Some(0x10),
+            index
+                .chunk_histogram(&storage, &1)
+                .map(|h| h.buckets().map(|(_, n)| n).sum())
+        );
+        assert_eq!(None, index.chunk_histogram(&storage, &0xF));
+
+        index.validate(&storage);
+    }
+
+    #[test]
+    fn test_validate_index_reports_inconsistencies() {
+        use std::sync::atomic::{AtomicU64, Ordering};
+        use std::sync::Arc;
+
+        // An indexing rule that changes without the storage changing, which the index can't
+        // notice on its own.
+        let modulus = Arc::new(AtomicU64::new(4));
+        let modulus_in_rule = Arc::clone(&modulus);
+
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let index: SecondaryIndex<u64, X, Option<u64>, u64> =
+            SecondaryIndex::new(&storage, move |x: &X| {
+                Cow::Owned(Some(x.1 % modulus_in_rule.load(Ordering::SeqCst)))
+            });
+
+        for i in 0..0x40 {
+            storage.add(X(i, i));
+        }
+
+        let report = index.validate_index(&storage);
+        assert!(report.is_consistent());
+        assert_eq!(4, report.chunks);
+        assert_eq!(0x40, report.elements);
+
+        modulus.store(8, Ordering::SeqCst);
+        let report = index.validate_index(&storage);
+        assert!(!report.is_consistent());
+        assert!(!report.wrong_storage);
+        assert!(report.dangling.is_empty());
+        assert_eq!(0x20, report.stale.len());
+        assert_eq!(0x20, report.missing.len());
+        assert!(report.stale.contains(&(ID.chunk(1).item(0x1C), 0)));
+        assert!(report.missing.contains(&(ID.chunk(1).item(0x1C), 4)));
+        assert!(!report
+            .stale
+            .iter()
+            .any(|(id, _)| *id == ID.chunk(1).item(0x13)));
+
+        index.invalidate_all();
+        assert!(index.validate_index(&storage).is_consistent());
+
+        let other: Storage<u64, u64, X> = Storage::new();
+        assert!(index.validate_index(&other).wrong_storage);
+    }
+
+    #[test]
+    fn test_reduce_query_agrees_with_filter() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let mut reduction: Reduction<u64, X, u64> = Reduction::new(
+            &storage,
+            2,
+            |x: &X, was: &u64| Some(x.1).filter(|x| x != was),
+            |xs: &[u64], was: &u64| Some(xs.iter().sum()).filter(|x| x != was),
+        );
+
+        for i in 0..0x100 {
+            storage.add(X(i, i));
+        }
+
+        let sum_of = |storage: &Storage<u64, u64, X>, f: fn(&X) -> bool| -> u64 {
+            storage.query(Everything.filter(f)).map(|x| x.1).sum()
+        };
+
+        assert_eq!(
+            Some(sum_of(&storage, |x| x.0 >= 0x20 && x.0 < 0x40)),
+            reduction.reduce_query(&storage, Chunks(vec![2, 3]))
+        );
+        assert_eq!(
+            Some(sum_of(&storage, |x| x.1 % 3 == 1)),
+            reduction.reduce_query(&storage, Everything.filter(|x: &X| x.1 % 3 == 1))
+        );
+
+        storage.modify(ID.chunk(2).item(0x21), |mut editor| {
+            editor.get_mut().1 = 0x1000
+        });
+        storage.remove(Chunks(vec![3]), std::mem::drop);
+
+        assert_eq!(
+            Some(sum_of(&storage, |x| x.0 >= 0x20 && x.0 < 0x40)),
+            reduction.reduce_query(&storage, Chunks(vec![2, 3]))
+        );
+        assert_eq!(
+            Some(sum_of(&storage, |_| true)),
+            reduction.reduce_query(&storage, Everything)
+        );
+        assert_eq!(None, reduction.reduce_query(&storage, Chunks(vec![3])));
+        assert_eq!(
+            Some(&sum_of(&storage, |_| true)),
+            reduction.reduce(&storage)
+        );
+    }
+
+    #[test]
+    fn test_reduce_query_skips_chunks_without_matches() {
+        let mut storage: Storage<u64, u64, (u64, u64, i64)> = Storage::new();
+        let mut reduction: Reduction<u64, (u64, u64, i64), i64> = Reduction::new(
+            &storage,
+            2,
+            |x: &(u64, u64, i64), _| Some(x.2),
+            |xs: &[i64], _| xs.iter().max().cloned(),
+        );
+
+        storage.add((1, 1, -5));
+        storage.add((2, 2, -7));
+        storage.add((3, 3, -3));
+
+        let below = |limit: i64| Everything.filter(move |x: &(u64, u64, i64)| x.2 < limit);
+
+        assert_eq!(Some(-5), reduction.reduce_query(&storage, below(-4)));
+        assert_eq!(Some(-7), reduction.reduce_query(&storage, below(-6)));
+        assert_eq!(None, reduction.reduce_query(&storage, below(-10)));
+        assert_eq!(
+            Some(-3),
+            reduction.reduce_query(&storage, Chunks(vec![1, 3]))
+        );
+        assert_eq!(Some(&-3), reduction.reduce(&storage));
+    }
+
+    #[test]
+    fn test_reduce_by_chunk_agrees_with_reduce_chunk() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let mut reduction: Reduction<u64, X, u64> = Reduction::new(
+            &storage,
+            2,
+            |x: &X, was: &u64| Some(x.1).filter(|x| x != was),
+            |xs: &[u64], was: &u64| Some(xs.iter().sum()).filter(|x| x != was),
+        );
+
+        for i in 0..0x100 {
+            storage.add(X(i, i));
+        }
+
+        // Summaries computed before reduce_by_chunk is first called must carry over.
+        assert!(reduction.reduce(&storage).is_some());
+
+        let check = |reduction: &mut Reduction<u64, X, u64>, storage: &Storage<u64, u64, X>| {
+            let expected: HashMap<u64, u64> = storage
+                .group_by(Everything, |x: &X| (x.0 & 0xF0) >> 4)
+                .into_iter()
+                .map(|(chunk_key, xs)| (chunk_key, xs.iter().map(|x| x.1).sum()))
+                .collect();
+            assert_eq!(&expected, reduction.reduce_by_chunk(storage));
+        };
+
+        check(&mut reduction, &storage);
+
+        storage.modify(ID.chunk(2).item(0x21), |mut editor| {
+            editor.get_mut().1 = 0x1000
+        });
+        storage.remove(Chunks(vec![3, 7]), std::mem::drop);
+        storage.add(X(0x1000, 1));
+        check(&mut reduction, &storage);
+        assert_eq!(14, reduction.reduce_by_chunk(&storage).len());
+
+        storage.remove(Everything, std::mem::drop);
+        check(&mut reduction, &storage);
+    }
+
+    #[test]
+    fn test_grouped_reduction_agrees_with_group_by() {
+        use crate::types::grouped_reduction::GroupedReduction;
+
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let mut reduction: GroupedReduction<u64, X, u64, BTreeSet<u64>, u64> =
+            GroupedReduction::new(
+                &storage,
+                |x: &X| Cow::Owned([x.1 % 3, 10 + x.1 % 2].iter().cloned().collect()),
+                |x: &X, was: &u64| Some(x.0).filter(|x| x != was),
+                |xs: &[u64], was: &u64| Some(xs.iter().sum()).filter(|x| x != was),
+            );
+
+        let mut check = |storage: &Storage<u64, u64, X>| {
+            let mut expected: HashMap<u64, u64> = HashMap::new();
+            for (k, group) in storage.group_by(Everything, |x: &X| x.1 % 3) {
+                expected.insert(k, group.iter().map(|x| x.0).sum());
+            }
+            for (k, group) in storage.group_by(Everything, |x: &X| 10 + x.1 % 2) {
+                expected.insert(k, group.iter().map(|x| x.0).sum());
+            }
+            assert_eq!(&expected, reduction.reduce(storage));
+            reduction.validate(storage);
+        };
+
+        for i in 0..0x80 {
+            storage.add(X(i, i));
+        }
+        check(&storage);
+
+        storage.modify(Everything.filter(|x: &X| x.0 % 5 == 1), |mut editor| {
+            editor.get_mut().1 += 1;
+        });
+        check(&storage);
+
+        storage.remove_chunk(&3);
+        storage.remove(Everything.filter(|x: &X| x.0 % 7 == 1), std::mem::drop);
+        check(&storage);
+
+        for i in 0x30..0x40 {
+            storage.add(X(i, 0));
+        }
+        check(&storage);
+
+        storage.remove(Everything.filter(|x: &X| x.1 % 3 == 2), std::mem::drop);
+        check(&storage);
+
+        storage.validate();
+    }
+
+    #[test]
+    fn test_reduction_group_agrees_with_filter() {
+        use crate::types::reduction_group::ReductionGroup;
+
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let mut group: ReductionGroup<u64, X> = ReductionGroup::new(&storage);
+        let sum = group.register(
+            2,
+            |x: &X, was: &u64| Some(x.1).filter(|x| x != was),
+            |xs: &[u64], was: &u64| Some(xs.iter().sum()).filter(|x| x != was),
+        );
+
+        for i in 0..0x100 {
+            storage.add(X(i, i));
+        }
+
+        assert_eq!(Some(&(0..0x100).sum()), group.reduce(&storage, &sum));
+
+        // A reduction registered late still sees every chunk.
+        let count = group.register(
+            4,
+            |_: &X, was: &usize| Some(1).filter(|x| x != was),
+            |xs: &[usize], was: &usize| Some(xs.iter().sum()).filter(|x| x != was),
+        );
+
+        let mut check = |storage: &Storage<u64, u64, X>| {
+            let expected_sum: u64 = storage.iter().map(|x| x.1).sum();
+            let expected_count = storage.iter().count();
+
+            if expected_count == 0 {
+                assert_eq!(None, group.reduce(storage, &sum));
+                assert_eq!(None, group.reduce(storage, &count));
+            } else {
+                assert_eq!(Some(&expected_sum), group.reduce(storage, &sum));
+                assert_eq!(Some(&expected_count), group.reduce(storage, &count));
+            }
+        };
+
+        check(&storage);
+
+        storage.modify(Everything.filter(|x: &X| x.0 % 5 == 1), |mut editor| {
+            editor.get_mut().1 *= 3;
+        });
+        check(&storage);
+
+        storage.remove_chunk(&3);
+        storage.remove(Everything.filter(|x: &X| x.0 % 7 == 1), std::mem::drop);
+        storage.add(X(0x1000, 1));
+        check(&storage);
+
+        storage.remove(Everything, std::mem::drop);
+        check(&storage);
+
+        storage.add(X(0x21, 7));
+        check(&storage);
+
+        storage.validate();
+    }
+
+    #[test]
+    fn test_reduction_generation_follows_result() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let mut reductions: Vec<Reduction<u64, X, u64>> = vec![
+            Reduction::new(
+                &storage,
+                2,
+                |x: &X, was: &u64| Some(x.1).filter(|x| x != was),
+                |xs: &[u64], was: &u64| Some(xs.iter().sum()).filter(|x| x != was),
+            ),
+            Reduction::new_invertible(
+                &storage,
+                |x: &X, was: &u64| Some(x.1).filter(|x| x != was),
+                |xs: &[u64], was: &u64| Some(xs.iter().sum()).filter(|x| x != was),
+                |total: &u64, x: &u64| total - x,
+            ),
+        ];
+
+        for reduction in reductions.iter_mut() {
+            assert_eq!(None, reduction.reduce(&storage));
+            assert_eq!(0, reduction.generation());
+        }
+
+        for i in 0..0x40 {
+            storage.add(X(i, i));
+        }
+
+        for reduction in reductions.iter_mut() {
+            reduction.reduce(&storage);
+            assert_eq!(1, reduction.generation());
+            reduction.reduce(&storage);
+            assert_eq!(1, reduction.generation());
+        }
+
+        storage.modify(ID.chunk(2).item(0x21), |mut editor| {
+            editor.get_mut().1 = 0x1000
+        });
+
+        for reduction in reductions.iter_mut() {
+            assert_eq!(Some(&(0x7e0 - 0x21 + 0x1000)), reduction.reduce(&storage));
+            assert_eq!(2, reduction.generation());
+        }
+
+        storage.remove(Everything, std::mem::drop);
+
+        for reduction in reductions.iter_mut() {
+            assert_eq!(None, reduction.reduce(&storage));
+            assert_eq!(3, reduction.generation());
+            reduction.reduce(&storage);
+            assert_eq!(3, reduction.generation());
+        }
+    }
+
+    #[test]
+    fn test_reduction_generation_ignores_non_extreme_elements() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let mut highest: Reduction<u64, X, u64> = Reduction::new(
+            &storage,
+            2,
+            |x: &X, was: &u64| Some(x.1).filter(|x| x != was),
+            |xs: &[u64], was: &u64| xs.iter().max().cloned().filter(|x| x != was),
+        );
+
+        storage.add(X(0x00, 20));
+        assert_eq!(Some(&20), highest.reduce(&storage));
+        assert_eq!(1, highest.generation());
+
+        // Each of these grows a reduction stack, within the chunk or across chunks, without
+        // changing the result.
+        for i in 1..0x40 {
+            storage.add(X(i, i % 10));
+            assert_eq!(Some(&20), highest.reduce(&storage));
+            assert_eq!(1, highest.generation());
+        }
+
+        storage.add(X(0x40, 30));
+        assert_eq!(Some(&30), highest.reduce(&storage));
+        assert_eq!(2, highest.generation());
+    }
+
+    #[test]
+    fn test_windowed_reduction_agrees_with_group_by() {
+        use crate::types::windowed_reduction::WindowedReduction;
+        use std::collections::BTreeMap;
+
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let mut reduction: WindowedReduction<u64, X, u64, u64> = WindowedReduction::new(
+            &storage,
+            |item_key: &u64| (item_key & 0x0F) >> 2,
+            |x: &X, was: &u64| Some(x.1).filter(|x| x != was),
+            |xs: &[u64], was: &u64| Some(xs.iter().sum()).filter(|x| x != was),
+        );
+
+        for i in 0..0x100 {
+            storage.add(X(i, i));
+        }
+
+        let check = |reduction: &mut WindowedReduction<u64, X, u64, u64>,
+                     storage: &Storage<u64, u64, X>| {
+            for chunk_key in 0..0x11 {
+                let expected: Vec<(u64, u64)> = storage
+                    .group_by(Chunks(vec![chunk_key]), |x: &X| (x.0 & 0x0F) >> 2)
+                    .into_iter()
+                    .map(|(window, xs)| (window, xs.iter().map(|x| x.1).sum()))
+                    .collect::<BTreeMap<u64, u64>>()
+                    .into_iter()
+                    .collect();
+                let actual: Vec<(u64, u64)> = reduction
+                    .reduce_chunk(storage, &chunk_key)
+                    .map(|(window, summary)| (*window, *summary))
+                    .collect();
+                assert_eq!(expected, actual);
+            }
+
+            for window in 0..4 {
+                let expected: u64 = storage
+                    .iter()
+                    .filter(|x| (x.0 & 0x0F) >> 2 == window)
+                    .map(|x| x.1)
+                    .sum();
+                assert_eq!(
+                    Some(expected).filter(|_| storage.iter().next().is_some()),
+                    reduction.reduce_window(storage, &window).cloned()
+                );
+            }
+
+            reduction.validate(storage);
+        };
+
+        check(&mut reduction, &storage);
+
+        storage.modify(ID.chunk(2).item(0x21), |mut editor| {
+            editor.get_mut().1 = 0x1000
+        });
+        storage.remove(ID.chunk(3).item(0x3C), std::mem::drop);
+        storage.remove(ID.chunk(3).item(0x3D), std::mem::drop);
+        storage.remove(ID.chunk(3).item(0x3E), std::mem::drop);
+        storage.remove(ID.chunk(3).item(0x3F), std::mem::drop);
+        storage.remove(Chunks(vec![7]), std::mem::drop);
+        storage.add(X(0x1000, 1));
+        check(&mut reduction, &storage);
+        assert_eq!(3, reduction.reduce_chunk(&storage, &3).count());
+
+        storage.remove(Everything, std::mem::drop);
+        check(&mut reduction, &storage);
+    }
+
+    #[test]
+    fn test_builtin_reductions_agree_with_iterators() {
+        use crate::reductions::count::Count;
+        use crate::reductions::min_max::MinMax;
+        use crate::reductions::sum::Sum;
+
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let mut count: Reduction<u64, X, Count> = Count::reduction(&storage);
+        let mut sum: Reduction<u64, X, Sum<u64>> = Sum::reduction(&storage, |x: &X| x.1);
+        let mut min_max: Reduction<u64, X, MinMax<u64, u64, u64>> =
+            MinMax::reduction(&storage, |x: &X| x.1);
+
+        for i in 0..0x400 {
+            storage.add(X(i, (i * 37) % 101));
+        }
+
+        let check = |count: &mut Reduction<u64, X, Count>,
+                     sum: &mut Reduction<u64, X, Sum<u64>>,
+                     min_max: &mut Reduction<u64, X, MinMax<u64, u64, u64>>,
+                     storage: &Storage<u64, u64, X>| {
+            let min = storage
+                .iter()
+                .map(|x| (x.1, Id((x.0 & 0xF0) >> 4, x.0)))
+                .min();
+            let max = storage
+                .iter()
+                .map(|x| (x.1, Id((x.0 & 0xF0) >> 4, x.0)))
+                .max();
+
+            assert_eq!(
+                Count(storage.iter().count()),
+                count.reduce(storage).cloned().unwrap_or_default()
+            );
+            assert_eq!(
+                Sum(storage.iter().map(|x| x.1).sum::<u64>()),
+                sum.reduce(storage).cloned().unwrap_or_default()
+            );
+            assert_eq!(
+                MinMax { min, max },
+                min_max.reduce(storage).cloned().unwrap_or_default()
+            );
+        };
+
+        check(&mut count, &mut sum, &mut min_max, &storage);
+
+        storage.remove(
+            Everything.filter(|x: &X| x.1 == 0 || x.1 == 100),
+            std::mem::drop,
+        );
+        storage.modify(ID.chunk(2).item(0x21), |mut editor| {
+            editor.get_mut().1 = 0x1000
+        });
+        storage.remove(Chunks(vec![3, 7]), std::mem::drop);
+        check(&mut count, &mut sum, &mut min_max, &storage);
+
+        storage.remove(Everything, std::mem::drop);
+        check(&mut count, &mut sum, &mut min_max, &storage);
+    }
+
+    #[test]
+    fn test_stats_agrees_with_two_pass_variance() {
+        use crate::reductions::stats::Stats;
+
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let mut stats: Reduction<u64, X, Stats> =
+            Stats::reduction(&storage, |x: &X| 1e12 + x.1 as f64 / 8.0);
+
+        for i in 0..0x400 {
+            storage.add(X(i, (i * 37) % 101));
+        }
+
+        let check = |stats: &mut Reduction<u64, X, Stats>, storage: &Storage<u64, u64, X>| {
+            let values: Vec<f64> = storage.iter().map(|x| x.1 as f64 / 8.0).collect();
+            let count = values.len() as f64;
+            let mean = values.iter().sum::<f64>() / count;
+            let variance = values.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / count;
+
+            let actual = stats.reduce(storage).unwrap();
+            assert_eq!(values.len() as u64, actual.count());
+            assert!((actual.mean() - 1e12 - mean).abs() < 1e-3);
+            assert!((actual.population_variance() - variance).abs() < 1e-3 * variance);
+        };
+
+        check(&mut stats, &storage);
+
+        storage.remove(Everything.filter(|x: &X| x.1 < 50), std::mem::drop);
+        storage.modify(ID.chunk(2).item(0x21), |mut editor| {
+            editor.get_mut().1 = 0x100
+        });
+        storage.remove(Chunks(vec![3, 7]), std::mem::drop);
+        check(&mut stats, &storage);
+
+        storage.remove(Everything, std::mem::drop);
+        assert_eq!(None, stats.reduce(&storage));
+    }
+
+    #[test]
+    fn test_reduce_range_agrees_with_filter() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let mut reductions: Vec<Reduction<u64, X, u64>> = vec![
+            Reduction::new(
+                &storage,
+                2,
+                |x: &X, was: &u64| Some(x.1).filter(|x| x != was),
+                |xs: &[u64], was: &u64| Some(xs.iter().sum()).filter(|x| x != was),
+            ),
+            Reduction::new_invertible(
+                &storage,
+                |x: &X, was: &u64| Some(x.1).filter(|x| x != was),
+                |xs: &[u64], was: &u64| Some(xs.iter().sum()).filter(|x| x != was),
+                |total: &u64, x: &u64| total - x,
+            ),
+        ];
+
+        for i in (0..0x100).rev() {
+            storage.add(X(i, i));
+        }
+
+        let check = |reduction: &mut Reduction<u64, X, u64>, storage: &Storage<u64, u64, X>| {
+            for lo in 0..0x11 {
+                for hi in lo..0x11 {
+                    let xs: Vec<u64> = storage
+                        .iter()
+                        .filter(|x| (lo..hi).contains(&((x.0 & 0xF0) >> 4)))
+                        .map(|x| x.1)
+                        .collect();
+                    let expected = Some(xs.iter().sum()).filter(|_| !xs.is_empty());
+                    assert_eq!(expected, reduction.reduce_range(storage, lo..hi));
+                }
+            }
+        };
+
+        for reduction in reductions.iter_mut() {
+            check(reduction, &storage);
+        }
+
+        storage.modify(ID.chunk(2).item(0x21), |mut editor| {
+            editor.get_mut().1 = 0x1000
+        });
+        storage.remove(Chunks(vec![3, 7]), std::mem::drop);
+        storage.remove(Chunks(vec![15]), std::mem::drop);
+        for reduction in reductions.iter_mut() {
+            check(reduction, &storage);
+        }
+
+        storage.add(X(0x30, 1));
+        for reduction in reductions.iter_mut() {
+            check(reduction, &storage);
+        }
+
+        storage.remove(Everything, std::mem::drop);
+        for reduction in reductions.iter_mut() {
+            check(reduction, &storage);
+        }
+    }
+
+    #[test]
+    fn test_storage_reduce_agrees_with_iterators() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+
+        for i in 0..0x400 {
+            storage.add(X(i, (i * 37) % 101));
+        }
+
+        let sum = |xs: &[u64]| xs.iter().sum::<u64>();
+        let query = Everything.filter(|x: &X| x.0 & 0x3 != 0);
+
+        assert_eq!(
+            Some(storage.query(query).map(|x| x.1).sum::<u64>()),
+            storage.reduce(query, |x: &X| x.1, sum)
+        );
+        assert_eq!(
+            storage.query(Chunks([2, 5])).map(|x| x.1).max(),
+            storage.reduce(
+                Chunks([2, 5]),
+                |x: &X| x.1,
+                |xs: &[u64]| { *xs.iter().max().unwrap() }
+            )
+        );
+        assert_eq!(
+            None,
+            storage.reduce(Everything.filter(|x: &X| x.1 > 200), |x: &X| x.1, sum)
+        );
+
+        #[cfg(feature = "rayon")]
+        {
+            storage.set_parallelism(crate::types::parallelism::Parallelism::parallel());
+            assert_eq!(
+                Some(storage.query(query).map(|x| x.1).sum::<u64>()),
+                storage.reduce(query, |x: &X| x.1, sum)
+            );
+        }
+    }
+
+    #[test]
+    fn test_concurrent_storage_agrees_with_storage() {
+        use crate::types::concurrent_storage::ConcurrentStorage;
+
+        let concurrent: ConcurrentStorage<u64, u64, X> = ConcurrentStorage::new();
+
+        std::thread::scope(|scope| {
+            for thread in 0..4 {
+                let concurrent = &concurrent;
+                scope.spawn(move || {
+                    for i in (thread..0x200).step_by(4) {
+                        concurrent.add(X(i, i * 3));
+                    }
+
+                    for i in (thread..0x200).step_by(8) {
+                        concurrent.write_chunk(&((i & 0xF0) >> 4), |storage| {
+                            storage.modify(ID.chunk((i & 0xF0) >> 4).item(i), |mut editor| {
+                                editor.get_mut().1 += 1;
+                            });
+                        });
+                    }
+                });
+            }
+
+            let concurrent = &concurrent;
+            scope.spawn(move || {
+                concurrent.remove_chunk(&7);
+            });
+        });
+
+        let mut expected: Storage<u64, u64, X> = Storage::new();
+        for i in 0..0x200 {
+            expected.add(X(i, i * 3 + if i % 8 < 4 { 1 } else { 0 }));
+        }
+
+        // chunk 7 may have lost any of its elements to the concurrent remove_chunk
+        for chunk_key in (0..0x10).filter(|chunk_key| *chunk_key != 7) {
+            assert_eq!(
+                concurrent.read_chunk(&chunk_key, |storage| storage
+                    .query(&Chunks([chunk_key]))
+                    .cloned()
+                    .collect::<BTreeSet<X>>()),
+                Some(expected.query(&Chunks([chunk_key])).cloned().collect())
+            );
+        }
+
+        assert_eq!(Some(X(0x42, 0xC7)), concurrent.get(&ID.chunk(4).item(0x42)));
+        assert_eq!(None, concurrent.get(&ID.chunk(4).item(0x1000)));
+
+        let mut storage = concurrent.into_storage();
+        storage.validate();
+        assert!(storage.iter().count() >= 0x200 - 0x20);
+        assert!(storage.iter().all(|x| x.1 == expected.get(x).unwrap().1));
+    }
+
+    #[test]
+    fn test_concurrent_storage_survives_failed_writes() {
+        use crate::types::concurrent_storage::ConcurrentStorage;
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+
+        let concurrent: ConcurrentStorage<u64, u64, X> = ConcurrentStorage::new();
+        concurrent.add(X(0x12, 1));
+
+        let duplicate = concurrent.try_add(X(0x12, 2)).err().map(|e| e.element);
+        assert_eq!(Some(X(0x12, 2)), duplicate);
+
+        // A panicking closure keeps its changes and doesn't brick the chunk.
+        assert!(catch_unwind(AssertUnwindSafe(|| {
+            concurrent.write_chunk(&1, |storage| {
+                storage.add(X(0x13, 1));
+                panic!("deliberate");
+            })
+        }))
+        .is_err());
+        assert!(catch_unwind(AssertUnwindSafe(|| concurrent.add(X(0x12, 3)))).is_err());
+        concurrent.add(X(0x14, 1));
+        assert_eq!(
+            Some(3),
+            concurrent.read_chunk(&1, |storage| storage.iter().count())
+        );
+
+        // An element from another chunk is taken out again before the panic.
+        assert!(catch_unwind(AssertUnwindSafe(|| {
+            concurrent.write_chunk(&1, |storage| {
+                storage.add(X(0x25, 1));
+            })
+        }))
+        .is_err());
+        assert_eq!(None, concurrent.get(&ID.chunk(2).item(0x25)));
+        assert_eq!(vec![1], concurrent.chunk_keys());
+
+        // A chunk only exists while it holds an element.
+        concurrent.write_chunk(&3, |_| ());
+        assert_eq!(
+            None,
+            concurrent.read_chunk(&3, |storage| storage.iter().count())
+        );
+        concurrent.write_chunk(&1, |storage| {
+            storage.remove(Chunks([1]), std::mem::drop);
+        });
+        assert!(concurrent.chunk_keys().is_empty());
+        assert_eq!(
+            None,
+            concurrent.read_chunk(&1, |storage| storage.iter().count())
+        );
+
+        concurrent.add(X(0x12, 4));
+        assert_eq!(Some(X(0x12, 4)), concurrent.get(&ID.chunk(1).item(0x12)));
+    }
+
+    #[test]
+    fn test_published_snapshots_are_consistent() {
+        use crate::types::published_storage::PublishedStorage;
+
+        let mut initial: Storage<u64, u64, X> = Storage::new();
+        for i in 0..0x100 {
+            initial.add(X(i, 0));
+        }
+
+        let mut published = PublishedStorage::new(initial);
+        let reader = published.subscribe();
+
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                let mut reader = reader.clone();
+                scope.spawn(move || {
+                    let mut generation = 0;
+                    while generation < 0x40 {
+                        let snapshot = reader.snapshot().clone();
+                        assert!(reader.generation() >= generation);
+                        generation = reader.generation();
+
+                        // Every element of a snapshot was last written by the same generation.
+                        assert_eq!(0x100, snapshot.iter().count());
+                        assert!(snapshot.iter().all(|x| x.1 == generation));
+                    }
+                });
+            }
+
+            for generation in 1..=0x40 {
+                published
+                    .storage_mut()
+                    .modify(Everything, |mut editor| editor.get_mut().1 = generation);
+                assert_eq!(generation, published.publish());
+            }
+        });
+
+        assert_eq!(0x40, published.generation());
+        assert_eq!(0, reader.generation());
+        published.into_storage().validate();
+    }
+
+    #[test]
+    fn test_snapshot_is_unaffected_by_modifications() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        for i in 0..0x100 {
+            storage.add(X(i, i * 3));
+        }
+
+        let snapshot = storage.snapshot();
+        let expected: Vec<X> = storage.iter().cloned().collect();
+
+        storage.modify(Chunks([0, 1]), |mut editor| editor.get_mut().1 += 1);
+        storage.remove(Chunks([2]), std::mem::drop);
+        storage.add(X(0x1000, 0));
+
+        assert_eq!(expected, snapshot.iter().cloned().collect::<Vec<X>>());
+        assert_eq!(Some(&X(0x05, 0x0F)), snapshot.get(&ID.chunk(0).item(0x05)));
+        assert_eq!(Some(&X(0x05, 0x10)), storage.get(&ID.chunk(0).item(0x05)));
+        assert_eq!(None, snapshot.get(&ID.chunk(0x100).item(0x1000)));
+
+        // A snapshot shares the secondary indexes of the original, like a clone does.
+        let index: SecondaryIndex<u64, X, Option<u64>, u64> =
+            SecondaryIndex::new(&storage, |x: &X| Cow::Owned(Some(x.1 % 2)));
+        let unique: UniqueIndex<u64, X, Option<u64>, u64> =
+            UniqueIndex::new(&mut storage, |x: &X| Cow::Owned(Some(x.1)));
+        for _ in 0..2 {
+            for storage in [&storage, &*snapshot].iter() {
+                for parity in 0..2 {
+                    let expected = storage.query(Everything.filter(move |x: &X| x.1 % 2 == parity));
+                    let actual = storage.query(Everything.matching(&index, Cow::Owned(parity)));
+                    assert!(expected.eq(actual));
+                }
+                for x in storage.iter() {
+                    assert_eq!(Some(x), unique.get_unique(storage, &x.1));
+                }
+                assert_eq!(None, unique.get_unique(storage, &0x10000));
+                index.validate(storage);
+                unique.validate(storage);
+            }
+        }
+        assert_eq!(None, unique.get_unique(&snapshot, &0x10));
+        assert_eq!(Some(&X(0x05, 0x0F)), unique.get_unique(&snapshot, &0x0F));
+        assert_eq!(Some(&X(0x05, 0x10)), unique.get_unique(&storage, &0x10));
+
+        let mut forked = snapshot.clone().into_storage();
+        forked.add(X(0x1001, 0));
+        assert_eq!(0x101, forked.iter().count());
+        assert_eq!(0x100, snapshot.iter().count());
+
+        storage.validate();
+        forked.validate();
+    }
+
+    #[test]
+    fn test_reduction_point_update_only_refolds_its_segments() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let maps = Arc::new(AtomicUsize::new(0));
+        let folds = Arc::new(AtomicUsize::new(0));
+        let maps_in_rule = Arc::clone(&maps);
+        let folds_in_rule = Arc::clone(&folds);
+        let mut storage: Storage<(), u64, (u64, u64)> = Storage::new();
+        let mut reduction: Reduction<(), (u64, u64), u64> = Reduction::new(
+            &storage,
+            4,
+            move |x: &(u64, u64), was: &u64| {
+                maps_in_rule.fetch_add(1, Ordering::Relaxed);
+                Some(x.1).filter(|x| x != was)
+            },
+            move |xs: &[u64], was: &u64| {
+                folds_in_rule.fetch_add(1, Ordering::Relaxed);
+                Some(xs.iter().sum()).filter(|x| x != was)
+            },
+        );
+
+        // A single chunk of 4^4 elements is folded in four layers of groups of four, and then
+        // once more across chunks.
+        for i in 0..0x100 {
+            storage.add((i, i));
+        }
+        assert_eq!(Some(&(0..0x100).sum::<u64>()), reduction.reduce(&storage));
+        assert_eq!(0x100, maps.swap(0, Ordering::Relaxed));
+        assert_eq!(
+            0x40 + 0x10 + 0x4 + 0x1 + 1,
+            folds.swap(0, Ordering::Relaxed)
+        );
+
+        storage.modify(ID.item(0x42), |mut editor| editor.get_mut().1 = 0);
+        assert_eq!(
+            Some(&((0..0x100).sum::<u64>() - 0x42)),
+            reduction.reduce(&storage)
+        );
+
+        // Only the edited element, and one segment in each layer above it, are revisited.
+        assert_eq!(1, maps.load(Ordering::Relaxed));
+        assert_eq!(4 + 1, folds.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_reduction_stays_incremental_across_storage_clone() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let maps = Arc::new(AtomicUsize::new(0));
+        let maps_in_rule = Arc::clone(&maps);
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let mut reduction: Reduction<u64, X, u64> = Reduction::new(
+            &storage,
+            4,
+            move |x: &X, was: &u64| {
+                maps_in_rule.fetch_add(1, Ordering::Relaxed);
+                Some(x.1).filter(|x| x != was)
+            },
+            |xs: &[u64], was: &u64| Some(xs.iter().sum()).filter(|x| x != was),
+        );
+
+        for i in 0..0x100 {
+            storage.add(X(i, i));
+        }
+
+        let total = |storage: &Storage<u64, u64, X>| storage.iter().map(|x| x.1).sum::<u64>();
+        assert_eq!(Some(&total(&storage)), reduction.reduce(&storage));
+
+        // Editing a copy-on-write clone of the storage must not recompute the whole chunk.
+        let mut snapshot = storage.clone();
+        snapshot.modify(ID.chunk(2).item(0x21), |mut editor| {
+            editor.get_mut().1 = 0x1000
+        });
+        maps.store(0, Ordering::Relaxed);
+        assert_eq!(Some(&total(&snapshot)), reduction.reduce(&snapshot));
+        assert_eq!(1, maps.load(Ordering::Relaxed));
+
+        // Going back to the original storage, which the reduction has since moved away from.
+        storage.modify(ID.chunk(3).item(0x31), |mut editor| {
+            editor.get_mut().1 = 0x2000
+        });
+        assert_eq!(Some(&total(&storage)), reduction.reduce(&storage));
+        assert_eq!(Some(&total(&snapshot)), reduction.reduce(&snapshot));
+        assert_ne!(total(&storage), total(&snapshot));
+    }
+
+    #[test]
+    fn test_bloom_index_agrees_with_get_and_find() {
+        use crate::queries::bloom_index::BloomIndex;
+
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let by_item: BloomIndex<u64, X, u64> = BloomIndex::item_keys(&storage, 8);
+        let by_value: BloomIndex<u64, X, u64> =
+            BloomIndex::new(&storage, 4, |x: &X| Cow::Owned(x.1));
+
+        let check = |storage: &Storage<u64, u64, X>| {
+            for i in 0..0x300 {
+                for chunk_key in 0..0x10 {
+                    let id = ID.chunk(chunk_key).item(i);
+                    assert_eq!(storage.get(&id), by_item.get(storage, &id));
+                    if storage.get(&id).is_some() {
+                        assert!(by_value.may_contain(
+                            storage,
+                            &chunk_key,
+                            &storage.get(&id).unwrap().1
+                        ));
+                    }
+                }
+                assert_eq!(storage.find(&i), by_item.find(storage, &i));
+            }
+            by_item.validate(storage);
+            by_value.validate(storage);
+        };
+
+        for i in 0..0x100 {
+            storage.add(X(i, i * 3));
+        }
+        check(&storage);
+
+        storage.remove(Everything.filter(|x: &X| x.0 % 3 == 0), std::mem::drop);
+        storage.remove_chunk(&7);
+        check(&storage);
+
+        for i in 0x100..0x200 {
+            storage.add(X(i, i * 3));
+        }
+        storage.modify(Everything.filter(|x: &X| x.0 % 5 == 0), |mut editor| {
+            editor.get_mut().1 += 1;
+        });
+        check(&storage);
+
+        let stats = by_item.stats();
+        assert!(stats.negatives > stats.false_positives * 10);
+        assert!(stats.false_positive_rate() < 0.1);
+
+        storage.validate();
+    }
+
+    #[test]
+    fn test_join_agrees_with_nested_loops() {
+        let mut left: Storage<u64, u64, X> = Storage::new();
+        let mut right: Storage<u64, u64, X> = Storage::new();
+
+        for i in 0..200 {
+            left.add(X(i, i % 7));
+        }
+
+        for i in 0..100 {
+            right.add(X(i * 3, i % 5));
+        }
+
+        let odd = Everything.filter(|x: &X| x.0 % 2 == 1);
+
+        let mut expected: Vec<(u64, u64)> = Vec::new();
+        for a in left.query(odd) {
+            for b in right.iter() {
+                if a.1 == b.1 {
+                    expected.push((a.0, b.0));
+                }
+            }
+        }
+        expected.sort();
+
+        let mut joined: Vec<(u64, u64)> = left
+            .join(odd, &right, Everything, |a| a.1, |b| b.1)
+            .map(|(a, b)| (a.0, b.0))
+            .collect();
+        joined.sort();
+        assert_eq!(expected, joined);
+
+        expected.retain(|(a, b)| X(*a, 0).chunk_key() == X(*b, 0).chunk_key());
+
+        let mut joined: Vec<(u64, u64)> = left
+            .join_by_chunk(odd, &right, Everything, |a| a.1, |b| b.1)
+            .map(|(a, b)| (a.0, b.0))
+            .collect();
+        joined.sort();
+        assert_eq!(expected, joined);
+    }
+
+    #[test]
+    fn test_chunks_where_combines_with_other_queries() {
+        use crate::queries::chunks_where::ChunksWhere;
+
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let index: SecondaryIndex<u64, X, Option<u64>, u64> =
+            SecondaryIndex::new(&storage, |x: &X| Cow::Owned(Some(x.1 & 0x1)));
+
+        for i in 0..0x100 {
+            storage.add(X(i, i));
+        }
+
+        let even_chunks = ChunksWhere(|chunk_key: &u64| chunk_key & 0x1 == 0);
+
+        assert_eq!(0x80, storage.count(even_chunks));
+        assert_eq!(
+            0x40,
+            storage.count(even_chunks.matching(&index, Cow::Owned(1)))
+        );
+        assert_eq!(0x80, storage.query(Not::new(even_chunks)).count());
+        assert_eq!(
+            0x90,
+            storage.query(Or::new(even_chunks, Chunks([1]))).count()
+        );
+
+        storage.remove(even_chunks, std::mem::drop);
+        assert_eq!(0x80, storage.iter().count());
+        assert!(storage.iter().all(|x| x.chunk_key().as_ref() & 0x1 == 1));
+    }
+
+    #[test]
+    fn test_not_matching_complements_matching() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let index: SecondaryIndex<u64, X, Option<u64>, u64> =
+            SecondaryIndex::new(&storage, |x: &X| Cow::Owned(Some(x.1 % 3)));
+
+        for i in 0..0x200 {
+            storage.add(X(i, i));
+        }
+
+        let some = Everything.matching(&index, Cow::Owned(0));
+        let rest = Everything.not_matching(&index, Cow::Owned(0));
+
+        assert!(rest.is_exact());
+        assert_eq!(
+            0x200,
+            storage.count(some.clone()) + storage.count(rest.clone())
+        );
+        assert!(storage.query(rest.clone()).all(|x| x.1 % 3 != 0));
+
+        storage.modify(&rest, |mut x| x.get_mut().1 = 0);
+        assert_eq!(0, storage.count(rest.clone()));
+        assert_eq!(0x200, storage.count(some));
+        assert_eq!(
+            0x200,
+            storage.count(Everything.not_matching(&index, Cow::Owned(7)))
+        );
+
+        index.validate(&storage);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_par_query_agrees_with_query() {
+        use rayon::prelude::*;
+
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let index: SecondaryIndex<u64, X, Option<u64>, u64> =
+            SecondaryIndex::new(&storage, |x: &X| Cow::Owned(Some(x.1 & 0x3)));
+
+        for i in 0..0x1000 {
+            storage.add(X(i, i));
+        }
+
+        let query = Chunks(0..8).matching(&index, Cow::Owned(2));
+
+        let mut expected: Vec<X> = storage.query(&query).cloned().collect();
+        let mut actual: Vec<X> = storage.par_query(&query).cloned().collect();
+        expected.sort();
+        actual.sort();
+
+        assert_eq!(0x200, actual.len());
+        assert_eq!(expected, actual);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_par_reduce_agrees_with_reduce() {
+        use crate::types::parallelism::Parallelism;
+
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        storage.set_parallelism(Parallelism::parallel());
+
+        let map = |x: &X, was: &u64| Some(x.1).filter(|x| x != was);
+        let fold = |xs: &[u64], was: &u64| Some(xs.iter().sum()).filter(|x| x != was);
+        let mut sequential: Reduction<u64, X, u64> = Reduction::new(&storage, 2, map, fold);
+        let mut parallel: Reduction<u64, X, u64> = Reduction::new(&storage, 2, map, fold);
+        let mut invertible: Reduction<u64, X, u64> =
+            Reduction::new_invertible(&storage, map, fold, |total: &u64, x: &u64| total - x);
+
+        let mut check = |storage: &Storage<u64, u64, X>| {
+            let expected = sequential.reduce(storage).cloned();
+            assert_eq!(expected, parallel.par_reduce(storage).cloned());
+            assert_eq!(expected, invertible.par_reduce(storage).cloned());
+        };
+
+        for i in 0..0x1000 {
+            storage.add(X(i, i));
+        }
+        check(&storage);
+
+        storage.modify(Everything.filter(|x: &X| x.0 % 5 == 1), |mut editor| {
+            editor.get_mut().1 *= 3;
+        });
+        check(&storage);
+
+        // Only one chunk changes, which isn't worth parallelizing.
+        storage.modify(ID.chunk(7).item(0x77), |mut editor| editor.get_mut().1 = 0);
+        check(&storage);
+
+        storage.remove_chunk(&3);
+        storage.remove(Everything.filter(|x: &X| x.0 % 7 == 1), std::mem::drop);
+        storage.add(X(0x1000, 1));
+        check(&storage);
+
+        storage.remove(Everything, std::mem::drop);
+        check(&storage);
+        assert_eq!(None, parallel.par_reduce(&storage));
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_par_modify_agrees_with_modify() {
+        use crate::types::parallelism::Parallelism;
+
+        let mut sequential: Storage<u64, u64, X> = Storage::new();
+        for i in 0..0x1000 {
+            sequential.add(X(i, i));
+        }
+
+        let mut parallel = sequential.clone();
+        parallel.set_parallelism(Parallelism::parallel());
+
+        let index: SecondaryIndex<u64, X, Option<u64>, u64> =
+            SecondaryIndex::new(&parallel, |x: &X| Cow::Owned(Some(x.1 & 0x3)));
+        let mut reduction: Reduction<u64, X, u64> = Reduction::new(
+            &parallel,
+            2,
+            |x: &X, was: &u64| Some(x.1).filter(|x| x != was),
+            |xs: &[u64], was: &u64| Some(xs.iter().sum()).filter(|x| x != was),
+        );
+        assert_eq!(
+            0x400,
+            parallel
+                .query(Everything.matching(&index, Cow::Owned(2)))
+                .count()
+        );
+        reduction.reduce(&parallel);
+
+        let query = Everything.filter(|x: &X| x.0 % 3 == 1);
+        let f = |mut editor: Editor<u64, u64, X>| editor.get_mut().1 = editor.get().1 * 5 + 1;
+        sequential.modify(query, f);
+        parallel.par_modify(query, f);
+
+        // Only one chunk changes, which isn't worth parallelizing.
+        sequential.modify(ID.chunk(7).item(0x77), |mut editor| editor.get_mut().1 = 2);
+        parallel.par_modify(ID.chunk(7).item(0x77), |mut editor| editor.get_mut().1 = 2);
+
+        assert_eq!(
+            sequential.iter().cloned().collect::<Vec<X>>(),
+            parallel.iter().cloned().collect::<Vec<X>>()
+        );
+        assert_eq!(
+            sequential
+                .query(Everything.filter(|x: &X| x.1 & 0x3 == 2))
+                .count(),
+            parallel
+                .query(Everything.matching(&index, Cow::Owned(2)))
+                .count()
+        );
+        assert_eq!(
+            Some(sequential.iter().map(|x| x.1).sum::<u64>()),
+            reduction.reduce(&parallel).cloned()
+        );
+
+        sequential.validate();
+        parallel.validate();
+        index.validate(&parallel);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_par_rebuild_agrees_with_rebuild() {
+        use crate::types::parallelism::Parallelism;
+
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        storage.set_parallelism(Parallelism::parallel());
+
+        let bits = |x: &X| {
+            let bits = x.1;
+            (0..4).filter(move |bit| bits & (1 << bit) != 0)
+        };
+        let sequential: SecondaryIndex<u64, X, BTreeSet<u64>, u64> =
+            SecondaryIndex::new_multi(&storage, bits);
+        let parallel: SecondaryIndex<u64, X, BTreeSet<u64>, u64> =
+            SecondaryIndex::new_multi(&storage, bits);
+        let scoped: SecondaryIndex<u64, X, Option<u64>, u64> = SecondaryIndex::new_scoped(
+            &storage,
+            |chunk_key: &u64| *chunk_key & 1 == 0,
+            |x: &X| Cow::Owned(Some(x.1 & 0x3)),
+        );
+
+        let check = |storage: &Storage<u64, u64, X>| {
+            sequential.rebuild(storage);
+            parallel.par_rebuild(storage);
+            scoped.par_rebuild(storage);
+
+            assert_eq!(
+                sequential.key_counts(storage).collect::<Vec<_>>(),
+                parallel.key_counts(storage).collect::<Vec<_>>()
+            );
+
+            for bit in 0..4 {
+                assert_eq!(
+                    storage
+                        .query(Everything.filter(move |x: &X| x.1 & (1 << bit) != 0))
+                        .count(),
+                    storage
+                        .query(Everything.matching(&parallel, Cow::Owned(bit)))
+                        .count()
+                );
+            }
+
+            assert_eq!(
+                storage
+                    .query(Everything.filter(|x: &X| x.1 & 0x3 == 1 && (x.0 >> 4) & 1 == 0))
+                    .count(),
+                storage
+                    .query(Everything.matching(&scoped, Cow::Owned(1)))
+                    .count()
+            );
+
+            parallel.validate(storage);
+            scoped.validate(storage);
+        };
+
+        for i in 0..0x1000 {
+            storage.add(X(i, i));
+        }
+        check(&storage);
+
+        storage.modify(Everything.filter(|x: &X| x.0 % 5 == 1), |mut editor| {
+            editor.get_mut().1 *= 3;
+        });
+        storage.remove_chunk(&3);
+        parallel.invalidate_chunk(&5);
+        check(&storage);
+
+        parallel.invalidate_all();
+        storage.remove(Everything.filter(|x: &X| x.0 % 7 == 1), std::mem::drop);
+        check(&storage);
+    }
+
+    #[test]
+    fn test_aggregates_agree_with_iterators() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+
+        for i in 0..0x400 {
+            storage.add(X(i, (i * 37) % 101));
+        }
+
+        let query = Everything.filter(|x: &X| x.0 & 0x3 != 0);
+        let key = |x: &X| x.1 % 10;
+
+        let groups = storage.group_by(query, key);
+        let sums = storage.sum_by(query, key, |x| x.1);
+        let mins = storage.min_by(query, key, |x| x.1);
+        let maxs = storage.max_by(query, key, |x| x.1);
+
+        assert_eq!(10, groups.len());
+        for (k, group) in groups.iter() {
+            let expected: Vec<&X> = storage.query(query).filter(|x| key(x) == *k).collect();
+
+            assert_eq!(&expected, group);
+            assert_eq!(expected.iter().map(|x| x.1).sum::<u64>(), sums[k]);
+            assert_eq!(expected.iter().map(|x| x.1).min(), Some(mins[k].1));
+            assert_eq!(expected.iter().map(|x| x.1).max(), Some(maxs[k].1));
+        }
+    }
+
+    #[test]
+    fn test_query_budgeted_agrees_with_query() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+
+        for i in 0..0x100 {
+            storage.add(X(i, i));
+        }
+
+        let query = Chunks(0..8).filter(|x: &X| x.1 & 0x3 == 0);
+        let mut expected: Vec<X> = storage.query(&query).cloned().collect();
+        expected.sort();
+
+        for (budget, expected_calls) in [
+            (Budget::Elements(10), 13),
+            (Budget::Elements(0), 128),
+            (Budget::Duration(Duration::from_secs(0)), 128),
+        ] {
+            let mut actual: Vec<X> = Vec::new();
+            let mut calls = 0;
+            let mut cursor = None;
+
+            loop {
+                let page = storage
+                    .query_budgeted(&query, cursor.as_ref(), budget)
+                    .unwrap();
+                actual.extend(page.elements.into_iter().cloned());
+                calls += 1;
+
+                cursor = match page.next {
+                    Some(next) => Some(next),
+                    None => break,
+                };
+            }
+
+            actual.sort();
+            assert_eq!(expected, actual);
+            assert_eq!(expected_calls, calls);
+        }
+    }
+
+    #[test]
+    fn test_after_agrees_with_filter() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+
+        for i in (0..0x100).rev() {
+            storage.add(X(i, i));
+        }
+
+        let odd = Everything.filter(|x: &X| x.1 & 0x1 == 1);
+
+        for (chunk_key, item_key) in [(0, 0x00), (3, 0x37), (4, 0x00), (3, 0x05), (15, 0xFF)] {
+            let mut expected: Vec<X> = storage
+                .query(&odd)
+                .filter(|x| ((x.0 & 0xF0) >> 4, x.0) > (chunk_key, item_key))
+                .cloned()
+                .collect();
+            let mut actual: Vec<X> = storage
+                .query(odd.after(ID.chunk(chunk_key).item(item_key)))
+                .cloned()
+                .collect();
+            expected.sort();
+            actual.sort();
+
+            assert_eq!(expected, actual);
+        }
+        assert_eq!(
+            13 * 8,
+            storage.query(odd.after(ID.chunk(3).item(0x05))).count()
+        );
+    }
+
+    #[test]
+    fn test_query_rev_agrees_with_query() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let index: SecondaryIndex<u64, X, Option<u64>, u64> =
+            SecondaryIndex::new(&storage, |x: &X| Cow::Owned(Some(x.1 % 3)));
+
+        for i in 0..0x200 {
+            storage.add(X((i * 0x35) & 0x1FF, i));
+        }
+
+        let query = Chunks(0..12).matching(&index, Cow::Owned(1));
+        let mut expected: Vec<X> = storage.query(&query).cloned().collect();
+        expected.reverse();
+        let actual: Vec<X> = storage.query(&query).rev().cloned().collect();
+
+        assert_eq!(0x200 * 12 / 16 / 3, actual.len());
+        assert_eq!(expected, actual);
+
+        let limit = query.clone().limit(5);
+        let mut expected: Vec<X> = storage.query(&limit).cloned().collect();
+        expected.reverse();
+        let actual: Vec<X> = storage.query(&limit).rev().cloned().collect();
+        assert_eq!(5, actual.len());
+        assert_eq!(expected, actual);
+
+        let skip = query.skip(0x80 - 2);
+        let mut expected: Vec<X> = storage.query(&skip).cloned().collect();
+        expected.reverse();
+        let actual: Vec<X> = storage.query(&skip).rev().cloned().collect();
+        assert_eq!(2, actual.len());
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_unique_index_follows_modifications() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let index: UniqueIndex<u64, X, Option<u64>, u64> =
+            UniqueIndex::new(&mut storage, |x: &X| Cow::Owned(Some(x.1)));
+
+        for i in 0..0x100 {
+            storage.add(X(i, i + 0x1000));
+        }
+
+        assert_eq!(Some(&X(0x42, 0x1042)), index.get_unique(&storage, &0x1042));
+        assert_eq!(None, index.get_unique(&storage, &42));
+
+        storage.modify(ID.chunk(4).item(0x42), |mut editor| editor.get_mut().1 = 42);
+        assert_eq!(None, index.get_unique(&storage, &0x1042));
+        assert_eq!(Some(&X(0x42, 42)), index.get_unique(&storage, &42));
+
+        // Moving an index key from one element to another never looks like a duplicate.
+        storage.modify(ID.chunk(4).item(0x42), |mut editor| {
+            editor.get_mut().1 = 0x1042
+        });
+        storage.modify(ID.chunk(9).item(0x99), |mut editor| editor.get_mut().1 = 42);
+        assert_eq!(Some(&X(0x99, 42)), index.get_unique(&storage, &42));
+
+        index.validate(&storage);
+    }
+
+    #[test]
+    fn test_unique_index_try_add() {
+        use crate::types::error::{DuplicateItemError, UniqueAddError};
+
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        for i in 0..0x20 {
+            storage.add(X(i, i + 0x1000));
+        }
+
+        let index: UniqueIndex<u64, X, Option<u64>, u64> =
+            UniqueIndex::new(&mut storage, |x: &X| Cow::Owned(Some(x.1)));
+        assert_eq!(Some(&X(0x11, 0x1011)), index.get_unique(&storage, &0x1011));
+
+        assert_eq!(
+            Err(UniqueAddError::DuplicateIndexKey {
+                element: X(0x35, 0x1011)
+            }),
+            index.try_add(&mut storage, X(0x35, 0x1011))
+        );
+        assert_eq!(
+            Err(UniqueAddError::DuplicateItem(DuplicateItemError {
+                element: X(0x11, 0x2011)
+            })),
+            index.try_add(&mut storage, X(0x11, 0x2011))
+        );
+        assert_eq!(None, storage.get(&ID.chunk(3).item(0x35)));
+        assert_eq!(None, index.get_unique(&storage, &0x2011));
+
+        // Once the element holding an index key is gone, the index key is free again.
+        storage.remove_chunk(&1);
+        assert_eq!(None, index.get_unique(&storage, &0x1011));
+        assert_eq!(Ok(()), index.try_add(&mut storage, X(0x35, 0x1011)));
+        assert_eq!(Some(&X(0x35, 0x1011)), index.get_unique(&storage, &0x1011));
+
+        storage.remove(ID.chunk(3).item(0x35), std::mem::drop);
+        assert_eq!(None, index.get_unique(&storage, &0x1011));
+        assert_eq!(Ok(()), index.try_add(&mut storage, X(0x21, 0x1011)));
+        assert_eq!(Some(&X(0x21, 0x1011)), index.get_unique(&storage, &0x1011));
+
+        storage.validate();
+        index.validate(&storage);
+    }
+
+    #[test]
+    fn test_multi_valued_index_follows_modifications() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let index: SecondaryIndex<u64, X, BTreeSet<u64>, u64> =
+            SecondaryIndex::new_multi(&storage, |x: &X| {
+                let bits = x.1;
+                (0..4).filter(move |bit| bits & (1 << bit) != 0)
+            });
+
+        for i in 0..0x100 {
+            storage.add(X(i, i));
+        }
+
+        for bit in 0..4 {
+            let expected = storage.query(Everything.filter(move |x: &X| x.1 & (1 << bit) != 0));
+            let actual = storage.query(Everything.matching(&index, Cow::Owned(bit)));
+            assert_eq!(expected.count(), actual.count());
+        }
+
+        storage.modify(Everything, |mut editor| editor.get_mut().1 = 0x3);
+        assert_eq!(
+            0x100,
+            storage
+                .query(Everything.matching(&index, Cow::Owned(1)))
+                .count()
+        );
+        assert_eq!(
+            0,
+            storage
+                .query(Everything.matching(&index, Cow::Owned(2)))
+                .count()
+        );
+
+        index.validate(&storage);
+    }
+
+    #[test]
+    fn test_eager_secondary_index_follows_writes() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let index: SecondaryIndex<u64, X, Option<u64>, u64> =
+            SecondaryIndex::new(&storage, |x: &X| Cow::Owned(Some(x.1 & 0x3)));
+
+        storage.add(X(0x001, 1));
+        index.maintain_eagerly(&mut storage);
+
+        // Read the index without letting it catch up, as a query would.
+        let check = |storage: &Storage<u64, u64, X>| {
+            for key in 0..4 {
+                let indexed: usize = index
+                    .postings_of(&key)
+                    .iter()
+                    .map(|(_, idxs)| idxs.len())
+                    .sum();
+                let expected = storage.iter().filter(|x| x.1 & 0x3 == key).count();
+                assert_eq!(expected, indexed);
+            }
+        };
+
+        check(&storage);
+
+        for i in 0x10..0x100 {
+            storage.add(X(i, i));
+        }
+        check(&storage);
+
+        storage.modify(Chunks(1..3), |mut editor| editor.get_mut().1 = 0);
+        check(&storage);
+
+        storage.remove(Chunks(3..4), std::mem::drop);
+        check(&storage);
+
+        storage.compare_and_swap(&ID.chunk(4).item(0x040), |x| Some(X(x.0, 1)));
+        check(&storage);
+
+        // Changes through an Entry are indexed by the next write.
+        storage
+            .entry(ID.chunk(5).item(0x050))
+            .and_modify(|x| x.1 = 1);
+        storage.add(X(0x100, 2));
+        check(&storage);
+
+        drop(index);
+        storage.add(X(0x101, 2));
+        storage.validate();
+    }
+
+    #[test]
+    fn test_invalidate_picks_up_a_changed_indexing_rule() {
+        use std::sync::atomic::{AtomicU64, Ordering};
+        use std::sync::Arc;
+
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let modulus = Arc::new(AtomicU64::new(2));
+        let modulus_in_rule = Arc::clone(&modulus);
+        let index: SecondaryIndex<u64, X, Option<u64>, u64> =
+            SecondaryIndex::new(&storage, move |x: &X| {
+                Cow::Owned(Some(x.1 % modulus_in_rule.load(Ordering::Relaxed)))
+            });
+
+        for i in 0..0x40 {
+            storage.add(X(i, i));
+        }
+
+        let zeros = |storage: &Storage<u64, u64, X>| {
+            storage
+                .query(Everything.matching(&index, Cow::Owned(0)))
+                .count()
+        };
+
+        index.rebuild(&storage);
+        assert_eq!(0x20, zeros(&storage));
+
+        // The index doesn't notice that its rule has changed until it is invalidated.
+        modulus.store(4, Ordering::Relaxed);
+        assert_eq!(0x20, zeros(&storage));
+
+        index.invalidate_chunk(&0);
+        assert_eq!(0x18 + 0x4, zeros(&storage));
+
+        index.invalidate_all();
+        index.rebuild(&storage);
+        assert_eq!(0x10, zeros(&storage));
+
+        index.validate(&storage);
+    }
+
+    #[test]
+    fn test_field_indexes() {
+        let mut storage: Storage<u64, u64, (u64, u64, Option<u64>)> = Storage::new();
+        let by_parent =
+            SecondaryIndex::on_optional_field(&storage, |x: &(u64, u64, Option<u64>)| &x.2);
+        let by_chunk = SecondaryIndex::on_field(&storage, |x: &(u64, u64, Option<u64>)| &x.0);
+
+        for i in 0..0x100 {
+            storage.add((i & 0x3, i, if i % 5 == 0 { None } else { Some(i % 5) }));
+        }
+
+        assert_eq!(
+            0x33,
+            storage
+                .query(Everything.matching(&by_parent, Cow::Owned(1)))
+                .count()
+        );
+        assert_eq!(
+            0x0D,
+            storage
+                .query(
+                    Everything
+                        .matching(&by_parent, Cow::Owned(1))
+                        .matching(&by_chunk, Cow::Owned(1))
+                )
+                .count()
+        );
+
+        storage.modify(ID.chunk(1).item(0x01), |mut editor| {
+            editor.get_mut().2 = None
+        });
+        assert_eq!(
+            0x32,
+            storage
+                .query(Everything.matching(&by_parent, Cow::Owned(1)))
+                .count()
+        );
+
+        by_parent.validate(&storage);
+        by_chunk.validate(&storage);
+    }
+
+    #[test]
+    fn test_index_memory_usage_covers_every_index() {
+        use crate::queries::bloom_index::BloomIndex;
+        use crate::queries::chunk_tag_index::ChunkTagIndex;
+        use crate::queries::counting_index::CountingIndex;
+        use crate::traits::memory_usage::MemoryUser;
+
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let mut by_value: SecondaryIndex<u64, X, Option<u64>, u64> =
+            SecondaryIndex::new(&storage, |x: &X| Cow::Owned(Some(x.1 & 0xF)));
+        let mut counts: CountingIndex<u64, X, Option<u64>, u64> =
+            CountingIndex::new(&storage, |x: &X| Cow::Owned(Some(x.1 & 0xF)));
+        let mut tags: ChunkTagIndex<u64, u64> =
+            ChunkTagIndex::new(&storage, |chunk_key: &u64| Some(chunk_key % 2));
+        let mut bloom: BloomIndex<u64, X, u64> = BloomIndex::item_keys(&storage, 8);
+
+        for i in 0..0x1000 {
+            storage.add(X(i, i));
+        }
+
+        let refresh = |storage: &Storage<u64, u64, X>| {
+            storage
+                .query(Everything.matching(&by_value, Cow::Owned(1)))
+                .count();
+            counts.count(storage, &1);
+            tags.chunk_keys(storage, &1);
+            bloom.find(storage, &0x10000);
+        };
+        refresh(&storage);
+
+        // Each index accounts for more than just the one token it keeps per element.
+        let before = by_value.memory_usage();
+        assert!(before.len > 0x1000);
+        assert!(counts.memory_usage().len > 0x1000);
+        assert!(bloom.memory_usage().len > 0x1000);
+        assert!(tags.memory_usage().len >= 0x10);
+
+        for chunk_key in 1..0x10 {
+            storage.remove_chunk(&chunk_key);
+        }
+        refresh(&storage);
+
+        by_value.shrink();
+        counts.shrink();
+        tags.shrink();
+        bloom.shrink();
+
+        let after = by_value.memory_usage();
+        assert!(after.len < before.len);
+        assert!(after.capacity < before.capacity);
+
+        by_value.validate(&storage);
+        counts.validate(&storage);
+        tags.validate(&storage);
+        bloom.validate(&storage);
+    }
+
+    #[test]
+    fn test_keys_of_reports_recorded_index_keys() {
+        use std::sync::atomic::{AtomicU64, Ordering};
+        use std::sync::Arc;
+
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let modulus = Arc::new(AtomicU64::new(2));
+        let modulus_in_rule = Arc::clone(&modulus);
+        let index: SecondaryIndex<u64, X, Option<u64>, u64> =
+            SecondaryIndex::new(&storage, move |x: &X| {
+                Cow::Owned(Some(x.1 % modulus_in_rule.load(Ordering::Relaxed)))
+            });
+
+        for i in 0..0x40 {
+            storage.add(X(i, i));
+        }
 
-        storage.entry(&ID.chunk(0).item(0));
-        storage.remove(&ID.chunk(0).item(0), std::mem::drop);
+        assert_eq!(Some(Some(1)), index.keys_of(&storage, &X(0x13, 0)));
+        assert_eq!(Some(Some(0)), index.keys_of(&storage, &X(0x22, 0)));
+        assert_eq!(None, index.keys_of(&storage, &X(0x50, 0)));
+
+        // An unchanged element keeps the keys it was indexed under, but a modified one doesn't.
+        modulus.store(4, Ordering::Relaxed);
+        storage.modify(ID.chunk(1).item(0x12), |mut editor| {
+            editor.get_mut().1 = 0x13
+        });
+        assert_eq!(Some(Some(1)), index.keys_of(&storage, &X(0x13, 0)));
+        assert_eq!(Some(Some(1)), index.keys_of(&storage, &X(0x23, 0)));
+        assert_eq!(Some(Some(3)), index.keys_of(&storage, &X(0x12, 0)));
+        assert_eq!(
+            Some(&X(0x12, 0x13)),
+            storage
+                .query(Chunks([1]).matching(&index, Cow::Owned(3)))
+                .find(|x| x.0 == 0x12)
+        );
+
+        index.validate(&storage);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_restored_index_follows_modifications() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        for i in 0..0x100 {
+            storage.add(X(i, i & 0x7));
+        }
+
+        let saved = SecondaryIndex::<u64, X, Option<u64>, u64>::new(&storage, |x: &X| {
+            Cow::Owned(Some(x.1))
+        })
+        .save(&storage);
+        assert_eq!(16, saved.len());
+
+        storage.remove(Chunks(2..3), std::mem::drop);
+        storage.modify(Chunks(3..4), |mut editor| editor.get_mut().1 = 1);
+        storage.add(X(0x020, 0));
+
+        let index: SecondaryIndex<u64, X, Option<u64>, u64> =
+            SecondaryIndex::new(&storage, |x: &X| Cow::Owned(Some(x.1)));
+        assert_eq!(14, index.restore(&storage, &saved));
+
+        storage.modify(Chunks(4..6), |mut editor| editor.get_mut().1 = 0);
+        storage.remove(Chunks(6..7), std::mem::drop);
+
+        for key in 0..8 {
+            assert_eq!(
+                storage
+                    .query(Everything.filter(move |x: &X| x.1 == key))
+                    .count(),
+                storage
+                    .query(Everything.matching(&index, Cow::Owned(key)))
+                    .count()
+            );
+        }
+
+        index.validate(&storage);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_restored_reduction_follows_modifications() {
+        let new_reduction = |storage: &Storage<u64, u64, X>| -> Reduction<u64, X, u64> {
+            Reduction::new(
+                storage,
+                2,
+                |x: &X, was: &u64| Some(x.1).filter(|x| x != was),
+                |xs: &[u64], was: &u64| Some(xs.iter().sum()).filter(|x| x != was),
+            )
+        };
+
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        for i in 0..0x100 {
+            storage.add(X(i, i & 0x7));
+        }
+
+        let saved = new_reduction(&storage).save(&storage);
+        assert_eq!(16, saved.len());
+
+        storage.remove(Chunks(2..3), std::mem::drop);
+        storage.modify(Chunks(3..4), |mut editor| editor.get_mut().1 = 1);
+        storage.add(X(0x020, 0));
+
+        let total = |storage: &Storage<u64, u64, X>| storage.iter().map(|x| x.1).sum::<u64>();
+        let mut reduction = new_reduction(&storage);
+        assert_eq!(14, reduction.restore(&storage, &saved));
+        assert_eq!(Some(&total(&storage)), reduction.reduce(&storage));
+
+        storage.modify(Chunks(4..6), |mut editor| editor.get_mut().1 = 0);
+        storage.remove(Chunks(6..7), std::mem::drop);
+        storage.add(X(0x1000, 9));
+        assert_eq!(Some(&total(&storage)), reduction.reduce(&storage));
+        assert_eq!(Some(&56), reduction.reduce_chunk(&storage, &1));
+    }
+
+    #[test]
+    fn test_secondary_index_is_shared_across_reader_threads() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let index: SecondaryIndex<u64, X, Option<u64>, u64> =
+            SecondaryIndex::new(&storage, |x: &X| Cow::Owned(Some(x.1 % 4)));
+
+        for i in 0..0x400 {
+            storage.add(X(i, i * 3));
+        }
+
+        let storage = &storage;
+        let index = &index;
+        std::thread::scope(|scope| {
+            for key in 0..4 {
+                scope.spawn(move || {
+                    assert_eq!(
+                        storage
+                            .query(Everything.filter(move |x: &X| x.1 % 4 == key))
+                            .count(),
+                        storage
+                            .query(Everything.matching(index, Cow::Owned(key)))
+                            .count()
+                    );
+                });
+            }
+        });
+
+        index.validate(storage);
+    }
+
+    #[test]
+    fn test_index_stats_agree_with_queries() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let bits: SecondaryIndex<u64, X, BTreeSet<u64>, u64> =
+            SecondaryIndex::new_multi(&storage, |x: &X| {
+                let bits = x.1;
+                (0..4).filter(move |bit| bits & (1 << bit) != 0)
+            });
+        let scoped: SecondaryIndex<u64, X, Option<u64>, u64> = SecondaryIndex::new_scoped(
+            &storage,
+            |chunk_key: &u64| *chunk_key < 4,
+            |x: &X| Cow::Owned(Some(x.1 & 0x3)),
+        );
+
+        for i in 0..0x100 {
+            storage.add(X(i, i & 0xF));
+        }
+
+        let stats = bits.stats(&storage);
+        assert_eq!(4, stats.distinct_keys);
+        assert_eq!(0x100 * 2, stats.postings);
+        assert_eq!(0x100, stats.elements);
+        for bit in 0..4 {
+            assert_eq!(
+                storage
+                    .query(Everything.matching(&bits, Cow::Owned(bit)))
+                    .count(),
+                stats.counts[&bit]
+            );
+            assert_eq!(0.5, stats.selectivity(&bit));
+        }
+        assert_eq!(vec![0, 1, 2, 3], bits.keys(&storage).collect::<Vec<u64>>());
+        for (bit, count) in bits.key_counts(&storage) {
+            assert_eq!(stats.counts[&bit], count);
+        }
+
+        let stats = scoped.stats(&storage);
+        assert_eq!(0x40, stats.postings);
+        assert_eq!(0x40, stats.elements);
+        assert_eq!(Some(0x10), stats.most_common().map(|(_, count)| count));
+
+        bits.validate(&storage);
+        scoped.validate(&storage);
+    }
+
+    #[test]
+    fn test_partial_index_only_matches_elements_satisfying_predicate() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let odd: SecondaryIndex<u64, X, Option<u64>, u64> = SecondaryIndex::new_partial(
+            &storage,
+            |x: &X| x.1 % 2 == 1,
+            |x: &X| Cow::Owned(Some(x.1 % 5)),
+        );
+
+        for i in 0..0x100 {
+            storage.add(X(i, i * 3));
+        }
+
+        let check = |storage: &Storage<u64, u64, X>| {
+            for key in 0..5 {
+                assert_eq!(
+                    storage
+                        .query(Everything.filter(move |x: &X| x.1 % 2 == 1 && x.1 % 5 == key))
+                        .count(),
+                    storage
+                        .query(Everything.matching(&odd, Cow::Owned(key)))
+                        .count()
+                );
+            }
+
+            assert_eq!(
+                storage
+                    .query(Everything.filter(|x: &X| x.1 % 2 == 1))
+                    .count(),
+                odd.stats(storage).postings
+            );
+        };
+
+        check(&storage);
+
+        storage.modify(Everything.filter(|x: &X| x.0 % 3 == 1), |mut x| {
+            x.get_mut().1 += 1;
+        });
+
+        check(&storage);
+
+        storage.validate();
+        odd.validate(&storage);
+    }
+
+    #[test]
+    fn test_query_in_order_agrees_with_order_by() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let index: OrderedSecondaryIndex<u64, X, Option<u64>, u64> =
+            OrderedSecondaryIndex::new(&storage, |x: &X| Cow::Owned(Some(x.1)));
+
+        for i in 0..0x200 {
+            storage.add(X(i, (i * 0x35) % 0x61));
+        }
+
+        let query = Chunks(2..14).filter(|x: &X| x.0 & 0x1 == 0);
+        let expected: Vec<X> = storage.order_by(&query, |x| x.1).cloned().collect();
+        let actual: Vec<X> = index.query_in_order(&storage, &query).cloned().collect();
+
+        assert_eq!(expected, actual);
+        assert_eq!(Some(0), index.min_key(&storage));
+        assert_eq!(Some(0x60), index.max_key(&storage));
+    }
+
+    #[test]
+    fn test_find_agrees_with_get() {
+        let mut indexed: Storage<u64, u64, X> = StorageBuilder::new().item_key_index(true).build();
+        let mut other: Storage<u64, u64, X> = StorageBuilder::new().item_key_index(true).build();
+
+        for i in 0..0x100 {
+            indexed.add(X(i, i));
+        }
+        for i in 0x100..0x140 {
+            other.add(X(i, i));
+        }
+
+        indexed.remove(Chunks(2..4), std::mem::drop);
+        indexed.remove_chunk(&5);
+        indexed.transfer_chunk(&6, &mut other);
+        indexed.append(&mut other);
+        indexed
+            .entry(ID.chunk(5).item(0x050))
+            .or_insert_with(|| X(0x050, 7));
+        indexed
+            .entry(ID.chunk(7).item(0x07F))
+            .and_modify(|x| x.1 = 7);
+
+        let mut unindexed: Storage<u64, u64, X> = Storage::new();
+        unindexed.add_chunks(indexed.raw().map(|chunk| chunk.to_vec()));
+        assert!(indexed.has_item_key_index());
+        assert!(!unindexed.has_item_key_index());
+
+        for i in 0..0x180 {
+            let expected = indexed.get(&ID.chunk((i & 0xF0) >> 4).item(i));
+            assert_eq!(expected, indexed.find(&i));
+            assert_eq!(expected, unindexed.find(&i));
+        }
+
+        assert_eq!(Some(&X(0x050, 7)), indexed.find(&0x050));
+        assert_eq!(None, indexed.find(&0x051));
+        assert_eq!(Some(&X(0x13F, 0x13F)), indexed.find(&0x13F));
+
+        indexed.validate();
+        other.validate();
+    }
+
+    #[test]
+    fn test_find_with_item_key_in_several_chunks() {
+        let mut storage: Storage<u64, u64, (u64, u64, &str)> =
+            StorageBuilder::new().item_key_index(true).build();
+        storage.add((1, 7, "a")).add((2, 7, "b")).add((3, 7, "c"));
+
+        storage.remove(ID.chunk(1).item(7), std::mem::drop);
+        assert!(storage.find(&7).is_some());
+        storage.remove_chunk(&3);
+        assert_eq!(Some(&(2, 7, "b")), storage.find(&7));
+        storage.remove(ID.chunk(2).item(7), std::mem::drop);
+        assert_eq!(None, storage.find(&7));
+
+        storage
+            .entry(ID.chunk(4).item(7))
+            .or_insert_with(|| (4, 7, "d"));
+        assert_eq!(Some(&(4, 7, "d")), storage.find(&7));
+        storage.validate();
+    }
+
+    #[test]
+    fn test_find_after_compare_and_swap_repairs_changed_id() {
+        let mut storage: Storage<u64, u64, X> = StorageBuilder::new()
+            .item_key_index(true)
+            .strictness(Strictness::Repair)
+            .build();
+        storage.add(X(0x001, 1)).add(X(0x011, 2));
+
+        storage.compare_and_swap(&ID.chunk(0).item(0x001), |_| Some(X(0x012, 3)));
+
+        assert_eq!(None, storage.find(&0x001));
+        assert_eq!(Some(&X(0x012, 3)), storage.find(&0x012));
+        storage.validate();
+    }
+
+    #[test]
+    fn test_ordered_secondary_index_follows_modifications() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let index: OrderedSecondaryIndex<u64, X, Option<u64>, u64> =
+            OrderedSecondaryIndex::new(&storage, |x: &X| Cow::Owned(Some(x.1)));
+
+        storage
+            .add(X(0x001, 10))
+            .add(X(0x002, 20))
+            .add(X(0x011, 30))
+            .add(X(0x012, 20));
+
+        let ids = |storage: &Storage<u64, u64, X>, start: u64, end: u64| {
+            let mut result: Vec<u64> = storage
+                .query(Everything.matching_range(&index, start..end))
+                .map(|x| x.0)
+                .collect();
+            result.sort();
+            result
+        };
+
+        assert_eq!(vec![0x002, 0x011, 0x012], ids(&storage, 20, 40));
+        assert_eq!(Vec::<u64>::new(), ids(&storage, 40, 20));
+
+        storage.modify(&ID.chunk(1).item(0x011), |mut editor| {
+            editor.get_mut().1 = 5;
+        });
+        storage.remove(&ID.chunk(0).item(0x002), std::mem::drop);
+
+        assert_eq!(vec![0x012], ids(&storage, 20, 40));
+        assert_eq!(vec![0x001, 0x011], ids(&storage, 0, 20));
+
+        storage.validate();
+        index.validate(&storage);
+    }
+
+    #[test]
+    fn test_dedup_window_on_chunk_paths() {
+        // long enough that nothing expires during the test, however slowly it runs
+        let mut storage: Storage<u64, u64, X> = StorageBuilder::new()
+            .dedup_window(DedupWindow::Duration(Duration::from_secs(3600)))
+            .build();
+
+        storage.add_chunk(vec![X(0x001, 1), X(0x002, 2)]);
+        storage.add_chunk(vec![X(0x002, 3), X(0x003, 4)]);
+        assert!(storage
+            .try_add_chunks(vec![vec![X(0x011, 5)], vec![X(0x003, 6)]])
+            .is_ok());
+        assert!(storage.try_add(X(0x011, 7)).is_ok());
+
+        assert_eq!(
+            vec![1, 2, 4, 5],
+            storage
+                .iter()
+                .map(|x| x.1)
+                .collect::<BTreeSet<u64>>()
+                .into_iter()
+                .collect::<Vec<u64>>()
+        );
+        assert_eq!(
+            Some(DedupStats {
+                admitted: 4,
+                dropped: 3
+            }),
+            storage.dedup_stats()
+        );
+
+        storage.remove(&ID.chunk(0).item(0x001), std::mem::drop);
+        storage.add(X(0x001, 8));
+        assert_eq!(None, storage.get(&ID.chunk(0).item(0x001)));
+
+        storage.validate();
+    }
+
+    #[test]
+    fn test_dedup_window_expires() {
+        use std::sync::mpsc::channel;
+        use std::time::Instant;
+
+        let window = Duration::from_millis(20);
+        let mut storage: Storage<u64, u64, X> = StorageBuilder::new()
+            .dedup_window(DedupWindow::Duration(window))
+            .build();
+
+        storage.add(X(0x001, 1));
+        let added = Instant::now();
+        storage.remove(ID.chunk(0).item(0x001), std::mem::drop);
+
+        // Signal once the window has certainly passed since the first add returned, however
+        // long any single wait turns out to be.
+        let (expired_tx, expired_rx) = channel();
+        std::thread::spawn(move || {
+            while added.elapsed() < window {
+                std::thread::park_timeout(window.saturating_sub(added.elapsed()));
+            }
+            expired_tx.send(()).unwrap();
+        });
+        expired_rx.recv().unwrap();
+
+        storage.add(X(0x001, 2));
+        assert_eq!(Some(&X(0x001, 2)), storage.get(&ID.chunk(0).item(0x001)));
+        assert_eq!(
+            Some(DedupStats {
+                admitted: 2,
+                dropped: 0
+            }),
+            storage.dedup_stats()
+        );
+
+        storage.validate();
+    }
+
+    #[cfg(feature = "rand")]
+    #[test]
+    fn test_sample_is_weighted_by_chunk_length() {
+        use rand::rngs::StdRng;
+        use rand::SeedableRng;
+
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        storage.add(X(0x001, 1));
+        for i in 0..9 {
+            storage.add(X(0x010 + i, 1));
+        }
+
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut small_chunk = 0;
+
+        for _ in 0..10000 {
+            let sample = storage.sample(Everything, 1, &mut rng);
+            assert_eq!(1, sample.len());
+            if sample[0].0 == 0x001 {
+                small_chunk += 1;
+            }
+        }
+
+        assert!(small_chunk > 850 && small_chunk < 1150, "{}", small_chunk);
+        assert!(storage.sample(Everything, 0, &mut rng).is_empty());
+        assert_eq!(10, storage.sample(Everything, 20, &mut rng).len());
+    }
+
+    #[test]
+    fn test_export_records_order_is_independent_of_history() {
+        let mut forward: Storage<u64, u64, X> = Storage::new();
+        let mut backward: Storage<u64, u64, X> = Storage::new();
+
+        for i in 0..0x40 {
+            forward.add(X(i, i));
+            backward.add(X(0x3F - i, 0x3F - i));
+        }
+
+        forward.remove(ID.chunk(1).item(0x13), std::mem::drop);
+        backward.remove(ID.chunk(1).item(0x13), std::mem::drop);
+
+        let export = |storage: &Storage<u64, u64, X>| -> Vec<(u64, u64)> {
+            storage
+                .export_records(Everything)
+                .map(|record| (*record.chunk_key, *record.item_key))
+                .collect()
+        };
+
+        let expected: Vec<(u64, u64)> = (0..0x40)
+            .filter(|i| *i != 0x13)
+            .map(|i| ((i & 0xF0) >> 4, i))
+            .collect();
+
+        assert_eq!(expected, export(&forward));
+        assert_eq!(expected, export(&backward));
+        assert_eq!(
+            vec![0, 1, 2, 3],
+            backward
+                .raw()
+                .map(|chunk| chunk[0].0 >> 4)
+                .collect::<Vec<u64>>()
+        );
+    }
+
+    #[test]
+    fn test_count_agrees_with_query() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let index: SecondaryIndex<u64, X, Option<u64>, u64> =
+            SecondaryIndex::new(&storage, |x: &X| Cow::Owned(Some(x.1 % 3)));
+
+        for i in 0..0x80 {
+            storage.add(X(i, i));
+        }
+        storage.remove(Chunks([2]), std::mem::drop);
+
+        fn check<Q: Query<u64, u64, X> + Clone>(storage: &Storage<u64, u64, X>, query: Q) {
+            assert_eq!(storage.query(query.clone()).count(), storage.count(query));
+        }
+
+        let zero = Everything.matching(&index, Cow::Owned(0));
+        let small = Everything.filter(|x: &X| x.0 < 0x18);
+
+        check(&storage, Everything);
+        check(&storage, Chunks([0, 2, 3, 9]));
+        check(&storage, ID.chunk(1).item(0x11));
+        check(&storage, ID.chunk(3).items(0x31..0x38));
+        check(&storage, ID.chunk(3).items([0x31, 0x55]));
+        check(&storage, zero.clone());
+        check(&storage, zero.clone().and(small));
+        check(&storage, zero.clone().or(small));
+        check(&storage, zero.clone().not());
+        check(&storage, zero.limit(7));
+    }
+
+    #[test]
+    fn test_prepared_query_follows_modifications() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let index: SecondaryIndex<u64, X, Option<u64>, u64> =
+            SecondaryIndex::new(&storage, |x: &X| Cow::Owned(Some(x.1 % 3)));
+
+        let query = Everything
+            .matching(&index, Cow::Owned(0))
+            .filter(|x: &X| x.0 & 1 == 0);
+        let mut prepared = PreparedQuery::new(&storage, query.clone());
+        // a query that isn't chunk-local must be evaluated again in full
+        let mut limited = PreparedQuery::new(&storage, query.clone().limit(5));
+
+        fn check<Q: Query<u64, u64, X> + Clone>(
+            storage: &Storage<u64, u64, X>,
+            prepared: &mut PreparedQuery<Q>,
+        ) {
+            let mut expected: Vec<X> = storage
+                .query(prepared.as_query().clone())
+                .cloned()
+                .collect();
+            let mut actual: Vec<X> = prepared.query(storage).cloned().collect();
+            expected.sort();
+            actual.sort();
+
+            assert_eq!(expected, actual);
+            assert_eq!(expected.len(), prepared.count(storage));
+        }
+
+        check(&storage, &mut prepared);
+        check(&storage, &mut limited);
+
+        for i in 0..0x80 {
+            storage.add(X(i, i));
+        }
+        check(&storage, &mut prepared);
+        check(&storage, &mut limited);
+
+        storage.remove(Chunks([2]), std::mem::drop);
+        check(&storage, &mut prepared);
+        check(&storage, &mut limited);
+
+        storage.modify(ID.chunk(5).items(0x50..0x58), |mut editor| {
+            editor.get_mut().1 += 1
+        });
+        check(&storage, &mut prepared);
+        check(&storage, &mut limited);
+
+        storage.remove_chunk(&0);
+        storage.add(X(0x90, 0));
+        check(&storage, &mut prepared);
+        check(&storage, &mut limited);
+    }
+
+    #[test]
+    fn test_query_mut_reindexes_after_drop() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        let mut reduction: Reduction<u64, X, u64> = Reduction::new(
+            &storage,
+            2,
+            |x: &X, _| Some(x.1),
+            |xs: &[u64], _| Some(xs.iter().sum::<u64>()),
+        );
+        let index: SecondaryIndex<u64, X, Option<u64>, u64> =
+            SecondaryIndex::new(&storage, |x: &X| Cow::Owned(Some(x.1 % 2)));
+
+        for i in 0..0x40 {
+            storage.add(X(i, i));
+        }
+
+        assert_eq!(Some(&0x7E0), reduction.reduce(&storage));
+        assert_eq!(
+            32,
+            storage.count(Everything.matching(&index, Cow::Owned(1)))
+        );
+
+        let mut visited = 0;
+        for mut editor in storage.query_mut(Everything.matching(&index, Cow::Owned(1))) {
+            if editor.id().0 == &3 {
+                break;
+            }
+
+            visited += 1;
+            editor.get_mut().1 += 1;
+        }
+
+        assert_eq!(24, visited);
+        assert_eq!(8, storage.count(Everything.matching(&index, Cow::Owned(1))));
+        assert_eq!(Some(&0x7F8), reduction.reduce(&storage));
+        assert_eq!(Some(&X(0x11, 0x12)), storage.get(&ID.chunk(1).item(0x11)));
 
         storage.validate();
     }
 
+    #[test]
+    #[should_panic]
+    fn test_query_mut_rejects_changed_item_key() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+        storage.add(X(0x001, 1));
+
+        for mut editor in storage.query_mut(Everything) {
+            editor.get_mut().0 = 0x002;
+        }
+    }
+
+    #[test]
+    fn test_query_page_invalidation() {
+        let mut storage: Storage<u64, u64, X> = Storage::new();
+
+        for i in (0..0x30).filter(|i| *i != 0x0F) {
+            storage.add(X(i, i));
+        }
+
+        let page = storage.query_page(Everything, None, 4).unwrap();
+        assert_eq!(
+            vec![0x00, 0x01, 0x02, 0x03],
+            page.elements.iter().map(|x| x.0).collect::<Vec<_>>()
+        );
+        let cursor = page.next.unwrap();
+
+        // Changes to other chunks, and additions to this chunk, don't invalidate the cursor.
+        storage.remove(ID.chunk(1).item(0x11), std::mem::drop);
+        storage.modify(ID.chunk(2).item(0x22), |mut editor| editor.get_mut().1 = 0);
+        storage.add(X(0x0F, 0));
+        let page = storage.query_page(Everything, Some(&cursor), 12).unwrap();
+        assert_eq!(
+            (0x04..0x10).collect::<Vec<u64>>(),
+            page.elements.iter().map(|x| x.0).collect::<Vec<_>>()
+        );
+
+        // Removals from this chunk do.
+        storage.remove(ID.chunk(0).item(0x02), std::mem::drop);
+        let error = storage
+            .query_page(Everything, Some(&cursor), 12)
+            .unwrap_err();
+        assert_eq!(cursor, error.cursor);
+
+        // So do removals of other chunks.
+        let page = storage.query_page(Everything, None, 4).unwrap();
+        let cursor = page.next.unwrap();
+        storage.remove(Chunks([2]), std::mem::drop);
+        assert!(storage.query_page(Everything, Some(&cursor), 4).is_err());
+
+        // And cursors from other storages are never valid.
+        let other: Storage<u64, u64, X> = Storage::new();
+        assert!(other
+            .query_page(Everything, Some(&Cursor::from_bytes(cursor.to_bytes())), 4)
+            .is_err());
+    }
+
     #[test]
     fn test_str() {
         let mut storage: Storage<str, str, S> = Storage::new();
@@ -940,4 +4439,30 @@ mod test {
         );
         assert_eq!(2, storage.query(Chunks(vec!["broberts"])).count());
     }
+
+    #[test]
+    fn test_blob_store_rejects_stale_blob_ids() {
+        use crate::types::blob_store::BlobStore;
+
+        let storage: Storage<u64, u64, X> = Storage::new();
+        let mut blobs: BlobStore<u64> = BlobStore::new(&storage);
+
+        let a = blobs.insert(&storage, &1, b"aaaa");
+        let b = blobs.insert(&storage, &1, b"bb");
+        assert!(blobs.remove(&1, a));
+        assert!(!blobs.remove(&1, a));
+
+        // The new blob reuses the slot of the removed one.
+        let c = blobs.insert(&storage, &1, b"c");
+        assert_ne!(a, c);
+        assert_eq!(None, blobs.get(&1, a));
+        assert!(!blobs.remove(&1, a));
+        assert_eq!(Some(&b"c"[..]), blobs.get(&1, c));
+
+        blobs.compact(&storage);
+        assert_eq!(None, blobs.get(&1, a));
+        assert_eq!(Some(&b"bb"[..]), blobs.get(&1, b));
+        assert_eq!(Some(&b"c"[..]), blobs.get(&1, c));
+        assert_eq!(3, blobs.arena(&1).unwrap().len());
+    }
 }
@@ -1,6 +1,8 @@
 pub use crate::queries::chunks::Chunks;
 pub use crate::queries::everything::Everything;
+pub use crate::queries::ordered_secondary_index::OrderedSecondaryIndex;
 pub use crate::queries::secondary_index::SecondaryIndex;
+pub use crate::queries::text_index::TextIndex;
 pub use crate::traits::query::Query;
 pub use crate::traits::record::Record;
 pub use crate::types::editor::Editor;
@@ -0,0 +1,549 @@
+use crate::internal::mr::rvec::RVec;
+use crate::internal::mr::summarize::{Summarize, SummaryRules};
+use crate::traits::memory_usage::{MemoryUsage, MemoryUser};
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::storage::Storage;
+use crate::types::storage_builder::Strictness;
+use std::borrow::Borrow;
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::sync::RwLock;
+
+/// A bloom filter for each chunk of a `Storage`, over the item keys of its elements or any
+/// other value computed from each element. A bloom filter can answer that a chunk definitely
+/// has no element with a key, without touching the chunk itself, so use a `BloomIndex` when
+/// most of the keys you look up don't exist.
+///
+/// A bloom filter sometimes answers that a chunk might have an element with a key when it
+/// doesn't, which is called a false positive. More bits per key make false positives rarer, at
+/// the cost of more memory: 10 bits per key gives about one false positive per hundred misses.
+/// `BloomIndex::stats` reports how many false positives have actually been seen.
+///
+/// Bloom filters can't forget a key, so each chunk's filter is rebuilt from scratch the next
+/// time it is used after any of its keys is removed or changed. Adding elements doesn't cause
+/// a rebuild until the filter runs out of room.
+///
+/// # Type Parameters
+///
+/// * `ChunkKey`: The chunk key type of the `Storage`.
+/// * `Element`: The element type of the `Storage`.
+/// * `Key`: The type of the keys in each filter. This is the `ItemKey` of the `Storage` for
+///   `BloomIndex::item_keys`.
+///
+/// # Example
+///
+/// ```
+/// use retriever::prelude::*;
+/// use retriever::queries::bloom_index::BloomIndex;
+///
+/// // Sessions chunked by shard, keyed by session token.
+/// let mut storage : Storage<u64, u64, (u64, u64, &'static str)> = Storage::new();
+/// let sessions : BloomIndex<u64, (u64, u64, &'static str), u64> =
+///   BloomIndex::item_keys(&storage, 10);
+///
+/// for token in 0..1000 {
+///   storage.add((token % 4, token, "active"));
+/// }
+///
+/// assert_eq!(Some(&(1, 17, "active")), sessions.get(&storage, &ID.chunk(1).item(17)));
+/// assert_eq!(Some(&(2, 42, "active")), sessions.find(&storage, &42));
+///
+/// // Most of these are turned away by the bloom filter alone.
+/// for token in 1000..2000 {
+///   assert_eq!(None, sessions.get(&storage, &ID.chunk(token % 4).item(token)));
+/// }
+///
+/// let stats = sessions.stats();
+/// assert!(stats.negatives > 900);
+/// assert!(stats.false_positive_rate() < 0.1);
+///
+/// # storage.validate();
+/// # sessions.validate(&storage);
+/// ```
+pub struct BloomIndex<ChunkKey, Element, Key>(
+    Arc<RwLock<BloomIndexImpl<ChunkKey, Element>>>,
+    // the type of the keys that were hashed into the filters
+    PhantomData<fn(&Key)>,
+)
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    Key: Hash + ?Sized;
+
+impl<ChunkKey, Element, Key> Clone for BloomIndex<ChunkKey, Element, Key>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    Key: Hash + ?Sized,
+{
+    fn clone(&self) -> Self {
+        BloomIndex(Arc::clone(&self.0), PhantomData)
+    }
+}
+
+/// Counts of the lookups made through a `BloomIndex`. See `BloomIndex::stats`.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct BloomStats {
+    /// The number of times a chunk's bloom filter was tested for a key.
+    pub probes: u64,
+    /// The number of probes answered by the bloom filter alone, because the key was
+    /// definitely absent.
+    pub negatives: u64,
+    /// The number of probes that the bloom filter passed on to the chunk, where the key turned
+    /// out to be absent after all. Only `BloomIndex::get` and `BloomIndex::find` can tell.
+    pub false_positives: u64,
+}
+
+impl BloomStats {
+    /// The fraction of the probes of absent keys that the bloom filter failed to answer, from
+    /// 0.0 to 1.0.
+    pub fn false_positive_rate(&self) -> f64 {
+        match self.negatives + self.false_positives {
+            0 => 0.0,
+            misses => self.false_positives as f64 / misses as f64,
+        }
+    }
+}
+
+#[derive(Default)]
+struct ChunkBloom {
+    bits: Vec<u64>,
+    // the number of keys in this filter
+    len: usize,
+    // true if the filter must be rebuilt before it can be used
+    stale: bool,
+}
+
+struct BloomIndexImpl<ChunkKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+{
+    // parent_id, used to see that this BloomIndex isn't suddenly used with a different parent storage
+    parent_id: u64,
+    // gc_chunk_list, remember the chunks from our last update, so we can remove filters for newly-absent chunks
+    gc_chunk_list: RVec<Option<ChunkKey::Owned>>,
+    // rule for hashing the key of each element
+    rules: Arc<SummaryRules<Element, Option<u64>, ChunkBloom>>,
+    bits_per_key: usize,
+    // the number of bits set for each key
+    hashes: u64,
+    // the filter of each chunk
+    index: HashMap<
+        ChunkKey::Owned,
+        Summarize<Element, Option<u64>, ChunkBloom>,
+        crate::internal::hasher::HasherImpl,
+    >,
+    stats: BloomStats,
+}
+
+impl<ChunkKey, Element, Key> BloomIndex<ChunkKey, Element, Key>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    Key: Hash + ToOwned + ?Sized,
+{
+    /// Create a new BloomIndex of a storage, with a filter of each chunk over the keys produced
+    /// by the given rule, using about `bits_per_key` bits per key.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `bits_per_key` is zero.
+    pub fn new<ItemKey, F>(
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        bits_per_key: usize,
+        f: F,
+    ) -> Self
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        F: Fn(&Element) -> Cow<Key> + Send + Sync + 'static,
+    {
+        assert!(
+            bits_per_key > 0,
+            "A BloomIndex needs at least one bit per key"
+        );
+
+        // the number of hashes that minimizes the false positive rate
+        let hashes = ((bits_per_key as f64) * std::f64::consts::LN_2)
+            .round()
+            .clamp(1.0, 16.0) as u64;
+
+        BloomIndex(
+            Arc::new(RwLock::new(BloomIndexImpl {
+                parent_id: storage.id(),
+                gc_chunk_list: RVec::default(),
+                rules: Arc::new(BloomIndexImpl::<ChunkKey, Element>::bloom_rules(
+                    bits_per_key,
+                    hashes,
+                    f,
+                )),
+                bits_per_key,
+                hashes,
+                index: HashMap::with_hasher(crate::internal::hasher::HasherImpl::default()),
+                stats: BloomStats::default(),
+            })),
+            PhantomData,
+        )
+    }
+
+    /// Bring the filter of the given chunk up to date, and test whether that chunk might have
+    /// an element with the given key. False means that it definitely doesn't.
+    pub fn may_contain<ItemKey>(
+        &self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        chunk_key: &ChunkKey,
+        key: &Key,
+    ) -> bool
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        self.0
+            .write()
+            .unwrap()
+            .probe(storage, chunk_key, hash_of(key))
+    }
+
+    /// Counts of the lookups made through this index so far.
+    pub fn stats(&self) -> BloomStats {
+        self.0.read().unwrap().stats
+    }
+
+    /// Panic if this storage is malformed or broken in any detectable way.
+    /// This is a slow operation and you shouldn't use it unless you suspect a problem.
+    pub fn validate<ItemKey>(&self, parent: &Storage<ChunkKey, ItemKey, Element>)
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        let mut bloom_index_impl = self.0.write().unwrap();
+        bloom_index_impl.refresh_all(parent);
+
+        for chunk_key in bloom_index_impl.index.keys() {
+            assert!(parent.internal_idx_of(chunk_key.borrow()).is_some());
+        }
+
+        let hashes = bloom_index_impl.hashes;
+        for chunk_storage in parent.internal_rvec().iter() {
+            let summarize = &bloom_index_impl.index[chunk_storage.chunk_key()];
+            let bloom = summarize.peek();
+            assert!(!bloom.stale);
+            for hash in summarize.tokens().iter().flatten() {
+                assert!(bloom.contains(*hash, hashes), "A BloomIndex lost a key");
+            }
+        }
+    }
+}
+
+impl<ChunkKey, Element, ItemKey> BloomIndex<ChunkKey, Element, ItemKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+{
+    /// Create a new BloomIndex of a storage, with a filter of each chunk over its item keys,
+    /// using about `bits_per_key` bits per key.
+    pub fn item_keys(storage: &Storage<ChunkKey, ItemKey, Element>, bits_per_key: usize) -> Self {
+        Self::new(storage, bits_per_key, |element: &Element| {
+            element.item_key()
+        })
+    }
+
+    /// Get an element by its `Id`, like `Storage::get`, but asking the chunk's bloom filter
+    /// first, so that most absent elements are turned away without looking inside the chunk.
+    /// This index must be over the item keys of the `Storage`; see `BloomIndex::item_keys`.
+    pub fn get<'a, R>(
+        &self,
+        storage: &'a Storage<ChunkKey, ItemKey, Element>,
+        unique_id: &R,
+    ) -> Option<&'a Element>
+    where
+        R: Record<ChunkKey, ItemKey>,
+    {
+        let mut bloom_index_impl = self.0.write().unwrap();
+        let item_key = unique_id.item_key();
+        if !bloom_index_impl.probe(
+            storage,
+            unique_id.chunk_key().borrow(),
+            hash_of::<ItemKey>(item_key.as_ref()),
+        ) {
+            return None;
+        }
+
+        let result = storage.get(unique_id);
+        if result.is_none() {
+            bloom_index_impl.stats.false_positives += 1;
+        }
+
+        result
+    }
+
+    /// Get an element knowing only its item key, like `Storage::find`, but only looking inside
+    /// the chunks whose bloom filters might have that item key.
+    /// This index must be over the item keys of the `Storage`; see `BloomIndex::item_keys`.
+    pub fn find<'a>(
+        &self,
+        storage: &'a Storage<ChunkKey, ItemKey, Element>,
+        item_key: &ItemKey,
+    ) -> Option<&'a Element> {
+        let mut bloom_index_impl = self.0.write().unwrap();
+        bloom_index_impl.refresh_all(storage);
+
+        let hash = hash_of(item_key);
+        let hashes = bloom_index_impl.hashes;
+        let BloomIndexImpl { index, stats, .. } = &mut *bloom_index_impl;
+
+        for chunk_storage in storage.internal_rvec().iter() {
+            stats.probes += 1;
+            if !index[chunk_storage.chunk_key()]
+                .peek()
+                .contains(hash, hashes)
+            {
+                stats.negatives += 1;
+                continue;
+            }
+
+            match chunk_storage.internal_idx_of(item_key) {
+                Some(idx) => return Some(chunk_storage.get_idx(idx)),
+                None => stats.false_positives += 1,
+            }
+        }
+
+        None
+    }
+}
+
+impl<ChunkKey, Element> BloomIndexImpl<ChunkKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+{
+    fn bloom_rules<Key, F>(
+        bits_per_key: usize,
+        hashes: u64,
+        f: F,
+    ) -> SummaryRules<Element, Option<u64>, ChunkBloom>
+    where
+        Key: Hash + ToOwned + ?Sized,
+        F: Fn(&Element) -> Cow<Key> + Send + Sync + 'static,
+    {
+        SummaryRules {
+            map: Arc::new(move |element, old_hash, _internal_idx| {
+                let new_hash = Some(hash_of(f(element).as_ref()));
+
+                if old_hash != &new_hash {
+                    Some(new_hash)
+                } else {
+                    None
+                }
+            }),
+            contribute: Arc::new(move |new_hash, _internal_idx, bloom| {
+                bloom.len += 1;
+                if bloom.len * bits_per_key > bloom.bits.len() * 64 {
+                    bloom.stale = true;
+                }
+
+                if let Some(hash) = new_hash {
+                    if !bloom.stale {
+                        bloom.insert(*hash, hashes);
+                    }
+                }
+            }),
+            uncontribute: Arc::new(|_old_hash, _internal_idx, bloom| {
+                bloom.len -= 1;
+                bloom.stale = true;
+            }),
+        }
+    }
+
+    fn probe<ItemKey>(
+        &mut self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        chunk_key: &ChunkKey,
+        hash: u64,
+    ) -> bool
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        self.check_parent(storage);
+        storage.gc(&mut self.gc_chunk_list, &mut self.index);
+
+        self.stats.probes += 1;
+        let result = match storage.internal_idx_of(chunk_key) {
+            Some(idx) => {
+                self.update_chunk(storage, idx);
+                self.index[chunk_key].peek().contains(hash, self.hashes)
+            }
+            None => false,
+        };
+
+        if !result {
+            self.stats.negatives += 1;
+        }
+
+        result
+    }
+
+    fn refresh_all<ItemKey>(&mut self, storage: &Storage<ChunkKey, ItemKey, Element>)
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        self.check_parent(storage);
+        storage.gc(&mut self.gc_chunk_list, &mut self.index);
+
+        for idx in 0..storage.internal_rvec().len() {
+            self.update_chunk(storage, idx);
+        }
+    }
+
+    fn check_parent<ItemKey>(&mut self, storage: &Storage<ChunkKey, ItemKey, Element>)
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        if self.parent_id != storage.id() {
+            assert_eq!(storage.strictness(), Strictness::Repair, "Id mismatch: a bloom index may only be used with it's parent Storage, never any other Storage");
+            #[cfg(feature = "log")]
+            log::warn!(
+                "retriever: repaired bloom index used with a different Storage by rebuilding it"
+            );
+            self.parent_id = storage.id();
+            self.gc_chunk_list = RVec::default();
+            self.index.clear();
+        }
+    }
+
+    fn update_chunk<ItemKey>(&mut self, storage: &Storage<ChunkKey, ItemKey, Element>, idx: usize)
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        let chunk_storage = &storage.internal_rvec()[idx];
+        let internal_storage = chunk_storage.internal_rvec();
+        let rules = &self.rules;
+        let summarize = self
+            .index
+            .entry(chunk_storage.chunk_key().to_owned())
+            .or_insert_with(|| Summarize::new(internal_storage, Arc::clone(rules)));
+        summarize.update(internal_storage);
+
+        if summarize.peek().stale {
+            let bloom = ChunkBloom::build(summarize.tokens(), self.bits_per_key, self.hashes);
+            *summarize.peek_mut() = bloom;
+        }
+    }
+}
+
+impl ChunkBloom {
+    // Build a filter of the given hashes, with room for about as many again.
+    fn build(tokens: &[Option<u64>], bits_per_key: usize, hashes: u64) -> Self {
+        let len = tokens.iter().flatten().count();
+        let mut bloom = ChunkBloom {
+            bits: vec![0; (len.max(32) * 2 * bits_per_key).div_ceil(64)],
+            len,
+            stale: false,
+        };
+
+        for hash in tokens.iter().flatten() {
+            bloom.insert(*hash, hashes);
+        }
+
+        bloom
+    }
+
+    fn insert(&mut self, hash: u64, hashes: u64) {
+        let n = self.bits.len() as u64 * 64;
+        for bit in bits_of(hash, hashes, n) {
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+    }
+
+    fn contains(&self, hash: u64, hashes: u64) -> bool {
+        let n = self.bits.len() as u64 * 64;
+        n > 0
+            && bits_of(hash, hashes, n)
+                .all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+}
+
+impl MemoryUser for ChunkBloom {
+    fn memory_usage(&self) -> MemoryUsage {
+        self.bits.memory_usage()
+    }
+
+    fn shrink_with<F: Fn(&MemoryUsage) -> Option<usize>>(&mut self, f: F) {
+        self.bits.shrink_with(f)
+    }
+}
+
+impl<ChunkKey, Element> MemoryUser for BloomIndexImpl<ChunkKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+{
+    fn memory_usage(&self) -> MemoryUsage {
+        let mut result = self.gc_chunk_list.memory_usage();
+        result = MemoryUsage::merge(result, self.index.memory_usage());
+
+        for s in self.index.values() {
+            result = MemoryUsage::merge(result, s.memory_usage());
+            result = MemoryUsage::merge(result, s.peek().memory_usage());
+        }
+
+        result
+    }
+
+    fn shrink_with<F: Fn(&MemoryUsage) -> Option<usize>>(&mut self, f: F) {
+        self.gc_chunk_list.shrink_with(&f);
+        self.index.shrink_with(&f);
+
+        for i in self.index.values_mut() {
+            i.shrink_with(&f);
+            i.peek_mut().shrink_with(&f);
+        }
+    }
+}
+
+impl<ChunkKey, Element, Key> MemoryUser for BloomIndex<ChunkKey, Element, Key>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    Key: Hash + ?Sized,
+{
+    fn memory_usage(&self) -> MemoryUsage {
+        self.0.read().unwrap().memory_usage()
+    }
+
+    fn shrink_with<F: Fn(&MemoryUsage) -> Option<usize>>(&mut self, f: F) {
+        self.0.write().unwrap().shrink_with(f)
+    }
+}
+
+// The bits, out of n, that are set for a key with the given hash.
+fn bits_of(hash: u64, hashes: u64, n: u64) -> impl Iterator<Item = u64> {
+    let step = hash.rotate_left(32) | 1;
+    (0..hashes).map(move |i| hash.wrapping_add(i.wrapping_mul(step)) % n)
+}
+
+fn hash_of<Key: Hash + ?Sized>(key: &Key) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
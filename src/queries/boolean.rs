@@ -0,0 +1,237 @@
+use crate::bits::Bitset;
+use crate::idxsets::idxrange::IdxRange;
+use crate::idxsets::intersection::Intersection;
+use crate::idxsets::union::Union;
+use crate::traits::idxset::IdxSet;
+use crate::traits::query::Query;
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::chunk_storage::ChunkStorage;
+use crate::types::storage::Storage;
+
+/// Visit only elements that belong to both of two `Queries`.
+#[derive(Copy, Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct And<A, B> {
+    a: A,
+    b: B,
+}
+
+impl<A, B> And<A, B> {
+    /// Construct a new `And` query. Prefer the `Query::and` method instead.
+    pub fn new(a: A, b: B) -> Self {
+        And { a, b }
+    }
+}
+
+impl<ChunkKey, ItemKey, Element, A, B> Query<ChunkKey, ItemKey, Element> for And<A, B>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+    A: Query<ChunkKey, ItemKey, Element>,
+    B: Query<ChunkKey, ItemKey, Element>,
+{
+    type ChunkIdxSet = Intersection<A::ChunkIdxSet, B::ChunkIdxSet>;
+    type ItemIdxSet = Intersection<A::ItemIdxSet, B::ItemIdxSet>;
+
+    fn chunk_idxs(&self, storage: &Storage<ChunkKey, ItemKey, Element>) -> Self::ChunkIdxSet {
+        self.a
+            .chunk_idxs(storage)
+            .intersection(self.b.chunk_idxs(storage))
+    }
+
+    fn item_idxs(
+        &self,
+        chunk_key: &ChunkKey,
+        chunk_storage: &ChunkStorage<ChunkKey, ItemKey, Element>,
+    ) -> Self::ItemIdxSet {
+        self.a
+            .item_idxs(chunk_key, chunk_storage)
+            .intersection(self.b.item_idxs(chunk_key, chunk_storage))
+    }
+
+    fn test(&self, element: &Element) -> bool {
+        self.a.test(element) && self.b.test(element)
+    }
+
+    fn test_chunk(&self, chunk_key: &ChunkKey) -> bool {
+        self.a.test_chunk(chunk_key) && self.b.test_chunk(chunk_key)
+    }
+
+    fn is_exact(&self) -> bool {
+        self.a.is_exact() && self.b.is_exact()
+    }
+
+    fn is_chunk_local(&self) -> bool {
+        self.a.is_chunk_local() && self.b.is_chunk_local()
+    }
+
+    fn describe(&self) -> String {
+        format!("And({}, {})", self.a.describe(), self.b.describe())
+    }
+}
+
+/// Visit elements that belong to either of two `Queries`.
+///
+/// Since each side of an `Or` may reject elements that the other side accepts, the elements
+/// of each visited chunk are tested eagerly against both sides.
+#[derive(Copy, Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Or<A, B> {
+    a: A,
+    b: B,
+}
+
+impl<A, B> Or<A, B> {
+    /// Construct a new `Or` query. Prefer the `Query::or` method instead.
+    pub fn new(a: A, b: B) -> Self {
+        Or { a, b }
+    }
+}
+
+impl<ChunkKey, ItemKey, Element, A, B> Query<ChunkKey, ItemKey, Element> for Or<A, B>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+    A: Query<ChunkKey, ItemKey, Element>,
+    B: Query<ChunkKey, ItemKey, Element>,
+{
+    type ChunkIdxSet = Union<A::ChunkIdxSet, B::ChunkIdxSet>;
+    type ItemIdxSet = Bitset;
+
+    fn chunk_idxs(&self, storage: &Storage<ChunkKey, ItemKey, Element>) -> Self::ChunkIdxSet {
+        self.a.chunk_idxs(storage).union(self.b.chunk_idxs(storage))
+    }
+
+    fn item_idxs(
+        &self,
+        chunk_key: &ChunkKey,
+        chunk_storage: &ChunkStorage<ChunkKey, ItemKey, Element>,
+    ) -> Self::ItemIdxSet {
+        let mut result = matching_idxs(&self.a, chunk_key, chunk_storage);
+
+        for idx in matching_idxs(&self.b, chunk_key, chunk_storage)
+            .iter()
+            .flatten()
+        {
+            result.set(idx);
+        }
+
+        result
+    }
+
+    fn test(&self, _element: &Element) -> bool {
+        true
+    }
+
+    fn test_chunk(&self, chunk_key: &ChunkKey) -> bool {
+        self.a.test_chunk(chunk_key) || self.b.test_chunk(chunk_key)
+    }
+
+    fn is_exact(&self) -> bool {
+        true
+    }
+
+    fn is_chunk_local(&self) -> bool {
+        self.a.is_chunk_local() && self.b.is_chunk_local()
+    }
+
+    fn describe(&self) -> String {
+        format!("Or({}, {})", self.a.describe(), self.b.describe())
+    }
+}
+
+/// Visit only elements that do not belong to a `Query`.
+///
+/// A `Not` query visits every chunk, so it is only as fast as the `Everything` query.
+/// Combine it with a more selective query using `Query::and` where possible.
+#[derive(Copy, Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Not<Q> {
+    query: Q,
+}
+
+impl<Q> Not<Q> {
+    /// Construct a new `Not` query. Prefer the `Query::not` method instead.
+    pub fn new(query: Q) -> Self {
+        Not { query }
+    }
+}
+
+impl<ChunkKey, ItemKey, Element, Q> Query<ChunkKey, ItemKey, Element> for Not<Q>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+    Q: Query<ChunkKey, ItemKey, Element>,
+{
+    type ChunkIdxSet = IdxRange;
+    type ItemIdxSet = Bitset;
+
+    fn chunk_idxs(&self, storage: &Storage<ChunkKey, ItemKey, Element>) -> Self::ChunkIdxSet {
+        // The negated query may need to bring its indices up to date, even though we
+        // visit every chunk regardless.
+        self.query.chunk_idxs(storage);
+
+        IdxRange(0..storage.internal_rvec().len())
+    }
+
+    fn item_idxs(
+        &self,
+        chunk_key: &ChunkKey,
+        chunk_storage: &ChunkStorage<ChunkKey, ItemKey, Element>,
+    ) -> Self::ItemIdxSet {
+        let excluded = matching_idxs(&self.query, chunk_key, chunk_storage);
+
+        (0..chunk_storage.len())
+            .filter(|idx| !excluded.get(*idx))
+            .collect()
+    }
+
+    fn test(&self, _element: &Element) -> bool {
+        true
+    }
+
+    fn is_exact(&self) -> bool {
+        true
+    }
+
+    fn is_chunk_local(&self) -> bool {
+        self.query.is_chunk_local()
+    }
+
+    fn describe(&self) -> String {
+        format!("Not({})", self.query.describe())
+    }
+}
+
+/// The indices of all elements of a chunk that actually belong to a query.
+fn matching_idxs<ChunkKey, ItemKey, Element, Q>(
+    query: &Q,
+    chunk_key: &ChunkKey,
+    chunk_storage: &ChunkStorage<ChunkKey, ItemKey, Element>,
+) -> Bitset
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+    Q: Query<ChunkKey, ItemKey, Element>,
+{
+    if !query.test_chunk(chunk_key) {
+        return Bitset::default();
+    }
+
+    query
+        .item_idxs(chunk_key, chunk_storage)
+        .into_idx_iter()
+        .flatten()
+        .filter(|idx| query.test(chunk_storage.get_idx(*idx)))
+        .collect()
+}
@@ -0,0 +1,278 @@
+use crate::queries::secondary_index::{KeySet, SecondaryIndex};
+use crate::traits::memory_usage::{MemoryUsage, MemoryUser};
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::storage::Storage;
+use std::borrow::{Borrow, Cow};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::sync::RwLock;
+
+// The value extracted from each element, with the hash of the element it was extracted from,
+// by chunk key and item key.
+type ValueCache<ChunkKey, ItemKey, Value> = HashMap<
+    <ChunkKey as ToOwned>::Owned,
+    HashMap<<ItemKey as ToOwned>::Owned, (u64, Value), crate::internal::hasher::HasherImpl>,
+    crate::internal::hasher::HasherImpl,
+>;
+
+/// A `SecondaryIndex` whose index keys are computed from a value that is expensive to extract
+/// from each element, such as a field parsed out of a JSON blob. The extracted value is cached
+/// for each element, and is only extracted again once the element itself changes, so
+/// re-indexing an element that was merely moved within its chunk, or whose neighbours changed,
+/// costs no more than hashing it.
+///
+/// Match against a `CachedIndex` using `Query::matching` and `CachedIndex::as_secondary_index`.
+///
+/// # Type Parameters
+///
+/// * `ChunkKey`: The chunk key type of the `Storage`.
+/// * `ItemKey`: The item key type of the `Storage`.
+/// * `Element`: The element type of the `Storage`.
+/// * `Value`: The type of the value extracted from each element.
+/// * `IndexKeys`: A collection containing the type parameter `IndexKey`, as for a `SecondaryIndex`.
+/// * `IndexKey`: The type of the secondary index key.
+///
+/// # Example
+///
+/// ```
+/// use retriever::prelude::*;
+/// use retriever::queries::cached_index::CachedIndex;
+/// use std::borrow::Cow;
+///
+/// // Events chunked by day, keyed by id, with a payload that is costly to parse.
+/// type Event = (u64, u64, String);
+/// let mut storage : Storage<u64, u64, Event> = Storage::new();
+/// let by_user : CachedIndex<u64, u64, Event, Option<String>, Option<String>, String> =
+///   CachedIndex::new(
+///     &storage,
+///     |x: &Event| x.2.split(';').find_map(|field| field.strip_prefix("user=")).map(String::from),
+///     |user: &Option<String>| Cow::Borrowed(user));
+///
+/// storage.add((1, 1, String::from("user=ann;action=login")));
+/// storage.add((1, 2, String::from("user=bob;action=login")));
+/// storage.add((2, 3, String::from("action=reboot")));
+/// storage.add((2, 4, String::from("user=ann;action=logout")));
+///
+/// let ann = String::from("ann");
+/// let mut ids : Vec<u64> = storage
+///   .query(Everything.matching(by_user.as_secondary_index(), Cow::Borrowed(&ann)))
+///   .map(|x| x.1)
+///   .collect();
+/// ids.sort();
+/// assert_eq!(vec![1, 4], ids);
+///
+/// assert_eq!(Some(Some(String::from("bob"))), by_user.cached_value_of(&storage, &ID.chunk(1).item(2)));
+/// assert_eq!(Some(None), by_user.cached_value_of(&storage, &ID.chunk(2).item(3)));
+/// assert_eq!(None, by_user.cached_value_of(&storage, &ID.chunk(2).item(5)));
+///
+/// # storage.validate();
+/// # by_user.validate(&storage);
+/// ```
+#[allow(clippy::type_complexity)]
+pub struct CachedIndex<ChunkKey, ItemKey, Element, Value, IndexKeys, IndexKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+{
+    index: SecondaryIndex<ChunkKey, Element, IndexKeys, IndexKey>,
+    cache: Arc<RwLock<ValueCache<ChunkKey, ItemKey, Value>>>,
+}
+
+impl<ChunkKey, ItemKey, Element, Value, IndexKeys, IndexKey> Clone
+    for CachedIndex<ChunkKey, ItemKey, Element, Value, IndexKeys, IndexKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+{
+    fn clone(&self) -> Self {
+        CachedIndex {
+            index: self.index.clone(),
+            cache: Arc::clone(&self.cache),
+        }
+    }
+}
+
+impl<ChunkKey, ItemKey, Element, Value, IndexKeys, IndexKey>
+    CachedIndex<ChunkKey, ItemKey, Element, Value, IndexKeys, IndexKey>
+where
+    ChunkKey: BorrowedKey + ?Sized + 'static,
+    ChunkKey::Owned: ValidKey + Send + Sync,
+    ItemKey: BorrowedKey + ?Sized + 'static,
+    ItemKey::Owned: ValidKey + Send + Sync,
+    Element: Record<ChunkKey, ItemKey> + Hash,
+    Value: Clone + Send + Sync + 'static,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+{
+    /// Create a new CachedIndex of a storage. The first rule extracts a value from each element,
+    /// and the second rule constructs the index keys of that value, in the same way as the rule
+    /// of `SecondaryIndex::new`. The first rule is only applied again to an element after the
+    /// element changes.
+    pub fn new<E, F>(storage: &Storage<ChunkKey, ItemKey, Element>, extract: E, f: F) -> Self
+    where
+        E: Fn(&Element) -> Value + Clone + Send + Sync + 'static,
+        F: Fn(&Value) -> Cow<IndexKeys> + Clone + Send + Sync + 'static,
+    {
+        let cache: Arc<RwLock<ValueCache<ChunkKey, ItemKey, Value>>> =
+            Arc::new(RwLock::new(HashMap::default()));
+        let cache_in_rule = Arc::clone(&cache);
+
+        CachedIndex {
+            index: SecondaryIndex::new(storage, move |element: &Element| {
+                Cow::Owned(f(&cached_value(&cache_in_rule, element, &extract)).into_owned())
+            }),
+            cache,
+        }
+    }
+
+    /// This same index, for matching against a single index key using `Query::matching`.
+    pub fn as_secondary_index(&self) -> &SecondaryIndex<ChunkKey, Element, IndexKeys, IndexKey> {
+        &self.index
+    }
+
+    /// Bring this index up to date for the chunk containing an element, and return the value
+    /// extracted from that element, or `None` if there is no such element. See
+    /// `CachedIndex` for an example.
+    pub fn cached_value_of<R>(
+        &self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        unique_id: &R,
+    ) -> Option<Value>
+    where
+        R: Record<ChunkKey, ItemKey>,
+    {
+        self.index.keys_of(storage, unique_id)?;
+
+        self.cache
+            .read()
+            .unwrap()
+            .get(unique_id.chunk_key().borrow())?
+            .get(unique_id.item_key().borrow())
+            .map(|(_, value)| value.clone())
+    }
+
+    /// Forget the cached values of elements that are no longer in the storage. Cached values
+    /// are otherwise kept for removed elements, in case they return.
+    pub fn evict_removed(&self, storage: &Storage<ChunkKey, ItemKey, Element>) {
+        let mut cache = self.cache.write().unwrap();
+
+        cache.retain(|chunk_key, chunk_cache| {
+            let chunk_storage = match storage.internal_idx_of::<ChunkKey>(chunk_key.borrow()) {
+                Some(idx) => &storage.internal_rvec()[idx],
+                None => return false,
+            };
+
+            chunk_cache.retain(|item_key, _| {
+                chunk_storage
+                    .internal_idx_of::<ItemKey>(item_key.borrow())
+                    .is_some()
+            });
+
+            !chunk_cache.is_empty()
+        });
+    }
+
+    /// Panic if this storage is malformed or broken in any detectable way.
+    /// This is a slow operation and you shouldn't use it unless you suspect a problem.
+    pub fn validate(&self, parent: &Storage<ChunkKey, ItemKey, Element>) {
+        self.index.validate(parent);
+    }
+}
+
+impl<ChunkKey, ItemKey, Element, Value, IndexKeys, IndexKey> MemoryUser
+    for CachedIndex<ChunkKey, ItemKey, Element, Value, IndexKeys, IndexKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+{
+    fn memory_usage(&self) -> MemoryUsage {
+        let cache = self.cache.read().unwrap();
+        let mut result = MemoryUsage::merge(self.index.memory_usage(), cache.memory_usage());
+
+        for chunk_cache in cache.values() {
+            result = MemoryUsage::merge(result, chunk_cache.memory_usage());
+        }
+
+        result
+    }
+
+    fn shrink_with<F: Fn(&MemoryUsage) -> Option<usize>>(&mut self, f: F) {
+        self.index.shrink_with(&f);
+
+        let mut cache = self.cache.write().unwrap();
+        cache.shrink_with(&f);
+
+        for chunk_cache in cache.values_mut() {
+            chunk_cache.shrink_with(&f);
+        }
+    }
+}
+
+// The value extracted from an element, reusing the cached value if the element hasn't changed
+// since it was extracted.
+fn cached_value<ChunkKey, ItemKey, Element, Value, E>(
+    cache: &RwLock<ValueCache<ChunkKey, ItemKey, Value>>,
+    element: &Element,
+    extract: &E,
+) -> Value
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey> + Hash,
+    Value: Clone,
+    E: Fn(&Element) -> Value,
+{
+    let hash = hash_of(element);
+    let chunk_key = element.chunk_key();
+    let item_key = element.item_key();
+
+    let cached = cache
+        .read()
+        .unwrap()
+        .get(chunk_key.borrow())
+        .and_then(|chunk_cache| chunk_cache.get(item_key.borrow()))
+        .filter(|(cached_hash, _)| *cached_hash == hash)
+        .map(|(_, value)| value.clone());
+
+    if let Some(value) = cached {
+        return value;
+    }
+
+    let value = extract(element);
+    cache
+        .write()
+        .unwrap()
+        .entry(chunk_key.into_owned())
+        .or_default()
+        .insert(item_key.into_owned(), (hash, value.clone()));
+
+    value
+}
+
+fn hash_of<Key: Hash + ?Sized>(key: &Key) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
@@ -0,0 +1,109 @@
+use crate::bits::Bitset;
+use crate::idxsets::idxrange::IdxRange;
+use crate::internal::bounds::borrow_bound;
+use crate::traits::query::Query;
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::chunk_storage::ChunkStorage;
+use crate::types::storage::Storage;
+use std::borrow::Borrow;
+use std::ops::{Range, RangeBounds, RangeFrom, RangeInclusive, RangeTo, RangeToInclusive};
+
+/// A `Query` that visits every chunk whose chunk key falls within a range, without
+/// enumerating every possible chunk key as `Chunks` would.
+///
+/// If the `Storage` was built with `StorageBuilder::ordered_chunk_keys`, only the matching
+/// chunks are visited. Otherwise every chunk key is tested against the range.
+///
+/// `ChunkRange` supports `Range`, `RangeInclusive`, `RangeFrom`, `RangeTo`, and
+/// `RangeToInclusive`.
+///
+/// # Example
+///
+/// ```
+/// use retriever::prelude::*;
+/// use retriever::queries::chunk_range::ChunkRange;
+/// use retriever::types::storage_builder::StorageBuilder;
+///
+/// // Events chunked by day.
+/// let mut storage : Storage<u32, u64, (u32, u64, &'static str)> = StorageBuilder::new()
+///   .ordered_chunk_keys(true)
+///   .build();
+///
+/// storage.add((20240101, 1, "new year"));
+/// storage.add((20240214, 1, "valentines"));
+/// storage.add((20240317, 1, "st. patrick's"));
+/// storage.add((20241031, 1, "halloween"));
+///
+/// let mut events : Vec<&str> = storage
+///   .query(ChunkRange(20240201..20240401))
+///   .map(|x| x.2)
+///   .collect();
+/// events.sort();
+///
+/// assert_eq!(vec!["st. patrick's", "valentines"], events);
+/// assert_eq!(1, storage.query(ChunkRange(20240601..)).count());
+///
+/// # storage.validate();
+/// ```
+#[derive(Copy, Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct ChunkRange<R>(pub R);
+
+macro_rules! chunk_range_query_impl {
+    ( $range:ident ) => {
+        impl<Q, ChunkKey, ItemKey, Element> Query<ChunkKey, ItemKey, Element>
+            for ChunkRange<$range<Q>>
+        where
+            Q: ValidKey + Borrow<ChunkKey>,
+            ChunkKey: BorrowedKey + ?Sized,
+            ChunkKey::Owned: ValidKey,
+            ItemKey: BorrowedKey + ?Sized,
+            ItemKey::Owned: ValidKey,
+            Element: Record<ChunkKey, ItemKey>,
+        {
+            type ChunkIdxSet = Bitset;
+            type ItemIdxSet = IdxRange;
+
+            fn chunk_idxs(
+                &self,
+                storage: &Storage<ChunkKey, ItemKey, Element>,
+            ) -> Self::ChunkIdxSet {
+                storage.internal_idxs_in_range(
+                    borrow_bound(self.0.start_bound()),
+                    borrow_bound(self.0.end_bound()),
+                )
+            }
+
+            fn item_idxs(
+                &self,
+                _chunk_key: &ChunkKey,
+                chunk_storage: &ChunkStorage<ChunkKey, ItemKey, Element>,
+            ) -> Self::ItemIdxSet {
+                IdxRange(0..chunk_storage.len())
+            }
+
+            #[inline(always)]
+            fn test(&self, _element: &Element) -> bool {
+                true
+            }
+
+            fn is_exact(&self) -> bool {
+                true
+            }
+
+            fn test_chunk(&self, chunk_key: &ChunkKey) -> bool {
+                (
+                    borrow_bound(self.0.start_bound()),
+                    borrow_bound(self.0.end_bound()),
+                )
+                    .contains(chunk_key)
+            }
+        }
+    };
+}
+
+chunk_range_query_impl!(Range);
+chunk_range_query_impl!(RangeInclusive);
+chunk_range_query_impl!(RangeFrom);
+chunk_range_query_impl!(RangeTo);
+chunk_range_query_impl!(RangeToInclusive);
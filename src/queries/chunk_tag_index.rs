@@ -0,0 +1,378 @@
+use crate::bits::Bitset;
+use crate::idxsets::idxrange::IdxRange;
+use crate::internal::mr::rvec::RVec;
+use crate::internal::type_name::short_type_name;
+use crate::traits::memory_usage::{MemoryUsage, MemoryUser};
+use crate::traits::query::Query;
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::chunk_storage::ChunkStorage;
+use crate::types::storage::Storage;
+use crate::types::storage_builder::Strictness;
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::RwLock;
+
+type TagRule<ChunkKey, Tag> = Arc<dyn Fn(&ChunkKey) -> Vec<Tag> + Send + Sync>;
+
+/// An index of the chunks of a `Storage` by some tags computed from each chunk key, such as the
+/// shard or region that a chunk belongs to. Select the chunks with a tag using
+/// `ChunksTagged`, or list them using `ChunkTagIndex::chunk_keys`.
+///
+/// The tagging rule runs once per chunk, when the chunk is first seen, instead of once per
+/// element. Like a `SecondaryIndex`, a `ChunkTagIndex` catches up with any chunks that were
+/// added or removed the next time it is used, so it never drifts out of sync with the `Storage`.
+///
+/// # Type Parameters
+///
+/// * `ChunkKey`: The chunk key type of the `Storage`.
+/// * `Tag`: The type of the tags of each chunk.
+///
+/// # Example
+///
+/// ```
+/// use retriever::prelude::*;
+/// use retriever::queries::chunk_tag_index::{ChunkTagIndex, ChunksTagged};
+///
+/// // Servers chunked by (region, rack), keyed by hostname.
+/// type Server = ((&'static str, u64), &'static str, ());
+/// let mut storage : Storage<(&'static str, u64), &'static str, Server> = Storage::new();
+/// let by_region : ChunkTagIndex<(&'static str, u64), &'static str> =
+///   ChunkTagIndex::new(&storage, |chunk_key: &(&'static str, u64)| Some(chunk_key.0));
+///
+/// storage.add((("eu", 1), "eu1-a", ()));
+/// storage.add((("eu", 2), "eu2-a", ()));
+/// storage.add((("eu", 2), "eu2-b", ()));
+/// storage.add((("us", 1), "us1-a", ()));
+///
+/// assert_eq!(3, storage.query(ChunksTagged::new(&by_region, "eu")).count());
+///
+/// let mut racks = by_region.chunk_keys(&storage, &"eu");
+/// racks.sort();
+/// assert_eq!(vec![("eu", 1), ("eu", 2)], racks);
+///
+/// storage.remove_chunk(&("eu", 1));
+/// assert_eq!(2, storage.query(ChunksTagged::new(&by_region, "eu")).count());
+///
+/// # storage.validate();
+/// # by_region.validate(&storage);
+/// ```
+pub struct ChunkTagIndex<ChunkKey, Tag>(Arc<RwLock<ChunkTagIndexImpl<ChunkKey, Tag>>>)
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    Tag: ValidKey;
+
+struct ChunkTagIndexImpl<ChunkKey, Tag>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    Tag: ValidKey,
+{
+    // parent_id, used to see that this ChunkTagIndex isn't suddenly used with a different parent storage
+    parent_id: u64,
+    // the chunk key and tags of the chunk at each internal index, as of our last update
+    chunk_tags: RVec<Option<(ChunkKey::Owned, Vec<Tag>)>>,
+    // rule for constructing the tags of a chunk
+    rule: TagRule<ChunkKey, Tag>,
+    // the internal indices of the chunks with each tag
+    index: HashMap<Tag, Bitset, crate::internal::hasher::HasherImpl>,
+}
+
+impl<ChunkKey, Tag> Clone for ChunkTagIndex<ChunkKey, Tag>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    Tag: ValidKey,
+{
+    fn clone(&self) -> Self {
+        ChunkTagIndex(Arc::clone(&self.0))
+    }
+}
+
+impl<ChunkKey, Tag> ChunkTagIndex<ChunkKey, Tag>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    Tag: ValidKey,
+{
+    /// Create a new ChunkTagIndex of a storage. The tagging rule yields 0 or more tags for each
+    /// chunk key, and should always yield the same tags for the same chunk key.
+    pub fn new<ItemKey, Element, F, I>(storage: &Storage<ChunkKey, ItemKey, Element>, f: F) -> Self
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        F: Fn(&ChunkKey) -> I + Send + Sync + 'static,
+        I: IntoIterator<Item = Tag>,
+    {
+        ChunkTagIndex(Arc::new(RwLock::new(ChunkTagIndexImpl {
+            parent_id: storage.id(),
+            chunk_tags: RVec::default(),
+            rule: Arc::new(move |chunk_key: &ChunkKey| f(chunk_key).into_iter().collect()),
+            index: HashMap::with_hasher(crate::internal::hasher::HasherImpl::default()),
+        })))
+    }
+
+    /// The chunk keys of every chunk with the given tag, in no particular order.
+    pub fn chunk_keys<ItemKey, Element>(
+        &self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        tag: &Tag,
+    ) -> Vec<ChunkKey::Owned>
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        let chunks = storage.internal_rvec();
+        self.chunk_idxs(storage, tag)
+            .iter()
+            .flatten()
+            .map(|idx| chunks[idx].chunk_key().to_owned())
+            .collect()
+    }
+
+    /// Panic if this storage is malformed or broken in any detectable way.
+    /// This is a slow operation and you shouldn't use it unless you suspect a problem.
+    pub fn validate<ItemKey, Element>(&self, parent: &Storage<ChunkKey, ItemKey, Element>)
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        let mut chunk_tag_index_impl = self.0.write().unwrap();
+        chunk_tag_index_impl.refresh(parent);
+
+        let mut expected: HashMap<Tag, Bitset> = HashMap::new();
+        for (idx, chunk_storage) in parent.internal_rvec().iter().enumerate() {
+            for tag in (chunk_tag_index_impl.rule)(chunk_storage.chunk_key()) {
+                expected.entry(tag).or_default().set(idx);
+            }
+        }
+
+        assert_eq!(expected.len(), chunk_tag_index_impl.index.len());
+        for (tag, idxs) in expected {
+            assert_eq!(
+                idxs.iter().flatten().collect::<Vec<usize>>(),
+                chunk_tag_index_impl.index[&tag]
+                    .iter()
+                    .flatten()
+                    .collect::<Vec<usize>>(),
+                "Chunks tagged with {:?} don't match the index",
+                tag
+            );
+        }
+    }
+
+    /// Bring this index up to date, and get the internal indices of the chunks with a tag.
+    pub(crate) fn chunk_idxs<ItemKey, Element>(
+        &self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        tag: &Tag,
+    ) -> Bitset
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        let mut chunk_tag_index_impl = self.0.write().unwrap();
+        chunk_tag_index_impl.refresh(storage);
+        chunk_tag_index_impl
+            .index
+            .get(tag)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn has_tag(&self, chunk_key: &ChunkKey, tag: &Tag) -> bool {
+        (self.0.read().unwrap().rule)(chunk_key).contains(tag)
+    }
+}
+
+impl<ChunkKey, Tag> ChunkTagIndexImpl<ChunkKey, Tag>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    Tag: ValidKey,
+{
+    fn refresh<ItemKey, Element>(&mut self, storage: &Storage<ChunkKey, ItemKey, Element>)
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        if self.parent_id != storage.id() {
+            assert_eq!(storage.strictness(), Strictness::Repair, "Id mismatch: a chunk tag index may only be used with it's parent Storage, never any other Storage");
+            #[cfg(feature = "log")]
+            log::warn!("retriever: repaired chunk tag index used with a different Storage by rebuilding it");
+            self.parent_id = storage.id();
+            self.chunk_tags = RVec::default();
+            self.index.clear();
+        }
+
+        let ChunkTagIndexImpl {
+            chunk_tags,
+            rule,
+            index,
+            ..
+        } = self;
+
+        chunk_tags.reduce(storage.internal_rvec(), 1, |chunk_storages, prev, idx| {
+            let chunk_key = chunk_storages.first().map(|c| c.chunk_key());
+            if chunk_key == prev.as_ref().map(|(chunk_key, _)| chunk_key.borrow()) {
+                return None;
+            }
+
+            if let Some((_, tags)) = prev {
+                for tag in tags {
+                    if let Some(idxs) = index.get_mut(tag) {
+                        idxs.unset(idx);
+                        if idxs.is_empty() {
+                            index.remove(tag);
+                        }
+                    }
+                }
+            }
+
+            let chunk_key = chunk_key?;
+            let tags = rule(chunk_key);
+            for tag in tags.iter() {
+                index.entry(tag.clone()).or_default().set(idx);
+            }
+
+            Some(Some((chunk_key.to_owned(), tags)))
+        });
+    }
+}
+
+impl<ChunkKey, Tag> MemoryUser for ChunkTagIndexImpl<ChunkKey, Tag>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    Tag: ValidKey,
+{
+    fn memory_usage(&self) -> MemoryUsage {
+        let mut result = self.chunk_tags.memory_usage();
+        result = MemoryUsage::merge(result, self.index.memory_usage());
+
+        for bs in self.index.values() {
+            result = MemoryUsage::merge(result, bs.memory_usage());
+        }
+
+        result
+    }
+
+    fn shrink_with<F: Fn(&MemoryUsage) -> Option<usize>>(&mut self, f: F) {
+        self.chunk_tags.shrink_with(&f);
+        self.index.shrink_with(&f);
+
+        for bs in self.index.values_mut() {
+            bs.shrink_with(&f);
+        }
+    }
+}
+
+impl<ChunkKey, Tag> MemoryUser for ChunkTagIndex<ChunkKey, Tag>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    Tag: ValidKey,
+{
+    fn memory_usage(&self) -> MemoryUsage {
+        self.0.read().unwrap().memory_usage()
+    }
+
+    fn shrink_with<F: Fn(&MemoryUsage) -> Option<usize>>(&mut self, f: F) {
+        self.0.write().unwrap().shrink_with(f)
+    }
+}
+
+/// A `Query` that visits every chunk with a tag of a `ChunkTagIndex`. Like `ChunksWhere`, but
+/// without evaluating a predicate against every chunk key.
+pub struct ChunksTagged<ChunkKey, Tag>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    Tag: ValidKey,
+{
+    chunk_tag_index: ChunkTagIndex<ChunkKey, Tag>,
+    tag: Tag,
+}
+
+impl<ChunkKey, Tag> Clone for ChunksTagged<ChunkKey, Tag>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    Tag: ValidKey,
+{
+    fn clone(&self) -> Self {
+        ChunksTagged {
+            chunk_tag_index: self.chunk_tag_index.clone(),
+            tag: self.tag.clone(),
+        }
+    }
+}
+
+impl<ChunkKey, Tag> ChunksTagged<ChunkKey, Tag>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    Tag: ValidKey,
+{
+    /// Query every chunk with the given tag.
+    pub fn new(chunk_tag_index: &ChunkTagIndex<ChunkKey, Tag>, tag: Tag) -> Self {
+        ChunksTagged {
+            chunk_tag_index: chunk_tag_index.clone(),
+            tag,
+        }
+    }
+}
+
+impl<ChunkKey, ItemKey, Element, Tag> Query<ChunkKey, ItemKey, Element>
+    for ChunksTagged<ChunkKey, Tag>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+    Tag: ValidKey,
+{
+    type ChunkIdxSet = Bitset;
+    type ItemIdxSet = IdxRange;
+
+    fn chunk_idxs(&self, storage: &Storage<ChunkKey, ItemKey, Element>) -> Self::ChunkIdxSet {
+        self.chunk_tag_index.chunk_idxs(storage, &self.tag)
+    }
+
+    fn item_idxs(
+        &self,
+        _chunk_key: &ChunkKey,
+        chunk_storage: &ChunkStorage<ChunkKey, ItemKey, Element>,
+    ) -> Self::ItemIdxSet {
+        IdxRange(0..chunk_storage.len())
+    }
+
+    #[inline(always)]
+    fn test(&self, _element: &Element) -> bool {
+        true
+    }
+
+    fn test_chunk(&self, chunk_key: &ChunkKey) -> bool {
+        self.chunk_tag_index.has_tag(chunk_key, &self.tag)
+    }
+
+    fn is_exact(&self) -> bool {
+        true
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "ChunksTagged(ChunkTagIndex<{}>, {:?})",
+            short_type_name::<Tag>(),
+            self.tag
+        )
+    }
+}
@@ -47,12 +47,24 @@ macro_rules! common_item_idxs_impl {
     };
 }
 
+macro_rules! common_test_chunk_impl {
+    () => {
+        fn test_chunk(&self, chunk_key: &ChunkKey) -> bool {
+            self.0.iter().any(|x| x.borrow() == chunk_key)
+        }
+    };
+}
+
 macro_rules! common_test_impl {
     () => {
         #[inline(always)]
         fn test(&self, _element: &Element) -> bool {
             true
         }
+
+        fn is_exact(&self) -> bool {
+            true
+        }
     };
 }
 
@@ -71,6 +83,7 @@ where
     common_chunk_idxs_impl!();
     common_item_idxs_impl!();
     common_test_impl!();
+    common_test_chunk_impl!();
 }
 
 impl<Q, S, ChunkKey, ItemKey, Element> Query<ChunkKey, ItemKey, Element> for Chunks<HashSet<Q, S>>
@@ -88,6 +101,7 @@ where
     common_chunk_idxs_impl!();
     common_item_idxs_impl!();
     common_test_impl!();
+    common_test_chunk_impl!();
 }
 
 impl<Q, ChunkKey, ItemKey, Element> Query<ChunkKey, ItemKey, Element> for Chunks<BTreeSet<Q>>
@@ -105,6 +119,10 @@ where
     common_chunk_idxs_impl!();
     common_item_idxs_impl!();
     common_test_impl!();
+
+    fn test_chunk(&self, chunk_key: &ChunkKey) -> bool {
+        self.0.contains(chunk_key)
+    }
 }
 
 impl<'a, Q, ChunkKey, ItemKey, Element> Query<ChunkKey, ItemKey, Element> for Chunks<&'a [Q]>
@@ -122,6 +140,7 @@ where
     common_chunk_idxs_impl!();
     common_item_idxs_impl!();
     common_test_impl!();
+    common_test_chunk_impl!();
 }
 
 impl<Q, ChunkKey, ItemKey, Element> Query<ChunkKey, ItemKey, Element> for Chunks<Range<Q>>
@@ -147,6 +166,10 @@ where
 
     common_item_idxs_impl!();
     common_test_impl!();
+
+    fn test_chunk(&self, chunk_key: &ChunkKey) -> bool {
+        self.0.start.borrow() <= chunk_key && chunk_key < self.0.end.borrow()
+    }
 }
 
 impl<Q, ChunkKey, ItemKey, Element> Query<ChunkKey, ItemKey, Element> for Chunks<RangeInclusive<Q>>
@@ -172,6 +195,10 @@ where
 
     common_item_idxs_impl!();
     common_test_impl!();
+
+    fn test_chunk(&self, chunk_key: &ChunkKey) -> bool {
+        self.0.start().borrow() <= chunk_key && chunk_key <= self.0.end().borrow()
+    }
 }
 
 macro_rules! sized_array_query_impl {
@@ -210,6 +237,7 @@ macro_rules! sized_array_query_impl {
 
         common_item_idxs_impl!();
         common_test_impl!();
+        common_test_chunk_impl!();
     }
 
     impl<Q, ChunkKey, ItemKey, Element> Query<ChunkKey, ItemKey, Element> for Chunks<[Q;$n]>
@@ -246,6 +274,7 @@ macro_rules! sized_array_query_impl {
 
         common_item_idxs_impl!();
         common_test_impl!();
+        common_test_chunk_impl!();
     }
   }
 }
@@ -0,0 +1,82 @@
+use crate::bits::Bitset;
+use crate::idxsets::idxrange::IdxRange;
+use crate::traits::query::Query;
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::chunk_storage::ChunkStorage;
+use crate::types::storage::Storage;
+
+/// A `Query` that visits every chunk whose chunk key satisfies a predicate. The predicate is
+/// evaluated once per chunk, rather than once per element as `Query::filter` would be, so
+/// prefer this whenever the decision only depends on the chunk key.
+///
+/// # Example
+///
+/// ```
+/// use retriever::prelude::*;
+/// use retriever::queries::chunks_where::ChunksWhere;
+///
+/// // Events chunked by year and month.
+/// let mut storage : Storage<(u32, u32), u64, ((u32, u32), u64, &'static str)> = Storage::new();
+///
+/// storage.add(((2022, 12), 1, "solstice"));
+/// storage.add(((2023, 1), 2, "new year"));
+/// storage.add(((2023, 6), 3, "solstice"));
+/// storage.add(((2024, 1), 4, "new year"));
+///
+/// let mut events : Vec<u64> = storage
+///   .query(ChunksWhere(|month: &(u32, u32)| month.0 == 2023))
+///   .map(|x| x.1)
+///   .collect();
+/// events.sort();
+///
+/// assert_eq!(vec![2, 3], events);
+///
+/// # storage.validate();
+/// ```
+#[derive(Copy, Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct ChunksWhere<F>(pub F);
+
+impl<F, ChunkKey, ItemKey, Element> Query<ChunkKey, ItemKey, Element> for ChunksWhere<F>
+where
+    F: Fn(&ChunkKey) -> bool,
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+{
+    type ChunkIdxSet = Bitset;
+    type ItemIdxSet = IdxRange;
+
+    fn chunk_idxs(&self, storage: &Storage<ChunkKey, ItemKey, Element>) -> Self::ChunkIdxSet {
+        storage
+            .internal_rvec()
+            .iter()
+            .enumerate()
+            .filter(|(_, chunk_storage)| (self.0)(chunk_storage.chunk_key()))
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    fn item_idxs(
+        &self,
+        _chunk_key: &ChunkKey,
+        chunk_storage: &ChunkStorage<ChunkKey, ItemKey, Element>,
+    ) -> Self::ItemIdxSet {
+        IdxRange(0..chunk_storage.len())
+    }
+
+    #[inline(always)]
+    fn test(&self, _element: &Element) -> bool {
+        true
+    }
+
+    fn test_chunk(&self, chunk_key: &ChunkKey) -> bool {
+        (self.0)(chunk_key)
+    }
+
+    fn is_exact(&self) -> bool {
+        true
+    }
+}
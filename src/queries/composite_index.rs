@@ -0,0 +1,304 @@
+use crate::bits::Bitset;
+use crate::idxsets::intersection::Intersection;
+use crate::internal::type_name::short_type_name;
+use crate::queries::ordered_secondary_index::OrderedSecondaryIndex;
+use crate::traits::idxset::IdxSet;
+use crate::traits::memory_usage::{MemoryUsage, MemoryUser};
+use crate::traits::query::Query;
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::chunk_storage::ChunkStorage;
+use crate::types::storage::Storage;
+use std::borrow::Cow;
+use std::ops::Bound;
+
+// The index key of a CompositeIndex. Since None sorts before Some(_), (a, None) sorts before
+// every index key with the leading value a.
+type CompositeKey<A, B> = (A, Option<B>);
+type CompositeOrderedIndex<ChunkKey, Element, A, B> =
+    OrderedSecondaryIndex<ChunkKey, Element, Option<CompositeKey<A, B>>, CompositeKey<A, B>>;
+
+/// A single index over a pair of values extracted from each element, such as
+/// `(country, status)`, which can be matched against both values or against just the leading
+/// value using `Query::matching_composite`. This is faster, and less code, than matching against
+/// two separate indices.
+///
+/// Only the leading value can be matched on its own. To match either value on its own, use
+/// two `SecondaryIndices`. If you only ever match both values, a plain `SecondaryIndex` with a
+/// tuple `IndexKey` uses less memory.
+///
+/// # Two fields only
+///
+/// A `CompositeIndex` always has exactly two fields, `A` and `B`, and a `CompositePattern` can
+/// match only all of `A` or all of `(A, B)`. Either one may be a tuple, but a tuple is matched as
+/// a whole, never by its own leading fields. So an index over `(country, (region, status))` can
+/// match a country, or a country, region and status together, but not a country and region.
+/// Choose `A` to be the longest prefix that you need to match on its own: an index over
+/// `((country, region), status)` matches a country and region, or all three fields.
+///
+/// # Type Parameters
+///
+/// * `ChunkKey`: The chunk key type of the `Storage`.
+/// * `Element`: The element type of the `Storage`.
+/// * `A`: The leading value of the index key, the only part that can be matched on its own.
+/// * `B`: The trailing value of the index key, which may itself be a tuple, but is only ever
+///   matched as a whole.
+///
+/// # Example
+///
+/// ```
+/// use retriever::prelude::*;
+/// use retriever::queries::composite_index::{CompositeIndex, CompositePattern};
+///
+/// // Customers chunked by signup year, keyed by id, with a country and a status.
+/// type Customer = (u64, u64, (&'static str, &'static str));
+/// let mut storage : Storage<u64, u64, Customer> = Storage::new();
+/// let by_country_status : CompositeIndex<u64, Customer, &'static str, &'static str> =
+///   CompositeIndex::new(&storage, |x: &Customer| x.2);
+///
+/// storage.add((2022, 1, ("NZ", "active")));
+/// storage.add((2022, 2, ("NZ", "lapsed")));
+/// storage.add((2023, 3, ("NZ", "active")));
+/// storage.add((2023, 4, ("US", "active")));
+///
+/// let active_nz = Everything.matching_composite(
+///   &by_country_status,
+///   CompositePattern::Exact("NZ", "active"));
+/// assert_eq!(2, storage.query(active_nz).count());
+///
+/// let nz = Everything.matching_composite(&by_country_status, CompositePattern::Prefix("NZ"));
+/// assert_eq!(3, storage.query(nz).count());
+///
+/// # storage.validate();
+/// # by_country_status.validate(&storage);
+/// ```
+pub struct CompositeIndex<ChunkKey, Element, A, B>(CompositeOrderedIndex<ChunkKey, Element, A, B>)
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    A: ValidKey + 'static,
+    B: ValidKey + 'static;
+
+impl<ChunkKey, Element, A, B> Clone for CompositeIndex<ChunkKey, Element, A, B>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    A: ValidKey + 'static,
+    B: ValidKey + 'static,
+{
+    fn clone(&self) -> Self {
+        CompositeIndex(self.0.clone())
+    }
+}
+
+impl<ChunkKey, Element, A, B> CompositeIndex<ChunkKey, Element, A, B>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    A: ValidKey + 'static,
+    B: ValidKey + 'static,
+{
+    /// Create a new CompositeIndex of a storage, indexing each element under the pair of values
+    /// produced by the given rule.
+    pub fn new<ItemKey, F>(storage: &Storage<ChunkKey, ItemKey, Element>, f: F) -> Self
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        F: Fn(&Element) -> (A, B) + Clone + Send + Sync + 'static,
+    {
+        CompositeIndex(OrderedSecondaryIndex::new(
+            storage,
+            move |element: &Element| {
+                let (a, b) = f(element);
+                Cow::Owned(Some((a, Some(b))))
+            },
+        ))
+    }
+
+    /// Panic if this storage is malformed or broken in any detectable way.
+    /// This is a slow operation and you shouldn't use it unless you suspect a problem.
+    pub fn validate<ItemKey>(&self, parent: &Storage<ChunkKey, ItemKey, Element>)
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        self.0.validate(parent);
+    }
+}
+
+impl<ChunkKey, Element, A, B> MemoryUser for CompositeIndex<ChunkKey, Element, A, B>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    A: ValidKey + 'static,
+    B: ValidKey + 'static,
+{
+    fn memory_usage(&self) -> MemoryUsage {
+        self.0.memory_usage()
+    }
+
+    fn shrink_with<F: Fn(&MemoryUsage) -> Option<usize>>(&mut self, f: F) {
+        self.0.shrink_with(f)
+    }
+}
+
+/// The index keys of a `CompositeIndex` to match against, using `Query::matching_composite`.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum CompositePattern<A, B> {
+    /// Match elements with exactly this pair of values.
+    Exact(A, B),
+    /// Match elements with this leading value, and any trailing value.
+    Prefix(A),
+}
+
+impl<A, B> CompositePattern<A, B>
+where
+    A: ValidKey + 'static,
+    B: ValidKey + 'static,
+{
+    // The first index key that could possibly be selected.
+    fn start(&self) -> CompositeKey<A, B> {
+        match self {
+            CompositePattern::Exact(a, b) => (a.clone(), Some(b.clone())),
+            CompositePattern::Prefix(a) => (a.clone(), None),
+        }
+    }
+
+    // Should the given index key, visited in order, be included or end the search?
+    fn select(&self, index_key: &CompositeKey<A, B>) -> Option<bool> {
+        let selected = match self {
+            CompositePattern::Exact(a, b) => &index_key.0 == a && index_key.1.as_ref() == Some(b),
+            CompositePattern::Prefix(a) => &index_key.0 == a,
+        };
+
+        if selected {
+            Some(true)
+        } else {
+            None
+        }
+    }
+}
+
+/// A Query matching a `CompositePattern` against a `CompositeIndex`. Construct using
+/// `Query::matching_composite`.
+pub struct MatchingComposite<Q, ChunkKey, Element, A, B>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    A: ValidKey + 'static,
+    B: ValidKey + 'static,
+{
+    query: Q,
+    composite_index: CompositeIndex<ChunkKey, Element, A, B>,
+    pattern: CompositePattern<A, B>,
+    start: CompositeKey<A, B>,
+}
+
+impl<Q, ChunkKey, Element, A, B> Clone for MatchingComposite<Q, ChunkKey, Element, A, B>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    A: ValidKey + 'static,
+    B: ValidKey + 'static,
+    Q: Clone,
+{
+    fn clone(&self) -> Self {
+        MatchingComposite {
+            query: self.query.clone(),
+            composite_index: self.composite_index.clone(),
+            pattern: self.pattern.clone(),
+            start: self.start.clone(),
+        }
+    }
+}
+
+impl<Q, ChunkKey, Element, A, B> MatchingComposite<Q, ChunkKey, Element, A, B>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    A: ValidKey + 'static,
+    B: ValidKey + 'static,
+{
+    pub(crate) fn new(
+        query: Q,
+        composite_index: &CompositeIndex<ChunkKey, Element, A, B>,
+        pattern: CompositePattern<A, B>,
+    ) -> Self {
+        MatchingComposite {
+            query,
+            composite_index: composite_index.clone(),
+            start: pattern.start(),
+            pattern,
+        }
+    }
+}
+
+impl<Q, ChunkKey, ItemKey, Element, A, B> Query<ChunkKey, ItemKey, Element>
+    for MatchingComposite<Q, ChunkKey, Element, A, B>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+    A: ValidKey + 'static,
+    B: ValidKey + 'static,
+    Q: Query<ChunkKey, ItemKey, Element> + Clone,
+{
+    type ChunkIdxSet = Q::ChunkIdxSet;
+    type ItemIdxSet = Intersection<Q::ItemIdxSet, Option<Bitset>>;
+
+    fn chunk_idxs(&self, storage: &Storage<ChunkKey, ItemKey, Element>) -> Self::ChunkIdxSet {
+        let result = self.query.chunk_idxs(storage);
+        self.composite_index
+            .0
+            .as_secondary_index()
+            .refresh(storage, &result);
+        result
+    }
+
+    fn item_idxs(
+        &self,
+        chunk_key: &ChunkKey,
+        chunk_storage: &ChunkStorage<ChunkKey, ItemKey, Element>,
+    ) -> Self::ItemIdxSet {
+        let parent_idxs = self.query.item_idxs(chunk_key, chunk_storage);
+        let ours_idxs = self
+            .composite_index
+            .0
+            .as_secondary_index()
+            .idxs_of_ordered_keys(chunk_key, Bound::Included(&self.start), |index_key| {
+                self.pattern.select(index_key)
+            });
+
+        IdxSet::intersection(parent_idxs, ours_idxs)
+    }
+
+    fn test(&self, element: &Element) -> bool {
+        self.query.test(element)
+    }
+
+    fn test_chunk(&self, chunk_key: &ChunkKey) -> bool {
+        self.query.test_chunk(chunk_key)
+    }
+
+    fn is_exact(&self) -> bool {
+        self.query.is_exact()
+    }
+
+    fn is_chunk_local(&self) -> bool {
+        self.query.is_chunk_local()
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "MatchingComposite({}, CompositeIndex<{}, {}>, {:?})",
+            self.query.describe(),
+            short_type_name::<A>(),
+            short_type_name::<B>(),
+            self.pattern
+        )
+    }
+}
@@ -0,0 +1,416 @@
+use crate::internal::mr::rvec::RVec;
+use crate::internal::mr::summarize::{Summarize, SummaryRules};
+use crate::queries::secondary_index::KeySet;
+use crate::traits::memory_usage::{MemoryUsage, MemoryUser};
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::storage::Storage;
+use crate::types::storage_builder::Strictness;
+use std::borrow::Borrow;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::sync::RwLock;
+
+/// A running count of the elements of a `Storage` under each of some index keys, such as
+/// the number of orders with each status. Read the counts using `Storage::counts_by`.
+///
+/// Where `SecondaryIndex::key_counts` counts the postings of every index key on each call,
+/// a `CountingIndex` only keeps the counts themselves. Each change to an element adjusts the
+/// counts of its old and new index keys, so reading the counts costs nothing more than copying
+/// them out, no matter how many elements there are.
+///
+/// # Type Parameters
+///
+/// * `ChunkKey`: The chunk key type of the `Storage`.
+/// * `Element`: The element type of the `Storage`.
+/// * `IndexKeys`: A collection containing the type parameter `IndexKey`, as for a `SecondaryIndex`.
+/// * `IndexKey`: The type of the keys to count elements by.
+///
+/// # Example
+///
+/// ```
+/// use retriever::prelude::*;
+/// use retriever::queries::counting_index::CountingIndex;
+/// use std::borrow::Cow;
+///
+/// let mut storage : Storage<u64, u64, (u64, u64, &'static str)> = Storage::new();
+/// let by_status : CountingIndex<u64, (u64, u64, &'static str), Option<&'static str>, &'static str> =
+///   CountingIndex::new(&storage, |x: &(u64, u64, &'static str)| Cow::Owned(Some(x.2)));
+///
+/// for i in 0..100 {
+///   storage.add((i % 10, i, if i % 4 == 0 { "failed" } else { "ok" }));
+/// }
+///
+/// let counts = storage.counts_by(&by_status);
+/// assert_eq!(25, counts["failed"]);
+/// assert_eq!(75, counts["ok"]);
+///
+/// storage.modify(Everything.filter(|x: &(u64, u64, &'static str)| x.1 < 20), |mut editor| {
+///   editor.get_mut().2 = "retrying";
+/// });
+/// storage.remove_chunk(&9);
+///
+/// let counts = storage.counts_by(&by_status);
+/// assert_eq!(20, counts["failed"]);
+/// assert_eq!(52, counts["ok"]);
+/// assert_eq!(18, counts["retrying"]);
+/// assert_eq!(0, by_status.count(&storage, &"lost"));
+///
+/// # storage.validate();
+/// # by_status.validate(&storage);
+/// ```
+pub struct CountingIndex<ChunkKey, Element, IndexKeys, IndexKey>(
+    Arc<RwLock<CountingIndexImpl<ChunkKey, Element, IndexKeys, IndexKey>>>,
+)
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>;
+
+impl<ChunkKey, Element, IndexKeys, IndexKey> Clone
+    for CountingIndex<ChunkKey, Element, IndexKeys, IndexKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+{
+    fn clone(&self) -> Self {
+        CountingIndex(Arc::clone(&self.0))
+    }
+}
+
+struct ChunkCounts<IndexKey>
+where
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+{
+    // the number of elements of this chunk with each index key
+    counts: HashMap<IndexKey::Owned, usize>,
+    // the changes to counts since they were last folded into the totals
+    changes: HashMap<IndexKey::Owned, isize>,
+}
+
+impl<IndexKey> Default for ChunkCounts<IndexKey>
+where
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+{
+    fn default() -> Self {
+        ChunkCounts {
+            counts: HashMap::default(),
+            changes: HashMap::default(),
+        }
+    }
+}
+
+struct CountingIndexImpl<ChunkKey, Element, IndexKeys, IndexKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+{
+    // parent_id, used to see that this CountingIndex isn't suddenly used with a different parent storage
+    parent_id: u64,
+    // gc_chunk_list, remember the chunks from our last update, so we can uncount newly-absent chunks
+    gc_chunk_list: RVec<Option<ChunkKey::Owned>>,
+    // rule for constructing index keys
+    rules: Arc<SummaryRules<Element, IndexKeys, ChunkCounts<IndexKey>>>,
+    // the counts of each chunk
+    index: HashMap<
+        ChunkKey::Owned,
+        Summarize<Element, IndexKeys, ChunkCounts<IndexKey>>,
+        crate::internal::hasher::HasherImpl,
+    >,
+    // the counts of every chunk, added together
+    totals: HashMap<IndexKey::Owned, usize>,
+}
+
+impl<ChunkKey, Element, IndexKeys, IndexKey> CountingIndex<ChunkKey, Element, IndexKeys, IndexKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+{
+    /// Create a new CountingIndex of a storage. The indexing rule works exactly like the
+    /// indexing rule of `SecondaryIndex::new`, and an element with several index keys is
+    /// counted once under each of them.
+    pub fn new<ItemKey, F>(storage: &Storage<ChunkKey, ItemKey, Element>, f: F) -> Self
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        F: Fn(&Element) -> Cow<IndexKeys> + Clone + Send + Sync + 'static,
+    {
+        CountingIndex(Arc::new(RwLock::new(CountingIndexImpl {
+            parent_id: storage.id(),
+            gc_chunk_list: RVec::default(),
+            rules: Arc::new(
+                CountingIndexImpl::<ChunkKey, Element, IndexKeys, IndexKey>::counting_rules(f),
+            ),
+            index: HashMap::with_hasher(crate::internal::hasher::HasherImpl::default()),
+            totals: HashMap::new(),
+        })))
+    }
+
+    /// Bring this index up to date and get the number of elements with each index key.
+    /// Index keys without any elements are left out. See also `Storage::counts_by`.
+    pub fn counts<ItemKey>(
+        &self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+    ) -> HashMap<IndexKey::Owned, usize>
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        let mut counting_index_impl = self.0.write().unwrap();
+        counting_index_impl.refresh(storage);
+        counting_index_impl.totals.clone()
+    }
+
+    /// Bring this index up to date and get the number of elements with the given index key.
+    pub fn count<ItemKey>(
+        &self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        index_key: &IndexKey,
+    ) -> usize
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        let mut counting_index_impl = self.0.write().unwrap();
+        counting_index_impl.refresh(storage);
+        counting_index_impl
+            .totals
+            .get(index_key)
+            .cloned()
+            .unwrap_or(0)
+    }
+
+    /// Panic if this storage is malformed or broken in any detectable way.
+    /// This is a slow operation and you shouldn't use it unless you suspect a problem.
+    pub fn validate<ItemKey>(&self, parent: &Storage<ChunkKey, ItemKey, Element>)
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        let mut counting_index_impl = self.0.write().unwrap();
+        counting_index_impl.refresh(parent);
+
+        for chunk_key in counting_index_impl.index.keys() {
+            assert!(parent.internal_idx_of(chunk_key.borrow()).is_some());
+        }
+
+        let mut expected: HashMap<IndexKey::Owned, usize> = HashMap::new();
+        for chunk_storage in parent.internal_rvec().iter() {
+            let summary = counting_index_impl.index[chunk_storage.chunk_key()].peek();
+            assert!(summary.changes.is_empty());
+            for (index_key, count) in summary.counts.iter() {
+                *expected.entry(index_key.clone()).or_insert(0) += count;
+            }
+        }
+
+        assert_eq!(expected, counting_index_impl.totals);
+    }
+}
+
+impl<ChunkKey, Element, IndexKeys, IndexKey>
+    CountingIndexImpl<ChunkKey, Element, IndexKeys, IndexKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+{
+    fn counting_rules<F>(f: F) -> SummaryRules<Element, IndexKeys, ChunkCounts<IndexKey>>
+    where
+        F: Fn(&Element) -> Cow<IndexKeys> + Clone + Send + Sync + 'static,
+    {
+        SummaryRules {
+            map: Arc::new(move |element, old_index_keys, _internal_idx| {
+                let new_index_keys = f(element);
+
+                if old_index_keys != new_index_keys.borrow() {
+                    Some(new_index_keys.into_owned())
+                } else {
+                    None
+                }
+            }),
+            contribute: Arc::new(|new_index_keys, _internal_idx, summary| {
+                for new_index_key in new_index_keys.iter_keys() {
+                    *summary
+                        .counts
+                        .entry(new_index_key.clone().into_owned())
+                        .or_insert(0) += 1;
+                    *summary
+                        .changes
+                        .entry(new_index_key.into_owned())
+                        .or_insert(0) += 1;
+                }
+            }),
+            uncontribute: Arc::new(|old_index_keys, _internal_idx, summary| {
+                for old_index_key in old_index_keys.iter_keys() {
+                    let mut remove = false;
+
+                    if let Some(count) = summary.counts.get_mut(old_index_key.borrow()) {
+                        *count -= 1;
+                        if *count == 0 {
+                            remove = true;
+                        }
+                    }
+
+                    if remove {
+                        summary.counts.remove(old_index_key.borrow());
+                    }
+
+                    *summary
+                        .changes
+                        .entry(old_index_key.into_owned())
+                        .or_insert(0) -= 1;
+                }
+            }),
+        }
+    }
+
+    fn refresh<ItemKey>(&mut self, storage: &Storage<ChunkKey, ItemKey, Element>)
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        if self.parent_id != storage.id() {
+            assert_eq!(storage.strictness(), Strictness::Repair, "Id mismatch: a counting index may only be used with it's parent Storage, never any other Storage");
+            #[cfg(feature = "log")]
+            log::warn!(
+                "retriever: repaired counting index used with a different Storage by rebuilding it"
+            );
+            self.parent_id = storage.id();
+            self.gc_chunk_list = RVec::default();
+            self.index.clear();
+            self.totals.clear();
+        }
+
+        let CountingIndexImpl {
+            gc_chunk_list,
+            rules,
+            index,
+            totals,
+            ..
+        } = self;
+
+        storage.gc_with(gc_chunk_list, index, |_, summarize| {
+            for (index_key, count) in summarize.peek().counts.iter() {
+                Self::add_to_total(totals, index_key.borrow(), -(*count as isize));
+            }
+        });
+
+        for chunk_storage in storage.internal_rvec().iter() {
+            let internal_storage = chunk_storage.internal_rvec();
+            let summarize = index
+                .entry(chunk_storage.chunk_key().to_owned())
+                .or_insert_with(|| Summarize::new(internal_storage, Arc::clone(rules)));
+            summarize.update(internal_storage);
+
+            for (index_key, change) in summarize.peek_mut().changes.drain() {
+                Self::add_to_total(totals, index_key.borrow(), change);
+            }
+        }
+    }
+
+    fn add_to_total(
+        totals: &mut HashMap<IndexKey::Owned, usize>,
+        index_key: &IndexKey,
+        change: isize,
+    ) {
+        if change == 0 {
+            return;
+        }
+
+        let total = totals.get(index_key).cloned().unwrap_or(0) as isize + change;
+        if total > 0 {
+            totals.insert(index_key.to_owned(), total as usize);
+        } else {
+            totals.remove(index_key);
+        }
+    }
+}
+
+impl<IndexKey> MemoryUser for ChunkCounts<IndexKey>
+where
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+{
+    fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage::merge(self.counts.memory_usage(), self.changes.memory_usage())
+    }
+
+    fn shrink_with<F: Fn(&MemoryUsage) -> Option<usize>>(&mut self, f: F) {
+        self.counts.shrink_with(&f);
+        self.changes.shrink_with(&f);
+    }
+}
+
+impl<ChunkKey, Element, IndexKeys, IndexKey> MemoryUser
+    for CountingIndexImpl<ChunkKey, Element, IndexKeys, IndexKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+{
+    fn memory_usage(&self) -> MemoryUsage {
+        let mut result = self.gc_chunk_list.memory_usage();
+        result = MemoryUsage::merge(result, self.index.memory_usage());
+        result = MemoryUsage::merge(result, self.totals.memory_usage());
+
+        for s in self.index.values() {
+            result = MemoryUsage::merge(result, s.memory_usage());
+            result = MemoryUsage::merge(result, s.peek().memory_usage());
+        }
+
+        result
+    }
+
+    fn shrink_with<F: Fn(&MemoryUsage) -> Option<usize>>(&mut self, f: F) {
+        self.gc_chunk_list.shrink_with(&f);
+        self.index.shrink_with(&f);
+        self.totals.shrink_with(&f);
+
+        for i in self.index.values_mut() {
+            i.shrink_with(&f);
+            i.peek_mut().shrink_with(&f);
+        }
+    }
+}
+
+impl<ChunkKey, Element, IndexKeys, IndexKey> MemoryUser
+    for CountingIndex<ChunkKey, Element, IndexKeys, IndexKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+{
+    fn memory_usage(&self) -> MemoryUsage {
+        self.0.read().unwrap().memory_usage()
+    }
+
+    fn shrink_with<F: Fn(&MemoryUsage) -> Option<usize>>(&mut self, f: F) {
+        self.0.write().unwrap().shrink_with(f)
+    }
+}
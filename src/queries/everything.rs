@@ -35,4 +35,8 @@ where
     fn test(&self, _element: &Element) -> bool {
         true
     }
+
+    fn is_exact(&self) -> bool {
+        true
+    }
 }
@@ -1,3 +1,4 @@
+use crate::traits::idxset::IdxSet;
 use crate::traits::query::Query;
 use crate::traits::record::Record;
 use crate::traits::valid_key::{BorrowedKey, ValidKey};
@@ -47,4 +48,65 @@ where
     fn test(&self, element: &Element) -> bool {
         self.parent.test(element) && (self.filter)(element)
     }
+
+    fn test_chunk(&self, chunk_key: &ChunkKey) -> bool {
+        self.parent.test_chunk(chunk_key)
+    }
+
+    fn is_chunk_local(&self) -> bool {
+        self.parent.is_chunk_local()
+    }
+
+    fn describe(&self) -> String {
+        format!("Filter({})", self.parent.describe())
+    }
+}
+
+/// Filter a `Query` and project each remaining element, in a single pass, by a function that
+/// returns `None` to drop an element. Construct one using `Query::filter_map`.
+#[derive(Copy, Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct FilterMap<Q, F> {
+    parent: Q,
+    filter_map: F,
+}
+
+impl<Q, F> FilterMap<Q, F> {
+    /// Construct a new `FilterMap`. You probably don't want to call this constructor directly.
+    /// Prefer the `Query::filter_map` method instead.
+    pub fn new(parent: Q, filter_map: F) -> Self {
+        FilterMap { parent, filter_map }
+    }
+
+    /// Run the underlying `Query` against the given `Storage`, yielding the projection of each
+    /// element for which the function returns `Some`.
+    pub fn query<'a, ChunkKey, ItemKey, Element, T>(
+        &'a self,
+        storage: &'a Storage<ChunkKey, ItemKey, Element>,
+    ) -> impl Iterator<Item = T> + 'a
+    where
+        ChunkKey: BorrowedKey + ?Sized,
+        ChunkKey::Owned: ValidKey,
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        Q: Query<ChunkKey, ItemKey, Element>,
+        F: Fn(&Element) -> Option<T>,
+    {
+        let chunks = storage.internal_rvec();
+
+        self.parent
+            .chunk_idxs(storage)
+            .into_idx_iter()
+            .flatten()
+            .map(move |idx| &*chunks[idx])
+            .flat_map(move |chunk_storage| {
+                self.parent
+                    .item_idxs(chunk_storage.chunk_key(), chunk_storage)
+                    .into_idx_iter()
+                    .flatten()
+                    .map(move |idx| chunk_storage.get_idx(idx))
+                    .filter(move |element| self.parent.test(element))
+                    .filter_map(move |element| (self.filter_map)(element))
+            })
+    }
 }
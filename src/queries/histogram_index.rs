@@ -0,0 +1,565 @@
+use crate::bits::Bitset;
+use crate::internal::mr::rvec::RVec;
+use crate::internal::mr::summarize::{Summarize, SummaryRules};
+use crate::traits::idxset::IdxSet;
+use crate::traits::memory_usage::{MemoryUsage, MemoryUser};
+use crate::traits::query::Query;
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::chunk_storage::ChunkStorage;
+use crate::types::storage::Storage;
+use crate::types::storage_builder::Strictness;
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::{Bound, Range, RangeBounds};
+use std::sync::Arc;
+use std::sync::RwLock;
+
+type Value<Element> = Arc<dyn Fn(&Element) -> f64 + Send + Sync + 'static>;
+// The number of elements in each bucket of a chunk, by bucket number.
+type ChunkHistogram = BTreeMap<i64, usize>;
+
+/// A histogram of each chunk of a `Storage`, over a numeric value computed from each element,
+/// such as a price or a timestamp. Use the histograms to estimate how many elements fall
+/// within a range of values before running a query, or to skip the chunks that can't have any
+/// such element using `Query::matching_histogram`.
+///
+/// Values are counted in buckets of a fixed width, so a histogram is only as precise as its
+/// buckets: a range that covers part of a bucket is estimated by assuming that the values in
+/// that bucket are spread evenly across it. Values that aren't finite aren't counted.
+///
+/// # Type Parameters
+///
+/// * `ChunkKey`: The chunk key type of the `Storage`.
+/// * `Element`: The element type of the `Storage`.
+///
+/// # Example
+///
+/// ```
+/// use retriever::prelude::*;
+/// use retriever::queries::histogram_index::HistogramIndex;
+///
+/// // Orders chunked by store, keyed by order id, with a total price.
+/// type Order = (u64, u64, f64);
+/// let mut storage : Storage<u64, u64, Order> = Storage::new();
+/// let by_price : HistogramIndex<u64, Order> = HistogramIndex::new(&storage, 10.0, |x: &Order| x.2);
+///
+/// for id in 0..100 {
+///   storage.add((id % 4, id, (id % 4) as f64 * 100.0 + id as f64));
+/// }
+///
+/// // Only the chunk of store 3 has any orders over 300.
+/// let expensive = Everything.matching_histogram(&by_price, 300.0..);
+/// assert_eq!(1, expensive.explain(&storage).chunks.len());
+/// assert_eq!(25, storage.query(expensive).count());
+///
+/// let histogram = by_price.histogram(&storage);
+/// assert_eq!(100, histogram.len());
+/// assert_eq!(25.0, histogram.estimate(300.0..));
+/// assert!(!histogram.may_contain(..0.0));
+///
+/// # storage.validate();
+/// # by_price.validate(&storage);
+/// ```
+pub struct HistogramIndex<ChunkKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+{
+    histograms: Arc<RwLock<HistogramIndexImpl<ChunkKey, Element>>>,
+    bucket_width: f64,
+    value: Value<Element>,
+}
+
+impl<ChunkKey, Element> Clone for HistogramIndex<ChunkKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+{
+    fn clone(&self) -> Self {
+        HistogramIndex {
+            histograms: Arc::clone(&self.histograms),
+            bucket_width: self.bucket_width,
+            value: Arc::clone(&self.value),
+        }
+    }
+}
+
+struct HistogramIndexImpl<ChunkKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+{
+    // parent_id, used to see that this HistogramIndex isn't suddenly used with a different parent storage
+    parent_id: u64,
+    // gc_chunk_list, remember the chunks from our last update, so we can remove histograms for newly-absent chunks
+    gc_chunk_list: RVec<Option<ChunkKey::Owned>>,
+    // rule for finding the bucket of each element
+    rules: Arc<SummaryRules<Element, Option<i64>, ChunkHistogram>>,
+    // the histogram of each chunk
+    index: HashMap<
+        ChunkKey::Owned,
+        Summarize<Element, Option<i64>, ChunkHistogram>,
+        crate::internal::hasher::HasherImpl,
+    >,
+}
+
+/// A histogram of some of the values of a `HistogramIndex`. See `HistogramIndex::histogram`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Histogram {
+    bucket_width: f64,
+    buckets: ChunkHistogram,
+}
+
+impl<ChunkKey, Element> HistogramIndex<ChunkKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+{
+    /// Create a new HistogramIndex of a storage, with buckets of the given width, over the
+    /// value produced by the given rule.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the bucket width isn't a positive, finite number.
+    pub fn new<ItemKey, F>(
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        bucket_width: f64,
+        f: F,
+    ) -> Self
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        F: Fn(&Element) -> f64 + Clone + Send + Sync + 'static,
+    {
+        assert!(
+            bucket_width > 0.0 && bucket_width.is_finite(),
+            "The bucket width of a HistogramIndex must be positive and finite"
+        );
+
+        HistogramIndex {
+            histograms: Arc::new(RwLock::new(HistogramIndexImpl {
+                parent_id: storage.id(),
+                gc_chunk_list: RVec::default(),
+                rules: Arc::new(HistogramIndexImpl::<ChunkKey, Element>::histogram_rules(
+                    bucket_width,
+                    f.clone(),
+                )),
+                index: HashMap::with_hasher(crate::internal::hasher::HasherImpl::default()),
+            })),
+            bucket_width,
+            value: Arc::new(f),
+        }
+    }
+
+    /// The width of each bucket of this index.
+    pub fn bucket_width(&self) -> f64 {
+        self.bucket_width
+    }
+
+    /// Bring this index up to date and get the histogram of the whole storage.
+    pub fn histogram<ItemKey>(&self, storage: &Storage<ChunkKey, ItemKey, Element>) -> Histogram
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        let mut histogram_index_impl = self.histograms.write().unwrap();
+        histogram_index_impl.refresh(storage, 0..storage.internal_rvec().len());
+
+        let mut result = Histogram::new(self.bucket_width);
+        for summarize in histogram_index_impl.index.values() {
+            for (bucket, count) in summarize.peek() {
+                *result.buckets.entry(*bucket).or_default() += count;
+            }
+        }
+
+        result
+    }
+
+    /// Bring the histogram of the given chunk up to date and get it, or `None` if there is no
+    /// such chunk.
+    pub fn chunk_histogram<ItemKey>(
+        &self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        chunk_key: &ChunkKey,
+    ) -> Option<Histogram>
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        let idx = storage.internal_idx_of(chunk_key)?;
+        let mut histogram_index_impl = self.histograms.write().unwrap();
+        histogram_index_impl.refresh(storage, idx..idx + 1);
+
+        Some(Histogram {
+            bucket_width: self.bucket_width,
+            buckets: histogram_index_impl.index[chunk_key].peek().clone(),
+        })
+    }
+
+    /// Panic if this storage is malformed or broken in any detectable way.
+    /// This is a slow operation and you shouldn't use it unless you suspect a problem.
+    pub fn validate<ItemKey>(&self, parent: &Storage<ChunkKey, ItemKey, Element>)
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        let mut histogram_index_impl = self.histograms.write().unwrap();
+        histogram_index_impl.refresh(parent, 0..parent.internal_rvec().len());
+
+        for chunk_key in histogram_index_impl.index.keys() {
+            assert!(parent.internal_idx_of(chunk_key.borrow()).is_some());
+        }
+
+        for chunk_storage in parent.internal_rvec().iter() {
+            let mut expected = ChunkHistogram::new();
+            for element in chunk_storage.internal_rvec().iter() {
+                if let Some(bucket) = bucket_of((self.value)(element), self.bucket_width) {
+                    *expected.entry(bucket).or_default() += 1;
+                }
+            }
+
+            assert_eq!(
+                &expected,
+                histogram_index_impl.index[chunk_storage.chunk_key()].peek(),
+                "A HistogramIndex miscounted a chunk"
+            );
+        }
+    }
+
+    // Bring the histograms of the given chunks up to date, and get the internal indices of
+    // those chunks that may have a value in the given range of buckets.
+    fn chunk_idxs<ItemKey, I>(
+        &self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        idxs: I,
+        buckets: (i64, i64),
+    ) -> Bitset
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        I: IdxSet,
+    {
+        let mut histogram_index_impl = self.histograms.write().unwrap();
+        let idxs: Vec<usize> = idxs.into_idx_iter().flatten().collect();
+        histogram_index_impl.refresh(storage, idxs.iter().copied());
+
+        idxs.into_iter()
+            .filter(|idx| {
+                let chunk_key = storage.internal_rvec()[*idx].chunk_key();
+                histogram_index_impl.index[chunk_key]
+                    .peek()
+                    .range(buckets.0..=buckets.1)
+                    .next()
+                    .is_some()
+            })
+            .collect()
+    }
+}
+
+impl<ChunkKey, Element> HistogramIndexImpl<ChunkKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+{
+    fn histogram_rules<F>(
+        bucket_width: f64,
+        f: F,
+    ) -> SummaryRules<Element, Option<i64>, ChunkHistogram>
+    where
+        F: Fn(&Element) -> f64 + Send + Sync + 'static,
+    {
+        SummaryRules {
+            map: Arc::new(move |element, old_bucket, _internal_idx| {
+                let new_bucket = bucket_of(f(element), bucket_width);
+
+                if old_bucket != &new_bucket {
+                    Some(new_bucket)
+                } else {
+                    None
+                }
+            }),
+            contribute: Arc::new(|new_bucket, _internal_idx, histogram| {
+                if let Some(bucket) = new_bucket {
+                    *histogram.entry(*bucket).or_default() += 1;
+                }
+            }),
+            uncontribute: Arc::new(|old_bucket, _internal_idx, histogram| {
+                if let Some(bucket) = old_bucket {
+                    let count = histogram
+                        .get_mut(bucket)
+                        .expect("a HistogramIndex lost a bucket");
+                    *count -= 1;
+                    if *count == 0 {
+                        histogram.remove(bucket);
+                    }
+                }
+            }),
+        }
+    }
+
+    fn refresh<ItemKey, I>(&mut self, storage: &Storage<ChunkKey, ItemKey, Element>, idxs: I)
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        I: IntoIterator<Item = usize>,
+    {
+        if self.parent_id != storage.id() {
+            assert_eq!(storage.strictness(), Strictness::Repair, "Id mismatch: a histogram index may only be used with it's parent Storage, never any other Storage");
+            #[cfg(feature = "log")]
+            log::warn!(
+                "retriever: repaired histogram index used with a different Storage by rebuilding it"
+            );
+            self.parent_id = storage.id();
+            self.gc_chunk_list = RVec::default();
+            self.index.clear();
+        }
+
+        storage.gc(&mut self.gc_chunk_list, &mut self.index);
+
+        for idx in idxs {
+            let chunk_storage = &storage.internal_rvec()[idx];
+            let internal_storage = chunk_storage.internal_rvec();
+            let rules = &self.rules;
+            self.index
+                .entry(chunk_storage.chunk_key().to_owned())
+                .or_insert_with(|| Summarize::new(internal_storage, Arc::clone(rules)))
+                .update(internal_storage);
+        }
+    }
+}
+
+impl<ChunkKey, Element> MemoryUser for HistogramIndexImpl<ChunkKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+{
+    fn memory_usage(&self) -> MemoryUsage {
+        let mut result = self.gc_chunk_list.memory_usage();
+        result = MemoryUsage::merge(result, self.index.memory_usage());
+
+        for s in self.index.values() {
+            result = MemoryUsage::merge(result, s.memory_usage());
+            result = MemoryUsage::merge(result, s.peek().memory_usage());
+        }
+
+        result
+    }
+
+    fn shrink_with<F: Fn(&MemoryUsage) -> Option<usize>>(&mut self, f: F) {
+        self.gc_chunk_list.shrink_with(&f);
+        self.index.shrink_with(&f);
+
+        for i in self.index.values_mut() {
+            i.shrink_with(&f);
+        }
+    }
+}
+
+impl<ChunkKey, Element> MemoryUser for HistogramIndex<ChunkKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+{
+    fn memory_usage(&self) -> MemoryUsage {
+        self.histograms.read().unwrap().memory_usage()
+    }
+
+    fn shrink_with<F: Fn(&MemoryUsage) -> Option<usize>>(&mut self, f: F) {
+        self.histograms.write().unwrap().shrink_with(f)
+    }
+}
+
+impl Histogram {
+    fn new(bucket_width: f64) -> Self {
+        Histogram {
+            bucket_width,
+            buckets: ChunkHistogram::new(),
+        }
+    }
+
+    /// The number of values counted by this histogram.
+    pub fn len(&self) -> usize {
+        self.buckets.values().sum()
+    }
+
+    /// True if this histogram counted no values.
+    pub fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+
+    /// The range of values and the number of values in each bucket that isn't empty, in order.
+    pub fn buckets(&self) -> impl Iterator<Item = (Range<f64>, usize)> + '_ {
+        let bucket_width = self.bucket_width;
+        self.buckets.iter().map(move |(bucket, count)| {
+            let start = *bucket as f64 * bucket_width;
+            (start..start + bucket_width, *count)
+        })
+    }
+
+    /// Test whether any value might fall within the given range. False means that none does.
+    pub fn may_contain<R: RangeBounds<f64>>(&self, range: R) -> bool {
+        match buckets_of(&range, self.bucket_width) {
+            Some((first, last)) => self.buckets.range(first..=last).next().is_some(),
+            None => false,
+        }
+    }
+
+    /// Estimate the number of values that fall within the given range.
+    pub fn estimate<R: RangeBounds<f64>>(&self, range: R) -> f64 {
+        let (first, last) = match buckets_of(&range, self.bucket_width) {
+            Some(buckets) => buckets,
+            None => return 0.0,
+        };
+
+        let start = match range.start_bound() {
+            Bound::Included(x) | Bound::Excluded(x) => *x,
+            Bound::Unbounded => f64::NEG_INFINITY,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(x) | Bound::Excluded(x) => *x,
+            Bound::Unbounded => f64::INFINITY,
+        };
+
+        self.buckets
+            .range(first..=last)
+            .map(|(bucket, count)| {
+                let bucket_start = *bucket as f64 * self.bucket_width;
+                let bucket_end = bucket_start + self.bucket_width;
+                let covered = end.min(bucket_end) - start.max(bucket_start);
+                *count as f64 * (covered / self.bucket_width).clamp(0.0, 1.0)
+            })
+            .sum()
+    }
+}
+
+// The bucket containing the given value, if it's finite.
+fn bucket_of(value: f64, bucket_width: f64) -> Option<i64> {
+    if value.is_finite() {
+        Some((value / bucket_width).floor() as i64)
+    } else {
+        None
+    }
+}
+
+// The first and last buckets that overlap the given range, if it isn't empty.
+fn buckets_of<R: RangeBounds<f64>>(range: &R, bucket_width: f64) -> Option<(i64, i64)> {
+    // float to integer casts saturate, so infinite bounds fall in the first or last bucket
+    let first = match range.start_bound() {
+        Bound::Included(x) | Bound::Excluded(x) => (x / bucket_width).floor() as i64,
+        Bound::Unbounded => i64::MIN,
+    };
+    let last = match range.end_bound() {
+        Bound::Included(x) => (x / bucket_width).floor() as i64,
+        // a bucket that starts at an excluded end has nothing in range
+        Bound::Excluded(x) => (x / bucket_width).ceil() as i64 - 1,
+        Bound::Unbounded => i64::MAX,
+    };
+
+    if first <= last {
+        Some((first, last))
+    } else {
+        None
+    }
+}
+
+/// A Query matching a range of values against a `HistogramIndex`. Construct using
+/// `Query::matching_histogram`.
+pub struct MatchingHistogram<Q, ChunkKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+{
+    query: Q,
+    histogram_index: HistogramIndex<ChunkKey, Element>,
+    range: (Bound<f64>, Bound<f64>),
+}
+
+impl<Q, ChunkKey, Element> Clone for MatchingHistogram<Q, ChunkKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    Q: Clone,
+{
+    fn clone(&self) -> Self {
+        MatchingHistogram {
+            query: self.query.clone(),
+            histogram_index: self.histogram_index.clone(),
+            range: self.range,
+        }
+    }
+}
+
+impl<Q, ChunkKey, Element> MatchingHistogram<Q, ChunkKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+{
+    pub(crate) fn new<R: RangeBounds<f64>>(
+        query: Q,
+        histogram_index: &HistogramIndex<ChunkKey, Element>,
+        range: R,
+    ) -> Self {
+        MatchingHistogram {
+            query,
+            histogram_index: histogram_index.clone(),
+            range: (range.start_bound().cloned(), range.end_bound().cloned()),
+        }
+    }
+}
+
+impl<Q, ChunkKey, ItemKey, Element> Query<ChunkKey, ItemKey, Element>
+    for MatchingHistogram<Q, ChunkKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+    Q: Query<ChunkKey, ItemKey, Element>,
+{
+    type ChunkIdxSet = Bitset;
+    type ItemIdxSet = Q::ItemIdxSet;
+
+    fn chunk_idxs(&self, storage: &Storage<ChunkKey, ItemKey, Element>) -> Self::ChunkIdxSet {
+        let idxs = self.query.chunk_idxs(storage);
+        match buckets_of(&self.range, self.histogram_index.bucket_width) {
+            Some(buckets) => self.histogram_index.chunk_idxs(storage, idxs, buckets),
+            None => Bitset::new(),
+        }
+    }
+
+    fn item_idxs(
+        &self,
+        chunk_key: &ChunkKey,
+        chunk_storage: &ChunkStorage<ChunkKey, ItemKey, Element>,
+    ) -> Self::ItemIdxSet {
+        self.query.item_idxs(chunk_key, chunk_storage)
+    }
+
+    fn test(&self, element: &Element) -> bool {
+        self.range.contains(&(self.histogram_index.value)(element)) && self.query.test(element)
+    }
+
+    fn test_chunk(&self, chunk_key: &ChunkKey) -> bool {
+        self.query.test_chunk(chunk_key)
+    }
+
+    fn is_chunk_local(&self) -> bool {
+        self.query.is_chunk_local()
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "MatchingHistogram({}, HistogramIndex, {:?})",
+            self.query.describe(),
+            self.range
+        )
+    }
+}
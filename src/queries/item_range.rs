@@ -0,0 +1,138 @@
+use crate::bits::bitfield::Bitfield;
+use crate::bits::Bitset;
+use crate::internal::bounds::borrow_bound;
+use crate::traits::item_selection::ItemSelection;
+use crate::traits::query::Query;
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::chunk_storage::ChunkStorage;
+use crate::types::storage::Storage;
+use std::borrow::Borrow;
+use std::ops::{Bound, Range, RangeBounds, RangeFrom, RangeInclusive, RangeTo, RangeToInclusive};
+
+/// A `Query` that visits the elements of a single chunk whose item keys fall within a range,
+/// in the manner of `BTreeMap::range`. If the `Storage` keeps its item keys in order (see
+/// `StorageBuilder::ordered_item_keys`), this never visits elements outside of the range;
+/// otherwise it tests every item key of the chunk. Construct one using `Id::items`.
+///
+/// # Example
+///
+/// ```
+/// use retriever::prelude::*;
+///
+/// // Readings chunked by sensor, keyed by timestamp.
+/// let mut storage : Storage<&'static str, u64, (&'static str, u64, f64)> = Storage::new();
+///
+/// for t in 0..10 {
+///   storage.add(("thermometer", 1700000000 + t * 100, 20.0 + t as f64));
+/// }
+///
+/// let mut readings : Vec<f64> = storage
+///   .query(ID.chunk("thermometer").items(1700000200..1700000500))
+///   .map(|x| x.2)
+///   .collect();
+///
+/// readings.sort_by(|a, b| a.partial_cmp(b).unwrap());
+/// assert_eq!(vec![22.0, 23.0, 24.0], readings);
+///
+/// assert_eq!(2, storage.query(ID.chunk("thermometer").items(1700000800..)).count());
+/// assert_eq!(0, storage.query(ID.chunk("barometer").items(1700000000..)).count());
+///
+/// // An inverted range is simply empty.
+/// assert_eq!(0, storage.query(ID.chunk("thermometer").items(1700000500..1700000200)).count());
+///
+/// # storage.validate();
+/// ```
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ItemRange<C, Q> {
+    chunk_key: C,
+    start: Bound<Q>,
+    end: Bound<Q>,
+}
+
+impl<C, Q> ItemRange<C, Q>
+where
+    Q: Clone,
+{
+    /// Construct a new `ItemRange` query. Prefer the `Id::items` method instead.
+    pub fn new<R>(chunk_key: C, range: R) -> Self
+    where
+        R: RangeBounds<Q>,
+    {
+        ItemRange {
+            chunk_key,
+            start: range.start_bound().cloned(),
+            end: range.end_bound().cloned(),
+        }
+    }
+}
+
+impl<ChunkKey, ItemKey, Element, C, Q> Query<ChunkKey, ItemKey, Element> for ItemRange<C, Q>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+    C: ValidKey + Borrow<ChunkKey>,
+    Q: ValidKey + Borrow<ItemKey>,
+{
+    type ChunkIdxSet = Bitfield;
+    type ItemIdxSet = Bitset;
+
+    fn chunk_idxs(&self, storage: &Storage<ChunkKey, ItemKey, Element>) -> Self::ChunkIdxSet {
+        Bitfield::from(storage.internal_idx_of(self.chunk_key.borrow()))
+    }
+
+    fn item_idxs(
+        &self,
+        _chunk_key: &ChunkKey,
+        chunk_storage: &ChunkStorage<ChunkKey, ItemKey, Element>,
+    ) -> Self::ItemIdxSet {
+        chunk_storage
+            .item_keys_in_range(
+                borrow_bound(self.start.as_ref()),
+                borrow_bound(self.end.as_ref()),
+            )
+            .map(|item_key| {
+                chunk_storage
+                    .internal_idx_of(item_key)
+                    .expect("ordered index should agree with index")
+            })
+            .collect()
+    }
+
+    fn test(&self, _element: &Element) -> bool {
+        true
+    }
+
+    fn is_exact(&self) -> bool {
+        true
+    }
+
+    fn test_chunk(&self, chunk_key: &ChunkKey) -> bool {
+        self.chunk_key.borrow() == chunk_key
+    }
+}
+
+macro_rules! item_range_selection_impl {
+    ( $range:ty ) => {
+        impl<C, Q> ItemSelection<C> for $range
+        where
+            Q: Clone,
+        {
+            type Query = ItemRange<C, Q>;
+
+            fn select(self, chunk_key: C) -> Self::Query {
+                ItemRange::new(chunk_key, self)
+            }
+        }
+    };
+}
+
+item_range_selection_impl!(Range<Q>);
+item_range_selection_impl!(RangeInclusive<Q>);
+item_range_selection_impl!(RangeFrom<Q>);
+item_range_selection_impl!(RangeTo<Q>);
+item_range_selection_impl!(RangeToInclusive<Q>);
+item_range_selection_impl!((Bound<Q>, Bound<Q>));
@@ -0,0 +1,258 @@
+use crate::bits::bitfield::Bitfield;
+use crate::bits::Bitset;
+use crate::traits::item_selection::ItemSelection;
+use crate::traits::query::Query;
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::chunk_storage::ChunkStorage;
+use crate::types::id::Id;
+use crate::types::storage::Storage;
+use std::borrow::Borrow;
+use std::collections::{BTreeSet, HashSet};
+use std::iter::FromIterator;
+
+/// A `Query` that visits an explicit list of elements within a single chunk, looking up each
+/// one in the chunk's item index rather than scanning the whole chunk. This is the item key
+/// analogue of `Chunks`. Construct one using `Id::items`.
+///
+/// # Example
+///
+/// ```
+/// use retriever::prelude::*;
+///
+/// let mut storage : Storage<&'static str, u64, (&'static str, u64, &'static str)> = Storage::new();
+///
+/// storage.add(("fruit", 1, "apple"));
+/// storage.add(("fruit", 2, "banana"));
+/// storage.add(("fruit", 3, "cherry"));
+/// storage.add(("vegetable", 1, "carrot"));
+///
+/// let mut names : Vec<&str> = storage
+///   .query(ID.chunk("fruit").items([1, 3, 7]))
+///   .map(|x| x.2)
+///   .collect();
+/// names.sort();
+///
+/// assert_eq!(vec!["apple", "cherry"], names);
+/// assert_eq!(1, storage.query(ID.chunk("fruit").items(vec![2])).count());
+/// # storage.validate();
+/// ```
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Items<C, Q> {
+    chunk_key: C,
+    item_keys: Vec<Q>,
+}
+
+impl<C, Q> Items<C, Q> {
+    /// Construct a new `Items` query. Prefer the `Id::items` method instead.
+    pub fn new<I>(chunk_key: C, item_keys: I) -> Self
+    where
+        I: IntoIterator<Item = Q>,
+    {
+        Items {
+            chunk_key,
+            item_keys: item_keys.into_iter().collect(),
+        }
+    }
+}
+
+impl<ChunkKey, ItemKey, Element, C, Q> Query<ChunkKey, ItemKey, Element> for Items<C, Q>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+    C: ValidKey + Borrow<ChunkKey>,
+    Q: ValidKey + Borrow<ItemKey>,
+{
+    type ChunkIdxSet = Bitfield;
+    type ItemIdxSet = Bitset;
+
+    fn chunk_idxs(&self, storage: &Storage<ChunkKey, ItemKey, Element>) -> Self::ChunkIdxSet {
+        Bitfield::from(storage.internal_idx_of(self.chunk_key.borrow()))
+    }
+
+    fn item_idxs(
+        &self,
+        _chunk_key: &ChunkKey,
+        chunk_storage: &ChunkStorage<ChunkKey, ItemKey, Element>,
+    ) -> Self::ItemIdxSet {
+        self.item_keys
+            .iter()
+            .filter_map(|item_key| chunk_storage.internal_idx_of(item_key.borrow()))
+            .collect()
+    }
+
+    fn test(&self, _element: &Element) -> bool {
+        true
+    }
+
+    fn is_exact(&self) -> bool {
+        true
+    }
+
+    fn test_chunk(&self, chunk_key: &ChunkKey) -> bool {
+        self.chunk_key.borrow() == chunk_key
+    }
+}
+
+impl<C, Q> ItemSelection<C> for Vec<Q> {
+    type Query = Items<C, Q>;
+
+    fn select(self, chunk_key: C) -> Self::Query {
+        Items::new(chunk_key, self)
+    }
+}
+
+impl<C, Q, const N: usize> ItemSelection<C> for [Q; N] {
+    type Query = Items<C, Q>;
+
+    fn select(self, chunk_key: C) -> Self::Query {
+        Items::new(chunk_key, self)
+    }
+}
+
+impl<C, Q> ItemSelection<C> for &[Q]
+where
+    Q: Clone,
+{
+    type Query = Items<C, Q>;
+
+    fn select(self, chunk_key: C) -> Self::Query {
+        Items::new(chunk_key, self.iter().cloned())
+    }
+}
+
+impl<C, Q, S> ItemSelection<C> for HashSet<Q, S> {
+    type Query = Items<C, Q>;
+
+    fn select(self, chunk_key: C) -> Self::Query {
+        Items::new(chunk_key, self)
+    }
+}
+
+impl<C, Q> ItemSelection<C> for BTreeSet<Q> {
+    type Query = Items<C, Q>;
+
+    fn select(self, chunk_key: C) -> Self::Query {
+        Items::new(chunk_key, self)
+    }
+}
+
+/// A `Query` that visits an explicit list of elements, by `Id`, across any number of chunks.
+/// Each element is looked up in the chunk index and item index rather than found by a scan.
+///
+/// # Example
+///
+/// ```
+/// use retriever::prelude::*;
+/// use retriever::queries::items::Ids;
+///
+/// let mut storage : Storage<u64, u64, (u64, u64, &'static str)> = Storage::new();
+///
+/// storage.add((1, 1, "a"));
+/// storage.add((1, 2, "b"));
+/// storage.add((2, 1, "c"));
+/// storage.add((3, 1, "d"));
+///
+/// let ids : Ids<u64, u64> = vec![ID.chunk(2).item(1), ID.chunk(1).item(2), ID.chunk(9).item(9)]
+///   .into_iter()
+///   .collect();
+///
+/// let mut letters : Vec<&str> = storage.query(&ids).map(|x| x.2).collect();
+/// letters.sort();
+///
+/// assert_eq!(vec!["b", "c"], letters);
+/// # storage.validate();
+/// ```
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Ids<C, I> {
+    // sorted and deduplicated, so that the ids of each chunk are contiguous
+    ids: Vec<Id<C, I>>,
+}
+
+impl<C, I> Ids<C, I>
+where
+    C: Ord,
+    I: Ord,
+{
+    /// Construct a new `Ids` query from any collection of `Ids`.
+    pub fn new<II>(ids: II) -> Self
+    where
+        II: IntoIterator<Item = Id<C, I>>,
+    {
+        let mut ids: Vec<Id<C, I>> = ids.into_iter().collect();
+        ids.sort();
+        ids.dedup();
+        Ids { ids }
+    }
+
+    /// The `Ids` belonging to the given chunk.
+    fn ids_of<ChunkKey>(&self, chunk_key: &ChunkKey) -> &[Id<C, I>]
+    where
+        ChunkKey: Ord + ?Sized,
+        C: Borrow<ChunkKey>,
+    {
+        let start = self.ids.partition_point(|id| id.0.borrow() < chunk_key);
+        let len = self.ids[start..].partition_point(|id| id.0.borrow() == chunk_key);
+        &self.ids[start..start + len]
+    }
+}
+
+impl<C, I> FromIterator<Id<C, I>> for Ids<C, I>
+where
+    C: Ord,
+    I: Ord,
+{
+    fn from_iter<II>(ids: II) -> Self
+    where
+        II: IntoIterator<Item = Id<C, I>>,
+    {
+        Ids::new(ids)
+    }
+}
+
+impl<ChunkKey, ItemKey, Element, C, I> Query<ChunkKey, ItemKey, Element> for Ids<C, I>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+    C: ValidKey + Borrow<ChunkKey>,
+    I: ValidKey + Borrow<ItemKey>,
+{
+    type ChunkIdxSet = Bitset;
+    type ItemIdxSet = Bitset;
+
+    fn chunk_idxs(&self, storage: &Storage<ChunkKey, ItemKey, Element>) -> Self::ChunkIdxSet {
+        self.ids
+            .iter()
+            .filter_map(|id| storage.internal_idx_of(id.0.borrow()))
+            .collect()
+    }
+
+    fn item_idxs(
+        &self,
+        chunk_key: &ChunkKey,
+        chunk_storage: &ChunkStorage<ChunkKey, ItemKey, Element>,
+    ) -> Self::ItemIdxSet {
+        self.ids_of(chunk_key)
+            .iter()
+            .filter_map(|id| chunk_storage.internal_idx_of(id.1.borrow()))
+            .collect()
+    }
+
+    fn test(&self, _element: &Element) -> bool {
+        true
+    }
+
+    fn is_exact(&self) -> bool {
+        true
+    }
+
+    fn test_chunk(&self, chunk_key: &ChunkKey) -> bool {
+        !self.ids_of(chunk_key).is_empty()
+    }
+}
@@ -0,0 +1,347 @@
+use crate::bits::Bitset;
+use crate::idxsets::difference::Difference;
+use crate::idxsets::intersection::Intersection;
+use crate::internal::hasher::HasherImpl;
+use crate::traits::idxset::IdxSet;
+use crate::traits::query::Query;
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::chunk_storage::ChunkStorage;
+use crate::types::id::Id;
+use crate::types::storage::Storage;
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::fmt::{self, Debug};
+use std::ops::Bound;
+use std::sync::{Arc, Mutex};
+
+/// Visit at most a fixed number of the elements of a `Query`.
+///
+/// A `Limit` chooses its elements each time a query begins: the first `n` elements in the order
+/// that `Storage::query` visits them. Every chunk after the last of those is skipped without
+/// looking at any of its elements. The same elements are visited however the query is run,
+/// whether in reverse, in parallel, or combined with other queries.
+///
+/// # One storage, one evaluation at a time
+///
+/// A `Limit` remembers its chosen elements from the moment a query begins until the next query
+/// using the same `Limit` begins, and every clone of it shares that memory. The chosen elements
+/// are keyed only by chunk key, so each `Limit` (with all of its clones) must serve one `Storage`
+/// and one evaluation at a time. Beginning a second query with the same `Limit`, whether on another
+/// `Storage` or from another thread, before the first query has finished will silently mix up the
+/// results of both. To run the same limit against several storages at once, construct a separate
+/// `Limit` for each.
+#[derive(Clone)]
+pub struct Limit<Q, C> {
+    query: Q,
+    limit: usize,
+    chosen: Chosen<C>,
+}
+
+impl<Q, C> Limit<Q, C> {
+    /// Construct a new `Limit` query. Prefer the `Query::limit` method instead.
+    pub fn new(query: Q, limit: usize) -> Self {
+        Limit {
+            query,
+            limit,
+            chosen: Arc::new(Mutex::new(HashMap::with_hasher(HasherImpl::default()))),
+        }
+    }
+}
+
+impl<Q, C> Debug for Limit<Q, C>
+where
+    Q: Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Limit")
+            .field("query", &self.query)
+            .field("limit", &self.limit)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<ChunkKey, ItemKey, Element, Q, C> Query<ChunkKey, ItemKey, Element> for Limit<Q, C>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+    Q: Query<ChunkKey, ItemKey, Element>,
+    ChunkKey: ToOwned<Owned = C>,
+    C: ValidKey + Borrow<ChunkKey>,
+{
+    type ChunkIdxSet = Intersection<Q::ChunkIdxSet, Bitset>;
+    type ItemIdxSet = Bitset;
+
+    fn chunk_idxs(&self, storage: &Storage<ChunkKey, ItemKey, Element>) -> Self::ChunkIdxSet {
+        let chunk_idxs = self.query.chunk_idxs(storage);
+        let chosen = first_elements(storage, &self.query, chunk_idxs.clone(), self.limit);
+        let chosen_chunk_idxs = chosen
+            .keys()
+            .filter_map(|chunk_key| storage.internal_idx_of(chunk_key.borrow()))
+            .collect();
+
+        *self.chosen.lock().unwrap() = chosen;
+        IdxSet::intersection(chunk_idxs, chosen_chunk_idxs)
+    }
+
+    fn item_idxs(
+        &self,
+        chunk_key: &ChunkKey,
+        _chunk_storage: &ChunkStorage<ChunkKey, ItemKey, Element>,
+    ) -> Self::ItemIdxSet {
+        self.chosen
+            .lock()
+            .unwrap()
+            .get(chunk_key)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn test(&self, _element: &Element) -> bool {
+        true
+    }
+
+    fn test_chunk(&self, chunk_key: &ChunkKey) -> bool {
+        self.chosen.lock().unwrap().contains_key(chunk_key)
+    }
+
+    fn is_exact(&self) -> bool {
+        true
+    }
+
+    fn is_chunk_local(&self) -> bool {
+        false
+    }
+
+    fn describe(&self) -> String {
+        format!("Limit({}, {})", self.query.describe(), self.limit)
+    }
+}
+
+/// Skip a fixed number of the elements of a `Query`, and visit the rest.
+///
+/// The skipped elements still have to be found, so skipping is not much faster than visiting.
+/// Like `Limit`, a `Skip` chooses the elements to skip each time a query begins, in the order
+/// that `Storage::query` visits them, and clones of a `Skip` share that choice.
+///
+/// # One storage, one evaluation at a time
+///
+/// As with `Limit`, each `Skip` (with all of its clones) must serve one `Storage` and one
+/// evaluation at a time. Beginning a second query with the same `Skip` before the first has
+/// finished will silently mix up the results of both. Construct a separate `Skip` for each
+/// `Storage` that is queried at the same time.
+#[derive(Clone)]
+pub struct Skip<Q, C> {
+    query: Q,
+    skip: usize,
+    chosen: Chosen<C>,
+}
+
+impl<Q, C> Skip<Q, C> {
+    /// Construct a new `Skip` query. Prefer the `Query::skip` method instead.
+    pub fn new(query: Q, skip: usize) -> Self {
+        Skip {
+            query,
+            skip,
+            chosen: Arc::new(Mutex::new(HashMap::with_hasher(HasherImpl::default()))),
+        }
+    }
+}
+
+impl<Q, C> Debug for Skip<Q, C>
+where
+    Q: Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Skip")
+            .field("query", &self.query)
+            .field("skip", &self.skip)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<ChunkKey, ItemKey, Element, Q, C> Query<ChunkKey, ItemKey, Element> for Skip<Q, C>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+    Q: Query<ChunkKey, ItemKey, Element>,
+    ChunkKey: ToOwned<Owned = C>,
+    C: ValidKey + Borrow<ChunkKey>,
+{
+    type ChunkIdxSet = Q::ChunkIdxSet;
+    type ItemIdxSet = Difference<Q::ItemIdxSet, Option<Bitset>>;
+
+    fn chunk_idxs(&self, storage: &Storage<ChunkKey, ItemKey, Element>) -> Self::ChunkIdxSet {
+        let chunk_idxs = self.query.chunk_idxs(storage);
+        *self.chosen.lock().unwrap() =
+            first_elements(storage, &self.query, chunk_idxs.clone(), self.skip);
+        chunk_idxs
+    }
+
+    fn item_idxs(
+        &self,
+        chunk_key: &ChunkKey,
+        chunk_storage: &ChunkStorage<ChunkKey, ItemKey, Element>,
+    ) -> Self::ItemIdxSet {
+        let skipped = self.chosen.lock().unwrap().get(chunk_key).cloned();
+        IdxSet::difference(self.query.item_idxs(chunk_key, chunk_storage), skipped)
+    }
+
+    fn test(&self, element: &Element) -> bool {
+        self.query.test(element)
+    }
+
+    fn test_chunk(&self, chunk_key: &ChunkKey) -> bool {
+        self.query.test_chunk(chunk_key)
+    }
+
+    fn is_exact(&self) -> bool {
+        self.query.is_exact()
+    }
+
+    fn is_chunk_local(&self) -> bool {
+        false
+    }
+
+    fn describe(&self) -> String {
+        format!("Skip({}, {})", self.query.describe(), self.skip)
+    }
+}
+
+// The item idxs chosen by a `Limit` or `Skip` in each chunk, by chunk key. Every clone of the
+// query shares them, so that each chunk sees the choice made when the query began. They can't be
+// keyed by storage, because `Query::item_idxs` only sees the chunk, and a snapshot shares both its
+// chunks and its id with the original; hence one storage and one evaluation at a time.
+pub(crate) type Chosen<C> = Arc<Mutex<HashMap<C, Bitset, HasherImpl>>>;
+
+// The first `n` elements of a `Query` among the given chunks, in the order that
+// `Storage::query` visits them, as item idxs by chunk key. Chunks without any are left out.
+pub(crate) fn first_elements<ChunkKey, ItemKey, Element, Q>(
+    storage: &Storage<ChunkKey, ItemKey, Element>,
+    query: &Q,
+    chunk_idxs: Q::ChunkIdxSet,
+    n: usize,
+) -> HashMap<ChunkKey::Owned, Bitset, HasherImpl>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+    Q: Query<ChunkKey, ItemKey, Element>,
+{
+    let mut result = HashMap::with_hasher(HasherImpl::default());
+    let mut remaining = n;
+
+    for idx in chunk_idxs.into_idx_iter().flatten() {
+        if remaining == 0 {
+            break;
+        }
+
+        let chunk_storage = &*storage.internal_rvec()[idx];
+        let chunk_key = chunk_storage.chunk_key();
+        let chosen: Bitset = query
+            .item_idxs(chunk_key, chunk_storage)
+            .into_idx_iter()
+            .flatten()
+            .filter(|item_idx| query.test(chunk_storage.get_idx(*item_idx)))
+            .take(remaining)
+            .collect();
+
+        if !chosen.is_empty() {
+            remaining -= chosen.len();
+            result.insert(chunk_key.to_owned(), chosen);
+        }
+    }
+
+    result
+}
+
+/// Visit only the elements of a `Query` that come strictly after a given `Id`, in order of
+/// chunk key and then item key.
+///
+/// Unlike `Skip`, an `After` doesn't count anything, so a page that begins after the last `Id`
+/// of the previous page stays put when elements are added or removed elsewhere. Every chunk
+/// whose key precedes the `Id`'s chunk key is skipped without looking at any of its elements.
+#[derive(Clone, Debug)]
+pub struct After<Q, C, I> {
+    query: Q,
+    id: Id<C, I>,
+}
+
+impl<Q, C, I> After<Q, C, I> {
+    /// Construct a new `After` query. Prefer the `Query::after` method instead.
+    pub fn new(query: Q, id: Id<C, I>) -> Self {
+        After { query, id }
+    }
+}
+
+impl<ChunkKey, ItemKey, Element, Q, C, I> Query<ChunkKey, ItemKey, Element> for After<Q, C, I>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+    Q: Query<ChunkKey, ItemKey, Element>,
+    C: ValidKey + Borrow<ChunkKey>,
+    I: ValidKey + Borrow<ItemKey>,
+{
+    type ChunkIdxSet = Intersection<Q::ChunkIdxSet, Bitset>;
+    type ItemIdxSet = Difference<Q::ItemIdxSet, Option<Bitset>>;
+
+    fn chunk_idxs(&self, storage: &Storage<ChunkKey, ItemKey, Element>) -> Self::ChunkIdxSet {
+        IdxSet::intersection(
+            self.query.chunk_idxs(storage),
+            storage.internal_idxs_in_range(Bound::Included(self.id.0.borrow()), Bound::Unbounded),
+        )
+    }
+
+    fn item_idxs(
+        &self,
+        chunk_key: &ChunkKey,
+        chunk_storage: &ChunkStorage<ChunkKey, ItemKey, Element>,
+    ) -> Self::ItemIdxSet {
+        let parent_idxs = self.query.item_idxs(chunk_key, chunk_storage);
+
+        // Only the Id's own chunk has elements to leave out.
+        let preceding_idxs = (chunk_key == self.id.0.borrow()).then(|| {
+            chunk_storage
+                .item_keys_in_range(Bound::Unbounded, Bound::Included(self.id.1.borrow()))
+                .map(|item_key| {
+                    chunk_storage
+                        .internal_idx_of(item_key)
+                        .expect("ordered index should agree with index")
+                })
+                .collect()
+        });
+
+        IdxSet::difference(parent_idxs, preceding_idxs)
+    }
+
+    fn test(&self, element: &Element) -> bool {
+        self.query.test(element)
+    }
+
+    fn test_chunk(&self, chunk_key: &ChunkKey) -> bool {
+        chunk_key >= self.id.0.borrow() && self.query.test_chunk(chunk_key)
+    }
+
+    fn is_exact(&self) -> bool {
+        self.query.is_exact()
+    }
+
+    fn is_chunk_local(&self) -> bool {
+        self.query.is_chunk_local()
+    }
+
+    fn describe(&self) -> String {
+        format!("After({}, {:?})", self.query.describe(), self.id)
+    }
+}
@@ -1,8 +1,54 @@
+/// A pre-computed index of per-chunk bloom filters that answers most lookups of absent keys.
+pub mod bloom_index;
+/// Queries combining other queries with boolean logic.
+pub mod boolean;
+/// A pre-computed index that caches the value extracted from each element.
+pub mod cached_index;
+/// Query all elements of the chunks whose keys fall within a range.
+pub mod chunk_range;
+/// Query all elements of the chunks with a tag, using a pre-computed index of chunk keys.
+pub mod chunk_tag_index;
 /// Query all elements of some explicitly enumerated chunks.
 pub mod chunks;
+/// Query all elements of the chunks whose keys satisfy a predicate.
+pub mod chunks_where;
+/// Query to filter elements by a pair of fields, or just the leading one, using a pre-computed
+/// index.
+pub mod composite_index;
+/// A pre-computed index that keeps a running count of elements by key.
+pub mod counting_index;
 /// Query every element.
 pub mod everything;
 /// Query to filter elements by predicate.
 pub mod filter;
+/// Query to filter elements by a range of numeric values, skipping chunks using a pre-computed
+/// histogram of each chunk.
+pub mod histogram_index;
+/// Query the elements of a chunk whose item keys fall within a range.
+pub mod item_range;
+/// Queries of explicitly enumerated elements.
+pub mod items;
+/// Queries to paginate the results of other queries.
+pub mod limit;
+/// Query to filter elements by a normalized string, such as a case-insensitive email address,
+/// using a pre-computed index.
+pub mod normalized_index;
+/// Query to filter elements by a range of a pre-computed, ordered index.
+pub mod ordered_secondary_index;
+/// Query to filter elements by a prefix of a hierarchical path, using a pre-computed tree of
+/// paths.
+pub mod prefix_index;
 /// Query to filter elements by a pre-computed index.
 pub mod secondary_index;
+/// Query to filter elements of several Storages of the same type by one shared, pre-computed
+/// index.
+pub mod shared_index;
+/// Query to filter elements by their position on a plane, using a pre-computed grid index.
+pub mod spatial_index;
+/// Query compiled at runtime from a textual query language.
+#[cfg(feature = "query_language")]
+pub mod text;
+/// Query to filter elements by the words of their text, using a pre-computed inverted index.
+pub mod text_index;
+/// A pre-computed index with at most one element per index key.
+pub mod unique_index;
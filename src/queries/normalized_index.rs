@@ -0,0 +1,200 @@
+use crate::queries::secondary_index::{MatchingSecondaryIndex, SecondaryIndex};
+use crate::traits::memory_usage::{MemoryUsage, MemoryUser};
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::storage::Storage;
+use std::borrow::Cow;
+use std::fmt;
+use std::sync::Arc;
+
+/// A Query matching a string against a `NormalizedIndex`. Construct using
+/// `Query::matching_normalized`.
+pub type MatchingNormalized<Q, ChunkKey, Element> =
+    MatchingSecondaryIndex<'static, Q, ChunkKey, Element, Option<String>, str>;
+
+/// A rule for normalizing strings before they are indexed or matched by a `NormalizedIndex`,
+/// so that strings that differ only in, say, case or surrounding whitespace have the same index
+/// key. Build one up from the identity using `Normalizer::default()` and the methods below,
+/// which are applied in the order they are called.
+///
+/// # Example
+///
+/// ```
+/// use retriever::queries::normalized_index::Normalizer;
+///
+/// let normalizer = Normalizer::default().trim().case_fold();
+/// assert_eq!("hello, world", normalizer.normalize("  Hello, WORLD\n"));
+///
+/// let no_dashes = normalizer.then(|s| s.replace('-', ""));
+/// assert_eq!("abc123", no_dashes.normalize(" ABC-123 "));
+/// ```
+#[derive(Clone)]
+pub struct Normalizer(Arc<dyn Fn(&str) -> String + Send + Sync + 'static>);
+
+impl Default for Normalizer {
+    fn default() -> Self {
+        Normalizer(Arc::new(|s: &str| String::from(s)))
+    }
+}
+
+impl fmt::Debug for Normalizer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Normalizer")
+    }
+}
+
+impl Normalizer {
+    /// Normalize a string.
+    pub fn normalize(&self, s: &str) -> String {
+        (self.0)(s)
+    }
+
+    /// After this normalizer, apply any other rule.
+    pub fn then<F>(&self, f: F) -> Self
+    where
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        let before = Arc::clone(&self.0);
+        Normalizer(Arc::new(move |s: &str| f(&before(s))))
+    }
+
+    /// After this normalizer, remove leading and trailing whitespace.
+    pub fn trim(&self) -> Self {
+        self.then(|s: &str| String::from(s.trim()))
+    }
+
+    /// After this normalizer, fold case by converting to lowercase, as `str::to_lowercase`.
+    pub fn case_fold(&self) -> Self {
+        self.then(str::to_lowercase)
+    }
+
+    /// After this normalizer, convert to Unicode Normalization Form C, so that a precomposed
+    /// character and the same character built from combining marks have the same index key.
+    #[cfg(feature = "unicode-normalization")]
+    pub fn nfc(&self) -> Self {
+        use unicode_normalization::UnicodeNormalization;
+        self.then(|s: &str| s.nfc().collect())
+    }
+}
+
+/// An index of the elements of a `Storage` by a string field, which is normalized by a
+/// `Normalizer` both when it's indexed and when it's matched, using
+/// `Query::matching_normalized`. Use this instead of normalizing the strings yourself, so that
+/// the index and its queries can't disagree about how.
+///
+/// # Example
+///
+/// ```
+/// use retriever::prelude::*;
+/// use retriever::queries::normalized_index::{NormalizedIndex, Normalizer};
+///
+/// // Users chunked by team, keyed by id, with an email address.
+/// type User = (u64, u64, String);
+/// let mut storage : Storage<u64, u64, User> = Storage::new();
+/// let by_email : NormalizedIndex<u64, User> = NormalizedIndex::new(
+///   &storage,
+///   Normalizer::default().trim().case_fold(),
+///   |x: &User| x.2.as_str());
+///
+/// storage.add((1, 1, String::from("Ann@Example.com")));
+/// storage.add((1, 2, String::from(" bob@example.com")));
+/// storage.add((2, 3, String::from("ann@example.com ")));
+///
+/// let mut ids : Vec<u64> = storage
+///   .query(Everything.matching_normalized(&by_email, "ANN@example.COM"))
+///   .map(|x| x.1)
+///   .collect();
+/// ids.sort();
+/// assert_eq!(vec![1, 3], ids);
+///
+/// assert_eq!(1, storage.query(Everything.matching_normalized(&by_email, "bob@EXAMPLE.com")).count());
+///
+/// # storage.validate();
+/// # by_email.validate(&storage);
+/// ```
+pub struct NormalizedIndex<ChunkKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+{
+    index: SecondaryIndex<ChunkKey, Element, Option<String>, str>,
+    normalizer: Normalizer,
+}
+
+impl<ChunkKey, Element> Clone for NormalizedIndex<ChunkKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+{
+    fn clone(&self) -> Self {
+        NormalizedIndex {
+            index: self.index.clone(),
+            normalizer: self.normalizer.clone(),
+        }
+    }
+}
+
+impl<ChunkKey, Element> NormalizedIndex<ChunkKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+{
+    /// Create a new NormalizedIndex of a storage, indexing each element under the normalized
+    /// form of the string produced by the given rule.
+    pub fn new<ItemKey, F>(
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        normalizer: Normalizer,
+        f: F,
+    ) -> Self
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        F: Fn(&Element) -> &str + Clone + Send + Sync + 'static,
+    {
+        let normalizer_in_rule = normalizer.clone();
+
+        NormalizedIndex {
+            index: SecondaryIndex::new(storage, move |element: &Element| {
+                Cow::Owned(Some(normalizer_in_rule.normalize(f(element))))
+            }),
+            normalizer,
+        }
+    }
+
+    /// The normalizer of this index.
+    pub fn normalizer(&self) -> &Normalizer {
+        &self.normalizer
+    }
+
+    /// This same index, for matching against a string that has already been normalized using
+    /// `Query::matching`.
+    pub fn as_secondary_index(&self) -> &SecondaryIndex<ChunkKey, Element, Option<String>, str> {
+        &self.index
+    }
+
+    /// Panic if this storage is malformed or broken in any detectable way.
+    /// This is a slow operation and you shouldn't use it unless you suspect a problem.
+    pub fn validate<ItemKey>(&self, parent: &Storage<ChunkKey, ItemKey, Element>)
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        self.index.validate(parent);
+    }
+}
+
+impl<ChunkKey, Element> MemoryUser for NormalizedIndex<ChunkKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+{
+    fn memory_usage(&self) -> MemoryUsage {
+        self.index.memory_usage()
+    }
+
+    fn shrink_with<F: Fn(&MemoryUsage) -> Option<usize>>(&mut self, f: F) {
+        self.index.shrink_with(f)
+    }
+}
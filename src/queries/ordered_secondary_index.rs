@@ -0,0 +1,425 @@
+use crate::bits::Bitset;
+use crate::idxsets::intersection::Intersection;
+use crate::internal::bounds::borrow_bound;
+use crate::internal::merge::KWayMerge;
+use crate::internal::type_name::short_type_name;
+use crate::queries::secondary_index::{KeySet, SecondaryIndex};
+use crate::traits::idxset::IdxSet;
+use crate::traits::memory_usage::{MemoryUsage, MemoryUser};
+use crate::traits::query::Query;
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::chunk_storage::ChunkStorage;
+use crate::types::storage::Storage;
+use std::borrow::Borrow;
+use std::borrow::Cow;
+use std::fmt::Debug;
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
+
+/// A `SecondaryIndex` that also keeps the index keys of each chunk in order, so that it can be
+/// matched against a range of index keys using `Query::matching_range`, as well as against
+/// a single index key using `Query::matching` on `OrderedSecondaryIndex::as_secondary_index`.
+///
+/// Keeping the index keys in order costs some extra memory and indexing time, so prefer a
+/// plain `SecondaryIndex` unless you need range matching.
+///
+/// # Type Parameters
+///
+/// The type parameters are the same as those of `SecondaryIndex`, except that `IndexKey`
+/// must also be ordered.
+///
+/// # Example
+///
+/// ```
+/// use retriever::prelude::*;
+/// use std::borrow::Cow;
+///
+/// // Products chunked by category, keyed by SKU, and indexed by price.
+/// let mut storage : Storage<&'static str, u64, (&'static str, u64, u64)> = Storage::new();
+/// let by_price : OrderedSecondaryIndex<&'static str, (&'static str, u64, u64), Option<u64>, u64> =
+///   OrderedSecondaryIndex::new(&storage, |x: &(&'static str, u64, u64)| Cow::Owned(Some(x.2)));
+///
+/// storage.add(("books", 1, 8));
+/// storage.add(("books", 2, 12));
+/// storage.add(("games", 3, 20));
+/// storage.add(("games", 4, 15));
+/// storage.add(("games", 5, 60));
+///
+/// let mut skus : Vec<u64> = storage
+///   .query(Everything.matching_range(&by_price, 10..=20))
+///   .map(|x| x.1)
+///   .collect();
+///
+/// skus.sort();
+/// assert_eq!(vec![2, 3, 4], skus);
+///
+/// assert_eq!(1, storage.query(Everything.matching_range(&by_price, ..10)).count());
+/// assert_eq!(
+///   1,
+///   storage.query(Everything.matching(by_price.as_secondary_index(), Cow::Owned(60))).count()
+/// );
+///
+/// # storage.validate();
+/// # by_price.validate(&storage);
+/// ```
+pub struct OrderedSecondaryIndex<ChunkKey, Element, IndexKeys, IndexKey>(
+    SecondaryIndex<ChunkKey, Element, IndexKeys, IndexKey>,
+)
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>;
+
+impl<ChunkKey, Element, IndexKeys, IndexKey> Clone
+    for OrderedSecondaryIndex<ChunkKey, Element, IndexKeys, IndexKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+{
+    fn clone(&self) -> Self {
+        OrderedSecondaryIndex(self.0.clone())
+    }
+}
+
+impl<ChunkKey, Element, IndexKeys, IndexKey>
+    OrderedSecondaryIndex<ChunkKey, Element, IndexKeys, IndexKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+{
+    /// Create a new OrderedSecondaryIndex of a storage. See `SecondaryIndex::new`.
+    pub fn new<ItemKey, F>(storage: &Storage<ChunkKey, ItemKey, Element>, f: F) -> Self
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        F: Fn(&Element) -> Cow<IndexKeys> + Clone + Send + Sync + 'static,
+    {
+        OrderedSecondaryIndex(SecondaryIndex::new_impl(storage, None, true, false, f))
+    }
+
+    /// Create a new OrderedSecondaryIndex that only indexes chunks whose chunk key satisfies the
+    /// given predicate. See `SecondaryIndex::new_scoped`.
+    pub fn new_scoped<ItemKey, S, F>(
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        chunk_scope: S,
+        f: F,
+    ) -> Self
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        S: Fn(&ChunkKey) -> bool + Send + Sync + 'static,
+        F: Fn(&Element) -> Cow<IndexKeys> + Clone + Send + Sync + 'static,
+    {
+        OrderedSecondaryIndex(SecondaryIndex::new_impl(
+            storage,
+            Some(Arc::new(chunk_scope)),
+            true,
+            false,
+            f,
+        ))
+    }
+
+    /// This same index, for matching against a single index key using `Query::matching`.
+    pub fn as_secondary_index(&self) -> &SecondaryIndex<ChunkKey, Element, IndexKeys, IndexKey> {
+        &self.0
+    }
+
+    /// The least index key of any element of the storage, or `None` if no element has any index
+    /// key. Only the first key of each chunk is examined.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    /// use std::borrow::Cow;
+    ///
+    /// // Exam results chunked by class, keyed by student, with a score.
+    /// let mut storage : Storage<u64, u64, (u64, u64, u32)> = Storage::new();
+    /// let by_score : OrderedSecondaryIndex<u64, (u64, u64, u32), Option<u32>, u32> =
+    ///   OrderedSecondaryIndex::new(&storage, |x: &(u64, u64, u32)| Cow::Owned(Some(x.2)));
+    ///
+    /// assert_eq!(None, by_score.min_key(&storage));
+    ///
+    /// storage.add((1, 1, 72));
+    /// storage.add((1, 2, 95));
+    /// storage.add((2, 3, 64));
+    /// storage.add((2, 4, 91));
+    ///
+    /// assert_eq!(Some(64), by_score.min_key(&storage));
+    /// assert_eq!(Some(95), by_score.max_key(&storage));
+    ///
+    /// let ranking : Vec<u64> = by_score
+    ///   .query_in_order(&storage, Everything)
+    ///   .map(|x| x.1)
+    ///   .collect();
+    /// assert_eq!(vec![3, 1, 4, 2], ranking);
+    ///
+    /// let top : Vec<u64> = by_score
+    ///   .query_in_order(&storage, Everything.matching_range(&by_score, 90..))
+    ///   .map(|x| x.1)
+    ///   .collect();
+    /// assert_eq!(vec![4, 2], top);
+    ///
+    /// # storage.validate();
+    /// # by_score.validate(&storage);
+    /// ```
+    pub fn min_key<ItemKey>(
+        &self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+    ) -> Option<IndexKey::Owned>
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        self.0.rebuild(storage);
+        self.0.extreme_ordered_key(false)
+    }
+
+    /// The greatest index key of any element of the storage, or `None` if no element has any
+    /// index key. See `OrderedSecondaryIndex::min_key`.
+    pub fn max_key<ItemKey>(
+        &self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+    ) -> Option<IndexKey::Owned>
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        self.0.rebuild(storage);
+        self.0.extreme_ordered_key(true)
+    }
+
+    /// Iterate over the elements matching some Query in ascending order of their index keys.
+    /// An element is visited once for each of its index keys, and not at all if it has none.
+    /// Elements with equal index keys are visited in the order `Storage::query` would visit them.
+    ///
+    /// Each chunk's index keys are already in order, so nothing is sorted. However, the matching
+    /// elements of every chunk, each with a copy of its index key, are collected up front, before
+    /// the first element is returned; only the merge of those runs happens as the iterator
+    /// advances. So taking just the first few elements still costs a visit to every matching
+    /// element. See `OrderedSecondaryIndex::min_key` for an example.
+    pub fn query_in_order<'a, ItemKey, Q>(
+        &self,
+        storage: &'a Storage<ChunkKey, ItemKey, Element>,
+        query: Q,
+    ) -> impl Iterator<Item = &'a Element> + 'a
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        IndexKey::Owned: 'a,
+        Q: Query<ChunkKey, ItemKey, Element>,
+    {
+        let chunk_idxs = query.chunk_idxs(storage);
+        self.0.refresh(storage, &chunk_idxs);
+
+        let runs: Vec<Vec<(IndexKey::Owned, &'a Element)>> = chunk_idxs
+            .into_idx_iter()
+            .flatten()
+            .filter_map(|idx| {
+                let chunk_storage = &storage.internal_rvec()[idx];
+                let item_idxs = query.item_idxs(chunk_storage.chunk_key(), chunk_storage);
+                let postings = self.0.ordered_postings(chunk_storage.chunk_key())?;
+                let mut run = Vec::new();
+
+                for (index_key, idxs) in postings {
+                    for bitfield in idxs.iter() {
+                        for item_idx in item_idxs.intersect(&bitfield) {
+                            let element = chunk_storage.get_idx(item_idx);
+                            if query.test(element) {
+                                run.push((index_key.clone(), element));
+                            }
+                        }
+                    }
+                }
+
+                Some(run)
+            })
+            .filter(|run| !run.is_empty())
+            .collect();
+
+        KWayMerge::new(runs, |(index_key, _): &(IndexKey::Owned, &'a Element)| {
+            index_key.clone()
+        })
+        .map(|(_, element)| element)
+    }
+
+    /// Panic if this storage is malformed or broken in any detectable way.
+    /// This is a slow operation and you shouldn't use it unless you suspect a problem.
+    pub fn validate<ItemKey>(&self, parent: &Storage<ChunkKey, ItemKey, Element>)
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        self.0.validate(parent);
+    }
+}
+
+impl<ChunkKey, Element, IndexKeys, IndexKey> MemoryUser
+    for OrderedSecondaryIndex<ChunkKey, Element, IndexKeys, IndexKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+{
+    fn memory_usage(&self) -> MemoryUsage {
+        self.0.memory_usage()
+    }
+
+    fn shrink_with<F: Fn(&MemoryUsage) -> Option<usize>>(&mut self, f: F) {
+        self.0.shrink_with(f)
+    }
+}
+
+/// A Query matching a range of index keys against an `OrderedSecondaryIndex`.
+/// Construct using `Query::matching_range`.
+///
+/// # Type Parameters
+///
+/// * `Q`: A `Query`.
+/// * `ChunkKey`: Chunk key of the backing `Storage`.
+/// * `Element`: Element of the backing `Storage`.
+/// * `IndexKeys`: The collection of index keys of the backing `OrderedSecondaryIndex`.
+/// * `IndexKey`: The indexing key of the backing `OrderedSecondaryIndex`.
+/// * `K`: The type of the bounds of the range, which can be borrowed as an `IndexKey`.
+///
+pub struct MatchingRange<Q, ChunkKey, Element, IndexKeys, IndexKey, K>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+{
+    query: Q,
+    secondary_index: SecondaryIndex<ChunkKey, Element, IndexKeys, IndexKey>,
+    start: Bound<K>,
+    end: Bound<K>,
+}
+
+impl<Q, ChunkKey, Element, IndexKeys, IndexKey, K> Clone
+    for MatchingRange<Q, ChunkKey, Element, IndexKeys, IndexKey, K>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+    Q: Clone,
+    K: Clone,
+{
+    fn clone(&self) -> Self {
+        MatchingRange {
+            query: self.query.clone(),
+            secondary_index: self.secondary_index.clone(),
+            start: self.start.clone(),
+            end: self.end.clone(),
+        }
+    }
+}
+
+impl<Q, ChunkKey, Element, IndexKeys, IndexKey, K>
+    MatchingRange<Q, ChunkKey, Element, IndexKeys, IndexKey, K>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+    K: Clone,
+{
+    pub(crate) fn new<R>(
+        query: Q,
+        secondary_index: &OrderedSecondaryIndex<ChunkKey, Element, IndexKeys, IndexKey>,
+        range: R,
+    ) -> Self
+    where
+        R: RangeBounds<K>,
+    {
+        MatchingRange {
+            query,
+            secondary_index: secondary_index.0.clone(),
+            start: range.start_bound().cloned(),
+            end: range.end_bound().cloned(),
+        }
+    }
+}
+
+impl<Q, ChunkKey, ItemKey, Element, IndexKeys, IndexKey, K> Query<ChunkKey, ItemKey, Element>
+    for MatchingRange<Q, ChunkKey, Element, IndexKeys, IndexKey, K>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+    Q: Query<ChunkKey, ItemKey, Element> + Clone,
+    K: Clone + Borrow<IndexKey>,
+{
+    type ChunkIdxSet = Q::ChunkIdxSet;
+    type ItemIdxSet = Intersection<Q::ItemIdxSet, Option<Bitset>>;
+
+    fn chunk_idxs(&self, storage: &Storage<ChunkKey, ItemKey, Element>) -> Self::ChunkIdxSet {
+        let result = self.query.chunk_idxs(storage);
+        self.secondary_index.refresh(storage, &result);
+        result
+    }
+
+    fn item_idxs(
+        &self,
+        chunk_key: &ChunkKey,
+        chunk_storage: &ChunkStorage<ChunkKey, ItemKey, Element>,
+    ) -> Self::ItemIdxSet {
+        let parent_idxs = self.query.item_idxs(chunk_key, chunk_storage);
+        let ours_idxs = self.secondary_index.idxs_in_range(
+            chunk_key,
+            borrow_bound(self.start.as_ref()),
+            borrow_bound(self.end.as_ref()),
+        );
+
+        IdxSet::intersection(parent_idxs, ours_idxs)
+    }
+
+    fn test(&self, element: &Element) -> bool {
+        self.query.test(element)
+    }
+
+    fn test_chunk(&self, chunk_key: &ChunkKey) -> bool {
+        self.query.test_chunk(chunk_key)
+    }
+
+    fn is_exact(&self) -> bool {
+        self.query.is_exact()
+    }
+
+    fn is_chunk_local(&self) -> bool {
+        self.query.is_chunk_local()
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "MatchingRange({}, OrderedSecondaryIndex<{}>)",
+            self.query.describe(),
+            short_type_name::<IndexKey>()
+        )
+    }
+}
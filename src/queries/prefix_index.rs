@@ -0,0 +1,561 @@
+use crate::bits::Bitset;
+use crate::idxsets::idxrange::IdxRange;
+use crate::idxsets::intersection::Intersection;
+use crate::internal::mr::rvec::RVec;
+use crate::internal::mr::summarize::{Summarize, SummaryRules};
+use crate::traits::idxset::IdxSet;
+use crate::traits::memory_usage::{MemoryUsage, MemoryUser};
+use crate::traits::query::Query;
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::chunk_storage::ChunkStorage;
+use crate::types::storage::Storage;
+use crate::types::storage_builder::Strictness;
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::sync::RwLock;
+
+/// An index of the elements of a `Storage` by a hierarchical path, such as the segments of a
+/// file path `a/b/c`, which can be matched against any prefix of a path using
+/// `Query::matching_prefix`, and which can count the elements under any prefix.
+///
+/// Each chunk keeps a tree of path segments, so elements that share a prefix share the nodes of
+/// that prefix. Each node knows every element at or below it, so matching or counting a prefix
+/// only has to walk down to that prefix, no matter how many elements are beneath it.
+///
+/// # Type Parameters
+///
+/// * `ChunkKey`: The chunk key type of the `Storage`.
+/// * `Element`: The element type of the `Storage`.
+/// * `Segment`: The type of each segment of a path.
+///
+/// # Example
+///
+/// ```
+/// use retriever::prelude::*;
+/// use retriever::queries::prefix_index::PrefixIndex;
+///
+/// // Files chunked by volume, keyed by inode, with a path.
+/// type File = (u64, u64, &'static str);
+/// let mut storage : Storage<u64, u64, File> = Storage::new();
+/// let by_path : PrefixIndex<u64, File, String> =
+///   PrefixIndex::paths(&storage, '/', |x: &File| x.2);
+///
+/// storage.add((1, 1, "src/lib.rs"));
+/// storage.add((1, 2, "src/queries/mod.rs"));
+/// storage.add((1, 3, "src/queries/prefix_index.rs"));
+/// storage.add((2, 4, "src/types/mod.rs"));
+/// storage.add((2, 5, "README.md"));
+///
+/// let queries = Everything.matching_prefix(&by_path, vec!["src", "queries"]);
+/// assert_eq!(2, storage.query(queries).count());
+///
+/// assert_eq!(4, by_path.count(&storage, vec!["src"]));
+/// assert_eq!(5, by_path.count(&storage, Vec::<String>::new()));
+///
+/// let children = by_path.children(&storage, vec!["src"]);
+/// assert_eq!(Some(&2), children.get("queries"));
+/// assert_eq!(Some(&1), children.get("types"));
+///
+/// # storage.validate();
+/// # by_path.validate(&storage);
+/// ```
+pub struct PrefixIndex<ChunkKey, Element, Segment>(
+    Arc<RwLock<PrefixIndexImpl<ChunkKey, Element, Segment>>>,
+)
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    Segment: ValidKey;
+
+impl<ChunkKey, Element, Segment> Clone for PrefixIndex<ChunkKey, Element, Segment>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    Segment: ValidKey,
+{
+    fn clone(&self) -> Self {
+        PrefixIndex(Arc::clone(&self.0))
+    }
+}
+
+type ChunkPaths<Element, Segment> = Summarize<Element, Option<Vec<Segment>>, PathTrie<Segment>>;
+
+// A node of the tree of paths of one chunk.
+struct PathTrie<Segment> {
+    // the internal indices of every element at or below this node
+    idxs: Bitset,
+    // the nodes one segment below this one
+    children: BTreeMap<Segment, PathTrie<Segment>>,
+}
+
+impl<Segment> Default for PathTrie<Segment> {
+    fn default() -> Self {
+        PathTrie {
+            idxs: Bitset::default(),
+            children: BTreeMap::new(),
+        }
+    }
+}
+
+struct PrefixIndexImpl<ChunkKey, Element, Segment>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    Segment: ValidKey,
+{
+    // parent_id, used to see that this PrefixIndex isn't suddenly used with a different parent storage
+    parent_id: u64,
+    // gc_chunk_list, remember the chunks from our last update, so we can remove trees for newly-absent chunks
+    gc_chunk_list: RVec<Option<ChunkKey::Owned>>,
+    // rule for constructing the path of each element
+    rules: Arc<SummaryRules<Element, Option<Vec<Segment>>, PathTrie<Segment>>>,
+    // the tree of paths of each chunk
+    index:
+        HashMap<ChunkKey::Owned, ChunkPaths<Element, Segment>, crate::internal::hasher::HasherImpl>,
+}
+
+impl<ChunkKey, Element, Segment> PrefixIndex<ChunkKey, Element, Segment>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    Segment: ValidKey,
+{
+    /// Create a new PrefixIndex of a storage, indexing each element under the path of segments
+    /// produced by the given rule. An element with an empty path is only matched by the empty
+    /// prefix.
+    pub fn new<ItemKey, F>(storage: &Storage<ChunkKey, ItemKey, Element>, f: F) -> Self
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        F: Fn(&Element) -> Vec<Segment> + Send + Sync + 'static,
+    {
+        PrefixIndex(Arc::new(RwLock::new(PrefixIndexImpl {
+            parent_id: storage.id(),
+            gc_chunk_list: RVec::default(),
+            rules: Arc::new(PrefixIndexImpl::<ChunkKey, Element, Segment>::path_rules(f)),
+            index: HashMap::with_hasher(crate::internal::hasher::HasherImpl::default()),
+        })))
+    }
+
+    /// The number of elements whose path starts with the given prefix.
+    pub fn count<ItemKey, I>(
+        &self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        prefix: I,
+    ) -> usize
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        I: IntoIterator,
+        I::Item: Into<Segment>,
+    {
+        let prefix: Vec<Segment> = prefix.into_iter().map(Into::into).collect();
+        let mut prefix_index_impl = self.0.write().unwrap();
+        prefix_index_impl.refresh(storage, &IdxRange(0..storage.internal_rvec().len()));
+
+        prefix_index_impl
+            .index
+            .values()
+            .filter_map(|summarize| summarize.peek().find(&prefix))
+            .map(|node| node.idxs.len())
+            .sum()
+    }
+
+    /// Each segment that follows the given prefix, with the number of elements whose path starts
+    /// with the prefix followed by that segment.
+    pub fn children<ItemKey, I>(
+        &self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        prefix: I,
+    ) -> BTreeMap<Segment, usize>
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        I: IntoIterator,
+        I::Item: Into<Segment>,
+    {
+        let prefix: Vec<Segment> = prefix.into_iter().map(Into::into).collect();
+        let mut prefix_index_impl = self.0.write().unwrap();
+        prefix_index_impl.refresh(storage, &IdxRange(0..storage.internal_rvec().len()));
+
+        let mut result = BTreeMap::new();
+        for node in prefix_index_impl
+            .index
+            .values()
+            .filter_map(|summarize| summarize.peek().find(&prefix))
+        {
+            for (segment, child) in node.children.iter() {
+                *result.entry(segment.clone()).or_insert(0) += child.idxs.len();
+            }
+        }
+
+        result
+    }
+
+    /// Panic if this storage is malformed or broken in any detectable way.
+    /// This is a slow operation and you shouldn't use it unless you suspect a problem.
+    pub fn validate<ItemKey>(&self, parent: &Storage<ChunkKey, ItemKey, Element>)
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        let mut prefix_index_impl = self.0.write().unwrap();
+        prefix_index_impl.refresh(parent, &IdxRange(0..parent.internal_rvec().len()));
+
+        for chunk_key in prefix_index_impl.index.keys() {
+            assert!(parent.internal_idx_of(chunk_key.borrow()).is_some());
+        }
+
+        for chunk_storage in parent.internal_rvec().iter() {
+            let summarize = &prefix_index_impl.index[chunk_storage.chunk_key()];
+            let trie = summarize.peek();
+            trie.validate();
+            assert_eq!(chunk_storage.len(), trie.idxs.len());
+
+            for (idx, path) in summarize.tokens().iter().enumerate() {
+                let path = path.as_ref().expect("every element should have a path");
+                let node = trie.find(path).expect("a PrefixIndex lost a path");
+                assert!(node.idxs.get(idx), "a PrefixIndex lost an element");
+            }
+        }
+    }
+
+    fn refresh<ItemKey, I>(&self, storage: &Storage<ChunkKey, ItemKey, Element>, idxs: &I)
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        I: IdxSet,
+    {
+        self.0.write().unwrap().refresh(storage, idxs);
+    }
+}
+
+impl<ChunkKey, Element> PrefixIndex<ChunkKey, Element, String>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+{
+    /// Create a new PrefixIndex of a storage, indexing each element under a string field split
+    /// on the given separator, such as `'/'` for file paths or `'.'` for domain-style names.
+    /// Empty segments are skipped, so `"/a//b/"` has the same path as `"a/b"`.
+    pub fn paths<ItemKey, F>(
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        separator: char,
+        f: F,
+    ) -> Self
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        F: Fn(&Element) -> &str + Send + Sync + 'static,
+    {
+        Self::new(storage, move |element: &Element| {
+            f(element)
+                .split(separator)
+                .filter(|segment| !segment.is_empty())
+                .map(String::from)
+                .collect()
+        })
+    }
+}
+
+impl<ChunkKey, Element, Segment> PrefixIndexImpl<ChunkKey, Element, Segment>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    Segment: ValidKey,
+{
+    fn path_rules<F>(f: F) -> SummaryRules<Element, Option<Vec<Segment>>, PathTrie<Segment>>
+    where
+        F: Fn(&Element) -> Vec<Segment> + Send + Sync + 'static,
+    {
+        SummaryRules {
+            map: Arc::new(move |element, old_path, _internal_idx| {
+                let new_path = Some(f(element));
+
+                if old_path != &new_path {
+                    Some(new_path)
+                } else {
+                    None
+                }
+            }),
+            contribute: Arc::new(|new_path, internal_idx, trie| {
+                if let Some(path) = new_path {
+                    trie.insert(path, internal_idx);
+                }
+            }),
+            uncontribute: Arc::new(|old_path, internal_idx, trie| {
+                if let Some(path) = old_path {
+                    trie.remove(path, internal_idx);
+                }
+            }),
+        }
+    }
+
+    fn refresh<ItemKey, I>(&mut self, storage: &Storage<ChunkKey, ItemKey, Element>, idxs: &I)
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        I: IdxSet,
+    {
+        if self.parent_id != storage.id() {
+            assert_eq!(storage.strictness(), Strictness::Repair, "Id mismatch: a prefix index may only be used with it's parent Storage, never any other Storage");
+            #[cfg(feature = "log")]
+            log::warn!(
+                "retriever: repaired prefix index used with a different Storage by rebuilding it"
+            );
+            self.parent_id = storage.id();
+            self.gc_chunk_list = RVec::default();
+            self.index.clear();
+        }
+
+        storage.gc(&mut self.gc_chunk_list, &mut self.index);
+
+        for idx in idxs.clone().into_idx_iter().flatten() {
+            let chunk_storage = &storage.internal_rvec()[idx];
+            let internal_storage = chunk_storage.internal_rvec();
+            let rules = &self.rules;
+            self.index
+                .entry(chunk_storage.chunk_key().to_owned())
+                .or_insert_with(|| Summarize::new(internal_storage, Arc::clone(rules)))
+                .update(internal_storage);
+        }
+    }
+}
+
+impl<Segment> PathTrie<Segment>
+where
+    Segment: ValidKey,
+{
+    fn insert(&mut self, path: &[Segment], idx: usize) {
+        self.idxs.set(idx);
+
+        if let Some((first, rest)) = path.split_first() {
+            self.children
+                .entry(first.clone())
+                .or_default()
+                .insert(rest, idx);
+        }
+    }
+
+    fn remove(&mut self, path: &[Segment], idx: usize) {
+        self.idxs.unset(idx);
+
+        if let Some((first, rest)) = path.split_first() {
+            if let Some(child) = self.children.get_mut(first) {
+                child.remove(rest, idx);
+                if child.idxs.is_empty() {
+                    self.children.remove(first);
+                }
+            }
+        }
+    }
+
+    // The node at the given prefix, if any element has that prefix.
+    fn find(&self, prefix: &[Segment]) -> Option<&Self> {
+        match prefix.split_first() {
+            Some((first, rest)) => self.children.get(first)?.find(rest),
+            None => Some(self),
+        }
+    }
+
+    fn validate(&self) {
+        for child in self.children.values() {
+            assert!(!child.idxs.is_empty(), "a PrefixIndex kept an empty node");
+            assert_eq!(
+                child.idxs.len(),
+                child.idxs.intersect_bitset(&self.idxs).len(),
+                "a PrefixIndex lost an element"
+            );
+            child.validate();
+        }
+    }
+}
+
+impl<Segment> MemoryUser for PathTrie<Segment> {
+    fn memory_usage(&self) -> MemoryUsage {
+        let mut result = MemoryUsage::merge(
+            self.idxs.memory_usage(),
+            MemoryUsage {
+                size_of: None,
+                len: self.children.len(),
+                capacity: self.children.len(),
+            },
+        );
+
+        for child in self.children.values() {
+            result = MemoryUsage::merge(result, child.memory_usage());
+        }
+
+        result
+    }
+
+    fn shrink_with<F: Fn(&MemoryUsage) -> Option<usize>>(&mut self, f: F) {
+        self.idxs.shrink_with(&f);
+
+        for child in self.children.values_mut() {
+            child.shrink_with(&f);
+        }
+    }
+}
+
+impl<ChunkKey, Element, Segment> MemoryUser for PrefixIndexImpl<ChunkKey, Element, Segment>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    Segment: ValidKey,
+{
+    fn memory_usage(&self) -> MemoryUsage {
+        let mut result = self.gc_chunk_list.memory_usage();
+        result = MemoryUsage::merge(result, self.index.memory_usage());
+
+        for s in self.index.values() {
+            result = MemoryUsage::merge(result, s.memory_usage());
+            result = MemoryUsage::merge(result, s.peek().memory_usage());
+        }
+
+        result
+    }
+
+    fn shrink_with<F: Fn(&MemoryUsage) -> Option<usize>>(&mut self, f: F) {
+        self.gc_chunk_list.shrink_with(&f);
+        self.index.shrink_with(&f);
+
+        for i in self.index.values_mut() {
+            i.shrink_with(&f);
+            i.peek_mut().shrink_with(&f);
+        }
+    }
+}
+
+impl<ChunkKey, Element, Segment> MemoryUser for PrefixIndex<ChunkKey, Element, Segment>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    Segment: ValidKey,
+{
+    fn memory_usage(&self) -> MemoryUsage {
+        self.0.read().unwrap().memory_usage()
+    }
+
+    fn shrink_with<F: Fn(&MemoryUsage) -> Option<usize>>(&mut self, f: F) {
+        self.0.write().unwrap().shrink_with(f)
+    }
+}
+
+/// A Query matching the elements whose path starts with a prefix, using a `PrefixIndex`.
+/// Construct using `Query::matching_prefix`.
+pub struct MatchingPrefix<Q, ChunkKey, Element, Segment>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    Segment: ValidKey,
+{
+    query: Q,
+    prefix_index: PrefixIndex<ChunkKey, Element, Segment>,
+    prefix: Vec<Segment>,
+}
+
+impl<Q, ChunkKey, Element, Segment> Clone for MatchingPrefix<Q, ChunkKey, Element, Segment>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    Segment: ValidKey,
+    Q: Clone,
+{
+    fn clone(&self) -> Self {
+        MatchingPrefix {
+            query: self.query.clone(),
+            prefix_index: self.prefix_index.clone(),
+            prefix: self.prefix.clone(),
+        }
+    }
+}
+
+impl<Q, ChunkKey, Element, Segment> MatchingPrefix<Q, ChunkKey, Element, Segment>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    Segment: ValidKey,
+{
+    pub(crate) fn new(
+        query: Q,
+        prefix_index: &PrefixIndex<ChunkKey, Element, Segment>,
+        prefix: Vec<Segment>,
+    ) -> Self {
+        MatchingPrefix {
+            query,
+            prefix_index: prefix_index.clone(),
+            prefix,
+        }
+    }
+}
+
+impl<Q, ChunkKey, ItemKey, Element, Segment> Query<ChunkKey, ItemKey, Element>
+    for MatchingPrefix<Q, ChunkKey, Element, Segment>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+    Segment: ValidKey,
+    Q: Query<ChunkKey, ItemKey, Element> + Clone,
+{
+    type ChunkIdxSet = Q::ChunkIdxSet;
+    type ItemIdxSet = Intersection<Q::ItemIdxSet, Option<Bitset>>;
+
+    fn chunk_idxs(&self, storage: &Storage<ChunkKey, ItemKey, Element>) -> Self::ChunkIdxSet {
+        let result = self.query.chunk_idxs(storage);
+        self.prefix_index.refresh(storage, &result);
+        result
+    }
+
+    fn item_idxs(
+        &self,
+        chunk_key: &ChunkKey,
+        chunk_storage: &ChunkStorage<ChunkKey, ItemKey, Element>,
+    ) -> Self::ItemIdxSet {
+        let prefix_index_impl = self.prefix_index.0.read().unwrap();
+        let parent_idxs = self.query.item_idxs(chunk_key, chunk_storage);
+        let ours_idxs: Option<Bitset> = prefix_index_impl
+            .index
+            .get(chunk_key)
+            .and_then(|summarize| summarize.peek().find(&self.prefix))
+            .map(|node| node.idxs.clone());
+
+        IdxSet::intersection(parent_idxs, ours_idxs)
+    }
+
+    fn test(&self, element: &Element) -> bool {
+        self.query.test(element)
+    }
+
+    fn test_chunk(&self, chunk_key: &ChunkKey) -> bool {
+        self.query.test_chunk(chunk_key)
+    }
+
+    fn is_exact(&self) -> bool {
+        self.query.is_exact()
+    }
+
+    fn is_chunk_local(&self) -> bool {
+        self.query.is_chunk_local()
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "MatchingPrefix({}, PrefixIndex, {:?})",
+            self.query.describe(),
+            self.prefix
+        )
+    }
+}
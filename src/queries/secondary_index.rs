@@ -1,7 +1,11 @@
 use crate::bits::Bitset;
+use crate::idxsets::difference::Difference;
+use crate::idxsets::idxrange::IdxRange;
 use crate::idxsets::intersection::Intersection;
+use crate::internal::bounds::is_valid_range;
 use crate::internal::mr::rvec::RVec;
 use crate::internal::mr::summarize::{Summarize, SummaryRules};
+use crate::internal::type_name::short_type_name;
 use crate::traits::idxset::IdxSet;
 use crate::traits::memory_usage::MemoryUsage;
 use crate::traits::memory_usage::MemoryUser;
@@ -9,13 +13,21 @@ use crate::traits::query::Query;
 use crate::traits::record::Record;
 use crate::traits::valid_key::{BorrowedKey, ValidKey};
 use crate::types::chunk_storage::ChunkStorage;
+use crate::types::id::Id;
+#[cfg(feature = "serde")]
+use crate::types::saved_index::{fingerprint, SavedChunkIndex, SavedIndex};
 use crate::types::storage::Storage;
+use crate::types::storage_builder::Strictness;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
 use std::borrow::Borrow;
 use std::borrow::Cow;
 use std::collections::HashMap;
-use std::collections::{BTreeSet, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::fmt::Debug;
-use std::iter::Map;
+use std::hash::Hash;
+use std::iter::{FromIterator, Map};
+use std::ops::Bound;
 use std::sync::Arc;
 use std::sync::RwLock;
 
@@ -69,6 +81,90 @@ where
     IndexKey::Owned: ValidKey,
 {
     reverse_index: HashMap<IndexKey::Owned, Bitset>,
+    // the keys of reverse_index in order, only maintained by an OrderedSecondaryIndex
+    ordered_keys: Option<BTreeSet<IndexKey::Owned>>,
+    // keys added to or removed from reverse_index since they were last collected, only
+    // recorded by a UniqueIndex
+    changed_keys: Vec<IndexKey::Owned>,
+}
+
+/// Statistics about the index keys of a `SecondaryIndex`. See `SecondaryIndex::stats`.
+#[derive(Clone, Debug)]
+pub struct IndexStats<IndexKey> {
+    /// The number of distinct index keys.
+    pub distinct_keys: usize,
+    /// The total number of (index key, element) pairs. This is more than the number of indexed
+    /// elements if some elements have more than one index key.
+    pub postings: usize,
+    /// The number of elements of every chunk covered by the index, whether or not they have any
+    /// index key.
+    pub elements: usize,
+    /// The number of elements with each index key.
+    pub counts: HashMap<IndexKey, usize>,
+}
+
+impl<IndexKey> IndexStats<IndexKey>
+where
+    IndexKey: Eq + Hash,
+{
+    /// The index key with the most elements and its count, if there are any index keys.
+    /// Ties are broken arbitrarily.
+    pub fn most_common(&self) -> Option<(&IndexKey, usize)> {
+        self.counts
+            .iter()
+            .max_by_key(|(_, count)| **count)
+            .map(|(index_key, count)| (index_key, *count))
+    }
+
+    /// The fraction of covered elements that have the given index key, from 0.0 to 1.0.
+    pub fn selectivity<Q>(&self, index_key: &Q) -> f64
+    where
+        IndexKey: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        match self.elements {
+            0 => 0.0,
+            elements => self.counts.get(index_key).cloned().unwrap_or(0) as f64 / elements as f64,
+        }
+    }
+}
+
+/// A report of how well a `SecondaryIndex` agrees with its `Storage`. See
+/// `SecondaryIndex::validate_index`.
+///
+/// # Type Parameters
+///
+/// * `ChunkKey`: the owned chunk key of the `Storage`.
+/// * `ItemKey`: the owned item key of the `Storage`.
+/// * `IndexKey`: the owned index key of the `SecondaryIndex`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct IndexReport<ChunkKey, ItemKey, IndexKey> {
+    /// True IFF the index belongs to a different `Storage`, in which case nothing else was
+    /// checked.
+    pub wrong_storage: bool,
+    /// The number of chunks checked.
+    pub chunks: usize,
+    /// The number of elements checked.
+    pub elements: usize,
+    /// Chunks within the scope of the index that aren't indexed.
+    pub unindexed_chunks: Vec<ChunkKey>,
+    /// Index keys that point at a position past the end of their chunk, with that position.
+    pub dangling: Vec<(ChunkKey, IndexKey, usize)>,
+    /// Index keys that point at an element whose rule no longer yields that index key.
+    pub stale: Vec<(Id<ChunkKey, ItemKey>, IndexKey)>,
+    /// Index keys that the rule yields for an element, but that don't point at that element.
+    pub missing: Vec<(Id<ChunkKey, ItemKey>, IndexKey)>,
+}
+
+impl<ChunkKey, ItemKey, IndexKey> IndexReport<ChunkKey, ItemKey, IndexKey> {
+    /// True IFF no problem was found.
+    pub fn is_consistent(&self) -> bool {
+        !self.wrong_storage
+            && self.unindexed_chunks.is_empty()
+            && self.dangling.is_empty()
+            && self.stale.is_empty()
+            && self.missing.is_empty()
+    }
 }
 
 /// A secondary index of the records in a `Storage`. You can attach as many `SecondaryIndices`
@@ -90,6 +186,12 @@ where
 /// | Index automobiles by model year            | `Option<i32>`        | `i32`              |
 /// | Index artwork by dominant color            | `HashSet<Color>`     | `Color`            |
 ///
+/// # Sharing
+///
+/// Queries only need a shared reference to a `SecondaryIndex`. The index brings itself up to
+/// date behind a lock, so any number of read-only query paths, or threads, can query the same
+/// `Storage` through the same index, or through cheap clones of it.
+///
 /// # Panic
 ///
 /// A `SecondaryIndex` is associated with exactly one storage.
@@ -119,6 +221,8 @@ where
     }
 }
 
+type ChunkScope<ChunkKey> = Arc<dyn Fn(&ChunkKey) -> bool + Send + Sync + 'static>;
+
 struct SecondaryIndexImpl<ChunkKey, Element, IndexKeys, IndexKey>
 where
     ChunkKey: BorrowedKey + ?Sized,
@@ -133,14 +237,24 @@ where
     gc_chunk_list: RVec<Option<ChunkKey::Owned>>,
     // rule for constructing index keys
     rules: Arc<SummaryRules<Element, IndexKeys, ChunkSecondaryIndex<IndexKey>>>,
+    // optional rule deciding which chunks are indexed at all
+    chunk_scope: Option<ChunkScope<ChunkKey>>,
     // the index itself
     index: HashMap<
         ChunkKey::Owned,
         Summarize<Element, IndexKeys, ChunkSecondaryIndex<IndexKey>>,
         crate::internal::hasher::HasherImpl,
     >,
+    // every indexed chunk holding each index key, only maintained by a UniqueIndex
+    chunks_by_key: Option<ChunksByKey<ChunkKey, IndexKey>>,
 }
 
+type ChunksByKey<ChunkKey, IndexKey> = HashMap<
+    <IndexKey as ToOwned>::Owned,
+    Vec<<ChunkKey as ToOwned>::Owned>,
+    crate::internal::hasher::HasherImpl,
+>;
+
 impl<ChunkKey, Element, IndexKeys, IndexKey> SecondaryIndex<ChunkKey, Element, IndexKeys, IndexKey>
 where
     ChunkKey: BorrowedKey + ?Sized,
@@ -157,6 +271,180 @@ where
     /// Try to re-use `SecondaryIndices` as much as possible. If you drop a `SecondaryIndex` and then
     /// re-create it, the index has to be rebuilt, which might take a long time.
     pub fn new<ItemKey, F>(storage: &Storage<ChunkKey, ItemKey, Element>, f: F) -> Self
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        F: Fn(&Element) -> Cow<IndexKeys> + Clone + Send + Sync + 'static,
+    {
+        Self::new_impl(storage, None, false, false, f)
+    }
+
+    /// Create a new SecondaryIndex of a storage, indexing each element under every key yielded by
+    /// the given rule, such as the tags of a record. The keys are collected into `IndexKeys`, so
+    /// use a set type such as `HashSet` or `BTreeSet`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    /// use std::collections::HashSet;
+    ///
+    /// // Articles chunked by author, keyed by article id, with some tags.
+    /// type Article = (&'static str, u64, Vec<&'static str>);
+    /// let mut storage : Storage<&'static str, u64, Article> = Storage::new();
+    /// let by_tag : SecondaryIndex<&'static str, Article, HashSet<&'static str>, &'static str> =
+    ///   SecondaryIndex::new_multi(&storage, |x: &Article| x.2.clone());
+    ///
+    /// storage.add(("alice", 1, vec!["rust", "databases"]));
+    /// storage.add(("alice", 2, vec!["gardening"]));
+    /// storage.add(("bob", 3, vec!["rust", "games"]));
+    ///
+    /// let mut ids : Vec<u64> = storage
+    ///   .query(Everything.matching(&by_tag, std::borrow::Cow::Owned("rust")))
+    ///   .map(|x| x.1)
+    ///   .collect();
+    /// ids.sort();
+    /// assert_eq!(vec![1, 3], ids);
+    ///
+    /// # storage.validate();
+    /// # by_tag.validate(&storage);
+    /// ```
+    pub fn new_multi<ItemKey, F, I>(storage: &Storage<ChunkKey, ItemKey, Element>, f: F) -> Self
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        F: Fn(&Element) -> I + Clone + Send + Sync + 'static,
+        I: IntoIterator<Item = IndexKey::Owned>,
+        IndexKeys: FromIterator<IndexKey::Owned>,
+    {
+        Self::new(storage, move |element: &Element| {
+            Cow::Owned(f(element).into_iter().collect())
+        })
+    }
+
+    /// Create a new SecondaryIndex that only indexes chunks whose chunk key satisfies the given
+    /// predicate. Chunks that are out of scope consume no index memory, and a query matching
+    /// against this index will never visit any element of an out-of-scope chunk.
+    ///
+    /// The predicate is re-evaluated every time the index is used, so it may depend on external
+    /// state. When a chunk moves into scope its index is built, and when it moves out of scope
+    /// its index is discarded.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    /// use std::borrow::Cow;
+    /// use std::sync::Arc;
+    /// use std::sync::atomic::{AtomicU64, Ordering};
+    ///
+    /// // Chunk by year, and only index years that haven't been archived yet.
+    /// let mut storage : Storage<u64, u64, (u64, u64, &'static str)> = Storage::new();
+    /// let archived_before = Arc::new(AtomicU64::new(2020));
+    /// let archived_before_in_scope = Arc::clone(&archived_before);
+    ///
+    /// let by_status : SecondaryIndex<u64, (u64, u64, &'static str), Option<&'static str>, &'static str> =
+    ///   SecondaryIndex::new_scoped(
+    ///     &storage,
+    ///     move |year: &u64| *year >= archived_before_in_scope.load(Ordering::Relaxed),
+    ///     |x: &(u64, u64, &'static str)| Cow::Owned(Some(x.2)));
+    ///
+    /// storage.add((2019, 1, "failed"));
+    /// storage.add((2020, 2, "failed"));
+    /// storage.add((2021, 3, "failed"));
+    ///
+    /// assert_eq!(2, storage.query(Everything.matching(&by_status, Cow::Owned("failed"))).count());
+    ///
+    /// archived_before.store(2021, Ordering::Relaxed);
+    /// assert_eq!(1, storage.query(Everything.matching(&by_status, Cow::Owned("failed"))).count());
+    ///
+    /// archived_before.store(0, Ordering::Relaxed);
+    /// assert_eq!(3, storage.query(Everything.matching(&by_status, Cow::Owned("failed"))).count());
+    ///
+    /// # storage.validate();
+    /// # by_status.validate(&storage);
+    /// ```
+    pub fn new_scoped<ItemKey, S, F>(
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        chunk_scope: S,
+        f: F,
+    ) -> Self
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        S: Fn(&ChunkKey) -> bool + Send + Sync + 'static,
+        F: Fn(&Element) -> Cow<IndexKeys> + Clone + Send + Sync + 'static,
+    {
+        Self::new_impl(storage, Some(Arc::new(chunk_scope)), false, false, f)
+    }
+
+    /// Create a new SecondaryIndex that only indexes elements satisfying the given predicate,
+    /// such as only the orders that are still pending. Every other element has no index keys,
+    /// so it consumes no index memory and is never matched by a query against this index.
+    ///
+    /// This is the same as returning an empty collection of index keys from the indexing rule,
+    /// but it keeps that guard separate from the rule itself.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    /// use std::borrow::Cow;
+    ///
+    /// // Orders chunked by customer, keyed by order id, with a status and a warehouse.
+    /// type Order = (u64, u64, (&'static str, &'static str));
+    /// let mut storage : Storage<u64, u64, Order> = Storage::new();
+    /// let pending_by_warehouse : SecondaryIndex<u64, Order, Option<&'static str>, &'static str> =
+    ///   SecondaryIndex::new_partial(
+    ///     &storage,
+    ///     |x: &Order| x.2.0 == "pending",
+    ///     |x: &Order| Cow::Owned(Some(x.2.1)));
+    ///
+    /// storage.add((1, 1, ("pending", "east")));
+    /// storage.add((1, 2, ("shipped", "east")));
+    /// storage.add((2, 3, ("pending", "west")));
+    /// storage.add((2, 4, ("pending", "east")));
+    ///
+    /// let pending_east = Everything.matching(&pending_by_warehouse, Cow::Owned("east"));
+    /// assert_eq!(2, storage.query(&pending_east).count());
+    ///
+    /// storage.modify(ID.chunk(1).item(1), |mut x| x.get_mut().2.0 = "shipped");
+    /// assert_eq!(1, storage.query(&pending_east).count());
+    ///
+    /// # storage.validate();
+    /// # pending_by_warehouse.validate(&storage);
+    /// ```
+    pub fn new_partial<ItemKey, P, F>(
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        predicate: P,
+        f: F,
+    ) -> Self
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        P: Fn(&Element) -> bool + Clone + Send + Sync + 'static,
+        F: Fn(&Element) -> Cow<IndexKeys> + Clone + Send + Sync + 'static,
+    {
+        Self::new(storage, move |element: &Element| {
+            if predicate(element) {
+                f(element)
+            } else {
+                Cow::Owned(IndexKeys::default())
+            }
+        })
+    }
+
+    pub(crate) fn new_impl<ItemKey, F>(
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        chunk_scope: Option<ChunkScope<ChunkKey>>,
+        ordered: bool,
+        by_key: bool,
+        f: F,
+    ) -> Self
     where
         ItemKey: BorrowedKey + ?Sized,
         ItemKey::Owned: ValidKey,
@@ -168,11 +456,637 @@ where
             gc_chunk_list: RVec::default(),
             index: HashMap::with_hasher(crate::internal::hasher::HasherImpl::default()),
             rules: Arc::new(
-                SecondaryIndexImpl::<ChunkKey, Element, IndexKeys, IndexKey>::indexing_rules(f),
+                SecondaryIndexImpl::<ChunkKey, Element, IndexKeys, IndexKey>::indexing_rules(
+                    f, ordered, by_key,
+                ),
             ),
+            chunk_scope,
+            chunks_by_key: if by_key {
+                Some(HashMap::with_hasher(
+                    crate::internal::hasher::HasherImpl::default(),
+                ))
+            } else {
+                None
+            },
         })))
     }
 
+    /// Keep this index up to date with every write to the parent `Storage`, instead of catching
+    /// up on the next query. This makes each write a little slower, but spares the first query
+    /// after a large burst of writes from re-indexing everything at once. The index is built
+    /// right away, and stays attached to the `Storage` until the last clone of it is dropped.
+    ///
+    /// Changes made through an `Entry` or `Storage::query_mut` are indexed by the next write,
+    /// or else by the next query. A clone of the `Storage` doesn't keep this index up to date.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    /// use std::borrow::Cow;
+    ///
+    /// let mut storage : Storage<u64, u64, (u64, u64, &'static str)> = Storage::new();
+    /// let by_status : SecondaryIndex<u64, (u64, u64, &'static str), Option<&'static str>, &'static str> =
+    ///   SecondaryIndex::new(&storage, |x: &(u64, u64, &'static str)| Cow::Owned(Some(x.2)));
+    /// by_status.maintain_eagerly(&mut storage);
+    ///
+    /// // Each of these writes updates by_status as it happens.
+    /// for i in 0..1000 {
+    ///   storage.add((i % 10, i, if i % 3 == 0 { "failed" } else { "ok" }));
+    /// }
+    ///
+    /// // So this query doesn't have any catching up to do.
+    /// assert_eq!(334, storage.query(Everything.matching(&by_status, Cow::Owned("failed"))).count());
+    ///
+    /// # storage.validate();
+    /// # by_status.validate(&storage);
+    /// ```
+    pub fn maintain_eagerly<ItemKey>(&self, storage: &mut Storage<ChunkKey, ItemKey, Element>)
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        ChunkKey::Owned: Send + Sync,
+        IndexKeys: Send + Sync,
+        IndexKey::Owned: Send + Sync,
+        Self: 'static,
+    {
+        let index = Arc::downgrade(&self.0);
+        storage.attach_maintainer(Arc::new(move |storage, idxs| match index.upgrade() {
+            Some(index) => {
+                SecondaryIndex(index).refresh(storage, idxs);
+                true
+            }
+            None => false,
+        }));
+
+        self.rebuild(storage);
+    }
+
+    /// Discard the index of the given chunk, so that it is rebuilt from scratch the next time
+    /// it is needed. Use this if the indexing rule depends on external state that has changed.
+    pub fn invalidate_chunk(&self, chunk_key: &ChunkKey) {
+        let mut secondary_index_impl = self.0.write().unwrap();
+        if let Some(summarize) = secondary_index_impl.index.remove(chunk_key) {
+            secondary_index_impl.forget_chunk(chunk_key, summarize.peek());
+        }
+    }
+
+    /// Discard the index of every chunk, so that each is rebuilt from scratch the next time
+    /// it is needed.
+    pub fn invalidate_all(&self) {
+        let mut secondary_index_impl = self.0.write().unwrap();
+        secondary_index_impl.index.clear();
+        if let Some(chunks_by_key) = secondary_index_impl.chunks_by_key.as_mut() {
+            chunks_by_key.clear();
+        }
+    }
+
+    /// Bring the index of every chunk up to date right now, rebuilding any chunk that was
+    /// invalidated, rather than leaving that work for the next query that visits each chunk.
+    /// Call this at a convenient time, such as after a bulk import or before a
+    /// latency-sensitive phase.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    /// use std::borrow::Cow;
+    ///
+    /// let mut storage : Storage<u64, u64, (u64, u64, &'static str)> = Storage::new();
+    /// let by_status : SecondaryIndex<u64, (u64, u64, &'static str), Option<&'static str>, &'static str> =
+    ///   SecondaryIndex::new(&storage, |x: &(u64, u64, &'static str)| Cow::Owned(Some(x.2)));
+    ///
+    /// // A bulk import, followed by indexing everything at once.
+    /// for i in 0..1000 {
+    ///   storage.add((i % 10, i, if i % 3 == 0 { "failed" } else { "ok" }));
+    /// }
+    /// by_status.rebuild(&storage);
+    ///
+    /// assert_eq!(334, storage.query(Everything.matching(&by_status, Cow::Owned("failed"))).count());
+    ///
+    /// // Start over from scratch, then index everything at once again.
+    /// by_status.invalidate_all();
+    /// by_status.rebuild(&storage);
+    ///
+    /// assert_eq!(666, storage.query(Everything.matching(&by_status, Cow::Owned("ok"))).count());
+    ///
+    /// # storage.validate();
+    /// # by_status.validate(&storage);
+    /// ```
+    pub fn rebuild<ItemKey>(&self, storage: &Storage<ChunkKey, ItemKey, Element>)
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        self.refresh(storage, &IdxRange(0..storage.internal_rvec().len()));
+    }
+
+    /// Bring the index of every chunk up to date right now, as `SecondaryIndex::rebuild` does,
+    /// but index chunks in parallel. If the `Storage` is large enough, according to its
+    /// `Parallelism`, each chunk is indexed on its own thread, so building an index over a
+    /// `Storage` with many chunks isn't limited to a single core.
+    ///
+    /// Each chunk is indexed by a single thread, so this gains nothing over
+    /// `SecondaryIndex::rebuild` when almost all of the elements live in the same chunk.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    /// use retriever::types::parallelism::Parallelism;
+    /// use std::borrow::Cow;
+    ///
+    /// let mut storage : Storage<u64, u64, (u64, u64, &'static str)> = Storage::new();
+    /// storage.set_parallelism(Parallelism::parallel());
+    ///
+    /// for i in 0..1000 {
+    ///   storage.add((i % 10, i, if i % 3 == 0 { "failed" } else { "ok" }));
+    /// }
+    ///
+    /// // Index a cold storage using every core.
+    /// let by_status : SecondaryIndex<u64, (u64, u64, &'static str), Option<&'static str>, &'static str> =
+    ///   SecondaryIndex::new(&storage, |x: &(u64, u64, &'static str)| Cow::Owned(Some(x.2)));
+    /// by_status.par_rebuild(&storage);
+    ///
+    /// assert_eq!(334, storage.query(Everything.matching(&by_status, Cow::Owned("failed"))).count());
+    ///
+    /// # storage.validate();
+    /// # by_status.validate(&storage);
+    /// ```
+    #[cfg(feature = "rayon")]
+    pub fn par_rebuild<ItemKey>(&self, storage: &Storage<ChunkKey, ItemKey, Element>)
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey> + Sync,
+        ChunkKey::Owned: Send,
+        IndexKeys: Send,
+        IndexKey::Owned: Send,
+    {
+        let chunk_idxs: Vec<usize> = (0..storage.internal_rvec().len()).collect();
+        if !storage.is_parallel(&chunk_idxs) {
+            return self.rebuild(storage);
+        }
+
+        let mut secondary_index_impl = self.0.write().unwrap();
+        secondary_index_impl.check_parent(storage);
+
+        secondary_index_impl.gc(storage);
+        secondary_index_impl.par_update_chunks(storage);
+    }
+
+    /// Bring this index up to date and save a copy of it, which can be serialized and later
+    /// restored using `SecondaryIndex::restore`. Only chunks within this index's scope are saved.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    /// use retriever::types::saved_index::SavedIndex;
+    /// use std::borrow::Cow;
+    ///
+    /// type Order = (u64, u64, String);
+    /// fn by_status(x: &Order) -> Cow<'_, Option<String>> {
+    ///   Cow::Owned(Some(x.2.clone()))
+    /// }
+    ///
+    /// let mut storage : Storage<u64, u64, Order> = Storage::new();
+    /// for i in 0..100 {
+    ///   storage.add((i % 10, i, String::from(if i % 4 == 0 { "shipped" } else { "pending" })));
+    /// }
+    ///
+    /// let index : SecondaryIndex<u64, Order, Option<String>, String> =
+    ///   SecondaryIndex::new(&storage, by_status);
+    /// let saved = serde_json::to_string(&index.save(&storage)).unwrap();
+    ///
+    /// // Later, perhaps after restarting the program and loading the Storage, but with one
+    /// // chunk that has changed since the index was saved.
+    /// storage.add((3, 100, String::from("shipped")));
+    ///
+    /// let saved : SavedIndex<u64, u64, Option<String>> = serde_json::from_str(&saved).unwrap();
+    /// let index : SecondaryIndex<u64, Order, Option<String>, String> =
+    ///   SecondaryIndex::new(&storage, by_status);
+    /// assert_eq!(9, index.restore(&storage, &saved));
+    ///
+    /// let shipped = storage.query(Everything.matching(&index, Cow::Owned(String::from("shipped"))));
+    /// assert_eq!(26, shipped.count());
+    ///
+    /// # storage.validate();
+    /// # index.validate(&storage);
+    /// ```
+    #[cfg(feature = "serde")]
+    pub fn save<ItemKey>(
+        &self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+    ) -> SavedIndex<ChunkKey::Owned, ItemKey::Owned, IndexKeys>
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey> + Hash,
+    {
+        self.rebuild(storage);
+
+        let secondary_index_impl = self.0.read().unwrap();
+        let chunks = storage
+            .internal_rvec()
+            .iter()
+            .filter_map(|chunk_storage| {
+                let summarize = secondary_index_impl.index.get(chunk_storage.chunk_key())?;
+                let index_keys = summarize
+                    .tokens()
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, index_keys)| **index_keys != IndexKeys::default())
+                    .map(|(idx, index_keys)| {
+                        (
+                            chunk_storage.get_idx(idx).item_key().into_owned(),
+                            index_keys.clone(),
+                        )
+                    })
+                    .collect();
+
+                Some(SavedChunkIndex {
+                    chunk_key: chunk_storage.chunk_key().to_owned(),
+                    fingerprint: fingerprint(chunk_storage),
+                    index_keys,
+                })
+            })
+            .collect();
+
+        SavedIndex { chunks }
+    }
+
+    /// Restore a copy of this index saved using `SecondaryIndex::save`, for every chunk that
+    /// hasn't changed since it was saved. Any other chunk is indexed as usual, the next time it
+    /// is needed. Returns the number of chunks restored.
+    ///
+    /// The saved index keys are trusted, so this index must have the same indexing rule as the
+    /// index that was saved.
+    #[cfg(feature = "serde")]
+    pub fn restore<ItemKey>(
+        &self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        saved: &SavedIndex<ChunkKey::Owned, ItemKey::Owned, IndexKeys>,
+    ) -> usize
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey> + Hash,
+    {
+        // Check the parent storage and catch up with any removed chunks, without indexing any.
+        self.refresh(storage, &IdxRange(0..0));
+
+        let mut secondary_index_impl = self.0.write().unwrap();
+        let mut restored = 0;
+
+        for saved_chunk in saved.chunks.iter() {
+            let chunk_storage = match storage.internal_idx_of(saved_chunk.chunk_key.borrow()) {
+                Some(idx) => &storage.internal_rvec()[idx],
+                None => continue,
+            };
+
+            if !secondary_index_impl.in_scope(chunk_storage.chunk_key())
+                || fingerprint(chunk_storage) != saved_chunk.fingerprint
+            {
+                continue;
+            }
+
+            let mut tokens = vec![IndexKeys::default(); chunk_storage.len()];
+            for (item_key, index_keys) in saved_chunk.index_keys.iter() {
+                if let Some(idx) = chunk_storage.internal_idx_of(item_key.borrow()) {
+                    tokens[idx] = index_keys.clone();
+                }
+            }
+
+            let summarize = Summarize::restore(
+                chunk_storage.internal_rvec(),
+                Arc::clone(&secondary_index_impl.rules),
+                tokens,
+            );
+            secondary_index_impl
+                .index
+                .insert(saved_chunk.chunk_key.clone(), summarize);
+            secondary_index_impl.collect_changed_keys(saved_chunk.chunk_key.borrow());
+            restored += 1;
+        }
+
+        restored
+    }
+
+    /// Bring this index up to date and iterate over every distinct index key of any element,
+    /// in order. This visits each chunk's postings instead of every element.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    /// use std::borrow::Cow;
+    ///
+    /// let mut storage : Storage<u64, u64, (u64, u64, &'static str)> = Storage::new();
+    /// let by_category : SecondaryIndex<u64, (u64, u64, &'static str), Option<&'static str>, &'static str> =
+    ///   SecondaryIndex::new(&storage, |x: &(u64, u64, &'static str)| Cow::Owned(Some(x.2)));
+    ///
+    /// storage.add((1, 1, "books"));
+    /// storage.add((1, 2, "games"));
+    /// storage.add((2, 3, "books"));
+    ///
+    /// assert_eq!(vec!["books", "games"], by_category.keys(&storage).collect::<Vec<_>>());
+    /// assert_eq!(
+    ///   vec![("books", 2), ("games", 1)],
+    ///   by_category.key_counts(&storage).collect::<Vec<_>>());
+    ///
+    /// # storage.validate();
+    /// # by_category.validate(&storage);
+    /// ```
+    pub fn keys<ItemKey>(
+        &self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+    ) -> impl Iterator<Item = IndexKey::Owned>
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        self.rebuild(storage);
+
+        let mut keys: BTreeSet<IndexKey::Owned> = BTreeSet::new();
+        self.for_each_posting(|_, index_key, _| {
+            if !keys.contains(index_key) {
+                keys.insert(index_key.to_owned());
+            }
+        });
+
+        keys.into_iter()
+    }
+
+    /// Bring this index up to date and iterate over every distinct index key of any element,
+    /// in order, along with the number of elements with that index key. See `keys`.
+    pub fn key_counts<ItemKey>(
+        &self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+    ) -> impl Iterator<Item = (IndexKey::Owned, usize)>
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        self.rebuild(storage);
+
+        let mut counts: BTreeMap<IndexKey::Owned, usize> = BTreeMap::new();
+        self.for_each_posting(|_, index_key, idxs| match counts.get_mut(index_key) {
+            Some(count) => *count += idxs.len(),
+            None => {
+                counts.insert(index_key.to_owned(), idxs.len());
+            }
+        });
+
+        counts.into_iter()
+    }
+
+    /// Bring this index up to date for the chunk of the given element and get the index keys
+    /// recorded for that element, which are the index keys a query matches it against. The
+    /// indexing rule isn't run again for an element that hasn't changed, so if the rule depends
+    /// on external state, the recorded keys may differ from what the rule would produce now.
+    ///
+    /// Returns `None` if there is no such element, or if its chunk isn't indexed at all (see
+    /// `SecondaryIndex::new_scoped`).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    /// use std::borrow::Cow;
+    /// use std::collections::BTreeSet;
+    ///
+    /// // Articles chunked by author, keyed by article id, with some tags.
+    /// type Article = (&'static str, u64, Vec<&'static str>);
+    /// let mut storage : Storage<&'static str, u64, Article> = Storage::new();
+    /// let by_tag : SecondaryIndex<&'static str, Article, BTreeSet<&'static str>, &'static str> =
+    ///   SecondaryIndex::new_multi(&storage, |x: &Article| x.2.clone());
+    ///
+    /// storage.add(("alice", 1, vec!["rust", "databases"]));
+    ///
+    /// let tags = by_tag.keys_of(&storage, &ID.chunk("alice").item(1)).unwrap();
+    /// assert_eq!(vec!["databases", "rust"], tags.into_iter().collect::<Vec<_>>());
+    /// assert_eq!(None, by_tag.keys_of(&storage, &ID.chunk("alice").item(2)));
+    ///
+    /// # storage.validate();
+    /// # by_tag.validate(&storage);
+    /// ```
+    pub fn keys_of<ItemKey, R>(
+        &self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        unique_id: &R,
+    ) -> Option<IndexKeys>
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        R: Record<ChunkKey, ItemKey>,
+    {
+        let chunk_key = unique_id.chunk_key();
+        let idx = storage.internal_idx_of(chunk_key.borrow())?;
+        let item_idx =
+            storage.internal_rvec()[idx].internal_idx_of(unique_id.item_key().borrow())?;
+
+        self.refresh(storage, &IdxRange(idx..idx + 1));
+
+        let secondary_index_impl = self.0.read().unwrap();
+        let summarize = secondary_index_impl.index.get(chunk_key.borrow())?;
+        summarize.tokens().get(item_idx).cloned()
+    }
+
+    /// Bring this index up to date and count the elements under each of its index keys. Use
+    /// this to detect a skewed index, where a few index keys match so many elements that a
+    /// query matching them would be no faster than a full scan.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    /// use std::borrow::Cow;
+    ///
+    /// let mut storage : Storage<u64, u64, (u64, u64, &'static str)> = Storage::new();
+    /// let by_status : SecondaryIndex<u64, (u64, u64, &'static str), Option<&'static str>, &'static str> =
+    ///   SecondaryIndex::new(&storage, |x: &(u64, u64, &'static str)| Cow::Owned(Some(x.2)));
+    ///
+    /// for i in 0..100 {
+    ///   storage.add((i % 10, i, if i < 95 { "ok" } else { "failed" }));
+    /// }
+    ///
+    /// let stats = by_status.stats(&storage);
+    /// assert_eq!(2, stats.distinct_keys);
+    /// assert_eq!(100, stats.postings);
+    /// assert_eq!(Some((&"ok", 95)), stats.most_common());
+    /// assert_eq!(0.05, stats.selectivity("failed"));
+    ///
+    /// # storage.validate();
+    /// # by_status.validate(&storage);
+    /// ```
+    pub fn stats<ItemKey>(
+        &self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+    ) -> IndexStats<IndexKey::Owned>
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        self.rebuild(storage);
+
+        let mut counts: HashMap<IndexKey::Owned, usize> = HashMap::new();
+        self.for_each_posting(|_, index_key, idxs| {
+            *counts.entry(index_key.to_owned()).or_insert(0) += idxs.len();
+        });
+
+        let elements = self
+            .0
+            .read()
+            .unwrap()
+            .index
+            .keys()
+            .filter_map(|chunk_key| storage.internal_idx_of(chunk_key.borrow()))
+            .map(|idx| storage.internal_rvec()[idx].len())
+            .sum();
+
+        IndexStats {
+            distinct_keys: counts.len(),
+            postings: counts.values().sum(),
+            elements,
+            counts,
+        }
+    }
+
+    /// Bring this index up to date, then check it against every element of the storage: each
+    /// index key must point at a live element whose rule still yields that index key, and every
+    /// index key yielded by the rule must point at its element. Unlike
+    /// `SecondaryIndex::validate`, this reports what it finds instead of panicking, so it's
+    /// suitable for health checks. This is a slow operation.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    /// use std::borrow::Cow;
+    ///
+    /// let mut storage : Storage<u64, u64, (u64, u64, &'static str)> = Storage::new();
+    /// let by_status : SecondaryIndex<u64, (u64, u64, &'static str), Option<&'static str>, &'static str> =
+    ///   SecondaryIndex::new(&storage, |x: &(u64, u64, &'static str)| Cow::Owned(Some(x.2)));
+    ///
+    /// storage.add((1, 1, "ok"));
+    /// storage.add((1, 2, "failed"));
+    /// storage.add((2, 3, "ok"));
+    ///
+    /// let report = by_status.validate_index(&storage);
+    /// assert!(report.is_consistent());
+    /// assert_eq!(2, report.chunks);
+    /// assert_eq!(3, report.elements);
+    ///
+    /// let other : Storage<u64, u64, (u64, u64, &'static str)> = Storage::new();
+    /// assert!(by_status.validate_index(&other).wrong_storage);
+    ///
+    /// # storage.validate();
+    /// # by_status.validate(&storage);
+    /// ```
+    pub fn validate_index<ItemKey>(
+        &self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+    ) -> IndexReport<ChunkKey::Owned, ItemKey::Owned, IndexKey::Owned>
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        let mut report = IndexReport {
+            wrong_storage: false,
+            chunks: 0,
+            elements: 0,
+            unindexed_chunks: Vec::new(),
+            dangling: Vec::new(),
+            stale: Vec::new(),
+            missing: Vec::new(),
+        };
+
+        if self.0.read().unwrap().parent_id != storage.id() {
+            report.wrong_storage = true;
+            return report;
+        }
+
+        self.rebuild(storage);
+        let secondary_index_impl = self.0.read().unwrap();
+
+        for chunk_storage in storage.internal_rvec().iter() {
+            let chunk_key = chunk_storage.chunk_key();
+            if !secondary_index_impl.in_scope(chunk_key) {
+                continue;
+            }
+
+            report.chunks += 1;
+            report.elements += chunk_storage.len();
+
+            let summarize = match secondary_index_impl.index.get(chunk_key) {
+                Some(summarize) => summarize,
+                None => {
+                    report.unindexed_chunks.push(chunk_key.to_owned());
+                    continue;
+                }
+            };
+
+            let id_of = |idx: usize| {
+                Id(
+                    chunk_key.to_owned(),
+                    chunk_storage.get_idx(idx).item_key().into_owned(),
+                )
+            };
+            let expected: Vec<IndexKeys> = chunk_storage
+                .internal_rvec()
+                .iter()
+                .enumerate()
+                .map(|(idx, element)| {
+                    (secondary_index_impl.rules.map)(element, &IndexKeys::default(), idx)
+                        .unwrap_or_default()
+                })
+                .collect();
+            let reverse_index = &summarize.peek().reverse_index;
+
+            for (index_key, idxs) in reverse_index.iter() {
+                for idx in idxs.iter().flatten() {
+                    match expected.get(idx) {
+                        None => {
+                            report
+                                .dangling
+                                .push((chunk_key.to_owned(), index_key.clone(), idx))
+                        }
+                        Some(index_keys)
+                            if !index_keys
+                                .iter_keys()
+                                .any(|k| k.as_ref() == index_key.borrow()) =>
+                        {
+                            report.stale.push((id_of(idx), index_key.clone()))
+                        }
+                        Some(_) => {}
+                    }
+                }
+            }
+
+            for (idx, index_keys) in expected.iter().enumerate() {
+                for index_key in index_keys.iter_keys() {
+                    let posted = reverse_index
+                        .get(index_key.borrow())
+                        .map(|idxs| idxs.get(idx))
+                        .unwrap_or(false);
+                    if !posted {
+                        report.missing.push((id_of(idx), index_key.into_owned()));
+                    }
+                }
+            }
+        }
+
+        report
+    }
+
     /// Panic if this storage is malformed or broken in any detectable way.
     /// This is a slow operation and you shouldn't use it unless you suspect a problem.
     pub fn validate<ItemKey>(&self, parent: &Storage<ChunkKey, ItemKey, Element>)
@@ -183,6 +1097,277 @@ where
     {
         self.0.write().unwrap().validate(parent);
     }
+
+    /// Bring the index up to date for the given chunks of the parent `Storage`, which a query is
+    /// about to visit.
+    pub(crate) fn refresh<ItemKey, I>(
+        &self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        idxs: &I,
+    ) where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        I: IdxSet,
+    {
+        let mut secondary_index_impl = self.0.write().unwrap();
+        secondary_index_impl.check_parent(storage);
+
+        secondary_index_impl.gc(storage);
+        for idx in idxs.clone().into_idx_iter().flatten() {
+            let chunk_key = secondary_index_impl.gc_chunk_list[idx]
+                .as_ref()
+                .cloned()
+                .expect("gc_chunk_list should not contain None immediately after gc");
+            if secondary_index_impl.in_scope(chunk_key.borrow()) {
+                secondary_index_impl
+                    .update_chunk(chunk_key.borrow(), &storage.internal_rvec()[idx]);
+            }
+        }
+    }
+
+    /// The index keys of the given element, according to the indexing rule.
+    pub(crate) fn index_keys_of(&self, element: &Element) -> IndexKeys {
+        let secondary_index_impl = self.0.read().unwrap();
+        (secondary_index_impl.rules.map)(element, &IndexKeys::default(), 0).unwrap_or_default()
+    }
+
+    /// Catch up with any chunks removed from the parent `Storage`, without indexing anything,
+    /// unless this index last followed a different `Storage`, such as a clone or `Snapshot` of
+    /// the parent, in which case rebuild the whole index.
+    pub(crate) fn catch_up<ItemKey>(&self, storage: &Storage<ChunkKey, ItemKey, Element>)
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        let followed = {
+            let secondary_index_impl = self.0.read().unwrap();
+            secondary_index_impl.parent_id == storage.id()
+                && !secondary_index_impl
+                    .gc_chunk_list
+                    .would_reset(storage.internal_rvec())
+        };
+
+        if followed {
+            self.refresh(storage, &IdxRange(0..0));
+        } else {
+            self.rebuild(storage);
+        }
+    }
+
+    /// The chunk key and internal indices of the elements of every indexed chunk that has any
+    /// element with the given index key. This visits every indexed chunk, unless this index
+    /// keeps track of the chunks holding each index key.
+    pub(crate) fn postings_of(&self, index_key: &IndexKey) -> Vec<(ChunkKey::Owned, Bitset)> {
+        let secondary_index_impl = self.0.read().unwrap();
+
+        if let Some(chunks_by_key) = secondary_index_impl.chunks_by_key.as_ref() {
+            return chunks_by_key
+                .get(index_key)
+                .into_iter()
+                .flatten()
+                .filter_map(|chunk_key| {
+                    let summarize = secondary_index_impl.index.get(chunk_key.borrow())?;
+                    let idxs = summarize.peek().reverse_index.get(index_key)?;
+                    Some((chunk_key.clone(), idxs.clone()))
+                })
+                .collect();
+        }
+
+        secondary_index_impl
+            .index
+            .iter()
+            .filter_map(|(chunk_key, summarize)| {
+                summarize
+                    .peek()
+                    .reverse_index
+                    .get(index_key)
+                    .map(|idxs| (chunk_key.clone(), idxs.clone()))
+            })
+            .collect()
+    }
+
+    /// Every index key of the given chunk in order, each with the internal indices of its
+    /// elements. `None` if the chunk is not indexed.
+    pub(crate) fn ordered_postings(
+        &self,
+        chunk_key: &ChunkKey,
+    ) -> Option<Vec<(IndexKey::Owned, Bitset)>> {
+        let secondary_index_impl = self.0.read().unwrap();
+        let summary = secondary_index_impl.index.get(chunk_key)?.peek();
+        let ordered_keys = summary.ordered_keys.as_ref()?;
+
+        Some(
+            ordered_keys
+                .iter()
+                .map(|index_key| {
+                    let idxs = summary.reverse_index[index_key.borrow()].clone();
+                    (index_key.clone(), idxs)
+                })
+                .collect(),
+        )
+    }
+
+    /// The least (or greatest, if `greatest` is set) index key of any indexed chunk.
+    pub(crate) fn extreme_ordered_key(&self, greatest: bool) -> Option<IndexKey::Owned> {
+        let secondary_index_impl = self.0.read().unwrap();
+        let chunk_extremes = secondary_index_impl
+            .index
+            .values()
+            .filter_map(|summarize| summarize.peek().ordered_keys.as_ref())
+            .filter_map(|ordered_keys| {
+                if greatest {
+                    ordered_keys.iter().next_back()
+                } else {
+                    ordered_keys.iter().next()
+                }
+            });
+
+        if greatest {
+            chunk_extremes.max().cloned()
+        } else {
+            chunk_extremes.min().cloned()
+        }
+    }
+
+    /// Visit the chunk key, index key, and internal indices of the elements of every index key
+    /// of every indexed chunk.
+    pub(crate) fn for_each_posting<F>(&self, mut f: F)
+    where
+        F: FnMut(&ChunkKey, &IndexKey, &Bitset),
+    {
+        let secondary_index_impl = self.0.read().unwrap();
+
+        for (chunk_key, summarize) in secondary_index_impl.index.iter() {
+            for (index_key, idxs) in summarize.peek().reverse_index.iter() {
+                f(chunk_key.borrow(), index_key.borrow(), idxs);
+            }
+        }
+    }
+
+    /// The internal indices of every element of the given chunk with at least one index key
+    /// within the range. `None` if the chunk is not indexed.
+    pub(crate) fn idxs_in_range(
+        &self,
+        chunk_key: &ChunkKey,
+        start: Bound<&IndexKey>,
+        end: Bound<&IndexKey>,
+    ) -> Option<Bitset> {
+        let secondary_index_impl = self.0.read().unwrap();
+        let summary = secondary_index_impl.index.get(chunk_key)?.peek();
+        let ordered_keys = summary.ordered_keys.as_ref()?;
+
+        if !is_valid_range(start, end) {
+            return None;
+        }
+
+        Some(
+            ordered_keys
+                .range::<IndexKey, _>((start, end))
+                .flat_map(|index_key| summary.reverse_index[index_key.borrow()].iter())
+                .flatten()
+                .collect(),
+        )
+    }
+
+    /// The internal indices of every element of the given chunk with at least one selected
+    /// index key. Index keys are visited in order beginning at `start`, and `select` decides
+    /// whether to include each one (`Some(true)`), skip it (`Some(false)`), or stop (`None`).
+    /// `None` if the chunk is not indexed.
+    pub(crate) fn idxs_of_ordered_keys<F>(
+        &self,
+        chunk_key: &ChunkKey,
+        start: Bound<&IndexKey>,
+        mut select: F,
+    ) -> Option<Bitset>
+    where
+        F: FnMut(&IndexKey) -> Option<bool>,
+    {
+        let secondary_index_impl = self.0.read().unwrap();
+        let summary = secondary_index_impl.index.get(chunk_key)?.peek();
+        let ordered_keys = summary.ordered_keys.as_ref()?;
+
+        Some(
+            ordered_keys
+                .range::<IndexKey, _>((start, Bound::Unbounded))
+                .map(|index_key| (select(index_key.borrow()), index_key))
+                .take_while(|(selected, _)| selected.is_some())
+                .filter(|(selected, _)| *selected == Some(true))
+                .fold(Bitset::default(), |result, (_, index_key)| {
+                    result.merge(&summary.reverse_index[index_key.borrow()])
+                }),
+        )
+    }
+}
+
+impl<ChunkKey, Element, IndexKey> SecondaryIndex<ChunkKey, Element, Option<IndexKey>, IndexKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    IndexKey: ValidKey,
+    for<'k> Option<IndexKey>: KeySet<'k, IndexKey>,
+{
+    /// Create a new SecondaryIndex of a storage, indexing each element under the value of one
+    /// of its fields. This is a shorthand for `SecondaryIndex::new` with a rule that returns
+    /// `Cow::Owned(Some(field.clone()))`, for the common case of one index key per element.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    /// use std::borrow::Cow;
+    ///
+    /// struct Order {
+    ///   customer: u64,
+    ///   id: u64,
+    ///   status: &'static str,
+    /// }
+    ///
+    /// impl Record<u64, u64> for Order {
+    ///   fn chunk_key(&self) -> Cow<u64> { Cow::Owned(self.customer) }
+    ///   fn item_key(&self) -> Cow<u64> { Cow::Owned(self.id) }
+    /// }
+    ///
+    /// let mut storage : Storage<u64, u64, Order> = Storage::new();
+    /// let by_status = SecondaryIndex::on_field(&storage, |x: &Order| &x.status);
+    ///
+    /// storage.add(Order { customer: 1, id: 1, status: "pending" });
+    /// storage.add(Order { customer: 1, id: 2, status: "shipped" });
+    /// storage.add(Order { customer: 2, id: 3, status: "pending" });
+    ///
+    /// assert_eq!(2, storage.query(Everything.matching(&by_status, Cow::Owned("pending"))).count());
+    ///
+    /// # storage.validate();
+    /// # by_status.validate(&storage);
+    /// ```
+    pub fn on_field<ItemKey, F>(storage: &Storage<ChunkKey, ItemKey, Element>, f: F) -> Self
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        F: Fn(&Element) -> &IndexKey + Clone + Send + Sync + 'static,
+    {
+        Self::new(storage, move |element: &Element| {
+            Cow::Owned(Some(f(element).clone()))
+        })
+    }
+
+    /// Create a new SecondaryIndex of a storage, indexing each element under the value of one
+    /// of its optional fields. Elements whose field is `None` aren't indexed at all. Unlike
+    /// `SecondaryIndex::on_field`, this borrows the field instead of cloning it.
+    pub fn on_optional_field<ItemKey, F>(
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        f: F,
+    ) -> Self
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        F: Fn(&Element) -> &Option<IndexKey> + Clone + Send + Sync + 'static,
+    {
+        Self::new(storage, move |element: &Element| Cow::Borrowed(f(element)))
+    }
 }
 
 impl<ChunkKey, Element, IndexKeys, IndexKey>
@@ -194,7 +1379,11 @@ where
     IndexKey::Owned: ValidKey,
     for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
 {
-    fn indexing_rules<F>(f: F) -> SummaryRules<Element, IndexKeys, ChunkSecondaryIndex<IndexKey>>
+    fn indexing_rules<F>(
+        f: F,
+        ordered: bool,
+        by_key: bool,
+    ) -> SummaryRules<Element, IndexKeys, ChunkSecondaryIndex<IndexKey>>
     where
         F: Fn(&Element) -> Cow<IndexKeys> + Clone + Send + Sync + 'static,
     {
@@ -208,8 +1397,22 @@ where
                     None
                 }
             }),
-            contribute: Arc::new(|new_index_keys, internal_idx, summary| {
+            contribute: Arc::new(move |new_index_keys, internal_idx, summary| {
                 for new_index_key in new_index_keys.iter_keys() {
+                    if !summary.reverse_index.contains_key(new_index_key.borrow()) {
+                        if ordered {
+                            summary
+                                .ordered_keys
+                                .get_or_insert_with(BTreeSet::new)
+                                .insert(new_index_key.clone().into_owned());
+                        }
+                        if by_key {
+                            summary
+                                .changed_keys
+                                .push(new_index_key.clone().into_owned());
+                        }
+                    }
+
                     let idx_set = summary
                         .reverse_index
                         .entry(new_index_key.into_owned())
@@ -218,7 +1421,7 @@ where
                     idx_set.set(internal_idx);
                 }
             }),
-            uncontribute: Arc::new(|old_index_keys, internal_idx, summary| {
+            uncontribute: Arc::new(move |old_index_keys, internal_idx, summary| {
                 for old_index_key in old_index_keys.iter_keys() {
                     let mut remove = false;
 
@@ -229,40 +1432,212 @@ where
                         }
                     }
 
-                    if remove {
-                        summary.reverse_index.remove(old_index_key.borrow());
-                    }
+                    if remove {
+                        summary.reverse_index.remove(old_index_key.borrow());
+                        if let Some(ordered_keys) = summary.ordered_keys.as_mut() {
+                            ordered_keys.remove(old_index_key.borrow());
+                        }
+                        if by_key {
+                            summary.changed_keys.push(old_index_key.into_owned());
+                        }
+                    }
+                }
+            }),
+        }
+    }
+
+    pub(crate) fn update_chunk<ItemKey>(
+        &mut self,
+        chunk_key: &ChunkKey,
+        chunk_storage: &ChunkStorage<ChunkKey, ItemKey, Element>,
+    ) where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        let index = &mut self.index;
+        let rules = &self.rules;
+        let internal_storage = chunk_storage.internal_rvec();
+
+        index
+            .entry(chunk_key.to_owned())
+            .or_insert_with(|| Summarize::new(&internal_storage, Arc::clone(rules)))
+            .update(&internal_storage);
+
+        self.collect_changed_keys(chunk_key);
+    }
+
+    /// Bring `chunks_by_key` up to date with the index keys that were added to or removed from
+    /// the given chunk since they were last collected.
+    fn collect_changed_keys(&mut self, chunk_key: &ChunkKey) {
+        let chunks_by_key = match self.chunks_by_key.as_mut() {
+            Some(chunks_by_key) => chunks_by_key,
+            None => return,
+        };
+        let summary = match self.index.get_mut(chunk_key) {
+            Some(summarize) => summarize.peek_mut(),
+            None => return,
+        };
+
+        for index_key in std::mem::take(&mut summary.changed_keys) {
+            if summary.reverse_index.contains_key(index_key.borrow()) {
+                let chunk_keys = chunks_by_key.entry(index_key).or_default();
+                if !chunk_keys.iter().any(|c| c.borrow() == chunk_key) {
+                    chunk_keys.push(chunk_key.to_owned());
+                }
+                continue;
+            }
+
+            let mut remove = false;
+
+            if let Some(chunk_keys) = chunks_by_key.get_mut(index_key.borrow()) {
+                chunk_keys.retain(|c| c.borrow() != chunk_key);
+                remove = chunk_keys.is_empty();
+            }
+
+            if remove {
+                chunks_by_key.remove(index_key.borrow());
+            }
+        }
+    }
+
+    /// Remove the given chunk, which is no longer indexed, from `chunks_by_key`.
+    fn forget_chunk(&mut self, chunk_key: &ChunkKey, summary: &ChunkSecondaryIndex<IndexKey>) {
+        if let Some(chunks_by_key) = self.chunks_by_key.as_mut() {
+            Self::forget_chunk_in(chunks_by_key, chunk_key, summary);
+        }
+    }
+
+    fn forget_chunk_in(
+        chunks_by_key: &mut ChunksByKey<ChunkKey, IndexKey>,
+        chunk_key: &ChunkKey,
+        summary: &ChunkSecondaryIndex<IndexKey>,
+    ) {
+        for index_key in summary.reverse_index.keys() {
+            let mut remove = false;
+
+            if let Some(chunk_keys) = chunks_by_key.get_mut(index_key.borrow()) {
+                chunk_keys.retain(|c| c.borrow() != chunk_key);
+                remove = chunk_keys.is_empty();
+            }
+
+            if remove {
+                chunks_by_key.remove(index_key.borrow());
+            }
+        }
+    }
+
+    pub(crate) fn gc<ItemKey>(&mut self, parent: &Storage<ChunkKey, ItemKey, Element>)
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        let chunks_by_key = &mut self.chunks_by_key;
+        parent.gc_with(
+            &mut self.gc_chunk_list,
+            &mut self.index,
+            |chunk_key, summarize| {
+                if let Some(chunks_by_key) = chunks_by_key.as_mut() {
+                    Self::forget_chunk_in(chunks_by_key, chunk_key.borrow(), summarize.peek());
+                }
+            },
+        );
+
+        if let Some(chunk_scope) = self.chunk_scope.as_ref() {
+            self.index.retain(|chunk_key, summarize| {
+                let in_scope = (chunk_scope)(chunk_key.borrow());
+                if let (false, Some(chunks_by_key)) = (in_scope, chunks_by_key.as_mut()) {
+                    Self::forget_chunk_in(chunks_by_key, chunk_key.borrow(), summarize.peek());
                 }
-            }),
+                in_scope
+            });
         }
     }
 
-    pub(crate) fn update_chunk<ItemKey>(
-        &mut self,
-        chunk_key: &ChunkKey,
-        chunk_storage: &ChunkStorage<ChunkKey, ItemKey, Element>,
-    ) where
+    /// Panic if the given `Storage` isn't the parent of this index, unless its `Strictness` is
+    /// `Repair`, in which case start over with the given `Storage` as the new parent.
+    fn check_parent<ItemKey>(&mut self, storage: &Storage<ChunkKey, ItemKey, Element>)
+    where
         ItemKey: BorrowedKey + ?Sized,
         ItemKey::Owned: ValidKey,
         Element: Record<ChunkKey, ItemKey>,
     {
-        let index = &mut self.index;
-        let rules = &self.rules;
-        let internal_storage = chunk_storage.internal_rvec();
-
-        index
-            .entry(chunk_key.to_owned())
-            .or_insert_with(|| Summarize::new(&internal_storage, Arc::clone(rules)))
-            .update(&internal_storage);
+        if self.parent_id != storage.id() {
+            assert_eq!(storage.strictness(), Strictness::Repair, "Id mismatch: a secondary index may only be used with it's parent Storage, never any other Storage");
+            #[cfg(feature = "log")]
+            log::warn!("retriever: repaired secondary index used with a different Storage by rebuilding it");
+            self.rebind(storage.id());
+        }
     }
 
-    pub(crate) fn gc<ItemKey>(&mut self, parent: &Storage<ChunkKey, ItemKey, Element>)
+    /// Bring the index of every chunk in scope up to date, each chunk on its own thread. The
+    /// index of each chunk is taken out of this index, updated independently of every other
+    /// chunk, and then merged back in.
+    #[cfg(feature = "rayon")]
+    fn par_update_chunks<ItemKey>(&mut self, storage: &Storage<ChunkKey, ItemKey, Element>)
     where
         ItemKey: BorrowedKey + ?Sized,
         ItemKey::Owned: ValidKey,
-        Element: Record<ChunkKey, ItemKey>,
+        Element: Record<ChunkKey, ItemKey> + Sync,
+        ChunkKey::Owned: Send,
+        IndexKeys: Send,
+        IndexKey::Owned: Send,
     {
-        parent.gc(&mut self.gc_chunk_list, &mut self.index);
+        let chunks = storage.internal_rvec();
+        let mut work = Vec::with_capacity(chunks.len());
+
+        for (idx, chunk) in chunks.iter().enumerate() {
+            let chunk_key = self.gc_chunk_list[idx]
+                .as_ref()
+                .cloned()
+                .expect("gc_chunk_list should not contain None immediately after gc");
+
+            if !self.in_scope(chunk_key.borrow()) {
+                continue;
+            }
+
+            let summary = match self.index.remove(chunk_key.borrow()) {
+                Some(summary) => summary,
+                None => Summarize::new(chunk.internal_rvec(), Arc::clone(&self.rules)),
+            };
+
+            work.push((chunk_key, summary, chunk.internal_rvec()));
+        }
+
+        work.par_iter_mut().for_each(|(_, summary, elements)| {
+            summary.update(elements);
+        });
+
+        let chunk_keys: Vec<ChunkKey::Owned> = work
+            .iter()
+            .map(|(chunk_key, _, _)| chunk_key.clone())
+            .collect();
+        self.index.extend(
+            work.into_iter()
+                .map(|(chunk_key, summary, _)| (chunk_key, summary)),
+        );
+        for chunk_key in chunk_keys {
+            self.collect_changed_keys(chunk_key.borrow());
+        }
+    }
+
+    /// Forget everything about the old parent `Storage` and start over with a new one.
+    pub(crate) fn rebind(&mut self, parent_id: u64) {
+        self.parent_id = parent_id;
+        self.gc_chunk_list = RVec::default();
+        self.index.clear();
+        if let Some(chunks_by_key) = self.chunks_by_key.as_mut() {
+            chunks_by_key.clear();
+        }
+    }
+
+    /// True IFF the given chunk should be indexed.
+    pub(crate) fn in_scope(&self, chunk_key: &ChunkKey) -> bool {
+        self.chunk_scope
+            .as_ref()
+            .map(|chunk_scope| (chunk_scope)(chunk_key))
+            .unwrap_or(true)
     }
 
     /// Panic if this storage is malformed or broken in any way.
@@ -278,6 +1653,29 @@ where
         for chunk_key in self.index.keys() {
             assert!(parent.internal_idx_of(chunk_key.borrow()).is_some());
         }
+
+        if let Some(chunks_by_key) = self.chunks_by_key.as_ref() {
+            let mut postings = 0;
+
+            for (chunk_key, summarize) in self.index.iter() {
+                for index_key in summarize.peek().reverse_index.keys() {
+                    assert!(
+                        chunks_by_key
+                            .get(index_key.borrow())
+                            .map(|chunk_keys| chunk_keys.contains(chunk_key))
+                            .unwrap_or(false),
+                        "a UniqueIndex lost track of the chunk holding an index key"
+                    );
+                    postings += 1;
+                }
+            }
+
+            assert_eq!(
+                postings,
+                chunks_by_key.values().map(Vec::len).sum::<usize>(),
+                "a UniqueIndex kept track of an index key that no chunk holds"
+            );
+        }
     }
 }
 
@@ -320,19 +1718,137 @@ where
     type ItemIdxSet = Intersection<Q::ItemIdxSet, Option<Bitset>>;
 
     fn chunk_idxs(&self, storage: &Storage<ChunkKey, ItemKey, Element>) -> Self::ChunkIdxSet {
-        let mut secondary_index_impl = self.secondary_index.0.write().unwrap();
-        assert_eq!(secondary_index_impl.parent_id, storage.id(), "Id mismatch: a secondary index may only be used with it's parent Storage, never any other Storage");
         let result = self.query.chunk_idxs(storage);
+        self.secondary_index.refresh(storage, &result);
+        result
+    }
 
-        secondary_index_impl.gc(storage);
-        for idx in result.clone().into_idx_iter().flatten() {
-            let chunk_key = secondary_index_impl.gc_chunk_list[idx]
-                .as_ref()
-                .cloned()
-                .expect("gc_chunk_list should not contain None immediately after gc");
-            secondary_index_impl.update_chunk(chunk_key.borrow(), &storage.internal_rvec()[idx]);
+    fn item_idxs(
+        &self,
+        chunk_key: &ChunkKey,
+        chunk_storage: &ChunkStorage<ChunkKey, ItemKey, Element>,
+    ) -> Self::ItemIdxSet {
+        let secondary_index_impl = self.secondary_index.0.read().unwrap();
+        let parent_idxs = self.query.item_idxs(chunk_key, chunk_storage);
+        let ours_idxs: Option<Bitset> = secondary_index_impl
+            .index
+            .get(chunk_key)
+            .and_then(|map_summarize| {
+                map_summarize
+                    .peek()
+                    .reverse_index
+                    .get(self.index_key.borrow())
+            })
+            .cloned();
+
+        IdxSet::intersection(parent_idxs, ours_idxs)
+    }
+
+    fn test(&self, element: &Element) -> bool {
+        self.query.test(element)
+    }
+
+    fn test_chunk(&self, chunk_key: &ChunkKey) -> bool {
+        self.query.test_chunk(chunk_key)
+    }
+
+    fn is_exact(&self) -> bool {
+        self.query.is_exact()
+    }
+
+    fn is_chunk_local(&self) -> bool {
+        self.query.is_chunk_local()
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "Matching({}, SecondaryIndex<{}>)",
+            self.query.describe(),
+            short_type_name::<IndexKey>()
+        )
+    }
+}
+
+/// A Query matching elements that are not indexed under some key of a `SecondaryIndex`.
+/// Construct using `Query::not_matching`.
+///
+/// # Type Parameters
+///
+/// The type parameters are the same as those of `MatchingSecondaryIndex`.
+///
+pub struct NotMatchingSecondaryIndex<'a, Q, ChunkKey, Element, IndexKeys, IndexKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey + Borrow<IndexKey>,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+{
+    query: Q,
+    secondary_index: SecondaryIndex<ChunkKey, Element, IndexKeys, IndexKey>,
+    index_key: Cow<'a, IndexKey>,
+}
+
+impl<'a, Q, ChunkKey, Element, IndexKeys, IndexKey> Clone
+    for NotMatchingSecondaryIndex<'a, Q, ChunkKey, Element, IndexKeys, IndexKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey + Borrow<IndexKey>,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+    Q: Clone,
+{
+    fn clone(&self) -> Self {
+        NotMatchingSecondaryIndex {
+            query: self.query.clone(),
+            secondary_index: self.secondary_index.clone(),
+            index_key: self.index_key.clone(),
+        }
+    }
+}
+
+impl<'a, Q, ChunkKey, Element, IndexKeys, IndexKey>
+    NotMatchingSecondaryIndex<'a, Q, ChunkKey, Element, IndexKeys, IndexKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey + Borrow<IndexKey>,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+{
+    pub(crate) fn new(
+        query: Q,
+        secondary_index: &SecondaryIndex<ChunkKey, Element, IndexKeys, IndexKey>,
+        index_key: Cow<'a, IndexKey>,
+    ) -> Self {
+        NotMatchingSecondaryIndex {
+            query,
+            secondary_index: secondary_index.clone(),
+            index_key,
         }
+    }
+}
+
+impl<'a, Q, ChunkKey, ItemKey, Element, IndexKeys, IndexKey> Query<ChunkKey, ItemKey, Element>
+    for NotMatchingSecondaryIndex<'a, Q, ChunkKey, Element, IndexKeys, IndexKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+    Q: Query<ChunkKey, ItemKey, Element> + Clone,
+{
+    type ChunkIdxSet = Q::ChunkIdxSet;
+    type ItemIdxSet = Difference<Q::ItemIdxSet, Option<Bitset>>;
 
+    fn chunk_idxs(&self, storage: &Storage<ChunkKey, ItemKey, Element>) -> Self::ChunkIdxSet {
+        let result = self.query.chunk_idxs(storage);
+        self.secondary_index.refresh(storage, &result);
         result
     }
 
@@ -354,12 +1870,171 @@ where
             })
             .cloned();
 
+        IdxSet::difference(parent_idxs, ours_idxs)
+    }
+
+    fn test(&self, element: &Element) -> bool {
+        self.query.test(element)
+    }
+
+    fn test_chunk(&self, chunk_key: &ChunkKey) -> bool {
+        self.query.test_chunk(chunk_key)
+    }
+
+    fn is_exact(&self) -> bool {
+        self.query.is_exact()
+    }
+
+    fn is_chunk_local(&self) -> bool {
+        self.query.is_chunk_local()
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "NotMatching({}, SecondaryIndex<{}>)",
+            self.query.describe(),
+            short_type_name::<IndexKey>()
+        )
+    }
+}
+
+/// A Query matching any of several index keys against a `SecondaryIndex`.
+/// Construct using `Query::matching_any`.
+///
+/// # Type Parameters
+///
+/// The type parameters are the same as those of `MatchingSecondaryIndex`.
+///
+pub struct MatchingAny<'a, Q, ChunkKey, Element, IndexKeys, IndexKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey + Borrow<IndexKey>,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+{
+    query: Q,
+    secondary_index: SecondaryIndex<ChunkKey, Element, IndexKeys, IndexKey>,
+    index_keys: Vec<Cow<'a, IndexKey>>,
+}
+
+impl<'a, Q, ChunkKey, Element, IndexKeys, IndexKey> Clone
+    for MatchingAny<'a, Q, ChunkKey, Element, IndexKeys, IndexKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey + Borrow<IndexKey>,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+    Q: Clone,
+{
+    fn clone(&self) -> Self {
+        MatchingAny {
+            query: self.query.clone(),
+            secondary_index: self.secondary_index.clone(),
+            index_keys: self.index_keys.clone(),
+        }
+    }
+}
+
+impl<'a, Q, ChunkKey, Element, IndexKeys, IndexKey>
+    MatchingAny<'a, Q, ChunkKey, Element, IndexKeys, IndexKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey + Borrow<IndexKey>,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+{
+    pub(crate) fn new<I>(
+        query: Q,
+        secondary_index: &SecondaryIndex<ChunkKey, Element, IndexKeys, IndexKey>,
+        index_keys: I,
+    ) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<Cow<'a, IndexKey>>,
+    {
+        MatchingAny {
+            query,
+            secondary_index: secondary_index.clone(),
+            index_keys: index_keys.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl<'a, Q, ChunkKey, ItemKey, Element, IndexKeys, IndexKey> Query<ChunkKey, ItemKey, Element>
+    for MatchingAny<'a, Q, ChunkKey, Element, IndexKeys, IndexKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+    Q: Query<ChunkKey, ItemKey, Element> + Clone,
+{
+    type ChunkIdxSet = Q::ChunkIdxSet;
+    type ItemIdxSet = Intersection<Q::ItemIdxSet, Option<Bitset>>;
+
+    fn chunk_idxs(&self, storage: &Storage<ChunkKey, ItemKey, Element>) -> Self::ChunkIdxSet {
+        let result = self.query.chunk_idxs(storage);
+        self.secondary_index.refresh(storage, &result);
+        result
+    }
+
+    fn item_idxs(
+        &self,
+        chunk_key: &ChunkKey,
+        chunk_storage: &ChunkStorage<ChunkKey, ItemKey, Element>,
+    ) -> Self::ItemIdxSet {
+        let secondary_index_impl = self.secondary_index.0.read().unwrap();
+        let parent_idxs = self.query.item_idxs(chunk_key, chunk_storage);
+        let ours_idxs: Option<Bitset> =
+            secondary_index_impl
+                .index
+                .get(chunk_key)
+                .and_then(|map_summarize| {
+                    let reverse_index = &map_summarize.peek().reverse_index;
+
+                    self.index_keys
+                        .iter()
+                        .filter_map(|index_key| reverse_index.get(index_key.borrow()))
+                        .fold(None, |result: Option<Bitset>, idx_set| match result {
+                            Some(result) => Some(result.merge(idx_set)),
+                            None => Some(idx_set.clone()),
+                        })
+                });
+
         IdxSet::intersection(parent_idxs, ours_idxs)
     }
 
     fn test(&self, element: &Element) -> bool {
         self.query.test(element)
     }
+
+    fn test_chunk(&self, chunk_key: &ChunkKey) -> bool {
+        self.query.test_chunk(chunk_key)
+    }
+
+    fn is_exact(&self) -> bool {
+        self.query.is_exact()
+    }
+
+    fn is_chunk_local(&self) -> bool {
+        self.query.is_chunk_local()
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "MatchingAny({}, SecondaryIndex<{}>, {} keys)",
+            self.query.describe(),
+            short_type_name::<IndexKey>(),
+            self.index_keys.len()
+        )
+    }
 }
 
 #[cfg(test)]
@@ -443,6 +2118,8 @@ where
     fn default() -> Self {
         ChunkSecondaryIndex {
             reverse_index: HashMap::default(),
+            ordered_keys: None,
+            changed_keys: Vec::new(),
         }
     }
 }
@@ -523,6 +2200,10 @@ where
             result = MemoryUsage::merge(result, bs.memory_usage());
         }
 
+        if let Some(ordered_keys) = self.ordered_keys.as_ref() {
+            result = MemoryUsage::merge(result, ordered_keys.memory_usage());
+        }
+
         result
     }
 
@@ -546,9 +2227,11 @@ where
 {
     fn memory_usage(&self) -> MemoryUsage {
         let mut result = self.gc_chunk_list.memory_usage();
+        result = MemoryUsage::merge(result, self.index.memory_usage());
 
         for s in self.index.values() {
             result = MemoryUsage::merge(result, s.memory_usage());
+            result = MemoryUsage::merge(result, s.peek().memory_usage());
         }
 
         result
@@ -556,9 +2239,11 @@ where
 
     fn shrink_with<F: Fn(&MemoryUsage) -> Option<usize>>(&mut self, f: F) {
         self.gc_chunk_list.shrink_with(&f);
+        self.index.shrink_with(&f);
 
         for i in self.index.values_mut() {
             i.shrink_with(&f);
+            i.peek_mut().shrink_with(&f);
         }
     }
 }
@@ -0,0 +1,291 @@
+use crate::queries::everything::Everything;
+use crate::queries::secondary_index::{KeySet, MatchingSecondaryIndex, SecondaryIndex};
+use crate::traits::memory_usage::{MemoryUsage, MemoryUser};
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::storage::Storage;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::sync::RwLock;
+
+type IndexingRule<Element, IndexKeys> =
+    Arc<dyn Fn(&Element) -> Cow<IndexKeys> + Send + Sync + 'static>;
+// The label and index of a registered storage.
+type Member<Label, ChunkKey, Element, IndexKeys, IndexKey> = (
+    Label,
+    SecondaryIndex<ChunkKey, Element, IndexKeys, IndexKey>,
+);
+
+/// One logical secondary index over several `Storages` of the same type, such as one `Storage`
+/// per tenant. Register each `Storage` with a label, then query any of them at once; each
+/// result is paired with the label of the `Storage` it came from.
+///
+/// Each registered `Storage` gets its own `SecondaryIndex`, built from the same indexing rule
+/// and kept up to date in the same way, so a `SharedIndex` costs no more than indexing each
+/// `Storage` separately.
+///
+/// # Type Parameters
+///
+/// * `Label`: The type of the label of each registered `Storage`, such as a tenant id.
+/// * `ChunkKey`: The chunk key type of the `Storages`.
+/// * `Element`: The element type of the `Storages`.
+/// * `IndexKeys`: A collection containing the type parameter `IndexKey`, as for a `SecondaryIndex`.
+/// * `IndexKey`: The type of the secondary index key.
+///
+/// # Panic
+///
+/// Querying a `Storage` that hasn't been registered will panic.
+///
+/// # Example
+///
+/// ```
+/// use retriever::prelude::*;
+/// use retriever::queries::shared_index::SharedIndex;
+/// use std::borrow::Cow;
+///
+/// // One storage of orders per tenant, chunked by customer, keyed by order id.
+/// type Order = (u64, u64, &'static str);
+/// let mut acme : Storage<u64, u64, Order> = Storage::new();
+/// let mut globex : Storage<u64, u64, Order> = Storage::new();
+///
+/// let by_status : SharedIndex<&'static str, u64, Order, Option<&'static str>, &'static str> =
+///   SharedIndex::new(|x: &Order| Cow::Owned(Some(x.2)));
+/// by_status.register(&acme, "acme");
+/// by_status.register(&globex, "globex");
+///
+/// acme.add((1, 1, "failed"));
+/// acme.add((1, 2, "ok"));
+/// globex.add((7, 1, "failed"));
+/// globex.add((8, 2, "failed"));
+///
+/// let mut failed : Vec<(&'static str, u64)> = by_status
+///   .matching(vec![&acme, &globex], &"failed")
+///   .into_iter()
+///   .map(|(tenant, x)| (tenant, x.0))
+///   .collect();
+/// failed.sort();
+/// assert_eq!(vec![("acme", 1), ("globex", 7), ("globex", 8)], failed);
+///
+/// # acme.validate();
+/// # globex.validate();
+/// # by_status.validate(vec![&acme, &globex]);
+/// ```
+#[allow(clippy::type_complexity)]
+pub struct SharedIndex<Label, ChunkKey, Element, IndexKeys, IndexKey>(
+    Arc<RwLock<SharedIndexImpl<Label, ChunkKey, Element, IndexKeys, IndexKey>>>,
+)
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>;
+
+impl<Label, ChunkKey, Element, IndexKeys, IndexKey> Clone
+    for SharedIndex<Label, ChunkKey, Element, IndexKeys, IndexKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+{
+    fn clone(&self) -> Self {
+        SharedIndex(Arc::clone(&self.0))
+    }
+}
+
+struct SharedIndexImpl<Label, ChunkKey, Element, IndexKeys, IndexKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+{
+    // rule for constructing index keys, shared by the index of every registered storage
+    rule: IndexingRule<Element, IndexKeys>,
+    // the label and index of each registered storage, by storage id
+    members: HashMap<u64, Member<Label, ChunkKey, Element, IndexKeys, IndexKey>>,
+}
+
+impl<Label, ChunkKey, Element, IndexKeys, IndexKey>
+    SharedIndex<Label, ChunkKey, Element, IndexKeys, IndexKey>
+where
+    Label: Clone,
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+{
+    /// Create a new SharedIndex with no registered `Storages`. The indexing rule works the same
+    /// way as the rule of `SecondaryIndex::new`.
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(&Element) -> Cow<IndexKeys> + Send + Sync + 'static,
+    {
+        SharedIndex(Arc::new(RwLock::new(SharedIndexImpl {
+            rule: Arc::new(f),
+            members: HashMap::new(),
+        })))
+    }
+
+    /// Register a `Storage` with this index under the given label. If the `Storage` was already
+    /// registered, only its label is replaced.
+    pub fn register<ItemKey>(&self, storage: &Storage<ChunkKey, ItemKey, Element>, label: Label)
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey> + 'static,
+        IndexKeys: 'static,
+    {
+        let mut shared_index_impl = self.0.write().unwrap();
+        let rule = Arc::clone(&shared_index_impl.rule);
+
+        match shared_index_impl.members.get_mut(&storage.id()) {
+            Some(member) => member.0 = label,
+            None => {
+                let secondary_index =
+                    SecondaryIndex::new(storage, move |element: &Element| (rule)(element));
+                shared_index_impl
+                    .members
+                    .insert(storage.id(), (label, secondary_index));
+            }
+        }
+    }
+
+    /// Stop indexing a `Storage`, releasing its index, and return the label it was registered
+    /// under.
+    pub fn unregister<ItemKey>(
+        &self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+    ) -> Option<Label>
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        self.0
+            .write()
+            .unwrap()
+            .members
+            .remove(&storage.id())
+            .map(|(label, _)| label)
+    }
+
+    /// The label that a `Storage` was registered under, if any.
+    pub fn label_of<ItemKey>(&self, storage: &Storage<ChunkKey, ItemKey, Element>) -> Option<Label>
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        self.0
+            .read()
+            .unwrap()
+            .members
+            .get(&storage.id())
+            .map(|(label, _)| label.clone())
+    }
+
+    /// Every element of the given `Storages` with the given index key, each paired with the label
+    /// of the `Storage` it came from.
+    pub fn matching<'a, ItemKey, I>(
+        &self,
+        storages: I,
+        index_key: &IndexKey,
+    ) -> Vec<(Label, &'a Element)>
+    where
+        ItemKey: BorrowedKey + ?Sized + 'a,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey> + 'a,
+        ChunkKey: 'a,
+        IndexKeys: 'a,
+        IndexKey: 'a,
+        I: IntoIterator<Item = &'a Storage<ChunkKey, ItemKey, Element>>,
+    {
+        let mut result = Vec::new();
+
+        for storage in storages {
+            let (label, secondary_index) = self.member_of(storage);
+            let query = MatchingSecondaryIndex::new(
+                Everything,
+                &secondary_index,
+                Cow::Owned(index_key.to_owned()),
+            );
+            result.extend(storage.query(query).map(|element| (label.clone(), element)));
+        }
+
+        result
+    }
+
+    /// Panic if this storage is malformed or broken in any detectable way.
+    /// This is a slow operation and you shouldn't use it unless you suspect a problem.
+    pub fn validate<'a, ItemKey, I>(&self, storages: I)
+    where
+        ItemKey: BorrowedKey + ?Sized + 'a,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey> + 'a,
+        ChunkKey: 'a,
+        IndexKeys: 'a,
+        I: IntoIterator<Item = &'a Storage<ChunkKey, ItemKey, Element>>,
+    {
+        for storage in storages {
+            self.member_of(storage).1.validate(storage);
+        }
+    }
+
+    fn member_of<ItemKey>(
+        &self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+    ) -> (
+        Label,
+        SecondaryIndex<ChunkKey, Element, IndexKeys, IndexKey>,
+    )
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        self.0
+            .read()
+            .unwrap()
+            .members
+            .get(&storage.id())
+            .cloned()
+            .expect("a Storage must be registered with a SharedIndex before it can be queried")
+    }
+}
+
+impl<Label, ChunkKey, Element, IndexKeys, IndexKey> MemoryUser
+    for SharedIndex<Label, ChunkKey, Element, IndexKeys, IndexKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+{
+    fn memory_usage(&self) -> MemoryUsage {
+        let shared_index_impl = self.0.read().unwrap();
+        let mut result = shared_index_impl.members.memory_usage();
+
+        for (_, secondary_index) in shared_index_impl.members.values() {
+            result = MemoryUsage::merge(result, secondary_index.memory_usage());
+        }
+
+        result
+    }
+
+    fn shrink_with<F: Fn(&MemoryUsage) -> Option<usize>>(&mut self, f: F) {
+        let mut shared_index_impl = self.0.write().unwrap();
+        shared_index_impl.members.shrink_with(&f);
+
+        for (_, secondary_index) in shared_index_impl.members.values_mut() {
+            secondary_index.shrink_with(&f);
+        }
+    }
+}
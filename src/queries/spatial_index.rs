@@ -0,0 +1,339 @@
+use crate::bits::Bitset;
+use crate::idxsets::intersection::Intersection;
+use crate::queries::ordered_secondary_index::OrderedSecondaryIndex;
+use crate::traits::idxset::IdxSet;
+use crate::traits::memory_usage::{MemoryUsage, MemoryUser};
+use crate::traits::query::Query;
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::chunk_storage::ChunkStorage;
+use crate::types::storage::Storage;
+use std::borrow::Cow;
+use std::ops::Bound;
+use std::sync::Arc;
+
+// The index key of a SpatialIndex: the column and row of the grid cell containing an element.
+type Cell = (i64, i64);
+type Position<Element> = Arc<dyn Fn(&Element) -> (f64, f64) + Send + Sync + 'static>;
+
+/// An index of the elements of a `Storage` by their position on a plane, such as the
+/// entities of a game world, which can be matched against a rectangle or a circle using
+/// `Query::matching_spatial`.
+///
+/// The plane is divided into a grid of square cells, and each element is indexed under the cell
+/// containing its position. A query only visits the elements of the cells that overlap the
+/// area being searched, then tests the exact position of each. Choose a cell size close to
+/// the size of a typical search: much smaller cells make each search visit many cells, while
+/// much larger cells make it test many elements that are out of range.
+///
+/// # Type Parameters
+///
+/// * `ChunkKey`: The chunk key type of the `Storage`.
+/// * `Element`: The element type of the `Storage`.
+///
+/// # Example
+///
+/// ```
+/// use retriever::prelude::*;
+/// use retriever::queries::spatial_index::{SpatialIndex, SpatialPattern};
+///
+/// // Entities chunked by zone, keyed by id, with an (x, y) position.
+/// type Entity = (u64, u64, (f64, f64));
+/// let mut storage : Storage<u64, u64, Entity> = Storage::new();
+/// let by_position : SpatialIndex<u64, Entity> =
+///   SpatialIndex::new(&storage, 10.0, |x: &Entity| x.2);
+///
+/// storage.add((1, 1, (0.0, 0.0)));
+/// storage.add((1, 2, (3.0, 4.0)));
+/// storage.add((1, 3, (25.0, 25.0)));
+/// storage.add((2, 4, (-4.0, 2.0)));
+///
+/// let near = Everything.matching_spatial(
+///   &by_position,
+///   SpatialPattern::Radius { center: (0.0, 0.0), radius: 5.0 });
+/// let mut ids : Vec<u64> = storage.query(near).map(|x| x.1).collect();
+/// ids.sort();
+/// assert_eq!(vec![1, 2, 4], ids);
+///
+/// let northeast = Everything.matching_spatial(
+///   &by_position,
+///   SpatialPattern::Rectangle { min: (1.0, 1.0), max: (30.0, 30.0) });
+/// assert_eq!(2, storage.query(northeast).count());
+///
+/// # storage.validate();
+/// # by_position.validate(&storage);
+/// ```
+pub struct SpatialIndex<ChunkKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+{
+    cells: OrderedSecondaryIndex<ChunkKey, Element, Option<Cell>, Cell>,
+    cell_size: f64,
+    position: Position<Element>,
+}
+
+impl<ChunkKey, Element> Clone for SpatialIndex<ChunkKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+{
+    fn clone(&self) -> Self {
+        SpatialIndex {
+            cells: self.cells.clone(),
+            cell_size: self.cell_size,
+            position: Arc::clone(&self.position),
+        }
+    }
+}
+
+impl<ChunkKey, Element> SpatialIndex<ChunkKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+{
+    /// Create a new SpatialIndex of a storage, with square grid cells of the given size,
+    /// indexing each element at the `(x, y)` position produced by the given rule.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the cell size isn't a positive, finite number.
+    pub fn new<ItemKey, F>(
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        cell_size: f64,
+        f: F,
+    ) -> Self
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        F: Fn(&Element) -> (f64, f64) + Clone + Send + Sync + 'static,
+    {
+        assert!(
+            cell_size > 0.0 && cell_size.is_finite(),
+            "The cell size of a SpatialIndex must be positive and finite"
+        );
+
+        let position_in_rule = f.clone();
+
+        SpatialIndex {
+            cells: OrderedSecondaryIndex::new(storage, move |element: &Element| {
+                Cow::Owned(Some(cell_of(position_in_rule(element), cell_size)))
+            }),
+            cell_size,
+            position: Arc::new(f),
+        }
+    }
+
+    /// The size of each grid cell of this index.
+    pub fn cell_size(&self) -> f64 {
+        self.cell_size
+    }
+
+    /// Panic if this storage is malformed or broken in any detectable way.
+    /// This is a slow operation and you shouldn't use it unless you suspect a problem.
+    pub fn validate<ItemKey>(&self, parent: &Storage<ChunkKey, ItemKey, Element>)
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        self.cells.validate(parent);
+    }
+}
+
+impl<ChunkKey, Element> MemoryUser for SpatialIndex<ChunkKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+{
+    fn memory_usage(&self) -> MemoryUsage {
+        self.cells.memory_usage()
+    }
+
+    fn shrink_with<F: Fn(&MemoryUsage) -> Option<usize>>(&mut self, f: F) {
+        self.cells.shrink_with(f)
+    }
+}
+
+// The grid cell containing the given position.
+fn cell_of(position: (f64, f64), cell_size: f64) -> Cell {
+    (
+        (position.0 / cell_size).floor() as i64,
+        (position.1 / cell_size).floor() as i64,
+    )
+}
+
+/// The area of a `SpatialIndex` to match against, using `Query::matching_spatial`. Elements on
+/// the boundary of the area are included.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SpatialPattern {
+    /// Match elements within the rectangle from the `min` corner to the `max` corner.
+    Rectangle {
+        /// The corner with the least `x` and `y`.
+        min: (f64, f64),
+        /// The corner with the greatest `x` and `y`.
+        max: (f64, f64),
+    },
+    /// Match elements within `radius` of `center`.
+    Radius {
+        /// The center of the circle.
+        center: (f64, f64),
+        /// The radius of the circle.
+        radius: f64,
+    },
+}
+
+impl SpatialPattern {
+    // The least and greatest corners of a rectangle that encloses this area.
+    fn bounds(&self) -> ((f64, f64), (f64, f64)) {
+        match *self {
+            SpatialPattern::Rectangle { min, max } => (min, max),
+            SpatialPattern::Radius { center, radius } => (
+                (center.0 - radius, center.1 - radius),
+                (center.0 + radius, center.1 + radius),
+            ),
+        }
+    }
+
+    // Is the given position within this area?
+    fn contains(&self, position: (f64, f64)) -> bool {
+        match *self {
+            SpatialPattern::Rectangle { min, max } => {
+                min.0 <= position.0
+                    && position.0 <= max.0
+                    && min.1 <= position.1
+                    && position.1 <= max.1
+            }
+            SpatialPattern::Radius { center, radius } => {
+                let dx = position.0 - center.0;
+                let dy = position.1 - center.1;
+                dx * dx + dy * dy <= radius * radius
+            }
+        }
+    }
+}
+
+/// A Query matching a `SpatialPattern` against a `SpatialIndex`. Construct using
+/// `Query::matching_spatial`.
+pub struct MatchingSpatial<Q, ChunkKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+{
+    query: Q,
+    spatial_index: SpatialIndex<ChunkKey, Element>,
+    pattern: SpatialPattern,
+    min_cell: Cell,
+    max_cell: Cell,
+}
+
+impl<Q, ChunkKey, Element> Clone for MatchingSpatial<Q, ChunkKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    Q: Clone,
+{
+    fn clone(&self) -> Self {
+        MatchingSpatial {
+            query: self.query.clone(),
+            spatial_index: self.spatial_index.clone(),
+            pattern: self.pattern,
+            min_cell: self.min_cell,
+            max_cell: self.max_cell,
+        }
+    }
+}
+
+impl<Q, ChunkKey, Element> MatchingSpatial<Q, ChunkKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+{
+    pub(crate) fn new(
+        query: Q,
+        spatial_index: &SpatialIndex<ChunkKey, Element>,
+        pattern: SpatialPattern,
+    ) -> Self {
+        let (min, max) = pattern.bounds();
+
+        MatchingSpatial {
+            query,
+            spatial_index: spatial_index.clone(),
+            pattern,
+            min_cell: cell_of(min, spatial_index.cell_size),
+            max_cell: cell_of(max, spatial_index.cell_size),
+        }
+    }
+
+    // Should the given cell, visited in order, be included or end the search?
+    fn select(&self, cell: &Cell) -> Option<bool> {
+        if cell.0 > self.max_cell.0 {
+            None
+        } else {
+            Some(self.min_cell.1 <= cell.1 && cell.1 <= self.max_cell.1)
+        }
+    }
+}
+
+impl<Q, ChunkKey, ItemKey, Element> Query<ChunkKey, ItemKey, Element>
+    for MatchingSpatial<Q, ChunkKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+    Q: Query<ChunkKey, ItemKey, Element> + Clone,
+{
+    type ChunkIdxSet = Q::ChunkIdxSet;
+    type ItemIdxSet = Intersection<Q::ItemIdxSet, Option<Bitset>>;
+
+    fn chunk_idxs(&self, storage: &Storage<ChunkKey, ItemKey, Element>) -> Self::ChunkIdxSet {
+        let result = self.query.chunk_idxs(storage);
+        self.spatial_index
+            .cells
+            .as_secondary_index()
+            .refresh(storage, &result);
+        result
+    }
+
+    fn item_idxs(
+        &self,
+        chunk_key: &ChunkKey,
+        chunk_storage: &ChunkStorage<ChunkKey, ItemKey, Element>,
+    ) -> Self::ItemIdxSet {
+        let parent_idxs = self.query.item_idxs(chunk_key, chunk_storage);
+        let ours_idxs = self
+            .spatial_index
+            .cells
+            .as_secondary_index()
+            .idxs_of_ordered_keys(chunk_key, Bound::Included(&self.min_cell), |cell| {
+                self.select(cell)
+            });
+
+        IdxSet::intersection(parent_idxs, ours_idxs)
+    }
+
+    fn test(&self, element: &Element) -> bool {
+        self.pattern
+            .contains((self.spatial_index.position)(element))
+            && self.query.test(element)
+    }
+
+    fn test_chunk(&self, chunk_key: &ChunkKey) -> bool {
+        self.query.test_chunk(chunk_key)
+    }
+
+    fn is_chunk_local(&self) -> bool {
+        self.query.is_chunk_local()
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "MatchingSpatial({}, SpatialIndex, {:?})",
+            self.query.describe(),
+            self.pattern
+        )
+    }
+}
@@ -0,0 +1,605 @@
+use crate::bits::Bitset;
+use crate::internal::hasher::HasherImpl;
+use crate::queries::chunks::Chunks;
+use crate::queries::everything::Everything;
+use crate::queries::limit::{first_elements, Chosen};
+use crate::queries::secondary_index::{KeySet, SecondaryIndex};
+use crate::traits::idxset::IdxSet;
+use crate::traits::query::Query;
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::chunk_storage::ChunkStorage;
+use crate::types::error::QueryParseError;
+use crate::types::storage::Storage;
+use std::borrow::{Borrow, Cow};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::iter::Peekable;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+type KeyParser<Key> = Arc<dyn Fn(&str) -> Option<Key> + Send + Sync + 'static>;
+type TermParser<ChunkKey, ItemKey, Element> =
+    Arc<dyn Fn(&str) -> Option<Arc<dyn Term<ChunkKey, ItemKey, Element>>> + Send + Sync + 'static>;
+type Tokens = Peekable<std::vec::IntoIter<(usize, Token)>>;
+
+/// A set of named `SecondaryIndexes` of a single `Storage`, against which textual queries can be
+/// compiled at runtime. This is meant for admin tools and debug consoles, which need to query a
+/// `Storage` without recompiling.
+///
+/// The query language is a list of clauses joined by `AND`, optionally followed by a `LIMIT`:
+///
+/// * `chunk = <value>` visits only the chunk with the given chunk key. Enable this clause with
+///   `QueryLanguage::chunk_keys`.
+/// * `idx:<name> = <value>` matches the `SecondaryIndex` registered under the given name.
+/// * `LIMIT <n>` visits at most `n` elements, just like `Query::limit`.
+///
+/// A value is either a bare word or a double-quoted string, and is parsed using `FromStr`.
+/// An empty query (or one consisting only of a `LIMIT`) visits every element.
+///
+/// # Example
+///
+/// ```
+/// use retriever::prelude::*;
+/// use retriever::queries::text::QueryLanguage;
+/// use std::borrow::Cow;
+///
+/// let mut storage : Storage<String, u64, (String, u64, String)> = Storage::new();
+/// let by_status : SecondaryIndex<String, (String, u64, String), Option<String>, String> =
+///   SecondaryIndex::new(&storage, |x: &(String, u64, String)| Cow::Owned(Some(x.2.clone())));
+///
+/// let language = QueryLanguage::new()
+///   .chunk_keys()
+///   .index("status", &by_status);
+///
+/// storage.add((String::from("2024-05"), 1, String::from("failed")));
+/// storage.add((String::from("2024-05"), 2, String::from("ok")));
+/// storage.add((String::from("2024-05"), 3, String::from("failed")));
+/// storage.add((String::from("2024-06"), 4, String::from("failed")));
+///
+/// let query = language.parse(r#"chunk = "2024-05" AND idx:status = "failed" LIMIT 100"#).unwrap();
+/// assert_eq!(Some(100), query.max_results());
+/// assert_eq!(2, storage.query(&query).count());
+///
+/// let query = language.parse("idx:status = failed LIMIT 2").unwrap();
+/// assert_eq!(2, storage.query(&query).count());
+///
+/// let query = language.parse("idx:status = failed").unwrap();
+/// assert_eq!(3, storage.query(&query).count());
+///
+/// assert!(language.parse("idx:color = red").is_err());
+/// ```
+pub struct QueryLanguage<ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+{
+    chunk_keys: Option<KeyParser<ChunkKey::Owned>>,
+    indexes: HashMap<String, TermParser<ChunkKey, ItemKey, Element>>,
+}
+
+/// A `Query` compiled from text by a `QueryLanguage`.
+pub struct TextQuery<ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+{
+    chunk_keys: Option<Vec<ChunkKey::Owned>>,
+    terms: Vec<Arc<dyn Term<ChunkKey, ItemKey, Element>>>,
+    limit: Option<usize>,
+    // the elements chosen by the `LIMIT`, shared between clones as in `Limit`
+    chosen: Chosen<ChunkKey::Owned>,
+}
+
+/// A single `idx:<name> = <value>` clause, with the types of its `SecondaryIndex` erased.
+trait Term<ChunkKey, ItemKey, Element>: Send + Sync
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+{
+    /// Bring the index up to date for the given chunks, or for every chunk.
+    fn update(
+        &self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        chunk_keys: Option<&[ChunkKey::Owned]>,
+    );
+
+    /// The matching elements of a single chunk.
+    fn item_idxs(
+        &self,
+        chunk_key: &ChunkKey,
+        chunk_storage: &ChunkStorage<ChunkKey, ItemKey, Element>,
+    ) -> Bitset;
+}
+
+struct MatchingTerm<ChunkKey, Element, IndexKeys, IndexKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+{
+    secondary_index: SecondaryIndex<ChunkKey, Element, IndexKeys, IndexKey>,
+    index_key: IndexKey::Owned,
+}
+
+#[derive(Debug)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Equals,
+}
+
+impl<ChunkKey, ItemKey, Element> QueryLanguage<ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+{
+    /// Construct a new `QueryLanguage` with no indexes.
+    pub fn new() -> Self {
+        QueryLanguage {
+            chunk_keys: None,
+            indexes: HashMap::new(),
+        }
+    }
+
+    /// Allow the `chunk = <value>` clause, parsing the value as a chunk key.
+    pub fn chunk_keys(mut self) -> Self
+    where
+        ChunkKey::Owned: FromStr,
+    {
+        self.chunk_keys = Some(Arc::new(|text: &str| text.parse().ok()));
+        self
+    }
+
+    /// Register a `SecondaryIndex` under the given name, so that it can be matched with the
+    /// `idx:<name> = <value>` clause. Registering another index under the same name replaces it.
+    pub fn index<IndexKeys, IndexKey>(
+        mut self,
+        name: &str,
+        secondary_index: &SecondaryIndex<ChunkKey, Element, IndexKeys, IndexKey>,
+    ) -> Self
+    where
+        IndexKey: BorrowedKey + ?Sized,
+        IndexKey::Owned: ValidKey + FromStr + Send + Sync,
+        for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+        SecondaryIndex<ChunkKey, Element, IndexKeys, IndexKey>: Send + Sync + 'static,
+    {
+        let secondary_index = secondary_index.clone();
+
+        self.indexes.insert(
+            name.to_owned(),
+            Arc::new(move |text: &str| {
+                let term: Arc<dyn Term<ChunkKey, ItemKey, Element>> = Arc::new(MatchingTerm {
+                    secondary_index: secondary_index.clone(),
+                    index_key: text.parse().ok()?,
+                });
+
+                Some(term)
+            }),
+        );
+
+        self
+    }
+
+    /// Compile a textual query.
+    pub fn parse(
+        &self,
+        text: &str,
+    ) -> Result<TextQuery<ChunkKey, ItemKey, Element>, QueryParseError> {
+        let mut tokens = tokenize(text)?.into_iter().peekable();
+        let mut query = TextQuery {
+            chunk_keys: None,
+            terms: Vec::new(),
+            limit: None,
+            chosen: Arc::new(Mutex::new(HashMap::with_hasher(HasherImpl::default()))),
+        };
+
+        if tokens.peek().is_some() && !is_keyword(tokens.peek(), "LIMIT") {
+            loop {
+                self.parse_clause(&mut tokens, &mut query)?;
+
+                if !is_keyword(tokens.peek(), "AND") {
+                    break;
+                }
+
+                tokens.next();
+            }
+        }
+
+        if is_keyword(tokens.peek(), "LIMIT") {
+            tokens.next();
+            let (position, limit) = value(tokens.next())?;
+            query.limit = Some(
+                limit
+                    .parse()
+                    .map_err(|_| QueryParseError::InvalidValue { position })?,
+            );
+        }
+
+        if let Some((position, _)) = tokens.next() {
+            return Err(QueryParseError::UnexpectedToken { position });
+        }
+
+        Ok(query)
+    }
+
+    fn parse_clause(
+        &self,
+        tokens: &mut Tokens,
+        query: &mut TextQuery<ChunkKey, ItemKey, Element>,
+    ) -> Result<(), QueryParseError> {
+        let field = match tokens.next() {
+            Some((_, Token::Word(field))) => field,
+            Some((position, _)) => return Err(QueryParseError::UnexpectedToken { position }),
+            None => return Err(QueryParseError::UnexpectedEnd),
+        };
+
+        match tokens.next() {
+            Some((_, Token::Equals)) => {}
+            Some((position, _)) => return Err(QueryParseError::UnexpectedToken { position }),
+            None => return Err(QueryParseError::UnexpectedEnd),
+        }
+
+        let (position, text) = value(tokens.next())?;
+
+        if field == "chunk" {
+            let parser = self
+                .chunk_keys
+                .as_ref()
+                .ok_or(QueryParseError::UnknownField { name: field })?;
+            let chunk_key = (parser)(&text).ok_or(QueryParseError::InvalidValue { position })?;
+
+            // Two different chunk clauses can never both match.
+            query.chunk_keys = Some(match query.chunk_keys.take() {
+                Some(chunk_keys) => chunk_keys.into_iter().filter(|k| *k == chunk_key).collect(),
+                None => vec![chunk_key],
+            });
+        } else if let Some(parser) = field
+            .strip_prefix("idx:")
+            .and_then(|name| self.indexes.get(name))
+        {
+            let term = (parser)(&text).ok_or(QueryParseError::InvalidValue { position })?;
+            query.terms.push(term);
+        } else {
+            return Err(QueryParseError::UnknownField { name: field });
+        }
+
+        Ok(())
+    }
+}
+
+impl<ChunkKey, ItemKey, Element> Default for QueryLanguage<ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+{
+    fn default() -> Self {
+        QueryLanguage::new()
+    }
+}
+
+impl<ChunkKey, ItemKey, Element> TextQuery<ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+{
+    /// The maximum number of elements requested by the `LIMIT` clause, if any.
+    pub fn max_results(&self) -> Option<usize> {
+        self.limit
+    }
+}
+
+impl<ChunkKey, ItemKey, Element> Clone for TextQuery<ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+{
+    fn clone(&self) -> Self {
+        TextQuery {
+            chunk_keys: self.chunk_keys.clone(),
+            terms: self.terms.clone(),
+            limit: self.limit,
+            chosen: self.chosen.clone(),
+        }
+    }
+}
+
+impl<ChunkKey, ItemKey, Element> Query<ChunkKey, ItemKey, Element>
+    for TextQuery<ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+{
+    type ChunkIdxSet = Bitset;
+    type ItemIdxSet = Bitset;
+
+    fn chunk_idxs(&self, storage: &Storage<ChunkKey, ItemKey, Element>) -> Self::ChunkIdxSet {
+        for term in self.terms.iter() {
+            term.update(storage, self.chunk_keys.as_deref());
+        }
+
+        let chunk_idxs: Bitset = match self.chunk_keys.as_ref() {
+            Some(chunk_keys) => chunk_keys
+                .iter()
+                .filter_map(|chunk_key| storage.internal_idx_of(chunk_key.borrow()))
+                .collect(),
+            None => (0..storage.internal_rvec().len()).collect(),
+        };
+
+        let limit = match self.limit {
+            Some(limit) => limit,
+            None => return chunk_idxs,
+        };
+
+        let unlimited = TextQuery {
+            chunk_keys: self.chunk_keys.clone(),
+            terms: self.terms.clone(),
+            limit: None,
+            chosen: self.chosen.clone(),
+        };
+        let chosen = first_elements(storage, &unlimited, chunk_idxs, limit);
+        let chosen_chunk_idxs = chosen
+            .keys()
+            .filter_map(|chunk_key| storage.internal_idx_of(chunk_key.borrow()))
+            .collect();
+
+        *self.chosen.lock().unwrap() = chosen;
+        chosen_chunk_idxs
+    }
+
+    fn item_idxs(
+        &self,
+        chunk_key: &ChunkKey,
+        chunk_storage: &ChunkStorage<ChunkKey, ItemKey, Element>,
+    ) -> Self::ItemIdxSet {
+        if self.limit.is_some() {
+            return self
+                .chosen
+                .lock()
+                .unwrap()
+                .get(chunk_key)
+                .cloned()
+                .unwrap_or_default();
+        }
+
+        let mut terms = self.terms.iter();
+
+        let mut result = match terms.next() {
+            Some(term) => term.item_idxs(chunk_key, chunk_storage),
+            None => return (0..chunk_storage.len()).collect(),
+        };
+
+        for term in terms {
+            result = to_bitset(result.intersection(term.item_idxs(chunk_key, chunk_storage)));
+        }
+
+        result
+    }
+
+    fn test(&self, _element: &Element) -> bool {
+        true
+    }
+
+    fn test_chunk(&self, chunk_key: &ChunkKey) -> bool {
+        match self.chunk_keys.as_ref() {
+            Some(chunk_keys) => chunk_keys.iter().any(|x| x.borrow() == chunk_key),
+            None => true,
+        }
+    }
+
+    fn is_chunk_local(&self) -> bool {
+        self.limit.is_none()
+    }
+}
+
+impl<ChunkKey, ItemKey, Element, IndexKeys, IndexKey> Term<ChunkKey, ItemKey, Element>
+    for MatchingTerm<ChunkKey, Element, IndexKeys, IndexKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey + Send + Sync,
+    Element: Record<ChunkKey, ItemKey>,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+    SecondaryIndex<ChunkKey, Element, IndexKeys, IndexKey>: Send + Sync,
+{
+    fn update(
+        &self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        chunk_keys: Option<&[ChunkKey::Owned]>,
+    ) {
+        let index_key = Cow::Borrowed(self.index_key.borrow());
+
+        match chunk_keys {
+            Some(chunk_keys) => {
+                Chunks(chunk_keys.to_vec())
+                    .matching(&self.secondary_index, index_key)
+                    .chunk_idxs(storage);
+            }
+            None => {
+                Everything
+                    .matching(&self.secondary_index, index_key)
+                    .chunk_idxs(storage);
+            }
+        }
+    }
+
+    fn item_idxs(
+        &self,
+        chunk_key: &ChunkKey,
+        chunk_storage: &ChunkStorage<ChunkKey, ItemKey, Element>,
+    ) -> Bitset {
+        to_bitset(
+            Everything
+                .matching(
+                    &self.secondary_index,
+                    Cow::Borrowed(self.index_key.borrow()),
+                )
+                .item_idxs(chunk_key, chunk_storage),
+        )
+    }
+}
+
+fn to_bitset<I: IdxSet>(idx_set: I) -> Bitset {
+    idx_set.into_idx_iter().flatten().collect()
+}
+
+fn is_keyword(token: Option<&(usize, Token)>, keyword: &str) -> bool {
+    match token {
+        Some((_, Token::Word(word))) => word.eq_ignore_ascii_case(keyword),
+        _ => false,
+    }
+}
+
+fn value(token: Option<(usize, Token)>) -> Result<(usize, String), QueryParseError> {
+    match token {
+        Some((position, Token::Word(text))) | Some((position, Token::Quoted(text))) => {
+            Ok((position, text))
+        }
+        Some((position, Token::Equals)) => Err(QueryParseError::UnexpectedToken { position }),
+        None => Err(QueryParseError::UnexpectedEnd),
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>, QueryParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+
+    while let Some(&(position, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '=' {
+            chars.next();
+            tokens.push((position, Token::Equals));
+        } else if c == '"' {
+            chars.next();
+            let mut quoted = String::new();
+
+            loop {
+                match chars.next() {
+                    Some((_, '"')) => break,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, c)) => quoted.push(c),
+                        None => return Err(QueryParseError::UnterminatedString { position }),
+                    },
+                    Some((_, c)) => quoted.push(c),
+                    None => return Err(QueryParseError::UnterminatedString { position }),
+                }
+            }
+
+            tokens.push((position, Token::Quoted(quoted)));
+        } else {
+            let mut word = String::new();
+
+            while let Some(&(_, c)) = chars.peek() {
+                if c.is_whitespace() || c == '=' || c == '"' {
+                    break;
+                }
+
+                word.push(c);
+                chars.next();
+            }
+
+            tokens.push((position, Token::Word(word)));
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    type Language = QueryLanguage<u64, u64, (u64, u64, String)>;
+
+    #[test]
+    fn test_parse_errors() {
+        let language = Language::new().chunk_keys();
+
+        assert_eq!(
+            Err(QueryParseError::UnexpectedEnd),
+            language.parse("chunk =").map(|q| q.max_results())
+        );
+        assert_eq!(
+            Err(QueryParseError::InvalidValue { position: 8 }),
+            language.parse("chunk = x").map(|q| q.max_results())
+        );
+        assert_eq!(
+            Err(QueryParseError::UnterminatedString { position: 8 }),
+            language.parse("chunk = \"1").map(|q| q.max_results())
+        );
+        assert_eq!(
+            Err(QueryParseError::UnknownField {
+                name: String::from("idx:status")
+            }),
+            language.parse("idx:status = 1").map(|q| q.max_results())
+        );
+        assert_eq!(
+            Err(QueryParseError::UnexpectedToken { position: 18 }),
+            language
+                .parse("chunk = 1 LIMIT 3 AND")
+                .map(|q| q.max_results())
+        );
+        assert_eq!(
+            Ok(Some(3)),
+            language.parse("limit 3").map(|q| q.max_results())
+        );
+        assert_eq!(Ok(None), language.parse("").map(|q| q.max_results()));
+    }
+
+    #[test]
+    fn test_conflicting_chunks_match_nothing() {
+        let mut storage: Storage<u64, u64, (u64, u64, String)> = Storage::new();
+        let language = Language::new().chunk_keys();
+
+        storage.add((1, 1, String::from("a")));
+        storage.add((2, 1, String::from("b")));
+
+        let query = language.parse("chunk = 1 AND chunk = 2").unwrap();
+        assert_eq!(0, storage.query(&query).count());
+
+        let query = language.parse("chunk = 1 AND chunk = \"1\"").unwrap();
+        assert_eq!(1, storage.query(&query).count());
+    }
+
+    #[test]
+    fn test_limit_chooses_the_same_elements_however_run() {
+        let mut storage: Storage<u64, u64, (u64, u64, String)> = Storage::new();
+        let language = Language::new();
+
+        for i in 0..8 {
+            storage.add((i / 4, i, String::new()));
+        }
+
+        let query = language.parse("LIMIT 3").unwrap();
+        let first: Vec<u64> = storage.query(&query).map(|x| x.1).collect();
+        assert_eq!(vec![0, 1, 2], first);
+
+        assert_eq!(3, storage.query(&query).zip(storage.query(&query)).count());
+        assert_eq!(0, storage.query(query.clone().and(Chunks([1]))).count());
+    }
+}
@@ -0,0 +1,366 @@
+use crate::bits::Bitset;
+use crate::idxsets::intersection::Intersection;
+use crate::queries::ordered_secondary_index::OrderedSecondaryIndex;
+use crate::traits::idxset::IdxSet;
+use crate::traits::memory_usage::{MemoryUsage, MemoryUser};
+use crate::traits::query::Query;
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::chunk_storage::ChunkStorage;
+use crate::types::storage::Storage;
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::ops::Bound;
+use std::sync::Arc;
+
+/// An inverted index of the words (or any other tokens) of the elements of a `Storage`, which
+/// can be matched against a word, a prefix of a word, any predicate on words, or any
+/// combination of these using `Query::matching_text`.
+///
+/// A `TextIndex` only knows about the tokens produced by its token extractor, so it can't find
+/// arbitrary substrings. `TextIndex::words` splits a string field on anything that isn't
+/// alphanumeric, which is what you want for log messages and the like.
+///
+/// # Example
+///
+/// ```
+/// use retriever::prelude::*;
+/// use retriever::queries::text_index::TextPattern;
+///
+/// // Log entries chunked by day, keyed by sequence number.
+/// let mut storage : Storage<u64, u64, (u64, u64, String)> = Storage::new();
+/// let by_word : TextIndex<u64, (u64, u64, String)> =
+///   TextIndex::words(&storage, |x: &(u64, u64, String)| x.2.as_str());
+///
+/// storage.add((1, 1, String::from("connection timeout on port 80")));
+/// storage.add((1, 2, String::from("connection established")));
+/// storage.add((2, 3, String::from("request timed out")));
+/// storage.add((2, 4, String::from("disk full")));
+///
+/// assert_eq!(1, storage.query(Everything.matching_text(&by_word, TextPattern::word("timeout"))).count());
+/// assert_eq!(2, storage.query(Everything.matching_text(&by_word, TextPattern::prefix("time"))).count());
+/// assert_eq!(
+///   1,
+///   storage.query(Everything.matching_text(&by_word, TextPattern::matching(|w| w.len() > 10))).count()
+/// );
+///
+/// let timeout_on_port = TextPattern::all(vec![TextPattern::word("timeout"), TextPattern::word("port")]);
+/// assert_eq!(1, storage.query(Everything.matching_text(&by_word, timeout_on_port)).count());
+///
+/// let disk_or_request = TextPattern::any(vec![TextPattern::word("disk"), TextPattern::word("request")]);
+/// assert_eq!(2, storage.query(Everything.matching_text(&by_word, disk_or_request)).count());
+///
+/// # storage.validate();
+/// # by_word.validate(&storage);
+/// ```
+pub struct TextIndex<ChunkKey, Element>(
+    OrderedSecondaryIndex<ChunkKey, Element, BTreeSet<String>, str>,
+)
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey;
+
+impl<ChunkKey, Element> Clone for TextIndex<ChunkKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+{
+    fn clone(&self) -> Self {
+        TextIndex(self.0.clone())
+    }
+}
+
+impl<ChunkKey, Element> TextIndex<ChunkKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+{
+    /// Create a new TextIndex of a storage, indexing each element under the tokens produced by
+    /// the given token extractor.
+    pub fn new<ItemKey, F>(storage: &Storage<ChunkKey, ItemKey, Element>, f: F) -> Self
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        F: Fn(&Element) -> BTreeSet<String> + Clone + Send + Sync + 'static,
+    {
+        TextIndex(OrderedSecondaryIndex::new(
+            storage,
+            move |element: &Element| Cow::Owned(f(element)),
+        ))
+    }
+
+    /// Create a new TextIndex of a storage, indexing each element under the words of a string
+    /// field. Words are separated by any character that isn't alphanumeric, and are case
+    /// sensitive.
+    pub fn words<ItemKey, F>(storage: &Storage<ChunkKey, ItemKey, Element>, f: F) -> Self
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        F: Fn(&Element) -> &str + Clone + Send + Sync + 'static,
+    {
+        Self::new(storage, move |element: &Element| {
+            f(element)
+                .split(|c: char| !c.is_alphanumeric())
+                .filter(|word| !word.is_empty())
+                .map(String::from)
+                .collect()
+        })
+    }
+
+    /// Panic if this storage is malformed or broken in any detectable way.
+    /// This is a slow operation and you shouldn't use it unless you suspect a problem.
+    pub fn validate<ItemKey>(&self, parent: &Storage<ChunkKey, ItemKey, Element>)
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        self.0.validate(parent);
+    }
+}
+
+impl<ChunkKey, Element> MemoryUser for TextIndex<ChunkKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+{
+    fn memory_usage(&self) -> MemoryUsage {
+        self.0.memory_usage()
+    }
+
+    fn shrink_with<F: Fn(&MemoryUsage) -> Option<usize>>(&mut self, f: F) {
+        self.0.shrink_with(f)
+    }
+}
+
+/// The tokens of a `TextIndex` to match against, using `Query::matching_text`.
+#[derive(Clone)]
+pub enum TextPattern<'a> {
+    /// Match elements with exactly this token.
+    Word(Cow<'a, str>),
+    /// Match elements with any token beginning with this prefix.
+    Prefix(Cow<'a, str>),
+    /// Match elements with any token satisfying this predicate, such as a compiled regular
+    /// expression. The predicate is tested against each distinct token of each visited chunk,
+    /// rather than against each element.
+    Matching(Arc<dyn Fn(&str) -> bool + Send + Sync + 'a>),
+    /// Match elements matching every one of these patterns. With no patterns, match every
+    /// element.
+    All(Vec<TextPattern<'a>>),
+    /// Match elements matching at least one of these patterns.
+    Any(Vec<TextPattern<'a>>),
+}
+
+impl<'a> TextPattern<'a> {
+    /// Match elements with exactly this token.
+    pub fn word<S: Into<Cow<'a, str>>>(word: S) -> Self {
+        TextPattern::Word(word.into())
+    }
+
+    /// Match elements with any token beginning with this prefix.
+    pub fn prefix<S: Into<Cow<'a, str>>>(prefix: S) -> Self {
+        TextPattern::Prefix(prefix.into())
+    }
+
+    /// Match elements with any token satisfying this predicate.
+    pub fn matching<F>(f: F) -> Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'a,
+    {
+        TextPattern::Matching(Arc::new(f))
+    }
+
+    /// Match elements matching every one of these patterns, such as several words that must
+    /// all appear.
+    pub fn all<I>(patterns: I) -> Self
+    where
+        I: IntoIterator<Item = TextPattern<'a>>,
+    {
+        TextPattern::All(patterns.into_iter().collect())
+    }
+
+    /// Match elements matching at least one of these patterns.
+    pub fn any<I>(patterns: I) -> Self
+    where
+        I: IntoIterator<Item = TextPattern<'a>>,
+    {
+        TextPattern::Any(patterns.into_iter().collect())
+    }
+
+    // The internal indices of the elements of the given chunk, of `len` elements, that match
+    // this pattern. `None` if the chunk is not indexed.
+    fn idxs<ChunkKey, Element>(
+        &self,
+        text_index: &TextIndex<ChunkKey, Element>,
+        chunk_key: &ChunkKey,
+        len: usize,
+    ) -> Option<Bitset>
+    where
+        ChunkKey: BorrowedKey + ?Sized,
+        ChunkKey::Owned: ValidKey,
+    {
+        match self {
+            TextPattern::All(patterns) => {
+                patterns
+                    .iter()
+                    .try_fold((0..len).collect(), |result: Bitset, pattern| {
+                        Some(result.intersect_bitset(&pattern.idxs(text_index, chunk_key, len)?))
+                    })
+            }
+            TextPattern::Any(patterns) => patterns
+                .iter()
+                .try_fold(Bitset::default(), |result, pattern| {
+                    Some(result.merge(&pattern.idxs(text_index, chunk_key, len)?))
+                }),
+            _ => text_index.0.as_secondary_index().idxs_of_ordered_keys(
+                chunk_key,
+                self.start(),
+                |token| self.select(token),
+            ),
+        }
+    }
+
+    // Should the given token, visited in order, be included, skipped, or end the search?
+    fn select(&self, token: &str) -> Option<bool> {
+        match self {
+            TextPattern::Word(word) if token == word => Some(true),
+            TextPattern::Word(_) => None,
+            TextPattern::Prefix(prefix) if token.starts_with(prefix.as_ref()) => Some(true),
+            TextPattern::Prefix(_) => None,
+            TextPattern::Matching(f) => Some(f(token)),
+            // never visited: see idxs
+            TextPattern::All(_) | TextPattern::Any(_) => None,
+        }
+    }
+
+    // The first token that could possibly be selected.
+    fn start(&self) -> Bound<&str> {
+        match self {
+            TextPattern::Word(word) => Bound::Included(word),
+            TextPattern::Prefix(prefix) => Bound::Included(prefix),
+            TextPattern::Matching(_) | TextPattern::All(_) | TextPattern::Any(_) => {
+                Bound::Unbounded
+            }
+        }
+    }
+}
+
+impl<'a> fmt::Debug for TextPattern<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextPattern::Word(word) => write!(f, "Word({:?})", word),
+            TextPattern::Prefix(prefix) => write!(f, "Prefix({:?})", prefix),
+            TextPattern::Matching(_) => write!(f, "Matching(..)"),
+            TextPattern::All(patterns) => write!(f, "All({:?})", patterns),
+            TextPattern::Any(patterns) => write!(f, "Any({:?})", patterns),
+        }
+    }
+}
+
+/// A Query matching a `TextPattern` against a `TextIndex`. Construct using
+/// `Query::matching_text`.
+pub struct MatchingText<'a, Q, ChunkKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+{
+    query: Q,
+    text_index: TextIndex<ChunkKey, Element>,
+    pattern: TextPattern<'a>,
+}
+
+impl<'a, Q, ChunkKey, Element> Clone for MatchingText<'a, Q, ChunkKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    Q: Clone,
+{
+    fn clone(&self) -> Self {
+        MatchingText {
+            query: self.query.clone(),
+            text_index: self.text_index.clone(),
+            pattern: self.pattern.clone(),
+        }
+    }
+}
+
+impl<'a, Q, ChunkKey, Element> MatchingText<'a, Q, ChunkKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+{
+    pub(crate) fn new(
+        query: Q,
+        text_index: &TextIndex<ChunkKey, Element>,
+        pattern: TextPattern<'a>,
+    ) -> Self {
+        MatchingText {
+            query,
+            text_index: text_index.clone(),
+            pattern,
+        }
+    }
+}
+
+impl<'a, Q, ChunkKey, ItemKey, Element> Query<ChunkKey, ItemKey, Element>
+    for MatchingText<'a, Q, ChunkKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+    Q: Query<ChunkKey, ItemKey, Element> + Clone,
+{
+    type ChunkIdxSet = Q::ChunkIdxSet;
+    type ItemIdxSet = Intersection<Q::ItemIdxSet, Option<Bitset>>;
+
+    fn chunk_idxs(&self, storage: &Storage<ChunkKey, ItemKey, Element>) -> Self::ChunkIdxSet {
+        let result = self.query.chunk_idxs(storage);
+        self.text_index
+            .0
+            .as_secondary_index()
+            .refresh(storage, &result);
+        result
+    }
+
+    fn item_idxs(
+        &self,
+        chunk_key: &ChunkKey,
+        chunk_storage: &ChunkStorage<ChunkKey, ItemKey, Element>,
+    ) -> Self::ItemIdxSet {
+        let parent_idxs = self.query.item_idxs(chunk_key, chunk_storage);
+        let ours_idxs = self
+            .pattern
+            .idxs(&self.text_index, chunk_key, chunk_storage.len());
+
+        IdxSet::intersection(parent_idxs, ours_idxs)
+    }
+
+    fn test(&self, element: &Element) -> bool {
+        self.query.test(element)
+    }
+
+    fn test_chunk(&self, chunk_key: &ChunkKey) -> bool {
+        self.query.test_chunk(chunk_key)
+    }
+
+    fn is_exact(&self) -> bool {
+        self.query.is_exact()
+    }
+
+    fn is_chunk_local(&self) -> bool {
+        self.query.is_chunk_local()
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "MatchingText({}, TextIndex, {:?})",
+            self.query.describe(),
+            self.pattern
+        )
+    }
+}
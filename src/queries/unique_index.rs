@@ -0,0 +1,244 @@
+use crate::queries::secondary_index::{KeySet, SecondaryIndex};
+use crate::traits::memory_usage::{MemoryUsage, MemoryUser};
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::error::UniqueAddError;
+use crate::types::storage::Storage;
+use std::borrow::Borrow;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+/// A `SecondaryIndex` that allows at most one element of the whole `Storage` under each index
+/// key, such as an index of users by email address. Look up that one element using
+/// `UniqueIndex::get_unique`, or match against the index using `Query::matching` on
+/// `UniqueIndex::as_secondary_index`.
+///
+/// A `UniqueIndex` is maintained eagerly, as by `SecondaryIndex::maintain_eagerly`, and
+/// remembers which chunk holds each index key, so `get_unique` costs a few hash lookups no
+/// matter how many chunks the `Storage` has.
+///
+/// Add elements using `UniqueIndex::try_add` to reject an element whose index key is already
+/// taken. `Storage::add` doesn't know about the index, so a second element with the same index
+/// key added that way doesn't fail right away. Instead, `get_unique` panics if it finds more
+/// than one element under the index key it was asked for, and `validate` panics if it finds
+/// any index key shared by more than one element.
+///
+/// # Type Parameters
+///
+/// The type parameters are the same as those of `SecondaryIndex`.
+///
+/// # Example
+///
+/// ```
+/// use retriever::prelude::*;
+/// use retriever::queries::unique_index::UniqueIndex;
+/// use std::borrow::Cow;
+///
+/// // Users chunked by team, keyed by user id, with an email address.
+/// type User = (&'static str, u64, &'static str);
+/// let mut storage : Storage<&'static str, u64, User> = Storage::new();
+/// let by_email : UniqueIndex<&'static str, User, Option<&'static str>, &'static str> =
+///   UniqueIndex::new(&mut storage, |x: &User| Cow::Owned(Some(x.2)));
+///
+/// by_email.try_add(&mut storage, ("red", 1, "alice@example.com")).unwrap();
+/// by_email.try_add(&mut storage, ("red", 2, "bob@example.com")).unwrap();
+/// by_email.try_add(&mut storage, ("blue", 3, "carol@example.com")).unwrap();
+///
+/// assert_eq!(Some(&("blue", 3, "carol@example.com")), by_email.get_unique(&storage, &"carol@example.com"));
+/// assert_eq!(None, by_email.get_unique(&storage, &"dave@example.com"));
+///
+/// // Carol's email address is taken.
+/// let error = by_email.try_add(&mut storage, ("red", 4, "carol@example.com")).unwrap_err();
+/// assert_eq!(&("red", 4, "carol@example.com"), error.element());
+/// assert_eq!(None, storage.get(&ID.chunk("red").item(4)));
+///
+/// # storage.validate();
+/// # by_email.validate(&storage);
+/// ```
+///
+/// ```should_panic
+/// use retriever::prelude::*;
+/// use retriever::queries::unique_index::UniqueIndex;
+/// use std::borrow::Cow;
+///
+/// type User = (&'static str, u64, &'static str);
+/// let mut storage : Storage<&'static str, u64, User> = Storage::new();
+/// let by_email : UniqueIndex<&'static str, User, Option<&'static str>, &'static str> =
+///   UniqueIndex::new(&mut storage, |x: &User| Cow::Owned(Some(x.2)));
+///
+/// storage.add(("red", 1, "alice@example.com"));
+/// storage.add(("blue", 2, "alice@example.com"));
+///
+/// by_email.get_unique(&storage, &"alice@example.com");
+/// ```
+pub struct UniqueIndex<ChunkKey, Element, IndexKeys, IndexKey>(
+    SecondaryIndex<ChunkKey, Element, IndexKeys, IndexKey>,
+)
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>;
+
+impl<ChunkKey, Element, IndexKeys, IndexKey> Clone
+    for UniqueIndex<ChunkKey, Element, IndexKeys, IndexKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+{
+    fn clone(&self) -> Self {
+        UniqueIndex(self.0.clone())
+    }
+}
+
+impl<ChunkKey, Element, IndexKeys, IndexKey> UniqueIndex<ChunkKey, Element, IndexKeys, IndexKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+{
+    /// Create a new UniqueIndex of a storage, and keep it up to date with every write to that
+    /// storage. See `SecondaryIndex::new` and `SecondaryIndex::maintain_eagerly`.
+    pub fn new<ItemKey, F>(storage: &mut Storage<ChunkKey, ItemKey, Element>, f: F) -> Self
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        F: Fn(&Element) -> Cow<IndexKeys> + Clone + Send + Sync + 'static,
+        ChunkKey::Owned: Send + Sync,
+        IndexKeys: Send + Sync,
+        IndexKey::Owned: Send + Sync,
+        SecondaryIndex<ChunkKey, Element, IndexKeys, IndexKey>: 'static,
+    {
+        let index = SecondaryIndex::new_impl(storage, None, false, true, f);
+        index.maintain_eagerly(storage);
+        UniqueIndex(index)
+    }
+
+    /// This same index, for matching against a single index key using `Query::matching`.
+    pub fn as_secondary_index(&self) -> &SecondaryIndex<ChunkKey, Element, IndexKeys, IndexKey> {
+        &self.0
+    }
+
+    /// Get the one element with the given index key, if there is one. This costs a few hash
+    /// lookups, however many chunks the `Storage` has. Changes made through an `Entry` or
+    /// `Storage::query_mut` aren't seen until the next write to the `Storage`.
+    ///
+    /// # Panic
+    ///
+    /// Panics if more than one element has the given index key.
+    pub fn get_unique<'a, ItemKey>(
+        &self,
+        storage: &'a Storage<ChunkKey, ItemKey, Element>,
+        index_key: &IndexKey,
+    ) -> Option<&'a Element>
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        self.0.catch_up(storage);
+
+        let postings = self.0.postings_of(index_key);
+        let count: usize = postings.iter().map(|(_, idxs)| idxs.len()).sum();
+        assert!(
+            count <= 1,
+            "Duplicate index key: {:?} is the index key of {} elements of a UniqueIndex",
+            index_key,
+            count
+        );
+
+        let (chunk_key, idxs) = postings.into_iter().find(|(_, idxs)| !idxs.is_empty())?;
+        let chunk_storage = &storage.internal_rvec()[storage
+            .internal_idx_of(chunk_key.borrow())
+            .expect("a refreshed index should only contain chunks of its Storage")];
+        let idx = idxs.iter().flatten().next()?;
+
+        Some(chunk_storage.get_idx(idx))
+    }
+
+    /// Add the given element to the storage, unless another element already has one of its
+    /// index keys, or an element with the same `Id` is already present. Either way, the
+    /// `Storage` is left unchanged and the element is returned in the error.
+    ///
+    /// # Panic
+    ///
+    /// Panics if more than one element already has one of the index keys of the given element.
+    pub fn try_add<ItemKey>(
+        &self,
+        storage: &mut Storage<ChunkKey, ItemKey, Element>,
+        element: Element,
+    ) -> Result<(), UniqueAddError<Element>>
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        let index_keys = self.0.index_keys_of(&element);
+        let taken = index_keys.iter_keys().any(|index_key| {
+            self.get_unique(storage, index_key.borrow())
+                .map(|other| {
+                    other.chunk_key() != element.chunk_key()
+                        || other.item_key() != element.item_key()
+                })
+                .unwrap_or(false)
+        });
+
+        if taken {
+            return Err(UniqueAddError::DuplicateIndexKey { element });
+        }
+
+        Ok(storage.try_add(element)?)
+    }
+
+    /// Panic if this storage is malformed or broken in any detectable way, or if any index key
+    /// belongs to more than one element.
+    /// This is a slow operation and you shouldn't use it unless you suspect a problem.
+    pub fn validate<ItemKey>(&self, parent: &Storage<ChunkKey, ItemKey, Element>)
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        self.0.validate(parent);
+        self.0.rebuild(parent);
+
+        let mut counts: HashMap<IndexKey::Owned, usize> = HashMap::new();
+        self.0.for_each_posting(|_, index_key, idxs| {
+            *counts.entry(index_key.to_owned()).or_insert(0) += idxs.len();
+        });
+
+        for (index_key, count) in counts {
+            assert_eq!(
+                1, count,
+                "Duplicate index key: {:?} is the index key of {} elements of a UniqueIndex",
+                index_key, count
+            );
+        }
+    }
+}
+
+impl<ChunkKey, Element, IndexKeys, IndexKey> MemoryUser
+    for UniqueIndex<ChunkKey, Element, IndexKeys, IndexKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+{
+    fn memory_usage(&self) -> MemoryUsage {
+        self.0.memory_usage()
+    }
+
+    fn shrink_with<F: Fn(&MemoryUsage) -> Option<usize>>(&mut self, f: F) {
+        self.0.shrink_with(f)
+    }
+}
@@ -0,0 +1,51 @@
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::reduction::Reduction;
+use crate::types::storage::Storage;
+
+/// The number of elements in a `Storage`, as summarized by a `Reduction` constructed using
+/// `Count::reduction`.
+///
+/// # Example
+///
+/// ```
+/// use retriever::prelude::*;
+/// use retriever::reductions::count::Count;
+///
+/// let mut storage : Storage<u64, u64, (u64, u64, &str)> = Storage::new();
+/// let mut count : Reduction<u64, (u64, u64, &str), Count> = Count::reduction(&storage);
+///
+/// storage.add((1, 1, "apple"));
+/// storage.add((1, 2, "banana"));
+/// storage.add((2, 3, "cherry"));
+/// assert_eq!(Some(&Count(3)), count.reduce(&storage));
+/// assert_eq!(Some(&Count(2)), count.reduce_chunk(&storage, &1));
+///
+/// storage.remove(&ID.chunk(1).item(1), std::mem::drop);
+/// assert_eq!(Some(&Count(2)), count.reduce(&storage));
+/// ```
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Count(pub usize);
+
+impl Count {
+    /// Create a new `Reduction` that counts the elements of a `Storage`.
+    pub fn reduction<ChunkKey, ItemKey, Element>(
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+    ) -> Reduction<ChunkKey, Element, Count>
+    where
+        ChunkKey: BorrowedKey + ?Sized,
+        ChunkKey::Owned: ValidKey,
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        Reduction::new_invertible(
+            storage,
+            |_: &Element, was: &Count| Some(Count(1)).filter(|x| x != was),
+            |xs: &[Count], was: &Count| {
+                Some(Count(xs.iter().map(|x| x.0).sum())).filter(|x| x != was)
+            },
+            |total: &Count, x: &Count| Count(total.0 - x.0),
+        )
+    }
+}
@@ -0,0 +1,101 @@
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::id::Id;
+use crate::types::reduction::Reduction;
+use crate::types::storage::Storage;
+
+/// The least and greatest of a value extracted from each element of a `Storage`, each with
+/// the `Id` of an element that it was extracted from, as summarized by a `Reduction` constructed
+/// using `MinMax::reduction`.
+///
+/// If several elements share the least or greatest value, then the one with the least `Id`
+/// is the witness of the least value, and the one with the greatest `Id` is the witness of the
+/// greatest value.
+///
+/// # Example
+///
+/// ```
+/// use retriever::prelude::*;
+/// use retriever::reductions::min_max::MinMax;
+///
+/// // Temperatures chunked by city, keyed by hour.
+/// type Reading = (&'static str, u64, i64);
+/// let mut storage : Storage<&'static str, u64, Reading> = Storage::new();
+/// let mut extremes : Reduction<&'static str, Reading, MinMax<i64, &'static str, u64>> =
+///   MinMax::reduction(&storage, |x: &Reading| x.2);
+///
+/// storage.add(("oslo", 1, -4));
+/// storage.add(("oslo", 2, 3));
+/// storage.add(("rome", 1, 12));
+///
+/// let summary = extremes.reduce(&storage).unwrap();
+/// assert_eq!(Some(&(-4, ID.chunk("oslo").item(1))), summary.min.as_ref());
+/// assert_eq!(Some(&(12, ID.chunk("rome").item(1))), summary.max.as_ref());
+///
+/// storage.remove(&ID.chunk("oslo").item(1), std::mem::drop);
+///
+/// let summary = extremes.reduce(&storage).unwrap();
+/// assert_eq!(Some(&(3, ID.chunk("oslo").item(2))), summary.min.as_ref());
+/// ```
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct MinMax<Value, ChunkKey, ItemKey> {
+    /// The least value, and the `Id` of the element it was extracted from.
+    pub min: Option<(Value, Id<ChunkKey, ItemKey>)>,
+    /// The greatest value, and the `Id` of the element it was extracted from.
+    pub max: Option<(Value, Id<ChunkKey, ItemKey>)>,
+}
+
+impl<Value, ChunkKey, ItemKey> Default for MinMax<Value, ChunkKey, ItemKey> {
+    fn default() -> Self {
+        MinMax {
+            min: None,
+            max: None,
+        }
+    }
+}
+
+impl<Value, ChunkKey, ItemKey> MinMax<Value, ChunkKey, ItemKey>
+where
+    Value: Clone + Ord + Send + Sync + 'static,
+    ChunkKey: ValidKey + Send + Sync + 'static,
+    ItemKey: ValidKey + Send + Sync + 'static,
+{
+    /// Create a new `Reduction` that finds the least and greatest of the value extracted from
+    /// each element of a `Storage` by the given rule.
+    pub fn reduction<BorrowedChunkKey, BorrowedItemKey, Element, F>(
+        storage: &Storage<BorrowedChunkKey, BorrowedItemKey, Element>,
+        f: F,
+    ) -> Reduction<BorrowedChunkKey, Element, Self>
+    where
+        BorrowedChunkKey: BorrowedKey<Owned = ChunkKey> + ?Sized,
+        BorrowedItemKey: BorrowedKey<Owned = ItemKey> + ?Sized,
+        Element: Record<BorrowedChunkKey, BorrowedItemKey>,
+        F: Fn(&Element) -> Value + Clone + Send + Sync + 'static,
+    {
+        Reduction::new(
+            storage,
+            super::GROUP_SIZE,
+            move |element: &Element, was: &Self| {
+                let id = Id(
+                    element.chunk_key().into_owned(),
+                    element.item_key().into_owned(),
+                );
+                let witness = Some((f(element), id));
+                let new = MinMax {
+                    min: witness.clone(),
+                    max: witness,
+                };
+
+                Some(new).filter(|x| x != was)
+            },
+            |xs: &[Self], was: &Self| {
+                let new = MinMax {
+                    min: xs.iter().filter_map(|x| x.min.as_ref()).min().cloned(),
+                    max: xs.iter().filter_map(|x| x.max.as_ref()).max().cloned(),
+                };
+
+                Some(new).filter(|x| x != was)
+            },
+        )
+    }
+}
@@ -1,5 +1,14 @@
-//pub mod sum;
-//pub mod smallest;
-//pub mod largest;
+/// Module for a reduction that counts elements.
+pub mod count;
+/// Module for a reduction that finds the least and greatest of a value extracted from each element.
+pub mod min_max;
+/// Module for a reduction that computes the count, mean and variance of a value extracted from
+/// each element.
+pub mod stats;
+/// Module for a reduction that adds up a value extracted from each element.
+pub mod sum;
 //pub mod any;
 //pub mod set;
+
+// The group size of the built-in reductions that can't subtract a summary back out.
+const GROUP_SIZE: usize = 16;
@@ -0,0 +1,139 @@
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::reduction::Reduction;
+use crate::types::storage::Storage;
+
+/// The count, mean and variance of a value extracted from each element of a `Storage`, as
+/// summarized by a `Reduction` constructed using `Stats::reduction`.
+///
+/// Rather than a sum and a sum of squares, which lose all precision when the values are large
+/// compared to their spread, a `Stats` keeps the mean and the sum of squared differences from
+/// the mean, and merges two `Stats` using Chan's parallel algorithm.
+///
+/// # Example
+///
+/// ```
+/// use retriever::prelude::*;
+/// use retriever::reductions::stats::Stats;
+///
+/// // Latencies chunked by endpoint, keyed by request id.
+/// let mut storage : Storage<u64, u64, (u64, u64, f64)> = Storage::new();
+/// let mut latency : Reduction<u64, (u64, u64, f64), Stats> =
+///   Stats::reduction(&storage, |x: &(u64, u64, f64)| x.2);
+///
+/// storage.add((1, 1, 1e9 + 4.0));
+/// storage.add((1, 2, 1e9 + 7.0));
+/// storage.add((2, 3, 1e9 + 13.0));
+/// storage.add((2, 4, 1e9 + 16.0));
+///
+/// let stats = latency.reduce(&storage).unwrap();
+/// assert_eq!(4, stats.count());
+/// assert_eq!(1e9 + 10.0, stats.mean());
+/// assert_eq!(22.5, stats.population_variance());
+/// assert_eq!(30.0, stats.sample_variance());
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Stats {
+    count: u64,
+    mean: f64,
+    // the sum of the squared differences of each value from the mean
+    m2: f64,
+}
+
+impl Stats {
+    /// The `Stats` of a single value.
+    pub fn of(value: f64) -> Self {
+        Stats {
+            count: 1,
+            mean: value,
+            m2: 0.0,
+        }
+    }
+
+    /// The `Stats` of every value of two `Stats` together.
+    pub fn merge(&self, other: &Stats) -> Self {
+        if self.count == 0 {
+            return *other;
+        }
+
+        if other.count == 0 {
+            return *self;
+        }
+
+        let count = self.count + other.count;
+        let delta = other.mean - self.mean;
+        let weight = other.count as f64 / count as f64;
+
+        Stats {
+            count,
+            mean: self.mean + delta * weight,
+            m2: self.m2 + other.m2 + delta * delta * self.count as f64 * weight,
+        }
+    }
+
+    /// The number of values.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The mean of the values, or NaN if there are none.
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 {
+            f64::NAN
+        } else {
+            self.mean
+        }
+    }
+
+    /// The variance of the values, taken as a whole population, or NaN if there are none.
+    pub fn population_variance(&self) -> f64 {
+        self.m2 / self.count as f64
+    }
+
+    /// The variance of the values, taken as a sample of a larger population, or NaN if there
+    /// are fewer than two.
+    pub fn sample_variance(&self) -> f64 {
+        if self.count < 2 {
+            f64::NAN
+        } else {
+            self.m2 / (self.count - 1) as f64
+        }
+    }
+
+    /// The standard deviation of the values, taken as a whole population, or NaN if there are
+    /// none.
+    pub fn population_std_dev(&self) -> f64 {
+        self.population_variance().sqrt()
+    }
+
+    /// The standard deviation of the values, taken as a sample of a larger population, or NaN if
+    /// there are fewer than two.
+    pub fn sample_std_dev(&self) -> f64 {
+        self.sample_variance().sqrt()
+    }
+
+    /// Create a new `Reduction` that computes the `Stats` of the value extracted from each
+    /// element of a `Storage` by the given rule.
+    pub fn reduction<ChunkKey, ItemKey, Element, F>(
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        f: F,
+    ) -> Reduction<ChunkKey, Element, Stats>
+    where
+        ChunkKey: BorrowedKey + ?Sized,
+        ChunkKey::Owned: ValidKey,
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        F: Fn(&Element) -> f64 + Clone + Send + Sync + 'static,
+    {
+        Reduction::new(
+            storage,
+            super::GROUP_SIZE,
+            move |element: &Element, was: &Stats| Some(Stats::of(f(element))).filter(|x| x != was),
+            |xs: &[Stats], was: &Stats| {
+                let stats = xs.iter().fold(Stats::default(), |stats, x| stats.merge(x));
+                Some(stats).filter(|x| x != was)
+            },
+        )
+    }
+}
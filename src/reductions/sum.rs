@@ -0,0 +1,66 @@
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::reduction::Reduction;
+use crate::types::storage::Storage;
+use std::ops::Add;
+
+/// The sum of a value extracted from each element of a `Storage`, as summarized by a
+/// `Reduction` constructed using `Sum::reduction`.
+///
+/// The values are added together in groups rather than one at a time, so floating point
+/// values are summed without any drift as elements are added and removed.
+///
+/// # Example
+///
+/// ```
+/// use retriever::prelude::*;
+/// use retriever::reductions::sum::Sum;
+///
+/// // Invoices chunked by customer, keyed by invoice number, with an amount due.
+/// let mut storage : Storage<u64, u64, (u64, u64, f64)> = Storage::new();
+/// let mut due : Reduction<u64, (u64, u64, f64), Sum<f64>> =
+///   Sum::reduction(&storage, |x: &(u64, u64, f64)| x.2);
+///
+/// storage.add((1, 1, 10.5));
+/// storage.add((1, 2, 20.0));
+/// storage.add((2, 3, 0.25));
+/// assert_eq!(Some(&Sum(30.75)), due.reduce(&storage));
+///
+/// storage.modify(&ID.chunk(1).item(2), |mut editor| editor.get_mut().2 = 5.0);
+/// assert_eq!(Some(&Sum(15.75)), due.reduce(&storage));
+/// assert_eq!(Some(&Sum(15.5)), due.reduce_chunk(&storage, &1));
+/// ```
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Sum<Value>(pub Value);
+
+impl<Value> Sum<Value>
+where
+    Value: Add<Output = Value> + Clone + Default + PartialEq + Send + Sync + 'static,
+{
+    /// Create a new `Reduction` that adds up the value extracted from each element of a
+    /// `Storage` by the given rule.
+    pub fn reduction<ChunkKey, ItemKey, Element, F>(
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        f: F,
+    ) -> Reduction<ChunkKey, Element, Sum<Value>>
+    where
+        ChunkKey: BorrowedKey + ?Sized,
+        ChunkKey::Owned: ValidKey,
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        F: Fn(&Element) -> Value + Clone + Send + Sync + 'static,
+    {
+        Reduction::new(
+            storage,
+            super::GROUP_SIZE,
+            move |element: &Element, was: &Sum<Value>| Some(Sum(f(element))).filter(|x| x != was),
+            |xs: &[Sum<Value>], was: &Sum<Value>| {
+                let total = xs
+                    .iter()
+                    .fold(Value::default(), |total, x| total + x.0.clone());
+                Some(Sum(total)).filter(|x| x != was)
+            },
+        )
+    }
+}
@@ -0,0 +1,24 @@
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+
+/// A `Record` that is also a conflict-free replicated data type. Two `Elements` with the same
+/// chunk key and item key can always be merged into one.
+///
+/// For a `CrdtStorage` to reach the same state regardless of the order in which it receives
+/// `Elements`, `merge` must be:
+///
+/// * commutative: `a.merge(b) == b.merge(a)`
+/// * associative: `a.merge(b).merge(c) == a.merge(b.merge(c))`
+/// * idempotent: `a.merge(a) == a`
+///
+/// The merged `Element` must keep the same chunk key and item key as its inputs.
+pub trait CrdtElement<ChunkKey, ItemKey>: Record<ChunkKey, ItemKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+{
+    /// Merge two versions of the same `Element` into one.
+    fn merge(self, other: Self) -> Self;
+}
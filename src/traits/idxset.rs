@@ -1,8 +1,77 @@
 use crate::bits::Bitfield;
+use crate::idxsets::difference::Difference;
 use crate::idxsets::intersection::Intersection;
+use crate::idxsets::union::Union;
 use std::iter::Flatten;
 
-/// A set of `usize` indices.
+/// A set of `usize` indices, such as the internal indices of the chunks a `Query` visits, or of
+/// the elements it visits within a chunk.
+///
+/// An `IdxSet` is a sorted sequence of `Bitfields`, each covering a distinct, aligned block of
+/// indices. To implement `IdxSet` yourself:
+///
+/// * `into_idx_iter` must yield valid `Bitfields` in ascending order of `Bitfield::start`,
+///   with no two covering the same block. A `Bitfield` may be empty.
+/// * `size` estimates the number of `Bitfields` `into_idx_iter` would yield. It's used to
+///   decide which side of an intersection to iterate, so it need not be exact.
+/// * `intersect` returns the indices of this `IdxSet` within the block of the given `Bitfield`,
+///   that are also set in that `Bitfield`.
+///
+/// Usually it's easier to build an `IdxSet` out of the ones that already exist: a `Bitset`,
+/// an `IdxRange`, or the `ChunkIdxSet` of another `Query`, combined with
+/// `IdxSet::intersection`, `IdxSet::union` and `IdxSet::difference`.
+///
+/// # Example
+///
+/// ```
+/// use retriever::prelude::*;
+/// use retriever::idxsets::difference::Difference;
+/// use retriever::idxsets::idxrange::IdxRange;
+/// use retriever::traits::idxset::IdxSet;
+/// use retriever::types::chunk_storage::ChunkStorage;
+///
+/// // Mail chunked by folder, keyed by message id.
+/// type Mail = (&'static str, u64, u64);
+///
+/// // A Query that visits every chunk except the archive.
+/// #[derive(Clone)]
+/// struct Unarchived;
+///
+/// impl Query<&'static str, u64, Mail> for Unarchived {
+///   type ChunkIdxSet = Difference<IdxRange, <Chunks<[&'static str; 1]> as Query<&'static str, u64, Mail>>::ChunkIdxSet>;
+///   type ItemIdxSet = IdxRange;
+///
+///   fn chunk_idxs(&self, storage: &Storage<&'static str, u64, Mail>) -> Self::ChunkIdxSet {
+///     let everything = Query::<&'static str, u64, Mail>::chunk_idxs(&Everything, storage);
+///     everything.difference(Chunks(["archive"]).chunk_idxs(storage))
+///   }
+///
+///   fn item_idxs(
+///     &self,
+///     chunk_key: &&'static str,
+///     chunk_storage: &ChunkStorage<&'static str, u64, Mail>,
+///   ) -> Self::ItemIdxSet {
+///     Query::<&'static str, u64, Mail>::item_idxs(&Everything, chunk_key, chunk_storage)
+///   }
+///
+///   fn test(&self, _element: &Mail) -> bool {
+///     true
+///   }
+///
+///   fn test_chunk(&self, chunk_key: &&'static str) -> bool {
+///     *chunk_key != "archive"
+///   }
+/// }
+///
+/// let mut storage : Storage<&'static str, u64, Mail> = Storage::new();
+/// storage.add(("inbox", 1, 0));
+/// storage.add(("archive", 2, 0));
+/// storage.add(("sent", 3, 0));
+///
+/// let mut ids : Vec<u64> = storage.query(Unarchived).map(|x| x.1).collect();
+/// ids.sort();
+/// assert_eq!(vec![1, 3], ids);
+/// ```
 pub trait IdxSet: Sized + Clone {
     /// A sorted `Iterator` over this `IdxSet`.
     type IdxIter: Iterator<Item = Bitfield> + DoubleEndedIterator;
@@ -21,6 +90,22 @@ pub trait IdxSet: Sized + Clone {
     {
         Intersection::new(self, b)
     }
+
+    /// Construct the union of this `IdxSet` with another `IdxSet`.
+    fn union<B>(self, b: B) -> Union<Self, B>
+    where
+        B: IdxSet,
+    {
+        Union::new(self, b)
+    }
+
+    /// Construct the indices of this `IdxSet` that are not in another `IdxSet`.
+    fn difference<B>(self, b: B) -> Difference<Self, B>
+    where
+        B: IdxSet,
+    {
+        Difference::new(self, b)
+    }
 }
 
 impl<T> IdxSet for Option<T>
@@ -0,0 +1,13 @@
+/// A selection of item keys within a single chunk, such as a range or an explicit list.
+/// This is what `Id::items` accepts. You never need to implement it yourself.
+///
+/// * Ranges (`Range`, `RangeInclusive`, `RangeFrom`, `RangeTo`, `RangeToInclusive`, and
+///   pairs of `Bound`) select an `ItemRange`.
+/// * Collections (`Vec`, arrays, slices, `HashSet`, and `BTreeSet`) select `Items`.
+pub trait ItemSelection<C> {
+    /// The `Query` that visits the selected elements.
+    type Query;
+
+    /// Construct the `Query` that visits the selected elements of the given chunk.
+    fn select(self, chunk_key: C) -> Self::Query;
+}
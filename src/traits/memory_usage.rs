@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::hash::{BuildHasher, Hash};
 
 /// A measurement of the memory allocated -vs- used.
@@ -79,6 +79,34 @@ impl<K: Eq + Hash, V, S: BuildHasher> MemoryUser for HashMap<K, V, S> {
     }
 }
 
+impl<K, V> MemoryUser for BTreeMap<K, V> {
+    fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            size_of: Some(std::mem::size_of::<K>() + std::mem::size_of::<V>()),
+            len: self.len(),
+            capacity: self.len(),
+        }
+    }
+
+    fn shrink_with<F: Fn(&MemoryUsage) -> Option<usize>>(&mut self, _f: F) {
+        // A BTreeMap never holds onto unused capacity.
+    }
+}
+
+impl<T> MemoryUser for BTreeSet<T> {
+    fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            size_of: Some(std::mem::size_of::<T>()),
+            len: self.len(),
+            capacity: self.len(),
+        }
+    }
+
+    fn shrink_with<F: Fn(&MemoryUsage) -> Option<usize>>(&mut self, _f: F) {
+        // A BTreeSet never holds onto unused capacity.
+    }
+}
+
 impl MemoryUsage {
     /// Merge two memory usages into a total of both.
     pub fn merge(a: MemoryUsage, b: MemoryUsage) -> MemoryUsage {
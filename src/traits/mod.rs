@@ -1,5 +1,9 @@
+/// Module for a trait that makes stored values mergeable as conflict-free replicated data types.
+pub mod crdt;
 /// Module for a trait that represents internal index sets.
 pub mod idxset;
+/// Module for a trait that selects item keys within a single chunk.
+pub mod item_selection;
 /// Module for a trait that measures memory usage and provides for cleanup of unused allocation.
 pub mod memory_usage;
 /// Module for a trait that defines various ways of querying stored data.
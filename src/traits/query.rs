@@ -1,17 +1,24 @@
+use crate::internal::type_name::short_type_name;
 use crate::queries::secondary_index::KeySet;
 use crate::traits::idxset::IdxSet;
 use crate::traits::record::Record;
 use crate::traits::valid_key::{BorrowedKey, ValidKey};
 use crate::types::chunk_storage::ChunkStorage;
+use crate::types::id::Id;
+use crate::types::query_plan::{ChunkPlan, QueryPlan};
 use crate::types::storage::Storage;
 use std::borrow::Borrow;
 use std::borrow::Cow;
 use std::fmt::Debug;
+use std::ops::RangeBounds;
 use std::rc::Rc;
 use std::sync::Arc;
 
 /// A `Query` defines a subset of the chunks and a subset of the data elements in each chunk.
 /// Use a `Query` to iterate, modify, or remove said elements.
+///
+/// A `Query` that restricts which chunks it visits in `chunk_idxs` must also override
+/// `test_chunk` to match. See `Query::test_chunk`.
 pub trait Query<ChunkKey, ItemKey, Element>
 where
     ChunkKey: BorrowedKey + ?Sized,
@@ -35,8 +42,109 @@ where
     ) -> Self::ItemIdxSet;
 
     /// Test whether or not a particular data element actually belongs to this `Query`.
+    /// Elements may be tested in any order, or more than once, so the result shouldn't depend
+    /// on which elements have already been tested.
     fn test(&self, element: &Element) -> bool;
 
+    /// Test whether or not a particular chunk may contain data elements belonging to this `Query`.
+    /// Combinators like `Or` and `Not` use it, instead of `chunk_idxs`, to decide which side
+    /// selected a chunk, because they only see one chunk at a time.
+    ///
+    /// # Overriding
+    ///
+    /// **The default implementation accepts every chunk.** It can't be derived from `chunk_idxs`,
+    /// which needs the whole `Storage`, so it is correct only for queries that visit every chunk,
+    /// or whose `item_idxs` and `test` already reject every element of the chunks they don't
+    /// visit. Any `Query` whose `chunk_idxs` leaves out chunks whose elements it would otherwise
+    /// accept *must* override this to reject those chunks. Otherwise `Or` and `Not` will wrongly
+    /// treat every element of those chunks as belonging to this `Query`.
+    fn test_chunk(&self, _chunk_key: &ChunkKey) -> bool {
+        true
+    }
+
+    /// True IFF `test` accepts every element selected by `item_idxs`, so that the elements
+    /// of this `Query` can be counted from its `IdxSets` alone, without visiting any of them.
+    /// See `Storage::count`. The default implementation returns false, which is always correct.
+    fn is_exact(&self) -> bool {
+        false
+    }
+
+    /// True IFF whether an element belongs to this `Query` depends only on the chunk holding it,
+    /// so that a chunk that hasn't changed still has the same matching elements. `Query::limit`
+    /// and `Query::skip` aren't chunk-local, because the elements they choose from one chunk
+    /// depend on every chunk before it. See `PreparedQuery`. The default implementation
+    /// returns true; a `Query` built from other queries must return false if any of them do.
+    fn is_chunk_local(&self) -> bool {
+        true
+    }
+
+    /// A short, human-readable description of this `Query`, for `Query::explain`. The built-in
+    /// combinators describe the queries they're built from. The default implementation gives
+    /// the name of the `Query` type.
+    fn describe(&self) -> String {
+        short_type_name::<Self>()
+    }
+
+    /// Report how this `Query` would run against the given `Storage`, without running it: which
+    /// chunks would be visited, how many candidate elements each chunk's indexes select, and
+    /// whether those candidates must still be scanned by a filter. Use this to find out whether
+    /// a slow `Query` is actually using an index.
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    /// use std::borrow::Cow;
+    ///
+    /// let mut storage : Storage<u64, u64, (u64, u64, bool)> = Storage::new();
+    /// let by_flag : SecondaryIndex<u64, (u64, u64, bool), Option<bool>, bool> =
+    ///   SecondaryIndex::new(&storage, |x: &(u64, u64, bool)| Cow::Owned(Some(x.2)));
+    ///
+    /// for i in 0..100 {
+    ///   storage.add((i % 4, i, i % 10 == 0));
+    /// }
+    ///
+    /// let query = Chunks([0, 2]).matching(&by_flag, Cow::Owned(true));
+    /// let plan = query.explain(&storage);
+    ///
+    /// assert_eq!("Matching(Chunks<[u64; 2]>, SecondaryIndex<bool>)", plan.description);
+    /// assert!(plan.exact);
+    /// assert_eq!(vec![0, 2], plan.chunks.iter().map(|c| c.chunk_key).collect::<Vec<u64>>());
+    /// assert_eq!(10, plan.candidates());
+    ///
+    /// // A filter can't use the index, so every candidate will be tested.
+    /// let plan = Everything.filter(|x: &(u64, u64, bool)| x.2).explain(&storage);
+    /// assert!(!plan.exact);
+    /// assert_eq!(100, plan.candidates());
+    /// ```
+    fn explain(&self, storage: &Storage<ChunkKey, ItemKey, Element>) -> QueryPlan<ChunkKey::Owned>
+    where
+        Self: Sized,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        let chunks = self
+            .chunk_idxs(storage)
+            .into_idx_iter()
+            .flatten()
+            .map(|idx| {
+                let chunk_storage = &*storage.internal_rvec()[idx];
+                ChunkPlan {
+                    chunk_key: chunk_storage.chunk_key().to_owned(),
+                    candidates: self
+                        .item_idxs(chunk_storage.chunk_key(), chunk_storage)
+                        .into_idx_iter()
+                        .map(|bitfield| bitfield.ones())
+                        .sum(),
+                    len: chunk_storage.len(),
+                }
+            })
+            .collect();
+
+        QueryPlan {
+            description: self.describe(),
+            exact: self.is_exact(),
+            chunks,
+        }
+    }
+
     /// Filter this `Query` according to some predicate.
     fn filter<F>(self, f: F) -> crate::queries::filter::Filter<Self, F>
     where
@@ -46,6 +154,170 @@ where
         crate::queries::filter::Filter::new(self, f)
     }
 
+    /// Filter this `Query` and project each remaining element in a single pass, by a function
+    /// that returns `None` to drop an element. Use `FilterMap::query` to run it against a
+    /// `Storage`.
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    ///
+    /// let mut storage : Storage<u64, u64, (u64, u64, &'static str)> = Storage::new();
+    ///
+    /// storage.add((1, 1, "17"));
+    /// storage.add((1, 2, "twelve"));
+    /// storage.add((2, 3, "-4"));
+    ///
+    /// // Each string is parsed only once.
+    /// let numbers = Everything.filter_map(|x: &(u64, u64, &'static str)| x.2.parse::<i64>().ok());
+    /// let mut parsed : Vec<i64> = numbers.query(&storage).collect();
+    /// parsed.sort();
+    ///
+    /// assert_eq!(vec![-4, 17], parsed);
+    /// ```
+    fn filter_map<F, T>(self, f: F) -> crate::queries::filter::FilterMap<Self, F>
+    where
+        Self: Sized,
+        F: Fn(&Element) -> Option<T>,
+    {
+        crate::queries::filter::FilterMap::new(self, f)
+    }
+
+    /// Visit at most `n` elements of this `Query`, and skip every chunk after the `n`th element.
+    /// Combine with `Query::skip` to paginate.
+    ///
+    /// `limit` and `skip` choose from the elements that this `Query` accepts, so apply them last,
+    /// after any `filter`, `matching`, or boolean combinators. For example,
+    /// `Everything.limit(3).and(query)` visits only those of the first three elements that
+    /// `query` also accepts.
+    ///
+    /// A `Limit` or `Skip` remembers the elements it chose when its query began, so each one
+    /// must serve only one `Storage` and one evaluation at a time. See `Limit` for details.
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    ///
+    /// let mut storage : Storage<u64, u64, (u64, u64, u64)> = Storage::new();
+    ///
+    /// for i in 0..10 {
+    ///   storage.add((0, i, i * i));
+    /// }
+    ///
+    /// let page = Everything.filter(|x: &(u64, u64, u64)| x.1 % 2 == 0).skip(1).limit(2);
+    /// let items : Vec<u64> = storage.query(&page).map(|x| x.1).collect();
+    /// assert_eq!(vec![2, 4], items);
+    ///
+    /// // Remove at most three large elements.
+    /// let large = Everything.filter(|x: &(u64, u64, u64)| x.2 > 10).limit(3);
+    /// storage.remove(&large, std::mem::drop);
+    /// assert_eq!(7, storage.iter().count());
+    /// ```
+    fn limit(self, n: usize) -> crate::queries::limit::Limit<Self, ChunkKey::Owned>
+    where
+        Self: Sized,
+    {
+        crate::queries::limit::Limit::new(self, n)
+    }
+
+    /// Skip the first `n` elements of this `Query`. See `Query::limit`.
+    fn skip(self, n: usize) -> crate::queries::limit::Skip<Self, ChunkKey::Owned>
+    where
+        Self: Sized,
+    {
+        crate::queries::limit::Skip::new(self, n)
+    }
+
+    /// Visit only the elements of this `Query` that come strictly after the given `Id`, in order
+    /// of chunk key and then item key. Paginate by passing the `Id` of the last element of each
+    /// page; unlike `Query::skip`, pages don't shift when elements are added or removed.
+    ///
+    /// `Storage::query` doesn't visit elements in key order, so use `Storage::order_by` to
+    /// find the first elements of each page.
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    ///
+    /// // Posts chunked by forum, keyed by post number.
+    /// type Post = (u64, u64, &'static str);
+    /// let mut storage : Storage<u64, u64, Post> = Storage::new();
+    ///
+    /// storage.add((1, 1, "first"));
+    /// storage.add((1, 2, "second"));
+    /// storage.add((1, 3, "[deleted]"));
+    /// storage.add((2, 1, "hello"));
+    /// storage.add((2, 2, "world"));
+    ///
+    /// let visible = Everything.filter(|x: &Post| x.2 != "[deleted]");
+    /// let page = |after: Id<u64, u64>| -> Vec<(u64, u64)> {
+    ///   storage
+    ///     .order_by(visible.after(after), |x| (x.0, x.1))
+    ///     .take(2)
+    ///     .map(|x| (x.0, x.1))
+    ///     .collect()
+    /// };
+    ///
+    /// assert_eq!(vec![(1, 2), (2, 1)], page(ID.chunk(1).item(1)));
+    /// assert_eq!(vec![(2, 2)], page(ID.chunk(2).item(1)));
+    /// assert_eq!(Vec::<(u64, u64)>::new(), page(ID.chunk(2).item(2)));
+    ///
+    /// // The Id needn't belong to any element.
+    /// assert_eq!(vec![(2, 1), (2, 2)], page(ID.chunk(1).item(99)));
+    /// ```
+    fn after<C, I>(self, id: Id<C, I>) -> crate::queries::limit::After<Self, C, I>
+    where
+        Self: Sized,
+    {
+        crate::queries::limit::After::new(self, id)
+    }
+
+    /// Intersect this `Query` with another `Query`: visit only elements that belong to both.
+    fn and<B>(self, other: B) -> crate::queries::boolean::And<Self, B>
+    where
+        Self: Sized,
+        B: Query<ChunkKey, ItemKey, Element>,
+    {
+        crate::queries::boolean::And::new(self, other)
+    }
+
+    /// Union this `Query` with another `Query`: visit elements that belong to either.
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    ///
+    /// let mut storage : Storage<u64, u64, (u64, u64, &'static str)> = Storage::new();
+    ///
+    /// storage.add((1, 1, "apple"));
+    /// storage.add((1, 2, "banana"));
+    /// storage.add((2, 1, "cherry"));
+    /// storage.add((3, 1, "durian"));
+    ///
+    /// let query = Chunks([1]).filter(|x: &(u64, u64, &str)| x.2 == "banana")
+    ///     .or(Chunks([3]));
+    ///
+    /// let mut fruit: Vec<&str> = storage.query(&query).map(|x| x.2).collect();
+    /// fruit.sort();
+    /// assert_eq!(vec!["banana", "durian"], fruit);
+    ///
+    /// let mut fruit: Vec<&str> = storage.query(&query.not()).map(|x| x.2).collect();
+    /// fruit.sort();
+    /// assert_eq!(vec!["apple", "cherry"], fruit);
+    /// ```
+    fn or<B>(self, other: B) -> crate::queries::boolean::Or<Self, B>
+    where
+        Self: Sized,
+        B: Query<ChunkKey, ItemKey, Element>,
+    {
+        crate::queries::boolean::Or::new(self, other)
+    }
+
+    /// Negate this `Query`: visit only elements that do not belong to it.
+    /// Where the key types of this `Query` can not be inferred, use `Not::new` instead.
+    fn not(self) -> crate::queries::boolean::Not<Self>
+    where
+        Self: Sized,
+    {
+        crate::queries::boolean::Not::new(self)
+    }
+
     /// Filter this `Query` by matching against a `SecondaryIndex`.
     ///
     /// ```
@@ -70,7 +342,7 @@ where
     ///
     /// let mut storage : Storage<(), String, Kitten> = Storage::new();
     ///
-    /// let mut by_color : SecondaryIndex<(),Kitten,HashSet<String>,str> =
+    /// let by_color : SecondaryIndex<(),Kitten,HashSet<String>,str> =
     /// SecondaryIndex::new(&storage, |kitten: &Kitten| Cow::Borrowed(&kitten.colors));
     ///
     /// storage.add(Kitten {
@@ -88,14 +360,14 @@ where
     ///   colors: vec![String::from("black")].into_iter().collect()
     /// });
     ///
-    /// for kitten in storage.query(&Everything.matching(&mut by_color, Cow::Borrowed("orange"))) {
+    /// for kitten in storage.query(&Everything.matching(&by_color, Cow::Borrowed("orange"))) {
     ///   assert_eq!("furball", &kitten.name);
     /// }
     ///
-    /// assert_eq!(2, storage.query(&Everything.matching(&mut by_color, Cow::Borrowed("black"))).count());
+    /// assert_eq!(2, storage.query(&Everything.matching(&by_color, Cow::Borrowed("black"))).count());
     ///
     /// # storage.validate();
-    /// # by_color.validate(&mut storage);
+    /// # by_color.validate(&storage);
     /// ```
     fn matching<'a, IndexKeys, IndexKey>(
         self,
@@ -123,6 +395,255 @@ where
     {
         crate::queries::secondary_index::MatchingSecondaryIndex::new(self, secondary_index, key)
     }
+
+    /// Filter this `Query` by matching against a `SecondaryIndex`, visiting only the elements
+    /// that are not indexed under the given key. This is the opposite of `Query::matching`,
+    /// and is just as cheap: the matching elements are subtracted using the index.
+    ///
+    /// Elements of chunks outside the scope of a scoped `SecondaryIndex` aren't indexed under
+    /// any key, so they're always visited.
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    /// use std::borrow::Cow;
+    ///
+    /// let mut storage : Storage<u64, u64, (u64, u64, &'static str)> = Storage::new();
+    /// let by_status : SecondaryIndex<u64, (u64, u64, &'static str), Option<&'static str>, &'static str> =
+    ///   SecondaryIndex::new(&storage, |x: &(u64, u64, &'static str)| Cow::Owned(Some(x.2)));
+    ///
+    /// storage.add((1, 1, "ok"));
+    /// storage.add((1, 2, "failed"));
+    /// storage.add((2, 3, "ok"));
+    /// storage.add((2, 4, "pending"));
+    ///
+    /// let mut not_ok : Vec<u64> = storage
+    ///   .query(Everything.not_matching(&by_status, Cow::Owned("ok")))
+    ///   .map(|x| x.1)
+    ///   .collect();
+    /// not_ok.sort();
+    ///
+    /// assert_eq!(vec![2, 4], not_ok);
+    ///
+    /// # storage.validate();
+    /// # by_status.validate(&storage);
+    /// ```
+    fn not_matching<'a, IndexKeys, IndexKey>(
+        self,
+        secondary_index: &'a crate::queries::secondary_index::SecondaryIndex<
+            ChunkKey,
+            Element,
+            IndexKeys,
+            IndexKey,
+        >,
+        key: Cow<'a, IndexKey>,
+    ) -> crate::queries::secondary_index::NotMatchingSecondaryIndex<
+        'a,
+        Self,
+        ChunkKey,
+        Element,
+        IndexKeys,
+        IndexKey,
+    >
+    where
+        Self: Sized,
+        IndexKey: BorrowedKey + ?Sized,
+        IndexKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+    {
+        crate::queries::secondary_index::NotMatchingSecondaryIndex::new(self, secondary_index, key)
+    }
+
+    /// Filter this `Query` by matching against any of several keys of a `SecondaryIndex`.
+    /// This is equivalent to, but much cheaper than, combining one `Query::matching` for each key
+    /// using `Query::or`.
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    /// use std::borrow::Cow;
+    ///
+    /// // Shirts chunked by size, keyed by SKU, and indexed by color.
+    /// let mut storage : Storage<&'static str, u64, (&'static str, u64, &'static str)> = Storage::new();
+    /// let by_color : SecondaryIndex<&'static str, (&'static str, u64, &'static str), Option<&'static str>, &'static str> =
+    ///   SecondaryIndex::new(&storage, |x: &(&'static str, u64, &'static str)| Cow::Owned(Some(x.2)));
+    ///
+    /// storage.add(("small", 1, "red"));
+    /// storage.add(("small", 2, "green"));
+    /// storage.add(("large", 3, "blue"));
+    /// storage.add(("large", 4, "red"));
+    /// storage.add(("large", 5, "yellow"));
+    ///
+    /// let mut skus : Vec<u64> = storage
+    ///   .query(Everything.matching_any(&by_color, vec![Cow::Owned("red"), Cow::Owned("blue")]))
+    ///   .map(|x| x.1)
+    ///   .collect();
+    ///
+    /// skus.sort();
+    /// assert_eq!(vec![1, 3, 4], skus);
+    ///
+    /// # storage.validate();
+    /// # by_color.validate(&storage);
+    /// ```
+    fn matching_any<'a, IndexKeys, IndexKey, I>(
+        self,
+        secondary_index: &crate::queries::secondary_index::SecondaryIndex<
+            ChunkKey,
+            Element,
+            IndexKeys,
+            IndexKey,
+        >,
+        keys: I,
+    ) -> crate::queries::secondary_index::MatchingAny<
+        'a,
+        Self,
+        ChunkKey,
+        Element,
+        IndexKeys,
+        IndexKey,
+    >
+    where
+        Self: Sized,
+        IndexKey: BorrowedKey + ?Sized,
+        IndexKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+        I: IntoIterator,
+        I::Item: Into<Cow<'a, IndexKey>>,
+    {
+        crate::queries::secondary_index::MatchingAny::new(self, secondary_index, keys)
+    }
+
+    /// Filter this `Query` by matching a `TextPattern` against a `TextIndex`.
+    /// See `TextIndex` for an example.
+    fn matching_text<'a>(
+        self,
+        text_index: &crate::queries::text_index::TextIndex<ChunkKey, Element>,
+        pattern: crate::queries::text_index::TextPattern<'a>,
+    ) -> crate::queries::text_index::MatchingText<'a, Self, ChunkKey, Element>
+    where
+        Self: Sized,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        crate::queries::text_index::MatchingText::new(self, text_index, pattern)
+    }
+
+    /// Filter this `Query` by matching a `CompositePattern` against a `CompositeIndex`.
+    /// See `CompositeIndex` for an example.
+    fn matching_composite<A, B>(
+        self,
+        composite_index: &crate::queries::composite_index::CompositeIndex<ChunkKey, Element, A, B>,
+        pattern: crate::queries::composite_index::CompositePattern<A, B>,
+    ) -> crate::queries::composite_index::MatchingComposite<Self, ChunkKey, Element, A, B>
+    where
+        Self: Sized,
+        Element: Record<ChunkKey, ItemKey>,
+        A: ValidKey + 'static,
+        B: ValidKey + 'static,
+    {
+        crate::queries::composite_index::MatchingComposite::new(self, composite_index, pattern)
+    }
+
+    /// Filter this `Query` by matching a `SpatialPattern` against a `SpatialIndex`.
+    /// See `SpatialIndex` for an example.
+    fn matching_spatial(
+        self,
+        spatial_index: &crate::queries::spatial_index::SpatialIndex<ChunkKey, Element>,
+        pattern: crate::queries::spatial_index::SpatialPattern,
+    ) -> crate::queries::spatial_index::MatchingSpatial<Self, ChunkKey, Element>
+    where
+        Self: Sized,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        crate::queries::spatial_index::MatchingSpatial::new(self, spatial_index, pattern)
+    }
+
+    /// Filter this `Query` to those elements whose path, in the given `PrefixIndex`, starts with
+    /// the given prefix. See `PrefixIndex` for an example.
+    fn matching_prefix<Segment, I>(
+        self,
+        prefix_index: &crate::queries::prefix_index::PrefixIndex<ChunkKey, Element, Segment>,
+        prefix: I,
+    ) -> crate::queries::prefix_index::MatchingPrefix<Self, ChunkKey, Element, Segment>
+    where
+        Self: Sized,
+        Element: Record<ChunkKey, ItemKey>,
+        Segment: ValidKey,
+        I: IntoIterator,
+        I::Item: Into<Segment>,
+    {
+        crate::queries::prefix_index::MatchingPrefix::new(
+            self,
+            prefix_index,
+            prefix.into_iter().map(Into::into).collect(),
+        )
+    }
+
+    /// Filter this `Query` to those elements whose value, in the given `HistogramIndex`, falls
+    /// within the given range. Chunks whose histograms show no value within the range are
+    /// skipped. See `HistogramIndex` for an example.
+    fn matching_histogram<R>(
+        self,
+        histogram_index: &crate::queries::histogram_index::HistogramIndex<ChunkKey, Element>,
+        range: R,
+    ) -> crate::queries::histogram_index::MatchingHistogram<Self, ChunkKey, Element>
+    where
+        Self: Sized,
+        Element: Record<ChunkKey, ItemKey>,
+        R: RangeBounds<f64>,
+    {
+        crate::queries::histogram_index::MatchingHistogram::new(self, histogram_index, range)
+    }
+
+    /// Filter this `Query` to those elements whose string, in the given `NormalizedIndex`, is
+    /// the same as the given string once both are normalized. See `NormalizedIndex` for an
+    /// example.
+    fn matching_normalized(
+        self,
+        normalized_index: &crate::queries::normalized_index::NormalizedIndex<ChunkKey, Element>,
+        key: &str,
+    ) -> crate::queries::normalized_index::MatchingNormalized<Self, ChunkKey, Element>
+    where
+        Self: Sized,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        crate::queries::secondary_index::MatchingSecondaryIndex::new(
+            self,
+            normalized_index.as_secondary_index(),
+            Cow::Owned(normalized_index.normalizer().normalize(key)),
+        )
+    }
+
+    /// Filter a `Query` to those elements with at least one index key, in the given
+    /// `OrderedSecondaryIndex`, that falls within the given range.
+    /// See `OrderedSecondaryIndex` for an example.
+    fn matching_range<IndexKeys, IndexKey, K, R>(
+        self,
+        secondary_index: &crate::queries::ordered_secondary_index::OrderedSecondaryIndex<
+            ChunkKey,
+            Element,
+            IndexKeys,
+            IndexKey,
+        >,
+        range: R,
+    ) -> crate::queries::ordered_secondary_index::MatchingRange<
+        Self,
+        ChunkKey,
+        Element,
+        IndexKeys,
+        IndexKey,
+        K,
+    >
+    where
+        Self: Sized,
+        IndexKey: BorrowedKey + ?Sized,
+        IndexKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+        K: Clone + Borrow<IndexKey>,
+        R: RangeBounds<K>,
+    {
+        crate::queries::ordered_secondary_index::MatchingRange::new(self, secondary_index, range)
+    }
 }
 
 impl<'a, Q, ChunkKey: ToOwned, ItemKey: ToOwned, Element> Query<ChunkKey, ItemKey, Element>
@@ -152,6 +673,22 @@ where
     fn test(&self, element: &Element) -> bool {
         Q::test(self, element)
     }
+
+    fn test_chunk(&self, chunk_key: &ChunkKey) -> bool {
+        Q::test_chunk(self, chunk_key)
+    }
+
+    fn is_exact(&self) -> bool {
+        Q::is_exact(self)
+    }
+
+    fn is_chunk_local(&self) -> bool {
+        Q::is_chunk_local(self)
+    }
+
+    fn describe(&self) -> String {
+        Q::describe(self)
+    }
 }
 
 impl<Q, ChunkKey: ToOwned, ItemKey: ToOwned, Element> Query<ChunkKey, ItemKey, Element> for Rc<Q>
@@ -180,6 +717,22 @@ where
     fn test(&self, element: &Element) -> bool {
         Q::test(Rc::as_ref(self), element)
     }
+
+    fn test_chunk(&self, chunk_key: &ChunkKey) -> bool {
+        Q::test_chunk(Rc::as_ref(self), chunk_key)
+    }
+
+    fn is_exact(&self) -> bool {
+        Q::is_exact(Rc::as_ref(self))
+    }
+
+    fn is_chunk_local(&self) -> bool {
+        Q::is_chunk_local(Rc::as_ref(self))
+    }
+
+    fn describe(&self) -> String {
+        Q::describe(Rc::as_ref(self))
+    }
 }
 
 impl<Q, ChunkKey: ToOwned, ItemKey: ToOwned, Element> Query<ChunkKey, ItemKey, Element> for Arc<Q>
@@ -208,6 +761,22 @@ where
     fn test(&self, element: &Element) -> bool {
         Q::test(Arc::as_ref(self), element)
     }
+
+    fn test_chunk(&self, chunk_key: &ChunkKey) -> bool {
+        Q::test_chunk(Arc::as_ref(self), chunk_key)
+    }
+
+    fn is_exact(&self) -> bool {
+        Q::is_exact(Arc::as_ref(self))
+    }
+
+    fn is_chunk_local(&self) -> bool {
+        Q::is_chunk_local(Arc::as_ref(self))
+    }
+
+    fn describe(&self) -> String {
+        Q::describe(Arc::as_ref(self))
+    }
 }
 
 impl<'a, Q, ChunkKey, ItemKey, Element> Query<ChunkKey, ItemKey, Element> for Cow<'a, Q>
@@ -236,4 +805,20 @@ where
     fn test(&self, element: &Element) -> bool {
         Q::test(Cow::borrow(self), element)
     }
+
+    fn test_chunk(&self, chunk_key: &ChunkKey) -> bool {
+        Q::test_chunk(Cow::borrow(self), chunk_key)
+    }
+
+    fn is_exact(&self) -> bool {
+        Q::is_exact(Cow::borrow(self))
+    }
+
+    fn is_chunk_local(&self) -> bool {
+        Q::is_chunk_local(Cow::borrow(self))
+    }
+
+    fn describe(&self) -> String {
+        Q::describe(Cow::borrow(self))
+    }
 }
@@ -0,0 +1,351 @@
+use crate::internal::generation::next_generation;
+use crate::internal::hasher::HasherImpl;
+use crate::internal::mr::rvec::RVec;
+use crate::traits::memory_usage::{MemoryUsage, MemoryUser};
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::storage::Storage;
+use crate::types::storage_builder::Strictness;
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// A handle to a blob stored in a `BlobStore`. A `BlobId` is only meaningful together with
+/// the chunk key of the arena it was stored in, and remains valid when the arena is compacted.
+///
+/// Each `BlobId` carries the generation at which its blob was stored. After a blob is removed,
+/// its slot may be reused for a later blob, but the stale `BlobId` never refers to that later
+/// blob: it simply finds nothing, even if the whole chunk was removed and created again.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct BlobId {
+    slot: usize,
+    generation: u64,
+}
+
+/// Large payloads stored outside of a `Storage`, in one contiguous arena per chunk.
+///
+/// Elements refer to their payloads by `BlobId`, so that multi-megabyte payloads don't live
+/// inside of the element storage, where they would ruin the locality of every query. Because
+/// each arena is a single contiguous buffer, it can be written out and memory-mapped as a unit
+/// (see `BlobStore::arena`), and because `BlobIds` are indirect, the arena can be relocated
+/// or compacted without updating any element.
+///
+/// The arena of a chunk is not freed as soon as that chunk is removed from the `Storage`, but
+/// lazily, by the next call to `BlobStore::gc`, `BlobStore::compact` or `BlobStore::insert`.
+/// Until then, `BlobStore::get` still finds the blobs of the removed chunk. Removing a single
+/// blob only marks its bytes as garbage; call `BlobStore::compact` to reclaim them.
+///
+/// # Type Parameters
+///
+/// * `ChunkKey`: matches the `ChunkKey` of the `Storage`.
+///
+/// # Example
+///
+/// ```
+/// use retriever::prelude::*;
+/// use retriever::types::blob_store::{BlobId, BlobStore};
+///
+/// // Documents chunked by folder and keyed by name, with their contents kept in a BlobStore.
+/// let mut storage : Storage<&'static str, &'static str, (&'static str, &'static str, BlobId)> =
+///   Storage::new();
+/// let mut blobs : BlobStore<&'static str> = BlobStore::new(&storage);
+///
+/// let readme = blobs.insert(&storage, &"docs", b"read me first");
+/// let notes = blobs.insert(&storage, &"docs", b"some notes");
+/// storage.add(("docs", "README", readme));
+/// storage.add(("docs", "NOTES", notes));
+///
+/// let blob_id = storage.get(&ID.chunk("docs").item("README")).unwrap().2;
+/// assert_eq!(Some(&b"read me first"[..]), blobs.get(&"docs", blob_id));
+///
+/// // Removing a blob leaves garbage in the arena until it is compacted.
+/// storage.remove(ID.chunk("docs").item("README"), std::mem::drop);
+/// blobs.remove(&"docs", readme);
+/// assert_eq!(13, blobs.garbage_bytes(&"docs"));
+///
+/// blobs.compact(&storage);
+/// assert_eq!(0, blobs.garbage_bytes(&"docs"));
+/// assert_eq!(Some(&b"some notes"[..]), blobs.get(&"docs", notes));
+///
+/// // Removing a chunk frees its arena the next time the BlobStore is garbage collected.
+/// storage.remove_chunk(&"docs");
+/// assert_eq!(Some(&b"some notes"[..]), blobs.get(&"docs", notes));
+/// blobs.gc(&storage);
+/// assert_eq!(None, blobs.get(&"docs", notes));
+///
+/// // A stale BlobId never refers to a later blob, even one that reuses its slot.
+/// let todo = blobs.insert(&storage, &"docs", b"todo");
+/// assert_eq!(None, blobs.get(&"docs", readme));
+/// assert_eq!(Some(&b"todo"[..]), blobs.get(&"docs", todo));
+/// ```
+pub struct BlobStore<ChunkKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+{
+    parent_id: u64,
+    gc_chunk_list: RVec<Option<ChunkKey::Owned>>,
+    arenas: HashMap<ChunkKey::Owned, BlobArena, HasherImpl>,
+}
+
+#[derive(Clone, Default)]
+struct BlobArena {
+    data: Vec<u8>,
+    // the bytes and generation of the blob in each slot, if any
+    slots: Vec<Option<(Range<usize>, u64)>>,
+    free_slots: Vec<usize>,
+    garbage: usize,
+}
+
+impl BlobArena {
+    fn insert(&mut self, bytes: &[u8]) -> BlobId {
+        let range = self.data.len()..self.data.len() + bytes.len();
+        self.data.extend_from_slice(bytes);
+
+        let generation = next_generation();
+        let slot = if let Some(slot) = self.free_slots.pop() {
+            self.slots[slot] = Some((range, generation));
+            slot
+        } else {
+            self.slots.push(Some((range, generation)));
+            self.slots.len() - 1
+        };
+
+        BlobId { slot, generation }
+    }
+
+    fn range_of(&self, blob_id: BlobId) -> Option<Range<usize>> {
+        match self.slots.get(blob_id.slot)? {
+            Some((range, generation)) if *generation == blob_id.generation => Some(range.clone()),
+            _ => None,
+        }
+    }
+
+    fn get(&self, blob_id: BlobId) -> Option<&[u8]> {
+        let range = self.range_of(blob_id)?;
+        Some(&self.data[range])
+    }
+
+    fn remove(&mut self, blob_id: BlobId) -> bool {
+        match self.range_of(blob_id) {
+            Some(range) => {
+                self.slots[blob_id.slot] = None;
+                self.garbage += range.len();
+                self.free_slots.push(blob_id.slot);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn compact(&mut self) {
+        if self.garbage == 0 {
+            return;
+        }
+
+        // Copy the live blobs into a new buffer in the order they appear in the old buffer,
+        // which keeps blobs that were inserted together next to each other.
+        let mut live: Vec<usize> = (0..self.slots.len())
+            .filter(|slot| self.slots[*slot].is_some())
+            .collect();
+        live.sort_by_key(|slot| self.slots[*slot].as_ref().map(|(range, _)| range.start));
+
+        let mut data = Vec::with_capacity(self.data.len() - self.garbage);
+        for slot in live {
+            let (range, generation) = self.slots[slot].take().unwrap();
+            let start = data.len();
+            data.extend_from_slice(&self.data[range]);
+            self.slots[slot] = Some((start..data.len(), generation));
+        }
+
+        // Trailing free slots don't need to be remembered.
+        while let Some(None) = self.slots.last() {
+            self.slots.pop();
+        }
+        let slots_len = self.slots.len();
+        self.free_slots.retain(|slot| *slot < slots_len);
+
+        self.data = data;
+        self.garbage = 0;
+    }
+}
+
+impl<ChunkKey> BlobStore<ChunkKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+{
+    /// Create a new, empty `BlobStore` for the given `Storage`.
+    pub fn new<ItemKey, Element>(storage: &Storage<ChunkKey, ItemKey, Element>) -> Self
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        BlobStore {
+            parent_id: storage.id(),
+            gc_chunk_list: RVec::default(),
+            arenas: HashMap::with_hasher(HasherImpl::default()),
+        }
+    }
+
+    /// Store a copy of the given bytes in the arena of the given chunk, returning the `BlobId`
+    /// that refers to them. The chunk doesn't need to exist in the `Storage` yet.
+    ///
+    /// # Panic
+    ///
+    /// This method panics if used with a `Storage` other than the one this `BlobStore` was
+    /// created with, unless that `Storage`'s `Strictness` is `Repair`.
+    pub fn insert<ItemKey, Element>(
+        &mut self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        chunk_key: &ChunkKey,
+        bytes: &[u8],
+    ) -> BlobId
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        self.gc(storage);
+
+        if !self.arenas.contains_key(chunk_key) {
+            self.arenas
+                .insert(chunk_key.to_owned(), BlobArena::default());
+        }
+
+        self.arenas.get_mut(chunk_key).unwrap().insert(bytes)
+    }
+
+    /// Get the bytes of a blob, if it exists. A `BlobId` of a removed blob finds nothing.
+    pub fn get(&self, chunk_key: &ChunkKey, blob_id: BlobId) -> Option<&[u8]> {
+        self.arenas.get(chunk_key)?.get(blob_id)
+    }
+
+    /// Remove a blob, returning true IFF it existed. The space it used is not reclaimed until
+    /// the arena is compacted. Removing a blob twice, or through a stale `BlobId`, does nothing.
+    pub fn remove(&mut self, chunk_key: &ChunkKey, blob_id: BlobId) -> bool {
+        self.arenas
+            .get_mut(chunk_key)
+            .map(|arena| arena.remove(blob_id))
+            .unwrap_or(false)
+    }
+
+    /// The entire arena of the given chunk, as a single contiguous buffer, including any
+    /// garbage left behind by removed blobs.
+    pub fn arena(&self, chunk_key: &ChunkKey) -> Option<&[u8]> {
+        self.arenas.get(chunk_key).map(|arena| &arena.data[..])
+    }
+
+    /// The number of bytes in the arena of the given chunk that belong to removed blobs.
+    pub fn garbage_bytes(&self, chunk_key: &ChunkKey) -> usize {
+        self.arenas
+            .get(chunk_key)
+            .map(|arena| arena.garbage)
+            .unwrap_or(0)
+    }
+
+    /// Free the arenas of any chunks that have been removed from the `Storage`.
+    ///
+    /// # Panic
+    ///
+    /// Like `BlobStore::insert`, this method panics if used with a `Storage` other than the one
+    /// this `BlobStore` was created with, unless that `Storage`'s `Strictness` is `Repair`.
+    pub fn gc<ItemKey, Element>(&mut self, storage: &Storage<ChunkKey, ItemKey, Element>)
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        self.check_parent(storage);
+        storage.gc(&mut self.gc_chunk_list, &mut self.arenas);
+    }
+
+    /// Free the arenas of any removed chunks, and reclaim the space used by removed blobs in
+    /// every other arena. Compacting relocates blobs within their arena, but every `BlobId`
+    /// remains valid.
+    ///
+    /// # Panic
+    ///
+    /// Like `BlobStore::insert`, this method panics if used with a `Storage` other than the one
+    /// this `BlobStore` was created with, unless that `Storage`'s `Strictness` is `Repair`.
+    pub fn compact<ItemKey, Element>(&mut self, storage: &Storage<ChunkKey, ItemKey, Element>)
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        self.gc(storage);
+
+        for arena in self.arenas.values_mut() {
+            arena.compact();
+        }
+    }
+
+    /// Reclaim the space used by removed blobs in the arena of a single chunk.
+    pub fn compact_chunk(&mut self, chunk_key: &ChunkKey) {
+        if let Some(arena) = self.arenas.get_mut(chunk_key) {
+            arena.compact();
+        }
+    }
+
+    fn check_parent<ItemKey, Element>(&mut self, storage: &Storage<ChunkKey, ItemKey, Element>)
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        if self.parent_id == storage.id() {
+            return;
+        }
+
+        assert_eq!(
+            storage.strictness(),
+            Strictness::Repair,
+            "Id mismatch: a BlobStore may only be used with it's parent Storage, never any other Storage"
+        );
+
+        #[cfg(feature = "log")]
+        log::warn!("retriever: repaired BlobStore used with a different Storage by rebinding it");
+
+        // Blobs are not derived from the Storage and can't be rebuilt, so keep them, but start
+        // over tracking which chunks have been removed.
+        self.parent_id = storage.id();
+        self.gc_chunk_list = RVec::default();
+    }
+}
+
+impl<ChunkKey> MemoryUser for BlobStore<ChunkKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+{
+    fn memory_usage(&self) -> MemoryUsage {
+        let mut result = MemoryUsage {
+            size_of: None,
+            len: 0,
+            capacity: 0,
+        };
+
+        result = MemoryUsage::merge(result, self.gc_chunk_list.memory_usage());
+        result = MemoryUsage::merge(result, self.arenas.memory_usage());
+
+        for arena in self.arenas.values() {
+            result = MemoryUsage::merge(result, arena.data.memory_usage());
+            result = MemoryUsage::merge(result, arena.slots.memory_usage());
+            result = MemoryUsage::merge(result, arena.free_slots.memory_usage());
+        }
+
+        result
+    }
+
+    fn shrink_with<F: Fn(&MemoryUsage) -> Option<usize>>(&mut self, f: F) {
+        self.gc_chunk_list.shrink_with(&f);
+        self.arenas.shrink_with(&f);
+
+        for arena in self.arenas.values_mut() {
+            arena.data.shrink_with(&f);
+            arena.slots.shrink_with(&f);
+            arena.free_slots.shrink_with(&f);
+        }
+    }
+}
@@ -0,0 +1,27 @@
+use std::time::Duration;
+
+/// How much work a single call to `Storage::query_budgeted` may do before it pauses and returns
+/// a `Cursor` to continue from. This spreads a long scan across the frames of a game loop or the
+/// ticks of an executor, without blocking any one of them for long.
+///
+/// Each call examines at least one element, even if the budget is zero or already spent, so a
+/// scan always makes progress.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Budget {
+    /// Examine at most this many elements, whether or not they match the query.
+    Elements(usize),
+    /// Stop examining elements once this much time has passed.
+    Duration(Duration),
+}
+
+impl Budget {
+    /// True IFF, having examined the given number of elements in the given time, there is no
+    /// budget left to examine another.
+    pub(crate) fn is_spent(&self, examined: usize, elapsed: Duration) -> bool {
+        examined > 0
+            && match self {
+                Budget::Elements(n) => examined >= *n,
+                Budget::Duration(duration) => elapsed >= *duration,
+            }
+    }
+}
@@ -0,0 +1,25 @@
+/// The outcome of `Storage::compare_and_swap`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum CasResult<Element> {
+    /// There is no element with the given `Id`, so nothing happened.
+    Missing,
+    /// The element was left unchanged because the function returned `None`.
+    Unchanged,
+    /// The element was replaced. This is the previous element.
+    Swapped(Element),
+}
+
+impl<Element> CasResult<Element> {
+    /// True IFF the element was replaced.
+    pub fn is_swapped(&self) -> bool {
+        matches!(self, CasResult::Swapped(_))
+    }
+
+    /// The previous element, if the element was replaced.
+    pub fn into_previous(self) -> Option<Element> {
+        match self {
+            CasResult::Swapped(previous) => Some(previous),
+            _ => None,
+        }
+    }
+}
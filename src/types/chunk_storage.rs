@@ -1,7 +1,10 @@
 use super::entry::Entry;
+use super::error::ValidationError;
 use super::id::Id;
+use crate::internal::bounds::is_valid_range;
+use crate::internal::generation::next_generation;
 use crate::internal::hasher::HasherImpl;
-use crate::internal::mr::rvec::RVec;
+use crate::internal::mr::rvec::{ChangedVec, RVec};
 use crate::traits::idxset::IdxSet;
 use crate::traits::memory_usage::{MemoryUsage, MemoryUser};
 use crate::traits::query::Query;
@@ -9,12 +12,16 @@ use crate::traits::record::Record;
 use crate::traits::valid_key::{BorrowedKey, ValidKey};
 use crate::types::editor::Editor;
 use std::borrow::Borrow;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::hash::Hash;
+use std::ops::{Bound, RangeBounds};
+use std::sync::{Arc, OnceLock};
+
+/// The index from each item key of a chunk to the position of its element.
+pub(crate) type ItemIndex<ItemKeyOwned> = HashMap<ItemKeyOwned, usize, HasherImpl>;
 
 /// A chunk of storage containing all elements with a common chunk key.
 /// End users will rarely if ever interact with this type.
-#[derive(Clone)]
 pub struct ChunkStorage<ChunkKey, ItemKey, Element>
 where
     ChunkKey: BorrowedKey + ?Sized,
@@ -24,7 +31,13 @@ where
 {
     chunk_key: ChunkKey::Owned,
     data: RVec<Element>,
-    index: HashMap<ItemKey::Owned, usize, HasherImpl>,
+    index: ItemIndex<ItemKey::Owned>,
+    // the same item keys as the index, but in order, if enabled
+    ordered_index: Option<BTreeSet<ItemKey::Owned>>,
+    // how to copy this chunk once it has been shared between clones of a Storage
+    unshare: OnceLock<fn(&Self) -> Self>,
+    // the generation at which an element of this chunk last moved to a different position
+    generation: u64,
 }
 
 impl<ChunkKey, ItemKey, Element> ChunkStorage<ChunkKey, ItemKey, Element>
@@ -35,11 +48,14 @@ where
     ItemKey::Owned: ValidKey,
     Element: Record<ChunkKey, ItemKey>,
 {
-    pub(crate) fn new(chunk_key: ChunkKey::Owned) -> Self {
+    pub(crate) fn new(chunk_key: ChunkKey::Owned, ordered_item_keys: bool) -> Self {
         ChunkStorage {
             chunk_key,
             data: RVec::default(),
             index: HashMap::with_hasher(crate::internal::hasher::HasherImpl::default()),
+            ordered_index: ordered_item_keys.then(BTreeSet::new),
+            unshare: OnceLock::new(),
+            generation: 0,
         }
     }
 
@@ -53,24 +69,125 @@ where
         self.data.len()
     }
 
+    /// Returns the generation at which an element of this `ChunkStorage` last moved to a
+    /// different position.
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation
+    }
+
     /// Returns the chunk key used by all elements in this `ChunkStorage`.
     pub(crate) fn chunk_key(&self) -> &ChunkKey {
         self.chunk_key.borrow()
     }
 
+    /// Iterate over the item keys of this `ChunkStorage` without touching any element.
+    pub(crate) fn item_keys(&self) -> impl Iterator<Item = &ItemKey> {
+        self.index.keys().map(|item_key| item_key.borrow())
+    }
+
+    /// True IFF this `ChunkStorage` keeps its item keys in order.
+    pub(crate) fn has_ordered_item_keys(&self) -> bool {
+        self.ordered_index.is_some()
+    }
+
+    /// Start or stop keeping the item keys of this `ChunkStorage` in order.
+    pub(crate) fn set_ordered_item_keys(&mut self, ordered_item_keys: bool) {
+        self.ordered_index = if ordered_item_keys {
+            Some(self.index.keys().cloned().collect())
+        } else {
+            None
+        };
+    }
+
+    /// Returns the smallest item key in this `ChunkStorage`.
+    pub(crate) fn min_item_key(&self) -> Option<&ItemKey> {
+        match self.ordered_index.as_ref() {
+            Some(ordered_index) => ordered_index.first().map(Borrow::borrow),
+            None => self.item_keys().min(),
+        }
+    }
+
+    /// Returns the largest item key in this `ChunkStorage`.
+    pub(crate) fn max_item_key(&self) -> Option<&ItemKey> {
+        match self.ordered_index.as_ref() {
+            Some(ordered_index) => ordered_index.last().map(Borrow::borrow),
+            None => self.item_keys().max(),
+        }
+    }
+
+    /// Returns the smallest item key greater than the given item key, or the smallest item key
+    /// if none is given.
+    pub(crate) fn next_item_key(&self, after: Option<&ItemKey>) -> Option<&ItemKey> {
+        match (self.ordered_index.as_ref(), after) {
+            (Some(ordered_index), Some(after)) => ordered_index
+                .range::<ItemKey, _>((Bound::Excluded(after), Bound::Unbounded))
+                .next()
+                .map(Borrow::borrow),
+            (Some(ordered_index), None) => ordered_index.first().map(Borrow::borrow),
+            (None, Some(after)) => self.item_keys().filter(|item_key| *item_key > after).min(),
+            (None, None) => self.item_keys().min(),
+        }
+    }
+
+    /// Iterate over the item keys of this `ChunkStorage` that fall within the given range. The
+    /// item keys come in order only if this `ChunkStorage` keeps its item keys in order; otherwise
+    /// every item key is tested. An inverted range is empty.
+    pub(crate) fn item_keys_in_range<'a>(
+        &'a self,
+        start: Bound<&'a ItemKey>,
+        end: Bound<&'a ItemKey>,
+    ) -> impl Iterator<Item = &'a ItemKey> + 'a {
+        let (ordered, unordered) = match self.ordered_index.as_ref() {
+            _ if !is_valid_range(start, end) => (None, None),
+            Some(ordered_index) => (Some(ordered_index.range::<ItemKey, _>((start, end))), None),
+            None => (None, Some(self.item_keys())),
+        };
+
+        ordered.into_iter().flatten().map(Borrow::borrow).chain(
+            unordered
+                .into_iter()
+                .flatten()
+                .filter(move |item_key| (start, end).contains(*item_key)),
+        )
+    }
+
     pub(crate) fn raw(&self) -> &[Element] {
         &self.data
     }
 
     pub(crate) fn add(&mut self, element: Element) -> usize {
-        let chunk_key = element.chunk_key();
-        let item_key = element.item_key();
-        assert_eq!(self.chunk_key.borrow(), chunk_key.borrow());
-        let old_key = self.index.insert(item_key.into_owned(), self.data.len());
-        assert!(old_key.is_none(), "duplicate item key within chunk");
+        match self.try_add(element) {
+            Ok(idx) => idx,
+            Err(_) => panic!("duplicate item key within chunk"),
+        }
+    }
+
+    /// Add an element, or give it back if an element with the same item key already exists.
+    pub(crate) fn try_add(&mut self, element: Element) -> Result<usize, Element> {
+        assert_eq!(self.chunk_key.borrow(), element.chunk_key().borrow());
+
+        if self.index.contains_key(element.item_key().borrow()) {
+            return Err(element);
+        }
+
         let idx = self.data.len();
+        self.index.insert(element.item_key().into_owned(), idx);
+        if let Some(ordered_index) = self.ordered_index.as_mut() {
+            ordered_index.insert(element.item_key().into_owned());
+        }
         self.data.push(element);
-        idx
+        Ok(idx)
+    }
+
+    /// Add an element, replacing any existing element with the same item key.
+    pub(crate) fn replace(&mut self, element: Element) -> Option<Element> {
+        match self.try_add(element) {
+            Ok(_) => None,
+            Err(element) => {
+                let idx = self.index[element.item_key().borrow()];
+                Some(std::mem::replace(&mut self.data[idx], element))
+            }
+        }
     }
 
     pub(crate) fn extend<I, K>(&mut self, i: I)
@@ -113,11 +230,7 @@ where
         &mut self.data[idx]
     }
 
-    pub(crate) fn iter(&self) -> impl Iterator<Item = &Element> {
-        self.data.iter()
-    }
-
-    pub(crate) fn query<'a, Q>(&'a self, query: Q) -> impl Iterator<Item = &'a Element>
+    pub(crate) fn query<'a, Q>(&'a self, query: Q) -> impl DoubleEndedIterator<Item = &'a Element>
     where
         Q: Query<ChunkKey, ItemKey, Element> + Clone + 'a,
     {
@@ -158,9 +271,9 @@ where
         }
     }
 
-    pub(crate) fn remove<'a, Q, F>(&'a mut self, query: &Q, f: &F)
+    pub(crate) fn remove<'a, Q, F>(&'a mut self, query: &Q, f: &mut F)
     where
-        F: Fn(Element),
+        F: FnMut(Element),
         Q: Query<ChunkKey, ItemKey, Element>,
     {
         let mut last_removed_idx = self.data.len();
@@ -178,7 +291,11 @@ where
     /// Remove the specified element and return it
     pub(crate) fn remove_idx(&mut self, idx: usize) -> Element {
         let result = self.data.swap_remove(idx);
+        self.generation = next_generation();
         self.index.remove(result.item_key().borrow());
+        if let Some(ordered_index) = self.ordered_index.as_mut() {
+            ordered_index.remove(result.item_key().borrow());
+        }
 
         if idx < self.data.len() {
             self.index
@@ -200,26 +317,145 @@ where
         &self.data
     }
 
-    pub(crate) fn validate(&self) {
+    /// Mutable access to every element of this `ChunkStorage`, alongside its chunk key and its
+    /// item key index, without touching any element. The caller must touch each element it
+    /// changes using the returned `ChangedVec`, and must not change any element's keys.
+    pub(crate) fn split_mut(
+        &mut self,
+    ) -> (
+        &ChunkKey,
+        &ItemIndex<ItemKey::Owned>,
+        &mut [Element],
+        &mut ChangedVec,
+    ) {
+        let (data, changed_vec) = self.data.split_mut();
+        (self.chunk_key.borrow(), &self.index, data, changed_vec)
+    }
+
+    pub(crate) fn try_validate(
+        &self,
+    ) -> Result<(), ValidationError<ChunkKey::Owned, ItemKey::Owned>> {
         for (idx, element) in self.data.iter().enumerate() {
-            assert_eq!(
-                self.chunk_key.borrow(),
-                element.chunk_key().borrow(),
-                "element chunk_key() does match chunk chunk_key()"
-            );
-            assert_eq!(
-                Some(&idx),
-                self.index.get(element.item_key().borrow()),
-                "element not indexed"
-            );
+            let item_key = element.item_key();
+
+            if self.chunk_key.borrow() != element.chunk_key().borrow() {
+                return Err(ValidationError::WrongChunk {
+                    chunk_key: self.chunk_key.clone(),
+                    item_key: item_key.into_owned(),
+                    idx,
+                });
+            }
+
+            match self.index.get(item_key.borrow()) {
+                Some(indexed_idx) if *indexed_idx == idx => {}
+                Some(indexed_idx)
+                    if self
+                        .data
+                        .get(*indexed_idx)
+                        .map(|other| other.item_key() == item_key)
+                        .unwrap_or(false) =>
+                {
+                    return Err(ValidationError::DuplicateItemKey {
+                        chunk_key: self.chunk_key.clone(),
+                        item_key: item_key.into_owned(),
+                        indexed_idx: *indexed_idx,
+                        duplicate_idx: idx,
+                    });
+                }
+                _ => {
+                    return Err(ValidationError::ItemNotIndexed {
+                        chunk_key: self.chunk_key.clone(),
+                        item_key: item_key.into_owned(),
+                        idx,
+                    });
+                }
+            }
         }
 
         for (item_key, idx) in self.index.iter() {
-            assert_eq!(
-                item_key.borrow(),
-                self.data[*idx].item_key().borrow(),
-                "element item_key() does not match index"
-            );
+            let matches = self
+                .data
+                .get(*idx)
+                .map(|element| item_key.borrow() == element.item_key().borrow())
+                .unwrap_or(false);
+
+            if !matches {
+                return Err(ValidationError::BrokenItemIndex {
+                    chunk_key: self.chunk_key.clone(),
+                    item_key: item_key.clone(),
+                    idx: *idx,
+                });
+            }
+        }
+
+        let unordered = self.ordered_index.as_ref().and_then(|ordered_index| {
+            ordered_index
+                .iter()
+                .find(|item_key| !self.index.contains_key((*item_key).borrow()))
+                .or_else(|| {
+                    self.index
+                        .keys()
+                        .find(|item_key| !ordered_index.contains((*item_key).borrow()))
+                })
+        });
+
+        if let Some(item_key) = unordered {
+            return Err(ValidationError::BrokenOrderedItemIndex {
+                chunk_key: self.chunk_key.clone(),
+                item_key: item_key.clone(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+impl<ChunkKey, ItemKey, Element> ChunkStorage<ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+{
+    /// Prepare this `ChunkStorage` to be shared between clones of a `Storage`.
+    pub(crate) fn share(&self)
+    where
+        Element: Clone,
+    {
+        let _ = self.unshare.set(<Self as Clone>::clone);
+    }
+
+    /// Copy this shared `ChunkStorage`.
+    pub(crate) fn unshare(&self) -> Self {
+        let unshare = self
+            .unshare
+            .get()
+            .expect("retriever bug: chunk was shared without calling share()");
+        unshare(self)
+    }
+
+    /// Take ownership of a `ChunkStorage`, copying it only if it's still shared.
+    pub(crate) fn unwrap_or_unshare(chunk: Arc<Self>) -> Self {
+        Arc::try_unwrap(chunk).unwrap_or_else(|chunk| chunk.unshare())
+    }
+}
+
+impl<ChunkKey, ItemKey, Element> Clone for ChunkStorage<ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Clone,
+{
+    fn clone(&self) -> Self {
+        ChunkStorage {
+            chunk_key: self.chunk_key.clone(),
+            data: self.data.clone(),
+            index: self.index.clone(),
+            ordered_index: self.ordered_index.clone(),
+            unshare: self.unshare.clone(),
+            generation: self.generation,
         }
     }
 }
@@ -244,11 +480,18 @@ where
     ItemKey::Owned: ValidKey,
 {
     fn memory_usage(&self) -> MemoryUsage {
-        MemoryUsage::merge(self.index.memory_usage(), self.data.memory_usage())
+        let mut result = MemoryUsage::merge(self.index.memory_usage(), self.data.memory_usage());
+        if let Some(ordered_index) = self.ordered_index.as_ref() {
+            result = MemoryUsage::merge(result, ordered_index.memory_usage());
+        }
+        result
     }
 
     fn shrink_with<F: Fn(&MemoryUsage) -> Option<usize>>(&mut self, f: F) {
         self.index.shrink_with(&f);
+        if let Some(ordered_index) = self.ordered_index.as_mut() {
+            ordered_index.shrink_with(&f);
+        }
         self.data.shrink_with(&f);
     }
 }
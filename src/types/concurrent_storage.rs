@@ -0,0 +1,329 @@
+use crate::internal::hasher::HasherImpl;
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::error::DuplicateItemError;
+use crate::types::storage::Storage;
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::sync::{Arc, PoisonError, RwLock, RwLockWriteGuard};
+
+/// A thread-safe collection of `Elements` that locks each chunk separately. Writers to different
+/// chunks never wait for each other, and any number of readers may share a chunk that isn't
+/// being written to. Only creating or removing a whole chunk briefly locks every chunk.
+///
+/// Each chunk lives in its own `Storage`, which `ConcurrentStorage::read_chunk` and
+/// `ConcurrentStorage::write_chunk` lend out while holding that chunk's lock, so every query and
+/// edit that a `Storage` supports is available one chunk at a time. There is no way to lock
+/// several chunks at once; use `ConcurrentStorage::into_storage` to query every chunk together.
+///
+/// # Example
+///
+/// ```
+/// use retriever::prelude::*;
+/// use retriever::types::concurrent_storage::ConcurrentStorage;
+///
+/// // Response times in milliseconds, chunked by host and keyed by request number.
+/// let samples : ConcurrentStorage<&'static str, u64, (&'static str, u64, u64)> =
+///   ConcurrentStorage::new();
+///
+/// std::thread::scope(|scope| {
+///   for host in ["alpha", "beta", "gamma"] {
+///     let samples = &samples;
+///     scope.spawn(move || {
+///       for i in 0..100 {
+///         samples.add((host, i, i % 10));
+///       }
+///     });
+///   }
+/// });
+///
+/// assert_eq!(Some(("beta", 42, 2)), samples.get(&ID.chunk("beta").item(42)));
+/// assert_eq!(Some(450), samples.read_chunk(&"gamma", |storage| storage.iter().map(|x| x.2).sum::<u64>()));
+/// assert_eq!(None, samples.read_chunk(&"delta", |storage| storage.iter().count()));
+///
+/// samples.write_chunk(&"alpha", |storage| {
+///   storage.remove(Everything.filter(|x: &(&str, u64, u64)| x.2 > 0), std::mem::drop);
+/// });
+///
+/// assert_eq!(10, samples.remove_chunk(&"alpha").len());
+/// assert_eq!(200, samples.into_storage().iter().count());
+/// ```
+pub struct ConcurrentStorage<ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+{
+    chunks: RwLock<Shards<ChunkKey, ItemKey, Element>>,
+}
+
+type SharedShard<ChunkKey, ItemKey, Element> = Arc<RwLock<Shard<ChunkKey, ItemKey, Element>>>;
+
+type Shards<ChunkKey, ItemKey, Element> =
+    HashMap<<ChunkKey as ToOwned>::Owned, SharedShard<ChunkKey, ItemKey, Element>, HasherImpl>;
+
+struct Shard<ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+{
+    storage: Storage<ChunkKey, ItemKey, Element>,
+    // set once this shard has been taken out of the map, so late writers know to look again
+    retired: bool,
+}
+
+impl<ChunkKey, ItemKey, Element> ConcurrentStorage<ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+{
+    /// Construct a new, empty `ConcurrentStorage`.
+    pub fn new() -> Self {
+        ConcurrentStorage {
+            chunks: RwLock::new(HashMap::with_hasher(HasherImpl::default())),
+        }
+    }
+
+    /// Add an `Element`, locking only the chunk it belongs to.
+    ///
+    /// # Panic
+    ///
+    /// Panics if an `Element` with the same `Id` is already present. The chunk stays usable
+    /// afterwards; use `ConcurrentStorage::try_add` to get the duplicate back instead.
+    pub fn add(&self, element: Element) -> &Self {
+        let chunk_key = element.chunk_key().into_owned();
+        self.write_chunk(chunk_key.borrow(), move |storage| {
+            storage.add(element);
+        });
+        self
+    }
+
+    /// Add an `Element`, locking only the chunk it belongs to, or return it as an error if an
+    /// `Element` with the same `Id` is already present.
+    pub fn try_add(&self, element: Element) -> Result<&Self, DuplicateItemError<Element>> {
+        let chunk_key = element.chunk_key().into_owned();
+        self.write_chunk(chunk_key.borrow(), move |storage| storage.try_add(element))?;
+        Ok(self)
+    }
+
+    /// Get a copy of an `Element`, or `None` if there is no such `Element`.
+    pub fn get<R>(&self, unique_id: &R) -> Option<Element>
+    where
+        R: Record<ChunkKey, ItemKey>,
+        Element: Clone,
+    {
+        self.read_chunk(unique_id.chunk_key().borrow(), |storage| {
+            storage.get(unique_id).cloned()
+        })
+        .flatten()
+    }
+
+    /// Lend out the `Storage` holding a single chunk while holding a shared lock on that chunk,
+    /// or return `None` without calling `f` if there is no such chunk.
+    pub fn read_chunk<F, T>(&self, chunk_key: &ChunkKey, f: F) -> Option<T>
+    where
+        F: FnOnce(&Storage<ChunkKey, ItemKey, Element>) -> T,
+    {
+        let shard = self.shard(chunk_key)?;
+        let shard = shard.read().unwrap_or_else(PoisonError::into_inner);
+
+        if shard.retired {
+            return None;
+        }
+
+        Some(f(&shard.storage))
+    }
+
+    /// Lend out the `Storage` holding a single chunk while holding an exclusive lock on that
+    /// chunk. The chunk is created if `f` adds to it, and removed again if `f` leaves it empty.
+    ///
+    /// A panic in `f` doesn't make the chunk unusable for other threads: the chunk keeps
+    /// whatever changes `f` made before it panicked, just as a `Storage` would.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `f` adds an `Element` belonging to any other chunk. Those `Elements` are
+    /// removed again before panicking.
+    pub fn write_chunk<F, T>(&self, chunk_key: &ChunkKey, f: F) -> T
+    where
+        F: FnOnce(&mut Storage<ChunkKey, ItemKey, Element>) -> T,
+    {
+        loop {
+            let shared_shard = match self.shard(chunk_key) {
+                Some(shard) => shard,
+                None => self
+                    .chunks
+                    .write()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .entry(chunk_key.to_owned())
+                    .or_insert_with(|| {
+                        Arc::new(RwLock::new(Shard {
+                            storage: Storage::new(),
+                            retired: false,
+                        }))
+                    })
+                    .clone(),
+            };
+
+            let mut shard = shared_shard.write().unwrap_or_else(PoisonError::into_inner);
+
+            if shard.retired {
+                continue;
+            }
+
+            // Retire the shard even if f panics, so an empty chunk never lingers.
+            let guard = RetireIfEmpty {
+                concurrent_storage: self,
+                chunk_key,
+                shared_shard: &shared_shard,
+                shard: &mut shard,
+            };
+
+            let result = f(&mut guard.shard.storage);
+
+            let foreign: Vec<ChunkKey::Owned> = guard
+                .shard
+                .storage
+                .chunk_keys()
+                .into_iter()
+                .filter(|other| *other != chunk_key)
+                .map(|other| other.to_owned())
+                .collect();
+
+            if !foreign.is_empty() {
+                for other in foreign.iter() {
+                    guard.shard.storage.remove_chunk(other.borrow());
+                }
+
+                panic!(
+                    "ConcurrentStorage::write_chunk added an element belonging to another chunk"
+                );
+            }
+
+            return result;
+        }
+    }
+
+    /// Remove an entire chunk, returning its `Elements` in no particular order.
+    pub fn remove_chunk(&self, chunk_key: &ChunkKey) -> Vec<Element> {
+        let shard = match self
+            .chunks
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(chunk_key)
+        {
+            Some(shard) => shard,
+            None => return Vec::new(),
+        };
+
+        let mut shard = shard.write().unwrap_or_else(PoisonError::into_inner);
+        shard.retired = true;
+
+        std::mem::replace(&mut shard.storage, Storage::new())
+            .dissolve()
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+
+    /// The chunk keys of every chunk, in no particular order. Other threads may add or remove
+    /// chunks as soon as this method returns.
+    pub fn chunk_keys(&self) -> Vec<ChunkKey::Owned> {
+        self.chunks
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    /// Combine every chunk into a single `Storage`.
+    pub fn into_storage(self) -> Storage<ChunkKey, ItemKey, Element> {
+        let mut result = Storage::new();
+
+        for (_, shard) in self
+            .chunks
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner)
+        {
+            let mut shard = shard.write().unwrap_or_else(PoisonError::into_inner);
+            result.append(&mut shard.storage);
+        }
+
+        result
+    }
+
+    fn shard(&self, chunk_key: &ChunkKey) -> Option<SharedShard<ChunkKey, ItemKey, Element>> {
+        self.chunks
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(chunk_key)
+            .cloned()
+    }
+}
+
+// Takes an emptied shard out of its ConcurrentStorage when the write that emptied it ends,
+// whether or not that write panicked.
+struct RetireIfEmpty<'a, 'b, ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+{
+    concurrent_storage: &'a ConcurrentStorage<ChunkKey, ItemKey, Element>,
+    chunk_key: &'a ChunkKey,
+    shared_shard: &'a SharedShard<ChunkKey, ItemKey, Element>,
+    shard: &'a mut RwLockWriteGuard<'b, Shard<ChunkKey, ItemKey, Element>>,
+}
+
+impl<ChunkKey, ItemKey, Element> Drop for RetireIfEmpty<'_, '_, ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+{
+    fn drop(&mut self) {
+        if self.shard.storage.iter().next().is_some() {
+            return;
+        }
+
+        // Lock order is always shard, then map, so this can't deadlock with another writer,
+        // which never holds the map lock while waiting for a shard.
+        self.shard.retired = true;
+        let mut chunks = self
+            .concurrent_storage
+            .chunks
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        if chunks
+            .get(self.chunk_key)
+            .is_some_and(|current| Arc::ptr_eq(current, self.shared_shard))
+        {
+            chunks.remove(self.chunk_key);
+        }
+    }
+}
+
+impl<ChunkKey, ItemKey, Element> Default for ConcurrentStorage<ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
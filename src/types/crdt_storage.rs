@@ -0,0 +1,164 @@
+use crate::traits::crdt::CrdtElement;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::id::Id;
+use crate::types::storage::Storage;
+use std::borrow::Borrow;
+
+/// A `Storage` of `CrdtElements`. Adding an `Element` whose `Id` is already present merges the
+/// two `Elements` rather than panicking, so replicas that exchange `Elements` in any order
+/// converge on the same contents.
+///
+/// `CrdtStorage` intentionally offers no way to remove or arbitrarily mutate an `Element`,
+/// since either operation could be undone by a later merge. Represent deletion as a tombstone
+/// within the `Element` instead.
+///
+/// # Example
+///
+/// ```
+/// use retriever::prelude::*;
+/// use retriever::traits::crdt::CrdtElement;
+/// use retriever::types::crdt_storage::CrdtStorage;
+/// use std::borrow::Cow;
+///
+/// // A grow-only counter with one slot per replica.
+/// #[derive(Clone, Debug, Eq, PartialEq)]
+/// struct Counter {
+///   name: &'static str,
+///   slots: [u64; 2],
+/// }
+///
+/// impl Record<(), str> for Counter {
+///   fn chunk_key(&self) -> Cow<()> {
+///     Cow::Owned(())
+///   }
+///
+///   fn item_key(&self) -> Cow<str> {
+///     Cow::Borrowed(self.name)
+///   }
+/// }
+///
+/// impl CrdtElement<(), str> for Counter {
+///   fn merge(self, other: Self) -> Self {
+///     Counter {
+///       name: self.name,
+///       slots: [self.slots[0].max(other.slots[0]), self.slots[1].max(other.slots[1])],
+///     }
+///   }
+/// }
+///
+/// let updates = vec![
+///   Counter { name: "clicks", slots: [3, 0] },
+///   Counter { name: "clicks", slots: [1, 5] },
+///   Counter { name: "views", slots: [0, 2] },
+/// ];
+///
+/// let mut a : CrdtStorage<(), str, Counter> = CrdtStorage::new();
+/// let mut b : CrdtStorage<(), str, Counter> = CrdtStorage::new();
+///
+/// a.apply(updates.iter().cloned());
+/// b.apply(updates.iter().rev().cloned());
+///
+/// assert_eq!(
+///   a.storage().get(&ID.item("clicks")),
+///   Some(&Counter { name: "clicks", slots: [3, 5] })
+/// );
+/// assert_eq!(a.storage().get(&ID.item("clicks")), b.storage().get(&ID.item("clicks")));
+/// assert_eq!(a.storage().get(&ID.item("views")), b.storage().get(&ID.item("views")));
+/// ```
+pub struct CrdtStorage<ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+{
+    storage: Storage<ChunkKey, ItemKey, Element>,
+}
+
+impl<ChunkKey, ItemKey, Element> CrdtStorage<ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: CrdtElement<ChunkKey, ItemKey>,
+{
+    /// Construct a new, empty `CrdtStorage`.
+    pub fn new() -> Self {
+        CrdtStorage {
+            storage: Storage::new(),
+        }
+    }
+
+    /// Add an `Element`, merging it with any existing `Element` that has the same `Id`.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `CrdtElement::merge` changes the chunk key or item key of the `Element`.
+    pub fn add(&mut self, element: Element) -> &mut Self {
+        let id: Id<ChunkKey::Owned, ItemKey::Owned> = Id::cloned(&element);
+
+        let merged = match self.storage.entry(id.clone()).remove() {
+            Some(existing) => existing.merge(element),
+            None => element,
+        };
+
+        assert_eq!(
+            id.0.borrow(),
+            merged.chunk_key().borrow(),
+            "CrdtElement::merge changed the chunk key"
+        );
+        assert_eq!(
+            id.1.borrow(),
+            merged.item_key().borrow(),
+            "CrdtElement::merge changed the item key"
+        );
+
+        self.storage.add(merged);
+        self
+    }
+
+    /// Add many `Elements`, merging each with any existing `Element` that has the same `Id`.
+    pub fn apply<I>(&mut self, elements: I) -> &mut Self
+    where
+        I: IntoIterator<Item = Element>,
+    {
+        for element in elements {
+            self.add(element);
+        }
+
+        self
+    }
+
+    /// Merge every `Element` of another replica into this one.
+    pub fn merge_from(&mut self, other: Self) -> &mut Self {
+        for chunk in other.storage.dissolve() {
+            self.apply(chunk);
+        }
+
+        self
+    }
+
+    /// Read-only access to the underlying `Storage`, for use with queries and indexes.
+    pub fn storage(&self) -> &Storage<ChunkKey, ItemKey, Element> {
+        &self.storage
+    }
+
+    /// Unwrap the underlying `Storage`.
+    pub fn into_storage(self) -> Storage<ChunkKey, ItemKey, Element> {
+        self.storage
+    }
+}
+
+impl<ChunkKey, ItemKey, Element> Default for CrdtStorage<ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: CrdtElement<ChunkKey, ItemKey>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,90 @@
+use std::convert::TryInto;
+
+/// An opaque token marking where a paged query left off. Get one from `Storage::query_page`,
+/// and pass it back to continue the query from the same place.
+///
+/// A `Cursor` remembers a chunk index, an item offset within that chunk, and the generation
+/// at which it was issued. It doesn't borrow the `Storage`, so the `Storage` may be freely
+/// modified between pages, and it can be converted to bytes and back with `Cursor::to_bytes`
+/// and `Cursor::from_bytes` to hand to a network client.
+///
+/// # Invalidation
+///
+/// Adding elements, and modifying or removing elements of other chunks, never invalidates a
+/// `Cursor`. Elements added to the chunk the `Cursor` points into are visited, as are elements
+/// of new chunks. Elements added to chunks that were already paged through are not.
+///
+/// A `Cursor` is invalidated, and `Storage::query_page` returns an `InvalidCursorError`,
+/// if any element is removed from the chunk it points into, or if any chunk at all is removed
+/// from the `Storage`, since either may move elements that haven't been visited yet to a
+/// position that has. A `Cursor` is also invalid for any `Storage` other than the one
+/// (or a clone of the one) that issued it, and doesn't survive the process that issued it.
+/// When a `Cursor` is invalidated, start the query over.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Cursor {
+    storage_id: u64,
+    chunk: usize,
+    offset: usize,
+    generation: u64,
+}
+
+/// One page of the elements matching a query, as returned by `Storage::query_page`.
+#[derive(Clone, Debug)]
+pub struct Page<'a, Element> {
+    /// The matching elements of this page.
+    pub elements: Vec<&'a Element>,
+    /// Where the next page begins, or `None` if there are no more elements.
+    pub next: Option<Cursor>,
+}
+
+impl Cursor {
+    pub(crate) fn new(storage_id: u64, chunk: usize, offset: usize, generation: u64) -> Self {
+        Cursor {
+            storage_id,
+            chunk,
+            offset,
+            generation,
+        }
+    }
+
+    pub(crate) fn storage_id(&self) -> u64 {
+        self.storage_id
+    }
+
+    /// The index of the chunk this `Cursor` points into.
+    pub(crate) fn chunk(&self) -> usize {
+        self.chunk
+    }
+
+    /// The internal index of the first element of the chunk not yet visited.
+    pub(crate) fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Encode this `Cursor` as bytes.
+    pub fn to_bytes(&self) -> [u8; 32] {
+        let mut result = [0; 32];
+        result[0..8].copy_from_slice(&self.storage_id.to_le_bytes());
+        result[8..16].copy_from_slice(&(self.chunk as u64).to_le_bytes());
+        result[16..24].copy_from_slice(&(self.offset as u64).to_le_bytes());
+        result[24..32].copy_from_slice(&self.generation.to_le_bytes());
+        result
+    }
+
+    /// Decode a `Cursor` from bytes produced by `Cursor::to_bytes`. Any 32 bytes decode to some
+    /// `Cursor`, but a `Cursor` that wasn't issued by a `Storage` is simply invalid.
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        let word = |i: usize| u64::from_le_bytes(bytes[i * 8..(i + 1) * 8].try_into().unwrap());
+
+        Cursor {
+            storage_id: word(0),
+            chunk: word(1) as usize,
+            offset: word(2) as usize,
+            generation: word(3),
+        }
+    }
+}
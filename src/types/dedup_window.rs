@@ -0,0 +1,128 @@
+use crate::types::id::Id;
+use std::collections::{HashSet, VecDeque};
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+/// How long a `Storage` remembers the `Id` of each element it ingests, so that it can quietly
+/// drop redeliveries of the same element. This is useful when the upstream source delivers
+/// messages at-least-once, and looking up every message with `Storage::get` would be too slow.
+///
+/// Only the `Id` is compared, never the rest of the element. The window is separate from the
+/// contents of the `Storage`, so an element that was removed is still dropped if it is added
+/// again within the window.
+///
+/// # Example
+///
+/// ```
+/// use retriever::prelude::*;
+/// use retriever::types::dedup_window::DedupWindow;
+/// use retriever::types::storage_builder::StorageBuilder;
+///
+/// let mut storage : Storage<u64, u64, (u64, u64, &'static str)> = StorageBuilder::new()
+///   .dedup_window(DedupWindow::Count(2))
+///   .build();
+///
+/// storage.add((1, 1, "first delivery"));
+/// storage.add((1, 1, "redelivery"));   // dropped, instead of panicking
+/// storage.add((1, 2, "hello"));
+/// storage.add((1, 3, "world"));        // (1, 1) falls out of the window
+///
+/// assert_eq!(Some(&(1, 1, "first delivery")), storage.get(&ID.chunk(1).item(1)));
+/// assert_eq!(1, storage.dedup_stats().unwrap().dropped);
+/// assert_eq!(3, storage.dedup_stats().unwrap().admitted);
+/// # storage.validate();
+/// ```
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum DedupWindow {
+    /// Remember the `Ids` of this many most recently ingested elements.
+    Count(usize),
+    /// Remember the `Ids` of the elements ingested within this much time.
+    Duration(Duration),
+}
+
+/// Counts of the elements admitted and dropped by a `Storage`'s `DedupWindow`.
+/// See `Storage::dedup_stats`.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct DedupStats {
+    /// The number of elements that were not in the window, and so were added.
+    pub admitted: u64,
+    /// The number of elements that were already in the window, and so were dropped.
+    pub dropped: u64,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Dedup<C, I> {
+    window: DedupWindow,
+    // the remembered ids, oldest first, with the time each was ingested if the window is timed
+    recent: VecDeque<(Id<C, I>, Option<Instant>)>,
+    // the same ids as recent, for fast lookup
+    ids: HashSet<Id<C, I>>,
+    stats: DedupStats,
+}
+
+impl<C, I> Dedup<C, I>
+where
+    C: Clone + Eq + Hash,
+    I: Clone + Eq + Hash,
+{
+    pub(crate) fn new(window: DedupWindow) -> Self {
+        Dedup {
+            window,
+            recent: VecDeque::new(),
+            ids: HashSet::new(),
+            stats: DedupStats::default(),
+        }
+    }
+
+    pub(crate) fn stats(&self) -> DedupStats {
+        self.stats
+    }
+
+    /// True IFF the given `Id` is not in the window. Either way, it is in the window afterwards.
+    pub(crate) fn admit(&mut self, id: Id<C, I>) -> bool {
+        let now = match self.window {
+            DedupWindow::Count(_) => None,
+            DedupWindow::Duration(duration) => {
+                let now = Instant::now();
+                self.expire(|ingested| now.duration_since(ingested) >= duration);
+                Some(now)
+            }
+        };
+
+        if self.ids.contains(&id) {
+            self.stats.dropped += 1;
+            return false;
+        }
+
+        self.stats.admitted += 1;
+        self.ids.insert(id.clone());
+        self.recent.push_back((id, now));
+
+        if let DedupWindow::Count(count) = self.window {
+            while self.recent.len() > count {
+                self.pop();
+            }
+        }
+
+        true
+    }
+
+    fn expire<F>(&mut self, expired: F)
+    where
+        F: Fn(Instant) -> bool,
+    {
+        while let Some((_, Some(ingested))) = self.recent.front() {
+            if !expired(*ingested) {
+                break;
+            }
+
+            self.pop();
+        }
+    }
+
+    fn pop(&mut self) {
+        if let Some((id, _)) = self.recent.pop_front() {
+            self.ids.remove(&id);
+        }
+    }
+}
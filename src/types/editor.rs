@@ -1,7 +1,10 @@
 use super::chunk_storage::ChunkStorage;
 use super::id::Id;
+use super::query_mut::DeferredTouches;
 use crate::traits::record::Record;
 use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use std::borrow::Borrow;
+use std::sync::Arc;
 
 /// An Editor for an element. An instance of `Editor` is proof that the backing element
 /// exists in `Storage`, and allows unlimited mutation (but not removal) of that element.
@@ -11,10 +14,30 @@ where
     ChunkKey::Owned: ValidKey,
     ItemKey: BorrowedKey,
     ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
 {
     id: Id<&'a ChunkKey, &'a ItemKey>,
     idx: usize,
-    storage: &'a mut ChunkStorage<ChunkKey, ItemKey, Element>,
+    target: Target<'a, ChunkKey, ItemKey, Element>,
+}
+
+// Where an Editor's element lives.
+enum Target<'a, ChunkKey: ?Sized, ItemKey: ?Sized, Element>
+where
+    ChunkKey: BorrowedKey,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey,
+    ItemKey::Owned: ValidKey,
+{
+    // the element is borrowed through its chunk, which tracks each change as it happens
+    Chunk(&'a mut ChunkStorage<ChunkKey, ItemKey, Element>),
+    // the element is borrowed directly, and any change is tracked once the Editor is dropped
+    Deferred {
+        element: &'a mut Element,
+        chunk: usize,
+        changed: bool,
+        touches: Arc<DeferredTouches<'a>>,
+    },
 }
 
 impl<'a, ChunkKey, ItemKey, Element> Editor<'a, ChunkKey, ItemKey, Element>
@@ -33,7 +56,32 @@ where
     where
         'x: 'a,
     {
-        Editor { id, idx, storage }
+        Editor {
+            id,
+            idx,
+            target: Target::Chunk(storage),
+        }
+    }
+
+    /// An `Editor` for an element borrowed directly out of its chunk. If the element is
+    /// changed, the change is passed to the given `DeferredTouches` when the `Editor` is dropped.
+    pub(super) fn deferred(
+        id: Id<&'a ChunkKey, &'a ItemKey>,
+        idx: usize,
+        element: &'a mut Element,
+        chunk: usize,
+        touches: Arc<DeferredTouches<'a>>,
+    ) -> Self {
+        Editor {
+            id,
+            idx,
+            target: Target::Deferred {
+                element,
+                chunk,
+                changed: false,
+                touches,
+            },
+        }
     }
 
     /// Returns this element's unique `Id`.
@@ -46,18 +94,20 @@ where
     /// For efficiency, try not to call modify until you're absolutely sure you need it. Once you
     /// obtain a mutable reference to the element, it must updated in all indices, which costs
     /// time and memory.
-    pub fn modify<F>(self, f: F) -> Self
+    pub fn modify<F>(mut self, f: F) -> Self
     where
         F: FnOnce(&mut Element),
     {
-        let element = self.storage.get_idx_mut(self.idx);
-        f(element);
+        f(self.get_mut());
         self
     }
 
     /// Get a reference to this element.
     pub fn get(&self) -> &Element {
-        self.storage.get_idx(self.idx)
+        match &self.target {
+            Target::Chunk(storage) => storage.get_idx(self.idx),
+            Target::Deferred { element, .. } => element,
+        }
     }
 
     /// Get a mutable reference to this element.
@@ -66,6 +116,40 @@ where
     /// obtain a mutable reference to the element, it must updated in all indices, which costs
     /// time and memory.
     pub fn get_mut(&mut self) -> &mut Element {
-        self.storage.get_idx_mut(self.idx)
+        match &mut self.target {
+            Target::Chunk(storage) => storage.get_idx_mut(self.idx),
+            Target::Deferred {
+                element, changed, ..
+            } => {
+                *changed = true;
+                element
+            }
+        }
+    }
+}
+
+impl<'a, ChunkKey, ItemKey, Element> Drop for Editor<'a, ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+{
+    fn drop(&mut self) {
+        if let Target::Deferred {
+            element,
+            chunk,
+            changed: true,
+            touches,
+        } = &self.target
+        {
+            touches.touch(*chunk, self.idx);
+
+            if !std::thread::panicking() {
+                assert_eq!(self.id.0, element.chunk_key().borrow());
+                assert_eq!(self.id.1, element.item_key().borrow());
+            }
+        }
     }
 }
@@ -0,0 +1,390 @@
+use super::cursor::Cursor;
+use std::fmt;
+
+/// Returned by `Storage::try_add_chunk` and `Storage::try_add_chunks` when a group of
+/// `Elements` does not share a single chunk key.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ChunkMismatchError<Element> {
+    /// The first `Element` whose chunk key did not match the first `Element` of its group.
+    pub element: Element,
+    /// The position of the offending group. Always zero for `try_add_chunk`.
+    pub group: usize,
+    /// The position of the offending `Element` within its group.
+    pub position: usize,
+}
+
+impl<Element> fmt::Display for ChunkMismatchError<Element> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "retriever: element {} of group {} does not share the chunk key of its group",
+            self.position, self.group
+        )
+    }
+}
+
+impl<Element> std::error::Error for ChunkMismatchError<Element> where Element: fmt::Debug {}
+
+/// Returned by `Storage::try_add_chunk` and `Storage::try_add_chunks` when a group of
+/// `Elements` can not be added.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum AddChunkError<Element> {
+    /// A group of `Elements` does not share a single chunk key.
+    ChunkMismatch(ChunkMismatchError<Element>),
+    /// An `Element` has the same `Id` as an `Element` already present, or as an earlier
+    /// `Element` of the same call.
+    DuplicateItem {
+        /// The first `Element` whose `Id` was already taken.
+        element: Element,
+        /// The position of the offending group. Always zero for `try_add_chunk`.
+        group: usize,
+        /// The position of the offending `Element` within its group.
+        position: usize,
+    },
+}
+
+impl<Element> AddChunkError<Element> {
+    /// The `Element` that could not be added.
+    pub fn element(&self) -> &Element {
+        match self {
+            AddChunkError::ChunkMismatch(error) => &error.element,
+            AddChunkError::DuplicateItem { element, .. } => element,
+        }
+    }
+}
+
+impl<Element> From<ChunkMismatchError<Element>> for AddChunkError<Element> {
+    fn from(error: ChunkMismatchError<Element>) -> Self {
+        AddChunkError::ChunkMismatch(error)
+    }
+}
+
+impl<Element> fmt::Display for AddChunkError<Element> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AddChunkError::ChunkMismatch(error) => error.fmt(f),
+            AddChunkError::DuplicateItem {
+                group, position, ..
+            } => write!(
+                f,
+                "retriever: element {} of group {} has a duplicate item key",
+                position, group
+            ),
+        }
+    }
+}
+
+impl<Element> std::error::Error for AddChunkError<Element> where Element: fmt::Debug {}
+
+/// Returned by `Storage::try_add` when an `Element` with the same `Id` is already present.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DuplicateItemError<Element> {
+    /// The `Element` that could not be added.
+    pub element: Element,
+}
+
+impl<Element> fmt::Display for DuplicateItemError<Element> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "retriever: duplicate item key within chunk")
+    }
+}
+
+impl<Element> std::error::Error for DuplicateItemError<Element> where Element: fmt::Debug {}
+
+/// Returned by `UniqueIndex::try_add` when an `Element` can not be added.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum UniqueAddError<Element> {
+    /// An `Element` with the same `Id` is already present.
+    DuplicateItem(DuplicateItemError<Element>),
+    /// Another `Element` already has one of the index keys of this `Element`.
+    DuplicateIndexKey {
+        /// The `Element` that could not be added.
+        element: Element,
+    },
+}
+
+impl<Element> UniqueAddError<Element> {
+    /// The `Element` that could not be added.
+    pub fn element(&self) -> &Element {
+        match self {
+            UniqueAddError::DuplicateItem(error) => &error.element,
+            UniqueAddError::DuplicateIndexKey { element } => element,
+        }
+    }
+}
+
+impl<Element> From<DuplicateItemError<Element>> for UniqueAddError<Element> {
+    fn from(error: DuplicateItemError<Element>) -> Self {
+        UniqueAddError::DuplicateItem(error)
+    }
+}
+
+impl<Element> fmt::Display for UniqueAddError<Element> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UniqueAddError::DuplicateItem(error) => error.fmt(f),
+            UniqueAddError::DuplicateIndexKey { .. } => {
+                write!(f, "retriever: duplicate index key within a unique index")
+            }
+        }
+    }
+}
+
+impl<Element> std::error::Error for UniqueAddError<Element> where Element: fmt::Debug {}
+
+/// Returned by `Storage::try_validate` to describe the first inconsistency found.
+///
+/// # Type Parameters
+///
+/// * `ChunkKey`: the owned form of the `Storage`'s chunk key.
+/// * `ItemKey`: the owned form of the `Storage`'s item key.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ValidationError<ChunkKey, ItemKey> {
+    /// A chunk exists at the given slot but is missing from the chunk index, or indexed at
+    /// a different slot.
+    ChunkNotIndexed {
+        /// The chunk key of the chunk.
+        chunk_key: ChunkKey,
+        /// The slot where the chunk was actually found.
+        idx: usize,
+    },
+    /// The chunk index points at a slot that is missing or holds a different chunk.
+    BrokenChunkIndex {
+        /// The chunk key found in the chunk index.
+        chunk_key: ChunkKey,
+        /// The slot recorded for that chunk key.
+        idx: usize,
+    },
+    /// A chunk exists but contains no elements.
+    EmptyChunk {
+        /// The chunk key of the empty chunk.
+        chunk_key: ChunkKey,
+    },
+    /// An element's `chunk_key()` does not match the chunk in which it is stored.
+    WrongChunk {
+        /// The chunk key of the chunk in which the element is stored.
+        chunk_key: ChunkKey,
+        /// The item key of the element.
+        item_key: ItemKey,
+        /// The element's slot within the chunk.
+        idx: usize,
+    },
+    /// Two elements in the same chunk have the same item key.
+    DuplicateItemKey {
+        /// The chunk key of the chunk containing both elements.
+        chunk_key: ChunkKey,
+        /// The shared item key.
+        item_key: ItemKey,
+        /// The slot of the element recorded in the item index.
+        indexed_idx: usize,
+        /// The slot of the element missing from the item index.
+        duplicate_idx: usize,
+    },
+    /// An element is missing from the item index of its chunk.
+    ItemNotIndexed {
+        /// The chunk key of the chunk.
+        chunk_key: ChunkKey,
+        /// The item key of the element.
+        item_key: ItemKey,
+        /// The element's slot within the chunk.
+        idx: usize,
+    },
+    /// The item index of a chunk points at a slot that is missing or holds a different element.
+    BrokenItemIndex {
+        /// The chunk key of the chunk.
+        chunk_key: ChunkKey,
+        /// The item key found in the item index.
+        item_key: ItemKey,
+        /// The slot recorded for that item key.
+        idx: usize,
+    },
+    /// A chunk key is present in only one of the chunk index and the ordered chunk index.
+    BrokenOrderedChunkIndex {
+        /// The chunk key found in only one index.
+        chunk_key: ChunkKey,
+    },
+    /// An item key is present in only one of the item index and the ordered item index of a
+    /// chunk.
+    BrokenOrderedItemIndex {
+        /// The chunk key of the chunk.
+        chunk_key: ChunkKey,
+        /// The item key found in only one index.
+        item_key: ItemKey,
+    },
+}
+
+impl<ChunkKey, ItemKey> fmt::Display for ValidationError<ChunkKey, ItemKey>
+where
+    ChunkKey: fmt::Debug,
+    ItemKey: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValidationError::ChunkNotIndexed { chunk_key, idx } => {
+                write!(f, "chunk not indexed: {:?} at slot {}", chunk_key, idx)
+            }
+            ValidationError::BrokenChunkIndex { chunk_key, idx } => {
+                write!(f, "index broken: {:?} at slot {}", chunk_key, idx)
+            }
+            ValidationError::EmptyChunk { chunk_key } => {
+                write!(f, "empty chunk: {:?}", chunk_key)
+            }
+            ValidationError::WrongChunk {
+                chunk_key,
+                item_key,
+                idx,
+            } => write!(
+                f,
+                "element chunk_key() does match chunk chunk_key(): {:?} in chunk {:?} at slot {}",
+                item_key, chunk_key, idx
+            ),
+            ValidationError::DuplicateItemKey {
+                chunk_key,
+                item_key,
+                indexed_idx,
+                duplicate_idx,
+            } => write!(
+                f,
+                "duplicate item key within chunk: {:?} in chunk {:?} at slots {} and {}",
+                item_key, chunk_key, indexed_idx, duplicate_idx
+            ),
+            ValidationError::ItemNotIndexed {
+                chunk_key,
+                item_key,
+                idx,
+            } => write!(
+                f,
+                "element not indexed: {:?} in chunk {:?} at slot {}",
+                item_key, chunk_key, idx
+            ),
+            ValidationError::BrokenItemIndex {
+                chunk_key,
+                item_key,
+                idx,
+            } => write!(
+                f,
+                "element item_key() does not match index: {:?} in chunk {:?} at slot {}",
+                item_key, chunk_key, idx
+            ),
+            ValidationError::BrokenOrderedChunkIndex { chunk_key } => {
+                write!(
+                    f,
+                    "ordered chunk index does not match index: {:?}",
+                    chunk_key
+                )
+            }
+            ValidationError::BrokenOrderedItemIndex {
+                chunk_key,
+                item_key,
+            } => write!(
+                f,
+                "ordered index does not match index: {:?} in chunk {:?}",
+                item_key, chunk_key
+            ),
+        }
+    }
+}
+
+impl<ChunkKey, ItemKey> std::error::Error for ValidationError<ChunkKey, ItemKey>
+where
+    ChunkKey: fmt::Debug,
+    ItemKey: fmt::Debug,
+{
+}
+
+/// Returned by `Storage::try_append` when both `Storages` have a chunk with the same chunk key.
+///
+/// # Type Parameters
+///
+/// * `ChunkKey`: the owned form of the `Storage`'s chunk key.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ChunkCollisionError<ChunkKey> {
+    /// The first chunk key found in both `Storages`.
+    pub chunk_key: ChunkKey,
+}
+
+impl<ChunkKey> fmt::Display for ChunkCollisionError<ChunkKey>
+where
+    ChunkKey: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "retriever: chunk {:?} is present in both storages",
+            self.chunk_key
+        )
+    }
+}
+
+impl<ChunkKey> std::error::Error for ChunkCollisionError<ChunkKey> where ChunkKey: fmt::Debug {}
+
+/// Returned by `Storage::query_page` when a `Cursor` can no longer resume its query.
+/// See `Cursor` for when this happens.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct InvalidCursorError {
+    /// The `Cursor` that was invalidated.
+    pub cursor: Cursor,
+}
+
+impl fmt::Display for InvalidCursorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "retriever: cursor was invalidated; restart the query from the beginning"
+        )
+    }
+}
+
+impl std::error::Error for InvalidCursorError {}
+
+/// Returned by `QueryLanguage::parse` when a textual query can not be compiled.
+#[cfg(feature = "query_language")]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum QueryParseError {
+    /// The query ended where more text was expected.
+    UnexpectedEnd,
+    /// Something unexpected was found at the given byte offset.
+    UnexpectedToken {
+        /// The byte offset of the unexpected text.
+        position: usize,
+    },
+    /// A quoted string starting at the given byte offset was never closed.
+    UnterminatedString {
+        /// The byte offset of the opening quote.
+        position: usize,
+    },
+    /// The field is neither `chunk` nor a registered `idx:<name>`.
+    UnknownField {
+        /// The field as written in the query.
+        name: String,
+    },
+    /// The value at the given byte offset could not be parsed as the required type.
+    InvalidValue {
+        /// The byte offset of the value.
+        position: usize,
+    },
+}
+
+#[cfg(feature = "query_language")]
+impl fmt::Display for QueryParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QueryParseError::UnexpectedEnd => write!(f, "retriever: unexpected end of query"),
+            QueryParseError::UnexpectedToken { position } => {
+                write!(f, "retriever: unexpected token at offset {}", position)
+            }
+            QueryParseError::UnterminatedString { position } => {
+                write!(f, "retriever: unterminated string at offset {}", position)
+            }
+            QueryParseError::UnknownField { name } => {
+                write!(f, "retriever: unknown field {:?}", name)
+            }
+            QueryParseError::InvalidValue { position } => {
+                write!(f, "retriever: invalid value at offset {}", position)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "query_language")]
+impl std::error::Error for QueryParseError {}
@@ -0,0 +1,23 @@
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use std::borrow::Cow;
+
+/// A single element along with its keys, as yielded by `Storage::export_records`.
+///
+/// The chunk key is borrowed directly from the chunk, so it is never recomputed per element,
+/// and the item key is whatever `Record::item_key` returns, which is usually borrowed.
+/// This makes `ExportRecord` cheap to hand directly to a serializer.
+#[derive(Clone, Debug)]
+pub struct ExportRecord<'a, ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+{
+    /// The chunk key shared by every element in this element's chunk.
+    pub chunk_key: &'a ChunkKey,
+    /// The item key of this element.
+    pub item_key: Cow<'a, ItemKey>,
+    /// The element itself.
+    pub element: &'a Element,
+}
@@ -0,0 +1,535 @@
+use crate::internal::mr::rvec::RVec;
+use crate::internal::mr::summarize::{Summarize, SummaryRules};
+use crate::queries::secondary_index::KeySet;
+use crate::traits::memory_usage::{MemoryUsage, MemoryUser};
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::storage::Storage;
+use crate::types::storage_builder::Strictness;
+use std::borrow::Borrow;
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::Debug;
+use std::sync::Arc;
+
+type FoldRule<Summary> =
+    Arc<dyn Fn(&[Summary], &Summary) -> Option<Summary> + Send + Sync + 'static>;
+
+/// Summarize the elements of a `Storage` once for each of some index keys, such as the total
+/// revenue of the orders in each product category. Read the summaries using
+/// `GroupedReduction::reduce`.
+///
+/// Where `Storage::group_by` visits every element on each call, a `GroupedReduction` remembers
+/// the summary of each index key within each chunk. Repeated evaluations only re-compute the
+/// summaries of those index keys that gained, lost, or changed an element, and only within the
+/// chunks where that happened.
+///
+/// The summaries of an index key are folded together in no particular order, so `Fold` must
+/// not depend on the order of the `Summaries`.
+///
+/// # Type Parameters
+///
+/// * `ChunkKey`: The chunk key type of the `Storage`.
+/// * `Element`: The element type of the `Storage`.
+/// * `Summary`: The type of the result of summarizing the elements under one index key.
+/// * `IndexKeys`: A collection containing the type parameter `IndexKey`, as for a `SecondaryIndex`.
+/// * `IndexKey`: The type of the keys to group elements by.
+///
+/// # Example
+///
+/// ```
+/// use retriever::prelude::*;
+/// use retriever::types::grouped_reduction::GroupedReduction;
+/// use std::borrow::Cow;
+///
+/// // Orders chunked by day, keyed by order id, with a product category and an amount.
+/// type Order = (u64, u64, (&'static str, i64));
+/// let mut storage : Storage<u64, u64, Order> = Storage::new();
+/// let mut revenue : GroupedReduction<u64, Order, i64, Option<&'static str>, &'static str> =
+///   GroupedReduction::new(
+///     &storage,
+///     |x: &Order| Cow::Owned(Some((x.2).0)),
+///     |x: &Order, was: &i64| Some((x.2).1).filter(|x| x != was),
+///     |xs: &[i64], was: &i64| Some(xs.iter().sum()).filter(|x| x != was),
+///   );
+///
+/// storage.add((1, 1, ("books", 10)));
+/// storage.add((1, 2, ("games", 20)));
+/// storage.add((2, 3, ("books", 30)));
+///
+/// let totals = revenue.reduce(&storage);
+/// assert_eq!(2, totals.len());
+/// assert_eq!(40, totals["books"]);
+/// assert_eq!(20, totals["games"]);
+///
+/// storage.modify(&ID.chunk(1).item(2), |mut editor| (editor.get_mut().2).0 = "books");
+/// storage.add((2, 4, ("music", 5)));
+///
+/// let totals = revenue.reduce(&storage);
+/// assert_eq!(2, totals.len());
+/// assert_eq!(60, totals["books"]);
+/// assert_eq!(5, totals["music"]);
+///
+/// storage.remove_chunk(&2);
+/// assert_eq!(Some(&30), revenue.reduce_key(&storage, &"books"));
+/// assert_eq!(None, revenue.reduce_key(&storage, &"music"));
+///
+/// # storage.validate();
+/// # revenue.validate(&storage);
+/// ```
+#[allow(clippy::type_complexity)]
+pub struct GroupedReduction<ChunkKey, Element, Summary, IndexKeys, IndexKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+{
+    // parent_id, used to see that this GroupedReduction isn't suddenly used with a different parent storage
+    parent_id: u64,
+    // gc_chunk_list, remember the chunks from our last update, so we can forget newly-absent chunks
+    gc_chunk_list: RVec<Option<ChunkKey::Owned>>,
+    // rules for constructing the index keys and summary of each element
+    rules: Arc<SummaryRules<Element, GroupToken<IndexKeys, Summary>, ChunkGroups<IndexKey>>>,
+    // rule for folding several summaries into one
+    fold: FoldRule<Summary>,
+    // the elements of each chunk under each index key
+    index: HashMap<
+        ChunkKey::Owned,
+        Summarize<Element, GroupToken<IndexKeys, Summary>, ChunkGroups<IndexKey>>,
+        crate::internal::hasher::HasherImpl,
+    >,
+    // the summary of the elements of each chunk, by index key and then by chunk key
+    chunkwise_summaries: HashMap<IndexKey::Owned, HashMap<ChunkKey::Owned, Summary>>,
+    // the summaries of every chunk, folded together by index key
+    totals: HashMap<IndexKey::Owned, Summary>,
+}
+
+// The index keys and summary of a single element.
+#[derive(Clone, Default)]
+struct GroupToken<IndexKeys, Summary> {
+    index_keys: IndexKeys,
+    summary: Summary,
+}
+
+// An element contributes to the summaries of its index keys and nothing else, so only its
+// index keys decide whether it contributes at all.
+impl<IndexKeys: PartialEq, Summary> PartialEq for GroupToken<IndexKeys, Summary> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index_keys == other.index_keys
+    }
+}
+
+impl<IndexKeys: Eq, Summary> Eq for GroupToken<IndexKeys, Summary> {}
+
+struct ChunkGroups<IndexKey>
+where
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+{
+    // the internal indices of the elements of this chunk with each index key
+    postings: HashMap<IndexKey::Owned, BTreeSet<usize>>,
+    // the index keys whose elements have changed since their summaries were last folded
+    dirty: BTreeSet<IndexKey::Owned>,
+}
+
+impl<IndexKey> Default for ChunkGroups<IndexKey>
+where
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+{
+    fn default() -> Self {
+        ChunkGroups {
+            postings: HashMap::default(),
+            dirty: BTreeSet::default(),
+        }
+    }
+}
+
+impl<ChunkKey, Element, Summary, IndexKeys, IndexKey>
+    GroupedReduction<ChunkKey, Element, Summary, IndexKeys, IndexKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    Summary: Default + Clone,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+{
+    /// Create a new `GroupedReduction` on a `Storage`. The indexing rule works exactly like the
+    /// indexing rule of `SecondaryIndex::new`, and an element with several index keys is
+    /// summarized once under each of them. The `Map` and `Fold` rules work exactly like the
+    /// rules of `Reduction::new`.
+    pub fn new<ItemKey, F, Map, Fold>(
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        f: F,
+        map: Map,
+        fold: Fold,
+    ) -> Self
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        F: Fn(&Element) -> Cow<IndexKeys> + Clone + Send + Sync + 'static,
+        Map: Fn(&Element, &Summary) -> Option<Summary> + Clone + Send + Sync + 'static,
+        Fold: Fn(&[Summary], &Summary) -> Option<Summary> + Clone + Send + Sync + 'static,
+    {
+        GroupedReduction {
+            parent_id: storage.id(),
+            gc_chunk_list: RVec::default(),
+            rules: Arc::new(Self::grouping_rules(f, map)),
+            fold: Arc::new(fold),
+            index: HashMap::with_hasher(crate::internal::hasher::HasherImpl::default()),
+            chunkwise_summaries: HashMap::new(),
+            totals: HashMap::new(),
+        }
+    }
+
+    fn grouping_rules<F, Map>(
+        f: F,
+        map: Map,
+    ) -> SummaryRules<Element, GroupToken<IndexKeys, Summary>, ChunkGroups<IndexKey>>
+    where
+        F: Fn(&Element) -> Cow<IndexKeys> + Clone + Send + Sync + 'static,
+        Map: Fn(&Element, &Summary) -> Option<Summary> + Clone + Send + Sync + 'static,
+    {
+        SummaryRules {
+            map: Arc::new(move |element, old_token, _internal_idx| {
+                let new_index_keys = f(element);
+                let new_summary = map(element, &old_token.summary);
+
+                if &old_token.index_keys == new_index_keys.borrow() && new_summary.is_none() {
+                    return None;
+                }
+
+                Some(GroupToken {
+                    index_keys: new_index_keys.into_owned(),
+                    summary: new_summary.unwrap_or_else(|| old_token.summary.clone()),
+                })
+            }),
+            contribute: Arc::new(|new_token, internal_idx, groups| {
+                for new_index_key in new_token.index_keys.iter_keys() {
+                    groups
+                        .postings
+                        .entry(new_index_key.clone().into_owned())
+                        .or_default()
+                        .insert(internal_idx);
+                    groups.dirty.insert(new_index_key.into_owned());
+                }
+            }),
+            uncontribute: Arc::new(|old_token, internal_idx, groups| {
+                for old_index_key in old_token.index_keys.iter_keys() {
+                    let mut remove = false;
+
+                    if let Some(idxs) = groups.postings.get_mut(old_index_key.borrow()) {
+                        idxs.remove(&internal_idx);
+                        remove = idxs.is_empty();
+                    }
+
+                    if remove {
+                        groups.postings.remove(old_index_key.borrow());
+                    }
+
+                    groups.dirty.insert(old_index_key.into_owned());
+                }
+            }),
+        }
+    }
+
+    /// Bring this reduction up to date and get the summary of the elements under each index
+    /// key. Index keys without any elements are left out.
+    ///
+    /// # Panic
+    ///
+    /// This method panics if used with a `Storage` other than the one this `GroupedReduction`
+    /// was created with, unless that `Storage`'s `Strictness` is `Repair`, in which case the
+    /// `GroupedReduction` is rebuilt for the new `Storage`.
+    pub fn reduce<ItemKey>(
+        &mut self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+    ) -> &HashMap<IndexKey::Owned, Summary>
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        self.refresh(storage);
+        &self.totals
+    }
+
+    /// Bring this reduction up to date and get the summary of the elements under a single
+    /// index key, or `None` if there are no such elements.
+    ///
+    /// # Panic
+    ///
+    /// Like `GroupedReduction::reduce`, this method panics if used with a `Storage` other than
+    /// the one this `GroupedReduction` was created with, unless that `Storage`'s `Strictness` is
+    /// `Repair`.
+    pub fn reduce_key<ItemKey>(
+        &mut self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        index_key: &IndexKey,
+    ) -> Option<&Summary>
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        self.refresh(storage);
+        self.totals.get(index_key)
+    }
+
+    /// Bring this reduction up to date and get the summary of the elements of a single chunk
+    /// under each index key, in index key order.
+    pub(crate) fn reduce_chunk<ItemKey>(
+        &mut self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        chunk_key: &ChunkKey,
+    ) -> Vec<(&IndexKey::Owned, &Summary)>
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        self.refresh(storage);
+
+        let summarize = match self.index.get(chunk_key) {
+            Some(summarize) => summarize,
+            None => return Vec::new(),
+        };
+
+        let chunkwise_summaries = &self.chunkwise_summaries;
+        let mut result: Vec<(&IndexKey::Owned, &Summary)> = summarize
+            .peek()
+            .postings
+            .keys()
+            .filter_map(|index_key| {
+                let summary = chunkwise_summaries
+                    .get::<IndexKey>(index_key.borrow())?
+                    .get(chunk_key)?;
+                Some((index_key, summary))
+            })
+            .collect();
+        result.sort_by(|a, b| a.0.cmp(b.0));
+
+        result
+    }
+
+    /// Panic if this storage is malformed or broken in any detectable way.
+    /// This is a slow operation and you shouldn't use it unless you suspect a problem.
+    pub fn validate<ItemKey>(&mut self, parent: &Storage<ChunkKey, ItemKey, Element>)
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        self.refresh(parent);
+
+        assert_eq!(parent.internal_rvec().len(), self.index.len());
+
+        let mut expected: HashMap<IndexKey::Owned, HashSet<ChunkKey::Owned>> = HashMap::new();
+        for chunk_storage in parent.internal_rvec().iter() {
+            let summarize = &self.index[chunk_storage.chunk_key()];
+            let groups = summarize.peek();
+            assert!(groups.dirty.is_empty());
+
+            for (internal_idx, token) in summarize.tokens().iter().enumerate() {
+                for index_key in token.index_keys.iter_keys() {
+                    assert!(groups.postings[index_key.borrow()].contains(&internal_idx));
+                }
+            }
+
+            for index_key in groups.postings.keys() {
+                expected
+                    .entry(index_key.clone())
+                    .or_default()
+                    .insert(chunk_storage.chunk_key().to_owned());
+            }
+        }
+
+        let actual: HashMap<IndexKey::Owned, HashSet<ChunkKey::Owned>> = self
+            .chunkwise_summaries
+            .iter()
+            .map(|(index_key, summaries)| (index_key.clone(), summaries.keys().cloned().collect()))
+            .collect();
+        assert_eq!(expected, actual);
+
+        let totals: HashSet<&IndexKey::Owned> = self.totals.keys().collect();
+        assert_eq!(expected.keys().collect::<HashSet<_>>(), totals);
+    }
+
+    fn refresh<ItemKey>(&mut self, storage: &Storage<ChunkKey, ItemKey, Element>)
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        if self.parent_id != storage.id() {
+            assert_eq!(storage.strictness(), Strictness::Repair, "Id mismatch: a GroupedReduction may only be used with it's parent Storage, never any other Storage");
+            #[cfg(feature = "log")]
+            log::warn!(
+                "retriever: repaired GroupedReduction used with a different Storage by rebuilding it"
+            );
+            self.parent_id = storage.id();
+            self.gc_chunk_list = RVec::default();
+            self.index.clear();
+            self.chunkwise_summaries.clear();
+            self.totals.clear();
+        }
+
+        let GroupedReduction {
+            gc_chunk_list,
+            rules,
+            fold,
+            index,
+            chunkwise_summaries,
+            totals,
+            ..
+        } = self;
+
+        // the index keys whose totals might have changed
+        let mut changed: HashSet<IndexKey::Owned> = HashSet::new();
+
+        storage.gc_with(gc_chunk_list, index, |chunk_key, summarize| {
+            for index_key in summarize.peek().postings.keys() {
+                if let Some(summaries) = chunkwise_summaries.get_mut::<IndexKey>(index_key.borrow())
+                {
+                    summaries.remove::<ChunkKey>(chunk_key.borrow());
+                }
+                changed.insert(index_key.clone());
+            }
+        });
+
+        for chunk_storage in storage.internal_rvec().iter() {
+            let chunk_key = chunk_storage.chunk_key();
+            let internal_storage = chunk_storage.internal_rvec();
+            let summarize = index
+                .entry(chunk_key.to_owned())
+                .or_insert_with(|| Summarize::new(internal_storage, Arc::clone(rules)));
+            summarize.update(internal_storage);
+
+            let dirty: Vec<IndexKey::Owned> = std::mem::take(&mut summarize.peek_mut().dirty)
+                .into_iter()
+                .collect();
+
+            for index_key in dirty {
+                let idxs = match summarize.peek().postings.get(index_key.borrow()) {
+                    Some(idxs) => idxs,
+                    None => {
+                        if let Some(summaries) = chunkwise_summaries.get_mut(index_key.borrow()) {
+                            summaries.remove(chunk_key);
+                        }
+                        changed.insert(index_key);
+                        continue;
+                    }
+                };
+
+                let tokens = summarize.tokens();
+                let element_summaries: Vec<Summary> = idxs
+                    .iter()
+                    .map(|internal_idx| tokens[*internal_idx].summary.clone())
+                    .collect();
+                let summaries = chunkwise_summaries.entry(index_key.clone()).or_default();
+
+                match summaries.get_mut(chunk_key) {
+                    Some(summary) => {
+                        if let Some(new_summary) = fold(&element_summaries, summary) {
+                            *summary = new_summary;
+                            changed.insert(index_key);
+                        }
+                    }
+                    None => {
+                        let was = Summary::default();
+                        let new_summary = fold(&element_summaries, &was).unwrap_or(was);
+                        summaries.insert(chunk_key.to_owned(), new_summary);
+                        changed.insert(index_key);
+                    }
+                }
+            }
+        }
+
+        for index_key in changed {
+            let chunk_summaries: Vec<Summary> = match chunkwise_summaries.get(index_key.borrow()) {
+                Some(summaries) if !summaries.is_empty() => summaries.values().cloned().collect(),
+                _ => {
+                    chunkwise_summaries.remove(index_key.borrow());
+                    totals.remove(index_key.borrow());
+                    continue;
+                }
+            };
+
+            match totals.get_mut(index_key.borrow()) {
+                Some(total) => {
+                    if let Some(new_total) = fold(&chunk_summaries, total) {
+                        *total = new_total;
+                    }
+                }
+                None => {
+                    let was = Summary::default();
+                    totals.insert(index_key, fold(&chunk_summaries, &was).unwrap_or(was));
+                }
+            }
+        }
+    }
+}
+
+impl<IndexKey> MemoryUser for ChunkGroups<IndexKey>
+where
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+{
+    fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage::merge(self.postings.memory_usage(), self.dirty.memory_usage())
+    }
+
+    fn shrink_with<F: Fn(&MemoryUsage) -> Option<usize>>(&mut self, f: F) {
+        self.postings.shrink_with(&f);
+        self.dirty.shrink_with(&f);
+    }
+}
+
+impl<ChunkKey, Element, Summary, IndexKeys, IndexKey> MemoryUser
+    for GroupedReduction<ChunkKey, Element, Summary, IndexKeys, IndexKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    Summary: Default,
+    IndexKey: BorrowedKey + ?Sized,
+    IndexKey::Owned: ValidKey,
+    for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+{
+    fn memory_usage(&self) -> MemoryUsage {
+        let mut result = self.gc_chunk_list.memory_usage();
+        result = MemoryUsage::merge(result, self.index.memory_usage());
+        result = MemoryUsage::merge(result, self.chunkwise_summaries.memory_usage());
+        result = MemoryUsage::merge(result, self.totals.memory_usage());
+
+        for s in self.index.values() {
+            result = MemoryUsage::merge(result, s.memory_usage());
+            result = MemoryUsage::merge(result, s.peek().memory_usage());
+        }
+
+        for summaries in self.chunkwise_summaries.values() {
+            result = MemoryUsage::merge(result, summaries.memory_usage());
+        }
+
+        result
+    }
+
+    fn shrink_with<F: Fn(&MemoryUsage) -> Option<usize>>(&mut self, f: F) {
+        self.gc_chunk_list.shrink_with(&f);
+        self.index.shrink_with(&f);
+        self.chunkwise_summaries.shrink_with(&f);
+        self.totals.shrink_with(&f);
+
+        for i in self.index.values_mut() {
+            i.shrink_with(&f);
+            i.peek_mut().shrink_with(&f);
+        }
+
+        for summaries in self.chunkwise_summaries.values_mut() {
+            summaries.shrink_with(&f);
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use crate::bits::bitfield::Bitfield;
+use crate::traits::item_selection::ItemSelection;
 use crate::traits::query::Query;
 use crate::traits::record::Record;
 use crate::traits::valid_key::BorrowedKey;
@@ -72,6 +73,17 @@ impl<C, I> Id<C, I> {
     pub fn item<II>(self, new_item_key: II) -> Id<C, II> {
         Id::new(self.0, new_item_key)
     }
+
+    /// Query the elements of this `Id`'s chunk that are selected by the given item keys.
+    /// A range of item keys selects an `ItemRange`, while a collection of item keys, such as
+    /// an array or `Vec`, selects `Items`. See `ItemSelection`.
+    #[must_use = "This method returns a new query and otherwise has no effect."]
+    pub fn items<S>(self, item_keys: S) -> S::Query
+    where
+        S: ItemSelection<C>,
+    {
+        item_keys.select(self.0)
+    }
 }
 
 impl<'a, ChunkKey, ItemKey> Id<Cow<'a, ChunkKey>, Cow<'a, ItemKey>>
@@ -160,4 +172,12 @@ where
         assert_eq!(self.item_key(), element.item_key());
         true
     }
+
+    fn is_exact(&self) -> bool {
+        true
+    }
+
+    fn test_chunk(&self, chunk_key: &ChunkKey) -> bool {
+        self.chunk_key().as_ref() == chunk_key
+    }
 }
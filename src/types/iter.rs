@@ -0,0 +1,84 @@
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::chunk_storage::ChunkStorage;
+use std::sync::Arc;
+
+/// An iterator over every element of a `Storage`. Construct one using `Storage::iter`, or by
+/// iterating over a `&Storage`.
+pub struct Iter<'a, ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+{
+    chunks: std::slice::Iter<'a, Arc<ChunkStorage<ChunkKey, ItemKey, Element>>>,
+    elements: std::slice::Iter<'a, Element>,
+}
+
+impl<'a, ChunkKey, ItemKey, Element> Iter<'a, ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+{
+    pub(crate) fn new(chunks: &'a [Arc<ChunkStorage<ChunkKey, ItemKey, Element>>]) -> Self {
+        Iter {
+            chunks: chunks.iter(),
+            elements: [].iter(),
+        }
+    }
+}
+
+impl<'a, ChunkKey, ItemKey, Element> Clone for Iter<'a, ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+{
+    fn clone(&self) -> Self {
+        Iter {
+            chunks: self.chunks.clone(),
+            elements: self.elements.clone(),
+        }
+    }
+}
+
+impl<'a, ChunkKey, ItemKey, Element> Iterator for Iter<'a, ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+{
+    type Item = &'a Element;
+
+    fn next(&mut self) -> Option<&'a Element> {
+        loop {
+            if let Some(element) = self.elements.next() {
+                return Some(element);
+            }
+
+            self.elements = self.chunks.next()?.raw().iter();
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining =
+            self.elements.len() + self.chunks.clone().map(|chunk| chunk.len()).sum::<usize>();
+        (remaining, Some(remaining))
+    }
+}
+
+impl<'a, ChunkKey, ItemKey, Element> ExactSizeIterator for Iter<'a, ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+{
+}
@@ -1,12 +1,68 @@
+/// Module for per-chunk arenas of large payloads that live outside of a Storage.
+pub mod blob_store;
+/// Module for limits on how much of a query a single call may run.
+pub mod budget;
+/// Module for the outcome of a compare-and-swap on a single element.
+pub mod cas_result;
 /// Module for a data type representing the storage for a single chunk.
 pub mod chunk_storage;
+/// Module for a thread-safe storage that locks each chunk separately.
+pub mod concurrent_storage;
+/// Module for a storage that merges, rather than rejects, values with colliding keys.
+pub mod crdt_storage;
+/// Module for tokens that resume a paged query where it left off.
+pub mod cursor;
+/// Module for dropping elements that were recently added already.
+pub mod dedup_window;
 /// Module for an interface to edit stored values.
 pub mod editor;
 /// Module for an interface to edit stored values that may or may not exist.
 pub mod entry;
+/// Module for error types returned by non-panicking operations.
+pub mod error;
+/// Module for a data type that pairs a stored value with its keys for export.
+pub mod export_record;
+/// Module for a reduction that keeps one summary per index key.
+pub mod grouped_reduction;
 /// Module for a data type that serves as reference to a stored value by it's chunk key and item key.
 pub mod id;
+/// Module for an iterator over every stored value.
+pub mod iter;
+/// Module for deciding when a query is large enough to run in parallel.
+#[cfg(feature = "rayon")]
+pub mod parallelism;
+/// Module for zero-copy access to a single chunk that doesn't borrow its Storage.
+pub mod pinned_chunk;
+/// Module for a query that only re-evaluates the chunks that changed since its last run.
+pub mod prepared_query;
+/// Module for a storage whose single writer publishes snapshots to many readers.
+pub mod published_storage;
+/// Module for an iterator of editors over the elements matching a query.
+pub mod query_mut;
+/// Module for reports of how a query would run.
+pub mod query_plan;
 /// Module for an interface to reduce a large number of collected values down to a single value.
 pub mod reduction;
+/// Module for several reductions that are kept up to date together.
+pub mod reduction_group;
+/// Module for an iterator that can be paused and resumed while its Storage changes.
+pub mod resumable_iter;
+/// Module for a copy of a SecondaryIndex that can be serialized and restored later.
+#[cfg(feature = "serde")]
+pub mod saved_index;
+/// Module for a copy of the chunk summaries of a Reduction that can be serialized and restored later.
+#[cfg(feature = "serde")]
+pub mod saved_reduction;
+/// Module for a map-like Storage that implements the standard container traits.
+pub mod simple_storage;
+/// Module for reports about the size of stored values.
+#[cfg(feature = "diagnostics")]
+pub mod size_profile;
+/// Module for a frozen view of a Storage that stays unchanged while the Storage is modified.
+pub mod snapshot;
 /// Module for the primary Storage type.
 pub mod storage;
+/// Module for configuring a Storage before constructing it.
+pub mod storage_builder;
+/// Module for a reduction that keeps one summary per window of item keys in each chunk.
+pub mod windowed_reduction;
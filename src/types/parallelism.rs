@@ -0,0 +1,82 @@
+/// Decides, per query, whether to run sequentially or in parallel.
+///
+/// Spreading a query across rayon's thread pool has a fixed cost, so small queries are faster
+/// when run sequentially. Before each parallel operation, retriever estimates the work as the
+/// number of chunks visited times the average size of those chunks, and only parallelizes if
+/// both thresholds are met.
+///
+/// # Example
+///
+/// ```
+/// use retriever::prelude::*;
+/// use retriever::types::parallelism::Parallelism;
+/// use retriever::types::storage_builder::StorageBuilder;
+///
+/// let storage : Storage<u64, u64, (u64, u64, u64)> = StorageBuilder::new()
+///   .parallelism(Parallelism::default().min_elements(100_000).min_chunks(8))
+///   .build();
+///
+/// assert_eq!(storage.parallelism().get_min_elements(), 100_000);
+/// ```
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Parallelism {
+    min_elements: usize,
+    min_chunks: usize,
+}
+
+impl Parallelism {
+    /// Never run in parallel.
+    pub fn sequential() -> Self {
+        Parallelism {
+            min_elements: usize::MAX,
+            min_chunks: usize::MAX,
+        }
+    }
+
+    /// Always run in parallel, if there is more than one chunk to visit.
+    pub fn parallel() -> Self {
+        Parallelism {
+            min_elements: 0,
+            min_chunks: 2,
+        }
+    }
+
+    /// Only run in parallel if the query is estimated to visit at least this many elements.
+    pub fn min_elements(mut self, min_elements: usize) -> Self {
+        self.min_elements = min_elements;
+        self
+    }
+
+    /// Only run in parallel if the query visits at least this many chunks. Work is divided
+    /// between threads chunk-by-chunk, so a single chunk is never worth parallelizing.
+    pub fn min_chunks(mut self, min_chunks: usize) -> Self {
+        self.min_chunks = min_chunks.max(2);
+        self
+    }
+
+    /// The minimum estimated number of elements worth parallelizing.
+    pub fn get_min_elements(&self) -> usize {
+        self.min_elements
+    }
+
+    /// The minimum number of chunks worth parallelizing.
+    pub fn get_min_chunks(&self) -> usize {
+        self.min_chunks
+    }
+
+    /// True IFF a query visiting `chunk_count` chunks, averaging `average_chunk_len` elements
+    /// each, should run in parallel.
+    pub fn is_parallel(&self, chunk_count: usize, average_chunk_len: usize) -> bool {
+        chunk_count >= self.min_chunks
+            && chunk_count.saturating_mul(average_chunk_len) >= self.min_elements
+    }
+}
+
+impl Default for Parallelism {
+    fn default() -> Self {
+        Parallelism {
+            min_elements: 16 * 1024,
+            min_chunks: 2,
+        }
+    }
+}
@@ -0,0 +1,87 @@
+#[cfg(feature = "debug_borrows")]
+use crate::internal::pins::PinToken;
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::chunk_storage::ChunkStorage;
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// Zero-copy access to the elements of a single chunk that doesn't borrow the `Storage`, so
+/// that it can be held across modifications of the `Storage` or sent to another thread.
+/// Construct one using `Storage::pin_chunk`.
+///
+/// A `PinnedChunk` shares its chunk with the `Storage`, exactly as a clone of the `Storage`
+/// would. Modifying the chunk while it is pinned copies the whole chunk, which is usually a
+/// mistake. With the `debug_borrows` feature, the `Storage` panics instead.
+pub struct PinnedChunk<ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+{
+    chunk: Arc<ChunkStorage<ChunkKey, ItemKey, Element>>,
+    #[cfg(feature = "debug_borrows")]
+    _token: Arc<PinToken>,
+}
+
+impl<ChunkKey, ItemKey, Element> PinnedChunk<ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+{
+    pub(crate) fn new(
+        chunk: Arc<ChunkStorage<ChunkKey, ItemKey, Element>>,
+        #[cfg(feature = "debug_borrows")] token: Arc<PinToken>,
+    ) -> Self {
+        PinnedChunk {
+            chunk,
+            #[cfg(feature = "debug_borrows")]
+            _token: token,
+        }
+    }
+
+    /// The chunk key of the pinned chunk.
+    pub fn chunk_key(&self) -> &ChunkKey {
+        self.chunk.chunk_key()
+    }
+
+    /// Raw access to the elements of the pinned chunk, in no particular order.
+    pub fn raw(&self) -> &[Element] {
+        self.chunk.raw()
+    }
+}
+
+impl<ChunkKey, ItemKey, Element> Clone for PinnedChunk<ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+{
+    fn clone(&self) -> Self {
+        PinnedChunk {
+            chunk: Arc::clone(&self.chunk),
+            #[cfg(feature = "debug_borrows")]
+            _token: Arc::clone(&self._token),
+        }
+    }
+}
+
+impl<ChunkKey, ItemKey, Element> Deref for PinnedChunk<ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+{
+    type Target = [Element];
+
+    fn deref(&self) -> &[Element] {
+        self.raw()
+    }
+}
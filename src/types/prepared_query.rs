@@ -0,0 +1,229 @@
+use crate::bits::Bitset;
+use crate::internal::mr::rvec::RVec;
+use crate::traits::idxset::IdxSet;
+use crate::traits::memory_usage::{MemoryUsage, MemoryUser};
+use crate::traits::query::Query;
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::storage::Storage;
+use crate::types::storage_builder::Strictness;
+
+/// A `Query` that remembers which elements it matched, so that running it again only
+/// re-evaluates the chunks that have changed since the last run. Use this for a query that
+/// runs many times while the `Storage` changes only a little between runs.
+///
+/// The `Query` must always give the same answer for the same element: if a `Filter` depends on
+/// some outside state, a `PreparedQuery` won't notice when that state changes.
+/// A `Query` that isn't chunk-local, such as `Query::limit`, is evaluated again in full every
+/// time; see `Query::is_chunk_local`.
+///
+/// # Type Parameters
+///
+/// * `Q`: the `Query` to prepare.
+///
+/// # Example
+///
+/// ```
+/// use retriever::prelude::*;
+/// use retriever::types::prepared_query::PreparedQuery;
+///
+/// let mut storage : Storage<u64, u64, (u64, u64, i64)> = Storage::new();
+///
+/// for i in 0..100 {
+///   storage.add((i % 10, i, i as i64 - 50));
+/// }
+///
+/// let mut negative = PreparedQuery::new(
+///   &storage,
+///   Everything.filter(|x: &(u64, u64, i64)| x.2 < 0));
+///
+/// assert_eq!(50, negative.count(&storage));
+///
+/// // Only chunk 3 is evaluated again.
+/// storage.entry(&ID.chunk(3).item(73)).get_mut().unwrap().2 = -1;
+/// assert_eq!(51, negative.count(&storage));
+///
+/// let total : i64 = negative.query(&storage).map(|x| x.2).sum();
+/// assert_eq!(-1276, total);
+/// # storage.validate();
+/// ```
+pub struct PreparedQuery<Q> {
+    parent_id: u64,
+    query: Q,
+    // the matching item indices of each chunk, indexed by chunk index
+    matches: RVec<Bitset>,
+}
+
+impl<Q> PreparedQuery<Q> {
+    /// Prepare a `Query` to be run against the given `Storage`.
+    pub fn new<ChunkKey, ItemKey, Element>(
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        query: Q,
+    ) -> Self
+    where
+        ChunkKey: BorrowedKey + ?Sized,
+        ChunkKey::Owned: ValidKey,
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        Q: Query<ChunkKey, ItemKey, Element>,
+    {
+        PreparedQuery {
+            parent_id: storage.id(),
+            query,
+            matches: RVec::default(),
+        }
+    }
+
+    /// The prepared `Query`.
+    pub fn as_query(&self) -> &Q {
+        &self.query
+    }
+
+    /// Iterate over the elements matching the prepared `Query`, as `Storage::query` would.
+    ///
+    /// # Panic
+    ///
+    /// This method panics if used with a `Storage` other than the one this `PreparedQuery` was
+    /// created with, unless that `Storage`'s `Strictness` is `Repair`.
+    pub fn query<'a, ChunkKey, ItemKey, Element>(
+        &'a mut self,
+        storage: &'a Storage<ChunkKey, ItemKey, Element>,
+    ) -> impl Iterator<Item = &'a Element> + 'a
+    where
+        ChunkKey: BorrowedKey + ?Sized,
+        ChunkKey::Owned: ValidKey,
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        Q: Query<ChunkKey, ItemKey, Element>,
+    {
+        self.update(storage);
+
+        let chunks = storage.internal_rvec();
+
+        self.matches
+            .iter()
+            .enumerate()
+            .flat_map(move |(idx, matches)| {
+                let chunk_storage = &*chunks[idx];
+                matches
+                    .iter()
+                    .flatten()
+                    .map(move |item_idx| chunk_storage.get_idx(item_idx))
+            })
+    }
+
+    /// Count the elements matching the prepared `Query`, without visiting any of them.
+    ///
+    /// # Panic
+    ///
+    /// Like `PreparedQuery::query`, this method panics if used with a `Storage` other than the
+    /// one this `PreparedQuery` was created with, unless that `Storage`'s `Strictness` is `Repair`.
+    pub fn count<ChunkKey, ItemKey, Element>(
+        &mut self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+    ) -> usize
+    where
+        ChunkKey: BorrowedKey + ?Sized,
+        ChunkKey::Owned: ValidKey,
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        Q: Query<ChunkKey, ItemKey, Element>,
+    {
+        self.update(storage);
+        self.matches.iter().map(Bitset::len).sum()
+    }
+
+    fn update<ChunkKey, ItemKey, Element>(&mut self, storage: &Storage<ChunkKey, ItemKey, Element>)
+    where
+        ChunkKey: BorrowedKey + ?Sized,
+        ChunkKey::Owned: ValidKey,
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        Q: Query<ChunkKey, ItemKey, Element>,
+    {
+        self.check_parent(storage);
+
+        // Resolving the chunk indices brings any SecondaryIndexes up to date, and lets a query
+        // like `Query::limit` choose its elements, so it must come before any chunk is visited.
+        let query = &self.query;
+        let chunk_idxs: Bitset = query
+            .chunk_idxs(storage)
+            .into_idx_iter()
+            .flatten()
+            .collect();
+
+        // Unchanged chunks may have new matches unless the query is chunk-local.
+        if !query.is_chunk_local() {
+            self.matches = RVec::default();
+        }
+
+        self.matches
+            .reduce(storage.internal_rvec(), 1, |chunks, _old_matches, idx| {
+                assert!(chunks.len() <= 1);
+
+                let chunk_storage = chunks.first()?;
+
+                if !chunk_idxs.get(idx) {
+                    return Some(Bitset::default());
+                }
+
+                Some(
+                    query
+                        .item_idxs(chunk_storage.chunk_key(), chunk_storage)
+                        .into_idx_iter()
+                        .flatten()
+                        .filter(|idx| query.test(chunk_storage.get_idx(*idx)))
+                        .collect(),
+                )
+            });
+    }
+
+    fn check_parent<ChunkKey, ItemKey, Element>(
+        &mut self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+    ) where
+        ChunkKey: BorrowedKey + ?Sized,
+        ChunkKey::Owned: ValidKey,
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        if self.parent_id == storage.id() {
+            return;
+        }
+
+        assert_eq!(
+            storage.strictness(),
+            Strictness::Repair,
+            "Id mismatch: a PreparedQuery may only be used with it's parent Storage, never any other Storage"
+        );
+
+        #[cfg(feature = "log")]
+        log::warn!(
+            "retriever: repaired PreparedQuery used with a different Storage by rebuilding it"
+        );
+
+        self.parent_id = storage.id();
+        self.matches = RVec::default();
+    }
+}
+
+impl<Q> MemoryUser for PreparedQuery<Q> {
+    fn memory_usage(&self) -> MemoryUsage {
+        let mut result = self.matches.memory_usage();
+
+        for matches in self.matches.iter() {
+            result = MemoryUsage::merge(result, matches.memory_usage());
+        }
+
+        result
+    }
+
+    fn shrink_with<F: Fn(&MemoryUsage) -> Option<usize>>(&mut self, f: F) {
+        self.matches.shrink_with(&f);
+    }
+}
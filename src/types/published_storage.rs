@@ -0,0 +1,196 @@
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::storage::Storage;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// A `Storage` with a single writer that publishes read-only snapshots to any number of
+/// readers. The writer edits its own private `Storage` without ever waiting on a reader, and
+/// calls `PublishedStorage::publish` to make the current contents visible. Each reader holds a
+/// `SnapshotReader`, which hands out the latest snapshot as an `Arc<Storage>`.
+///
+/// Publishing clones the `Storage`, which shares every chunk with the snapshot rather than
+/// copying it. A chunk is only copied the next time the writer modifies it while a snapshot
+/// still holds it, so the cost of publishing is proportional to the number of chunks, and the
+/// cost of each edit afterwards to the size of the chunk being edited.
+///
+/// `SnapshotReader::snapshot` checks a single atomic counter and takes no lock unless a new
+/// snapshot has been published since its last call. Readers therefore take a brief lock at
+/// most once per publication, and never while the writer is editing.
+///
+/// # Example
+///
+/// ```
+/// use retriever::prelude::*;
+/// use retriever::types::published_storage::PublishedStorage;
+///
+/// // Player positions, chunked by zone and keyed by player name.
+/// let mut world : PublishedStorage<u64, &'static str, (u64, &'static str, (i64, i64))> =
+///   PublishedStorage::new(Storage::new());
+/// let mut reader = world.subscribe();
+///
+/// world.storage_mut().add((1, "alice", (0, 0)));
+/// world.storage_mut().add((1, "bob", (5, 5)));
+/// assert_eq!(0, reader.snapshot().iter().count());
+///
+/// world.publish();
+/// let tick_1 = reader.snapshot().clone();
+/// assert_eq!(2, tick_1.iter().count());
+///
+/// world.storage_mut().modify(&ID.chunk(1).item("alice"), |mut editor| editor.get_mut().2 = (1, 0));
+/// world.publish();
+///
+/// // Old snapshots never change.
+/// assert_eq!(Some(&(1, "alice", (0, 0))), tick_1.get(&ID.chunk(1).item("alice")));
+/// assert_eq!(Some(&(1, "alice", (1, 0))), reader.snapshot().get(&ID.chunk(1).item("alice")));
+/// assert_eq!(2, reader.generation());
+/// ```
+pub struct PublishedStorage<ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+{
+    storage: Storage<ChunkKey, ItemKey, Element>,
+    publication: Arc<Publication<ChunkKey, ItemKey, Element>>,
+}
+
+/// A handle through which one reader gets the snapshots of a `PublishedStorage`. Clone it to
+/// give another reader its own handle.
+pub struct SnapshotReader<ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+{
+    publication: Arc<Publication<ChunkKey, ItemKey, Element>>,
+    generation: u64,
+    snapshot: Arc<Storage<ChunkKey, ItemKey, Element>>,
+}
+
+struct Publication<ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+{
+    // the generation of the latest snapshot, readable without taking the lock
+    generation: AtomicU64,
+    latest: Mutex<(u64, Published<ChunkKey, ItemKey, Element>)>,
+}
+
+type Published<ChunkKey, ItemKey, Element> = Arc<Storage<ChunkKey, ItemKey, Element>>;
+
+impl<ChunkKey, ItemKey, Element> PublishedStorage<ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey> + Clone,
+{
+    /// Begin publishing snapshots of a `Storage`, starting with a snapshot of its current
+    /// contents as generation 0.
+    pub fn new(storage: Storage<ChunkKey, ItemKey, Element>) -> Self {
+        let snapshot = Arc::new(storage.clone());
+
+        PublishedStorage {
+            storage,
+            publication: Arc::new(Publication {
+                generation: AtomicU64::new(0),
+                latest: Mutex::new((0, snapshot)),
+            }),
+        }
+    }
+
+    /// The writer's private `Storage`, including any changes that haven't been published yet.
+    pub fn storage(&self) -> &Storage<ChunkKey, ItemKey, Element> {
+        &self.storage
+    }
+
+    /// Edit the writer's private `Storage`. No reader sees these changes until the next call
+    /// to `PublishedStorage::publish`.
+    pub fn storage_mut(&mut self) -> &mut Storage<ChunkKey, ItemKey, Element> {
+        &mut self.storage
+    }
+
+    /// Publish a snapshot of the current contents of the `Storage`, returning its generation.
+    pub fn publish(&mut self) -> u64 {
+        let snapshot = Arc::new(self.storage.clone());
+        let mut latest = self.publication.latest.lock().unwrap();
+        let generation = latest.0 + 1;
+
+        *latest = (generation, snapshot);
+        self.publication
+            .generation
+            .store(generation, Ordering::Release);
+
+        generation
+    }
+
+    /// The generation of the latest published snapshot.
+    pub fn generation(&self) -> u64 {
+        self.publication.generation.load(Ordering::Acquire)
+    }
+
+    /// Create a new handle for a reader, starting from the latest published snapshot.
+    pub fn subscribe(&self) -> SnapshotReader<ChunkKey, ItemKey, Element> {
+        let (generation, snapshot) = self.publication.latest.lock().unwrap().clone();
+
+        SnapshotReader {
+            publication: self.publication.clone(),
+            generation,
+            snapshot,
+        }
+    }
+
+    /// Stop publishing and take back the writer's `Storage`. Readers keep the snapshots they
+    /// already have, but never see another one.
+    pub fn into_storage(self) -> Storage<ChunkKey, ItemKey, Element> {
+        self.storage
+    }
+}
+
+impl<ChunkKey, ItemKey, Element> SnapshotReader<ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+{
+    /// The latest published snapshot. Clone the `Arc` to keep a snapshot for longer than this
+    /// borrow, for example across several calls to `SnapshotReader::snapshot`.
+    pub fn snapshot(&mut self) -> &Arc<Storage<ChunkKey, ItemKey, Element>> {
+        if self.publication.generation.load(Ordering::Acquire) != self.generation {
+            let (generation, snapshot) = self.publication.latest.lock().unwrap().clone();
+            self.generation = generation;
+            self.snapshot = snapshot;
+        }
+
+        &self.snapshot
+    }
+
+    /// The generation of the snapshot most recently returned by `SnapshotReader::snapshot`.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+}
+
+impl<ChunkKey, ItemKey, Element> Clone for SnapshotReader<ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+{
+    fn clone(&self) -> Self {
+        SnapshotReader {
+            publication: self.publication.clone(),
+            generation: self.generation,
+            snapshot: self.snapshot.clone(),
+        }
+    }
+}
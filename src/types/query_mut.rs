@@ -0,0 +1,192 @@
+use crate::internal::mr::rvec::ChangedVec;
+use crate::traits::idxset::IdxSet;
+use crate::traits::query::Query;
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::chunk_storage::{ChunkStorage, ItemIndex};
+use crate::types::editor::Editor;
+use crate::types::id::Id;
+use std::borrow::Borrow;
+use std::sync::{Arc, Mutex};
+
+/// An `Iterator` of `Editor`s over the elements matching some `Query`. Construct one using
+/// `Storage::query_mut`.
+///
+/// Unlike `Storage::modify`, this lets you use `?`, break out early, or interleave the
+/// `Editor`s with other iterators. Changes are only recorded for re-indexing once this
+/// iterator, and every `Editor` it produced, has been dropped.
+pub struct QueryMut<'a, ChunkKey, ItemKey, Element, Q>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+{
+    query: Q,
+    chunks: std::vec::IntoIter<&'a mut ChunkStorage<ChunkKey, ItemKey, Element>>,
+    current: Option<CurrentChunk<'a, ChunkKey, ItemKey, Element>>,
+    touches: Arc<DeferredTouches<'a>>,
+}
+
+// The chunk a QueryMut is visiting.
+struct CurrentChunk<'a, ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+{
+    // the position of this chunk's ChangedVec within the DeferredTouches
+    touch_idx: usize,
+    chunk_key: &'a ChunkKey,
+    index: &'a ItemIndex<ItemKey::Owned>,
+    // the elements following the last element visited
+    rest: &'a mut [Element],
+    // the item index of the first element of rest
+    offset: usize,
+    item_idxs: std::vec::IntoIter<usize>,
+}
+
+/// The changes made through the `Editor`s of a `QueryMut`, which are applied to each chunk's
+/// change tracking all at once when the last `Editor` (or the `QueryMut` itself) is dropped.
+pub(crate) struct DeferredTouches<'a> {
+    changed_vecs: Mutex<Vec<&'a mut ChangedVec>>,
+    // pairs of (position within changed_vecs, item index)
+    touched: Mutex<Vec<(usize, usize)>>,
+}
+
+impl<'a> DeferredTouches<'a> {
+    fn push(&self, changed_vec: &'a mut ChangedVec) -> usize {
+        let mut changed_vecs = self.changed_vecs.lock().unwrap_or_else(|e| e.into_inner());
+        changed_vecs.push(changed_vec);
+        changed_vecs.len() - 1
+    }
+
+    /// Note that the element at the given item index of the given chunk has changed.
+    pub(crate) fn touch(&self, touch_idx: usize, item_idx: usize) {
+        self.touched
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push((touch_idx, item_idx));
+    }
+}
+
+impl<'a> Drop for DeferredTouches<'a> {
+    fn drop(&mut self) {
+        let changed_vecs = self
+            .changed_vecs
+            .get_mut()
+            .unwrap_or_else(|e| e.into_inner());
+        let touched = self.touched.get_mut().unwrap_or_else(|e| e.into_inner());
+
+        for (touch_idx, item_idx) in touched.drain(..) {
+            changed_vecs[touch_idx].touch(item_idx);
+        }
+    }
+}
+
+impl<'a, ChunkKey, ItemKey, Element, Q> QueryMut<'a, ChunkKey, ItemKey, Element, Q>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+    Q: Query<ChunkKey, ItemKey, Element>,
+{
+    /// Visit the given chunks, which must already be unshared.
+    pub(crate) fn new(
+        query: Q,
+        chunks: Vec<&'a mut ChunkStorage<ChunkKey, ItemKey, Element>>,
+    ) -> Self {
+        QueryMut {
+            query,
+            chunks: chunks.into_iter(),
+            current: None,
+            touches: Arc::new(DeferredTouches {
+                changed_vecs: Mutex::new(Vec::new()),
+                touched: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    // Begin visiting the next chunk, returning false if there are none left.
+    fn next_chunk(&mut self) -> bool {
+        let chunk = match self.chunks.next() {
+            Some(chunk) => chunk,
+            None => return false,
+        };
+
+        let mut item_idxs: Vec<usize> = self
+            .query
+            .item_idxs(chunk.chunk_key(), chunk)
+            .into_idx_iter()
+            .flatten()
+            .collect();
+        item_idxs.sort_unstable();
+        item_idxs.dedup();
+
+        let (chunk_key, index, rest, changed_vec) = chunk.split_mut();
+
+        self.current = Some(CurrentChunk {
+            touch_idx: self.touches.push(changed_vec),
+            chunk_key,
+            index,
+            rest,
+            offset: 0,
+            item_idxs: item_idxs.into_iter(),
+        });
+
+        true
+    }
+}
+
+impl<'a, ChunkKey, ItemKey, Element, Q> Iterator for QueryMut<'a, ChunkKey, ItemKey, Element, Q>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+    Q: Query<ChunkKey, ItemKey, Element>,
+{
+    type Item = Editor<'a, ChunkKey, ItemKey, Element>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(current) = self.current.as_mut() {
+                for idx in current.item_idxs.by_ref() {
+                    let rest = std::mem::take(&mut current.rest);
+                    let (element, rest) = rest[idx - current.offset..]
+                        .split_first_mut()
+                        .expect("retriever bug: item index out of range");
+                    current.rest = rest;
+                    current.offset = idx + 1;
+
+                    if !self.query.test(element) {
+                        continue;
+                    }
+
+                    let (item_key, _) = current
+                        .index
+                        .get_key_value(element.item_key().borrow())
+                        .expect("retriever bug: element should be indexed");
+
+                    return Some(Editor::deferred(
+                        Id::new(current.chunk_key, item_key.borrow()),
+                        idx,
+                        element,
+                        current.touch_idx,
+                        Arc::clone(&self.touches),
+                    ));
+                }
+
+                self.current = None;
+            }
+
+            if !self.next_chunk() {
+                return None;
+            }
+        }
+    }
+}
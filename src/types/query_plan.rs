@@ -0,0 +1,79 @@
+use std::fmt;
+use std::fmt::Debug;
+
+/// A report of how a `Query` would run against a `Storage`, as returned by `Query::explain`.
+///
+/// # Type Parameters
+///
+/// * `ChunkKey`: the owned chunk key of the `Storage`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct QueryPlan<ChunkKey> {
+    /// A description of the `Query`. See `Query::describe`.
+    pub description: String,
+    /// True IFF every candidate element belongs to the `Query`. If false, each candidate
+    /// is tested individually, for example by a `Filter`.
+    pub exact: bool,
+    /// The chunks that would be visited, in the order they would be visited.
+    pub chunks: Vec<ChunkPlan<ChunkKey>>,
+}
+
+/// How a `Query` would run against a single chunk. See `QueryPlan`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ChunkPlan<ChunkKey> {
+    /// The chunk key of this chunk.
+    pub chunk_key: ChunkKey,
+    /// The number of elements selected by the `Query`'s indexes, before any testing.
+    /// This is an estimate of the number of elements that the `Query` would visit.
+    pub candidates: usize,
+    /// The total number of elements in this chunk.
+    pub len: usize,
+}
+
+impl<ChunkKey> QueryPlan<ChunkKey> {
+    /// The total number of candidate elements in every chunk. If the plan is exact, this is
+    /// the number of elements that belong to the `Query`; otherwise it's an upper bound.
+    pub fn candidates(&self) -> usize {
+        self.chunks.iter().map(|chunk| chunk.candidates).sum()
+    }
+
+    /// The total number of elements in every chunk that would be visited.
+    pub fn len(&self) -> usize {
+        self.chunks.iter().map(|chunk| chunk.len).sum()
+    }
+
+    /// True IFF no chunk would be visited.
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+}
+
+impl<ChunkKey> fmt::Display for QueryPlan<ChunkKey>
+where
+    ChunkKey: Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.description)?;
+        writeln!(
+            f,
+            "  {} candidates of {} elements in {} chunks, {}",
+            self.candidates(),
+            self.len(),
+            self.chunks.len(),
+            if self.exact {
+                "exact"
+            } else {
+                "each candidate tested"
+            }
+        )?;
+
+        for chunk in self.chunks.iter() {
+            writeln!(
+                f,
+                "  chunk {:?}: {} candidates of {} elements",
+                chunk.chunk_key, chunk.candidates, chunk.len
+            )?;
+        }
+
+        Ok(())
+    }
+}
@@ -1,11 +1,28 @@
 use crate::internal::mr::reduce::*;
 use crate::internal::mr::rvec::RVec;
+use crate::traits::idxset::IdxSet;
 use crate::traits::memory_usage::MemoryUsage;
 use crate::traits::memory_usage::MemoryUser;
+use crate::traits::query::Query;
 use crate::traits::record::Record;
 use crate::traits::valid_key::{BorrowedKey, ValidKey};
+#[cfg(feature = "serde")]
+use crate::types::saved_index::fingerprint;
+#[cfg(feature = "serde")]
+use crate::types::saved_reduction::{SavedChunkSummary, SavedReduction};
 use crate::types::storage::Storage;
-use std::collections::HashMap;
+use crate::types::storage_builder::Strictness;
+use std::borrow::Borrow;
+use std::collections::{HashMap, HashSet};
+#[cfg(feature = "serde")]
+use std::hash::Hash;
+use std::ops::{Bound, RangeBounds};
+
+// The summary of each chunk, with the chunk list used to garbage collect it.
+type ChunkSummaries<ChunkKey, Summary> = (
+    RVec<Option<<ChunkKey as ToOwned>::Owned>>,
+    HashMap<<ChunkKey as ToOwned>::Owned, Summary>,
+);
 
 /// Summarize a `Storage` using a cached multi-layered reduction strategy.
 /// Repeated evaluations will only re-compute the parts of the reduction that have changed.
@@ -23,12 +40,36 @@ where
 {
     parent_id: u64,
     group_size: usize,
+    reduction_rules: ReduceRules<Summary, Summary>,
     gc_chunk_list: RVec<Option<ChunkKey::Owned>>,
     rules: ReduceRules<Element, Summary>,
     chunkwise_reductions:
         HashMap<ChunkKey::Owned, Reduce<Element, Summary>, crate::internal::hasher::HasherImpl>,
     chunkwise_summaries: RVec<Summary>,
     reduction: Reduce<Summary, Summary>,
+    // the summary of each chunk, only maintained once `reduce_by_chunk` has been called
+    by_chunk: Option<ChunkSummaries<ChunkKey, Summary>>,
+    // the summary of each chunk in chunk key order, only maintained once `reduce_range` has
+    // been called
+    by_key: Option<OrderedSummaries<ChunkKey, Summary>>,
+    // the number of times the result has changed, and the version of the result when it last did
+    generation: u64,
+    result_version: Option<u64>,
+}
+
+// The summary of each chunk in chunk key order, with a reduction tree over them, so that the
+// summaries of any range of chunk keys can be folded together from a few nodes of the tree.
+struct OrderedSummaries<ChunkKey, Summary>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+{
+    // the chunk key at each index of the parent storage, used to notice chunks that come, go, or
+    // change
+    chunk_list: RVec<Option<ChunkKey::Owned>>,
+    keys: Vec<ChunkKey::Owned>,
+    summaries: RVec<Summary>,
+    tree: Reduce<Summary, Summary>,
 }
 
 impl<ChunkKey, Element, Summary> Reduction<ChunkKey, Element, Summary>
@@ -52,6 +93,12 @@ where
     /// Try to re-use `Reductions` as much as possible. If you drop a `Reduction` and re-create it,
     /// then the `Reduction`'s internal index has to be rebuilt, which might take a lot of time.
     ///
+    /// Each chunk is already divided into fixed-size segments: its elements are summarized in
+    /// groups of `group_size`, those summaries again in groups of `group_size`, and so on, with
+    /// every group keeping its own partial result. Changing a single element only re-maps that
+    /// element and re-folds the one group in each layer above it, so a smaller `group_size`
+    /// gives finer-grained segments at the cost of more layers.
+    ///
     /// # Type Parameters
     ///
     /// * `ItemKey`: this is the `ItemKey` matching the `Storage`.
@@ -72,26 +119,109 @@ where
         Map: Fn(&Element, &Summary) -> Option<Summary> + Clone + Send + Sync + 'static,
         Fold: Fn(&[Summary], &Summary) -> Option<Summary> + Clone + Send + Sync + 'static,
     {
-        let chunkwise_summaries = RVec::default();
-        let reduction = Reduce::new(
-            &chunkwise_summaries,
+        Self::from_rules(
+            storage,
             group_size,
-            Self::reduction_rules(map.clone(), fold.clone()),
-        );
+            Self::chunkwise_rules(map.clone(), fold.clone()),
+            Self::reduction_rules(map, fold),
+        )
+    }
+
+    /// Create a new `Reduction` on a `Storage`, where any `Summary` can be subtracted back out
+    /// of a `Summary` that it was folded into.
+    ///
+    /// This works like `Reduction::new`, except that when an element is added, removed, or
+    /// changed, the `Reduction` subtracts the old `Summary` of that element and folds in the new
+    /// one, rather than re-folding every `Summary` in the neighborhood of that element. This makes
+    /// each change O(1), no matter how large the chunk.
+    ///
+    /// For this to work, `Fold` must not depend on the order of the `Summaries`, `Summary::default()`
+    /// must be the identity of `Fold`, and `Inverse` must exactly undo `Fold`.
+    ///
+    /// # Type Parameters
+    ///
+    /// * `Inverse`: given a `Summary` of many elements and the `Summary` of one of those elements,
+    ///   returns the `Summary` of all the other elements.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    ///
+    /// let mut storage : Storage<u64, u64, (u64, u64, i64)> = Storage::new();
+    /// let mut total : Reduction<u64, (u64, u64, i64), i64> = Reduction::new_invertible(
+    ///   &storage,
+    ///   |element: &(u64, u64, i64), was: &i64| Some(element.2).filter(|x| x != was),
+    ///   |xs: &[i64], was: &i64| Some(xs.iter().sum()).filter(|x| x != was),
+    ///   |total: &i64, x: &i64| total - x,
+    /// );
+    ///
+    /// storage.add((1, 1, 10));
+    /// storage.add((1, 2, 20));
+    /// storage.add((2, 1, 30));
+    /// assert_eq!(Some(&60), total.reduce(&storage));
+    ///
+    /// storage.modify(&ID.chunk(1).item(2), |mut editor| editor.get_mut().2 = 25);
+    /// storage.remove(&ID.chunk(2).item(1), std::mem::drop);
+    /// assert_eq!(Some(&35), total.reduce(&storage));
+    /// ```
+    pub fn new_invertible<ItemKey, Map, Fold, Inverse>(
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        map: Map,
+        fold: Fold,
+        inverse: Inverse,
+    ) -> Self
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        Map: Fn(&Element, &Summary) -> Option<Summary> + Clone + Send + Sync + 'static,
+        Fold: Fn(&[Summary], &Summary) -> Option<Summary> + Clone + Send + Sync + 'static,
+        Inverse: Fn(&Summary, &Summary) -> Summary + Clone + Send + Sync + 'static,
+    {
+        Self::from_rules(
+            storage,
+            2,
+            Self::chunkwise_rules(map.clone(), fold.clone()).with_inverse(inverse.clone()),
+            Self::reduction_rules(map, fold).with_inverse(inverse),
+        )
+    }
+
+    fn from_rules<ItemKey>(
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        group_size: usize,
+        rules: ReduceRules<Element, Summary>,
+        reduction_rules: ReduceRules<Summary, Summary>,
+    ) -> Self
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        let chunkwise_summaries = RVec::default();
+        let reduction = Reduce::new(&chunkwise_summaries, group_size, reduction_rules.clone());
         Reduction {
             parent_id: storage.id(),
             group_size,
+            reduction_rules,
             gc_chunk_list: RVec::default(),
-            rules: Self::chunkwise_rules(map.clone(), fold.clone()),
+            rules,
             chunkwise_reductions: HashMap::with_hasher(
                 crate::internal::hasher::HasherImpl::default(),
             ),
             chunkwise_summaries,
             reduction,
+            by_chunk: None,
+            by_key: None,
+            generation: 0,
+            result_version: None,
         }
     }
 
-    fn reduction_rules<Map, Reduce>(_map: Map, reduce: Reduce) -> ReduceRules<Summary, Summary>
+    pub(crate) fn reduction_rules<Map, Reduce>(
+        _map: Map,
+        reduce: Reduce,
+    ) -> ReduceRules<Summary, Summary>
     where
         Map: Fn(&Element, &Summary) -> Option<Summary> + Clone + Send + Sync + 'static,
         Reduce: Fn(&[Summary], &Summary) -> Option<Summary> + Clone + Send + Sync + 'static,
@@ -100,7 +230,10 @@ where
         ReduceRules::new(move |ss, s, _| map(std::slice::from_ref(ss), s), reduce)
     }
 
-    fn chunkwise_rules<Map, Reduce>(map: Map, reduce: Reduce) -> ReduceRules<Element, Summary>
+    pub(crate) fn chunkwise_rules<Map, Reduce>(
+        map: Map,
+        reduce: Reduce,
+    ) -> ReduceRules<Element, Summary>
     where
         Map: Fn(&Element, &Summary) -> Option<Summary> + Clone + Send + Sync + 'static,
         Reduce: Fn(&[Summary], &Summary) -> Option<Summary> + Clone + Send + Sync + 'static,
@@ -238,6 +371,12 @@ where
     /// assert!(summary.services.contains("mail"));
     /// assert!(summary.services.contains("games"));
     ///```
+    ///
+    /// # Panic
+    ///
+    /// This method panics if used with a `Storage` other than the one this `Reduction` was
+    /// created with, unless that `Storage`'s `Strictness` is `Repair`, in which case the
+    /// `Reduction` is rebuilt for the new `Storage`.
     pub fn reduce<ItemKey>(
         &mut self,
         storage: &Storage<ChunkKey, ItemKey, Element>,
@@ -247,43 +386,662 @@ where
         ItemKey: BorrowedKey + ?Sized,
         ItemKey::Owned: ValidKey,
     {
-        assert_eq!(
-      self.parent_id,
-      storage.id(),
-      "Id mismatch: a Reduction may only be used with it's parent Storage, never any other Storage"
-    );
+        self.check_parent(storage);
 
         self.gc(storage);
+        self.update_chunkwise(storage);
 
-        let chunkwise_reductions = &mut self.chunkwise_reductions;
-        let chunkwise_summaries = &mut self.chunkwise_summaries;
+        self.reduction.update(&self.chunkwise_summaries);
+        self.update_generation();
+        self.reduction.peek()
+    }
+
+    /// Reduce all of the elements of the given `Storage` down to a single value, exactly like
+    /// `Reduction::reduce`, except that the chunks that changed are re-summarized in parallel,
+    /// and the summaries of those chunks are folded together in parallel.
+    ///
+    /// Whether this runs in parallel at all is decided by the `Storage`'s `Parallelism`, based on
+    /// the chunks that changed, so this is as fast as `Reduction::reduce` when only a few
+    /// elements have changed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    /// use retriever::types::parallelism::Parallelism;
+    ///
+    /// let mut storage : Storage<u64, u64, (u64, u64, u64)> = Storage::new();
+    /// storage.set_parallelism(Parallelism::parallel());
+    ///
+    /// let mut total : Reduction<u64, (u64, u64, u64), u64> = Reduction::new(
+    ///   &storage,
+    ///   16,
+    ///   |element: &(u64, u64, u64), was: &u64| Some(element.2).filter(|x| x != was),
+    ///   |xs: &[u64], was: &u64| Some(xs.iter().sum()).filter(|x| x != was),
+    /// );
+    ///
+    /// for i in 0..10_000 {
+    ///   storage.add((i % 100, i, i));
+    /// }
+    ///
+    /// assert_eq!(Some(&(0..10_000).sum::<u64>()), total.par_reduce(&storage));
+    ///
+    /// storage.remove(Chunks(vec![0]), std::mem::drop);
+    /// assert_eq!(total.reduce(&storage).cloned(), total.par_reduce(&storage).cloned());
+    /// ```
+    ///
+    /// # Panic
+    ///
+    /// Like `Reduction::reduce`, this method panics if used with a `Storage` other than the one
+    /// this `Reduction` was created with, unless that `Storage`'s `Strictness` is `Repair`.
+    #[cfg(feature = "rayon")]
+    pub fn par_reduce<ItemKey>(
+        &mut self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+    ) -> Option<&Summary>
+    where
+        Element: Record<ChunkKey, ItemKey> + Sync,
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Summary: Send + Sync,
+    {
+        use rayon::prelude::*;
+
+        self.check_parent(storage);
+
+        self.gc(storage);
+
+        // Find the chunks that changed, without summarizing them yet.
+        let mut dirty = Vec::new();
+        self.chunkwise_summaries
+            .reduce(storage.internal_rvec(), 1, |chunks, _old_summary, idx| {
+                if !chunks.is_empty() {
+                    dirty.push(idx);
+                }
+
+                None
+            });
+
+        if !storage.is_parallel(&dirty) {
+            // Nothing has been summarized yet, so go back and summarize sequentially.
+            for idx in dirty {
+                self.update_chunk(storage, idx);
+            }
+
+            self.reduction.update(&self.chunkwise_summaries);
+            self.update_generation();
+            return self.reduction.peek();
+        }
+
+        let chunk_storages = storage.internal_rvec();
         let group_size = self.group_size;
         let rules = &self.rules;
+        let chunkwise_reductions = &mut self.chunkwise_reductions;
+
+        let mut work: Vec<(usize, &RVec<Element>, Reduce<Element, Summary>)> = dirty
+            .into_iter()
+            .map(|idx| {
+                let internal_storage = chunk_storages[idx].internal_rvec();
+                let reduction = chunkwise_reductions
+                    .remove(chunk_storages[idx].chunk_key())
+                    .unwrap_or_else(|| Reduce::new(internal_storage, group_size, rules.clone()));
+                (idx, internal_storage, reduction)
+            })
+            .collect();
+
+        let summaries: Vec<Summary> = work
+            .par_iter_mut()
+            .map(|(_, internal_storage, reduction)| {
+                reduction
+                    .par_update(internal_storage)
+                    .cloned()
+                    .unwrap_or_default()
+            })
+            .collect();
 
-        chunkwise_summaries.reduce(&self.gc_chunk_list, 1, |chunk_key, _old_summary, idx| {
-            assert!(chunk_key.len() <= 1);
+        for ((idx, _, reduction), summary) in work.into_iter().zip(summaries) {
+            let chunk_key = chunk_storages[idx].chunk_key();
 
-            if chunk_key.is_empty() {
-                return None;
+            if let Some((_, by_chunk)) = self.by_chunk.as_mut() {
+                by_chunk.insert(chunk_key.to_owned(), summary.clone());
             }
 
-            let chunk_key = chunk_key[0]
-                .as_ref()
-                .cloned()
-                .expect("retriever bug: chunk keys should be defined for all indices after gc");
-            let internal_storage = storage.internal_rvec()[idx].internal_rvec();
+            self.chunkwise_reductions
+                .insert(chunk_key.to_owned(), reduction);
+            self.chunkwise_summaries[idx] = summary;
+        }
+
+        self.reduction.par_update(&self.chunkwise_summaries);
+        self.update_generation();
+        self.reduction.peek()
+    }
+
+    /// The number of times that the result of `Reduction::reduce` or `Reduction::par_reduce`
+    /// has changed. Poll this to find out whether the result has changed since you last looked at
+    /// it, without comparing the results yourself.
+    ///
+    /// The result is considered to have changed whenever the `Map` or `Fold` rules report a new
+    /// `Summary` that reaches the result, or when the result appears or disappears because the
+    /// `Storage` has gained its first element or lost its last one. Removing enough elements to
+    /// collapse a layer of the reduction may also advance the generation, even if the result is
+    /// the same. The generation only advances when the `Reduction` is reduced, not when the
+    /// `Storage` changes.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    ///
+    /// let mut storage : Storage<u64, u64, (u64, u64, u64)> = Storage::new();
+    /// let mut highest : Reduction<u64, (u64, u64, u64), u64> = Reduction::new(
+    ///   &storage,
+    ///   2,
+    ///   |element: &(u64, u64, u64), was: &u64| Some(element.2).filter(|x| x != was),
+    ///   |xs: &[u64], was: &u64| xs.iter().max().cloned().filter(|x| x != was),
+    /// );
+    ///
+    /// storage.add((1, 1, 10));
+    /// storage.add((1, 2, 20));
+    /// highest.reduce(&storage);
+    /// let seen = highest.generation();
+    ///
+    /// // Lowering an element that isn't the highest doesn't change the result.
+    /// storage.modify(&ID.chunk(1).item(1), |mut editor| editor.get_mut().2 = 5);
+    /// highest.reduce(&storage);
+    /// assert_eq!(seen, highest.generation());
+    ///
+    /// storage.add((2, 3, 30));
+    /// assert_eq!(Some(&30), highest.reduce(&storage));
+    /// assert!(highest.generation() > seen);
+    /// ```
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn update_generation(&mut self) {
+        let result_version = self.reduction.result_version();
+
+        if result_version != self.result_version {
+            self.generation += 1;
+            self.result_version = result_version;
+        }
+    }
+
+    /// Bring this reduction up to date and save a copy of the summary of each chunk, which can
+    /// be serialized and later restored using `Reduction::restore`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    /// use retriever::types::saved_reduction::SavedReduction;
+    ///
+    /// type Sale = (u64, u64, i64);
+    /// fn total(storage: &Storage<u64, u64, Sale>) -> Reduction<u64, Sale, i64> {
+    ///   Reduction::new(
+    ///     storage,
+    ///     2,
+    ///     |x: &Sale, was: &i64| Some(x.2).filter(|x| x != was),
+    ///     |xs: &[i64], was: &i64| Some(xs.iter().sum()).filter(|x| x != was),
+    ///   )
+    /// }
+    ///
+    /// let mut storage : Storage<u64, u64, Sale> = Storage::new();
+    /// for i in 0..100 {
+    ///   storage.add((i % 10, i, 1));
+    /// }
+    ///
+    /// let saved = serde_json::to_string(&total(&storage).save(&storage)).unwrap();
+    ///
+    /// // Later, perhaps after restarting the program and loading the Storage, but with one
+    /// // chunk that has changed since the reduction was saved.
+    /// storage.add((3, 100, 5));
+    ///
+    /// let saved : SavedReduction<u64, i64> = serde_json::from_str(&saved).unwrap();
+    /// let mut reduction = total(&storage);
+    /// assert_eq!(9, reduction.restore(&storage, &saved));
+    /// assert_eq!(Some(&105), reduction.reduce(&storage));
+    /// ```
+    ///
+    /// # Panic
+    ///
+    /// Like `Reduction::reduce`, this method panics if used with a `Storage` other than the one
+    /// this `Reduction` was created with, unless that `Storage`'s `Strictness` is `Repair`.
+    #[cfg(feature = "serde")]
+    pub fn save<ItemKey>(
+        &mut self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+    ) -> SavedReduction<ChunkKey::Owned, Summary>
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey> + Hash,
+    {
+        self.reduce(storage);
+
+        let chunks = storage
+            .internal_rvec()
+            .iter()
+            .enumerate()
+            .map(|(idx, chunk_storage)| SavedChunkSummary {
+                chunk_key: chunk_storage.chunk_key().to_owned(),
+                fingerprint: fingerprint(chunk_storage),
+                summary: self.chunkwise_summaries[idx].clone(),
+            })
+            .collect();
+
+        SavedReduction { chunks }
+    }
+
+    /// Restore a copy of the chunk summaries saved using `Reduction::save`, for every chunk
+    /// that hasn't changed since it was saved, and summarize every other chunk as usual. Returns
+    /// the number of chunks restored.
+    ///
+    /// A restored chunk is summarized from scratch the next time it changes, rather than
+    /// incrementally. The saved summaries are trusted, so this `Reduction` must have the same
+    /// rules as the `Reduction` that was saved.
+    ///
+    /// # Panic
+    ///
+    /// Like `Reduction::reduce`, this method panics if used with a `Storage` other than the one
+    /// this `Reduction` was created with, unless that `Storage`'s `Strictness` is `Repair`.
+    #[cfg(feature = "serde")]
+    pub fn restore<ItemKey>(
+        &mut self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        saved: &SavedReduction<ChunkKey::Owned, Summary>,
+    ) -> usize
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey> + Hash,
+    {
+        self.check_parent(storage);
+        self.gc(storage);
+
+        let saved_summaries: HashMap<&ChunkKey::Owned, &SavedChunkSummary<_, _>> = saved
+            .chunks
+            .iter()
+            .map(|saved_chunk| (&saved_chunk.chunk_key, saved_chunk))
+            .collect();
+
+        let chunk_storages = storage.internal_rvec();
+        let mut stale = Vec::new();
+        let summaries: Vec<Summary> = chunk_storages
+            .iter()
+            .enumerate()
+            .map(|(idx, chunk_storage)| {
+                match saved_summaries.get(&chunk_storage.chunk_key().to_owned()) {
+                    Some(saved_chunk) if saved_chunk.fingerprint == fingerprint(chunk_storage) => {
+                        saved_chunk.summary.clone()
+                    }
+                    _ => {
+                        stale.push(idx);
+                        Summary::default()
+                    }
+                }
+            })
+            .collect();
+        let restored = summaries.len() - stale.len();
+
+        self.chunkwise_summaries = RVec::reduced_from(chunk_storages, summaries);
+        self.by_chunk = None;
+        self.by_key = None;
+
+        for idx in stale {
+            self.update_chunk(storage, idx);
+        }
+
+        restored
+    }
+
+    /// Reduce the elements of each chunk of the given `Storage` down to a single value per
+    /// chunk. Like `Reduction::reduce`, repeated evaluations only re-compute the chunks that
+    /// have changed, and chunks that have been removed from the `Storage` are forgotten.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    ///
+    /// // Sales chunked by day, keyed by sale id, with an amount.
+    /// let mut storage : Storage<u64, u64, (u64, u64, i64)> = Storage::new();
+    /// let mut daily : Reduction<u64, (u64, u64, i64), i64> = Reduction::new(
+    ///   &storage,
+    ///   2,
+    ///   |element: &(u64, u64, i64), was: &i64| Some(element.2).filter(|x| x != was),
+    ///   |xs: &[i64], was: &i64| Some(xs.iter().sum()).filter(|x| x != was),
+    /// );
+    ///
+    /// storage.add((1, 1, 10));
+    /// storage.add((1, 2, 20));
+    /// storage.add((2, 3, 30));
+    ///
+    /// let totals = daily.reduce_by_chunk(&storage);
+    /// assert_eq!(2, totals.len());
+    /// assert_eq!(Some(&30), totals.get(&1));
+    /// assert_eq!(Some(&30), totals.get(&2));
+    ///
+    /// storage.add((2, 4, 5));
+    /// storage.remove(Chunks(vec![1]), std::mem::drop);
+    ///
+    /// let totals = daily.reduce_by_chunk(&storage);
+    /// assert_eq!(1, totals.len());
+    /// assert_eq!(Some(&35), totals.get(&2));
+    /// ```
+    ///
+    /// # Panic
+    ///
+    /// Like `Reduction::reduce`, this method panics if used with a `Storage` other than the one
+    /// this `Reduction` was created with, unless that `Storage`'s `Strictness` is `Repair`.
+    pub fn reduce_by_chunk<ItemKey>(
+        &mut self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+    ) -> &HashMap<ChunkKey::Owned, Summary>
+    where
+        Element: Record<ChunkKey, ItemKey>,
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+    {
+        self.check_parent(storage);
+
+        self.gc(storage);
+        self.update_chunkwise(storage);
+
+        if self.by_chunk.is_none() {
+            // Every chunk is up to date now, so start from the summaries we already have.
+            let summaries = storage
+                .internal_rvec()
+                .iter()
+                .enumerate()
+                .map(|(idx, chunk)| {
+                    (
+                        chunk.chunk_key().to_owned(),
+                        self.chunkwise_summaries[idx].clone(),
+                    )
+                })
+                .collect();
+            self.by_chunk = Some((RVec::default(), summaries));
+        }
+
+        let (gc_chunk_list, summaries) = self.by_chunk.as_mut().unwrap();
+        storage.gc(gc_chunk_list, summaries);
+
+        summaries
+    }
+
+    /// Reduce the elements of every chunk whose chunk key is within a range down to a single
+    /// value, or `None` if there are no such chunks. The summaries of the chunks are kept in
+    /// chunk key order under a tree of `Fold`s, so this only folds O(log(n)) summaries together,
+    /// no matter how many chunks the range covers.
+    ///
+    /// Chunks are best added in increasing chunk key order, such as when the chunk key is a date.
+    /// Adding or removing a chunk anywhere but at the end of the order re-folds the whole tree.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    ///
+    /// // Sales chunked by day, keyed by sale id, with an amount.
+    /// let mut storage : Storage<u64, u64, (u64, u64, i64)> = Storage::new();
+    /// let mut total : Reduction<u64, (u64, u64, i64), i64> = Reduction::new(
+    ///   &storage,
+    ///   2,
+    ///   |element: &(u64, u64, i64), was: &i64| Some(element.2).filter(|x| x != was),
+    ///   |xs: &[i64], was: &i64| Some(xs.iter().sum()).filter(|x| x != was),
+    /// );
+    ///
+    /// for day in 1..=31 {
+    ///   storage.add((day, day, 10));
+    ///   storage.add((day, 100 + day, day as i64));
+    /// }
+    ///
+    /// assert_eq!(Some(70 + 1 + 2 + 3 + 4 + 5 + 6 + 7), total.reduce_range(&storage, 1..8));
+    /// assert_eq!(Some(10 + 31), total.reduce_range(&storage, 31..));
+    /// assert_eq!(None, total.reduce_range(&storage, 32..));
+    ///
+    /// storage.remove(Chunks(vec![2]), std::mem::drop);
+    /// assert_eq!(Some(20 + 1 + 3), total.reduce_range(&storage, ..=3));
+    /// ```
+    ///
+    /// # Panic
+    ///
+    /// Like `Reduction::reduce`, this method panics if used with a `Storage` other than the one
+    /// this `Reduction` was created with, unless that `Storage`'s `Strictness` is `Repair`.
+    pub fn reduce_range<ItemKey, R>(
+        &mut self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        range: R,
+    ) -> Option<Summary>
+    where
+        Element: Record<ChunkKey, ItemKey>,
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        R: RangeBounds<ChunkKey>,
+    {
+        self.check_parent(storage);
+
+        self.gc(storage);
+        self.update_chunkwise(storage);
+
+        let group_size = self.group_size;
+        let reduction_rules = &self.reduction_rules;
+        let by_key = self
+            .by_key
+            .get_or_insert_with(|| OrderedSummaries::new(group_size, reduction_rules));
+        by_key.update(storage, &self.chunkwise_summaries);
+        by_key.fold_range(range)
+    }
+
+    // Bring the summary of the chunk at the given index up to date, after the chunk list has
+    // already been reduced.
+    #[cfg(any(feature = "rayon", feature = "serde"))]
+    fn update_chunk<ItemKey>(&mut self, storage: &Storage<ChunkKey, ItemKey, Element>, idx: usize)
+    where
+        Element: Record<ChunkKey, ItemKey>,
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+    {
+        let chunk = &storage.internal_rvec()[idx];
+        let internal_storage = chunk.internal_rvec();
+        let group_size = self.group_size;
+        let rules = &self.rules;
+        let summary = self
+            .chunkwise_reductions
+            .entry(chunk.chunk_key().to_owned())
+            .or_insert_with(|| Reduce::new(internal_storage, group_size, rules.clone()))
+            .update(internal_storage)
+            .cloned()
+            .unwrap_or_default();
+
+        if let Some((_, by_chunk)) = self.by_chunk.as_mut() {
+            by_chunk.insert(chunk.chunk_key().to_owned(), summary.clone());
+        }
+
+        self.chunkwise_summaries[idx] = summary;
+    }
+
+    // Bring the summary of every chunk up to date.
+    fn update_chunkwise<ItemKey>(&mut self, storage: &Storage<ChunkKey, ItemKey, Element>)
+    where
+        Element: Record<ChunkKey, ItemKey>,
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+    {
+        let chunkwise_reductions = &mut self.chunkwise_reductions;
+        let chunkwise_summaries = &mut self.chunkwise_summaries;
+        let mut by_chunk = self.by_chunk.as_mut().map(|(_, summaries)| summaries);
+        let group_size = self.group_size;
+        let rules = &self.rules;
+
+        // Reduce directly from the list of chunks, which notices when any chunk is modified,
+        // not just when chunks come and go.
+        chunkwise_summaries.reduce(storage.internal_rvec(), 1, |chunks, _old_summary, _| {
+            assert!(chunks.len() <= 1);
 
-            chunkwise_reductions
-                .entry(chunk_key)
+            let chunk = chunks.first()?;
+            let internal_storage = chunk.internal_rvec();
+            let summary = chunkwise_reductions
+                .entry(chunk.chunk_key().to_owned())
                 .or_insert_with(|| Reduce::new(internal_storage, group_size, rules.clone()))
-                .update(&internal_storage)
+                .update(internal_storage)
                 .cloned()
+                .unwrap_or_default();
+
+            if let Some(by_chunk) = by_chunk.as_mut() {
+                by_chunk.insert(chunk.chunk_key().to_owned(), summary.clone());
+            }
+
+            Some(summary)
         });
+    }
+
+    /// Reduce only those elements of the given `Storage` that belong to a `Query`. The summary
+    /// of each chunk that the `Query` covers completely is reused from the cached reduction of
+    /// that chunk, so this is fastest for queries that select whole chunks, like `Chunks`. The
+    /// elements of any other chunk are summarized from scratch. Returns `None` if the `Query`
+    /// matches no elements.
+    ///
+    /// A chunk is only known to be covered completely if the `Query` is exact; see
+    /// `Query::is_exact`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    ///
+    /// // Sales chunked by store, keyed by sale id, with an amount.
+    /// let mut storage : Storage<u64, u64, (u64, u64, i64)> = Storage::new();
+    /// let mut total : Reduction<u64, (u64, u64, i64), i64> = Reduction::new(
+    ///   &storage,
+    ///   2,
+    ///   |element: &(u64, u64, i64), was: &i64| Some(element.2).filter(|x| x != was),
+    ///   |xs: &[i64], was: &i64| Some(xs.iter().sum()).filter(|x| x != was),
+    /// );
+    ///
+    /// storage.add((1, 1, 10));
+    /// storage.add((1, 2, 20));
+    /// storage.add((2, 3, 30));
+    /// storage.add((3, 4, 40));
+    ///
+    /// assert_eq!(Some(70), total.reduce_query(&storage, Chunks(vec![2, 3])));
+    /// assert_eq!(Some(70), total.reduce_query(&storage, Everything.filter(|x: &(u64, u64, i64)| x.2 >= 30)));
+    /// assert_eq!(None, total.reduce_query(&storage, Chunks(vec![4])));
+    /// assert_eq!(Some(&100), total.reduce(&storage));
+    /// ```
+    ///
+    /// # Panic
+    ///
+    /// Like `Reduction::reduce`, this method panics if used with a `Storage` other than the one
+    /// this `Reduction` was created with, unless that `Storage`'s `Strictness` is `Repair`.
+    pub fn reduce_query<ItemKey, Q>(
+        &mut self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        query: Q,
+    ) -> Option<Summary>
+    where
+        Element: Record<ChunkKey, ItemKey>,
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Q: Query<ChunkKey, ItemKey, Element>,
+    {
+        self.check_parent(storage);
+
+        self.gc(storage);
+
+        let chunkwise_reductions = &mut self.chunkwise_reductions;
+        let group_size = self.group_size;
+        let rules = &self.rules;
+        let exact = query.is_exact();
+        let mut summaries = Vec::new();
+
+        for idx in query.chunk_idxs(storage).into_idx_iter().flatten() {
+            let chunk_storage = &storage.internal_rvec()[idx];
+            let chunk_key = chunk_storage.chunk_key();
+            let item_idxs = query.item_idxs(chunk_key, chunk_storage);
+            let candidates: usize = item_idxs
+                .clone()
+                .into_idx_iter()
+                .map(|bitfield| bitfield.ones())
+                .sum();
+
+            if exact && candidates == chunk_storage.len() {
+                let internal_storage = chunk_storage.internal_rvec();
+                summaries.extend(
+                    chunkwise_reductions
+                        .entry(chunk_key.to_owned())
+                        .or_insert_with(|| Reduce::new(internal_storage, group_size, rules.clone()))
+                        .update(internal_storage)
+                        .cloned(),
+                );
+                continue;
+            }
+
+            let element_summaries: Vec<Summary> = item_idxs
+                .into_idx_iter()
+                .flatten()
+                .filter_map(|item_idx| {
+                    let element = chunk_storage.get_idx(item_idx);
+                    if query.test(element) {
+                        Some(rules.map_one(element, item_idx))
+                    } else {
+                        None
+                    }
+                })
+                .collect();
+
+            // A chunk without any matching element contributes nothing, not a default summary.
+            if !element_summaries.is_empty() {
+                summaries.push(rules.fold(&element_summaries));
+            }
+        }
+
+        if summaries.is_empty() {
+            None
+        } else {
+            Some(rules.fold(&summaries))
+        }
+    }
+
+    fn check_parent<ItemKey>(&mut self, storage: &Storage<ChunkKey, ItemKey, Element>)
+    where
+        Element: Record<ChunkKey, ItemKey>,
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+    {
+        if self.parent_id == storage.id() {
+            return;
+        }
+
+        assert_eq!(
+            storage.strictness(),
+            Strictness::Repair,
+            "Id mismatch: a Reduction may only be used with it's parent Storage, never any other Storage"
+        );
 
-        self.reduction.update(&self.chunkwise_summaries)
+        #[cfg(feature = "log")]
+        log::warn!("retriever: repaired Reduction used with a different Storage by rebuilding it");
+
+        self.parent_id = storage.id();
+        self.gc_chunk_list = RVec::default();
+        self.chunkwise_reductions.clear();
+        self.chunkwise_summaries = RVec::default();
+        self.reduction = Reduce::new(
+            &self.chunkwise_summaries,
+            self.group_size,
+            self.reduction_rules.clone(),
+        );
+        self.by_chunk = None;
+        self.by_key = None;
     }
 
     /// Reduce all of the elements of a single chunk down to a single value.
+    ///
+    /// # Panic
+    ///
+    /// Like `Reduction::reduce`, this method panics if used with a `Storage` other than the one
+    /// this `Reduction` was created with, unless that `Storage`'s `Strictness` is `Repair`.
     pub fn reduce_chunk<ItemKey>(
         &mut self,
         storage: &Storage<ChunkKey, ItemKey, Element>,
@@ -294,11 +1052,7 @@ where
         ItemKey: BorrowedKey + ?Sized,
         ItemKey::Owned: ValidKey,
     {
-        assert_eq!(
-      self.parent_id,
-      storage.id(),
-      "Id mismatch: a Reduction may only be used with it's parent Storage, never any other Storage"
-    );
+        self.check_parent(storage);
 
         self.gc(storage);
 
@@ -316,6 +1070,142 @@ where
     }
 }
 
+impl<ChunkKey, Summary> OrderedSummaries<ChunkKey, Summary>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    Summary: Default + Clone,
+{
+    fn new(group_size: usize, rules: &ReduceRules<Summary, Summary>) -> Self {
+        let summaries = RVec::default();
+        let tree = Reduce::new(&summaries, group_size, rules.without_inverse());
+
+        OrderedSummaries {
+            chunk_list: RVec::default(),
+            keys: Vec::new(),
+            summaries,
+            tree,
+        }
+    }
+
+    // Catch up with every chunk that was added, removed, or modified, given the up-to-date
+    // summary of the chunk at each index of the parent storage.
+    fn update<ItemKey, Element>(
+        &mut self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        chunkwise_summaries: &RVec<Summary>,
+    ) where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        let mut dirty: Vec<usize> = Vec::new();
+        let mut removed: HashSet<ChunkKey::Owned> = HashSet::new();
+        let mut added: HashSet<ChunkKey::Owned> = HashSet::new();
+
+        self.chunk_list
+            .reduce(storage.internal_rvec(), 1, |chunks, prev_chunk_key, idx| {
+                let chunk = match chunks.first() {
+                    Some(chunk) => chunk,
+                    None => {
+                        removed.extend(prev_chunk_key.clone());
+                        return None;
+                    }
+                };
+
+                dirty.push(idx);
+
+                if Some(chunk.chunk_key()) == prev_chunk_key.as_ref().map(Borrow::borrow) {
+                    return None;
+                }
+
+                removed.extend(prev_chunk_key.clone());
+                added.insert(chunk.chunk_key().to_owned());
+                Some(Some(chunk.chunk_key().to_owned()))
+            });
+
+        for chunk_key in removed.difference(&added) {
+            self.remove(chunk_key);
+        }
+
+        for idx in dirty {
+            let chunk_key = storage.internal_rvec()[idx].chunk_key();
+            self.set(chunk_key, chunkwise_summaries[idx].clone());
+        }
+
+        self.tree.update(&self.summaries);
+    }
+
+    fn set(&mut self, chunk_key: &ChunkKey, summary: Summary) {
+        match self.keys.binary_search_by(|k| k.borrow().cmp(chunk_key)) {
+            Ok(i) => self.summaries[i] = summary,
+            Err(i) if i == self.keys.len() => {
+                self.keys.push(chunk_key.to_owned());
+                self.summaries.push(summary);
+            }
+            Err(i) => {
+                // Everything after this chunk moves over, so start over.
+                let mut summaries: Vec<Summary> = self.summaries.to_vec();
+                summaries.insert(i, summary);
+                self.keys.insert(i, chunk_key.to_owned());
+                self.summaries = RVec::from(summaries);
+            }
+        }
+    }
+
+    fn remove(&mut self, chunk_key: &ChunkKey::Owned) {
+        match self.keys.binary_search(chunk_key) {
+            Ok(i) if i + 1 == self.keys.len() => {
+                self.keys.pop();
+                self.summaries.swap_remove(i);
+            }
+            Ok(i) => {
+                // Everything after this chunk moves over, so start over.
+                let mut summaries: Vec<Summary> = self.summaries.to_vec();
+                summaries.remove(i);
+                self.keys.remove(i);
+                self.summaries = RVec::from(summaries);
+            }
+            Err(_) => {}
+        }
+    }
+
+    fn fold_range<R: RangeBounds<ChunkKey>>(&self, range: R) -> Option<Summary> {
+        let lo = self.keys.partition_point(|k| match range.start_bound() {
+            Bound::Included(start) => k.borrow() < start,
+            Bound::Excluded(start) => k.borrow() <= start,
+            Bound::Unbounded => false,
+        });
+        let hi = self.keys.partition_point(|k| match range.end_bound() {
+            Bound::Included(end) => k.borrow() <= end,
+            Bound::Excluded(end) => k.borrow() < end,
+            Bound::Unbounded => true,
+        });
+
+        self.tree.fold_range(lo..hi)
+    }
+}
+
+impl<ChunkKey, Summary> MemoryUser for OrderedSummaries<ChunkKey, Summary>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+{
+    fn memory_usage(&self) -> MemoryUsage {
+        let mut result = self.chunk_list.memory_usage();
+        result = MemoryUsage::merge(result, self.keys.memory_usage());
+        result = MemoryUsage::merge(result, self.summaries.memory_usage());
+        MemoryUsage::merge(result, self.tree.memory_usage())
+    }
+
+    fn shrink_with<F: Fn(&MemoryUsage) -> Option<usize>>(&mut self, f: F) {
+        self.chunk_list.shrink_with(&f);
+        self.keys.shrink_with(&f);
+        self.summaries.shrink_with(&f);
+        self.tree.shrink_with(&f);
+    }
+}
+
 impl<ChunkKey, Element, Summary> MemoryUser for Reduction<ChunkKey, Element, Summary>
 where
     ChunkKey: BorrowedKey + ?Sized,
@@ -336,6 +1226,15 @@ where
             result = MemoryUsage::merge(result, reduction.memory_usage());
         }
 
+        if let Some((gc_chunk_list, summaries)) = self.by_chunk.as_ref() {
+            result = MemoryUsage::merge(result, gc_chunk_list.memory_usage());
+            result = MemoryUsage::merge(result, summaries.memory_usage());
+        }
+
+        if let Some(by_key) = self.by_key.as_ref() {
+            result = MemoryUsage::merge(result, by_key.memory_usage());
+        }
+
         result
     }
 
@@ -347,5 +1246,14 @@ where
         for reduction in self.chunkwise_reductions.values_mut() {
             reduction.shrink_with(&f);
         }
+
+        if let Some((gc_chunk_list, summaries)) = self.by_chunk.as_mut() {
+            gc_chunk_list.shrink_with(&f);
+            summaries.shrink_with(&f);
+        }
+
+        if let Some(by_key) = self.by_key.as_mut() {
+            by_key.shrink_with(&f);
+        }
     }
 }
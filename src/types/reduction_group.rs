@@ -0,0 +1,397 @@
+use crate::internal::mr::reduce::*;
+use crate::internal::mr::rvec::RVec;
+use crate::traits::memory_usage::{MemoryUsage, MemoryUser};
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::reduction::Reduction;
+use crate::types::storage::Storage;
+use crate::types::storage_builder::Strictness;
+use std::any::Any;
+use std::borrow::Borrow;
+use std::collections::{HashMap, HashSet};
+use std::marker::PhantomData;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+
+static ID_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Several `Reductions` of the same `Storage`, kept up to date together. Each time any of them
+/// is evaluated, every chunk that changed is visited once and fed to every member, instead of
+/// once per `Reduction`.
+///
+/// Register each reduction with `ReductionGroup::register`, which works exactly like
+/// `Reduction::new` and returns a `ReductionHandle` to read its summary with.
+///
+/// # Example
+///
+/// ```
+/// use retriever::prelude::*;
+/// use retriever::types::reduction_group::ReductionGroup;
+///
+/// // Sales chunked by store, keyed by sale id, with an amount.
+/// type Sale = (u64, u64, i64);
+/// let mut storage : Storage<u64, u64, Sale> = Storage::new();
+/// let mut group : ReductionGroup<u64, Sale> = ReductionGroup::new(&storage);
+///
+/// let total = group.register(
+///   2,
+///   |x: &Sale, was: &i64| Some(x.2).filter(|x| x != was),
+///   |xs: &[i64], was: &i64| Some(xs.iter().sum()).filter(|x| x != was),
+/// );
+/// let largest = group.register(
+///   2,
+///   |x: &Sale, was: &Option<i64>| Some(Some(x.2)).filter(|x| x != was),
+///   |xs: &[Option<i64>], was: &Option<i64>| Some(xs.iter().cloned().max().flatten()).filter(|x| x != was),
+/// );
+///
+/// storage.add((1, 1, 10));
+/// storage.add((1, 2, 20));
+/// storage.add((2, 3, 30));
+/// assert_eq!(Some(&60), group.reduce(&storage, &total));
+/// assert_eq!(Some(&Some(30)), group.reduce(&storage, &largest));
+///
+/// storage.remove(&ID.chunk(2).item(3), std::mem::drop);
+/// assert_eq!(Some(&30), group.reduce(&storage, &total));
+/// assert_eq!(Some(&Some(20)), group.reduce(&storage, &largest));
+///
+/// # storage.validate();
+/// ```
+pub struct ReductionGroup<ChunkKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+{
+    // id, used to see that a handle belongs to this group
+    id: u64,
+    // parent_id, used to see that this ReductionGroup isn't suddenly used with a different parent storage
+    parent_id: u64,
+    // the key of each chunk as of the last update, used to find the chunks that changed
+    chunk_list: RVec<Option<ChunkKey::Owned>>,
+    // every registered reduction, by the index in its handle
+    members: Vec<Box<dyn GroupMember<ChunkKey, Element>>>,
+}
+
+/// A handle to one of the `Reductions` registered with a `ReductionGroup`.
+pub struct ReductionHandle<Summary> {
+    group_id: u64,
+    idx: usize,
+    _summary: PhantomData<fn() -> Summary>,
+}
+
+impl<Summary> Clone for ReductionHandle<Summary> {
+    fn clone(&self) -> Self {
+        ReductionHandle {
+            group_id: self.group_id,
+            idx: self.idx,
+            _summary: PhantomData,
+        }
+    }
+}
+
+// One reduction of a group, with the element type and summary type erased.
+trait GroupMember<ChunkKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+{
+    // Match the number of chunk summaries to the number of chunks.
+    fn resize(&mut self, chunk_count: usize);
+
+    // Bring the summary of one chunk up to date.
+    fn update_chunk(&mut self, idx: usize, chunk_key: &ChunkKey, internal_storage: &RVec<Element>);
+
+    // Forget a chunk that was removed from the storage.
+    fn forget_chunk(&mut self, chunk_key: &ChunkKey);
+
+    // Forget everything, to be rebuilt from scratch.
+    fn clear(&mut self);
+
+    // As MemoryUser::memory_usage.
+    fn memory_usage(&self) -> MemoryUsage;
+
+    // As MemoryUser::shrink_with.
+    fn shrink_with(&mut self, f: &dyn Fn(&MemoryUsage) -> Option<usize>);
+
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+struct Member<ChunkKey, Element, Summary>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+{
+    group_size: usize,
+    rules: ReduceRules<Element, Summary>,
+    reduction_rules: ReduceRules<Summary, Summary>,
+    chunkwise_reductions:
+        HashMap<ChunkKey::Owned, Reduce<Element, Summary>, crate::internal::hasher::HasherImpl>,
+    chunkwise_summaries: RVec<Summary>,
+    reduction: Reduce<Summary, Summary>,
+}
+
+impl<ChunkKey, Element> ReductionGroup<ChunkKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized + 'static,
+    ChunkKey::Owned: ValidKey,
+    Element: 'static,
+{
+    /// Create a new `ReductionGroup`, with no registered reductions, on a `Storage`.
+    pub fn new<ItemKey>(storage: &Storage<ChunkKey, ItemKey, Element>) -> Self
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        ReductionGroup {
+            id: ID_COUNTER.fetch_add(1, Ordering::Relaxed),
+            parent_id: storage.id(),
+            chunk_list: RVec::default(),
+            members: Vec::new(),
+        }
+    }
+
+    /// Register a new reduction with this group. The rules work exactly like the rules of
+    /// `Reduction::new`. Registering a reduction causes every chunk to be visited again the next
+    /// time any member of this group is evaluated, so register every reduction up front if you
+    /// can.
+    pub fn register<Summary, Map, Fold>(
+        &mut self,
+        group_size: usize,
+        map: Map,
+        fold: Fold,
+    ) -> ReductionHandle<Summary>
+    where
+        Summary: Default + Clone + 'static,
+        Map: Fn(&Element, &Summary) -> Option<Summary> + Clone + Send + Sync + 'static,
+        Fold: Fn(&[Summary], &Summary) -> Option<Summary> + Clone + Send + Sync + 'static,
+    {
+        let rules =
+            Reduction::<ChunkKey, Element, Summary>::chunkwise_rules(map.clone(), fold.clone());
+        let reduction_rules = Reduction::<ChunkKey, Element, Summary>::reduction_rules(map, fold);
+        let chunkwise_summaries = RVec::default();
+        let reduction = Reduce::new(&chunkwise_summaries, group_size, reduction_rules.clone());
+
+        self.members
+            .push(Box::new(Member::<ChunkKey, Element, Summary> {
+                group_size,
+                rules,
+                reduction_rules,
+                chunkwise_reductions: HashMap::with_hasher(
+                    crate::internal::hasher::HasherImpl::default(),
+                ),
+                chunkwise_summaries,
+                reduction,
+            }));
+
+        // The new member hasn't seen any chunk yet.
+        self.chunk_list = RVec::default();
+
+        ReductionHandle {
+            group_id: self.id,
+            idx: self.members.len() - 1,
+            _summary: PhantomData,
+        }
+    }
+
+    /// Bring every member of this group up to date, and reduce all of the elements of the given
+    /// `Storage` down to the single value of one member.
+    ///
+    /// # Panic
+    ///
+    /// This method panics if used with a `Storage` other than the one this `ReductionGroup` was
+    /// created with, unless that `Storage`'s `Strictness` is `Repair`, in which case every member
+    /// is rebuilt for the new `Storage`. It also panics if given a handle from another
+    /// `ReductionGroup`.
+    pub fn reduce<ItemKey, Summary>(
+        &mut self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        handle: &ReductionHandle<Summary>,
+    ) -> Option<&Summary>
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        Summary: Default + Clone + 'static,
+    {
+        assert_eq!(
+            self.id, handle.group_id,
+            "a ReductionHandle may only be used with the ReductionGroup that created it"
+        );
+
+        self.refresh(storage);
+
+        let member = self.members[handle.idx]
+            .as_any_mut()
+            .downcast_mut::<Member<ChunkKey, Element, Summary>>()
+            .expect("retriever bug: ReductionHandle has the wrong summary type");
+
+        member.reduction.update(&member.chunkwise_summaries)
+    }
+
+    fn refresh<ItemKey>(&mut self, storage: &Storage<ChunkKey, ItemKey, Element>)
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        if self.parent_id != storage.id() {
+            assert_eq!(
+                storage.strictness(),
+                Strictness::Repair,
+                "Id mismatch: a ReductionGroup may only be used with it's parent Storage, never any other Storage"
+            );
+
+            #[cfg(feature = "log")]
+            log::warn!(
+                "retriever: repaired ReductionGroup used with a different Storage by rebuilding it"
+            );
+
+            self.parent_id = storage.id();
+            self.chunk_list = RVec::default();
+
+            for member in self.members.iter_mut() {
+                member.clear();
+            }
+        }
+
+        let mut dirty: Vec<usize> = Vec::new();
+        let mut removed: HashSet<ChunkKey::Owned> = HashSet::new();
+        let mut added: HashSet<ChunkKey::Owned> = HashSet::new();
+
+        // A single pass over the list of chunks finds every chunk that was added, removed, or
+        // modified, on behalf of every member.
+        self.chunk_list
+            .reduce(storage.internal_rvec(), 1, |chunks, prev_chunk_key, idx| {
+                let chunk = match chunks.first() {
+                    Some(chunk) => chunk,
+                    None => {
+                        removed.extend(prev_chunk_key.clone());
+                        return None;
+                    }
+                };
+
+                dirty.push(idx);
+
+                if Some(chunk.chunk_key()) == prev_chunk_key.as_ref().map(Borrow::borrow) {
+                    return None;
+                }
+
+                removed.extend(prev_chunk_key.clone());
+                added.insert(chunk.chunk_key().to_owned());
+                Some(Some(chunk.chunk_key().to_owned()))
+            });
+
+        let chunk_storages = storage.internal_rvec();
+
+        for member in self.members.iter_mut() {
+            for chunk_key in removed.difference(&added) {
+                member.forget_chunk(chunk_key.borrow());
+            }
+
+            member.resize(chunk_storages.len());
+        }
+
+        for idx in dirty {
+            let chunk = &chunk_storages[idx];
+
+            for member in self.members.iter_mut() {
+                member.update_chunk(idx, chunk.chunk_key(), chunk.internal_rvec());
+            }
+        }
+    }
+}
+
+impl<ChunkKey, Element, Summary> GroupMember<ChunkKey, Element>
+    for Member<ChunkKey, Element, Summary>
+where
+    ChunkKey: BorrowedKey + ?Sized + 'static,
+    ChunkKey::Owned: ValidKey,
+    Element: 'static,
+    Summary: Default + Clone + 'static,
+{
+    fn resize(&mut self, chunk_count: usize) {
+        while self.chunkwise_summaries.len() > chunk_count {
+            self.chunkwise_summaries
+                .swap_remove(self.chunkwise_summaries.len() - 1);
+        }
+
+        while self.chunkwise_summaries.len() < chunk_count {
+            self.chunkwise_summaries.push(Summary::default());
+        }
+    }
+
+    fn update_chunk(&mut self, idx: usize, chunk_key: &ChunkKey, internal_storage: &RVec<Element>) {
+        let group_size = self.group_size;
+        let rules = &self.rules;
+
+        self.chunkwise_summaries[idx] = self
+            .chunkwise_reductions
+            .entry(chunk_key.to_owned())
+            .or_insert_with(|| Reduce::new(internal_storage, group_size, rules.clone()))
+            .update(internal_storage)
+            .cloned()
+            .unwrap_or_default();
+    }
+
+    fn forget_chunk(&mut self, chunk_key: &ChunkKey) {
+        self.chunkwise_reductions.remove(chunk_key);
+    }
+
+    fn clear(&mut self) {
+        self.chunkwise_reductions.clear();
+        self.chunkwise_summaries = RVec::default();
+        self.reduction = Reduce::new(
+            &self.chunkwise_summaries,
+            self.group_size,
+            self.reduction_rules.clone(),
+        );
+    }
+
+    fn memory_usage(&self) -> MemoryUsage {
+        let mut result = self.chunkwise_summaries.memory_usage();
+        result = MemoryUsage::merge(result, self.reduction.memory_usage());
+
+        for reduction in self.chunkwise_reductions.values() {
+            result = MemoryUsage::merge(result, reduction.memory_usage());
+        }
+
+        result
+    }
+
+    fn shrink_with(&mut self, f: &dyn Fn(&MemoryUsage) -> Option<usize>) {
+        self.chunkwise_summaries.shrink_with(f);
+        self.reduction.shrink_with(f);
+
+        for reduction in self.chunkwise_reductions.values_mut() {
+            reduction.shrink_with(f);
+        }
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl<ChunkKey, Element> MemoryUser for ReductionGroup<ChunkKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+{
+    fn memory_usage(&self) -> MemoryUsage {
+        let mut result = self.chunk_list.memory_usage();
+
+        for member in self.members.iter() {
+            result = MemoryUsage::merge(result, member.memory_usage());
+        }
+
+        result
+    }
+
+    fn shrink_with<F: Fn(&MemoryUsage) -> Option<usize>>(&mut self, f: F) {
+        self.chunk_list.shrink_with(&f);
+
+        for member in self.members.iter_mut() {
+            member.shrink_with(&f);
+        }
+    }
+}
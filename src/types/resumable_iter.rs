@@ -0,0 +1,165 @@
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::storage::Storage;
+use std::borrow::Borrow;
+use std::collections::HashMap;
+
+/// A scan over every element of a `Storage` that can be paused and resumed. Construct one
+/// using `Storage::iter_resumable`.
+///
+/// A `ResumableIter` remembers only the keys of the last element it visited in each chunk,
+/// so it never borrows the `Storage` between calls and the `Storage` may be freely modified
+/// while the scan is paused. Each call to `next_element` makes progress: it never returns
+/// an element that has already been visited.
+///
+/// Chunks are visited in order of chunk key, and the elements of each chunk in order of
+/// item key. Elements that are added during the scan are visited if they belong to a chunk
+/// that the scan has not yet reached, or if they belong to the current chunk and have a
+/// greater item key than the last element visited. Elements added to a chunk that has
+/// already been visited are skipped, unless `include_additions` is enabled.
+///
+/// Finding the next element of a chunk scans every item key of that chunk, unless the
+/// `Storage` keeps its item keys in order (see `StorageBuilder::ordered_item_keys`).
+#[derive(Clone, Debug)]
+pub struct ResumableIter<ChunkKey, ItemKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+{
+    storage_id: u64,
+    include_additions: bool,
+    // chunks waiting to be visited, largest chunk key first
+    pending: Vec<ChunkKey::Owned>,
+    current: Option<ChunkKey::Owned>,
+    // the last item key visited in each chunk that has been reached
+    visited: HashMap<ChunkKey::Owned, Option<ItemKey::Owned>>,
+}
+
+impl<ChunkKey, ItemKey> ResumableIter<ChunkKey, ItemKey>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+{
+    pub(crate) fn new(storage_id: u64) -> Self {
+        ResumableIter {
+            storage_id,
+            include_additions: false,
+            pending: Vec::new(),
+            current: None,
+            visited: HashMap::new(),
+        }
+    }
+
+    /// Choose whether elements added to an already-visited chunk will be visited once every
+    /// chunk has been visited. Only elements with a greater item key than any element
+    /// previously visited in that chunk are picked up, which suits chunks whose item keys
+    /// only ever grow, such as timestamps.
+    ///
+    /// If elements are added faster than they are visited, a scan that includes additions
+    /// may never finish.
+    pub fn include_additions(mut self, include_additions: bool) -> Self {
+        self.include_additions = include_additions;
+        self
+    }
+
+    /// Visit the next element, or return `None` if the scan is finished.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the given `Storage` is not the `Storage` (or a clone of the `Storage`)
+    /// that began this scan.
+    pub fn next_element<'a, Element>(
+        &mut self,
+        storage: &'a Storage<ChunkKey, ItemKey, Element>,
+    ) -> Option<&'a Element>
+    where
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        assert_eq!(
+            self.storage_id,
+            storage.id(),
+            "Id mismatch: a ResumableIter may only be used with the Storage that began it"
+        );
+
+        loop {
+            if let Some(chunk_key) = self.current.as_ref() {
+                let last_item_key = self
+                    .visited
+                    .get_mut(chunk_key.borrow())
+                    .expect("the current chunk should always have been visited");
+                let chunk = storage
+                    .internal_idx_of(chunk_key.borrow())
+                    .map(|idx| &storage.internal_rvec()[idx]);
+
+                if let Some(chunk) = chunk {
+                    if let Some(item_key) =
+                        chunk.next_item_key(last_item_key.as_ref().map(Borrow::borrow))
+                    {
+                        *last_item_key = Some(item_key.to_owned());
+                        let idx = chunk
+                            .internal_idx_of(item_key)
+                            .expect("ordered index should agree with index");
+                        return Some(chunk.get_idx(idx));
+                    }
+                }
+
+                self.current = None;
+            }
+
+            match self.pending.pop() {
+                Some(chunk_key) => {
+                    self.visited.entry(chunk_key.clone()).or_insert(None);
+                    self.current = Some(chunk_key);
+                }
+                None => {
+                    if !self.refill(storage) {
+                        return None;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Resume this scan, borrowing the given `Storage` until the returned `Iterator` is
+    /// dropped. Use `Iterator::take` to visit a limited number of elements before pausing
+    /// again.
+    pub fn resume<'a, Element>(
+        &'a mut self,
+        storage: &'a Storage<ChunkKey, ItemKey, Element>,
+    ) -> impl Iterator<Item = &'a Element> + 'a
+    where
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        std::iter::from_fn(move || self.next_element(storage))
+    }
+
+    // Find the chunks that still need to be visited. Returns false if there are none.
+    fn refill<Element>(&mut self, storage: &Storage<ChunkKey, ItemKey, Element>) -> bool
+    where
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        let visited = &self.visited;
+        let include_additions = self.include_additions;
+
+        self.pending = storage
+            .chunk_keys()
+            .into_iter()
+            .filter(|chunk_key| match visited.get(*chunk_key) {
+                None => true,
+                Some(last_item_key) => {
+                    include_additions
+                        && storage.max_item_key(chunk_key)
+                            > last_item_key.as_ref().map(Borrow::borrow)
+                }
+            })
+            .map(ToOwned::to_owned)
+            .collect();
+        self.pending.sort_unstable_by(|a, b| b.cmp(a));
+
+        !self.pending.is_empty()
+    }
+}
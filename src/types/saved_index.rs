@@ -0,0 +1,67 @@
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::chunk_storage::ChunkStorage;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// A copy of the contents of a `SecondaryIndex`, taken using `SecondaryIndex::save`. Serialize
+/// it with any serde format, and later pass it to `SecondaryIndex::restore` to skip re-indexing
+/// every chunk that hasn't changed in the meantime.
+///
+/// Each chunk is saved with a fingerprint of its elements. A chunk is only restored if its
+/// fingerprint still matches, so a chunk that has changed, or a fingerprint that is computed
+/// differently by a new version of rust, just means that chunk is re-indexed as usual.
+///
+/// # Type Parameters
+///
+/// * `ChunkKey`: The owned chunk key of the `Storage`.
+/// * `ItemKey`: The owned item key of the `Storage`.
+/// * `IndexKeys`: The collection of index keys of the `SecondaryIndex`.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SavedIndex<ChunkKey, ItemKey, IndexKeys> {
+    pub(crate) chunks: Vec<SavedChunkIndex<ChunkKey, ItemKey, IndexKeys>>,
+}
+
+/// The saved contents of a `SecondaryIndex` for a single chunk.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub(crate) struct SavedChunkIndex<ChunkKey, ItemKey, IndexKeys> {
+    pub(crate) chunk_key: ChunkKey,
+    pub(crate) fingerprint: u64,
+    // the index keys of every element that has any
+    pub(crate) index_keys: Vec<(ItemKey, IndexKeys)>,
+}
+
+impl<ChunkKey, ItemKey, IndexKeys> SavedIndex<ChunkKey, ItemKey, IndexKeys> {
+    /// The number of chunks saved.
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// True IFF no chunks were saved.
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+}
+
+/// A fingerprint of every element of a chunk, regardless of their order.
+pub(crate) fn fingerprint<ChunkKey, ItemKey, Element>(
+    chunk_storage: &ChunkStorage<ChunkKey, ItemKey, Element>,
+) -> u64
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey> + Hash,
+{
+    chunk_storage
+        .raw()
+        .iter()
+        .map(|element| {
+            let mut hasher = DefaultHasher::new();
+            element.hash(&mut hasher);
+            hasher.finish()
+        })
+        .fold(0, u64::wrapping_add)
+}
@@ -0,0 +1,37 @@
+use serde::{Deserialize, Serialize};
+
+/// A copy of the summary of each chunk of a `Reduction`, taken using `Reduction::save`.
+/// Serialize it with any serde format, and later pass it to `Reduction::restore` to skip
+/// re-summarizing every chunk that hasn't changed in the meantime.
+///
+/// Like a `SavedIndex`, each chunk is saved with a fingerprint of its elements, and a chunk is
+/// only restored if its fingerprint still matches.
+///
+/// # Type Parameters
+///
+/// * `ChunkKey`: The owned chunk key of the `Storage`.
+/// * `Summary`: The summary type of the `Reduction`.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct SavedReduction<ChunkKey, Summary> {
+    pub(crate) chunks: Vec<SavedChunkSummary<ChunkKey, Summary>>,
+}
+
+/// The saved summary of a `Reduction` for a single chunk.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub(crate) struct SavedChunkSummary<ChunkKey, Summary> {
+    pub(crate) chunk_key: ChunkKey,
+    pub(crate) fingerprint: u64,
+    pub(crate) summary: Summary,
+}
+
+impl<ChunkKey, Summary> SavedReduction<ChunkKey, Summary> {
+    /// The number of chunks saved.
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// True IFF no chunks were saved.
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+}
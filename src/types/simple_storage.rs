@@ -0,0 +1,200 @@
+use crate::traits::valid_key::ValidKey;
+use crate::types::id::ID;
+use crate::types::iter::Iter;
+use crate::types::storage::Storage;
+use std::iter::{FromIterator, Map};
+use std::ops::Index;
+
+/// A map from keys to values, backed by a single-chunk `Storage`, that implements the usual
+/// container traits of the standard library. This lets generic code written against
+/// `HashMap`-like interfaces accept a retriever-backed storage with few changes.
+///
+/// Use `SimpleStorage::as_storage` to run `Queries`, or to attach `SecondaryIndexes` and
+/// `Reductions`, exactly as for any other `Storage`.
+///
+/// # Example
+///
+/// ```
+/// use retriever::prelude::*;
+/// use retriever::types::simple_storage::SimpleStorage;
+///
+/// let mut inventory : SimpleStorage<&'static str, u64> =
+///   vec![("apples", 3), ("pears", 5)].into_iter().collect();
+///
+/// assert_eq!(Some(3), inventory.insert("apples", 4));
+/// inventory.extend(vec![("plums", 0)]);
+///
+/// assert_eq!(4, inventory[&"apples"]);
+/// assert_eq!(3, inventory.len());
+/// assert_eq!(9, (&inventory).into_iter().map(|(_, count)| count).sum::<u64>());
+///
+/// assert_eq!(Some(0), inventory.remove(&"plums"));
+/// assert!(!inventory.contains_key(&"plums"));
+///
+/// // The backing Storage supports everything that any other Storage does.
+/// assert_eq!(1, inventory.as_storage().query(Everything.filter(|x: &(&str, u64)| x.1 > 4)).count());
+/// ```
+pub struct SimpleStorage<K, V>
+where
+    K: ValidKey,
+{
+    storage: Storage<(), K, (K, V)>,
+}
+
+impl<K, V> SimpleStorage<K, V>
+where
+    K: ValidKey,
+{
+    /// Construct a new, empty `SimpleStorage`.
+    pub fn new() -> Self {
+        SimpleStorage {
+            storage: Storage::new(),
+        }
+    }
+
+    /// The number of entries.
+    pub fn len(&self) -> usize {
+        self.storage.iter().len()
+    }
+
+    /// True IFF there are no entries.
+    pub fn is_empty(&self) -> bool {
+        self.storage.iter().next().is_none()
+    }
+
+    /// Get the value of the given key.
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.storage.get(&ID.item(key)).map(|(_, v)| v)
+    }
+
+    /// True IFF there is an entry with the given key.
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Set the value of the given key, returning the previous value, if any.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let previous = self.remove(&key);
+        self.storage.add((key, value));
+        previous
+    }
+
+    /// Remove the entry with the given key, returning its value, if any.
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        self.storage.entry(ID.item(key)).remove().map(|(_, v)| v)
+    }
+
+    /// Iterate over every entry, in no particular order.
+    pub fn iter(&self) -> SimpleIter<'_, K, V> {
+        self.storage.iter().map(split_entry)
+    }
+
+    /// Iterate over every key, in no particular order.
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.iter().map(|(k, _)| k)
+    }
+
+    /// Iterate over every value, in no particular order.
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.iter().map(|(_, v)| v)
+    }
+
+    /// The backing `Storage`.
+    pub fn as_storage(&self) -> &Storage<(), K, (K, V)> {
+        &self.storage
+    }
+
+    /// Convert this `SimpleStorage` into its backing `Storage`.
+    pub fn into_storage(self) -> Storage<(), K, (K, V)> {
+        self.storage
+    }
+}
+
+fn split_entry<K, V>((k, v): &(K, V)) -> (&K, &V) {
+    (k, v)
+}
+
+/// An iterator over the entries of a `SimpleStorage`.
+pub type SimpleIter<'a, K, V> = Map<Iter<'a, (), K, (K, V)>, fn(&'a (K, V)) -> (&'a K, &'a V)>;
+
+impl<K, V> Default for SimpleStorage<K, V>
+where
+    K: ValidKey,
+{
+    fn default() -> Self {
+        SimpleStorage::new()
+    }
+}
+
+impl<K, V> Clone for SimpleStorage<K, V>
+where
+    K: ValidKey,
+    V: Clone,
+{
+    fn clone(&self) -> Self {
+        SimpleStorage {
+            storage: self.storage.clone(),
+        }
+    }
+}
+
+impl<K, V> From<Storage<(), K, (K, V)>> for SimpleStorage<K, V>
+where
+    K: ValidKey,
+{
+    fn from(storage: Storage<(), K, (K, V)>) -> Self {
+        SimpleStorage { storage }
+    }
+}
+
+impl<K, V> Extend<(K, V)> for SimpleStorage<K, V>
+where
+    K: ValidKey,
+{
+    fn extend<I>(&mut self, i: I)
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        for (k, v) in i {
+            self.insert(k, v);
+        }
+    }
+}
+
+impl<K, V> FromIterator<(K, V)> for SimpleStorage<K, V>
+where
+    K: ValidKey,
+{
+    fn from_iter<I>(i: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut result = SimpleStorage::new();
+        result.extend(i);
+        result
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a SimpleStorage<K, V>
+where
+    K: ValidKey,
+{
+    type Item = (&'a K, &'a V);
+    type IntoIter = SimpleIter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<K, V> Index<&K> for SimpleStorage<K, V>
+where
+    K: ValidKey,
+{
+    type Output = V;
+
+    fn index(&self, key: &K) -> &V {
+        self.get(key)
+            .expect("retriever: no entry with the given key")
+    }
+}
@@ -0,0 +1,39 @@
+use crate::types::id::Id;
+
+/// A report of unusually large elements and chunks, returned by `Storage::size_outliers`.
+///
+/// All sizes are in whatever unit the sizer function measures, usually bytes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SizeOutliers<ChunkKey, ItemKey> {
+    /// The largest of the sampled elements, largest first.
+    pub largest_elements: Vec<ElementSize<ChunkKey, ItemKey>>,
+    /// The chunks with the largest estimated total size, largest first.
+    pub largest_chunks: Vec<ChunkSize<ChunkKey>>,
+    /// The number of elements that were measured.
+    pub sampled: usize,
+    /// The number of elements in the `Storage`.
+    pub total: usize,
+}
+
+/// The measured size of a single element.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ElementSize<ChunkKey, ItemKey> {
+    /// The `Id` of the element.
+    pub id: Id<ChunkKey, ItemKey>,
+    /// The measured size of the element.
+    pub size: usize,
+}
+
+/// The estimated size of a chunk, extrapolated from a sample of its elements.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChunkSize<ChunkKey> {
+    /// The chunk key of the chunk.
+    pub chunk_key: ChunkKey,
+    /// The number of elements in the chunk.
+    pub len: usize,
+    /// The estimated total size of every element in the chunk.
+    pub estimated_size: usize,
+    /// The estimated size of this chunk divided by the average estimated size of all chunks.
+    /// A well-balanced `Storage` has a skew close to 1.0 for every chunk.
+    pub skew: f64,
+}
@@ -0,0 +1,73 @@
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::storage::Storage;
+use std::ops::Deref;
+
+/// A frozen, consistent view of a `Storage` as it was at one moment, that stays valid and
+/// unchanged while the original `Storage` continues to be modified. Construct one using
+/// `Storage::snapshot`.
+///
+/// A `Snapshot` dereferences to a read-only `Storage`, so every query works exactly as it would
+/// have on the original at the moment the `Snapshot` was taken. It shares its chunks with the
+/// original, so taking a `Snapshot` doesn't copy any `Elements`; the original copies a chunk
+/// the first time it modifies that chunk while any `Snapshot` still holds it.
+///
+/// Like a clone of a `Storage`, a `Snapshot` keeps the identity of the original, so the
+/// `SecondaryIndexes` and `Reductions` of the original work with it too. Moving an index back
+/// and forth between the original and a `Snapshot` re-indexes the chunks that differ between
+/// them each time.
+pub struct Snapshot<ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+{
+    storage: Storage<ChunkKey, ItemKey, Element>,
+}
+
+impl<ChunkKey, ItemKey, Element> Snapshot<ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+{
+    pub(crate) fn new(storage: Storage<ChunkKey, ItemKey, Element>) -> Self {
+        Snapshot { storage }
+    }
+
+    /// Turn this `Snapshot` into an independent `Storage` that can be modified without
+    /// affecting the original.
+    pub fn into_storage(self) -> Storage<ChunkKey, ItemKey, Element> {
+        self.storage
+    }
+}
+
+impl<ChunkKey, ItemKey, Element> Deref for Snapshot<ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+{
+    type Target = Storage<ChunkKey, ItemKey, Element>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.storage
+    }
+}
+
+impl<ChunkKey, ItemKey, Element> Clone for Snapshot<ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Clone,
+{
+    fn clone(&self) -> Self {
+        Snapshot {
+            storage: self.storage.clone(),
+        }
+    }
+}
@@ -1,21 +1,69 @@
+use super::budget::Budget;
+use super::cas_result::CasResult;
 use super::chunk_storage::*;
+use super::cursor::{Cursor, Page};
+use super::dedup_window::{Dedup, DedupStats, DedupWindow};
 use super::entry::Entry;
+use super::error::{
+    AddChunkError, ChunkCollisionError, ChunkMismatchError, DuplicateItemError, InvalidCursorError,
+    ValidationError,
+};
+use super::export_record::ExportRecord;
+use super::id::Id;
+use super::iter::Iter;
+use super::pinned_chunk::PinnedChunk;
+use super::query_mut::QueryMut;
+use super::resumable_iter::ResumableIter;
+#[cfg(feature = "diagnostics")]
+use super::size_profile::{ChunkSize, ElementSize, SizeOutliers};
+use super::snapshot::Snapshot;
+use super::storage_builder::Strictness;
+use crate::bits::Bitset;
+use crate::internal::bounds::is_valid_range;
+use crate::internal::generation::{current_generation, next_generation};
 use crate::internal::hasher::HasherImpl;
+use crate::internal::merge::KWayMerge;
 use crate::internal::mr::rvec::RVec;
+#[cfg(feature = "debug_borrows")]
+use crate::internal::pins::PinRegistry;
+#[cfg(feature = "rand")]
+use crate::internal::sample::Reservoir;
+use crate::queries::counting_index::CountingIndex;
+use crate::queries::secondary_index::{KeySet, SecondaryIndex};
 use crate::traits::idxset::IdxSet;
 use crate::traits::memory_usage::{MemoryUsage, MemoryUser};
 use crate::traits::query::Query;
 use crate::traits::record::Record;
 use crate::traits::valid_key::{BorrowedKey, ValidKey};
 use crate::types::editor::Editor;
+#[cfg(feature = "rayon")]
+use crate::types::parallelism::Parallelism;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
 use std::borrow::Borrow;
-use std::collections::{HashMap, HashSet};
-use std::hash::Hash;
+use std::borrow::Cow;
+use std::cmp;
+use std::collections::hash_map::Entry as HashEntry;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::Debug;
+use std::hash::{BuildHasher, Hash};
+use std::ops::{AddAssign, Bound, Index, RangeBounds};
 use std::sync::atomic::AtomicU64;
 use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Instant;
 
 static ID_COUNTER: AtomicU64 = AtomicU64::new(0);
 
+/// The most elements `Storage::size_outliers` will measure in any one chunk.
+#[cfg(feature = "diagnostics")]
+const SIZE_SAMPLES_PER_CHUNK: usize = 256;
+
+/// A rule that brings an eagerly-maintained index up to date with the given chunks of a `Storage`,
+/// returning false once the index has been dropped.
+pub(crate) type Maintainer<ChunkKey, ItemKey, Element> =
+    Arc<dyn Fn(&Storage<ChunkKey, ItemKey, Element>, &Bitset) -> bool + Send + Sync>;
+
 /// Chunked, indexed storage.
 ///
 /// # Type Parameters
@@ -26,7 +74,19 @@ static ID_COUNTER: AtomicU64 = AtomicU64::new(0);
 /// * `ItemKey`: each `Element` is a `Record` that has exactly one `ItemKey`. Every `Element`
 ///   within a chunk must have an `ItemKey` that is unique to that chunk.
 /// * `Element`: the type contained in this `Storage`.
-#[derive(Clone)]
+///
+/// # Cloning
+///
+/// Cloning a `Storage` is cheap: chunks are shared between the original and the clone, and a
+/// chunk is only copied the first time either copy modifies it. This makes it practical to
+/// take a snapshot of a large `Storage` as often as every frame. A `Reduction` of the original
+/// also stays incremental when used with the clone, only revisiting what changed since.
+///
+/// # Strictness
+///
+/// By default, a `Storage` panics when one of its invariants would be violated, unless you use a
+/// `try_` method that returns an error instead. Use a `StorageBuilder` to choose a different
+/// `Strictness`.
 pub struct Storage<ChunkKey: ?Sized, ItemKey: ?Sized, Element>
 where
     ChunkKey: BorrowedKey,
@@ -35,9 +95,27 @@ where
     ItemKey::Owned: ValidKey,
 {
     id: u64,
-    chunks: RVec<ChunkStorage<ChunkKey, ItemKey, Element>>,
+    strictness: Strictness,
+    #[cfg(feature = "rayon")]
+    parallelism: Parallelism,
+    chunks: RVec<Arc<ChunkStorage<ChunkKey, ItemKey, Element>>>,
     dirty: Vec<usize>,
     index: HashMap<ChunkKey::Owned, usize, HasherImpl>,
+    // the same chunk keys as the index, but in order, if enabled
+    ordered_index: Option<BTreeSet<ChunkKey::Owned>>,
+    // whether each chunk keeps its item keys in order
+    ordered_item_keys: bool,
+    // the chunk key of every item key, if enabled
+    item_index: Option<HashMap<ItemKey::Owned, Vec<ChunkKey::Owned>, HasherImpl>>,
+    // rules bringing each eagerly-maintained index up to date with some chunks
+    maintainers: Vec<Maintainer<ChunkKey, ItemKey, Element>>,
+    // the ids of recently added elements, if enabled
+    dedup: Option<Dedup<ChunkKey::Owned, ItemKey::Owned>>,
+    // the outstanding PinnedChunks of this Storage
+    #[cfg(feature = "debug_borrows")]
+    pins: PinRegistry,
+    // the generation at which a chunk last moved to a different position
+    generation: u64,
 }
 
 impl<ChunkKey, ItemKey, Element> Storage<ChunkKey, ItemKey, Element>
@@ -79,9 +157,20 @@ where
     pub fn new() -> Self {
         Storage {
             id: ID_COUNTER.fetch_add(1, Ordering::Relaxed),
+            strictness: Strictness::default(),
+            #[cfg(feature = "rayon")]
+            parallelism: Parallelism::default(),
             chunks: RVec::default(),
             dirty: Vec::default(),
             index: HashMap::with_hasher(crate::internal::hasher::HasherImpl::default()),
+            ordered_index: None,
+            ordered_item_keys: false,
+            item_index: None,
+            maintainers: Vec::new(),
+            dedup: None,
+            #[cfg(feature = "debug_borrows")]
+            pins: PinRegistry::default(),
+            generation: 0,
         }
     }
 
@@ -89,6 +178,180 @@ where
         self.id
     }
 
+    /// How this `Storage` responds when one of its invariants would be violated.
+    pub fn strictness(&self) -> Strictness {
+        self.strictness
+    }
+
+    pub(crate) fn set_strictness(&mut self, strictness: Strictness) {
+        self.strictness = strictness;
+    }
+
+    /// True IFF this `Storage` keeps its chunk keys in order, to speed up `ChunkRange` queries.
+    pub fn has_ordered_chunk_keys(&self) -> bool {
+        self.ordered_index.is_some()
+    }
+
+    pub(crate) fn set_ordered_chunk_keys(&mut self, ordered_chunk_keys: bool) {
+        self.ordered_index = if ordered_chunk_keys {
+            Some(self.index.keys().cloned().collect())
+        } else {
+            None
+        };
+    }
+
+    /// True IFF each chunk of this `Storage` keeps its item keys in order, to speed up
+    /// `ItemRange`, `After` and resumable iteration.
+    pub fn has_ordered_item_keys(&self) -> bool {
+        self.ordered_item_keys
+    }
+
+    pub(crate) fn set_ordered_item_keys(&mut self, ordered_item_keys: bool) {
+        self.ordered_item_keys = ordered_item_keys;
+        for idx in 0..self.chunks.len() {
+            if self.chunks[idx].has_ordered_item_keys() != ordered_item_keys {
+                self.chunk_mut(idx).set_ordered_item_keys(ordered_item_keys);
+            }
+        }
+    }
+
+    /// True IFF this `Storage` keeps a map from each item key to the chunk keys of every chunk
+    /// holding it, to speed up `Storage::find`.
+    pub fn has_item_key_index(&self) -> bool {
+        self.item_index.is_some()
+    }
+
+    pub(crate) fn set_item_key_index(&mut self, item_key_index: bool) {
+        self.item_index = None;
+
+        if item_key_index {
+            self.item_index = Some(HashMap::with_hasher(HasherImpl::default()));
+            for idx in 0..self.chunks.len() {
+                self.index_items(idx, 0);
+            }
+        }
+    }
+
+    /// Record the chunk key of every element of the chunk at the given index, starting from the
+    /// given item index, if this `Storage` has an item key index.
+    fn index_items(&mut self, idx: usize, from: usize) {
+        if let Some(item_index) = self.item_index.as_mut() {
+            let chunk = &self.chunks[idx];
+            let chunk_key = chunk.chunk_key();
+            for element in &chunk.raw()[from..] {
+                let chunk_keys = item_index
+                    .entry(element.item_key().into_owned())
+                    .or_default();
+                if !chunk_keys.iter().any(|c| c.borrow() == chunk_key) {
+                    chunk_keys.push(chunk_key.to_owned());
+                }
+            }
+        }
+    }
+
+    /// Record the chunk key of every element added to the given chunk since it had the given
+    /// length, if this `Storage` has an item key index.
+    fn index_new_items(&mut self, chunk_key: &ChunkKey, from: usize) {
+        if self.item_index.is_none() {
+            return;
+        }
+
+        if let Some(idx) = self.internal_idx_of(chunk_key) {
+            self.index_items(idx, from);
+        }
+    }
+
+    /// Forget that the given item keys belong to the given chunk, leaving any other chunks that
+    /// hold the same item keys indexed.
+    fn unindex_items<I, K>(&mut self, chunk_key: &ChunkKey, item_keys: I)
+    where
+        I: IntoIterator<Item = K>,
+        K: Borrow<ItemKey>,
+    {
+        if let Some(item_index) = self.item_index.as_mut() {
+            for item_key in item_keys {
+                let item_key = item_key.borrow();
+                if let Some(chunk_keys) = item_index.get_mut(item_key) {
+                    chunk_keys.retain(|c| c.borrow() != chunk_key);
+                    if chunk_keys.is_empty() {
+                        item_index.remove(item_key);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Bring an index up to date with every write to this `Storage`, until the index is dropped.
+    pub(crate) fn attach_maintainer(&mut self, maintainer: Maintainer<ChunkKey, ItemKey, Element>) {
+        self.maintainers.push(maintainer);
+    }
+
+    /// Bring every eagerly-maintained index up to date with the given chunks.
+    fn maintain(&mut self, idxs: &Bitset) {
+        if self.maintainers.is_empty() {
+            return;
+        }
+
+        let mut maintainers = std::mem::take(&mut self.maintainers);
+        maintainers.retain(|maintainer| maintainer(self, idxs));
+        maintainers.append(&mut self.maintainers);
+        self.maintainers = maintainers;
+    }
+
+    /// Bring every eagerly-maintained index up to date with the given chunk.
+    fn maintain_chunk(&mut self, chunk_key: &ChunkKey) {
+        if self.maintainers.is_empty() {
+            return;
+        }
+
+        if let Some(idx) = self.internal_idx_of(chunk_key) {
+            self.maintain(&std::iter::once(idx).collect());
+        }
+    }
+
+    pub(crate) fn set_dedup_window(&mut self, dedup_window: Option<DedupWindow>) {
+        self.dedup = dedup_window.map(Dedup::new);
+    }
+
+    /// How many elements this `Storage`'s `DedupWindow` has admitted and dropped, or `None` if
+    /// it has no `DedupWindow`. Use a `StorageBuilder` to choose a `DedupWindow`.
+    pub fn dedup_stats(&self) -> Option<DedupStats> {
+        self.dedup.as_ref().map(Dedup::stats)
+    }
+
+    /// False IFF this record should be dropped because it was ingested within the `DedupWindow`.
+    fn admit<R>(&mut self, record: &R) -> bool
+    where
+        R: Record<ChunkKey, ItemKey>,
+    {
+        match self.dedup.as_mut() {
+            Some(dedup) => dedup.admit(Id::cloned(record)),
+            None => true,
+        }
+    }
+
+    /// Thresholds deciding when a query on this `Storage` is large enough to run in parallel.
+    #[cfg(feature = "rayon")]
+    pub fn parallelism(&self) -> Parallelism {
+        self.parallelism
+    }
+
+    /// Change the thresholds deciding when a query on this `Storage` is large enough to run in
+    /// parallel.
+    #[cfg(feature = "rayon")]
+    pub fn set_parallelism(&mut self, parallelism: Parallelism) {
+        self.parallelism = parallelism;
+    }
+
+    /// True IFF a query visiting the given chunks should run in parallel.
+    #[cfg(feature = "rayon")]
+    pub(crate) fn is_parallel(&self, chunk_idxs: &[usize]) -> bool {
+        let total_len: usize = chunk_idxs.iter().map(|idx| self.chunks[*idx].len()).sum();
+        let average_chunk_len = total_len.checked_div(chunk_idxs.len()).unwrap_or(0);
+        self.parallelism
+            .is_parallel(chunk_idxs.len(), average_chunk_len)
+    }
+
     /// Get the ChunkStorage corresponding the given ChunkKey.
     fn chunk(
         &mut self,
@@ -100,7 +363,13 @@ where
         } else {
             let new_idx = self.chunks.len();
             self.index.insert(chunk_key.to_owned(), new_idx);
-            self.chunks.push(ChunkStorage::new(chunk_key.to_owned()));
+            if let Some(ordered_index) = self.ordered_index.as_mut() {
+                ordered_index.insert(chunk_key.to_owned());
+            }
+            self.chunks.push(Arc::new(ChunkStorage::new(
+                chunk_key.to_owned(),
+                self.ordered_item_keys,
+            )));
             new_idx
         };
 
@@ -108,7 +377,22 @@ where
             self.dirty(idx);
         }
 
-        &mut self.chunks[idx]
+        self.chunk_mut(idx)
+    }
+
+    /// Get mutable access to the ChunkStorage at the given index, first copying it if it is
+    /// shared with a clone of this Storage.
+    fn chunk_mut(&mut self, idx: usize) -> &mut ChunkStorage<ChunkKey, ItemKey, Element> {
+        #[cfg(feature = "debug_borrows")]
+        self.pins.modify(Arc::as_ptr(&self.chunks[idx]));
+
+        let chunk = &mut self.chunks[idx];
+
+        if Arc::get_mut(chunk).is_none() {
+            *chunk = Arc::new(chunk.unshare());
+        }
+
+        Arc::get_mut(chunk).expect("retriever bug: chunk should be unshared")
     }
 
     /// Add the given element to this Storage.
@@ -168,20 +452,85 @@ where
     /// # storage.validate();
     /// ```
     pub fn add(&mut self, element: Element) -> &mut Self {
+        if !self.admit(&element) {
+            return self;
+        }
+
         self.clean();
 
-        let chunk_key = element.chunk_key();
-        let chunk_key_ref = chunk_key.borrow();
-        self.chunk(chunk_key_ref, false).add(element);
+        let repair = self.strictness == Strictness::Repair;
+        let chunk_key = element.chunk_key().into_owned();
+        let chunk = self.chunk(chunk_key.borrow(), false);
+        let len = chunk.len();
+
+        if repair {
+            if chunk.replace(element).is_some() {
+                #[cfg(feature = "log")]
+                log::warn!("retriever: repaired duplicate item key by replacing the old element");
+            }
+        } else {
+            chunk.add(element);
+        }
+
+        self.index_new_items(chunk_key.borrow(), len);
+        self.maintain_chunk(chunk_key.borrow());
 
         self
     }
 
+    /// Add the given element to this Storage, without panicking if an element with the same
+    /// `Id` is already present.
+    ///
+    /// If this `Storage`'s `Strictness` is `Error` (the default), the duplicate element is
+    /// returned and the `Storage` is unchanged. If it is `Repair`, the old element is replaced.
+    /// If it is `Panic`, this method panics just like `Storage::add`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    ///
+    /// let mut storage : Storage<u64, u64, (u64, u64, &'static str)> = Storage::new();
+    ///
+    /// assert!(storage.try_add((1, 1, "hello")).is_ok());
+    ///
+    /// let error = storage.try_add((1, 1, "goodbye")).unwrap_err();
+    /// assert_eq!(error.element, (1, 1, "goodbye"));
+    /// assert_eq!(Some(&(1, 1, "hello")), storage.get(&ID.chunk(1).item(1)));
+    ///
+    /// # storage.validate();
+    /// ```
+    pub fn try_add(&mut self, element: Element) -> Result<(), DuplicateItemError<Element>> {
+        if self.strictness != Strictness::Error {
+            self.add(element);
+            return Ok(());
+        }
+
+        if !self.admit(&element) {
+            return Ok(());
+        }
+
+        self.clean();
+
+        let chunk_key = element.chunk_key().into_owned();
+        let chunk = self.chunk(chunk_key.borrow(), false);
+        let len = chunk.len();
+        chunk
+            .try_add(element)
+            .map_err(|element| DuplicateItemError { element })?;
+
+        self.index_new_items(chunk_key.borrow(), len);
+        self.maintain_chunk(chunk_key.borrow());
+
+        Ok(())
+    }
+
     /// Add some elements that are all part of the same chunk.
     ///
     /// # Panic
     ///
-    /// This method panics if any `Element` does not have the same chunk key as the others.
+    /// This method panics if any `Element` does not have the same chunk key as the others,
+    /// unless this `Storage`'s `Strictness` is `Repair`.
     ///
     pub fn add_chunk<I, K>(&mut self, i: I) -> &mut Self
     where
@@ -191,10 +540,33 @@ where
     {
         self.clean();
 
+        if self.strictness == Strictness::Repair {
+            return self.repair_chunk(i);
+        }
+
+        if self.dedup.is_some() {
+            let admitted: Vec<K> = i.into_iter().filter(|k| self.admit(k)).collect();
+            return self.add_chunk_unchecked(admitted);
+        }
+
+        self.add_chunk_unchecked(i)
+    }
+
+    /// Add some elements that are all part of the same chunk, without any checks.
+    fn add_chunk_unchecked<I, K>(&mut self, i: I) -> &mut Self
+    where
+        I: IntoIterator<Item = K>,
+        Element: Borrow<K>,
+        K: ToOwned<Owned = Element> + Record<ChunkKey, ItemKey>,
+    {
         let mut i = i.into_iter().peekable();
 
-        if let Some(chunk_key_cow) = i.peek().map(|x| x.chunk_key()) {
-            self.chunk(chunk_key_cow.borrow(), false).extend(i);
+        if let Some(chunk_key) = i.peek().map(|x| x.chunk_key().into_owned()) {
+            let chunk = self.chunk(chunk_key.borrow(), false);
+            let len = chunk.len();
+            chunk.extend(i);
+            self.index_new_items(chunk_key.borrow(), len);
+            self.maintain_chunk(chunk_key.borrow());
         }
 
         self
@@ -229,6 +601,220 @@ where
         self
     }
 
+    /// Add some elements that are all part of the same chunk, without panicking if they aren't.
+    ///
+    /// If any `Element` does not have the same chunk key as the first `Element`, or has the same
+    /// item key as an `Element` already present or an earlier `Element` of the group, this method
+    /// returns the offending `Element` and leaves the `Storage` unchanged.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    /// use retriever::types::error::AddChunkError;
+    ///
+    /// let mut storage : Storage<u64, u64, (u64, u64, &'static str)> = Storage::new();
+    ///
+    /// let result = storage.try_add_chunk(vec![
+    ///   (1, 1, "hello"),
+    ///   (1, 2, "world"),
+    ///   (2, 3, "wrong chunk"),
+    /// ]);
+    ///
+    /// match result.unwrap_err() {
+    ///   AddChunkError::ChunkMismatch(error) => {
+    ///     assert_eq!(error.element, (2, 3, "wrong chunk"));
+    ///     assert_eq!(error.position, 2);
+    ///   }
+    ///   error => panic!("unexpected {:?}", error),
+    /// }
+    /// assert_eq!(storage.iter().count(), 0);
+    ///
+    /// assert!(storage.try_add_chunk(vec![(1, 1, "hello"), (1, 2, "world")]).is_ok());
+    /// assert_eq!(storage.iter().count(), 2);
+    ///
+    /// let result = storage.try_add_chunk(vec![(1, 3, "new"), (1, 2, "duplicate")]);
+    /// assert_eq!(&(1, 2, "duplicate"), result.unwrap_err().element());
+    /// assert_eq!(storage.iter().count(), 2);
+    ///
+    /// # storage.validate();
+    /// ```
+    pub fn try_add_chunk<I, K>(&mut self, i: I) -> Result<(), AddChunkError<Element>>
+    where
+        I: IntoIterator<Item = K>,
+        Element: Borrow<K>,
+        K: ToOwned<Owned = Element> + Record<ChunkKey, ItemKey>,
+    {
+        if self.strictness != Strictness::Error {
+            self.add_chunk(i);
+            return Ok(());
+        }
+
+        let elements = Self::check_chunk(i, 0)?;
+        self.try_add_checked_chunks(vec![elements])
+    }
+
+    /// Add many many elements, grouped into chunks, without panicking if any group does not
+    /// share a common chunk key, or if any item key is a duplicate.
+    ///
+    /// Every group is checked before any `Element` is added, so if this method returns an error,
+    /// the `Storage` is unchanged.
+    pub fn try_add_chunks<I, II, K>(&mut self, ii: II) -> Result<(), AddChunkError<Element>>
+    where
+        II: IntoIterator<Item = I>,
+        I: IntoIterator<Item = K>,
+        Element: Borrow<K>,
+        K: ToOwned<Owned = Element> + Record<ChunkKey, ItemKey>,
+    {
+        if self.strictness != Strictness::Error {
+            self.add_chunks(ii);
+            return Ok(());
+        }
+
+        let mut groups = Vec::new();
+
+        for (group, i) in ii.into_iter().enumerate() {
+            groups.push(Self::check_chunk(i, group)?);
+        }
+
+        self.try_add_checked_chunks(groups)
+    }
+
+    /// Add a group of elements one at a time, so that any element with the wrong chunk key
+    /// simply goes to its own chunk.
+    fn repair_chunk<I, K>(&mut self, i: I) -> &mut Self
+    where
+        I: IntoIterator<Item = K>,
+        Element: Borrow<K>,
+        K: ToOwned<Owned = Element> + Record<ChunkKey, ItemKey>,
+    {
+        let mut i = i.into_iter().peekable();
+        let first_chunk_key = i.peek().map(|x| x.chunk_key().into_owned());
+
+        for k in i {
+            if Some(k.chunk_key().borrow()) != first_chunk_key.as_ref().map(Borrow::borrow) {
+                #[cfg(feature = "log")]
+                log::warn!("retriever: repaired element with mismatched chunk key in add_chunk");
+            }
+
+            self.add(k.to_owned());
+        }
+
+        self
+    }
+
+    /// Collect a group of elements, verifying that they all share the same chunk key.
+    fn check_chunk<I, K>(i: I, group: usize) -> Result<Vec<Element>, ChunkMismatchError<Element>>
+    where
+        I: IntoIterator<Item = K>,
+        Element: Borrow<K>,
+        K: ToOwned<Owned = Element> + Record<ChunkKey, ItemKey>,
+    {
+        let mut elements: Vec<Element> = i.into_iter().map(|k| k.to_owned()).collect();
+
+        let mismatch = elements.first().and_then(|first| {
+            let chunk_key = first.chunk_key();
+            elements
+                .iter()
+                .position(|element| element.chunk_key() != chunk_key)
+        });
+
+        if let Some(position) = mismatch {
+            return Err(ChunkMismatchError {
+                element: elements.swap_remove(position),
+                group,
+                position,
+            });
+        }
+
+        Ok(elements)
+    }
+
+    /// Add groups of elements already known to each share the same chunk key, or leave the
+    /// `Storage` unchanged if any item key is a duplicate. Elements dropped by the
+    /// `DedupWindow` are not duplicates.
+    fn try_add_checked_chunks(
+        &mut self,
+        mut groups: Vec<Vec<Element>>,
+    ) -> Result<(), AddChunkError<Element>> {
+        self.clean();
+
+        // Admit against a copy of the window, so that an error leaves it unchanged too.
+        let mut dedup = self.dedup.clone();
+        let mut admitted: Vec<Vec<bool>> = Vec::with_capacity(groups.len());
+        let mut seen = HashSet::new();
+
+        for (group, elements) in groups.iter().enumerate() {
+            let flags: Vec<bool> = elements
+                .iter()
+                .map(|element| match dedup.as_mut() {
+                    Some(dedup) => dedup.admit(Id::cloned(element)),
+                    None => true,
+                })
+                .collect();
+
+            let duplicate = elements
+                .iter()
+                .zip(flags.iter())
+                .position(|(element, admit)| {
+                    *admit && (self.get(element).is_some() || !seen.insert(Id::cloned(element)))
+                });
+
+            if let Some(position) = duplicate {
+                return Err(AddChunkError::DuplicateItem {
+                    element: groups[group].swap_remove(position),
+                    group,
+                    position,
+                });
+            }
+
+            admitted.push(flags);
+        }
+
+        self.dedup = dedup;
+
+        for (elements, flags) in groups.into_iter().zip(admitted) {
+            let elements = elements
+                .into_iter()
+                .zip(flags)
+                .filter_map(|(element, admit)| admit.then_some(element));
+            self.add_checked_chunk(elements);
+        }
+
+        Ok(())
+    }
+
+    /// Add a group of elements already known to share the same chunk key, and to not duplicate
+    /// any item key.
+    fn add_checked_chunk<I>(&mut self, elements: I)
+    where
+        I: Iterator<Item = Element>,
+    {
+        let mut elements = elements.peekable();
+
+        if let Some(chunk_key_cow) = elements.peek().map(|x| x.chunk_key().into_owned()) {
+            let chunk = self.chunk(chunk_key_cow.borrow(), false);
+            let len = chunk.len();
+
+            for element in elements {
+                chunk.add(element);
+            }
+
+            self.index_new_items(chunk_key_cow.borrow(), len);
+            self.maintain_chunk(chunk_key_cow.borrow());
+        }
+    }
+
+    /// The given chunk idxs, in order of chunk key.
+    fn sorted_chunk_idxs<I>(&self, idxs: I) -> Vec<usize>
+    where
+        I: IntoIterator<Item = usize>,
+    {
+        let mut idxs: Vec<usize> = idxs.into_iter().collect();
+        idxs.sort_by(|a, b| self.chunks[*a].chunk_key().cmp(self.chunks[*b].chunk_key()));
+        idxs
+    }
+
     fn clean(&mut self) {
         if self.dirty.is_empty() {
             return;
@@ -237,13 +823,21 @@ where
         self.dirty.sort_unstable();
         self.dirty.dedup();
 
+        // Catch up with any changes made through an Entry since the last write.
+        let dirty: Bitset = self.dirty.iter().cloned().collect();
+        self.maintain(&dirty);
+
         for idx in self.dirty.iter().rev() {
             if !self.chunks[*idx].is_empty() {
                 continue;
             }
 
             self.index.remove(self.chunks[*idx].chunk_key());
+            if let Some(ordered_index) = self.ordered_index.as_mut() {
+                ordered_index.remove(self.chunks[*idx].chunk_key());
+            }
             self.chunks.swap_remove(*idx);
+            self.generation = next_generation();
             if self.chunks.len() > *idx {
                 self.index
                     .insert(self.chunks[*idx].chunk_key().to_owned(), *idx);
@@ -260,12 +854,18 @@ where
     /// Dissolve this Storage into a list of chunks.
     pub fn dissolve(self) -> impl IntoIterator<Item = Vec<Element>> {
         let chunks: Vec<_> = self.chunks.into();
-        chunks.into_iter().map(|chunk| chunk.into())
+        chunks
+            .into_iter()
+            .map(|chunk| ChunkStorage::unwrap_or_unshare(chunk).into())
     }
 
     /// Raw serial access to all element data by reference.
     /// In many cases, you may prefer to use `Storage::iter()` to simply iterate every element.
     ///
+    /// Chunks are visited in order of chunk key. The order of the elements within each chunk
+    /// depends on the history of the chunk, so if you need byte-stable output, use
+    /// `Storage::export_records` instead.
+    ///
     /// You can also use `Storage::dissolve()`, but this consumes the `Storage`.
     ///
     /// # Example
@@ -301,55 +901,139 @@ where
     /// # duplicated_storage.validate();
     /// ```
     pub fn raw(&self) -> impl Iterator<Item = &[Element]> {
-        self.chunks.iter().map(|chunk| chunk.raw())
+        self.sorted_chunk_idxs(0..self.chunks.len())
+            .into_iter()
+            .map(move |idx| self.chunks[idx].raw())
     }
 
-    /// Get an `Element`, if it exists. An `Element` is a `Record` that is uniquely identified
-    /// by the combination of its `ChunkKey` and `ItemKey`.
-    ///
-    /// Returns None if the data element does not exist.
-    ///
-    /// # Type Parameters
+    /// Pin a single chunk, for zero-copy access to its elements that doesn't borrow this
+    /// `Storage`. Returns None if the chunk does not exist.
     ///
-    /// * `R`: Any `Record` with the same `ChunkKey` and `ItemKey` as the record you want to
-    /// access. If there's no obvious choice for `R`, consider using `retriever::types::id::Id`
-    /// to construct an appropriate key.
+    /// While a chunk is pinned, modifying it copies the whole chunk. That is the right thing
+    /// for a snapshot, but for a long-lived consumer that was meant to finish first, it is
+    /// a silent performance bug. With the `debug_borrows` feature, this `Storage` tracks its
+    /// outstanding pins and panics if a pinned chunk would be modified. Ordinary `raw()` and
+    /// `query()` borrows need no tracking, since they already prevent any modification.
     ///
     /// # Example
     ///
     /// ```
     /// use retriever::prelude::*;
-    /// use std::borrow::Cow;
-    ///
-    /// struct Song {
-    ///   playlist_id: usize,
-    ///   song_id: usize,
-    ///   favorite: bool,
-    /// }
     ///
-    /// let mut storage : Storage<usize,usize,Song> = Storage::new();
+    /// let mut storage : Storage<u64, u64, (u64, u64, f32)> = Storage::new();
     ///
-    /// impl Record<usize,usize> for Song {
-    ///   fn chunk_key(&self) -> Cow<usize> {
-    ///     Cow::Owned(self.playlist_id)
-    ///   }
+    /// storage.add((1, 1, 0.5));
+    /// storage.add((1, 2, 1.5));
+    /// storage.add((2, 1, 2.5));
     ///
-    ///   fn item_key(&self) -> Cow<usize> {
-    ///     Cow::Owned(self.song_id)
-    ///   }
-    /// }
+    /// let pinned = storage.pin_chunk(&1).unwrap();
     ///
-    /// storage.add(Song {
-    ///   playlist_id: 1,
-    ///   song_id: 1,
-    ///   favorite: false,
-    /// });
+    /// // The pin doesn't borrow the storage, so other chunks can be modified.
+    /// storage.add((2, 2, 3.5));
     ///
-    /// storage.add(Song {
-    ///   playlist_id: 1,
-    ///   song_id: 2,
-    ///   favorite: true,
-    /// });
+    /// let writer = std::thread::spawn(move || pinned.iter().map(|x| x.2).sum::<f32>());
+    /// assert_eq!(2.0, writer.join().unwrap());
+    ///
+    /// assert!(storage.pin_chunk(&3).is_none());
+    /// # storage.validate();
+    /// ```
+    pub fn pin_chunk(&self, chunk_key: &ChunkKey) -> Option<PinnedChunk<ChunkKey, ItemKey, Element>>
+    where
+        Element: Clone,
+    {
+        let chunk = &self.chunks[self.internal_idx_of(chunk_key)?];
+        chunk.share();
+
+        Some(PinnedChunk::new(
+            Arc::clone(chunk),
+            #[cfg(feature = "debug_borrows")]
+            self.pins.pin(Arc::as_ptr(chunk)),
+        ))
+    }
+
+    /// Take a `Snapshot` of this `Storage`: a frozen, consistent view of its current contents
+    /// that stays unchanged while this `Storage` continues to be modified.
+    ///
+    /// Taking a `Snapshot` copies no `Elements`. Instead, this `Storage` copies a chunk the
+    /// first time it modifies that chunk while a `Snapshot` still holds it, so a long-running
+    /// reader only costs as much memory as the chunks that change while it runs.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    ///
+    /// let mut storage : Storage<u64, u64, (u64, u64, &'static str)> = Storage::new();
+    ///
+    /// storage.add((1, 1, "apple"));
+    /// storage.add((1, 2, "pear"));
+    /// storage.add((2, 1, "plum"));
+    ///
+    /// let snapshot = storage.snapshot();
+    /// let export = std::thread::spawn(move || {
+    ///   snapshot.query(Everything).map(|x| x.2).collect::<Vec<_>>()
+    /// });
+    ///
+    /// storage.modify(&ID.chunk(1).item(1), |mut editor| editor.get_mut().2 = "apricot");
+    /// storage.remove(&ID.chunk(2).item(1), std::mem::drop);
+    /// storage.add((3, 1, "quince"));
+    ///
+    /// assert_eq!(vec!["apple", "pear", "plum"], export.join().unwrap());
+    /// # storage.validate();
+    /// ```
+    pub fn snapshot(&self) -> Snapshot<ChunkKey, ItemKey, Element>
+    where
+        Element: Clone,
+    {
+        Snapshot::new(self.clone())
+    }
+
+    /// Get an `Element`, if it exists. An `Element` is a `Record` that is uniquely identified
+    /// by the combination of its `ChunkKey` and `ItemKey`.
+    ///
+    /// Returns None if the data element does not exist.
+    ///
+    /// # Type Parameters
+    ///
+    /// * `R`: Any `Record` with the same `ChunkKey` and `ItemKey` as the record you want to
+    /// access. If there's no obvious choice for `R`, consider using `retriever::types::id::Id`
+    /// to construct an appropriate key.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    /// use std::borrow::Cow;
+    ///
+    /// struct Song {
+    ///   playlist_id: usize,
+    ///   song_id: usize,
+    ///   favorite: bool,
+    /// }
+    ///
+    /// let mut storage : Storage<usize,usize,Song> = Storage::new();
+    ///
+    /// impl Record<usize,usize> for Song {
+    ///   fn chunk_key(&self) -> Cow<usize> {
+    ///     Cow::Owned(self.playlist_id)
+    ///   }
+    ///
+    ///   fn item_key(&self) -> Cow<usize> {
+    ///     Cow::Owned(self.song_id)
+    ///   }
+    /// }
+    ///
+    /// storage.add(Song {
+    ///   playlist_id: 1,
+    ///   song_id: 1,
+    ///   favorite: false,
+    /// });
+    ///
+    /// storage.add(Song {
+    ///   playlist_id: 1,
+    ///   song_id: 2,
+    ///   favorite: true,
+    /// });
     ///
     /// storage.add(Song {
     ///   playlist_id: 2,
@@ -371,6 +1055,57 @@ where
             .and_then(|idx| self.chunks[idx].get(unique_id))
     }
 
+    /// Get an element knowing only its item key, without its chunk key. If more than one chunk
+    /// has an element with this item key, this returns any one of them.
+    ///
+    /// If this `Storage` was built with `StorageBuilder::item_key_index`, this costs a single
+    /// hash lookup, plus one lookup per chunk that shares the item key. Otherwise, it tests every
+    /// chunk.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    /// use retriever::types::storage_builder::StorageBuilder;
+    ///
+    /// // Orders chunked by customer id, keyed by order number.
+    /// let mut storage : Storage<u64, u64, (u64, u64, &'static str)> = StorageBuilder::new()
+    ///   .item_key_index(true)
+    ///   .build();
+    ///
+    /// storage.add((7, 1001, "shipped"));
+    /// storage.add((7, 1002, "pending"));
+    /// storage.add((9, 1003, "pending"));
+    ///
+    /// // A customer service call only knows the order number.
+    /// assert_eq!(Some(&(9, 1003, "pending")), storage.find(&1003));
+    /// assert_eq!(None, storage.find(&1004));
+    ///
+    /// storage.remove(ID.chunk(9).item(1003), std::mem::drop);
+    /// assert_eq!(None, storage.find(&1003));
+    ///
+    /// # storage.validate();
+    /// ```
+    pub fn find(&self, item_key: &ItemKey) -> Option<&Element> {
+        let item_index = match self.item_index.as_ref() {
+            Some(item_index) => item_index,
+            None => {
+                return self.chunks.iter().find_map(|chunk| {
+                    chunk
+                        .internal_idx_of(item_key)
+                        .map(|idx| chunk.get_idx(idx))
+                })
+            }
+        };
+
+        item_index.get(item_key)?.iter().find_map(|chunk_key| {
+            let chunk = &self.chunks[self.internal_idx_of(chunk_key.borrow())?];
+            chunk
+                .internal_idx_of(item_key)
+                .map(|idx| chunk.get_idx(idx))
+        })
+    }
+
     /// Get an `Entry` for an `Element` that may or may not exist. An `Element` is a `Record`
     /// that is uniquely identified by the combination of its `ChunkKey` and `ItemKey`.
     ///
@@ -457,10 +1192,110 @@ where
         R: Record<ChunkKey, ItemKey> + 'a,
     {
         self.clean();
+
+        // An Entry can insert an element without going through this Storage, so record its
+        // chunk key up front. `find` skips the chunk if the Entry never inserts anything.
+        if let Some(item_index) = self.item_index.as_mut() {
+            let chunk_keys = item_index
+                .entry(unique_id.item_key().into_owned())
+                .or_default();
+            if !chunk_keys
+                .iter()
+                .any(|c| *c.borrow() == *unique_id.chunk_key())
+            {
+                chunk_keys.push(unique_id.chunk_key().into_owned());
+            }
+        }
+
         self.chunk(unique_id.borrow().chunk_key().borrow(), true)
             .entry(unique_id)
     }
 
+    /// Read an element, compute its replacement, and replace it, all in one step. If the
+    /// function returns `None`, the element is left unchanged. This is a convenient way to
+    /// drive state-machine transitions, where the new state depends on the current state.
+    ///
+    /// The replacement must have the same `Id` as the element it replaces.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the replacement has a different `Id`, unless this `Storage`'s `Strictness`
+    /// is `Repair`, in which case the old element is removed and the replacement is added.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    /// use retriever::types::cas_result::CasResult;
+    ///
+    /// let mut storage : Storage<u64, u64, (u64, u64, &'static str)> = Storage::new();
+    /// storage.add((1, 1, "pending"));
+    ///
+    /// let approve = |order: &(u64, u64, &'static str)| {
+    ///   if order.2 == "pending" { Some((order.0, order.1, "approved")) } else { None }
+    /// };
+    ///
+    /// assert_eq!(
+    ///   CasResult::Swapped((1, 1, "pending")),
+    ///   storage.compare_and_swap(&ID.chunk(1).item(1), approve)
+    /// );
+    /// assert_eq!(CasResult::Unchanged, storage.compare_and_swap(&ID.chunk(1).item(1), approve));
+    /// assert_eq!(CasResult::Missing, storage.compare_and_swap(&ID.chunk(1).item(2), approve));
+    ///
+    /// assert_eq!(Some(&(1, 1, "approved")), storage.get(&ID.chunk(1).item(1)));
+    ///
+    /// # storage.validate();
+    /// ```
+    pub fn compare_and_swap<R, F>(&mut self, unique_id: &R, f: F) -> CasResult<Element>
+    where
+        R: Record<ChunkKey, ItemKey>,
+        F: FnOnce(&Element) -> Option<Element>,
+    {
+        self.clean();
+
+        let chunk_idx = match self.internal_idx_of(unique_id.chunk_key().borrow()) {
+            Some(idx) => idx,
+            None => return CasResult::Missing,
+        };
+        let item_idx = match self.chunks[chunk_idx].internal_idx_of(unique_id.item_key().borrow()) {
+            Some(idx) => idx,
+            None => return CasResult::Missing,
+        };
+        let replacement = match f(self.chunks[chunk_idx].get_idx(item_idx)) {
+            Some(replacement) => replacement,
+            None => return CasResult::Unchanged,
+        };
+
+        if replacement.chunk_key() != unique_id.chunk_key()
+            || replacement.item_key() != unique_id.item_key()
+        {
+            assert_eq!(
+                self.strictness,
+                Strictness::Repair,
+                "compare_and_swap: the replacement must have the same Id as the element it replaces"
+            );
+            #[cfg(feature = "log")]
+            log::warn!(
+                "retriever: repaired compare_and_swap with a different Id by moving the element"
+            );
+
+            self.dirty(chunk_idx);
+            let previous = self.chunk_mut(chunk_idx).remove_idx(item_idx);
+            self.unindex_items(
+                unique_id.chunk_key().borrow(),
+                std::iter::once(unique_id.item_key()),
+            );
+            self.add(replacement);
+            return CasResult::Swapped(previous);
+        }
+
+        let previous =
+            std::mem::replace(self.chunk_mut(chunk_idx).get_idx_mut(item_idx), replacement);
+        self.maintain(&std::iter::once(chunk_idx).collect());
+
+        CasResult::Swapped(previous)
+    }
+
     /// Iterate over every element in storage.
     ///
     /// # Example
@@ -483,12 +1318,90 @@ where
     ///
     /// # storage.validate();
     /// ```
-    pub fn iter(&self) -> impl Iterator<Item = &Element> {
-        self.chunks.iter().flat_map(|chunk| chunk.iter())
+    pub fn iter(&self) -> Iter<'_, ChunkKey, ItemKey, Element> {
+        Iter::new(&self.chunks)
+    }
+
+    /// Begin a scan of every element in storage that can be paused and resumed, even while
+    /// the storage is being modified in between. The scan is represented by a `ResumableIter`,
+    /// which does not borrow the storage while it is paused.
+    ///
+    /// Elements are visited in order of chunk key and then item key. Every element that is
+    /// present for the whole duration of the scan is visited exactly once. Elements that are
+    /// removed before the scan reaches them are never visited. See `ResumableIter` for
+    /// the treatment of elements added during the scan.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    ///
+    /// let mut storage : Storage<u64, u64, (u64, u64, &'static str)> = Storage::new();
+    ///
+    /// storage.add((1, 1, "apple"));
+    /// storage.add((1, 2, "banana"));
+    /// storage.add((2, 1, "cherry"));
+    ///
+    /// let mut scan = storage.iter_resumable();
+    ///
+    /// assert_eq!(Some(&(1, 1, "apple")), scan.next_element(&storage));
+    ///
+    /// // The scan does not borrow the storage while it is paused.
+    /// storage.remove(&ID.chunk(1).item(2), std::mem::drop);
+    /// storage.add((3, 1, "durian"));
+    ///
+    /// let rest : Vec<_> = scan.resume(&storage).map(|x| x.2).collect();
+    /// assert_eq!(vec!["cherry", "durian"], rest);
+    ///
+    /// # storage.validate();
+    /// ```
+    pub fn iter_resumable(&self) -> ResumableIter<ChunkKey, ItemKey> {
+        ResumableIter::new(self.id)
+    }
+
+    /// Iterate over the `Id` of every element in storage, in no particular order.
+    ///
+    /// The keys are read from the internal chunk and item indices, so this never touches
+    /// the elements themselves.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    /// use std::collections::HashSet;
+    ///
+    /// let mut storage : Storage<u64, u64, (u64, u64, &'static str)> = Storage::new();
+    ///
+    /// storage.add((1, 10, "hello"));
+    /// storage.add((1, 11, "world"));
+    /// storage.add((2, 20, "goodbye"));
+    ///
+    /// let ids : HashSet<(u64, u64)> = storage.keys()
+    ///   .map(|id| (id.0.into_owned(), id.1.into_owned()))
+    ///   .collect();
+    /// let remote : HashSet<(u64, u64)> = vec![(1, 10), (2, 20), (3, 30)].into_iter().collect();
+    ///
+    /// assert_eq!(vec![&(1, 11)], ids.difference(&remote).collect::<Vec<_>>());
+    /// assert_eq!(vec![&(3, 30)], remote.difference(&ids).collect::<Vec<_>>());
+    /// ```
+    pub fn keys(&self) -> impl Iterator<Item = Id<Cow<'_, ChunkKey>, Cow<'_, ItemKey>>> {
+        self.chunks.iter().flat_map(|chunk| {
+            let chunk_key = chunk.chunk_key();
+
+            chunk
+                .item_keys()
+                .map(move |item_key| Id::new(Cow::Borrowed(chunk_key), Cow::Borrowed(item_key)))
+        })
     }
 
     /// Iterate over elements according to some Query. A variety of builtin queries are provided.
     ///
+    /// The returned iterator is double-ended, so `Iterator::rev` visits the same elements in
+    /// exactly the reverse order: the last chunk first, and the elements of each chunk from last
+    /// to first, without collecting anything. This holds for `Query::limit` and `Query::skip`
+    /// too, because they choose their elements before the iteration begins. To visit elements
+    /// in reverse order of some key, use `Storage::order_by` with `std::cmp::Reverse`.
+    ///
     /// # Type Parameters
     ///
     /// * `Q`: Any `Query`. There are a variety of useful `Queries`:
@@ -528,13 +1441,17 @@ where
     /// assert_eq!(99, storage.query(Everything.filter(|x : &(u8,u16,i64)| x.2 > 0)).map(|x| x.2).sum::<i64>());
     ///
     /// // Or accelerate the exact same filter using a SecondaryIndex:
-    /// let mut positive_numbers : SecondaryIndex<u8,(u8,u16,i64),Option<bool>,bool> =
+    /// let positive_numbers : SecondaryIndex<u8,(u8,u16,i64),Option<bool>,bool> =
     ///     SecondaryIndex::new(&storage, |x : &(u8,u16,i64)| Cow::Owned(Some(x.2 > 0)));
-    /// assert_eq!(99, storage.query(&Everything.matching(&mut positive_numbers, Cow::Owned(true))).map(|x| x.2).sum::<i64>());
+    /// assert_eq!(99, storage.query(&Everything.matching(&positive_numbers, Cow::Owned(true))).map(|x| x.2).sum::<i64>());
+    ///
+    /// // Visit the most recently added elements of a chunk first:
+    /// let newest : Vec<u16> = storage.query(Chunks([1])).rev().map(|x| x.1).collect();
+    /// assert_eq!(vec![1002, 1001, 1000], newest);
     ///
     /// # storage.validate();
     /// ```
-    pub fn query<'a, Q>(&'a self, query: Q) -> impl Iterator<Item = &'a Element>
+    pub fn query<'a, Q>(&'a self, query: Q) -> impl DoubleEndedIterator<Item = &'a Element>
     where
         Q: Query<ChunkKey, ItemKey, Element> + Clone + 'a,
     {
@@ -543,7 +1460,7 @@ where
         chunk_idxs
             .into_idx_iter()
             .flatten()
-            .map(move |idx| &self.chunks[idx])
+            .map(move |idx| &*self.chunks[idx])
             .flat_map(
                 move |chunk_storage: &ChunkStorage<ChunkKey, ItemKey, Element>| {
                     chunk_storage.query(query.clone())
@@ -551,114 +1468,1458 @@ where
             )
     }
 
-    /// Iterate over a Query and modify each element via a callback.
-    /// The callback provides retriever's Editor API, which in turn provides
-    /// a mutable or immutable reference to the underlying element.
-    ///
-    /// Since re-indexing is a potentially expensive operation, it's best to examine an immutable
-    /// reference to a data element to make sure you really want to mutate it before obtaining a
-    /// mutable reference.
+    /// Iterate over elements according to some Query, one contiguous slice at a time, along
+    /// with the chunk key of each slice. This suits processing that wants each chunk's data
+    /// laid out contiguously, such as SIMD-style loops.
     ///
-    /// # Type Parameters
+    /// When every element of a chunk matches, as it does for `Everything` or `Chunks`, the
+    /// whole chunk is yielded as a single slice. Otherwise, each run of adjacent matching
+    /// elements is yielded as its own slice, so a chunk may yield more than one slice.
+    /// The order of the elements within a chunk depends on the history of the chunk.
     ///
-    /// * `Q`: Any `Query`. There are a variety of useful `Queries`:
-    ///   * `Everything`
-    ///   * `Chunks(...)`, an explicit list of chunks
-    ///   * `Id`, the Id of a specific element
-    ///   * Most other `Queries` can be constructed by chaining the methods of the `Query` trait.
+    /// # Example
     ///
     /// ```
     /// use retriever::prelude::*;
-    /// use std::borrow::Cow;
-    ///
-    /// struct BankAccount {
-    ///   id: usize,
-    ///   balance: i64,
-    /// }
     ///
-    /// impl Record<(),usize> for BankAccount {
-    ///   fn chunk_key(&self) -> Cow<()> {
-    ///     Cow::Owned(())
-    ///   }
+    /// let mut storage : Storage<u64, u64, (u64, u64, f32)> = Storage::new();
     ///
-    ///   fn item_key(&self) -> Cow<usize> {
-    ///     Cow::Owned(self.id)
-    ///   }
+    /// for i in 0..12 {
+    ///   storage.add((i % 3, i, i as f32));
     /// }
     ///
-    /// let mut storage : Storage<(),usize,BankAccount> = Storage::new();
-    ///
-    /// storage.add(BankAccount { id: 1, balance: 25 });
-    /// storage.add(BankAccount { id: 2, balance: 13 });
-    /// storage.add(BankAccount { id: 3, balance: -900 });
-    /// storage.add(BankAccount { id: 4, balance: 27000 });
-    /// storage.add(BankAccount { id: 5, balance: -13 });
-    ///
-    /// // Charge an overdraft fee to everyone with a negative balance.
-    /// storage.modify(Everything.filter(|account : &BankAccount| account.balance < 0), |mut account| {
-    ///   account.get_mut().balance -= 25;
-    /// });
+    /// let mut sums : Vec<(u64, f32)> = storage
+    ///   .query_chunks(Chunks([0, 2]))
+    ///   .map(|(chunk_key, slice)| (*chunk_key, slice.iter().map(|x| x.2).sum()))
+    ///   .collect();
+    /// sums.sort_by(|a, b| a.0.cmp(&b.0));
     ///
-    /// assert_eq!(Some(25),    storage.get(&ID.item(1)).map(|x| x.balance));
-    /// assert_eq!(Some(13),    storage.get(&ID.item(2)).map(|x| x.balance));
-    /// assert_eq!(Some(-925),  storage.get(&ID.item(3)).map(|x| x.balance));
-    /// assert_eq!(Some(27000), storage.get(&ID.item(4)).map(|x| x.balance));
-    /// assert_eq!(Some(-38),   storage.get(&ID.item(5)).map(|x| x.balance));
+    /// assert_eq!(vec![(0, 18.0), (2, 26.0)], sums);
     ///
-    /// # storage.validate();
+    /// // Every matching element is visited exactly once, whether or not the matches are adjacent.
+    /// let odd = Everything.filter(|x: &(u64, u64, f32)| x.1 % 2 == 1);
+    /// assert_eq!(6, storage.query_chunks(odd).map(|(_, slice)| slice.len()).sum::<usize>());
     /// ```
-    pub fn modify<Q, F>(&mut self, query: Q, f: F)
+    pub fn query_chunks<'a, Q>(
+        &'a self,
+        query: Q,
+    ) -> impl Iterator<Item = (&'a ChunkKey, &'a [Element])>
     where
-        Q: Query<ChunkKey, ItemKey, Element>,
-        F: Fn(Editor<ChunkKey, ItemKey, Element>),
+        Q: Query<ChunkKey, ItemKey, Element> + Clone + 'a,
     {
-        self.clean();
+        let chunk_idxs = query.chunk_idxs(self);
 
-        for idx in query.chunk_idxs(self).into_idx_iter().flatten() {
-            self.chunks[idx].modify(&query, &f);
-        }
+        chunk_idxs
+            .into_idx_iter()
+            .flatten()
+            .map(move |idx| &*self.chunks[idx])
+            .flat_map(
+                move |chunk_storage: &'a ChunkStorage<ChunkKey, ItemKey, Element>| {
+                    let chunk_key = chunk_storage.chunk_key();
+                    let raw = chunk_storage.raw();
+                    let mut runs: Vec<(&'a ChunkKey, &'a [Element])> = Vec::new();
+                    let mut run: Option<(usize, usize)> = None;
+
+                    for idx in query
+                        .item_idxs(chunk_key, chunk_storage)
+                        .into_idx_iter()
+                        .flatten()
+                        .filter(|idx| query.test(&raw[*idx]))
+                    {
+                        run = match run {
+                            Some((start, end)) if end == idx => Some((start, idx + 1)),
+                            Some((start, end)) => {
+                                runs.push((chunk_key, &raw[start..end]));
+                                Some((idx, idx + 1))
+                            }
+                            None => Some((idx, idx + 1)),
+                        };
+                    }
+
+                    if let Some((start, end)) = run {
+                        runs.push((chunk_key, &raw[start..end]));
+                    }
+
+                    runs
+                },
+            )
     }
 
-    /// Remove all of the specified elements from this storage.
-    ///
-    /// # Type Parameters
+    /// Iterate over elements according to some Query, as `Storage::query` does, along with the
+    /// `Id` of each element. The chunk key is borrowed from the chunk rather than recomputed for
+    /// each element. Use `Id::cloned` to keep an `Id` for a later targeted modification.
     ///
-    /// * `Q`: Any `Query`. There are a variety of useful `Queries`:
-    ///   * `Everything`
-    ///   * `Chunks(...)`, an explicit list of chunks
-    ///   * `Id`, the Id of a specific element
-    ///   * Most other `Queries` can be constructed by chaining the methods of the `Query` trait.
+    /// # Example
     ///
     /// ```
     /// use retriever::prelude::*;
-    /// use retriever::queries::everything::Everything;
-    /// use std::borrow::Cow;
-    ///
-    /// // In this example, we will store log entries, some of which might contain sensitive
-    /// // information that we need to delete later.
-    /// struct LogEntry {
-    ///   stardate: u128,
-    ///   msg: String,
-    /// }
     ///
-    /// impl Record<u128, u128> for LogEntry {
-    ///   fn chunk_key(&self) -> Cow<u128> {
-    ///     Cow::Owned(self.stardate / 1000)
-    ///   }
+    /// let mut storage : Storage<u64, u64, (u64, u64, u64)> = Storage::new();
     ///
-    ///   fn item_key(&self) -> Cow<u128> {
-    ///     Cow::Borrowed(&self.stardate)
-    ///   }
+    /// for i in 0..10 {
+    ///   storage.add((i % 2, i, i * i));
     /// }
     ///
-    /// let mut storage : Storage<u128, u128, LogEntry> = Storage::new();
+    /// let big : Vec<Id<u64, u64>> = storage
+    ///   .query_with_keys(Everything.filter(|x: &(u64, u64, u64)| x.2 > 40))
+    ///   .map(|(id, _)| Id::cloned(&id))
+    ///   .collect();
     ///
-    /// storage.add(LogEntry {
-    ///   stardate: 109301,
-    ///   msg: String::from("Departed from Starbase Alpha"),
-    /// });
+    /// for id in big.iter() {
+    ///   storage.modify(id, |mut editor| editor.get_mut().2 = 0);
+    /// }
     ///
-    /// storage.add(LogEntry {
+    /// assert_eq!(91, storage.iter().map(|x| x.2).sum::<u64>());
+    /// ```
+    pub fn query_with_keys<'a, Q>(
+        &'a self,
+        query: Q,
+    ) -> impl Iterator<Item = (Id<Cow<'a, ChunkKey>, Cow<'a, ItemKey>>, &'a Element)>
+    where
+        Q: Query<ChunkKey, ItemKey, Element> + Clone + 'a,
+    {
+        let chunk_idxs = query.chunk_idxs(self);
+
+        chunk_idxs
+            .into_idx_iter()
+            .flatten()
+            .map(move |idx| &*self.chunks[idx])
+            .flat_map(
+                move |chunk_storage: &'a ChunkStorage<ChunkKey, ItemKey, Element>| {
+                    let chunk_key = chunk_storage.chunk_key();
+
+                    chunk_storage.query(query.clone()).map(move |element| {
+                        (
+                            Id::new(Cow::Borrowed(chunk_key), element.item_key()),
+                            element,
+                        )
+                    })
+                },
+            )
+    }
+
+    /// Iterate over the elements matching some Query, in ascending order of an arbitrary key.
+    /// Elements with equal keys keep the order in which `Storage::query` would visit them.
+    ///
+    /// Each chunk is sorted on its own, and the sorted chunks are then lazily merged, so
+    /// keys are only held for the elements at the head of each chunk, and no element is ever
+    /// cloned.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    ///
+    /// let mut storage : Storage<&'static str, u64, (&'static str, u64, i64)> = Storage::new();
+    ///
+    /// storage.add(("north", 1, 30));
+    /// storage.add(("north", 2, -5));
+    /// storage.add(("south", 3, 12));
+    /// storage.add(("south", 4, 30));
+    /// storage.add(("east", 5, 0));
+    ///
+    /// let ascending : Vec<u64> = storage
+    ///   .order_by(Everything, |x| x.2)
+    ///   .map(|x| x.1)
+    ///   .collect();
+    /// assert_eq!(5, ascending.len());
+    /// assert_eq!(&[2, 5, 3], &ascending[0..3]);
+    ///
+    /// let descending : Vec<i64> = storage
+    ///   .order_by(Chunks(["north", "east"]), |x| std::cmp::Reverse(x.2))
+    ///   .map(|x| x.2)
+    ///   .collect();
+    /// assert_eq!(vec![30, 0, -5], descending);
+    /// ```
+    pub fn order_by<'a, Q, K, F>(&'a self, query: Q, key: F) -> impl Iterator<Item = &'a Element>
+    where
+        Q: Query<ChunkKey, ItemKey, Element> + Clone + 'a,
+        K: Ord,
+        F: Fn(&Element) -> K + 'a,
+    {
+        let runs: Vec<Vec<&'a Element>> = query
+            .chunk_idxs(self)
+            .into_idx_iter()
+            .flatten()
+            .map(|idx| {
+                let mut run: Vec<&'a Element> = self.chunks[idx].query(query.clone()).collect();
+                run.sort_by_key(|element| key(element));
+                run
+            })
+            .filter(|run| !run.is_empty())
+            .collect();
+
+        KWayMerge::new(runs, move |element: &&'a Element| key(element))
+    }
+
+    /// Iterate over every pair of elements, one from this `Storage` and one from another, whose
+    /// join keys are equal. Only the elements matching each side's `Query` take part.
+    ///
+    /// This is a hash join: the matching elements of the other `Storage` are grouped by join key
+    /// up front, and then the matching elements of this `Storage` are visited lazily. Put the
+    /// smaller side second. If both `Storages` share a chunk key and the join key never crosses
+    /// chunks, `Storage::join_by_chunk` uses much less memory.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    ///
+    /// // Users keyed by user id, and sessions keyed by session id.
+    /// let mut users : Storage<(), u64, ((), u64, &'static str)> = Storage::new();
+    /// let mut sessions : Storage<(), u64, ((), u64, u64)> = Storage::new();
+    ///
+    /// users.add(((), 1, "alice"));
+    /// users.add(((), 2, "bob"));
+    /// users.add(((), 3, "carol"));
+    ///
+    /// sessions.add(((), 100, 1));
+    /// sessions.add(((), 101, 3));
+    /// sessions.add(((), 102, 1));
+    ///
+    /// let mut pairs : Vec<(&'static str, u64)> = users
+    ///   .join(Everything, &sessions, Everything, |user| user.1, |session| session.2)
+    ///   .map(|(user, session)| (user.2, session.1))
+    ///   .collect();
+    /// pairs.sort();
+    ///
+    /// assert_eq!(vec![("alice", 100), ("alice", 102), ("carol", 101)], pairs);
+    /// ```
+    pub fn join<'a, Q, OtherChunkKey, OtherItemKey, Other, OtherQ, K, F, G>(
+        &'a self,
+        query: Q,
+        other: &'a Storage<OtherChunkKey, OtherItemKey, Other>,
+        other_query: OtherQ,
+        key: F,
+        other_key: G,
+    ) -> impl Iterator<Item = (&'a Element, &'a Other)>
+    where
+        Q: Query<ChunkKey, ItemKey, Element> + Clone + 'a,
+        OtherChunkKey: BorrowedKey + ?Sized,
+        OtherChunkKey::Owned: ValidKey,
+        OtherItemKey: BorrowedKey + ?Sized,
+        OtherItemKey::Owned: ValidKey,
+        Other: Record<OtherChunkKey, OtherItemKey>,
+        OtherQ: Query<OtherChunkKey, OtherItemKey, Other> + Clone + 'a,
+        K: Eq + Hash + 'a,
+        F: Fn(&Element) -> K + 'a,
+        G: Fn(&Other) -> K,
+    {
+        let groups = group_by_key(other.query(other_query), other_key);
+
+        self.query(query).flat_map(move |element| {
+            let group = groups.get(&key(element)).cloned().unwrap_or_default();
+            (0..group.len()).map(move |i| (element, group[i]))
+        })
+    }
+
+    /// Iterate over every pair of elements, one from this `Storage` and one from another with
+    /// the same chunk key type, whose chunk keys and join keys are both equal. Only the elements
+    /// matching each side's `Query` take part.
+    ///
+    /// Each chunk of this `Storage` is only paired with the chunk of the other `Storage` that has
+    /// the same chunk key, so only one chunk of the other `Storage` is held in memory at a time,
+    /// and chunks missing from either side are skipped entirely.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    ///
+    /// // Users and sessions, both chunked by tenant.
+    /// let mut users : Storage<&'static str, u64, (&'static str, u64, &'static str)> = Storage::new();
+    /// let mut sessions : Storage<&'static str, u64, (&'static str, u64, u64)> = Storage::new();
+    ///
+    /// users.add(("acme", 1, "alice"));
+    /// users.add(("acme", 2, "bob"));
+    /// users.add(("initech", 1, "peter"));
+    ///
+    /// sessions.add(("acme", 100, 2));
+    /// sessions.add(("initech", 101, 1));
+    /// sessions.add(("initech", 102, 2));
+    ///
+    /// let mut pairs : Vec<(&'static str, u64)> = users
+    ///   .join_by_chunk(Everything, &sessions, Everything, |user| user.1, |session| session.2)
+    ///   .map(|(user, session)| (user.2, session.1))
+    ///   .collect();
+    /// pairs.sort();
+    ///
+    /// assert_eq!(vec![("bob", 100), ("peter", 101)], pairs);
+    /// ```
+    pub fn join_by_chunk<'a, Q, OtherItemKey, Other, OtherQ, K, F, G>(
+        &'a self,
+        query: Q,
+        other: &'a Storage<ChunkKey, OtherItemKey, Other>,
+        other_query: OtherQ,
+        key: F,
+        other_key: G,
+    ) -> impl Iterator<Item = (&'a Element, &'a Other)>
+    where
+        Q: Query<ChunkKey, ItemKey, Element> + Clone + 'a,
+        OtherItemKey: BorrowedKey + ?Sized,
+        OtherItemKey::Owned: ValidKey,
+        Other: Record<ChunkKey, OtherItemKey>,
+        OtherQ: Query<ChunkKey, OtherItemKey, Other> + Clone + 'a,
+        K: Eq + Hash + 'a,
+        F: Fn(&Element) -> K + 'a,
+        G: Fn(&Other) -> K + 'a,
+    {
+        let other_chunk_idxs: Bitset = other_query
+            .chunk_idxs(other)
+            .into_idx_iter()
+            .flatten()
+            .collect();
+
+        query
+            .chunk_idxs(self)
+            .into_idx_iter()
+            .flatten()
+            .map(move |idx| &*self.chunks[idx])
+            .flat_map(
+                move |chunk_storage: &'a ChunkStorage<ChunkKey, ItemKey, Element>| {
+                    let other_chunk_storage = other
+                        .internal_idx_of(chunk_storage.chunk_key())
+                        .filter(|other_idx| other_chunk_idxs.get(*other_idx))
+                        .map(|other_idx| &*other.chunks[other_idx]);
+
+                    let mut pairs: Vec<(&'a Element, &'a Other)> = Vec::new();
+
+                    if let Some(other_chunk_storage) = other_chunk_storage {
+                        let groups = group_by_key(
+                            other_chunk_storage.query(other_query.clone()),
+                            &other_key,
+                        );
+
+                        for element in chunk_storage.query(query.clone()) {
+                            if let Some(group) = groups.get(&key(element)) {
+                                pairs.extend(
+                                    group.iter().map(|other_element| (element, *other_element)),
+                                );
+                            }
+                        }
+                    }
+
+                    pairs
+                },
+            )
+    }
+
+    /// Group the elements matching some Query by an arbitrary key. Within each group, elements
+    /// are in the order `Storage::query` would visit them.
+    ///
+    /// Elements are grouped one chunk at a time, and then each chunk's groups are merged into
+    /// the result, so the groups of a chunk stay hot in cache while it is visited.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    /// use std::collections::HashMap;
+    ///
+    /// // Sales chunked by store, keyed by receipt number, with a product and a price.
+    /// let mut storage : Storage<u64, u64, (u64, u64, (&'static str, u64))> = Storage::new();
+    ///
+    /// storage.add((1, 1, ("apple", 3)));
+    /// storage.add((1, 2, ("pear", 4)));
+    /// storage.add((2, 3, ("apple", 5)));
+    /// storage.add((2, 4, ("apple", 2)));
+    ///
+    /// let by_product = storage.group_by(Everything, |x| x.2 .0);
+    /// assert_eq!(3, by_product["apple"].len());
+    /// assert_eq!(1, by_product["pear"].len());
+    ///
+    /// let revenue : HashMap<&'static str, u64> = storage.sum_by(Everything, |x| x.2 .0, |x| x.2 .1);
+    /// assert_eq!(10, revenue["apple"]);
+    ///
+    /// let cheapest = storage.min_by(Everything, |x| x.2 .0, |x| x.2 .1);
+    /// assert_eq!(4, cheapest["apple"].1);
+    ///
+    /// let dearest = storage.max_by(Everything, |x| x.2 .0, |x| x.2 .1);
+    /// assert_eq!(3, dearest["apple"].1);
+    /// ```
+    pub fn group_by<'a, Q, K, F>(&'a self, query: Q, key: F) -> HashMap<K, Vec<&'a Element>>
+    where
+        Q: Query<ChunkKey, ItemKey, Element> + Clone + 'a,
+        K: Eq + Hash,
+        F: Fn(&Element) -> K,
+    {
+        self.aggregate_by(
+            query,
+            key,
+            |element| vec![element],
+            |group, element| group.push(element),
+            |group, other| group.extend(other),
+        )
+    }
+
+    /// Sum an arbitrary value over each group of the elements matching some Query, grouped by
+    /// an arbitrary key. See `Storage::group_by`.
+    pub fn sum_by<'a, Q, K, V, F, G>(&'a self, query: Q, key: F, value: G) -> HashMap<K, V>
+    where
+        Q: Query<ChunkKey, ItemKey, Element> + Clone + 'a,
+        K: Eq + Hash,
+        V: AddAssign,
+        F: Fn(&Element) -> K,
+        G: Fn(&Element) -> V,
+    {
+        self.aggregate_by(
+            query,
+            key,
+            &value,
+            |sum, element| *sum += value(element),
+            |sum, other| *sum += other,
+        )
+    }
+
+    /// Find the element with the least value within each group of the elements matching some
+    /// Query, grouped by an arbitrary key. Of several elements with the same least value, the
+    /// first one `Storage::query` would visit is chosen. See `Storage::group_by`.
+    pub fn min_by<'a, Q, K, V, F, G>(
+        &'a self,
+        query: Q,
+        key: F,
+        value: G,
+    ) -> HashMap<K, &'a Element>
+    where
+        Q: Query<ChunkKey, ItemKey, Element> + Clone + 'a,
+        K: Eq + Hash,
+        V: Ord,
+        F: Fn(&Element) -> K,
+        G: Fn(&Element) -> V,
+    {
+        self.extreme_by(query, key, value, cmp::Ordering::Less)
+    }
+
+    /// Find the element with the greatest value within each group of the elements matching some
+    /// Query, grouped by an arbitrary key. Of several elements with the same greatest value, the
+    /// first one `Storage::query` would visit is chosen. See `Storage::group_by`.
+    pub fn max_by<'a, Q, K, V, F, G>(
+        &'a self,
+        query: Q,
+        key: F,
+        value: G,
+    ) -> HashMap<K, &'a Element>
+    where
+        Q: Query<ChunkKey, ItemKey, Element> + Clone + 'a,
+        K: Eq + Hash,
+        V: Ord,
+        F: Fn(&Element) -> K,
+        G: Fn(&Element) -> V,
+    {
+        self.extreme_by(query, key, value, cmp::Ordering::Greater)
+    }
+
+    /// Count the elements of this `Storage` under each index key of a `CountingIndex`. Unlike
+    /// `Storage::group_by`, this doesn't visit any elements: the `CountingIndex` keeps its
+    /// counts up to date as elements change. See `CountingIndex`.
+    pub fn counts_by<IndexKeys, IndexKey>(
+        &self,
+        index: &CountingIndex<ChunkKey, Element, IndexKeys, IndexKey>,
+    ) -> HashMap<IndexKey::Owned, usize>
+    where
+        IndexKey: BorrowedKey + ?Sized,
+        IndexKey::Owned: ValidKey,
+        for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+    {
+        index.counts(self)
+    }
+
+    // Find the element of each group whose value compares to every other as `preferred`.
+    fn extreme_by<'a, Q, K, V, F, G>(
+        &'a self,
+        query: Q,
+        key: F,
+        value: G,
+        preferred: cmp::Ordering,
+    ) -> HashMap<K, &'a Element>
+    where
+        Q: Query<ChunkKey, ItemKey, Element> + Clone + 'a,
+        K: Eq + Hash,
+        V: Ord,
+        F: Fn(&Element) -> K,
+        G: Fn(&Element) -> V,
+    {
+        let replace = |best: &mut (V, &'a Element), other: (V, &'a Element)| {
+            if other.0.cmp(&best.0) == preferred {
+                *best = other;
+            }
+        };
+
+        self.aggregate_by(
+            query,
+            key,
+            |element| (value(element), element),
+            |best, element| replace(best, (value(element), element)),
+            |best, other| replace(best, other),
+        )
+        .into_iter()
+        .map(|(k, (_, element))| (k, element))
+        .collect()
+    }
+
+    // Aggregate the elements matching some Query by key, one chunk at a time: `init` starts a
+    // group, `add` adds an element to a group, and `merge` adds one chunk's group to the result.
+    fn aggregate_by<'a, Q, K, T, F, I, A, M>(
+        &'a self,
+        query: Q,
+        key: F,
+        init: I,
+        add: A,
+        merge: M,
+    ) -> HashMap<K, T>
+    where
+        Q: Query<ChunkKey, ItemKey, Element> + Clone + 'a,
+        K: Eq + Hash,
+        F: Fn(&Element) -> K,
+        I: Fn(&'a Element) -> T,
+        A: Fn(&mut T, &'a Element),
+        M: Fn(&mut T, T),
+    {
+        let mut result: HashMap<K, T> = HashMap::new();
+
+        for idx in query.chunk_idxs(self).into_idx_iter().flatten() {
+            let mut chunk_result: HashMap<K, T> = HashMap::new();
+
+            for element in self.chunks[idx].query(query.clone()) {
+                match chunk_result.entry(key(element)) {
+                    HashEntry::Occupied(mut group) => add(group.get_mut(), element),
+                    HashEntry::Vacant(group) => {
+                        group.insert(init(element));
+                    }
+                }
+            }
+
+            for (k, group) in chunk_result {
+                match result.entry(k) {
+                    HashEntry::Occupied(mut prev) => merge(prev.get_mut(), group),
+                    HashEntry::Vacant(prev) => {
+                        prev.insert(group);
+                    }
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Count the elements matching some Query.
+    ///
+    /// If the `Query` is exact (see `Query::is_exact`), the count comes straight from its
+    /// index sets without visiting any element. This covers `Everything`, `Chunks`, and
+    /// `SecondaryIndex` matches on top of them, among others. Otherwise, this falls back to
+    /// counting the elements visited by `Storage::query`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    /// use std::borrow::Cow;
+    ///
+    /// let mut storage : Storage<u64, u64, (u64, u64, bool)> = Storage::new();
+    /// let by_flag : SecondaryIndex<u64, (u64, u64, bool), Option<bool>, bool> =
+    ///   SecondaryIndex::new(&storage, |x: &(u64, u64, bool)| Cow::Owned(Some(x.2)));
+    ///
+    /// for i in 0..100 {
+    ///   storage.add((i % 4, i, i % 3 == 0));
+    /// }
+    ///
+    /// assert_eq!(100, storage.count(Everything));
+    /// assert_eq!(50, storage.count(Chunks([0, 2])));
+    /// assert_eq!(34, storage.count(Everything.matching(&by_flag, Cow::Owned(true))));
+    /// assert_eq!(10, storage.count(Everything.filter(|x: &(u64, u64, bool)| x.1 < 10)));
+    /// ```
+    pub fn count<Q>(&self, query: Q) -> usize
+    where
+        Q: Query<ChunkKey, ItemKey, Element> + Clone,
+    {
+        if !query.is_exact() {
+            return self.query(query).count();
+        }
+
+        query
+            .chunk_idxs(self)
+            .into_idx_iter()
+            .flatten()
+            .map(|idx| {
+                let chunk_storage = &*self.chunks[idx];
+                query
+                    .item_idxs(chunk_storage.chunk_key(), chunk_storage)
+                    .into_idx_iter()
+                    .map(|bitfield| bitfield.ones())
+                    .sum::<usize>()
+            })
+            .sum()
+    }
+
+    /// True IFF at least one element matches some Query.
+    ///
+    /// This stops at the first matching element, skipping any remaining chunks entirely, and
+    /// doesn't need to clone the `Query`. If the `Query` is exact (see `Query::is_exact`),
+    /// no element is visited at all.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    ///
+    /// let mut storage : Storage<u64, u64, (u64, u64, &'static str)> = Storage::new();
+    ///
+    /// storage.add((1, 1, "lemon"));
+    /// storage.add((1, 2, "lime"));
+    /// storage.add((2, 1, "orange"));
+    ///
+    /// assert!(storage.exists(Everything));
+    /// assert!(storage.exists(Chunks([2])));
+    /// assert!(!storage.exists(Chunks([3])));
+    /// assert!(storage.exists(Everything.filter(|x: &(u64, u64, &str)| x.2.starts_with("li"))));
+    /// assert!(!storage.exists(Chunks([2]).filter(|x: &(u64, u64, &str)| x.2.starts_with("li"))));
+    /// ```
+    pub fn exists<Q>(&self, query: Q) -> bool
+    where
+        Q: Query<ChunkKey, ItemKey, Element>,
+    {
+        let exact = query.is_exact();
+
+        query.chunk_idxs(self).into_idx_iter().flatten().any(|idx| {
+            let chunk_storage = &*self.chunks[idx];
+            let mut item_idxs = query
+                .item_idxs(chunk_storage.chunk_key(), chunk_storage)
+                .into_idx_iter();
+
+            if exact {
+                item_idxs.any(|bitfield| bitfield.ones() > 0)
+            } else {
+                item_idxs
+                    .flatten()
+                    .any(|idx| query.test(chunk_storage.get_idx(idx)))
+            }
+        })
+    }
+
+    /// Run a Query one page of at most `n` elements at a time, for handing a huge `Storage` to
+    /// a client in pieces. Pass `None` to get the first page, and then the `Cursor` from each
+    /// `Page` to get the page after it, until a `Page` has no next `Cursor`.
+    ///
+    /// The `Storage` may be modified between pages. See `Cursor` for which modifications
+    /// invalidate a `Cursor`, in which case this returns an `InvalidCursorError` and the query
+    /// must be started over. Pages are visited in order of chunk index rather than chunk key,
+    /// and a `Page` may end with a next `Cursor` even if there turn out to be no more elements.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    /// use retriever::types::cursor::Cursor;
+    ///
+    /// let mut storage : Storage<u64, u64, (u64, u64, u64)> = Storage::new();
+    ///
+    /// for i in 0..100 {
+    ///   storage.add((i % 4, i, i));
+    /// }
+    ///
+    /// let even = Everything.filter(|x: &(u64, u64, u64)| x.2 % 2 == 0);
+    /// let mut total = 0;
+    /// let mut cursor = None;
+    ///
+    /// loop {
+    ///   let page = storage.query_page(&even, cursor.as_ref(), 7).unwrap();
+    ///   total += page.elements.iter().map(|x| x.2).sum::<u64>();
+    ///
+    ///   // A cursor can be sent to a client as bytes, and later read back.
+    ///   cursor = match page.next {
+    ///     Some(next) => Some(Cursor::from_bytes(next.to_bytes())),
+    ///     None => break,
+    ///   };
+    ///
+    ///   // Modifying other chunks doesn't invalidate the cursor.
+    ///   storage.add((4, 100 + total, 1));
+    /// }
+    ///
+    /// assert_eq!((0..100).filter(|i| i % 2 == 0).sum::<u64>(), total);
+    ///
+    /// // Removing a chunk invalidates every cursor.
+    /// let cursor = storage.query_page(&even, None, 7).unwrap().next;
+    /// storage.remove_chunk(&1);
+    /// assert!(storage.query_page(&even, cursor.as_ref(), 7).is_err());
+    /// ```
+    pub fn query_page<'a, Q>(
+        &'a self,
+        query: Q,
+        cursor: Option<&Cursor>,
+        n: usize,
+    ) -> Result<Page<'a, Element>, InvalidCursorError>
+    where
+        Q: Query<ChunkKey, ItemKey, Element>,
+    {
+        self.query_page_until(query, cursor, |_, matched| matched >= n)
+    }
+
+    /// Run some Query for a limited `Budget` of elements or time, returning the matching
+    /// elements found so far and a `Cursor` to continue from on the next call. Unlike
+    /// `Storage::query_page`, the budget counts every element examined, so a call returns
+    /// promptly even when few elements match.
+    ///
+    /// Pass `None` to begin the query. See `Storage::query_page` for how the `Storage` may be
+    /// modified between calls.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    /// use retriever::types::budget::Budget;
+    /// use std::time::Duration;
+    ///
+    /// let mut storage : Storage<u64, u64, (u64, u64, u64)> = Storage::new();
+    ///
+    /// for i in 0..1000 {
+    ///   storage.add((i % 4, i, i));
+    /// }
+    ///
+    /// let rare = Everything.filter(|x: &(u64, u64, u64)| x.2 % 100 == 0);
+    /// let mut found = Vec::new();
+    /// let mut frames = 0;
+    /// let mut cursor = None;
+    ///
+    /// loop {
+    ///   // Examine at most 50 elements each frame.
+    ///   let page = storage.query_budgeted(&rare, cursor.as_ref(), Budget::Elements(50)).unwrap();
+    ///   found.extend(page.elements.iter().map(|x| x.2));
+    ///   frames += 1;
+    ///
+    ///   cursor = match page.next {
+    ///     Some(next) => Some(next),
+    ///     None => break,
+    ///   };
+    /// }
+    ///
+    /// found.sort();
+    /// assert_eq!((0..10).map(|i| i * 100).collect::<Vec<u64>>(), found);
+    /// assert_eq!(20, frames);
+    ///
+    /// // Or run for at most a millisecond.
+    /// let page = storage.query_budgeted(&rare, None, Budget::Duration(Duration::from_millis(1)));
+    /// assert!(page.is_ok());
+    /// ```
+    pub fn query_budgeted<'a, Q>(
+        &'a self,
+        query: Q,
+        cursor: Option<&Cursor>,
+        budget: Budget,
+    ) -> Result<Page<'a, Element>, InvalidCursorError>
+    where
+        Q: Query<ChunkKey, ItemKey, Element>,
+    {
+        let started = Instant::now();
+        self.query_page_until(query, cursor, |examined, _| {
+            budget.is_spent(examined, started.elapsed())
+        })
+    }
+
+    // Run some Query from the given cursor until `done` says so, given the numbers of elements
+    // examined and matched so far.
+    fn query_page_until<'a, Q, F>(
+        &'a self,
+        query: Q,
+        cursor: Option<&Cursor>,
+        mut done: F,
+    ) -> Result<Page<'a, Element>, InvalidCursorError>
+    where
+        Q: Query<ChunkKey, ItemKey, Element>,
+        F: FnMut(usize, usize) -> bool,
+    {
+        let (start_chunk, start_offset) = match cursor {
+            Some(cursor) if self.is_valid_cursor(cursor) => (cursor.chunk(), cursor.offset()),
+            Some(cursor) => return Err(InvalidCursorError { cursor: *cursor }),
+            None => (0, 0),
+        };
+
+        let generation = current_generation();
+        let mut chunk_idxs: Vec<usize> = query
+            .chunk_idxs(self)
+            .into_idx_iter()
+            .flatten()
+            .filter(|idx| *idx >= start_chunk)
+            .collect();
+        chunk_idxs.sort_unstable();
+        chunk_idxs.dedup();
+
+        let mut elements = Vec::new();
+        let mut examined = 0;
+
+        for idx in chunk_idxs {
+            let chunk_storage = &*self.chunks[idx];
+            let offset = if idx == start_chunk { start_offset } else { 0 };
+            let mut item_idxs: Vec<usize> = query
+                .item_idxs(chunk_storage.chunk_key(), chunk_storage)
+                .into_idx_iter()
+                .flatten()
+                .filter(|item_idx| *item_idx >= offset)
+                .collect();
+            item_idxs.sort_unstable();
+            item_idxs.dedup();
+
+            for item_idx in item_idxs {
+                if done(examined, elements.len()) {
+                    return Ok(Page {
+                        elements,
+                        next: Some(Cursor::new(self.id, idx, item_idx, generation)),
+                    });
+                }
+
+                examined += 1;
+                let element = chunk_storage.get_idx(item_idx);
+                if query.test(element) {
+                    elements.push(element);
+                }
+            }
+        }
+
+        Ok(Page {
+            elements,
+            next: None,
+        })
+    }
+
+    /// True IFF no element that a `Cursor` hasn't yet visited could have moved to a position
+    /// that it has.
+    fn is_valid_cursor(&self, cursor: &Cursor) -> bool {
+        cursor.storage_id() == self.id
+            && self.generation <= cursor.generation()
+            && self
+                .chunks
+                .get(cursor.chunk())
+                .map(|chunk| chunk.generation() <= cursor.generation())
+                .unwrap_or(false)
+    }
+
+    /// Choose a uniform random sample of `n` of the elements matching some Query, or all of the
+    /// matching elements if there are no more than `n`. The sample is in no particular order.
+    ///
+    /// Each chunk is sampled on its own, and each chunk's sample is merged into the overall
+    /// sample in proportion to the number of matching elements in that chunk, so at most `n`
+    /// elements are held from the overall sample and from the current chunk at any one time.
+    /// Every matching element is still visited once.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    /// use rand::SeedableRng;
+    /// use rand::rngs::StdRng;
+    ///
+    /// let mut storage : Storage<u64, u64, (u64, u64, u64)> = Storage::new();
+    ///
+    /// for i in 0..1000 {
+    ///   storage.add((i % 7, i, i * 3));
+    /// }
+    ///
+    /// let mut rng = StdRng::seed_from_u64(17);
+    ///
+    /// let sample = storage.sample(Everything, 10, &mut rng);
+    /// assert_eq!(10, sample.len());
+    /// assert!(sample.iter().all(|x| x.2 == x.1 * 3));
+    ///
+    /// let sample = storage.sample(Chunks([3]), 1000, &mut rng);
+    /// assert_eq!(storage.query(Chunks([3])).count(), sample.len());
+    /// ```
+    #[cfg(feature = "rand")]
+    pub fn sample<'a, Q, R>(&'a self, query: Q, n: usize, rng: &mut R) -> Vec<&'a Element>
+    where
+        Q: Query<ChunkKey, ItemKey, Element> + Clone + 'a,
+        R: rand::Rng + ?Sized,
+    {
+        let mut result = Reservoir::new(n);
+
+        for idx in query.chunk_idxs(self).into_idx_iter().flatten() {
+            let mut chunk_sample = Reservoir::new(n);
+
+            for element in self.chunks[idx].query(query.clone()) {
+                chunk_sample.offer(element, rng);
+            }
+
+            if chunk_sample.seen() > 0 {
+                result = result.merge(chunk_sample, rng);
+            }
+        }
+
+        result.into_sample()
+    }
+
+    /// Reduce the elements matching some Query down to a single value in one pass, without
+    /// setting up a `Reduction`. The `Map` rule summarizes each element, and the `Fold` rule
+    /// folds several summaries into one: first the summaries of the elements of each chunk,
+    /// then the summaries of the chunks. Returns `None` if no element matches.
+    ///
+    /// Nothing is remembered between calls, so use a `Reduction` to reduce the same elements
+    /// again and again as they change.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    ///
+    /// // Readings chunked by sensor, keyed by reading number.
+    /// let mut storage : Storage<u64, u64, (u64, u64, i64)> = Storage::new();
+    ///
+    /// for i in 0..1000 {
+    ///   storage.add((i % 10, i, i as i64 - 500));
+    /// }
+    ///
+    /// let lowest = storage.reduce(
+    ///   Chunks([3, 4]),
+    ///   |x: &(u64, u64, i64)| x.2,
+    ///   |xs: &[i64]| *xs.iter().min().unwrap());
+    /// assert_eq!(Some(-497), lowest);
+    ///
+    /// let none = storage.reduce(Chunks([10]), |x: &(u64, u64, i64)| x.2, |xs: &[i64]| xs[0]);
+    /// assert_eq!(None, none);
+    /// ```
+    pub fn reduce<Q, Map, Fold, Summary>(&self, query: Q, map: Map, fold: Fold) -> Option<Summary>
+    where
+        Q: Query<ChunkKey, ItemKey, Element> + Clone,
+        Map: Fn(&Element) -> Summary,
+        Fold: Fn(&[Summary]) -> Summary,
+    {
+        let chunk_summaries: Vec<Summary> = query
+            .chunk_idxs(self)
+            .into_idx_iter()
+            .flatten()
+            .filter_map(|idx| self.reduce_chunk_idx(idx, &query, &map, &fold))
+            .collect();
+
+        Some(chunk_summaries)
+            .filter(|summaries| !summaries.is_empty())
+            .map(|summaries| fold(&summaries))
+    }
+
+    /// Like `Storage::reduce`, but if the query is large enough, according to this `Storage`'s
+    /// `Parallelism`, chunks are reduced in parallel.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    /// use retriever::types::parallelism::Parallelism;
+    ///
+    /// // Readings chunked by sensor, keyed by reading number.
+    /// let mut storage : Storage<u64, u64, (u64, u64, i64)> = Storage::new();
+    /// storage.set_parallelism(Parallelism::parallel());
+    ///
+    /// for i in 0..1000 {
+    ///   storage.add((i % 10, i, i as i64 - 500));
+    /// }
+    ///
+    /// let lowest = storage.par_reduce(
+    ///   Chunks([3, 4]),
+    ///   |x: &(u64, u64, i64)| x.2,
+    ///   |xs: &[i64]| *xs.iter().min().unwrap());
+    /// assert_eq!(Some(-497), lowest);
+    ///
+    /// let none = storage.par_reduce(Chunks([10]), |x: &(u64, u64, i64)| x.2, |xs: &[i64]| xs[0]);
+    /// assert_eq!(None, none);
+    /// ```
+    #[cfg(feature = "rayon")]
+    pub fn par_reduce<Q, Map, Fold, Summary>(
+        &self,
+        query: Q,
+        map: Map,
+        fold: Fold,
+    ) -> Option<Summary>
+    where
+        Self: Sync,
+        Q: Query<ChunkKey, ItemKey, Element> + Clone + Send + Sync,
+        Map: Fn(&Element) -> Summary + Send + Sync,
+        Fold: Fn(&[Summary]) -> Summary + Send + Sync,
+        Summary: Send,
+    {
+        let chunk_idxs: Vec<usize> = query.chunk_idxs(self).into_idx_iter().flatten().collect();
+
+        let chunk_summaries: Vec<Summary> = if self.is_parallel(&chunk_idxs) {
+            chunk_idxs
+                .par_iter()
+                .filter_map(|idx| self.reduce_chunk_idx(*idx, &query, &map, &fold))
+                .collect()
+        } else {
+            chunk_idxs
+                .iter()
+                .filter_map(|idx| self.reduce_chunk_idx(*idx, &query, &map, &fold))
+                .collect()
+        };
+
+        Some(chunk_summaries)
+            .filter(|summaries| !summaries.is_empty())
+            .map(|summaries| fold(&summaries))
+    }
+
+    // The fold of the summaries of the elements of one chunk that match a query, or None if
+    // there are none.
+    fn reduce_chunk_idx<Q, Map, Fold, Summary>(
+        &self,
+        idx: usize,
+        query: &Q,
+        map: &Map,
+        fold: &Fold,
+    ) -> Option<Summary>
+    where
+        Q: Query<ChunkKey, ItemKey, Element> + Clone,
+        Map: Fn(&Element) -> Summary,
+        Fold: Fn(&[Summary]) -> Summary,
+    {
+        let summaries: Vec<Summary> = self.chunks[idx].query(query.clone()).map(map).collect();
+
+        Some(summaries)
+            .filter(|summaries| !summaries.is_empty())
+            .map(|summaries| fold(&summaries))
+    }
+
+    /// Call a function on every element matching some Query. If the query is large enough,
+    /// according to this `Storage`'s `Parallelism`, chunks are visited in parallel.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    /// use retriever::types::parallelism::Parallelism;
+    /// use std::sync::atomic::{AtomicU64, Ordering};
+    ///
+    /// let mut storage : Storage<u64, u64, (u64, u64, u64)> = Storage::new();
+    /// storage.set_parallelism(Parallelism::parallel());
+    ///
+    /// for i in 0..1000 {
+    ///   storage.add((i % 10, i, i));
+    /// }
+    ///
+    /// let total = AtomicU64::new(0);
+    /// storage.par_for_each(&Everything, |x| {
+    ///   total.fetch_add(x.2, Ordering::Relaxed);
+    /// });
+    ///
+    /// assert_eq!(total.into_inner(), (0..1000).sum::<u64>());
+    /// ```
+    #[cfg(feature = "rayon")]
+    pub fn par_for_each<Q, F>(&self, query: Q, f: F)
+    where
+        Self: Sync,
+        Q: Query<ChunkKey, ItemKey, Element> + Clone + Send + Sync,
+        F: Fn(&Element) + Send + Sync,
+    {
+        let chunk_idxs: Vec<usize> = query.chunk_idxs(self).into_idx_iter().flatten().collect();
+
+        if self.is_parallel(&chunk_idxs) {
+            chunk_idxs.par_iter().for_each(|idx| {
+                self.chunks[*idx].query(query.clone()).for_each(&f);
+            });
+        } else {
+            for idx in chunk_idxs {
+                self.chunks[idx].query(query.clone()).for_each(&f);
+            }
+        }
+    }
+
+    /// Iterate over the elements matching some Query as a rayon `ParallelIterator`, visiting
+    /// chunks in parallel. Unlike `Storage::par_for_each`, this always runs on the rayon thread
+    /// pool, regardless of this `Storage`'s `Parallelism`, and the full set of rayon adapters
+    /// is available.
+    ///
+    /// Each chunk is visited by a single thread, so this gains nothing over `Storage::query`
+    /// when all of the matching elements live in the same chunk.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    /// use rayon::prelude::*;
+    ///
+    /// let mut storage : Storage<u64, u64, (u64, u64, u64)> = Storage::new();
+    ///
+    /// for i in 0..1000 {
+    ///   storage.add((i % 10, i, i));
+    /// }
+    ///
+    /// let total : u64 = storage
+    ///   .par_query(Everything.filter(|x: &(u64, u64, u64)| x.2 % 2 == 0))
+    ///   .map(|x| x.2)
+    ///   .sum();
+    ///
+    /// assert_eq!((0..1000).filter(|i| i % 2 == 0).sum::<u64>(), total);
+    /// ```
+    #[cfg(feature = "rayon")]
+    pub fn par_query<'a, Q>(&'a self, query: Q) -> impl ParallelIterator<Item = &'a Element> + 'a
+    where
+        Self: Sync,
+        Element: Sync,
+        Q: Query<ChunkKey, ItemKey, Element> + Clone + Send + Sync + 'a,
+    {
+        let chunk_idxs: Vec<usize> = query.chunk_idxs(self).into_idx_iter().flatten().collect();
+
+        chunk_idxs
+            .into_par_iter()
+            .flat_map_iter(move |idx| self.chunks[idx].query(query.clone()))
+    }
+
+    /// Build a throwaway `SecondaryIndex`, use it, and discard it. This suits one-off
+    /// analytical jobs that need better-than-linear filtering within large chunks, but that
+    /// don't justify keeping an index up to date forever.
+    ///
+    /// Like any `SecondaryIndex`, the temporary index only indexes the chunks visited by
+    /// queries that match against it, so the cost is proportional to the chunks those queries
+    /// touch rather than to the whole `Storage`. All of its memory is released as soon as the
+    /// given function returns.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    /// use std::borrow::Cow;
+    ///
+    /// // Orders chunked by day, and keyed by order number.
+    /// let mut storage : Storage<u64, u64, (u64, u64, &'static str)> = Storage::new();
+    ///
+    /// for i in 0..1000 {
+    ///   storage.add((i / 100, i, if i % 10 == 0 { "refunded" } else { "shipped" }));
+    /// }
+    ///
+    /// let refunds_on_day_3 = storage.with_temp_index(
+    ///   |x: &(u64, u64, &'static str)| Cow::Owned(Some(x.2)),
+    ///   |by_status: &SecondaryIndex<_, _, Option<&'static str>, &'static str>, storage| {
+    ///     storage
+    ///       .query(Chunks([3]).matching(by_status, Cow::Owned("refunded")))
+    ///       .count()
+    ///   });
+    ///
+    /// assert_eq!(10, refunds_on_day_3);
+    /// ```
+    pub fn with_temp_index<IndexKeys, IndexKey, F, G, T>(&self, f: F, g: G) -> T
+    where
+        IndexKey: BorrowedKey + ?Sized,
+        IndexKey::Owned: ValidKey,
+        for<'k> IndexKeys: Clone + Debug + Default + Eq + KeySet<'k, IndexKey>,
+        F: Fn(&Element) -> Cow<IndexKeys> + Clone + Send + Sync + 'static,
+        G: FnOnce(&SecondaryIndex<ChunkKey, Element, IndexKeys, IndexKey>, &Self) -> T,
+    {
+        let secondary_index = SecondaryIndex::new(self, f);
+        g(&secondary_index, self)
+    }
+
+    /// Iterate over elements according to some Query, yielding each element together with
+    /// its chunk key and item key. This is intended to feed serializers and other export
+    /// pipelines directly.
+    ///
+    /// Elements are always yielded in order of chunk key and then item key, regardless of
+    /// the order in which they were added or the platform, so exported files are byte-stable
+    /// for signing and content-addressed storage. Each chunk is sorted as it is reached.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    ///
+    /// let mut storage : Storage<&'static str, u64, (&'static str, u64, f64)> = Storage::new();
+    ///
+    /// storage.add(("celsius", 1, 21.5));
+    /// storage.add(("celsius", 2, 22.0));
+    /// storage.add(("fahrenheit", 3, 70.1));
+    ///
+    /// let mut lines : Vec<String> = storage
+    ///   .export_records(Chunks(["celsius"]))
+    ///   .map(|record| format!("{},{},{}", record.chunk_key, record.item_key, record.element.2))
+    ///   .collect();
+    ///
+    /// assert_eq!(lines, vec!["celsius,1,21.5", "celsius,2,22"]);
+    /// ```
+    pub fn export_records<'a, Q>(
+        &'a self,
+        query: Q,
+    ) -> impl Iterator<Item = ExportRecord<'a, ChunkKey, ItemKey, Element>>
+    where
+        Q: Query<ChunkKey, ItemKey, Element> + Clone + 'a,
+    {
+        let chunk_idxs = query.chunk_idxs(self);
+
+        self.sorted_chunk_idxs(chunk_idxs.into_idx_iter().flatten())
+            .into_iter()
+            .map(move |idx| &*self.chunks[idx])
+            .flat_map(
+                move |chunk_storage: &'a ChunkStorage<ChunkKey, ItemKey, Element>| {
+                    let chunk_key = chunk_storage.chunk_key();
+                    let mut elements: Vec<&'a Element> =
+                        chunk_storage.query(query.clone()).collect();
+                    elements.sort_by(|a, b| a.item_key().cmp(&b.item_key()));
+
+                    elements.into_iter().map(move |element| ExportRecord {
+                        chunk_key,
+                        item_key: element.item_key(),
+                        element,
+                    })
+                },
+            )
+    }
+
+    /// Iterate over a Query and modify each element via a callback.
+    /// The callback provides retriever's Editor API, which in turn provides
+    /// a mutable or immutable reference to the underlying element.
+    ///
+    /// Since re-indexing is a potentially expensive operation, it's best to examine an immutable
+    /// reference to a data element to make sure you really want to mutate it before obtaining a
+    /// mutable reference.
+    ///
+    /// # Type Parameters
+    ///
+    /// * `Q`: Any `Query`. There are a variety of useful `Queries`:
+    ///   * `Everything`
+    ///   * `Chunks(...)`, an explicit list of chunks
+    ///   * `Id`, the Id of a specific element
+    ///   * Most other `Queries` can be constructed by chaining the methods of the `Query` trait.
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    /// use std::borrow::Cow;
+    ///
+    /// struct BankAccount {
+    ///   id: usize,
+    ///   balance: i64,
+    /// }
+    ///
+    /// impl Record<(),usize> for BankAccount {
+    ///   fn chunk_key(&self) -> Cow<()> {
+    ///     Cow::Owned(())
+    ///   }
+    ///
+    ///   fn item_key(&self) -> Cow<usize> {
+    ///     Cow::Owned(self.id)
+    ///   }
+    /// }
+    ///
+    /// let mut storage : Storage<(),usize,BankAccount> = Storage::new();
+    ///
+    /// storage.add(BankAccount { id: 1, balance: 25 });
+    /// storage.add(BankAccount { id: 2, balance: 13 });
+    /// storage.add(BankAccount { id: 3, balance: -900 });
+    /// storage.add(BankAccount { id: 4, balance: 27000 });
+    /// storage.add(BankAccount { id: 5, balance: -13 });
+    ///
+    /// // Charge an overdraft fee to everyone with a negative balance.
+    /// storage.modify(Everything.filter(|account : &BankAccount| account.balance < 0), |mut account| {
+    ///   account.get_mut().balance -= 25;
+    /// });
+    ///
+    /// assert_eq!(Some(25),    storage.get(&ID.item(1)).map(|x| x.balance));
+    /// assert_eq!(Some(13),    storage.get(&ID.item(2)).map(|x| x.balance));
+    /// assert_eq!(Some(-925),  storage.get(&ID.item(3)).map(|x| x.balance));
+    /// assert_eq!(Some(27000), storage.get(&ID.item(4)).map(|x| x.balance));
+    /// assert_eq!(Some(-38),   storage.get(&ID.item(5)).map(|x| x.balance));
+    ///
+    /// # storage.validate();
+    /// ```
+    pub fn modify<Q, F>(&mut self, query: Q, f: F)
+    where
+        Q: Query<ChunkKey, ItemKey, Element>,
+        F: Fn(Editor<ChunkKey, ItemKey, Element>),
+    {
+        self.clean();
+
+        let mut modified = Bitset::default();
+
+        for idx in query.chunk_idxs(self).into_idx_iter().flatten() {
+            self.chunk_mut(idx).modify(&query, &f);
+            modified.set(idx);
+        }
+
+        self.maintain(&modified);
+    }
+
+    /// Modify each element matching some Query, as `Storage::modify` does. If the query is
+    /// large enough, according to this `Storage`'s `Parallelism`, chunks are modified in
+    /// parallel, each by exactly one thread.
+    ///
+    /// Only the edits themselves run in parallel. Indexes maintained eagerly, using
+    /// `SecondaryIndex::maintain_eagerly`, are brought up to date afterwards on the calling
+    /// thread. Other indexes catch up on their next query, as usual, or all at once in parallel
+    /// using `SecondaryIndex::par_rebuild`.
+    ///
+    /// Each chunk is modified by a single thread, so this gains nothing over `Storage::modify`
+    /// when all of the matching elements live in the same chunk.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    /// use retriever::types::parallelism::Parallelism;
+    ///
+    /// let mut storage : Storage<u64, u64, (u64, u64, u64)> = Storage::new();
+    /// storage.set_parallelism(Parallelism::parallel());
+    ///
+    /// for i in 0..1000 {
+    ///   storage.add((i % 10, i, 0));
+    /// }
+    ///
+    /// // Recompute a derived field of every element.
+    /// storage.par_modify(&Everything, |mut editor| {
+    ///   let square = editor.get().1 * editor.get().1;
+    ///   editor.get_mut().2 = square;
+    /// });
+    ///
+    /// assert_eq!(Some(&(7, 997, 994009)), storage.get(&ID.chunk(7).item(997)));
+    /// # storage.validate();
+    /// ```
+    #[cfg(feature = "rayon")]
+    pub fn par_modify<Q, F>(&mut self, query: Q, f: F)
+    where
+        ChunkKey::Owned: Send,
+        ItemKey::Owned: Send,
+        Element: Send,
+        Q: Query<ChunkKey, ItemKey, Element> + Sync,
+        F: Fn(Editor<ChunkKey, ItemKey, Element>) + Send + Sync,
+    {
+        self.clean();
+
+        let mut chunk_idxs: Vec<usize> = query.chunk_idxs(self).into_idx_iter().flatten().collect();
+        chunk_idxs.sort_unstable();
+        chunk_idxs.dedup();
+
+        let parallel = self.is_parallel(&chunk_idxs);
+        let mut modified = Bitset::default();
+
+        for idx in chunk_idxs.iter() {
+            self.chunk_mut(*idx);
+            modified.set(*idx);
+        }
+
+        // Every chunk was touched by chunk_mut, so there's nothing more to track here.
+        let (chunks, _) = self.chunks.split_mut();
+        let mut chunk_idxs = chunk_idxs.into_iter().peekable();
+        let mut chunks: Vec<&mut ChunkStorage<ChunkKey, ItemKey, Element>> = chunks
+            .iter_mut()
+            .enumerate()
+            .filter(|(idx, _)| chunk_idxs.next_if_eq(idx).is_some())
+            .map(|(_, chunk)| Arc::get_mut(chunk).expect("retriever bug: chunk should be unshared"))
+            .collect();
+
+        if parallel {
+            chunks
+                .par_iter_mut()
+                .for_each(|chunk| chunk.modify(&query, &f));
+        } else {
+            for chunk in chunks {
+                chunk.modify(&query, &f);
+            }
+        }
+
+        self.maintain(&modified);
+    }
+
+    /// Iterate over a Query, yielding an `Editor` for each element, as `Storage::modify` does.
+    /// Because this is an ordinary `Iterator`, you can use `?`, break out early, or interleave it
+    /// with other iterators.
+    ///
+    /// Re-indexing is deferred until the returned `QueryMut`, and every `Editor` it produced,
+    /// has been dropped. Every chunk the Query might visit is copied up front if it's shared
+    /// with a clone of this `Storage`, even if you stop iterating before reaching it.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    ///
+    /// let mut storage : Storage<u64, u64, (u64, u64, String)> = Storage::new();
+    ///
+    /// storage.add((1, 1, String::from("7")));
+    /// storage.add((1, 2, String::from("12")));
+    /// storage.add((2, 3, String::from("seven")));
+    /// storage.add((2, 4, String::from("3")));
+    ///
+    /// fn double_all(storage: &mut Storage<u64, u64, (u64, u64, String)>)
+    ///   -> Result<(), std::num::ParseIntError>
+    /// {
+    ///   for mut editor in storage.query_mut(Chunks([1])) {
+    ///     let n : u64 = editor.get().2.parse()?;
+    ///     editor.get_mut().2 = (n * 2).to_string();
+    ///   }
+    ///
+    ///   Ok(())
+    /// }
+    ///
+    /// assert!(double_all(&mut storage).is_ok());
+    /// assert_eq!("14", storage.get(&ID.chunk(1).item(1)).unwrap().2);
+    /// assert_eq!("24", storage.get(&ID.chunk(1).item(2)).unwrap().2);
+    ///
+    /// // Stop at the first element that isn't a number.
+    /// let parsed : Result<Vec<u64>, _> = storage
+    ///   .query_mut(Chunks([2]))
+    ///   .map(|editor| editor.get().2.parse::<u64>())
+    ///   .collect();
+    /// assert!(parsed.is_err());
+    ///
+    /// # storage.validate();
+    /// ```
+    pub fn query_mut<'a, Q>(&'a mut self, query: Q) -> QueryMut<'a, ChunkKey, ItemKey, Element, Q>
+    where
+        Q: Query<ChunkKey, ItemKey, Element> + 'a,
+    {
+        self.clean();
+
+        let mut chunk_idxs: Vec<usize> = query.chunk_idxs(self).into_idx_iter().flatten().collect();
+        chunk_idxs.sort_unstable();
+        chunk_idxs.dedup();
+
+        for idx in chunk_idxs.iter() {
+            self.chunk_mut(*idx);
+            self.dirty(*idx);
+        }
+
+        // Every chunk was touched by chunk_mut, so there's nothing more to track here.
+        let (chunks, _) = self.chunks.split_mut();
+        let mut chunk_idxs = chunk_idxs.into_iter().peekable();
+        let chunks = chunks
+            .iter_mut()
+            .enumerate()
+            .filter(|(idx, _)| chunk_idxs.next_if_eq(idx).is_some())
+            .map(|(_, chunk)| Arc::get_mut(chunk).expect("retriever bug: chunk should be unshared"))
+            .collect();
+
+        QueryMut::new(query, chunks)
+    }
+
+    /// Remove all of the specified elements from this storage.
+    ///
+    /// # Type Parameters
+    ///
+    /// * `Q`: Any `Query`. There are a variety of useful `Queries`:
+    ///   * `Everything`
+    ///   * `Chunks(...)`, an explicit list of chunks
+    ///   * `Id`, the Id of a specific element
+    ///   * Most other `Queries` can be constructed by chaining the methods of the `Query` trait.
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    /// use retriever::queries::everything::Everything;
+    /// use std::borrow::Cow;
+    ///
+    /// // In this example, we will store log entries, some of which might contain sensitive
+    /// // information that we need to delete later.
+    /// struct LogEntry {
+    ///   stardate: u128,
+    ///   msg: String,
+    /// }
+    ///
+    /// impl Record<u128, u128> for LogEntry {
+    ///   fn chunk_key(&self) -> Cow<u128> {
+    ///     Cow::Owned(self.stardate / 1000)
+    ///   }
+    ///
+    ///   fn item_key(&self) -> Cow<u128> {
+    ///     Cow::Borrowed(&self.stardate)
+    ///   }
+    /// }
+    ///
+    /// let mut storage : Storage<u128, u128, LogEntry> = Storage::new();
+    ///
+    /// storage.add(LogEntry {
+    ///   stardate: 109301,
+    ///   msg: String::from("Departed from Starbase Alpha"),
+    /// });
+    ///
+    /// storage.add(LogEntry {
     ///   stardate: 109302,
     ///   msg: String::from("Purchased illegal cloaking device from aliens"),
     /// });
@@ -685,57 +2946,511 @@ where
     ///
     /// # storage.validate();
     /// ```
-    pub fn remove<Q, F>(&mut self, query: Q, f: F)
+    pub fn remove<Q, F>(&mut self, query: Q, mut f: F)
     where
-        F: Fn(Element),
+        F: FnMut(Element),
         Q: Query<ChunkKey, ItemKey, Element>,
     {
         for idx in query.chunk_idxs(self).into_idx_iter().flatten() {
             self.dirty(idx);
-            self.chunks[idx].remove(&query, &f);
+
+            if self.item_index.is_none() {
+                self.chunk_mut(idx).remove(&query, &mut f);
+                continue;
+            }
+
+            let mut removed_item_keys = Vec::new();
+            self.chunk_mut(idx).remove(&query, &mut |element: Element| {
+                removed_item_keys.push(element.item_key().into_owned());
+                f(element);
+            });
+
+            let chunk_key = self.chunks[idx].chunk_key().to_owned();
+            self.unindex_items(chunk_key.borrow(), removed_item_keys);
         }
 
         self.clean();
     }
 
+    /// Remove all of the specified elements from this storage and return them.
+    ///
+    /// This is a convenience wrapper around `Storage::remove` for when you just want the
+    /// removed elements back.
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    ///
+    /// let mut storage : Storage<u64, u64, (u64, u64, &'static str)> = Storage::new();
+    ///
+    /// storage.add((1, 1, "apple"));
+    /// storage.add((1, 2, "banana"));
+    /// storage.add((2, 3, "avocado"));
+    ///
+    /// let mut removed = storage.remove_collect(Everything.filter(|x: &(u64, u64, &'static str)| {
+    ///   x.2.starts_with('a')
+    /// }));
+    /// removed.sort();
+    ///
+    /// assert_eq!(removed, vec![(1, 1, "apple"), (2, 3, "avocado")]);
+    /// assert_eq!(storage.iter().count(), 1);
+    ///
+    /// # storage.validate();
+    /// ```
+    pub fn remove_collect<Q>(&mut self, query: Q) -> Vec<Element>
+    where
+        Q: Query<ChunkKey, ItemKey, Element>,
+    {
+        let mut result = Vec::new();
+        self.remove(query, |element| result.push(element));
+        result
+    }
+
     /// List all chunks
     pub fn chunk_keys(&self) -> impl IntoIterator<Item = &ChunkKey> {
         self.chunks.iter().map(|chunk| chunk.chunk_key())
     }
 
+    /// The smallest item key in the given chunk, or None if there is no such chunk.
+    ///
+    /// This never scans the chunk if the `Storage` keeps its item keys in order
+    /// (see `StorageBuilder::ordered_item_keys`).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    ///
+    /// // Readings chunked by sensor, keyed by timestamp.
+    /// let mut storage : Storage<&'static str, u64, (&'static str, u64, f64)> = Storage::new();
+    ///
+    /// storage.add(("thermometer", 1700000300, 21.5));
+    /// storage.add(("thermometer", 1700000100, 21.0));
+    /// storage.add(("thermometer", 1700000200, 21.2));
+    /// storage.add(("barometer", 1700000900, 1013.0));
+    ///
+    /// assert_eq!(Some(&1700000100), storage.min_item_key(&"thermometer"));
+    /// assert_eq!(Some(&1700000300), storage.max_item_key(&"thermometer"));
+    /// assert_eq!(None, storage.min_item_key(&"hygrometer"));
+    ///
+    /// storage.remove(&ID.chunk("thermometer").item(1700000100), std::mem::drop);
+    /// assert_eq!(Some(&1700000200), storage.min_item_key(&"thermometer"));
+    ///
+    /// assert_eq!(Some(ID.chunk(&"thermometer").item(&1700000200)), storage.min_item_id());
+    /// assert_eq!(Some(ID.chunk(&"barometer").item(&1700000900)), storage.max_item_id());
+    ///
+    /// # storage.validate();
+    /// ```
+    pub fn min_item_key(&self, chunk_key: &ChunkKey) -> Option<&ItemKey> {
+        let idx = self.internal_idx_of(chunk_key)?;
+        self.chunks[idx].min_item_key()
+    }
+
+    /// The largest item key in the given chunk, or None if there is no such chunk.
+    ///
+    /// This never scans the chunk if the `Storage` keeps its item keys in order
+    /// (see `StorageBuilder::ordered_item_keys`).
+    pub fn max_item_key(&self, chunk_key: &ChunkKey) -> Option<&ItemKey> {
+        let idx = self.internal_idx_of(chunk_key)?;
+        self.chunks[idx].max_item_key()
+    }
+
+    /// The `Id` of the element with the smallest item key in this entire `Storage`. If several
+    /// chunks share that item key, the smallest chunk key wins.
+    ///
+    /// This visits every chunk, and also scans each chunk unless the `Storage` keeps its item
+    /// keys in order (see `StorageBuilder::ordered_item_keys`).
+    pub fn min_item_id(&self) -> Option<Id<&ChunkKey, &ItemKey>> {
+        self.chunks
+            .iter()
+            .filter_map(|chunk| Some(Id::new(chunk.chunk_key(), chunk.min_item_key()?)))
+            .min_by(|a, b| a.1.cmp(b.1).then_with(|| a.0.cmp(b.0)))
+    }
+
+    /// The `Id` of the element with the largest item key in this entire `Storage`. If several
+    /// chunks share that item key, the largest chunk key wins.
+    ///
+    /// This visits every chunk, and also scans each chunk unless the `Storage` keeps its item
+    /// keys in order (see `StorageBuilder::ordered_item_keys`).
+    pub fn max_item_id(&self) -> Option<Id<&ChunkKey, &ItemKey>> {
+        self.chunks
+            .iter()
+            .filter_map(|chunk| Some(Id::new(chunk.chunk_key(), chunk.max_item_key()?)))
+            .max_by(|a, b| a.1.cmp(b.1).then_with(|| a.0.cmp(b.0)))
+    }
+
+    /// Estimate the size of the elements in this storage, and report the `n` largest elements
+    /// and the `n` largest chunks. Use this to find oversized records and badly skewed chunks
+    /// when choosing a schema or a chunk key.
+    ///
+    /// The size of each element is measured by the given sizer function. This could be a
+    /// hand-written estimate of heap usage, or the length of the element's serialized form.
+    ///
+    /// At most 256 evenly spaced elements are measured in each chunk, and the size of
+    /// larger chunks is extrapolated from that sample, so an oversized element in a very large
+    /// chunk may be missed.
+    ///
+    /// Requires the `diagnostics` feature.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    ///
+    /// let mut storage : Storage<u64, u64, (u64, u64, String)> = Storage::new();
+    ///
+    /// storage.add((1, 1, String::from("short")));
+    /// storage.add((1, 2, String::from("a little longer")));
+    /// storage.add((2, 1, "enormous".repeat(100)));
+    ///
+    /// // Measure each element by the length of its JSON serialization.
+    /// let report = storage.size_outliers(1, |x| serde_json::to_vec(x).unwrap().len());
+    ///
+    /// assert_eq!(ID.chunk(2).item(1), report.largest_elements[0].id);
+    /// assert_eq!(2, report.largest_chunks[0].chunk_key);
+    /// assert!(report.largest_chunks[0].skew > 1.0);
+    /// assert_eq!(3, report.sampled);
+    /// ```
+    #[cfg(feature = "diagnostics")]
+    pub fn size_outliers<F>(
+        &self,
+        n: usize,
+        sizer: F,
+    ) -> SizeOutliers<ChunkKey::Owned, ItemKey::Owned>
+    where
+        F: Fn(&Element) -> usize,
+    {
+        let mut largest_elements = Vec::new();
+        let mut largest_chunks = Vec::new();
+        let mut sampled = 0;
+        let mut total = 0;
+
+        for chunk in self.chunks.iter() {
+            let stride = chunk.len().div_ceil(SIZE_SAMPLES_PER_CHUNK).max(1);
+            let mut chunk_sampled = 0;
+            let mut chunk_size = 0;
+
+            for element in chunk.raw().iter().step_by(stride) {
+                let size = sizer(element);
+                chunk_sampled += 1;
+                chunk_size += size;
+                largest_elements.push((size, element));
+            }
+
+            sampled += chunk_sampled;
+            total += chunk.len();
+            largest_chunks.push(ChunkSize {
+                chunk_key: chunk.chunk_key().to_owned(),
+                len: chunk.len(),
+                estimated_size: (chunk_size * chunk.len())
+                    .checked_div(chunk_sampled)
+                    .unwrap_or(0),
+                skew: 0.0,
+            });
+        }
+
+        let mean_chunk_size = largest_chunks
+            .iter()
+            .map(|chunk| chunk.estimated_size as f64)
+            .sum::<f64>()
+            / largest_chunks.len().max(1) as f64;
+        for chunk in largest_chunks.iter_mut() {
+            if mean_chunk_size > 0.0 {
+                chunk.skew = chunk.estimated_size as f64 / mean_chunk_size;
+            }
+        }
+
+        largest_elements.sort_by_key(|(size, _)| std::cmp::Reverse(*size));
+        largest_elements.truncate(n);
+        largest_chunks.sort_by_key(|chunk| std::cmp::Reverse(chunk.estimated_size));
+        largest_chunks.truncate(n);
+
+        SizeOutliers {
+            largest_elements: largest_elements
+                .into_iter()
+                .map(|(size, element)| ElementSize {
+                    id: Id::cloned(element),
+                    size,
+                })
+                .collect(),
+            largest_chunks,
+            sampled,
+            total,
+        }
+    }
+
     /// Drop an entire chunk and return all associated elements
     pub fn remove_chunk(&mut self, chunk_key: &ChunkKey) -> Option<Vec<Element>> {
+        let chunk = self.take_chunk(chunk_key)?;
+        Some(ChunkStorage::unwrap_or_unshare(chunk).into())
+    }
+
+    /// Move an entire chunk into another `Storage`, including its internal index, without
+    /// copying or re-indexing any elements. Returns false if there is no such chunk.
+    ///
+    /// If `dest` already has a chunk with the same chunk key, the elements are added to it
+    /// one at a time, just as if by `Storage::add`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    ///
+    /// let mut hot : Storage<u64, u64, (u64, u64, &'static str)> = Storage::new();
+    /// let mut cold : Storage<u64, u64, (u64, u64, &'static str)> = Storage::new();
+    ///
+    /// hot.add((1, 1, "old news"));
+    /// hot.add((1, 2, "more old news"));
+    /// hot.add((2, 1, "breaking news"));
+    ///
+    /// assert!(hot.transfer_chunk(&1, &mut cold));
+    /// assert!(!hot.transfer_chunk(&1, &mut cold));
+    ///
+    /// assert_eq!(hot.iter().count(), 1);
+    /// assert_eq!(cold.iter().count(), 2);
+    /// assert_eq!(Some(&(1, 2, "more old news")), cold.get(&ID.chunk(1).item(2)));
+    ///
+    /// # hot.validate();
+    /// # cold.validate();
+    /// ```
+    pub fn transfer_chunk(&mut self, chunk_key: &ChunkKey, dest: &mut Self) -> bool {
+        let chunk = match self.take_chunk(chunk_key) {
+            Some(chunk) => chunk,
+            None => return false,
+        };
+
+        dest.adopt_chunk(chunk);
+
+        true
+    }
+
+    /// Move every chunk of another `Storage` into this one, leaving the other `Storage` empty.
+    ///
+    /// Chunks whose chunk keys are not already present in this `Storage` are moved whole,
+    /// without copying or re-indexing any elements. If both `Storages` have a chunk with the
+    /// same chunk key, the elements of that chunk are added one at a time, just as if by
+    /// `Storage::add`. Use `Storage::try_append` to refuse such collisions instead.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    ///
+    /// let mut monday : Storage<u64, u64, (u64, u64, &'static str)> = Storage::new();
+    /// let mut tuesday : Storage<u64, u64, (u64, u64, &'static str)> = Storage::new();
+    ///
+    /// monday.add((1, 1, "coffee"));
+    /// tuesday.add((2, 1, "tea"));
+    /// tuesday.add((2, 2, "more tea"));
+    ///
+    /// monday.append(&mut tuesday);
+    ///
+    /// assert_eq!(monday.iter().count(), 3);
+    /// assert_eq!(tuesday.iter().count(), 0);
+    /// assert_eq!(Some(&(2, 2, "more tea")), monday.get(&ID.chunk(2).item(2)));
+    ///
+    /// # monday.validate();
+    /// # tuesday.validate();
+    /// ```
+    pub fn append(&mut self, other: &mut Self) -> &mut Self {
+        other.clean();
+
+        while !other.chunks.is_empty() {
+            let chunk = other.chunks.swap_remove(other.chunks.len() - 1);
+            self.adopt_chunk(chunk);
+        }
+
+        other.index.clear();
+        if let Some(ordered_index) = other.ordered_index.as_mut() {
+            ordered_index.clear();
+        }
+        if let Some(item_index) = other.item_index.as_mut() {
+            item_index.clear();
+        }
+        other.maintain(&Bitset::default());
+        other.generation = next_generation();
+
+        self
+    }
+
+    /// Move every chunk of another `Storage` into this one, without merging any chunks.
+    ///
+    /// If this `Storage`'s `Strictness` is `Error` (the default) and both `Storages` have a chunk
+    /// with the same chunk key, this method returns that chunk key and leaves both `Storages`
+    /// unchanged. Otherwise, this method behaves just like `Storage::append`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    ///
+    /// let mut storage : Storage<u64, u64, (u64, u64, &'static str)> = Storage::new();
+    /// let mut other : Storage<u64, u64, (u64, u64, &'static str)> = Storage::new();
+    ///
+    /// storage.add((1, 1, "hello"));
+    /// other.add((1, 2, "world"));
+    /// other.add((2, 1, "elsewhere"));
+    ///
+    /// let error = storage.try_append(&mut other).unwrap_err();
+    /// assert_eq!(error.chunk_key, 1);
+    /// assert_eq!(storage.iter().count(), 1);
+    /// assert_eq!(other.iter().count(), 2);
+    ///
+    /// other.remove_chunk(&1);
+    /// assert!(storage.try_append(&mut other).is_ok());
+    /// assert_eq!(storage.iter().count(), 2);
+    ///
+    /// # storage.validate();
+    /// # other.validate();
+    /// ```
+    pub fn try_append(
+        &mut self,
+        other: &mut Self,
+    ) -> Result<(), ChunkCollisionError<ChunkKey::Owned>> {
+        if self.strictness == Strictness::Error {
+            self.clean();
+            other.clean();
+
+            if let Some(chunk_key) = other
+                .chunks
+                .iter()
+                .map(|chunk| chunk.chunk_key())
+                .find(|chunk_key| self.index.contains_key(*chunk_key))
+            {
+                return Err(ChunkCollisionError {
+                    chunk_key: chunk_key.to_owned(),
+                });
+            }
+        }
+
+        self.append(other);
+
+        Ok(())
+    }
+
+    /// Attach an entire chunk to this Storage, merging it into any existing chunk with the
+    /// same chunk key.
+    fn adopt_chunk(&mut self, chunk: Arc<ChunkStorage<ChunkKey, ItemKey, Element>>) {
+        self.clean();
+
+        if self.index.contains_key(chunk.chunk_key()) {
+            let elements: Vec<Element> = ChunkStorage::unwrap_or_unshare(chunk).into();
+            for element in elements {
+                self.add(element);
+            }
+        } else {
+            self.index
+                .insert(chunk.chunk_key().to_owned(), self.chunks.len());
+            if let Some(ordered_index) = self.ordered_index.as_mut() {
+                ordered_index.insert(chunk.chunk_key().to_owned());
+            }
+            self.chunks.push(chunk);
+            self.index_items(self.chunks.len() - 1, 0);
+            self.maintain(&std::iter::once(self.chunks.len() - 1).collect());
+        }
+    }
+
+    /// Detach an entire chunk from this Storage.
+    fn take_chunk(
+        &mut self,
+        chunk_key: &ChunkKey,
+    ) -> Option<Arc<ChunkStorage<ChunkKey, ItemKey, Element>>> {
         self.clean();
         let idx = self.index.remove(chunk_key)?;
+        if let Some(ordered_index) = self.ordered_index.as_mut() {
+            ordered_index.remove(chunk_key);
+        }
         let chunk = self.chunks.swap_remove(idx);
-        Some(chunk.into())
+        self.generation = next_generation();
+
+        if idx < self.chunks.len() {
+            self.index
+                .insert(self.chunks[idx].chunk_key().to_owned(), idx);
+        }
+
+        self.unindex_items(
+            chunk.chunk_key().borrow(),
+            chunk.raw().iter().map(|element| element.item_key()),
+        );
+        self.maintain(&Bitset::default());
+
+        Some(chunk)
     }
 
     /// Panic if this storage is malformed or broken in any way.
     /// This is a slow operation and you shouldn't use it unless you suspect a problem.
     pub fn validate(&mut self) {
+        if let Err(error) = self.try_validate() {
+            panic!("{}", error);
+        }
+    }
+
+    /// Check that this storage is well-formed, and describe the first problem found if it
+    /// isn't. This is the non-panicking form of `Storage::validate`, suitable for periodic
+    /// integrity checks in a long-running service.
+    /// This is a slow operation and you shouldn't use it unless you suspect a problem.
+    ///
+    /// ```
+    /// use retriever::prelude::*;
+    ///
+    /// let mut storage : Storage<u64, u64, (u64, u64, &'static str)> = Storage::new();
+    ///
+    /// storage.add((1, 1, "hello"));
+    /// storage.add((2, 1, "world"));
+    ///
+    /// assert_eq!(Ok(()), storage.try_validate());
+    /// ```
+    pub fn try_validate(&mut self) -> Result<(), ValidationError<ChunkKey::Owned, ItemKey::Owned>> {
         self.clean();
 
         for (idx, chunk) in self.chunks.iter().enumerate() {
-            assert_eq!(
-                self.index.get(chunk.chunk_key()),
-                Some(&idx),
-                "chunk not indexed"
-            );
+            if self.index.get(chunk.chunk_key()) != Some(&idx) {
+                return Err(ValidationError::ChunkNotIndexed {
+                    chunk_key: chunk.chunk_key().to_owned(),
+                    idx,
+                });
+            }
         }
 
         for (chunk_key, idx) in self.index.iter() {
-            assert_eq!(
-                self.chunks[*idx].chunk_key(),
-                chunk_key.borrow(),
-                "index broken"
-            );
-            assert_ne!(self.chunks[*idx].len(), 0, "empty chunk");
+            let chunk = match self.chunks.get(*idx) {
+                Some(chunk) if chunk.chunk_key() == chunk_key.borrow() => chunk,
+                _ => {
+                    return Err(ValidationError::BrokenChunkIndex {
+                        chunk_key: chunk_key.clone(),
+                        idx: *idx,
+                    });
+                }
+            };
+
+            if chunk.is_empty() {
+                return Err(ValidationError::EmptyChunk {
+                    chunk_key: chunk_key.clone(),
+                });
+            }
+        }
+
+        if let Some(ordered_index) = self.ordered_index.as_ref() {
+            if let Some(chunk_key) = self
+                .index
+                .keys()
+                .find(|chunk_key| !ordered_index.contains((*chunk_key).borrow()))
+                .or_else(|| {
+                    ordered_index
+                        .iter()
+                        .find(|chunk_key| !self.index.contains_key((*chunk_key).borrow()))
+                })
+            {
+                return Err(ValidationError::BrokenOrderedChunkIndex {
+                    chunk_key: chunk_key.clone(),
+                });
+            }
         }
 
         for chunk in self.chunks.iter() {
-            chunk.validate();
+            chunk.try_validate()?;
         }
+
+        Ok(())
     }
 
     pub(crate) fn internal_idx_of<Q>(&self, chunk_key: &Q) -> Option<usize>
@@ -746,7 +3461,31 @@ where
         self.index.get(chunk_key).cloned()
     }
 
-    pub(crate) fn internal_rvec(&self) -> &RVec<ChunkStorage<ChunkKey, ItemKey, Element>> {
+    /// The internal indices of every chunk whose chunk key falls within the given range.
+    pub(crate) fn internal_idxs_in_range(
+        &self,
+        start: Bound<&ChunkKey>,
+        end: Bound<&ChunkKey>,
+    ) -> Bitset {
+        if !is_valid_range(start, end) {
+            return Bitset::default();
+        }
+
+        match self.ordered_index.as_ref() {
+            Some(ordered_index) => ordered_index
+                .range::<ChunkKey, _>((start, end))
+                .map(|chunk_key| self.index[chunk_key.borrow()])
+                .collect(),
+            None => self
+                .index
+                .iter()
+                .filter(|(chunk_key, _)| (start, end).contains::<ChunkKey>((*chunk_key).borrow()))
+                .map(|(_, idx)| *idx)
+                .collect(),
+        }
+    }
+
+    pub(crate) fn internal_rvec(&self) -> &RVec<Arc<ChunkStorage<ChunkKey, ItemKey, Element>>> {
         &self.chunks
     }
 
@@ -756,11 +3495,27 @@ where
     ///
     /// The `chunk_list` parameter is an `RVec` containing all chunk keys. It is created for the
     /// purpose of being managed by this method and is managed entirely and only by this method.
-    pub(crate) fn gc<T>(
+    pub(crate) fn gc<T, S: BuildHasher>(
         &self,
         chunk_list: &mut RVec<Option<ChunkKey::Owned>>,
-        data: &mut HashMap<ChunkKey::Owned, T, crate::internal::hasher::HasherImpl>,
+        data: &mut HashMap<ChunkKey::Owned, T, S>,
     ) {
+        self.gc_with(chunk_list, data, |_, t| std::mem::drop(t));
+    }
+
+    /// Like `gc`, but hands each deleted entry, with its chunk key, to `f` instead of dropping it.
+    pub(crate) fn gc_with<T, S, F>(
+        &self,
+        chunk_list: &mut RVec<Option<ChunkKey::Owned>>,
+        data: &mut HashMap<ChunkKey::Owned, T, S>,
+        mut f: F,
+    ) where
+        S: BuildHasher,
+        F: FnMut(ChunkKey::Owned, T),
+    {
+        // If the chunk list starts over, it can't tell which chunks have gone since last time.
+        let reset = chunk_list.would_reset(&self.chunks);
+
         let mut removed: HashSet<ChunkKey::Owned, _> =
             HashSet::with_hasher(crate::internal::hasher::HasherImpl::default());
         let mut added: HashSet<ChunkKey::Owned, _> =
@@ -785,8 +3540,100 @@ where
             }
         });
 
+        if reset {
+            removed.extend(
+                data.keys()
+                    .filter(|chunk_key| self.internal_idx_of((*chunk_key).borrow()).is_none())
+                    .cloned(),
+            );
+        }
+
         for chunk_key in removed.difference(&added) {
-            data.remove(chunk_key.borrow());
+            if let Some((chunk_key, t)) = data.remove_entry(chunk_key.borrow()) {
+                f(chunk_key, t);
+            }
+        }
+    }
+}
+
+impl<'a, ChunkKey, ItemKey, Element> IntoIterator for &'a Storage<ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+{
+    type Item = &'a Element;
+    type IntoIter = Iter<'a, ChunkKey, ItemKey, Element>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Look up an element by its `Id`, in the same manner as `Storage::get`.
+///
+/// # Panic
+///
+/// Panics if there is no element with the given `Id`.
+///
+/// # Example
+///
+/// ```
+/// use retriever::prelude::*;
+///
+/// let mut storage : Storage<u64, u64, (u64, u64, &'static str)> = Storage::new();
+/// storage.add((1, 2, "hello"));
+///
+/// assert_eq!("hello", storage[&ID.chunk(1).item(2)].2);
+/// ```
+impl<ChunkKey, ItemKey, Element, R> Index<&R> for Storage<ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Record<ChunkKey, ItemKey>,
+    R: Record<ChunkKey, ItemKey>,
+{
+    type Output = Element;
+
+    fn index(&self, unique_id: &R) -> &Element {
+        self.get(unique_id)
+            .expect("retriever: no element with the given id")
+    }
+}
+
+impl<ChunkKey, ItemKey, Element> Clone for Storage<ChunkKey, ItemKey, Element>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    ItemKey: BorrowedKey + ?Sized,
+    ItemKey::Owned: ValidKey,
+    Element: Clone,
+{
+    fn clone(&self) -> Self {
+        for chunk in self.chunks.iter() {
+            chunk.share();
+        }
+
+        Storage {
+            id: self.id,
+            strictness: self.strictness,
+            #[cfg(feature = "rayon")]
+            parallelism: self.parallelism,
+            chunks: self.chunks.clone(),
+            dirty: self.dirty.clone(),
+            index: self.index.clone(),
+            ordered_index: self.ordered_index.clone(),
+            ordered_item_keys: self.ordered_item_keys,
+            item_index: self.item_index.clone(),
+            maintainers: Vec::new(),
+            dedup: self.dedup.clone(),
+            #[cfg(feature = "debug_borrows")]
+            pins: PinRegistry::default(),
+            generation: self.generation,
         }
     }
 }
@@ -817,6 +3664,12 @@ where
         };
 
         result = MemoryUsage::merge(result, self.index.memory_usage());
+        if let Some(ordered_index) = self.ordered_index.as_ref() {
+            result = MemoryUsage::merge(result, ordered_index.memory_usage());
+        }
+        if let Some(item_index) = self.item_index.as_ref() {
+            result = MemoryUsage::merge(result, item_index.memory_usage());
+        }
         result = MemoryUsage::merge(result, self.chunks.memory_usage());
 
         for chunk in self.chunks.iter() {
@@ -829,7 +3682,10 @@ where
     fn shrink_with<F: Fn(&MemoryUsage) -> Option<usize>>(&mut self, f: F) {
         for i in 0..self.chunks.len() {
             if let Some(_min_capacity) = f(&self.chunks[i].memory_usage()) {
-                self.chunks[i].shrink_with(&f);
+                // Don't copy a shared chunk just to shrink it.
+                if let Some(chunk) = Arc::get_mut(&mut self.chunks[i]) {
+                    chunk.shrink_with(&f);
+                }
             }
         }
 
@@ -837,3 +3693,22 @@ where
         self.chunks.shrink_with(&f);
     }
 }
+
+// Group some elements by a key, for a hash join.
+fn group_by_key<'a, Element, K, I, G>(elements: I, key: G) -> HashMap<K, Arc<[&'a Element]>>
+where
+    K: Eq + Hash,
+    I: Iterator<Item = &'a Element>,
+    G: Fn(&Element) -> K,
+{
+    let mut groups: HashMap<K, Vec<&'a Element>> = HashMap::new();
+
+    for element in elements {
+        groups.entry(key(element)).or_default().push(element);
+    }
+
+    groups
+        .into_iter()
+        .map(|(k, group)| (k, Arc::from(group)))
+        .collect()
+}
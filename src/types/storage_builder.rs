@@ -0,0 +1,129 @@
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::dedup_window::DedupWindow;
+#[cfg(feature = "rayon")]
+use crate::types::parallelism::Parallelism;
+use crate::types::storage::Storage;
+
+/// How a `Storage` responds when one of its invariants would be violated, for example by adding
+/// two elements with the same `Id`, adding a group of elements that don't share a chunk key, or
+/// using a `SecondaryIndex` or `Reduction` with a `Storage` other than its own.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum Strictness {
+    /// Always panic, even from the `try_` methods that would otherwise return an error.
+    Panic,
+    /// The `try_` methods (such as `Storage::try_add`) return an error. All other methods panic.
+    /// This is the default.
+    #[default]
+    Error,
+    /// Repair the problem as best as possible and carry on. If the `log` feature is enabled,
+    /// each repair is reported as a warning.
+    ///
+    /// * An element whose `Id` is already present replaces the existing element.
+    /// * An element added to a group with a different chunk key is added to its own chunk.
+    /// * A `SecondaryIndex` or `Reduction` used with a different `Storage` is rebuilt for that
+    ///   `Storage`.
+    Repair,
+}
+
+/// Builder for a `Storage` with non-default configuration.
+///
+/// # Example
+///
+/// ```
+/// use retriever::prelude::*;
+/// use retriever::types::storage_builder::{StorageBuilder, Strictness};
+///
+/// let mut storage : Storage<u64, u64, (u64, u64, &'static str)> = StorageBuilder::new()
+///   .strictness(Strictness::Repair)
+///   .build();
+///
+/// storage.add((1, 1, "hello"));
+/// storage.add((1, 1, "goodbye"));  // would panic with the default Strictness
+///
+/// assert_eq!(Some(&(1, 1, "goodbye")), storage.get(&ID.chunk(1).item(1)));
+/// # storage.validate();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct StorageBuilder {
+    strictness: Strictness,
+    #[cfg(feature = "rayon")]
+    parallelism: Parallelism,
+    ordered_chunk_keys: bool,
+    ordered_item_keys: bool,
+    item_key_index: bool,
+    dedup_window: Option<DedupWindow>,
+}
+
+impl StorageBuilder {
+    /// Construct a new `StorageBuilder` with the default configuration.
+    pub fn new() -> Self {
+        StorageBuilder::default()
+    }
+
+    /// Choose how the `Storage` responds to invariant violations.
+    pub fn strictness(mut self, strictness: Strictness) -> Self {
+        self.strictness = strictness;
+        self
+    }
+
+    /// Choose when queries on the `Storage` are large enough to run in parallel.
+    #[cfg(feature = "rayon")]
+    pub fn parallelism(mut self, parallelism: Parallelism) -> Self {
+        self.parallelism = parallelism;
+        self
+    }
+
+    /// Choose whether the `Storage` keeps its chunk keys in order. This makes `ChunkRange`
+    /// queries visit only the matching chunks, at a small cost whenever a chunk is created or
+    /// removed. Without it, a `ChunkRange` query tests every chunk key.
+    pub fn ordered_chunk_keys(mut self, ordered_chunk_keys: bool) -> Self {
+        self.ordered_chunk_keys = ordered_chunk_keys;
+        self
+    }
+
+    /// Choose whether each chunk of the `Storage` keeps its item keys in order. This makes
+    /// `ItemRange` and `After` queries and resumable iteration visit only the matching item keys,
+    /// at the cost of a second copy of every item key and a small cost whenever an element is
+    /// added or removed. Without it, those queries test every item key of each chunk they visit.
+    pub fn ordered_item_keys(mut self, ordered_item_keys: bool) -> Self {
+        self.ordered_item_keys = ordered_item_keys;
+        self
+    }
+
+    /// Choose whether the `Storage` keeps a map from each item key to its chunk keys. This lets
+    /// `Storage::find` locate an element by its item key alone with a single hash lookup, at the
+    /// cost of a second copy of every item key and chunk key. Without it, `Storage::find` tests
+    /// every chunk.
+    pub fn item_key_index(mut self, item_key_index: bool) -> Self {
+        self.item_key_index = item_key_index;
+        self
+    }
+
+    /// Choose a `DedupWindow`, so that the `Storage` drops any element added with the same
+    /// `Id` as another element added within the window. By default, there is no window.
+    pub fn dedup_window(mut self, dedup_window: DedupWindow) -> Self {
+        self.dedup_window = Some(dedup_window);
+        self
+    }
+
+    /// Construct the `Storage`.
+    pub fn build<ChunkKey, ItemKey, Element>(&self) -> Storage<ChunkKey, ItemKey, Element>
+    where
+        ChunkKey: BorrowedKey + ?Sized,
+        ChunkKey::Owned: ValidKey,
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        let mut storage = Storage::new();
+        storage.set_strictness(self.strictness);
+        #[cfg(feature = "rayon")]
+        storage.set_parallelism(self.parallelism);
+        storage.set_ordered_chunk_keys(self.ordered_chunk_keys);
+        storage.set_ordered_item_keys(self.ordered_item_keys);
+        storage.set_item_key_index(self.item_key_index);
+        storage.set_dedup_window(self.dedup_window);
+        storage
+    }
+}
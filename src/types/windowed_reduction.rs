@@ -0,0 +1,182 @@
+use crate::traits::memory_usage::{MemoryUsage, MemoryUser};
+use crate::traits::record::Record;
+use crate::traits::valid_key::{BorrowedKey, ValidKey};
+use crate::types::grouped_reduction::GroupedReduction;
+use crate::types::storage::Storage;
+use std::borrow::Cow;
+
+/// Summarize the elements of each chunk of a `Storage` once for each window of item keys, such
+/// as the number of requests in each minute of a day, where the item key is a timestamp.
+/// Read the summaries of a chunk using `WindowedReduction::reduce_chunk`.
+///
+/// Like a `GroupedReduction`, a `WindowedReduction` remembers the summary of each window within
+/// each chunk, so repeated evaluations only re-compute the summaries of those windows that
+/// gained, lost, or changed an element. The summaries of a window in every chunk are also
+/// available, folded together, using `WindowedReduction::reduce_window`.
+///
+/// The elements of a window are folded together in no particular order, so `Fold` must not
+/// depend on the order of the `Summaries`.
+///
+/// # Type Parameters
+///
+/// * `ChunkKey`: The chunk key type of the `Storage`.
+/// * `Element`: The element type of the `Storage`.
+/// * `Window`: The type of the window that each item key falls into.
+/// * `Summary`: The type of the result of summarizing the elements of one window.
+///
+/// # Example
+///
+/// ```
+/// use retriever::prelude::*;
+/// use retriever::types::windowed_reduction::WindowedReduction;
+///
+/// // Response times chunked by host, keyed by timestamp in seconds.
+/// type Sample = (&'static str, u64, u64);
+/// let mut storage : Storage<&'static str, u64, Sample> = Storage::new();
+/// let mut per_minute : WindowedReduction<&'static str, Sample, u64, u64> =
+///   WindowedReduction::new(
+///     &storage,
+///     |timestamp: &u64| timestamp / 60,
+///     |x: &Sample, was: &u64| Some(x.2).filter(|x| x != was),
+///     |xs: &[u64], was: &u64| xs.iter().max().cloned().filter(|x| x != was),
+///   );
+///
+/// storage.add(("alpha", 0, 30));
+/// storage.add(("alpha", 59, 45));
+/// storage.add(("alpha", 61, 20));
+/// storage.add(("beta", 10, 90));
+///
+/// let windows : Vec<(u64, u64)> = per_minute
+///   .reduce_chunk(&storage, &"alpha")
+///   .map(|(minute, slowest)| (*minute, *slowest))
+///   .collect();
+/// assert_eq!(vec![(0, 45), (1, 20)], windows);
+///
+/// storage.modify(&ID.chunk("alpha").item(59), |mut editor| editor.get_mut().2 = 10);
+/// storage.add(("alpha", 185, 15));
+///
+/// let windows : Vec<(u64, u64)> = per_minute
+///   .reduce_chunk(&storage, &"alpha")
+///   .map(|(minute, slowest)| (*minute, *slowest))
+///   .collect();
+/// assert_eq!(vec![(0, 30), (1, 20), (3, 15)], windows);
+///
+/// assert_eq!(Some(&90), per_minute.reduce_window(&storage, &0));
+/// assert_eq!(None, per_minute.reduce_window(&storage, &2));
+///
+/// # storage.validate();
+/// # per_minute.validate(&storage);
+/// ```
+pub struct WindowedReduction<ChunkKey, Element, Window, Summary>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    Window: ValidKey + 'static,
+{
+    grouped: GroupedReduction<ChunkKey, Element, Summary, Option<Window>, Window>,
+}
+
+impl<ChunkKey, Element, Window, Summary> WindowedReduction<ChunkKey, Element, Window, Summary>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    Window: ValidKey + 'static,
+    Summary: Default + Clone,
+{
+    /// Create a new `WindowedReduction` on a `Storage`. The window rule decides which window
+    /// each item key falls into. The `Map` and `Fold` rules work exactly like the rules of
+    /// `Reduction::new`.
+    pub fn new<ItemKey, W, Map, Fold>(
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        window: W,
+        map: Map,
+        fold: Fold,
+    ) -> Self
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+        W: Fn(&ItemKey) -> Window + Clone + Send + Sync + 'static,
+        Map: Fn(&Element, &Summary) -> Option<Summary> + Clone + Send + Sync + 'static,
+        Fold: Fn(&[Summary], &Summary) -> Option<Summary> + Clone + Send + Sync + 'static,
+    {
+        WindowedReduction {
+            grouped: GroupedReduction::new(
+                storage,
+                move |element: &Element| Cow::Owned(Some(window(&element.item_key()))),
+                map,
+                fold,
+            ),
+        }
+    }
+
+    /// Bring this reduction up to date and iterate over the summary of each window of a single
+    /// chunk, in window order. Windows without any elements are left out.
+    ///
+    /// # Panic
+    ///
+    /// This method panics if used with a `Storage` other than the one this `WindowedReduction`
+    /// was created with, unless that `Storage`'s `Strictness` is `Repair`, in which case the
+    /// `WindowedReduction` is rebuilt for the new `Storage`.
+    pub fn reduce_chunk<ItemKey>(
+        &mut self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        chunk_key: &ChunkKey,
+    ) -> impl Iterator<Item = (&Window, &Summary)>
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        self.grouped.reduce_chunk(storage, chunk_key).into_iter()
+    }
+
+    /// Bring this reduction up to date and get the summary of a single window across every
+    /// chunk, or `None` if there are no elements in that window.
+    ///
+    /// # Panic
+    ///
+    /// Like `WindowedReduction::reduce_chunk`, this method panics if used with a `Storage` other
+    /// than the one this `WindowedReduction` was created with, unless that `Storage`'s
+    /// `Strictness` is `Repair`.
+    pub fn reduce_window<ItemKey>(
+        &mut self,
+        storage: &Storage<ChunkKey, ItemKey, Element>,
+        window: &Window,
+    ) -> Option<&Summary>
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        self.grouped.reduce_key(storage, window)
+    }
+
+    /// Panic if this storage is malformed or broken in any detectable way.
+    /// This is a slow operation and you shouldn't use it unless you suspect a problem.
+    pub fn validate<ItemKey>(&mut self, parent: &Storage<ChunkKey, ItemKey, Element>)
+    where
+        ItemKey: BorrowedKey + ?Sized,
+        ItemKey::Owned: ValidKey,
+        Element: Record<ChunkKey, ItemKey>,
+    {
+        self.grouped.validate(parent);
+    }
+}
+
+impl<ChunkKey, Element, Window, Summary> MemoryUser
+    for WindowedReduction<ChunkKey, Element, Window, Summary>
+where
+    ChunkKey: BorrowedKey + ?Sized,
+    ChunkKey::Owned: ValidKey,
+    Window: ValidKey + 'static,
+    Summary: Default,
+{
+    fn memory_usage(&self) -> MemoryUsage {
+        self.grouped.memory_usage()
+    }
+
+    fn shrink_with<F: Fn(&MemoryUsage) -> Option<usize>>(&mut self, f: F) {
+        self.grouped.shrink_with(f)
+    }
+}